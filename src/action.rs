@@ -0,0 +1,92 @@
+use misc;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::os::unix;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Makes the rest of the run a dry run, where each action is printed rather than taken.
+pub fn set_dry_run() {
+    DRY_RUN.store(true, Ordering::SeqCst);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst)
+}
+
+/// Prints an action which a dry run skipped, such as `would move a to b`.
+pub fn report<D: Display>(action: D) {
+    println!("would {}", action);
+}
+
+/// Takes an action which modifies the filesystem or uses the network, unless this is a dry run,
+/// in which case the action is reported, and the default value is returned in its place.
+pub fn perform<D, F, T>(action: D, take: F) -> io::Result<T>
+    where D: Display,
+          F: FnOnce() -> io::Result<T>,
+          T: Default
+{
+    if is_dry_run() {
+        report(action);
+        Ok(T::default())
+    } else {
+        take()
+    }
+}
+
+pub fn copy<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> io::Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    perform(format_args!("copy {} to {}", src.display(), dst.display()), || {
+        fs::copy(src, dst).map(|_| ())
+    })
+}
+
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    perform(format_args!("create {}", path.display()), || fs::create_dir_all(path))
+}
+
+pub fn exchange<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> io::Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    perform(format_args!("move {} into the place of {}", src.display(), dst.display()), || {
+        misc::exchange(src, dst)
+    })
+}
+
+pub fn hard_link<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> io::Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    perform(format_args!("link {} to {}", src.display(), dst.display()), || fs::hard_link(src, dst))
+}
+
+pub fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    perform(format_args!("remove {}", path.display()), || fs::remove_dir(path))
+}
+
+pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    perform(format_args!("remove {}", path.display()), || fs::remove_dir_all(path))
+}
+
+pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    perform(format_args!("delete {}", path.display()), || fs::remove_file(path))
+}
+
+pub fn rename<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> io::Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    perform(format_args!("move {} to {}", src.display(), dst.display()), || fs::rename(src, dst))
+}
+
+pub fn symlink<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> io::Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    perform(format_args!("link {} to {}", dst.display(), src.display()), || unix::fs::symlink(src, dst))
+}
+
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    perform(format_args!("write {}", path.display()), || misc::write(path, contents))
+}
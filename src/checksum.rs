@@ -1,7 +1,30 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, Metadata};
 use std::io;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use digest::Digest;
 use hex_view::HexView;
+use libc;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
 
+/// The digest families that the indices may list, which are selected by their flags.
+pub const MD5: u8 = 1;
+pub const SHA1: u8 = 2;
+pub const SHA256: u8 = 4;
+pub const SHA512: u8 = 8;
+pub const ALL_DIGESTS: u8 = MD5 | SHA1 | SHA256 | SHA512;
+
+/// Files of at least 64 MiB are hashed from a memory map, rather than read through a buffer.
+pub const DEFAULT_MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
 
 pub(crate) fn hasher<H: Digest, R: io::Read>(mut reader: R) -> io::Result<String> {
     let mut buffer = [0u8; 8 * 1024];
@@ -15,3 +38,408 @@ pub(crate) fn hasher<H: Digest, R: io::Read>(mut reader: R) -> io::Result<String
 
     Ok(format!("{:x}", HexView::from(hasher.result().as_slice())))
 }
+
+/// The digests that apt expects of each file in the `Packages` and `Release` files, of which those
+/// that were not selected are empty.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Digests {
+    pub md5:    String,
+    pub sha1:   String,
+    pub sha256: String,
+    pub sha512: String,
+}
+
+/// Computes the MD5, SHA1, SHA256, and SHA512 digests of the reader in a single pass, so that
+/// large files in the pool only need to be read once.
+pub(crate) fn multi_hasher<R: io::Read>(reader: R) -> io::Result<Digests> {
+    selected_hasher(reader, ALL_DIGESTS)
+}
+
+/// Computes only the selected digests of the reader, in a single pass.
+pub(crate) fn selected_hasher<R: io::Read>(reader: R, selected: u8) -> io::Result<Digests> {
+    copy_digests(reader, &mut io::sink(), selected)
+}
+
+/// Copies the reader to the writer, while computing the selected digests of what was copied, so
+/// that a file which is copied into the pool is not read a second time to be indexed.
+pub(crate) fn copy_digests<R: io::Read, W: io::Write>(
+    mut reader: R,
+    writer: &mut W,
+    selected: u8
+) -> io::Result<Digests> {
+    let mut buffer = [0u8; 64 * 1024];
+    let mut hashers = Hashers::new(selected);
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 { break }
+        let data = &buffer[..read];
+        writer.write_all(data)?;
+        hashers.input(data);
+    }
+
+    Ok(hashers.finish())
+}
+
+/// Computes the selected digests of a file, which is hashed from a memory map if it has at least
+/// `mmap_threshold` bytes, as the kernel pages in a multi-gigabyte file faster than it may be
+/// copied through a buffer.
+pub(crate) fn file_digests(
+    file: File,
+    size: u64,
+    selected: u8,
+    mmap_threshold: Option<u64>
+) -> io::Result<Digests> {
+    match mmap_threshold {
+        // An empty file may not be mapped.
+        Some(threshold) if size >= threshold.max(1) && size <= usize::max_value() as u64 => {
+            let mapped = Mapped::new(&file, size as usize)?;
+            let mut hashers = Hashers::new(selected);
+
+            // Each hasher is given the same chunk in turn, while it is still in the CPU's cache.
+            for chunk in mapped.as_slice().chunks(1024 * 1024) {
+                hashers.input(chunk);
+            }
+
+            Ok(hashers.finish())
+        }
+        _ => selected_hasher(file, selected)
+    }
+}
+
+/// The time taken to hash a file by reading it, or from a memory map, for `debrep benchmark`.
+pub fn time_hashing(path: &Path, mapped: bool) -> io::Result<Duration> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let start = Instant::now();
+    file_digests(file, size, ALL_DIGESTS, if mapped { Some(0) } else { None })?;
+    Ok(start.elapsed())
+}
+
+/// The hashers of the selected digests, which are each given the same data.
+struct Hashers {
+    md5: Option<Md5>,
+    sha1: Option<Sha1>,
+    sha256: Option<Sha256>,
+    sha512: Option<Sha512>,
+}
+
+impl Hashers {
+    fn new(selected: u8) -> Self {
+        Hashers {
+            md5: if selected & MD5 != 0 { Some(Md5::new()) } else { None },
+            sha1: if selected & SHA1 != 0 { Some(Sha1::new()) } else { None },
+            sha256: if selected & SHA256 != 0 { Some(Sha256::new()) } else { None },
+            sha512: if selected & SHA512 != 0 { Some(Sha512::new()) } else { None },
+        }
+    }
+
+    fn input(&mut self, data: &[u8]) {
+        fn input<H: Digest>(hasher: &mut Option<H>, data: &[u8]) {
+            if let Some(ref mut hasher) = *hasher {
+                hasher.input(data);
+            }
+        }
+
+        input(&mut self.md5, data);
+        input(&mut self.sha1, data);
+        input(&mut self.sha256, data);
+        input(&mut self.sha512, data);
+    }
+
+    fn finish(self) -> Digests {
+        fn hex<H: Digest>(hasher: Option<H>) -> String {
+            hasher.map_or_else(String::new, |hasher| {
+                format!("{:x}", HexView::from(hasher.result().as_slice()))
+            })
+        }
+
+        Digests {
+            md5: hex(self.md5),
+            sha1: hex(self.sha1),
+            sha256: hex(self.sha256),
+            sha512: hex(self.sha512),
+        }
+    }
+}
+
+/// A file which is mapped into memory, read-only, until it is dropped.
+///
+/// Files of the pool are replaced by renaming over them, rather than modified in place, so a
+/// mapped file is not truncated while it is being hashed.
+struct Mapped {
+    data: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapped {
+    fn new(file: &File, len: usize) -> io::Result<Mapped> {
+        let data = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The file is read from start to end, so the kernel may read well ahead of the hashers.
+        unsafe { libc::madvise(data, len, libc::MADV_SEQUENTIAL); }
+        Ok(Mapped { data, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.data, self.len); }
+    }
+}
+
+/// The digests of a file, along with the metadata of the file when they were computed.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct CachedDigests {
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub inode: u64,
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+    pub sha512: String,
+}
+
+impl CachedDigests {
+    fn new(metadata: &Metadata, digests: &Digests) -> CachedDigests {
+        CachedDigests {
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            inode: metadata.ino(),
+            md5: digests.md5.clone(),
+            sha1: digests.sha1.clone(),
+            sha256: digests.sha256.clone(),
+            sha512: digests.sha512.clone(),
+        }
+    }
+}
+
+/// The digests of the files which were hashed as they were copied into the pool by this run, by
+/// their canonical path, which the `DigestCache` of the suite is given when it is generated.
+///
+/// The recorded digests are shared by the clones of a config, so that the packages which are
+/// pooled while building are not hashed again when the suite is generated afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct PooledDigests {
+    entries: Arc<Mutex<BTreeMap<String, CachedDigests>>>,
+    selected: u8,
+}
+
+impl PooledDigests {
+    pub fn new(selected: u8) -> Self {
+        PooledDigests { entries: Arc::default(), selected }
+    }
+
+    /// Copies the file to its destination, recording the selected digests of the copy.
+    pub fn copy(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let digests = {
+            let mut destination = File::create(dst)?;
+            copy_digests(File::open(src)?, &mut destination, self.selected)?
+        };
+
+        fs::set_permissions(dst, fs::metadata(src)?.permissions())?;
+
+        // The metadata of the copy is read once it is written, as the cache compares it.
+        let key = fs::canonicalize(dst)?.to_string_lossy().into_owned();
+        let cached = CachedDigests::new(&fs::metadata(dst)?, &digests);
+        self.entries.lock().unwrap().insert(key, cached);
+        Ok(())
+    }
+
+    /// Takes the digests which were recorded so far.
+    pub fn take(&self) -> BTreeMap<String, CachedDigests> {
+        mem::replace(&mut *self.entries.lock().unwrap(), BTreeMap::new())
+    }
+}
+
+/// Digests of the files which were hashed before, by their canonical path.
+///
+/// A cached digest is reused for as long as the size, modification time, and inode of its file
+/// are unchanged, so that only the files which were added or replaced since are hashed again.
+/// Only the selected digests are computed, and returned.
+pub(crate) struct DigestCache {
+    entries: Mutex<BTreeMap<String, CachedDigests>>,
+    selected: u8,
+    mmap_threshold: Option<u64>,
+}
+
+impl DigestCache {
+    pub fn new(entries: BTreeMap<String, CachedDigests>, selected: u8) -> Self {
+        DigestCache {
+            entries: Mutex::new(entries),
+            selected,
+            mmap_threshold: Some(DEFAULT_MMAP_THRESHOLD),
+        }
+    }
+
+    /// The size from which files are hashed from a memory map, if they are mapped at all.
+    pub fn mmap_threshold(mut self, threshold: Option<u64>) -> Self {
+        self.mmap_threshold = threshold;
+        self
+    }
+
+    /// Clears the digests which were not selected.
+    fn select(&self, digest: &str, flag: u8) -> String {
+        if self.selected & flag != 0 { digest.to_owned() } else { String::new() }
+    }
+
+    pub fn digests(&self, path: &Path) -> io::Result<Digests> {
+        let key = fs::canonicalize(path)?.to_string_lossy().into_owned();
+
+        // The metadata is read before hashing, so that changes made while hashing are noticed.
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let stamp = (metadata.size(), metadata.mtime(), metadata.mtime_nsec(), metadata.ino());
+
+        if let Some(cached) = self.entries.lock().unwrap().get(&key) {
+            // Digests which were not selected when the file was cached are empty.
+            let complete = [
+                (&cached.md5, MD5),
+                (&cached.sha1, SHA1),
+                (&cached.sha256, SHA256),
+                (&cached.sha512, SHA512),
+            ].iter().all(|&(digest, flag)| self.selected & flag == 0 || !digest.is_empty());
+
+            if complete && (cached.size, cached.mtime, cached.mtime_nsec, cached.inode) == stamp {
+                return Ok(Digests {
+                    md5: self.select(&cached.md5, MD5),
+                    sha1: self.select(&cached.sha1, SHA1),
+                    sha256: self.select(&cached.sha256, SHA256),
+                    sha512: self.select(&cached.sha512, SHA512),
+                });
+            }
+        }
+
+        let digests = file_digests(file, metadata.size(), self.selected, self.mmap_threshold)?;
+        self.entries.lock().unwrap().insert(key, CachedDigests::new(&metadata, &digests));
+
+        Ok(digests)
+    }
+
+    /// The cached digests of the files which still exist.
+    pub fn into_entries(self) -> BTreeMap<String, CachedDigests> {
+        self.entries.into_inner().unwrap()
+            .into_iter()
+            .filter(|&(ref path, _)| Path::new(path).exists())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn single_pass_digests() {
+        let data: &[u8] = b"abc";
+        let digests = multi_hasher(data).unwrap();
+        assert_eq!(digests.md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digests.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(digests.sha256, hasher::<Sha256, &[u8]>(data).unwrap());
+        assert_eq!(digests.sha512, hasher::<Sha512, &[u8]>(data).unwrap());
+
+        let strong = selected_hasher(data, SHA256).unwrap();
+        assert_eq!(strong, Digests { sha256: digests.sha256, ..Digests::default() });
+    }
+
+    #[test]
+    fn cached_digests() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("foo_1.0_amd64.deb");
+        misc::write(&path, b"abc").unwrap();
+
+        let cache = DigestCache::new(BTreeMap::new(), ALL_DIGESTS);
+        let digests = cache.digests(&path).unwrap();
+        assert_eq!(digests, multi_hasher(&b"abc"[..]).unwrap());
+
+        // A digest is reused while the file is unchanged, even if it is wrong.
+        let mut entries = cache.into_entries();
+        entries.values_mut().next().unwrap().sha256 = "cached".into();
+        let cache = DigestCache::new(entries, ALL_DIGESTS);
+        assert_eq!(cache.digests(&path).unwrap().sha256, "cached");
+
+        // Only the selected digests are given, which are computed again if any were not cached.
+        let selected = DigestCache::new(cache.into_entries(), SHA256);
+        assert_eq!(selected.digests(&path).unwrap(), Digests {
+            sha256: "cached".into(),
+            ..Digests::default()
+        });
+
+        let mut entries = selected.into_entries();
+        entries.values_mut().next().unwrap().sha512 = String::new();
+        let selected = DigestCache::new(entries, SHA256 | SHA512);
+        assert_eq!(selected.digests(&path).unwrap(), Digests {
+            sha256: digests.sha256.clone(),
+            sha512: digests.sha512.clone(),
+            ..Digests::default()
+        });
+
+        let cache = DigestCache::new(selected.into_entries(), ALL_DIGESTS);
+
+        // Replacing the file gives it another inode, and so it is hashed again.
+        let replacement = dir.path().join("replacement");
+        misc::write(&replacement, b"abc").unwrap();
+        fs::rename(&replacement, &path).unwrap();
+        assert_eq!(cache.digests(&path).unwrap(), digests);
+
+        fs::remove_file(&path).unwrap();
+        assert!(cache.into_entries().is_empty());
+    }
+
+    #[test]
+    fn pooled_digests() {
+        let dir = tempdir().unwrap();
+        let (src, dst) = (dir.path().join("foo_1.0_amd64.deb"), dir.path().join("pooled.deb"));
+        misc::write(&src, b"abc").unwrap();
+
+        let pooled = PooledDigests::new(SHA256);
+        pooled.clone().copy(&src, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), b"abc");
+
+        // The digests of the copy are given to the cache, and are reused while it is unchanged.
+        let mut entries = pooled.take();
+        assert_eq!(entries.len(), 1);
+        assert!(pooled.take().is_empty());
+
+        entries.values_mut().next().unwrap().sha256 = "copied".into();
+        let cache = DigestCache::new(entries, SHA256);
+        assert_eq!(cache.digests(&dst).unwrap().sha256, "copied");
+        assert_eq!(cache.digests(&src).unwrap().sha256, hasher::<Sha256, &[u8]>(b"abc").unwrap());
+    }
+
+    #[test]
+    fn mapped_digests() {
+        let dir = tempdir().unwrap();
+        let (path, empty) = (dir.path().join("foo.iso"), dir.path().join("empty"));
+        let data = (0..3 * 1024 * 1024).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+        misc::write(&path, &data).unwrap();
+        misc::write(&empty, b"").unwrap();
+
+        let digests = |path: &Path, threshold| {
+            let file = File::open(path).unwrap();
+            let size = file.metadata().unwrap().len();
+            file_digests(file, size, ALL_DIGESTS, threshold).unwrap()
+        };
+
+        assert_eq!(digests(&path, Some(1024)), multi_hasher(data.as_slice()).unwrap());
+        assert_eq!(digests(&path, None), multi_hasher(data.as_slice()).unwrap());
+        assert_eq!(digests(&empty, Some(0)), multi_hasher(&b""[..]).unwrap());
+
+        let cache = DigestCache::new(BTreeMap::new(), SHA256).mmap_threshold(Some(1));
+        assert_eq!(cache.digests(&path).unwrap().sha256, hasher::<Sha256, &[u8]>(&data).unwrap());
+    }
+}
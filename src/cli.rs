@@ -3,21 +3,84 @@ use clap::ArgMatches;
 /// Possible actions that the user may request when running the application.
 #[derive(Debug, PartialEq)]
 pub enum Action<'a> {
+    Archive(&'a str),
+    Benchmark(Vec<&'a str>),
     Build(Vec<&'a str>, bool),
-    Clean,
+    Check(bool),
+    ChrootCreate(&'a str, &'a str),
+    ChrootUpdate,
+    Clean(Vec<&'a str>),
+    Copy(Vec<&'a str>, &'a str, &'a str),
+    Diff(&'a str, &'a str, &'a str),
     Dist,
+    Export(&'a str),
     Fetch(&'a str),
     FetchConfig,
-    Migrate(Vec<&'a str>, &'a str, &'a str),
+    Import(&'a str, Option<&'a str>),
+    Incoming(&'a str, bool),
+    Info(&'a str),
+    Init(&'a str, Option<&'a str>),
+    KeyExport(Option<&'a str>),
+    List(Option<&'a str>),
+    Migrate(Option<Vec<&'a str>>, &'a str, &'a str, bool),
+    PhaseBump(&'a str, Option<&'a str>, &'a str),
+    PlanApply(&'a str),
+    PlanCreate(&'a str, &'a str),
     Pool,
-    Remove(Vec<&'a str>),
+    PoolRepair(bool),
+    Prune(Option<&'a str>),
+    Publish,
+    QueueApprove(Vec<&'a str>),
+    QueueList,
+    QueueReject(Vec<&'a str>),
+    Remove(Vec<&'a str>, Option<&'a str>),
+    Resign,
+    Search(&'a str, bool),
+    Serve(&'a str, &'a str, Option<&'a str>),
+    SnapshotCreate(&'a str),
+    SnapshotList,
+    SnapshotPublish(&'a str),
+    SnapshotServe(&'a str, &'a str),
+    TestApt(Vec<&'a str>),
     Update(&'a str, &'a str),
     UpdateRepository,
+    Verify(Option<&'a str>),
+    Watch(Option<&'a str>),
 }
 
 impl<'a> Action<'a> {
+    /// Whether the action modifies or publishes the repository, and so must not overlap with
+    /// another instance which does.
+    pub fn is_mutating(&self) -> bool {
+        match *self {
+            Action::Benchmark(_)
+                | Action::Check(_)
+                | Action::Diff(..)
+                | Action::Export(_)
+                | Action::Fetch(_)
+                | Action::FetchConfig
+                | Action::Incoming(_, true)
+                | Action::Info(_)
+                | Action::Init(..)
+                | Action::List(_)
+                | Action::PlanCreate(..)
+                | Action::PoolRepair(true)
+                | Action::QueueList
+                | Action::Search(..)
+                | Action::Serve(..)
+                | Action::SnapshotList
+                | Action::SnapshotServe(..)
+                | Action::TestApt(_)
+                | Action::Verify(_)
+                | Action::Watch(_) => false,
+            _ => true
+        }
+    }
+
     pub fn new(matches: &'a ArgMatches) -> Action<'a> {
         match matches.subcommand() {
+            ("archive", Some(archive)) => Action::Archive(archive.value_of("suite").unwrap()),
+            ("benchmark", Some(benchmark)) => Action::Benchmark(benchmark.values_of("files").unwrap().collect()),
             ("build", Some(build)) => match build.subcommand() {
                 ("packages", Some(pkgs)) => {
                     Action::Build(pkgs.values_of("packages").unwrap().collect(), pkgs.is_present("force"))
@@ -26,7 +89,29 @@ impl<'a> Action<'a> {
                 ("dist", _) => Action::Dist,
                 _ => Action::UpdateRepository
             }
-            ("clean", _) => Action::Clean,
+            ("check", Some(check)) => Action::Check(check.is_present("urls")),
+            ("chroot", Some(chroot)) => match chroot.subcommand() {
+                ("create", Some(create)) => {
+                    Action::ChrootCreate(create.value_of("suite").unwrap(), create.value_of("arch").unwrap())
+                }
+                _ => Action::ChrootUpdate
+            }
+            ("clean", Some(clean)) => Action::Clean(
+                ["sources", "builds", "cache", "logs", "stale", "all"].iter()
+                    .cloned()
+                    .filter(|target| clean.is_present(target))
+                    .collect()
+            ),
+            ("copy", Some(copy)) => Action::Copy(
+                copy.values_of("packages").unwrap().collect(),
+                copy.value_of("from").unwrap(),
+                copy.value_of("to").unwrap()
+            ),
+            ("diff", Some(diff)) => Action::Diff(
+                diff.value_of("from").unwrap(),
+                diff.value_of("to").unwrap(),
+                diff.value_of("format").unwrap()
+            ),
             ("config", Some(config)) => {
                 config.value_of("key").map_or(Action::FetchConfig, |key| {
                     config.value_of("value").map_or(Action::Fetch(key), |value| {
@@ -34,16 +119,79 @@ impl<'a> Action<'a> {
                     })
                 })
             }
+            ("import", Some(import)) => Action::Import(import.value_of("path").unwrap(), import.value_of("suite")),
+            ("incoming", Some(incoming)) => match incoming.subcommand() {
+                ("process", Some(process)) => {
+                    Action::Incoming(process.value_of("directory").unwrap(), process.is_present("watch"))
+                }
+                _ => unreachable!()
+            }
+            ("info", Some(info)) => Action::Info(info.value_of("package").unwrap()),
+            ("export", Some(export)) => Action::Export(export.value_of("format").unwrap()),
+            ("key", Some(key)) => match key.subcommand() {
+                ("export", Some(export)) => Action::KeyExport(export.value_of("name")),
+                _ => unreachable!()
+            }
+            ("init", Some(init)) => Action::Init(init.value_of("name").unwrap(), init.value_of("suite")),
+            ("list", Some(list)) => Action::List(list.value_of("arch")),
+            ("phase", Some(phase)) => match phase.subcommand() {
+                ("bump", Some(bump)) => Action::PhaseBump(
+                    bump.value_of("package").unwrap(),
+                    bump.value_of("version"),
+                    bump.value_of("by").unwrap()
+                ),
+                _ => unreachable!()
+            }
+            ("plan", Some(plan)) => match plan.subcommand() {
+                ("apply", Some(apply)) => Action::PlanApply(apply.value_of("plan").unwrap()),
+                ("create", Some(create)) => {
+                    Action::PlanCreate(create.value_of("suite").unwrap(), create.value_of("directory").unwrap())
+                }
+                _ => unreachable!()
+            }
+            ("pool", Some(pool)) => match pool.subcommand() {
+                ("repair", Some(repair)) => Action::PoolRepair(repair.is_present("check")),
+                _ => unreachable!()
+            }
+            ("prune", Some(prune)) => Action::Prune(prune.value_of("keep")),
+            ("publish", _) => Action::Publish,
+            ("queue", Some(queue)) => match queue.subcommand() {
+                ("approve", Some(approve)) => Action::QueueApprove(approve.values_of("packages").unwrap().collect()),
+                ("list", _) => Action::QueueList,
+                ("reject", Some(reject)) => Action::QueueReject(reject.values_of("packages").unwrap().collect()),
+                _ => unreachable!()
+            }
             ("remove", Some(pkgs)) => {
-                Action::Remove(pkgs.values_of("packages").unwrap().collect())
+                Action::Remove(pkgs.values_of("packages").unwrap().collect(), pkgs.value_of("version"))
+            }
+            ("resign", _) => Action::Resign,
+            ("search", Some(search)) => {
+                Action::Search(search.value_of("pattern").unwrap(), search.is_present("snapshots"))
+            }
+            ("serve", Some(serve)) => {
+                Action::Serve(serve.value_of("address").unwrap(), serve.value_of("port").unwrap(), serve.value_of("auth"))
+            }
+            ("snapshot", Some(snapshot)) => match snapshot.subcommand() {
+                ("create", Some(create)) => Action::SnapshotCreate(create.value_of("name").unwrap()),
+                ("publish", Some(publish)) => Action::SnapshotPublish(publish.value_of("name").unwrap()),
+                ("serve", Some(serve)) => {
+                    Action::SnapshotServe(serve.value_of("name").unwrap(), serve.value_of("address").unwrap())
+                }
+                _ => Action::SnapshotList
             }
             ("migrate", Some(migrate)) => {
                 Action::Migrate(
-                    migrate.values_of("packages").unwrap().collect(),
+                    migrate.values_of("packages").map(|packages| packages.collect()),
                     migrate.value_of("from").unwrap(),
-                    migrate.value_of("to").unwrap()
+                    migrate.value_of("to").unwrap(),
+                    migrate.is_present("copy")
                 )
             }
+            ("test-apt", Some(test)) => {
+                Action::TestApt(test.values_of("packages").map_or_else(Vec::new, |x| x.collect()))
+            }
+            ("verify", Some(verify)) => Action::Verify(verify.value_of("keyring")),
+            ("watch", Some(watch)) => Action::Watch(watch.value_of("metrics")),
             _ => unreachable!()
         }
     }
@@ -1,6 +1,8 @@
-use std::process::{self, Stdio};
+use error::Error as ContextError;
+use std::process::{self, ExitStatus, Stdio};
 use std::io::{self, BufRead, BufReader, Error, ErrorKind};
 use std::ffi::OsStr;
+use std::path::Path;
 use std::thread;
 
 pub struct Command(process::Command);
@@ -20,6 +22,11 @@ impl Command {
         self
     }
 
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Command {
+        self.0.current_dir(dir);
+        self
+    }
+
     pub fn env(&mut self, key: &str, value: &str) {
         self.0.env(key, value);
     }
@@ -38,32 +45,35 @@ impl Command {
 
         self.0.stdout(Stdio::piped());
 
-        let child = self.0.spawn().map_err(|why| Error::new(
-            ErrorKind::Other,
-            format!("chroot command failed to spawn: {}", why)
-        ))?;
+        let child = self.0.spawn().map_err(|why| failed(&cmd, why))?;
 
         child.wait_with_output()
-            .map_err(|why| Error::new(
-                ErrorKind::Other,
-                format!("failed to get output of {}: {}", cmd, why)
-            ))
-            .and_then(|output| {
-                String::from_utf8(output.stdout)
-                    .map_err(|why| Error::new(
-                        ErrorKind::Other,
-                        format!("command output has invalid UTF-8: {}", why)
-                    ))
-            })
+            .map_err(|why| failed(&cmd, why))
+            .and_then(|output| stdout(&cmd, output.stdout))
+    }
+
+    /// Like `run_with_stdout`, but the command also fails if it exits with an error status.
+    pub fn run_checked_with_stdout(&mut self) -> io::Result<String> {
+        let cmd = format!("{:?}", self.0);
+        debug!("running {}", cmd);
+
+        self.0.stdout(Stdio::piped());
+
+        let child = self.0.spawn().map_err(|why| failed(&cmd, why))?;
+
+        let output = child.wait_with_output().map_err(|why| failed(&cmd, why))?;
+        if !output.status.success() {
+            return Err(exited(&cmd, output.status));
+        }
+
+        stdout(&cmd, output.stdout)
     }
 
     pub fn run(&mut self) -> io::Result<()> {
-        debug!("running {:?}", self.0);
+        let cmd = format!("{:?}", self.0);
+        debug!("running {}", cmd);
 
-        let mut child = self.0.spawn().map_err(|why| Error::new(
-            ErrorKind::Other,
-            format!("chroot command failed to spawn: {}", why)
-        ))?;
+        let mut child = self.0.spawn().map_err(|why| failed(&cmd, why))?;
 
         if let Some(stdout) = child.stdout.take() {
             let mut stdout = BufReader::new(stdout);
@@ -97,18 +107,26 @@ impl Command {
             });
         }
 
-        let status = child.wait().map_err(|why| Error::new(
-            ErrorKind::Other,
-            format!("waiting on child process failed: {}", why)
-        ))?;
-
+        let status = child.wait().map_err(|why| failed(&cmd, why))?;
         if status.success() {
             Ok(())
         } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("command failed with exit status: {}", status)
-            ))
+            Err(exited(&cmd, status))
         }
     }
 }
+
+/// The error of a command which could not be run to completion, which names the command.
+fn failed(cmd: &str, why: io::Error) -> io::Error {
+    ContextError::Command { command: cmd.to_owned(), why }.into()
+}
+
+/// The error of a command which ran, but exited with an error status.
+fn exited(cmd: &str, status: ExitStatus) -> io::Error {
+    failed(cmd, Error::new(ErrorKind::Other, format!("exited with {}", status)))
+}
+
+/// The output of a command as a string, which fails if it is not valid UTF-8.
+fn stdout(cmd: &str, output: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(output).map_err(|why| failed(cmd, Error::new(ErrorKind::InvalidData, why)))
+}
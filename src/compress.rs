@@ -2,30 +2,42 @@ use bus_writer::BusWriter;
 use deflate::Compression;
 use deflate::write::GzEncoder;
 use xz2::write::XzEncoder;
-use std::io;
+use zstd::stream::write::Encoder as ZstdEncoder;
+use std::io::{self, Write};
 use std::path::Path;
 use std::fs::File;
+use std::sync::{Arc, Mutex};
 
 pub const UNCOMPRESSED: u8 = 1;
 pub const GZ_COMPRESS: u8 = 2;
 pub const XZ_COMPRESS: u8 = 4;
+pub const ZSTD_COMPRESS: u8 = 8;
 
 pub trait SyncWrite: Send + Sync + io::Write {}
 impl<T: Send + Sync + io::Write> SyncWrite for T {}
 
 pub fn compress<R: io::Read>(name: &str, path: &Path, stream: R, support: u8) -> io::Result<()> {
-    inner_compress(name, path, stream, support)
+    compress_level(name, path, stream, support, None)
+}
+
+/// Compresses with the given level, rather than the best compression of each format.
+///
+/// Levels are given on the 0-9 scale of gzip and xz, and levels beyond the range of a format are
+/// clamped to it. Zstd accepts levels of up to 19.
+pub fn compress_level<R: io::Read>(name: &str, path: &Path, stream: R, support: u8, level: Option<u32>) -> io::Result<()> {
+    inner_compress(name, path, stream, support, level)
         .map_err(|why| io::Error::new(
             io::ErrorKind::Other,
             format!("failed to compress output to {} in {}: {}", name, path.display(), why)
         ))
 }
 
-fn inner_compress<R: io::Read>(name: &str, path: &Path, stream: R, support: u8) -> io::Result<()> {
+fn inner_compress<R: io::Read>(name: &str, path: &Path, stream: R, support: u8, level: Option<u32>) -> io::Result<()> {
     if support == 0 {
         return Ok(());
     }
 
+    let mut zstd = None;
     let mut destinations = {
         let mut writers: Vec<Box<SyncWrite>> = Vec::new();
         if support & UNCOMPRESSED != 0 {
@@ -34,25 +46,119 @@ fn inner_compress<R: io::Read>(name: &str, path: &Path, stream: R, support: u8)
 
         if support & GZ_COMPRESS != 0 {
             let mut gz_file = File::create(path.join([name, ".gz"].concat()))?;
-            writers.push(Box::new(GzEncoder::new(gz_file, Compression::Best)));
+            let compression = match level {
+                Some(0...3) => Compression::Fast,
+                Some(4...6) => Compression::Default,
+                _ => Compression::Best
+            };
+            writers.push(Box::new(GzEncoder::new(gz_file, compression)));
         }
 
         if support & XZ_COMPRESS != 0 {
             let mut xz_file = File::create(path.join([name, ".xz"].concat()))?;
-            writers.push(Box::new(XzEncoder::new(xz_file, 9)));
+            writers.push(Box::new(XzEncoder::new(xz_file, level.map_or(9, |level| level.min(9)))));
+        }
+
+        if support & ZSTD_COMPRESS != 0 {
+            let zstd_file = File::create(path.join([name, ".zst"].concat()))?;
+            let level = level.map_or(19, |level| level.max(1).min(19)) as i32;
+            let writer = ZstdWriter(Arc::new(Mutex::new(Some(ZstdEncoder::new(zstd_file, level)?))));
+            writers.push(Box::new(writer.clone()));
+            zstd = Some(writer);
         }
 
         writers
     };
 
     info!(
-        "compressing {} to {}: uncompressed: {}, gzip: {}, xz: {}",
+        "compressing {} to {}: uncompressed: {}, gzip: {}, xz: {}, zstd: {}",
         name,
         path.display(),
         support & UNCOMPRESSED != 0,
         support & GZ_COMPRESS != 0,
-        support & XZ_COMPRESS != 0
+        support & XZ_COMPRESS != 0,
+        support & ZSTD_COMPRESS != 0
     );
 
-    BusWriter::new(stream, &mut destinations, |_| {}, || false).write()
-}
\ No newline at end of file
+    BusWriter::new(stream, &mut destinations, |_| {}, || false).write()?;
+    zstd.map_or(Ok(()), |zstd| zstd.finish())
+}
+
+/// A zstd encoder which is shared with the destinations, so that its frame can be finished once
+/// the stream has been written, where a failure to write the end of the archive is returned. The
+/// encoder is locked so that it satisfies the `Sync` bound of the destinations.
+#[derive(Clone)]
+struct ZstdWriter(Arc<Mutex<Option<ZstdEncoder<File>>>>);
+
+impl ZstdWriter {
+    fn finish(&self) -> io::Result<()> {
+        match self.0.lock().unwrap().take() {
+            Some(encoder) => encoder.finish().map(|_| ()),
+            None => Ok(())
+        }
+    }
+}
+
+impl Write for ZstdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self.0.lock().unwrap() {
+            Some(ref mut encoder) => encoder.write(buf),
+            None => Ok(0)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self.0.lock().unwrap() {
+            Some(ref mut encoder) => encoder.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+/// An archive which was never finished, as its stream failed, is incomplete, so it is not
+/// finished as it is dropped.
+impl Drop for ZstdWriter {
+    fn drop(&mut self) {
+        if let Ok(mut encoder) = self.0.lock() {
+            if encoder.take().is_some() {
+                error!("the zstd archive was dropped before it was finished");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use misc;
+    use tempfile::tempdir;
+    use toml;
+    use zstd;
+
+    #[test]
+    fn configured_compression() {
+        let config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "test"
+            label = "test"
+            email = "test@example.com"
+            compression = ["zstd", "gz"]
+            compression_level = 1
+        "#).unwrap();
+
+        assert_eq!(config.compression(), ZSTD_COMPRESS | GZ_COMPRESS);
+
+        let dir = tempdir().unwrap();
+        let data = b"Package: foo\nVersion: 1.0\n";
+        compress_level("Packages", dir.path(), &data[..], config.compression(), config.compression_level).unwrap();
+
+        assert!(!dir.path().join("Packages").exists());
+        assert!(!dir.path().join("Packages.xz").exists());
+        assert!(dir.path().join("Packages.gz").exists());
+
+        let zstd = misc::read(dir.path().join("Packages.zst")).unwrap();
+        assert_eq!(zstd::stream::decode_all(&zstd[..]).unwrap(), &data[..]);
+    }
+}
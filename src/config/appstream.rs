@@ -0,0 +1,14 @@
+/// Generates the DEP-11 AppStream metadata of each component, along with its icon tarballs, from
+/// the metainfo files, desktop entries, and icons that its packages install.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct AppStream {
+    /// The sizes of the icons which are cached, of those in the `hicolor` theme.
+    #[serde(default = "default_icon_sizes")]
+    pub icon_sizes: Vec<String>,
+    /// The `MediaBaseUrl` of the metadata, which the URLs of its media are relative to.
+    pub media_base_url: Option<String>,
+}
+
+fn default_icon_sizes() -> Vec<String> {
+    vec!["48x48".into(), "64x64".into(), "128x128".into()]
+}
@@ -0,0 +1,207 @@
+use toml::Value;
+use toml::value::{Datetime, Table};
+
+/// The variables which debrep gives to its hooks, or reads itself, which are not config values.
+const RESERVED: &[&str] = &[
+    "DEBREP_OUTPUT_DIR",
+    "DEBREP_REPO",
+    "DEBREP_SERVE_AUTH",
+    "DEBREP_SOURCE",
+    "DEBREP_SOURCE_DIR",
+    "DEBREP_SUITE",
+];
+
+/// A scalar value of the config which is overridden for this run, by `--set key=value` or by a
+/// `DEBREP_*` environment variable, without being written back to the config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    /// The dotted path of the key, such as `archive` or `source.foo.version`.
+    pub key: String,
+    pub value: String,
+    /// The assignment is of a `DEBREP_*` variable, which is ignored unless it names a config field.
+    pub environment: bool,
+}
+
+impl Assignment {
+    /// Parses the `key=value` of `--set`.
+    pub fn parse(assignment: &str) -> Result<Assignment, String> {
+        match assignment.find('=') {
+            Some(pos) if pos != 0 => Ok(Assignment {
+                key: assignment[..pos].trim().to_owned(),
+                value: assignment[pos + 1..].to_owned(),
+                environment: false,
+            }),
+            _ => Err(format!("`{}` is not a `key=value` assignment", assignment)),
+        }
+    }
+}
+
+/// The assignments of each `DEBREP_*` variable of the environment, where the rest of its name is
+/// the key in lowercase, and `__` separates the keys of a table, as in `DEBREP_SIGNING__KEY`.
+pub fn environment_assignments<I: IntoIterator<Item = (String, String)>>(vars: I) -> Vec<Assignment> {
+    let mut assignments = vars.into_iter()
+        .filter(|&(ref name, _)| name.starts_with("DEBREP_") && !RESERVED.contains(&name.as_str()))
+        .map(|(name, value)| Assignment {
+            key: name[7..].to_lowercase().replace("__", "."),
+            value,
+            environment: true,
+        })
+        .filter(|assignment| !assignment.key.is_empty())
+        .collect::<Vec<Assignment>>();
+
+    assignments.sort_by(|a, b| a.key.cmp(&b.key));
+    assignments
+}
+
+/// Assigns a value to the key of a config, which is parsed as the type of the value which it
+/// replaces, returning whether the config did not define the key. A key which the config does
+/// not define is given a string, which the config may still expect as another type; see
+/// `retype`.
+///
+/// Each key of the path is that of a table, or the index of an array, or else the `name` of a
+/// table of an array, such as a source of the `source` array.
+pub fn assign(config: &mut Value, assignment: &Assignment) -> Result<bool, String> {
+    let key = assignment.key.as_str();
+    let (table, last) = parent_table(config, key)?;
+    let parsed = match table.get(last) {
+        Some(&Value::Array(_)) | Some(&Value::Table(_)) => {
+            return Err(format!("`{}` is not a scalar value", key));
+        }
+        Some(existing) => parse_as(existing, key, &assignment.value)?,
+        None => Value::String(assignment.value.clone())
+    };
+
+    let absent = table.insert(last.to_owned(), parsed).is_none();
+    Ok(absent)
+}
+
+/// Assigns the boolean or integer that the value parses as to a key which the config did not
+/// define, where the config refused the string that `assign` gave it, returning whether the
+/// value parses as either.
+pub fn retype(config: &mut Value, assignment: &Assignment) -> Result<bool, String> {
+    let typed = if let Ok(boolean) = assignment.value.parse::<bool>() {
+        Value::Boolean(boolean)
+    } else if let Ok(integer) = assignment.value.parse::<i64>() {
+        Value::Integer(integer)
+    } else {
+        return Ok(false);
+    };
+
+    let (table, last) = parent_table(config, &assignment.key)?;
+    table.insert(last.to_owned(), typed);
+    Ok(true)
+}
+
+/// Whether the key is defined by the config, such as a config which was serialized after it was
+/// parsed, where only the keys of its fields remain.
+pub fn defines(config: &Value, key: &str) -> bool {
+    key.split('.').fold(Some(config), |value, key| match value {
+        Some(&Value::Table(ref table)) => table.get(key),
+        Some(&Value::Array(ref array)) => match key.parse::<usize>() {
+            Ok(index) => array.get(index),
+            Err(_) => array.iter().find(|table| table.get("name").and_then(Value::as_str) == Some(key)),
+        },
+        _ => None
+    }).is_some()
+}
+
+/// The table which holds the last key of the path, which is created if it does not exist, and
+/// the last key.
+fn parent_table<'a, 'b>(config: &'a mut Value, key: &'b str) -> Result<(&'a mut Table, &'b str), String> {
+    let keys = key.split('.').collect::<Vec<&str>>();
+    if keys.iter().any(|key| key.is_empty()) {
+        return Err(format!("`{}` is not a key", key));
+    }
+
+    let (last, parents) = keys.split_last().unwrap();
+    let mut value = config;
+    for parent in parents {
+        let current = value;
+        value = match *current {
+            Value::Table(ref mut table) => table.entry(parent.to_string())
+                .or_insert_with(|| Value::Table(Table::new())),
+            Value::Array(ref mut array) => {
+                let element = match parent.parse::<usize>() {
+                    Ok(index) => array.get_mut(index),
+                    Err(_) => array.iter_mut()
+                        .find(|table| table.get("name").and_then(Value::as_str) == Some(*parent)),
+                };
+
+                element.ok_or_else(|| format!("`{}` does not define `{}`", key, parent))?
+            }
+            _ => return Err(format!("`{}` is not within a table", key)),
+        };
+    }
+
+    match *value {
+        Value::Table(ref mut table) => Ok((table, *last)),
+        _ => Err(format!("`{}` is not within a table", key)),
+    }
+}
+
+fn parse_as(existing: &Value, key: &str, value: &str) -> Result<Value, String> {
+    let parsed = match *existing {
+        Value::Boolean(_) => value.parse::<bool>().map(Value::Boolean).ok(),
+        Value::Datetime(_) => value.parse::<Datetime>().map(Value::Datetime).ok(),
+        Value::Float(_) => value.parse::<f64>().map(Value::Float).ok(),
+        Value::Integer(_) => value.parse::<i64>().map(Value::Integer).ok(),
+        _ => Some(Value::String(value.to_owned())),
+    };
+
+    parsed.ok_or_else(|| format!("`{}` must be of type {}, not `{}`", key, existing.type_str(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    #[test]
+    fn assigned_values() {
+        let mut config: Value = toml::from_str(r#"
+            archive = "cosmic"
+            retain = 2
+            architectures = ["amd64"]
+
+            [[source]]
+            name = "foo"
+            version = "1.0"
+        "#).unwrap();
+
+        let vars = vec![
+            ("DEBREP_ARCHIVE".to_owned(), "disco".to_owned()),
+            ("DEBREP_SIGNING__KEY".to_owned(), "ABCD1234".to_owned()),
+            ("DEBREP_SUITE".to_owned(), "bionic".to_owned()),
+            ("PATH".to_owned(), "/usr/bin".to_owned()),
+        ];
+
+        let mut assignments = environment_assignments(vars);
+        assert_eq!(assignments.iter().map(|x| x.key.as_str()).collect::<Vec<&str>>(), vec!["archive", "signing.key"]);
+
+        assignments.push(Assignment::parse("source.foo.version=1.1").unwrap());
+        assignments.push(Assignment::parse("retain=3").unwrap());
+        assignments.push(Assignment::parse("pocket=proposed").unwrap());
+        assignments.push(Assignment::parse("signing_key=12345678").unwrap());
+        for assignment in &assignments {
+            assign(&mut config, assignment).unwrap();
+        }
+
+        assert_eq!(config["archive"].as_str(), Some("disco"));
+        assert_eq!(config["signing"]["key"].as_str(), Some("ABCD1234"));
+        assert_eq!(config["source"][0]["version"].as_str(), Some("1.1"));
+        assert_eq!(config["retain"].as_integer(), Some(3));
+        assert_eq!(config["pocket"].as_str(), Some("proposed"));
+        assert_eq!(config["signing_key"].as_str(), Some("12345678"));
+
+        let fail = Assignment::parse("closure.fail=true").unwrap();
+        assert_eq!(assign(&mut config, &fail), Ok(true));
+        assert_eq!(retype(&mut config, &fail), Ok(true));
+        assert_eq!(config["closure"]["fail"].as_bool(), Some(true));
+        assert!(defines(&config, "source.foo.version") && !defines(&config, "source.bar.version"));
+
+        assert!(Assignment::parse("=value").is_err());
+        assert!(assign(&mut config, &Assignment::parse("retain=many").unwrap()).is_err());
+        assert!(assign(&mut config, &Assignment::parse("architectures=i386").unwrap()).is_err());
+        assert!(assign(&mut config, &Assignment::parse("source.bar.version=1.0").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+/// The tool which builds the packages of a source.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuilderBackend {
+    /// Builds within a clean schroot with `sbuild`.
+    Sbuild,
+    /// Builds within a clean base tarball with `pbuilder`.
+    Pbuilder,
+    /// Builds directly on the host with `dpkg-buildpackage`.
+    Dpkg,
+}
+
+impl Default for BuilderBackend {
+    fn default() -> Self { BuilderBackend::Sbuild }
+}
+
+/// Configures how sources are built. This may be given for the repo, and for each source, where
+/// the fields of a source take precedence over those of the repo.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Builder {
+    pub backend: Option<BuilderBackend>,
+    /// The schroot of sbuild, or the base tarball of pbuilder, to build within.
+    pub chroot: Option<String>,
+    /// The distribution to build for, which defaults to the suite.
+    pub distribution: Option<String>,
+    /// Repos to enable within the chroot, in addition to the suite's `extra_repos`.
+    pub extra_repos: Option<Vec<String>>,
+    /// The architectures to build for, which default to the suite's architectures.
+    pub architectures: Option<Vec<String>>,
+    /// The mirror that a new chroot is bootstrapped from.
+    pub mirror: Option<String>,
+}
+
+impl Builder {
+    /// Fills the unset fields of this builder from another.
+    pub fn or(&self, other: &Builder) -> Builder {
+        Builder {
+            backend: self.backend.or(other.backend),
+            chroot: self.chroot.clone().or_else(|| other.chroot.clone()),
+            distribution: self.distribution.clone().or_else(|| other.distribution.clone()),
+            extra_repos: self.extra_repos.clone().or_else(|| other.extra_repos.clone()),
+            architectures: self.architectures.clone().or_else(|| other.architectures.clone()),
+            mirror: self.mirror.clone().or_else(|| other.mirror.clone()),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+/// Publishes the changelog of each source of the suite under `changelogs/<suite>/`, where
+/// `apt changelog` finds it by the `Changelogs` field of the `Release` file.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Changelogs {
+    /// The URL that the `changelogs` directory of the repo is served from, such as
+    /// `https://apt.example.com/changelogs`, or else the template of the `Changelogs` field
+    /// itself, if it contains `@CHANGEPATH@`.
+    pub url: String,
+}
+
+impl Changelogs {
+    /// The `Changelogs` field of the suite, where apt replaces `@CHANGEPATH@` with the path of
+    /// a source, as in `main/f/foo/foo_1.0-1`.
+    pub fn template(&self, suite: &str) -> String {
+        if self.url.contains("@CHANGEPATH@") {
+            self.url.clone()
+        } else {
+            [self.url.trim_right_matches('/'), "/", suite, "/@CHANGEPATH@/changelog"].concat()
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use checksum::SHA256;
+use http;
+use misc;
+use reqwest::{Client, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use toml::{self, de, Value};
+use url::UrlTokenizer;
+use super::{expand_suites, resolve_includes, Config, Exclusion, ParsingError, SourceLocation};
+
+/// A problem found within a config, and the line and column where it was found, if it has one.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The line and column of the problem, which count from one.
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Checks the config at `path` for problems which would otherwise only be found during a build.
+///
+/// Syntax errors, type errors, and missing fields are reported first, as nothing else can be
+/// checked without them. Otherwise, keys which are ignored by the config, conflicting package
+/// definitions, and duplicate package names are reported, along with unreachable source URLs if
+/// `urls` is set.
+pub fn check(path: &Path, urls: bool) -> Result<Vec<Diagnostic>, ParsingError> {
+    let buffer = misc::read(path)
+        .map_err(|why| ParsingError::File { file: path.to_path_buf(), why })?;
+    Ok(diagnose(path, &String::from_utf8_lossy(&buffer), urls))
+}
+
+fn diagnose(path: &Path, text: &str, urls: bool) -> Vec<Diagnostic> {
+    let positions = Positions::scan(text);
+    let input = match toml::from_str::<Value>(text) {
+        Ok(input) => input,
+        Err(why) => return vec![Diagnostic { position: line_col(&why), message: why.to_string() }],
+    };
+
+    // Only the keys of the config itself have positions, so a config which includes other files,
+    // or which has defaults, is checked by its merged keys instead of its text.
+    let merged = input.get("include").is_some() || input.get("defaults").is_some();
+    let input = match resolve_includes(path, input) {
+        Ok((input, _)) => input,
+        Err(why) => return vec![Diagnostic { position: positions.locate(&["include"]), message: why }],
+    };
+
+    let mut diagnostics = Vec::new();
+    if input.get("suites").is_none() {
+        let config = if merged { input.clone().try_into() } else { toml::from_str(text) };
+        diagnose_config(&input, config, &positions, urls, &mut diagnostics);
+    } else {
+        // Each suite is checked as it is expanded, and problems that they share are reported once.
+        match expand_suites(input) {
+            Ok(suites) => for suite in suites {
+                let config = suite.clone().try_into();
+                diagnose_config(&suite, config, &positions, urls, &mut diagnostics);
+            },
+            Err(why) => diagnostics.push(Diagnostic {
+                position: positions.locate(&["suites"]),
+                message: why,
+            }),
+        }
+    }
+
+    diagnostics.sort_by(|a, b| (a.position, &a.message).cmp(&(b.position, &b.message)));
+    diagnostics.dedup();
+    diagnostics
+}
+
+fn diagnose_config(
+    input: &Value,
+    config: Result<Config, de::Error>,
+    positions: &Positions,
+    urls: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let diagnostic = |path: &[&str], message: String| Diagnostic {
+        position: positions.locate(path),
+        message,
+    };
+
+    let config = match config {
+        Ok(config) => config,
+        Err(why) => {
+            // Errors which are not given a position, such as missing fields, may name their table.
+            let message = why.to_string();
+            let position = line_col(&why).or_else(|| {
+                let key = message.find("for key `").map(|pos| &message[pos + 9..])?;
+                let key = &key[..key.find('`')?];
+                positions.locate(&key.split('.').collect::<Vec<&str>>())
+            });
+
+            diagnostics.push(Diagnostic { position, message });
+            return;
+        }
+    };
+
+    // Keys which were ignored by the config do not survive a round trip through it.
+    let output = Value::try_from(&config).ok();
+    let mut ignored = Vec::new();
+    ignored_keys(input, output.as_ref(), &mut Vec::new(), &mut ignored);
+    for path in ignored {
+        let path = path.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+        diagnostics.push(diagnostic(&path, format!("unknown key `{}`", path.join("."))));
+    }
+
+    if config.compression() == 0 {
+        let message = "no compression formats were given for the dist indices";
+        diagnostics.push(diagnostic(&["compression"], message.into()));
+    }
+
+    if config.digests() & SHA256 == 0 {
+        let message = "the digests of the indices do not include sha256, which apt requires";
+        diagnostics.push(diagnostic(&["digests"], message.into()));
+    }
+
+    if let Some(ref cache) = config.compiler_cache {
+        if !cache.directory.is_absolute() {
+            let message = "the compiler cache must be an absolute path, as it is mounted at that path in each chroot";
+            diagnostics.push(diagnostic(&["compiler_cache", "directory"], message.into()));
+        }
+    }
+
+    for (id, exclusion) in config.exclude.iter().flat_map(|x| x.iter()).enumerate() {
+        if *exclusion == Exclusion::default() {
+            let message = "an exclusion without any globs excludes every package";
+            diagnostics.push(diagnostic(&["exclude", &id.to_string()], message.into()));
+        }
+    }
+
+    let mut defined: HashMap<&str, (&str, String)> = HashMap::new();
+    for (id, direct) in config.direct.iter().flat_map(|x| x.iter()).enumerate() {
+        let id = id.to_string();
+        match (direct.urls.is_empty(), direct.path.is_some()) {
+            (false, true) => diagnostics.push(diagnostic(
+                &["direct", &id, "path"],
+                format!("both `urls` and a `path` are defined for {}, but not both", direct.name)
+            )),
+            (true, false) => diagnostics.push(diagnostic(
+                &["direct", &id],
+                format!("neither `urls` nor a `path` are defined for {}", direct.name)
+            )),
+            _ => ()
+        }
+
+        duplicate(diagnostics, &mut defined, positions, "direct", id, &direct.name);
+    }
+
+    for (id, source) in config.source.iter().flat_map(|x| x.iter()).enumerate() {
+        if source.location.is_none() {
+            diagnostics.push(diagnostic(
+                &["source", &id.to_string()],
+                format!("no location is defined to fetch the source of {} from", source.name)
+            ));
+        }
+
+        let id = id.to_string();
+        match (&source.signature, &source.location) {
+            (&Some(_), &Some(SourceLocation::URL { .. })) | (&None, _) => (),
+            (&Some(_), _) => diagnostics.push(diagnostic(
+                &["source", &id, "signature"],
+                format!(
+                    "{} has a signature, but only the tarball of a `url` location is verified by one",
+                    source.name
+                )
+            )),
+        }
+
+        let builder = config.builder_for(source);
+        let architectures = builder.architectures.as_ref().unwrap_or(&config.architectures);
+        for arch in source.archs.iter().flat_map(|x| x.iter()) {
+            if !architectures.contains(arch) {
+                diagnostics.push(diagnostic(
+                    &["source", &id, "archs"],
+                    format!(
+                        "{} is restricted to {}, which the suite is not built for",
+                        source.name,
+                        arch
+                    )
+                ));
+            }
+        }
+
+        duplicate(diagnostics, &mut defined, positions, "source", id, &source.name);
+    }
+
+    if urls {
+        let client = match http::client(&config.http_settings()) {
+            Ok(client) => client,
+            Err(why) => {
+                diagnostics.push(diagnostic(&["http"], format!("invalid HTTP settings: {}", why)));
+                return;
+            }
+        };
+
+        for (path, url) in source_urls(&config) {
+            let path = path.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+            match url {
+                Ok(url) => if let Err(why) = reachable(&client, &url) {
+                    diagnostics.push(diagnostic(&path, format!("{} is unreachable: {}", url, why)));
+                },
+                Err(text) => {
+                    diagnostics.push(diagnostic(&path, format!("unsupported variable: {}", text)))
+                }
+            }
+        }
+    }
+}
+
+/// Reports a package which was already defined, by either the same or another kind of package.
+fn duplicate<'a>(
+    diagnostics: &mut Vec<Diagnostic>,
+    defined: &mut HashMap<&'a str, (&'a str, String)>,
+    positions: &Positions,
+    kind: &'a str,
+    id: String,
+    name: &'a str,
+) {
+    if let Some(&(first_kind, ref first_id)) = defined.get(name) {
+        let first = match positions.locate(&[first_kind, first_id, "name"]) {
+            Some((line, _)) => format!(", which was first defined on line {}", line),
+            None => String::new(),
+        };
+
+        let message = if first_kind == kind {
+            format!("duplicate {} package name `{}`{}", kind, name, first)
+        } else {
+            format!("`{}` is both a {} package and a {} package{}", name, first_kind, kind, first)
+        };
+
+        diagnostics.push(Diagnostic { position: positions.locate(&[kind, &id, "name"]), message });
+        return
+    }
+
+    defined.insert(name, (kind, id));
+}
+
+/// The aliases which the config accepts for some of its keys, by the key that they are read as.
+const ALIASES: &[(&str, &str)] = &[("checksum", "sha256")];
+
+/// Collects the path of each key within the input which is missing from the output.
+///
+/// A key which is an alias is compared to the key that it was read as, which the output has.
+fn ignored_keys(
+    input: &Value,
+    output: Option<&Value>,
+    path: &mut Vec<String>,
+    ignored: &mut Vec<Vec<String>>,
+) {
+    match (input, output) {
+        (&Value::Table(ref input), Some(&Value::Table(ref output))) => {
+            for (key, value) in input {
+                path.push(key.clone());
+                let aliased = || ALIASES.iter()
+                    .find(|&&(alias, _)| alias == key)
+                    .and_then(|&(_, canonical)| output.get(canonical));
+                match output.get(key).or_else(aliased) {
+                    Some(output) => ignored_keys(value, Some(output), path, ignored),
+                    None => ignored.push(path.clone()),
+                }
+                path.pop();
+            }
+        }
+        (&Value::Array(ref input), Some(&Value::Array(ref output))) => {
+            for (id, value) in input.iter().enumerate() {
+                path.push(id.to_string());
+                ignored_keys(value, output.get(id), path, ignored);
+                path.pop();
+            }
+        }
+        _ => ()
+    }
+}
+
+/// The URLs of each direct package and source which are fetched over HTTP, by their key.
+fn source_urls(config: &Config) -> Vec<(Vec<String>, Result<String, String>)> {
+    let mut urls = Vec::new();
+    for (id, direct) in config.direct.iter().flat_map(|x| x.iter()).enumerate() {
+        for (url_id, item) in direct.urls.iter().enumerate() {
+            let name = item.name.as_ref().unwrap_or(&direct.name);
+            let url = UrlTokenizer::finalize(&item.url, name, &direct.version)
+                .map_err(|text| text.to_owned());
+            let path = vec![
+                "direct".into(),
+                id.to_string(),
+                "urls".into(),
+                url_id.to_string(),
+                "url".into(),
+            ];
+
+            urls.push((path, url));
+        }
+    }
+
+    for (id, source) in config.source.iter().flat_map(|x| x.iter()).enumerate() {
+        if let Some(ref signature) = source.signature {
+            let path = vec!["source".into(), id.to_string(), "signature".into(), "url".into()];
+            urls.push((path, Ok(signature.url.clone())));
+        }
+
+        let (key, url) = match source.location {
+            Some(SourceLocation::URL { ref url, .. }) => ("url", url),
+            Some(SourceLocation::Dsc { ref dsc }) => ("dsc", dsc),
+            _ => continue
+        };
+
+        let url = match source.version {
+            Some(ref version) => {
+                UrlTokenizer::finalize(url, &source.name, version).map_err(|text| text.to_owned())
+            }
+            None => Ok(url.clone()),
+        };
+
+        urls.push((vec!["source".into(), id.to_string(), "location".into(), key.into()], url));
+    }
+
+    urls
+}
+
+fn reachable(client: &Client, url: &str) -> Result<(), String> {
+    let mut response = client.head(url).send().map_err(|why| why.to_string())?;
+
+    // Some servers do not answer HEAD requests, which are retried as GET requests.
+    if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+        response = client.get(url).send().map_err(|why| why.to_string())?;
+    }
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(response.status().to_string())
+    }
+}
+
+fn line_col(why: &toml::de::Error) -> Option<(usize, usize)> {
+    why.line_col().map(|(line, column)| (line + 1, column + 1))
+}
+
+/// The line and column of each key and table header within a config, by the dotted path of the
+/// key, where the elements of an array of tables are given by their index.
+struct Positions(HashMap<String, (usize, usize)>);
+
+impl Positions {
+    fn scan(text: &str) -> Positions {
+        let mut positions = HashMap::new();
+        let mut arrays: HashMap<String, usize> = HashMap::new();
+        let mut table = String::new();
+        let mut multiline = false;
+
+        for (number, line) in text.lines().enumerate() {
+            let trimmed = line.trim_left();
+            let position = (number + 1, line.len() - trimmed.len() + 1);
+            let within_string = multiline;
+            let delimiters = line.matches("\"\"\"").count() + line.matches("'''").count();
+            if delimiters % 2 == 1 {
+                multiline = !multiline;
+            }
+
+            if within_string || trimmed.is_empty() || trimmed.starts_with('#') {
+                continue
+            }
+
+            if trimmed.starts_with("[[") {
+                let header = match trimmed.find("]]") {
+                    Some(end) => resolve(&arrays, &trimmed[2..end]),
+                    None => continue
+                };
+
+                let count = arrays.entry(header.clone()).or_insert(0);
+                table = [&header, ".", &count.to_string()].concat();
+                *count += 1;
+                positions.entry(header).or_insert(position);
+                positions.entry(table.clone()).or_insert(position);
+            } else if trimmed.starts_with('[') {
+                table = match trimmed.find(']') {
+                    Some(end) => resolve(&arrays, &trimmed[1..end]),
+                    None => continue
+                };
+
+                positions.entry(table.clone()).or_insert(position);
+            } else if let Some(end) = trimmed.find('=') {
+                let key = trimmed[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+                let bare = key.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+                if key.is_empty() || !bare {
+                    continue
+                }
+
+                let path = if table.is_empty() {
+                    key.to_owned()
+                } else {
+                    [&table, ".", key].concat()
+                };
+                positions.entry(path).or_insert(position);
+            }
+        }
+
+        Positions(positions)
+    }
+
+    /// The position of a key, or of the nearest table which contains it, if the key was not found.
+    fn locate(&self, path: &[&str]) -> Option<(usize, usize)> {
+        (1..path.len() + 1).rev()
+            .filter_map(|len| self.0.get(&path[..len].join(".")))
+            .next()
+            .cloned()
+    }
+}
+
+/// Resolves a table header to its path, where the tables above it which are arrays of tables
+/// refer to their last element.
+fn resolve(arrays: &HashMap<String, usize>, header: &str) -> String {
+    let mut path = String::new();
+    for segment in header.split('.') {
+        if !path.is_empty() {
+            if let Some(count) = arrays.get(&path) {
+                path.push('.');
+                path.push_str(&(count - 1).to_string());
+            }
+
+            path.push('.');
+        }
+
+        path.push_str(segment.trim().trim_matches(|c| c == '"' || c == '\''));
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_diagnostics() {
+        let config = r#"archive = "cosmic"
+version = "18.10"
+origin = "pop-os"
+label = "Pop!_OS"
+email = "info@system76.com"
+architectures = ["amd64"]
+arhcitectures = ["i386"]
+
+[[direct]]
+name = "foo"
+version = "1.0"
+path = "vendor/foo"
+urls = [{ url = "https://example.com/foo_1.0_amd64.deb" }]
+
+[[source]]
+name = "bar"
+location = { git = "https://example.com/bar" }
+
+[[source]]
+name = "foo"
+
+[source.location]
+git = "https://example.com/foo"
+brnach = "master"
+"#;
+
+        let diagnostics = diagnose(Path::new("debrep.toml"), config, false).into_iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<String>>();
+
+        assert_eq!(diagnostics, vec![
+            "7:1: unknown key `arhcitectures`",
+            "12:1: both `urls` and a `path` are defined for foo, but not both",
+            "20:1: `foo` is both a direct package and a source package, which was first defined \
+                on line 10",
+            "24:1: unknown key `source.1.location.brnach`",
+        ]);
+
+        let syntax = diagnose(Path::new("debrep.toml"), "archive = \"cosmic\"\nversion = [\n", false);
+        assert_eq!(syntax.len(), 1);
+        assert!(syntax[0].position.is_some());
+
+        let missing = diagnose(Path::new("debrep.toml"), "archive = \"cosmic\"\n", false);
+        assert!(missing[0].message.contains("missing field `version`"));
+
+        let archs = diagnose(Path::new("debrep.toml"), r#"archive = "cosmic"
+version = "18.10"
+origin = "pop-os"
+label = "Pop!_OS"
+email = "info@system76.com"
+architectures = ["amd64"]
+
+[[source]]
+name = "foo"
+location = { git = "https://example.com/foo" }
+archs = ["amd64", "arm64"]
+"#, false);
+        assert_eq!(archs.len(), 1);
+        assert_eq!(archs[0].message, "foo is restricted to arm64, which the suite is not built for");
+    }
+
+    #[test]
+    fn aliased_keys() {
+        let config = r#"archive = "cosmic"
+version = "18.10"
+origin = "pop-os"
+label = "Pop!_OS"
+email = "info@system76.com"
+
+[[source]]
+name = "foo"
+location = { url = "https://example.com/foo-1.0.tar.gz", checksum = "abc123" }
+"#;
+
+        assert_eq!(diagnose(Path::new("debrep.toml"), config, false), Vec::new());
+    }
+}
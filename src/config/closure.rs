@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+/// Resolves the dependencies of every package of the generated `Packages` indices, against the
+/// packages of the suite and of the upstream repositories that it is installed alongside.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Closure {
+    /// The repositories whose packages satisfy the dependencies that the suite does not.
+    #[serde(default)]
+    pub upstream: Vec<Upstream>,
+    /// Fails the generation of the dist files if any dependency cannot be satisfied, so that
+    /// the suite is not published, rather than only reporting it.
+    #[serde(default)]
+    pub fail: bool,
+}
+
+/// A suite of an existing apt repository, such as the distribution that the suite extends.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Upstream {
+    /// The root of the apt repository, which contains its `dists` directory.
+    pub url: String,
+    /// The suite of the apt repository whose indices are resolved against.
+    pub suite: String,
+    /// The keyring which verifies the `InRelease`, or `Release.gpg`, of the suite.
+    pub keyring: PathBuf,
+    /// The components of the suite whose indices are resolved against.
+    #[serde(default = "default_components")]
+    pub components: Vec<String>,
+}
+
+fn default_components() -> Vec<String> { vec!["main".into()] }
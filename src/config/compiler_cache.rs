@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+/// The compiler cache which is shared by the builds of a suite.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompilerCacheTool {
+    /// Caches C and C++ compilations, as `/usr/lib/ccache` is searched before the compilers.
+    Ccache,
+    /// Caches Rust compilations, as `RUSTC_WRAPPER` is set to `sccache`.
+    Sccache,
+}
+
+impl Default for CompilerCacheTool {
+    fn default() -> Self { CompilerCacheTool::Ccache }
+}
+
+impl CompilerCacheTool {
+    /// The package, and command, of the tool.
+    pub fn name(self) -> &'static str {
+        match self {
+            CompilerCacheTool::Ccache => "ccache",
+            CompilerCacheTool::Sccache => "sccache",
+        }
+    }
+}
+
+/// A directory of the host which caches the compilations of every build of the suite, and which
+/// is mounted at the same path within each build environment.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct CompilerCache {
+    #[serde(default)]
+    pub tool: CompilerCacheTool,
+    /// An absolute path, as it is the same within the chroots as it is on the host.
+    pub directory: PathBuf,
+    /// The size that the cache is trimmed to, such as `20G`.
+    pub max_size: Option<String>,
+}
+
+impl CompilerCache {
+    /// The variables which configure the tool within a build, where `path` is the `PATH` which
+    /// the compilers are otherwise found by.
+    pub fn env(&self, path: &str) -> Vec<(String, String)> {
+        let directory = self.directory.display().to_string();
+        let mut env = match self.tool {
+            CompilerCacheTool::Ccache => vec![
+                ("CCACHE_DIR".to_owned(), directory),
+                ("PATH".to_owned(), ["/usr/lib/ccache:", path].concat()),
+            ],
+            CompilerCacheTool::Sccache => vec![
+                ("SCCACHE_DIR".to_owned(), directory),
+                ("RUSTC_WRAPPER".to_owned(), "sccache".to_owned()),
+            ],
+        };
+
+        if let Some(ref size) = self.max_size {
+            let key = match self.tool {
+                CompilerCacheTool::Ccache => "CCACHE_MAXSIZE",
+                CompilerCacheTool::Sccache => "SCCACHE_CACHE_SIZE",
+            };
+
+            env.push((key.to_owned(), size.clone()));
+        }
+
+        env
+    }
+}
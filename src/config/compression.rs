@@ -0,0 +1,22 @@
+use compress::*;
+
+/// A compression format that the dist indices may be published with.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionFormat {
+    Uncompressed,
+    Gz,
+    Xz,
+    Zstd,
+}
+
+impl CompressionFormat {
+    pub fn flag(self) -> u8 {
+        match self {
+            CompressionFormat::Uncompressed => UNCOMPRESSED,
+            CompressionFormat::Gz => GZ_COMPRESS,
+            CompressionFormat::Xz => XZ_COMPRESS,
+            CompressionFormat::Zstd => ZSTD_COMPRESS,
+        }
+    }
+}
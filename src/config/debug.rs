@@ -0,0 +1,22 @@
+/// Routes the debug symbol packages of the suite, such as `foo-dbgsym` and `.ddeb` archives,
+/// away from the indices of its other packages.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum DebugSymbols {
+    /// Stores the debug symbols of each component in a subcomponent, such as `main/debug`.
+    Component { component: String },
+    /// Stores the debug symbols in a suite of their own, such as `bionic-debug`.
+    Suite { suite: String },
+}
+
+impl DebugSymbols {
+    /// The suite and component that the debug symbols of a component are stored in.
+    pub fn destination<'a>(&'a self, suite: &'a str, component: &str) -> (&'a str, String) {
+        match *self {
+            DebugSymbols::Component { component: ref debug } => {
+                (suite, [component, "/", debug].concat())
+            }
+            DebugSymbols::Suite { ref suite } => (suite, component.to_owned()),
+        }
+    }
+}
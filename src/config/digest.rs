@@ -0,0 +1,22 @@
+use checksum::{MD5, SHA1, SHA256, SHA512};
+
+/// A digest family that the `Release`, `Packages`, and `Sources` indices may list.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    pub fn flag(self) -> u8 {
+        match self {
+            DigestAlgorithm::Md5 => MD5,
+            DigestAlgorithm::Sha1 => SHA1,
+            DigestAlgorithm::Sha256 => SHA256,
+            DigestAlgorithm::Sha512 => SHA512,
+        }
+    }
+}
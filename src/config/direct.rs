@@ -1,5 +1,5 @@
 use debian::DEB_SOURCE_EXTENSIONS;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::borrow::Cow;
 use std::io;
 use url::UrlTokenizer;
@@ -25,11 +25,15 @@ pub struct BinaryDestinations {
     pub pool: PathBuf,
     /// Where the file can be obtained
     pub url: String,
+    /// The SHA256 digest that the downloaded file is verified against, if one was given.
+    pub sha256: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct DirectPath {
     pub checksum: Option<String>,
+    /// The SHA256 digest of the file, which may be given in place of `checksum`.
+    pub sha256:   Option<String>,
     pub arch:     Option<String>,
     pub name:     Option<String>,
     pub url:      String,
@@ -40,13 +44,38 @@ pub struct DirectPath {
 pub struct Direct {
     pub name:      String,
     pub version:   String,
+    #[serde(default)]
     pub urls:      Vec<DirectPath>,
     pub checksum:  Option<String>,
+    /// A local directory of prebuilt packages, such as vendor packages, to copy into the pool.
+    pub path:      Option<PathBuf>,
+    /// Hard-links the packages of `path` into the pool, rather than copying them, so that they
+    /// are stored once however many suites publish them.
+    #[serde(default)]
+    pub link:      bool,
+    /// The component that this package is stored in, instead of the default.
+    pub component: Option<String>,
     pub update:    Option<Update>,
+    /// Only the files of these architectures, and those which are architecture independent, are
+    /// downloaded from `urls`.
+    pub archs:     Option<Vec<String>>,
+}
+
+impl DirectPath {
+    /// The SHA256 digest that the downloaded file is verified against, if one was given.
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_ref().or_else(|| self.checksum.as_ref()).map(|x| x.as_str())
+    }
 }
 
 impl Direct {
-    pub fn get_destinations(&self, suite: &str, component: &str) -> io::Result<Vec<BinaryDestinations>> {
+    /// Whether the files of this architecture are downloaded.
+    fn includes(&self, arch: &str) -> bool {
+        arch == "all" || self.archs.as_ref().map_or(true, |archs| archs.iter().any(|x| x == arch))
+    }
+
+    pub fn get_destinations(&self, pool: &Path, suite: &str, component: &str) -> io::Result<Vec<BinaryDestinations>> {
+        let component = self.component.as_ref().map_or(component, |x| x.as_str());
         let mut output = Vec::new();
 
         fn gen_filename(name: &str, version: &str, arch: &str, ext: &str) -> String {
@@ -89,12 +118,18 @@ impl Direct {
                     None => misc::get_arch_from_stem(&file[..ext_pos - 1]),
                 };
 
-                let filename = gen_filename(name, &self.version, arch, extension);
-                let dst = match extension {
-                    "tar.gz" | "tar.xz" | "dsc" => ["/", component, "/source/"].concat(),
-                    _ => ["/", component, "/binary-", arch, "/"].concat()
+                let (dst, restricted) = match extension {
+                    "tar.gz" | "tar.xz" | "dsc" => (["/", component, "/source/"].concat(), false),
+                    _ => (["/", component, "/binary-", arch, "/"].concat(), !self.includes(arch))
                 };
 
+                if restricted {
+                    debug!("skipping {}, as {} is not built for {}", url, self.name, arch);
+                    continue
+                }
+
+                let filename = gen_filename(name, &self.version, arch, extension);
+
                 if extension == "deb" {
                     let base = format!("assets/replace/{}{}/{}/", suite, dst, name);
                     let files = PathBuf::from([&base, "files"].concat());
@@ -106,10 +141,11 @@ impl Direct {
                 }
 
 
-                PathBuf::from(["repo/pool/", suite, &dst, &name[0..1], "/", name, "/", &filename].concat())
+                pool.join([suite, &dst, misc::pool_bucket(name)?, "/", name, "/", &filename].concat())
             };
 
-            output.push(BinaryDestinations { assets, pool, url });
+            let sha256 = file_item.sha256().map(|x| x.to_owned());
+            output.push(BinaryDestinations { assets, pool, url, sha256 });
         }
 
         Ok(output)
@@ -122,6 +158,8 @@ impl ConfigFetch for Direct {
             "name" => Some(Cow::Borrowed(&self.name)),
             "version" => Some(Cow::Borrowed(&self.version)),
             "urls" => Some(Cow::Owned(format!("{:#?}", self.urls))),
+            "component" => self.component.as_ref().map(|x| Cow::Borrowed(x.as_str())),
+            "path" => self.path.as_ref().map(|x| x.to_string_lossy()),
             _ => None,
         }
     }
@@ -130,6 +168,8 @@ impl ConfigFetch for Direct {
         match key {
             "name" => self.name = value,
             "version" => self.version = value,
+            "component" => self.component = Some(value),
+            "path" => self.path = Some(PathBuf::from(value)),
             _ => return Err(ConfigError::InvalidKey),
         }
 
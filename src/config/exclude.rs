@@ -0,0 +1,65 @@
+use super::mirror::matches_glob;
+
+/// Keeps the packages of the pool which match each of its globs out of the published indices,
+/// without removing them from the pool, such as to pull a bad build while keeping its archive.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Exclusion {
+    /// The name of the package, or of the source package within the `Sources` index.
+    pub package: Option<String>,
+    /// The version of the package, without its epoch.
+    pub version: Option<String>,
+    /// The architecture of the package, which is `source` within the `Sources` index.
+    pub arch: Option<String>,
+    /// The section of the package, once any overrides have replaced it.
+    pub section: Option<String>,
+}
+
+impl Exclusion {
+    /// Whether the package matches every glob of the exclusion. A package whose section is not
+    /// yet known only matches the exclusions which do not have a section.
+    pub fn matches(&self, package: &str, version: &str, arch: &str, section: Option<&str>) -> bool {
+        let matches = |pattern: &Option<String>, input: &str| {
+            pattern.as_ref().map_or(true, |pattern| matches_glob(pattern, input))
+        };
+
+        let version = version.find(':').map_or(version, |pos| &version[pos + 1..]);
+        let section = match (self.section.as_ref(), section) {
+            (Some(pattern), Some(section)) => matches_glob(pattern, section),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        section
+            && matches(&self.package, package)
+            && matches(&self.version, version)
+            && matches(&self.arch, arch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_exclusion() {
+        let bad_build = Exclusion {
+            package: Some("libfoo*".into()),
+            version: Some("1.2.*".into()),
+            ..Exclusion::default()
+        };
+
+        assert!(bad_build.matches("libfoo1", "1:1.2.3-1", "amd64", None));
+        assert!(bad_build.matches("libfoo-dev", "1.2.0-1", "source", Some("libdevel")));
+        assert!(!bad_build.matches("libfoo1", "1.3.0-1", "amd64", None));
+
+        let games = Exclusion {
+            section: Some("games".into()),
+            arch: Some("i386".into()),
+            ..Exclusion::default()
+        };
+
+        assert!(!games.matches("frozen-bubble", "2.2.1", "i386", None));
+        assert!(games.matches("frozen-bubble", "2.2.1", "i386", Some("games")));
+        assert!(!games.matches("frozen-bubble", "2.2.1", "amd64", Some("games")));
+    }
+}
@@ -0,0 +1,23 @@
+use checksum::DEFAULT_MMAP_THRESHOLD;
+
+/// How the files of the pool are read to be hashed, which `debrep benchmark` may be used to tune.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Hashing {
+    /// Files of at least this many bytes are hashed from a memory map, rather than read through a
+    /// buffer, which defaults to 64 MiB.
+    pub mmap_threshold: Option<u64>,
+    /// Reads every file through a buffer, such as when the pool is on a network filesystem.
+    #[serde(default)]
+    pub no_mmap: bool,
+}
+
+impl Hashing {
+    /// The size from which files are hashed from a memory map, if they are mapped at all.
+    pub fn mmap_threshold(&self) -> Option<u64> {
+        if self.no_mmap {
+            None
+        } else {
+            Some(self.mmap_threshold.unwrap_or(DEFAULT_MMAP_THRESHOLD))
+        }
+    }
+}
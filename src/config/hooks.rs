@@ -0,0 +1,18 @@
+/// Scripts which are run by `sh -c` from the project directory before and after a source is
+/// built, with `DEBREP_SUITE`, `DEBREP_SOURCE`, `DEBREP_SOURCE_DIR`, and `DEBREP_OUTPUT_DIR` in
+/// their environment. A hook which fails fails the build of its source.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct SourceHooks {
+    /// Runs once the source is prepared, with its patches applied, before it is built.
+    pub prebuild: Option<String>,
+    /// Runs once the source is built, before its packages are moved into the pool.
+    pub postbuild: Option<String>,
+}
+
+/// Scripts which are run by `sh -c` from the project directory, for the suite as a whole.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Hooks {
+    /// Runs once the dist files of the suite are generated, such as to purge a CDN, with
+    /// `DEBREP_SUITE` and `DEBREP_REPO` in its environment.
+    pub post_generate: Option<String>,
+}
@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+/// Configures the proxies and certificates that upstream packages, sources, and indices are
+/// fetched with.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct HttpSettings {
+    /// The proxy of `http` URLs, which defaults to `http_proxy`.
+    pub http_proxy: Option<String>,
+    /// The proxy of `https` URLs, which defaults to `https_proxy`.
+    pub https_proxy: Option<String>,
+    /// Hosts which are fetched without a proxy, in addition to those of `no_proxy`. A host which
+    /// begins with `.` matches each of its subdomains.
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    /// PEM or DER CA certificates which are trusted as roots in addition to those of the system,
+    /// such as the CA of a proxy which intercepts TLS. They do not pin the certificate of a host.
+    #[serde(default)]
+    pub certificates: Vec<PathBuf>,
+}
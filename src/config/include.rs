@@ -0,0 +1,290 @@
+use glob::glob;
+use misc;
+use std::collections::BTreeMap;
+use std::mem;
+use std::path::{Path, PathBuf};
+use super::suites::merge;
+use toml::{self, Value};
+use toml::value::Table;
+
+/// The arrays of packages, which are named by their `name`, that a `defaults` table may give
+/// common keys to.
+const PACKAGES: &[&str] = &["direct", "source"];
+
+/// The file which defined each key and package of a config, by its dotted path, where any key
+/// which was not included from another file was defined by the config itself.
+struct Origins<'a> {
+    config: &'a Path,
+    defined: BTreeMap<String, PathBuf>,
+}
+
+impl<'a> Origins<'a> {
+    /// The file which defined the key, which is that of the deepest table containing it that
+    /// was included.
+    fn of(&self, key: &str) -> &Path {
+        self.defined.iter()
+            .filter(|&(defined, _)| key == defined.as_str() || key.starts_with(&[defined.as_str(), "."].concat()))
+            .max_by_key(|&(defined, _)| defined.len())
+            .map_or(self.config, |(_, file)| file.as_path())
+    }
+
+    fn conflict(&self, key: &str, file: &Path) -> String {
+        format!("`{}` is defined by both {} and {}", key, self.of(key).display(), file.display())
+    }
+}
+
+/// Resolves the `include` globs and the `defaults` table of the config at `path`, returning the
+/// config with each included file merged into it, and the files which were included.
+///
+/// Globs are relative to the directory of the config, and the files that each matches are
+/// included in the order of their paths. The arrays of tables of an included file, such as its
+/// `source` and `direct` packages, are appended to those of the config, and its tables are
+/// merged key by key. A package or key which two files define differently is an error naming
+/// both of them.
+///
+/// The `[defaults.source]` and `[defaults.direct]` tables are merged beneath each package of that
+/// kind, where the defaults of an included file only apply to its own packages, and are merged
+/// over the defaults of the config.
+pub fn resolve_includes(path: &Path, mut config: Value) -> Result<(Value, Vec<PathBuf>), String> {
+    let mut included = Vec::new();
+    if !config.is_table() {
+        return Ok((config, included));
+    }
+
+    {
+        let table = config.as_table_mut().unwrap();
+
+        let patterns = match table.remove("include") {
+            Some(Value::Array(patterns)) => patterns.into_iter()
+                .map(|pattern| pattern.as_str().map(String::from))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| "`include` must be an array of globs".to_owned())?,
+            Some(_) => return Err("`include` must be an array of globs".into()),
+            None => Vec::new(),
+        };
+
+        let defaults = table.remove("defaults");
+        let mut origins = Origins { config: path, defined: BTreeMap::new() };
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        for pattern in patterns {
+            let pattern = directory.join(&pattern);
+            let mut files = glob(&pattern.to_string_lossy())
+                .map_err(|why| format!("invalid include {}: {}", pattern.display(), why))?
+                .collect::<Result<Vec<PathBuf>, _>>()
+                .map_err(|why| why.to_string())?;
+
+            if files.is_empty() {
+                warn!("{} does not include any files", pattern.display());
+            }
+
+            files.sort();
+            for file in files {
+                // A file which is matched by several globs is only included once.
+                if included.contains(&file) {
+                    continue
+                }
+
+                let defined = read_included(&file)?;
+                merge_defined(table, defined, "", &file, &mut origins)?;
+                included.push(file);
+            }
+        }
+
+        if let Some(defaults) = defaults {
+            apply_defaults(table, defaults)?;
+        }
+    }
+
+    Ok((config, included))
+}
+
+/// Reads an included file, with its own defaults applied to its packages.
+fn read_included(file: &Path) -> Result<Table, String> {
+    let buffer = misc::read(file).map_err(|why| format!("error reading {}: {}", file.display(), why))?;
+    let mut defined: Table = toml::from_slice(&buffer)
+        .map_err(|why| format!("failed to parse TOML syntax in {}: {}", file.display(), why))?;
+
+    for key in &["include", "suites"] {
+        if defined.contains_key(*key) {
+            return Err(format!("{} defines `{}`, which only the config itself may define", file.display(), key));
+        }
+    }
+
+    if let Some(defaults) = defined.remove("defaults") {
+        apply_defaults(&mut defined, defaults)
+            .map_err(|why| format!("{}: {}", file.display(), why))?;
+    }
+
+    Ok(defined)
+}
+
+/// Merges the keys which an included file defines into the config, where tables are merged key
+/// by key, and arrays of tables are appended, so long as no key or package is defined twice.
+fn merge_defined(
+    base: &mut Table,
+    defined: Table,
+    prefix: &str,
+    file: &Path,
+    origins: &mut Origins
+) -> Result<(), String> {
+    for (key, value) in defined {
+        let path = if prefix.is_empty() { key.clone() } else { [prefix, ".", &key].concat() };
+        if !base.contains_key(&key) {
+            origins.defined.insert(path, file.to_path_buf());
+            base.insert(key, value);
+            continue
+        }
+
+        match (base.get_mut(&key).unwrap(), value) {
+            (&mut Value::Table(ref mut existing), Value::Table(table)) => {
+                merge_defined(existing, table, &path, file, origins)?;
+            }
+            (&mut Value::Array(ref mut existing), Value::Array(array)) => {
+                append(existing, array, &path, file, origins)?;
+            }
+            (existing, value) => if *existing != value {
+                return Err(origins.conflict(&path, file));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Appends the tables of an included file to an array of the config, where a table with the
+/// `name` of a table which is already defined is a conflicting definition of that package.
+fn append(
+    existing: &mut Vec<Value>,
+    array: Vec<Value>,
+    path: &str,
+    file: &Path,
+    origins: &mut Origins
+) -> Result<(), String> {
+    // Arrays of values, such as the `architectures` of the suite, are not appended.
+    if !is_tables(existing) || !is_tables(&array) {
+        return if *existing == array { Ok(()) } else { Err(origins.conflict(path, file)) };
+    }
+
+    for value in array {
+        if let Some(name) = value.get("name").and_then(Value::as_str) {
+            let key = [path, ".", name].concat();
+            if existing.iter().any(|table| table.get("name").and_then(Value::as_str) == Some(name)) {
+                return Err(format!(
+                    "{} `{}` is defined by both {} and {}",
+                    path,
+                    name,
+                    origins.of(&key).display(),
+                    file.display()
+                ));
+            }
+
+            origins.defined.insert(key, file.to_path_buf());
+        }
+
+        existing.push(value);
+    }
+
+    Ok(())
+}
+
+fn is_tables(array: &[Value]) -> bool {
+    array.iter().all(Value::is_table)
+}
+
+/// Merges each table of `defaults` beneath the packages of its kind, so that the keys which a
+/// package defines are kept over those of the defaults.
+fn apply_defaults(config: &mut Table, defaults: Value) -> Result<(), String> {
+    let defaults = match defaults {
+        Value::Table(defaults) => defaults,
+        _ => return Err("`defaults` must be a table of the defaults of each kind of package".into()),
+    };
+
+    for (kind, defaults) in defaults {
+        if !PACKAGES.contains(&kind.as_str()) {
+            return Err(format!("`defaults.{}` is not a kind of package, which are {}", kind, PACKAGES.join(" and ")));
+        }
+
+        if !defaults.is_table() {
+            return Err(format!("`defaults.{}` must be a table", kind));
+        }
+
+        if let Some(&mut Value::Array(ref mut packages)) = config.get_mut(&kind) {
+            for package in packages.iter_mut() {
+                let mut merged = defaults.clone();
+                merge(&mut merged, mem::replace(package, Value::Boolean(false)));
+                *package = merged;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn included_packages() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("debrep.toml");
+        fs::create_dir(dir.path().join("packages")).unwrap();
+        misc::write(dir.path().join("packages/bar.toml"), br#"
+            [defaults.source]
+            component = "universe"
+
+            [[source]]
+            name = "bar"
+            location = { git = "https://github.com/pop-os/bar" }
+
+            [[direct]]
+            name = "baz"
+            urls = [{ url = "https://example.com/baz_1.0_amd64.deb" }]
+        "#).unwrap();
+
+        let value: Value = toml::from_str(r#"
+            archive = "cosmic"
+            include = ["packages/*.toml"]
+
+            [defaults.source]
+            component = "main"
+            builder = { chroot = "cosmic-amd64" }
+
+            [[source]]
+            name = "foo"
+            location = { git = "https://github.com/pop-os/foo" }
+            builder = { backend = "sbuild" }
+        "#).unwrap();
+
+        let (value, included) = resolve_includes(&config, value).unwrap();
+        assert_eq!(included, vec![dir.path().join("packages/bar.toml")]);
+        assert!(value.get("include").is_none() && value.get("defaults").is_none());
+
+        let sources = value["source"].as_array().unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0]["component"].as_str(), Some("main"));
+        assert_eq!(sources[0]["builder"]["backend"].as_str(), Some("sbuild"));
+        assert_eq!(sources[0]["builder"]["chroot"].as_str(), Some("cosmic-amd64"));
+        assert_eq!(sources[1]["name"].as_str(), Some("bar"));
+        assert_eq!(sources[1]["component"].as_str(), Some("universe"));
+        assert_eq!(value["direct"][0]["name"].as_str(), Some("baz"));
+
+        // A package which is defined by two files names both of them.
+        misc::write(dir.path().join("packages/foo.toml"), br#"
+            archive = "bionic"
+
+            [[source]]
+            name = "foo"
+        "#).unwrap();
+
+        let value: Value = toml::from_str("include = [\"packages/foo.toml\"]\n[[source]]\nname = \"foo\"\n").unwrap();
+        let why = resolve_includes(&config, value).unwrap_err();
+        assert_eq!(why, format!("source `foo` is defined by both {} and {}", config.display(), dir.path().join("packages/foo.toml").display()));
+
+        let value: Value = toml::from_str("archive = \"cosmic\"\ninclude = [\"packages/*.toml\"]\n").unwrap();
+        let why = resolve_includes(&config, value).unwrap_err();
+        assert_eq!(why, format!("`archive` is defined by both {} and {}", config.display(), dir.path().join("packages/foo.toml").display()));
+    }
+}
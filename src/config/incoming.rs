@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+/// Accepts signed uploads of packages into the suite with `debrep incoming process`, as dput
+/// places them into an incoming directory.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Incoming {
+    /// The keyring of the keys which may upload to the suite. The `.changes` of each upload must
+    /// be signed by one of them.
+    pub keyring: PathBuf,
+    /// The component that uploads are stored in, instead of the default.
+    pub component: Option<String>,
+}
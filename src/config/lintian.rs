@@ -0,0 +1,40 @@
+/// The severity of a lintian tag, from the least to the most severe.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum LintianSeverity {
+    Pedantic,
+    Info,
+    Warning,
+    Error,
+    /// Above every severity, so that no tag reaches it.
+    Never,
+}
+
+/// Runs lintian on the packages of each source after it has been built. This may be given for
+/// the repo, and for each source, where the fields of a source take precedence over those of
+/// the repo.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Lintian {
+    /// Tags of this severity or higher fail the build of the source, which defaults to `error`.
+    pub fail_on: Option<LintianSeverity>,
+    /// Tags of this severity or higher are reported as warnings, which defaults to `warning`.
+    pub warn_on: Option<LintianSeverity>,
+}
+
+impl Lintian {
+    /// Fills the unset fields of this config from another.
+    pub fn or(&self, other: &Lintian) -> Lintian {
+        Lintian {
+            fail_on: self.fail_on.or(other.fail_on),
+            warn_on: self.warn_on.or(other.warn_on),
+        }
+    }
+
+    pub fn fail_on(&self) -> LintianSeverity {
+        self.fail_on.unwrap_or(LintianSeverity::Error)
+    }
+
+    pub fn warn_on(&self) -> LintianSeverity {
+        self.warn_on.unwrap_or(LintianSeverity::Warning)
+    }
+}
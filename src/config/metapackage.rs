@@ -0,0 +1,29 @@
+/// A package without any files of its own, which only depends upon other packages, and which is
+/// synthesized by debrep instead of being built with equivs.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Metapackage {
+    pub name: String,
+    pub version: String,
+    /// The first line is the synopsis of the package, and the rest its extended description.
+    pub description: String,
+    #[serde(default)]
+    pub depends: Vec<String>,
+    #[serde(default)]
+    pub recommends: Vec<String>,
+    #[serde(default)]
+    pub suggests: Vec<String>,
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+    #[serde(default)]
+    pub provides: Vec<String>,
+    /// The architecture of the package, which defaults to `all`.
+    pub architecture: Option<String>,
+    /// The section of the package, which defaults to `metapackages`.
+    pub section: Option<String>,
+    /// The priority of the package, which defaults to `optional`.
+    pub priority: Option<String>,
+    /// The maintainer of the package, which defaults to the label and email of the repo.
+    pub maintainer: Option<String>,
+    /// The component that the package is stored in, instead of the default.
+    pub component: Option<String>,
+}
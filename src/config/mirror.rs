@@ -0,0 +1,82 @@
+use glob::Pattern;
+use std::path::PathBuf;
+
+/// Pins packages from an existing apt repository, which are downloaded from its `Packages`
+/// indices and republished within the pool alongside the packages that are built.
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Mirror {
+    /// The root of the apt repository, which contains its `dists` and `pool` directories.
+    pub url: String,
+    /// The suite of the apt repository to pull packages from.
+    pub suite: String,
+    /// The keyring which verifies the `InRelease`, or `Release.gpg`, of the suite, whose digests
+    /// in turn verify the indices that packages are selected from.
+    pub keyring: PathBuf,
+    /// The components of the suite whose indices are searched.
+    #[serde(default = "default_components")]
+    pub components: Vec<String>,
+    /// The architectures whose indices are searched, which default to the suite's architectures.
+    pub architectures: Option<Vec<String>>,
+    /// The component that mirrored packages are stored in, instead of the default.
+    pub component: Option<String>,
+    /// The packages to pull. If several versions of a package are selected, the newest is pulled.
+    pub packages: Vec<MirrorPackage>,
+}
+
+/// Selects packages by glob patterns of their name, and optionally of their version.
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct MirrorPackage {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl Mirror {
+    /// Whether the package of the given name and version is selected to be mirrored.
+    pub fn selects(&self, name: &str, version: &str) -> bool {
+        self.packages.iter().any(|package| {
+            matches_glob(&package.name, name)
+                && package.version.as_ref().map_or(true, |pattern| matches_glob(pattern, version))
+        })
+    }
+}
+
+pub(super) fn matches_glob(pattern: &str, input: &str) -> bool {
+    match Pattern::new(pattern) {
+        Ok(pattern) => pattern.matches(input),
+        Err(why) => {
+            warn!("invalid glob pattern: '{}': {}", pattern, why);
+            false
+        }
+    }
+}
+
+fn default_components() -> Vec<String> { vec!["main".into()] }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirror_selection() {
+        let package = |name: &str, version: Option<&str>| MirrorPackage {
+            name: name.to_owned(),
+            version: version.map(|x| x.to_owned()),
+        };
+
+        let mirror = Mirror {
+            url: "http://archive.ubuntu.com/ubuntu".into(),
+            suite: "bionic".into(),
+            keyring: "ubuntu-archive-keyring.gpg".into(),
+            components: default_components(),
+            architectures: None,
+            component: None,
+            packages: vec![package("libfoo*", Some("1.2.*")), package("bar", None)],
+        };
+
+        assert!(mirror.selects("libfoo1", "1.2.3-1"));
+        assert!(mirror.selects("libfoo-dev", "1.2.0-1"));
+        assert!(!mirror.selects("libfoo1", "1.3.0-1"));
+        assert!(mirror.selects("bar", "2:0.1"));
+        assert!(!mirror.selects("barbaz", "0.1"));
+    }
+}
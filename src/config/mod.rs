@@ -1,19 +1,87 @@
 use std::borrow::Cow;
 use std::fs::{self, File};
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 
-use toml::{self, de};
+use toml::{self, de, Value};
+use checksum::{PooledDigests, ALL_DIGESTS, SHA256};
+use compress::*;
 use misc;
 
+mod appstream;
+mod assignments;
+mod builder;
+mod changelogs;
+mod check;
+mod closure;
+mod compiler_cache;
+mod compression;
+mod debug;
+mod digest;
 mod direct;
+mod exclude;
+mod hashing;
+mod hooks;
+mod http;
+mod include;
+mod incoming;
+mod lintian;
+mod metapackage;
+mod mirror;
+mod network;
+mod notify;
+mod old_releases;
+mod overrides;
+mod ownership;
+mod policy;
+mod publish;
+mod queue;
 mod repos;
+mod retry;
+mod signing;
 mod source;
+mod suites;
+mod test_apt;
+mod transform;
+mod worker;
 
+pub use self::appstream::*;
+pub use self::assignments::*;
+pub use self::builder::*;
+pub use self::changelogs::*;
+pub use self::check::*;
+pub use self::closure::*;
+pub use self::compiler_cache::*;
+pub use self::compression::*;
+pub use self::debug::*;
+pub use self::digest::*;
 pub use self::direct::*;
+pub use self::exclude::*;
+pub use self::hashing::*;
+pub use self::hooks::*;
+pub use self::http::*;
+pub use self::include::*;
+pub use self::incoming::*;
+pub use self::lintian::*;
+pub use self::metapackage::*;
+pub use self::mirror::*;
+pub use self::network::*;
+pub use self::notify::*;
+pub use self::old_releases::*;
+pub use self::overrides::*;
+pub use self::ownership::*;
+pub use self::policy::*;
+pub use self::publish::*;
+pub use self::queue::*;
 pub use self::repos::*;
+pub use self::retry::*;
+pub use self::signing::*;
 pub use self::source::*;
+pub use self::suites::*;
+pub use self::test_apt::*;
+pub use self::transform::*;
+pub use self::worker::*;
 
 #[derive(Debug, Fail)]
 pub enum ParsingError {
@@ -28,7 +96,23 @@ pub enum ParsingError {
     #[fail(display = "source URL and path defined for {}. Only one should be defined.", source)]
     SourcePathAndUrlDefined { source: String },
     #[fail(display = "neither a URL or path was defined for the source named {}", source)]
-    SourceNotDefined { source: String }
+    SourceNotDefined { source: String },
+    #[fail(display = "no compression formats were given for the dist indices in {:?}", file)]
+    NoCompression { file: PathBuf },
+    #[fail(display = "the digests of the indices in {:?} do not include sha256, which apt requires", file)]
+    NoSha256 { file: PathBuf },
+    #[fail(display = "invalid suites in {:?}: {}", file, why)]
+    Suites { file: PathBuf, why: String },
+    #[fail(display = "{:?} defines several suites, so it cannot be written back", file)]
+    SharedConfig { file: PathBuf },
+    #[fail(display = "invalid includes in {:?}: {}", file, why)]
+    Include { file: PathBuf, why: String },
+    #[fail(display = "{:?} includes other files, so it cannot be written back", file)]
+    IncludingConfig { file: PathBuf },
+    #[fail(display = "failed to override a value of {:?}: {}", file, why)]
+    Assignment { file: PathBuf, why: String },
+    #[fail(display = "{:?} has values which were overridden for this run, so it cannot be written back", file)]
+    AssignedConfig { file: PathBuf },
 }
 
 #[derive(Debug, Fail)]
@@ -55,15 +139,172 @@ pub struct Config {
     pub source: Option<Vec<Source>>,
     /// Repos to pull packages from.
     pub repos: Option<Vec<Repo>>,
+    /// Metapackages which are synthesized from the config.
+    pub metapackage: Option<Vec<Metapackage>>,
+    /// Packages which are emitted as modified copies of existing packages.
+    pub transform: Option<Vec<Transform>>,
     #[serde(default = "default_component")]
     pub default_component: String,
     pub extra_repos: Option<Vec<String>>,
+    /// Where the dists and pool of the repository are generated, which defaults to `repo`.
+    pub repo_path: Option<PathBuf>,
+    /// The pocket that this suite serves, such as `staging` or `release`, which migrations may
+    /// name instead of the suite.
+    pub pocket: Option<String>,
+    /// The number of packages which may be built concurrently.
+    pub jobs: Option<usize>,
+    /// Shares a ccache or sccache directory between the builds of the suite.
+    pub compiler_cache: Option<CompilerCache>,
+    /// The number of versions of each package which `prune` keeps in the pool.
+    pub keep_versions: Option<usize>,
+    /// The number of build logs of each package which are kept in `logs`, which defaults to 5.
+    pub keep_logs: Option<usize>,
+    /// The number of days that links to the previous paths of the pool are kept for, once the
+    /// archive or components have changed, which defaults to 30.
+    pub layout_grace_days: Option<u64>,
+    /// Rejects any archive larger than this many bytes from being moved into the pool.
+    pub max_package_bytes: Option<u64>,
+    /// Checks which each archive must pass before it is moved into the pool, or else is warned of.
+    pub policy: Option<PoolPolicy>,
+    /// Gives the pool and dists to this owner and group once the dist files are generated.
+    pub ownership: Option<Ownership>,
+    /// Writes an HTML report of each run of the builds of the suite to `reports` within the repo.
+    #[serde(default)]
+    pub build_report: bool,
+    /// Records a signed `.repo-state` marker of every dist file's digest at publish time.
+    #[serde(default)]
+    pub repo_state: bool,
+    /// Publishes the `Packages`, `Sources`, and `Release` at the root of the repo, instead of a
+    /// suite within `dists`, for sources such as `deb http://host/ ./`.
+    #[serde(default)]
+    pub flat: bool,
+    /// Leaves the `Release` of a flat repository unsigned, for sources which are `trusted=yes`.
+    #[serde(default)]
+    pub unsigned: bool,
+    /// Publishes only the source packages of the sources, without building their binaries, for
+    /// suites whose binaries are built elsewhere.
+    #[serde(default)]
+    pub source_only: bool,
+    /// Publishes indices under `by-hash/<digest>` for each of the `digests`, keeping this many
+    /// prior generations of each.
+    pub by_hash: Option<usize>,
+    /// Publishes `Packages.diff` indices for incremental updates, keeping this many diffs of each.
+    pub pdiffs: Option<usize>,
+    /// Publishes debdelta deltas to the published version of each package under `deltas/`,
+    /// keeping this many deltas to each version.
+    pub deltas: Option<usize>,
+    /// Publishes the changelog of each source under `changelogs/`, for `apt changelog`.
+    pub changelogs: Option<Changelogs>,
+    /// The ID of the key that signs the dist files, if it should not be found by the email, or
+    /// the name of one of the `signing.keys`.
+    pub signing_key: Option<String>,
+    /// Pins the `Date` of the `Release` file to this timestamp, instead of `SOURCE_DATE_EPOCH`.
+    pub source_date_epoch: Option<i64>,
+    /// The `Suite` of the `Release` file, such as `stable`, which defaults to the archive.
+    pub suite: Option<String>,
+    /// The `Codename` of the `Release` file, which defaults to the archive.
+    pub codename: Option<String>,
+    /// The `Description` of the `Release` file, which defaults to the label, archive, and version.
+    pub description: Option<String>,
+    /// The number of seconds after its `Date` that the `Release` file expires, as `Valid-Until`.
+    pub valid_until: Option<u64>,
+    /// Marks the suite as `NotAutomatic`, so that apt only installs its packages when asked to.
+    #[serde(default)]
+    pub not_automatic: bool,
+    /// Marks the suite as `ButAutomaticUpgrades`, so that installed packages are still upgraded
+    /// from a `NotAutomatic` suite.
+    #[serde(default)]
+    pub but_automatic_upgrades: bool,
+    /// The formats that the `Packages`, `Sources`, and `Contents` indices are published with.
+    pub compression: Option<Vec<CompressionFormat>>,
+    /// The level of each compressed index, from 0 to 9, which defaults to the best compression.
+    pub compression_level: Option<u32>,
+    /// The digests that the `Release`, `Packages`, and `Sources` indices list of each file, which
+    /// default to MD5, SHA1, SHA256, and SHA512.
+    pub digests: Option<Vec<DigestAlgorithm>>,
+    /// Selects how large files are read to be hashed.
+    pub hashing: Option<Hashing>,
+    /// Selects how the dist files are signed.
+    pub signing: Option<Signing>,
+    /// Selects how sources are built, unless a source has its own builder.
+    pub builder: Option<Builder>,
+    /// Retries downloads and rsync transfers which fail with a transient error.
+    pub retry: Option<RetryPolicy>,
+    /// Limits the concurrency and bandwidth of downloads and publishes.
+    pub network: Option<NetworkLimits>,
+    /// The proxies and certificates that upstreams are fetched with.
+    pub http: Option<HttpSettings>,
+    /// Pins packages from an existing apt repository, which are republished in the pool.
+    pub mirror: Option<Mirror>,
+    /// Routes debug symbol packages into a component or suite of their own.
+    pub debug_symbols: Option<DebugSymbols>,
+    /// Runs lintian on the packages of each source after it has been built.
+    pub lintian: Option<Lintian>,
+    /// Publishes the repo to an S3-compatible bucket with `debrep publish`.
+    pub s3: Option<S3Bucket>,
+    /// Publishes the repo into directories, which may be on other hosts, with `debrep publish`.
+    pub rsync: Option<Vec<RsyncTarget>>,
+    /// Where `debrep archive` moves the suite, once it has reached its end of life.
+    pub old_releases: Option<OldReleases>,
+    /// Notifies webhooks and email addresses of failed builds, and of publishes.
+    pub notify: Option<Notify>,
+    /// Scripts which are run once the dist files of the suite are generated.
+    pub hooks: Option<Hooks>,
+    /// Accepts signed uploads into the suite with `debrep incoming process`.
+    pub incoming: Option<Incoming>,
+    /// Holds new packages, or those which match its policy, for review before they are pooled.
+    pub queue: Option<Queue>,
+    /// Replaces the section, priority, and maintainer of packages in the `Packages` indices.
+    pub overrides: Option<Overrides>,
+    /// Keeps the matching packages of the pool out of the published indices.
+    pub exclude: Option<Vec<Exclusion>>,
+    /// Generates the DEP-11 AppStream metadata of the applications of each component.
+    pub appstream: Option<AppStream>,
+    /// Checks that the dependencies of each package are satisfiable once the indices are generated.
+    pub closure: Option<Closure>,
+    /// Installs packages from the published suite with apt, by `debrep test-apt`.
+    pub test_apt: Option<TestApt>,
+    /// Hosts which the builds of some sources, or architectures, are dispatched to over ssh.
+    pub worker: Option<Vec<Worker>>,
     #[serde(skip)]
     pub extra_keys: Vec<PathBuf>,
+    /// Overrides the `repo_path` for this run, without being written back to the config.
+    #[serde(skip)]
+    pub output: Option<PathBuf>,
+    /// Appends a JSON summary of the builds of this run to this file.
+    #[serde(skip)]
+    pub summary: Option<PathBuf>,
+    /// Pools built packages with a lower version than the version which is already published.
+    #[serde(skip)]
+    pub allow_downgrade: bool,
+    /// The config is one of the suites of a config which defines several suites.
+    #[serde(skip)]
+    pub shared: bool,
+    /// The files whose packages and keys were included into the config.
+    #[serde(skip)]
+    pub includes: Vec<PathBuf>,
+    /// Values of the config were overridden for this run, by `--set` or the environment.
+    #[serde(skip)]
+    pub assigned: bool,
+    /// The digests of the files which this run copied into the pool.
+    #[serde(skip)]
+    pub pooled: PooledDigests,
 }
 
 impl Config {
     pub fn write_to_disk(&self) -> Result<(), ParsingError> {
+        if self.shared {
+            return Err(ParsingError::SharedConfig { file: self.path.clone() });
+        }
+
+        if !self.includes.is_empty() {
+            return Err(ParsingError::IncludingConfig { file: self.path.clone() });
+        }
+
+        if self.assigned {
+            return Err(ParsingError::AssignedConfig { file: self.path.clone() });
+        }
+
         toml::ser::to_vec(self)
             .map_err(|why| ParsingError::TomlSerialize { why })
             .and_then(|data| {
@@ -73,6 +314,179 @@ impl Config {
             })
     }
 
+    /// The root of the repository, which holds its dists and pool directories.
+    pub fn repo_path(&self) -> &Path {
+        self.output.as_ref()
+            .or_else(|| self.repo_path.as_ref())
+            .map_or(Path::new("repo"), |path| path.as_path())
+    }
+
+    /// The directory which holds the pool of every suite.
+    pub fn pool_root(&self) -> PathBuf {
+        self.repo_path().join("pool")
+    }
+
+    /// The pool of this suite.
+    pub fn pool_path(&self) -> PathBuf {
+        self.pool_root().join(&self.archive)
+    }
+
+    /// The `Suite` of the `Release` file.
+    pub fn release_suite(&self) -> &str {
+        self.suite.as_ref().unwrap_or(&self.archive)
+    }
+
+    /// The `Codename` of the `Release` file.
+    pub fn release_codename(&self) -> &str {
+        self.codename.as_ref().unwrap_or(&self.archive)
+    }
+
+    /// The `Description` of the `Release` file.
+    pub fn release_description(&self) -> String {
+        self.description.clone()
+            .unwrap_or_else(|| format!("{} ({} {})", self.label, self.archive, self.version))
+    }
+
+    /// The retry policy of downloads and rsync transfers, which defaults to three attempts.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry.clone().unwrap_or_default()
+    }
+
+    /// The limits of downloads and publishes, which default to 8 downloads at a time, at any rate.
+    pub fn network_limits(&self) -> NetworkLimits {
+        self.network.clone().unwrap_or_default()
+    }
+
+    /// The checks that archives must pass before they are pooled, where `max_package_bytes` is
+    /// the maximum size of an archive, unless the policy sets its own.
+    pub fn pool_policy(&self) -> PoolPolicy {
+        let mut policy = self.policy.clone().unwrap_or_default();
+        if policy.max_bytes.is_none() {
+            policy.max_bytes = self.max_package_bytes;
+        }
+
+        policy
+    }
+
+    /// The proxies and certificates of upstream fetches, which default to the proxies of the
+    /// environment, and the certificates of the system.
+    pub fn http_settings(&self) -> HttpSettings {
+        self.http.clone().unwrap_or_default()
+    }
+
+    /// The key that signs the dist files, which defaults to the key of the configured email.
+    pub fn signing_key(&self) -> &str {
+        match (self.named_key(), self.signing.as_ref()) {
+            (Some(name), Some(signing)) => &signing.keys[name],
+            _ => self.signing_key.as_ref().unwrap_or(&self.email)
+        }
+    }
+
+    /// The name of the `signing.keys` entry that signs the suite, which is named by its
+    /// `signing_key`, or else by its pocket.
+    pub fn named_key(&self) -> Option<&str> {
+        let keys = &self.signing.as_ref()?.keys;
+        self.signing_key.as_ref()
+            .or_else(|| self.pocket.as_ref())
+            .map(|name| name.as_str())
+            .filter(|name| keys.contains_key(*name))
+    }
+
+    /// The compression flags of the dist indices, which default to uncompressed, gz, and xz.
+    pub fn compression(&self) -> u8 {
+        self.compression.as_ref().map_or(UNCOMPRESSED | GZ_COMPRESS | XZ_COMPRESS, |formats| {
+            formats.iter().fold(0, |flags, format| flags | format.flag())
+        })
+    }
+
+    /// The flags of the digests that the indices list, which default to every digest.
+    pub fn digests(&self) -> u8 {
+        self.digests.as_ref().map_or(ALL_DIGESTS, |digests| {
+            digests.iter().fold(0, |flags, digest| flags | digest.flag())
+        })
+    }
+
+    /// How files are hashed, where files of at least 64 MiB are hashed from a memory map.
+    pub fn hashing(&self) -> Hashing {
+        self.hashing.clone().unwrap_or_default()
+    }
+
+    /// Whether a package of the pool is kept out of the published indices by an exclusion.
+    pub fn excludes(&self, package: &str, version: &str, arch: &str, section: Option<&str>) -> bool {
+        self.exclude.iter()
+            .flat_map(|exclusions| exclusions.iter())
+            .any(|exclusion| exclusion.matches(package, version, arch, section))
+    }
+
+    /// The component that a package is stored in, which is the default unless it has its own.
+    pub fn component<'a>(&'a self, component: &'a Option<String>) -> &'a str {
+        component.as_ref().unwrap_or(&self.default_component)
+    }
+
+    /// The architectures listed by the `Release` file. Architecture independent packages are
+    /// listed in the index of every architecture, so `all` is not listed.
+    pub fn release_architectures(&self) -> Vec<&str> {
+        self.architectures.iter().map(|x| x.as_str()).filter(|&arch| arch != "all").collect()
+    }
+
+    /// Every component that the suite's packages are stored in.
+    pub fn components(&self) -> Vec<&str> {
+        let mut components = vec![self.default_component.as_str()];
+        let sources = self.source.iter().flat_map(|x| x.iter()).map(|x| &x.component);
+        let direct = self.direct.iter().flat_map(|x| x.iter()).map(|x| &x.component);
+        let repos = self.repos.iter().flat_map(|x| x.iter()).map(|x| &x.component);
+        let mirror = self.mirror.iter().map(|x| &x.component);
+        let incoming = self.incoming.iter().map(|x| &x.component);
+        let metapackages = self.metapackage.iter().flat_map(|x| x.iter()).map(|x| &x.component);
+        let transforms = self.transform.iter().flat_map(|x| x.iter()).map(|x| &x.component);
+        components.extend(
+            sources.chain(direct).chain(repos).chain(mirror).chain(incoming)
+                .chain(metapackages).chain(transforms)
+                .filter_map(|x| x.as_ref().map(|x| x.as_str()))
+        );
+        components.sort();
+        components.dedup();
+        components
+    }
+
+    /// The config of the suite that debug symbols are published in, if they have their own.
+    pub fn debug_suite(&self) -> Option<Config> {
+        match self.debug_symbols {
+            Some(DebugSymbols::Suite { ref suite }) => {
+                let mut config = self.clone();
+                config.archive = suite.clone();
+                config.debug_symbols = None;
+                config.suite = None;
+                config.codename = None;
+                config.description = None;
+                Some(config)
+            }
+            _ => None
+        }
+    }
+
+    /// The builder of a source, which falls back to the repo's builder for each unset field.
+    pub fn builder_for(&self, source: &Source) -> Builder {
+        let default = Builder::default();
+        let repo = self.builder.as_ref().unwrap_or(&default);
+        source.builder.as_ref().map_or_else(|| repo.clone(), |builder| builder.or(repo))
+    }
+
+    /// The first worker which builds this source for the architecture, if any, as the build is
+    /// otherwise run by this host.
+    pub fn worker_for(&self, source: &str, arch: &str) -> Option<&Worker> {
+        self.worker.as_ref().and_then(|workers| workers.iter().find(|worker| worker.builds(source, arch)))
+    }
+
+    /// The lintian config of a source, if either the source or the repo enables lintian.
+    pub fn lintian_for(&self, source: &Source) -> Option<Lintian> {
+        match (source.lintian.as_ref(), self.lintian.as_ref()) {
+            (Some(source), Some(repo)) => Some(source.or(repo)),
+            (Some(lintian), None) | (None, Some(lintian)) => Some(lintian.clone()),
+            (None, None) => None
+        }
+    }
+
     pub fn direct_exists(&self, filename: &str) -> bool {
         self.direct.as_ref()
             .map_or(false, |packages| {
@@ -95,7 +509,7 @@ impl Config {
 }
 
 fn default_architectures() -> Vec<String> {
-    vec!["amd64".into(), "i368".into()]
+    vec!["amd64".into(), "i386".into()]
 }
 fn default_component() -> String { "main".into() }
 
@@ -116,6 +530,7 @@ impl ConfigFetch for Config {
             "origin" => Some(Cow::Borrowed(&self.origin)),
             "label" => Some(Cow::Borrowed(&self.label)),
             "email" => Some(Cow::Borrowed(&self.email)),
+            "signing_key" => self.signing_key.as_ref().map(|key| Cow::Borrowed(key.as_str())),
             "direct" => Some(Cow::Owned(format!("{:#?}", self.direct))),
             _ => {
                 if key.starts_with("direct.") {
@@ -160,6 +575,7 @@ impl ConfigFetch for Config {
             "origin" => self.origin = value,
             "label" => self.label = value,
             "email" => self.email = value,
+            "signing_key" => self.signing_key = Some(value),
             _ => {
                 if key.starts_with("direct.") {
                     let key = &key[7..];
@@ -198,13 +614,102 @@ impl ConfigFetch for Config {
 }
 
 pub fn parse(path: PathBuf) -> Result<Config, ParsingError> {
-    let mut config: Config = misc::read(&path)
+    let config: Config = misc::read(&path)
         .map_err(|why| ParsingError::File { file: path.clone(), why })
         .and_then(|buffer| {
             toml::from_slice(&buffer).map_err(|why| ParsingError::Toml { file: path.clone(), why })
         })?;
 
+    loaded(config, path)
+}
+
+/// Parses the config of each suite that the config at `path` defines, which may be several,
+/// along with the files that it includes, and with the assignments applied to each suite.
+pub fn parse_suites(path: PathBuf, assignments: &[Assignment]) -> Result<Vec<Config>, ParsingError> {
+    let value: Value = misc::read(&path)
+        .map_err(|why| ParsingError::File { file: path.clone(), why })
+        .and_then(|buffer| {
+            toml::from_slice(&buffer).map_err(|why| ParsingError::Toml { file: path.clone(), why })
+        })?;
+
+    let (value, includes) = resolve_includes(&path, value)
+        .map_err(|why| ParsingError::Include { file: path.clone(), why })?;
+
+    let shared = value.get("suites").is_some();
+    let suites = expand_suites(value)
+        .map_err(|why| ParsingError::Suites { file: path.clone(), why })?;
+
+    suites.into_iter()
+        .map(|mut suite| {
+            let mut absent = Vec::new();
+            let mut defined = false;
+            for assignment in assignments {
+                match assign(&mut suite, assignment) {
+                    Ok(true) => absent.push(assignment),
+                    Ok(false) => defined = true,
+                    Err(why) => if assignment.environment {
+                        debug!("ignoring DEBREP_{}: {}", assignment.key, why);
+                    } else {
+                        return Err(ParsingError::Assignment { file: path.clone(), why });
+                    }
+                }
+            }
+
+            // A key which the config did not define was assigned a string. Where the config
+            // refuses that string, the boolean or integer that it parses as is assigned instead.
+            let mut retyped = Vec::new();
+            let mut config: Config = loop {
+                let why = match suite.clone().try_into() {
+                    Ok(config) => break config,
+                    Err(why) => why
+                };
+
+                let refused = format!("{}", why);
+                let assignment = absent.iter()
+                    .find(|a| !retyped.contains(&a.key) && refused.contains(&format!("string {:?}", a.value)));
+                match assignment {
+                    Some(assignment) => {
+                        let retyped_value = retype(&mut suite, assignment)
+                            .map_err(|why| ParsingError::Assignment { file: path.clone(), why })?;
+                        if !retyped_value {
+                            return Err(ParsingError::Toml { file: path.clone(), why });
+                        }
+                        retyped.push(assignment.key.clone());
+                    }
+                    None => return Err(ParsingError::Toml { file: path.clone(), why })
+                }
+            };
+
+            // Only the keys of the config's fields remain once it is serialized, so a variable
+            // of the environment which does not name a field is ignored.
+            let fields = Value::try_from(&config).unwrap_or_else(|_| Value::Table(Default::default()));
+            for assignment in absent {
+                if defines(&fields, &assignment.key) {
+                    defined = true;
+                } else if !assignment.environment {
+                    warn!("`{}` is not a key of the config, and is ignored", assignment.key);
+                }
+            }
+
+            config.shared = shared;
+            config.includes = includes.clone();
+            config.assigned = defined;
+            loaded(config, path.clone())
+        })
+        .collect()
+}
+
+fn loaded(mut config: Config, path: PathBuf) -> Result<Config, ParsingError> {
+    if config.compression() == 0 {
+        return Err(ParsingError::NoCompression { file: path });
+    }
+
+    if config.digests() & SHA256 == 0 {
+        return Err(ParsingError::NoSha256 { file: path });
+    }
+
     config.path = path;
+    config.pooled = PooledDigests::new(config.digests());
     if let Ok(key_dir) = fs::read_dir("keys") {
         for key in key_dir.flat_map(|x| x.ok()) {
             let path = key.path();
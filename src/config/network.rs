@@ -0,0 +1,18 @@
+/// Limits the concurrency and bandwidth of the downloads and publishes of a run, so that they do
+/// not saturate the uplink that they share.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct NetworkLimits {
+    /// The number of downloads, and of uploads to a bucket, which are made at a time, which
+    /// defaults to 8.
+    pub max_transfers: Option<usize>,
+    /// The bytes per second that each download or upload is limited to.
+    pub connection_bytes_per_second: Option<u64>,
+    /// The bytes per second that every download and upload of a run is limited to in total.
+    pub bytes_per_second: Option<u64>,
+}
+
+impl NetworkLimits {
+    pub fn max_transfers(&self) -> usize {
+        self.max_transfers.unwrap_or(8).max(1)
+    }
+}
@@ -0,0 +1,31 @@
+/// Notifies people when the builds of a suite fail, or when it has been published, so that a
+/// failing nightly build is noticed before its packages go stale.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Notify {
+    /// Webhooks which are posted a Slack-style JSON payload, as Slack and Mattermost accept.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+    /// Mails each notification through an SMTP relay.
+    pub email: Option<EmailNotify>,
+    /// Notifies of builds which succeeded, in addition to those which failed.
+    #[serde(default)]
+    pub on_success: bool,
+}
+
+/// Mails notifications through an SMTP relay which accepts mail without authentication, such as
+/// the MTA of the build host.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct EmailNotify {
+    /// The addresses which are mailed.
+    pub to: Vec<String>,
+    /// The sender of each mail, which defaults to the email of the repo.
+    pub from: Option<String>,
+    /// The address of the relay, which defaults to `localhost:25`.
+    pub server: Option<String>,
+}
+
+impl EmailNotify {
+    pub fn server(&self) -> &str {
+        self.server.as_ref().map_or("localhost:25", |x| x.as_str())
+    }
+}
@@ -0,0 +1,30 @@
+use std::path::{Path, PathBuf};
+use super::RsyncTarget;
+
+/// Where `debrep archive` moves the suites which have reached their end of life, as Debian
+/// retires its releases to `archive.debian.org`.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct OldReleases {
+    /// The root that archived suites are moved into, which defaults to `old` within the project.
+    pub path: Option<PathBuf>,
+    /// The URL that the archived suites are served from, which the tombstone `Release` of each
+    /// suite points users at.
+    pub url: Option<String>,
+    /// Pushes each archived suite to this target, rather than keeping it within `path`.
+    pub rsync: Option<RsyncTarget>,
+}
+
+impl OldReleases {
+    pub fn path(&self) -> &Path {
+        self.path.as_ref().map_or(Path::new("old"), |path| path.as_path())
+    }
+
+    /// Where users are pointed at to find the archived suite.
+    pub fn location(&self) -> String {
+        match (self.url.as_ref(), self.rsync.as_ref()) {
+            (Some(url), _) => url.clone(),
+            (None, Some(target)) => target.destination(),
+            (None, None) => self.path().display().to_string(),
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use debian::MULTI_ARCH_VALUES;
+use misc;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Replaces the `Section`, `Priority`, `Maintainer`, and `Multi-Arch` of packages in the
+/// `Packages` indices, without modifying their archives, such as to reclassify third-party
+/// packages whose control data is wrong.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Overrides {
+    /// An override file in the format of `apt-ftparchive`, where each line is a package, its
+    /// priority, its section, and optionally its maintainer.
+    pub file: Option<PathBuf>,
+    /// Overrides of each package, by its name, which take precedence over those of the file.
+    #[serde(default)]
+    pub packages: BTreeMap<String, Override>,
+}
+
+/// The fields which replace those of a package's control file.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Override {
+    pub section: Option<String>,
+    pub priority: Option<String>,
+    pub maintainer: Option<String>,
+    /// The `Multi-Arch` of the package, which may only be given inline, as the override file has
+    /// no column for it.
+    pub multi_arch: Option<String>,
+    /// The `Phased-Update-Percentage` of each version of the package, by its version, which
+    /// clients that honor phasing roll the version out to.
+    #[serde(default)]
+    pub phased: BTreeMap<String, u32>,
+    /// Only replaces the maintainer if it is this maintainer, as `old => new` does in an
+    /// override file.
+    #[serde(skip)]
+    pub previous_maintainer: Option<String>,
+}
+
+impl Overrides {
+    /// Reads the override file, if there is one, and merges the inline overrides into it.
+    pub fn load(&self) -> io::Result<BTreeMap<String, Override>> {
+        let mut overrides = match self.file {
+            Some(ref path) => parse_override_file(&misc::read_to_string(path)?).map_err(|why| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to parse the override file at {}: {}", path.display(), why)
+                )
+            })?,
+            None => BTreeMap::new()
+        };
+
+        for (package, inline) in &self.packages {
+            let entry = overrides.entry(package.clone()).or_insert_with(Override::default);
+            if inline.section.is_some() {
+                entry.section = inline.section.clone();
+            }
+
+            if inline.priority.is_some() {
+                entry.priority = inline.priority.clone();
+            }
+
+            if inline.maintainer.is_some() {
+                entry.maintainer = inline.maintainer.clone();
+                entry.previous_maintainer = None;
+            }
+
+            if let Some(ref multi_arch) = inline.multi_arch {
+                if !MULTI_ARCH_VALUES.contains(&multi_arch.as_str()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "the override of {} sets an invalid Multi-Arch: {}, which must be one of {}",
+                            package,
+                            multi_arch,
+                            MULTI_ARCH_VALUES.join(", ")
+                        )
+                    ));
+                }
+
+                entry.multi_arch = Some(multi_arch.clone());
+            }
+
+            for (version, &percentage) in &inline.phased {
+                if percentage > 100 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "the override of {} {} sets a Phased-Update-Percentage of {}, which exceeds 100",
+                            package,
+                            version,
+                            percentage
+                        )
+                    ));
+                }
+
+                entry.phased.insert(version.clone(), percentage);
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Advances the rollout of a version of a package by the given percentage, returning its new
+    /// percentage. A version which is not yet phased starts from nothing, and a version which
+    /// reaches 100 percent is no longer phased, as it has been rolled out to every client.
+    pub fn bump_phase(&mut self, package: &str, version: &str, by: u32) -> u32 {
+        let phased = &mut self.packages.entry(package.to_owned())
+            .or_insert_with(Override::default)
+            .phased;
+
+        let percentage = phased.get(version).map_or(0, |&x| x).saturating_add(by).min(100);
+        if percentage == 100 {
+            phased.remove(version);
+        } else {
+            phased.insert(version.to_owned(), percentage);
+        }
+
+        percentage
+    }
+}
+
+impl Override {
+    /// Replaces the fields of the control file with those of the override.
+    pub fn apply(&self, control: &mut BTreeMap<String, String>) {
+        if let Some(ref section) = self.section {
+            control.insert("Section".into(), section.clone());
+        }
+
+        if let Some(ref priority) = self.priority {
+            control.insert("Priority".into(), priority.clone());
+        }
+
+        if let Some(ref maintainer) = self.maintainer {
+            let replaces = self.previous_maintainer.as_ref().map_or(true, |previous| {
+                control.get("Maintainer").map_or(false, |current| current == previous)
+            });
+
+            if replaces {
+                control.insert("Maintainer".into(), maintainer.clone());
+            }
+        }
+
+        if let Some(ref multi_arch) = self.multi_arch {
+            control.insert("Multi-Arch".into(), multi_arch.clone());
+        }
+
+        let phased = control.get("Version").and_then(|version| self.phased.get(version)).cloned();
+        if let Some(percentage) = phased {
+            control.insert("Phased-Update-Percentage".into(), percentage.to_string());
+        }
+    }
+}
+
+/// Parses an override file of `apt-ftparchive`, where `#` begins a comment, and the maintainer
+/// may be given as `old => new` to only replace the maintainer `old`.
+pub fn parse_override_file(contents: &str) -> Result<BTreeMap<String, Override>, String> {
+    let mut overrides = BTreeMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.splitn(2, '#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue
+        }
+
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() < 3 {
+            return Err(format!("line {} lacks a package, priority, and section", number + 1));
+        }
+
+        let (package, priority, section) = (fields[0], fields[1], fields[2]);
+        let (previous_maintainer, maintainer) = if fields.len() == 3 {
+            (None, None)
+        } else {
+            let maintainer = fields[3..].join(" ");
+            match maintainer.find("=>") {
+                Some(pos) => (
+                    Some(maintainer[..pos].trim().to_owned()),
+                    Some(maintainer[pos + 2..].trim().to_owned())
+                ),
+                None => (None, Some(maintainer))
+            }
+        };
+
+        overrides.insert(package.to_owned(), Override {
+            section: Some(section.to_owned()),
+            priority: Some(priority.to_owned()),
+            maintainer,
+            multi_arch: None,
+            phased: BTreeMap::new(),
+            previous_maintainer,
+        });
+    }
+
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_overrides() {
+        let file = "# reclassified\n\
+            foo optional utils\n\
+            bar extra libs Old <old@example.com> => New <new@example.com>\n\
+            baz optional net\tOps <ops@example.com>\n";
+
+        let overrides = parse_override_file(file).unwrap();
+        assert_eq!(overrides.len(), 3);
+        assert_eq!(overrides["baz"].maintainer, Some("Ops <ops@example.com>".into()));
+        assert!(parse_override_file("foo optional\n").unwrap_err().starts_with("line 1"));
+
+        let control = |maintainer: &str| -> BTreeMap<String, String> {
+            vec![
+                ("Section".to_owned(), "misc".to_owned()),
+                ("Priority".to_owned(), "optional".to_owned()),
+                ("Maintainer".to_owned(), maintainer.to_owned()),
+            ].into_iter().collect()
+        };
+
+        let mut bar = control("Old <old@example.com>");
+        overrides["bar"].apply(&mut bar);
+        assert_eq!(bar["Section"], "libs");
+        assert_eq!(bar["Priority"], "extra");
+        assert_eq!(bar["Maintainer"], "New <new@example.com>");
+
+        let mut other = control("Other <other@example.com>");
+        overrides["bar"].apply(&mut other);
+        assert_eq!(other["Maintainer"], "Other <other@example.com>");
+
+        // Inline overrides replace only the fields that they give.
+        let mut inline = Overrides::default();
+        inline.packages.insert("foo".into(), Override {
+            section: Some("admin".into()),
+            ..Override::default()
+        });
+
+        inline.packages.insert("libfoo1".into(), Override {
+            multi_arch: Some("same".into()),
+            ..Override::default()
+        });
+
+        let overrides = inline.load().unwrap();
+        let mut foo = control("Foo <foo@example.com>");
+        overrides["foo"].apply(&mut foo);
+        assert_eq!(foo["Section"], "admin");
+        assert_eq!(foo["Maintainer"], "Foo <foo@example.com>");
+        assert!(!foo.contains_key("Multi-Arch"));
+
+        let mut libfoo = control("Foo <foo@example.com>");
+        overrides["libfoo1"].apply(&mut libfoo);
+        assert_eq!(libfoo["Multi-Arch"], "same");
+
+        inline.packages.get_mut("libfoo1").unwrap().multi_arch = Some("yes".into());
+        assert!(inline.load().is_err());
+    }
+
+    #[test]
+    fn phased_updates() {
+        let mut overrides = Overrides::default();
+        assert_eq!(overrides.bump_phase("foo", "1.1-1", 10), 10);
+        assert_eq!(overrides.bump_phase("foo", "1.1-1", 40), 50);
+
+        let mut control = vec![("Version".to_owned(), "1.1-1".to_owned())].into_iter().collect();
+        overrides.load().unwrap()["foo"].apply(&mut control);
+        assert_eq!(control["Phased-Update-Percentage"], "50");
+
+        // Other versions of the package are not phased.
+        let mut other = vec![("Version".to_owned(), "1.0-1".to_owned())].into_iter().collect();
+        overrides.packages["foo"].apply(&mut other);
+        assert!(!other.contains_key("Phased-Update-Percentage"));
+
+        assert_eq!(overrides.bump_phase("foo", "1.1-1", 80), 100);
+        assert!(overrides.packages["foo"].phased.is_empty());
+
+        overrides.packages.get_mut("foo").unwrap().phased.insert("1.2-1".into(), 101);
+        assert!(overrides.load().is_err());
+    }
+}
@@ -0,0 +1,7 @@
+/// The owner and group that the pool and dists of the repo are given once its dist files are
+/// generated, for web servers which serve the repo as another user, such as `www-data`.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Ownership {
+    pub uid: u32,
+    pub gid: u32,
+}
@@ -0,0 +1,43 @@
+use super::mirror::matches_glob;
+
+/// A check of the pool policy, which may only be warned of rather than refusing the archive.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyCheck {
+    ForbiddenPaths,
+    MaxBytes,
+    RequiredFields,
+}
+
+/// Checks which each archive must pass before it is moved into the pool, from the size of the
+/// archive to the files that it installs, and the fields of its control file.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct PoolPolicy {
+    /// Refuses archives larger than this many bytes, which defaults to `max_package_bytes`.
+    pub max_bytes: Option<u64>,
+    /// Globs of the paths that packages must not install, such as `/usr/local/*`.
+    pub forbidden_paths: Option<Vec<String>>,
+    /// Fields that the control file of each package must declare, such as `Homepage`.
+    pub required_fields: Option<Vec<String>>,
+    /// The checks which only warn of the archives which fail them, rather than refusing them.
+    pub warn: Option<Vec<PolicyCheck>>,
+}
+
+impl PoolPolicy {
+    pub fn warns(&self, check: PolicyCheck) -> bool {
+        self.warn.as_ref().map_or(false, |checks| checks.contains(&check))
+    }
+
+    /// The glob which forbids the path, if any does.
+    pub fn forbids(&self, path: &str) -> Option<&str> {
+        self.forbidden_paths.as_ref()?.iter()
+            .find(|glob| matches_glob(glob, path))
+            .map(|glob| glob.as_str())
+    }
+
+    /// Whether the archive must be read to check it, rather than only its size.
+    pub fn inspects(&self) -> bool {
+        self.forbidden_paths.as_ref().map_or(false, |globs| !globs.is_empty())
+            || self.required_fields.as_ref().map_or(false, |fields| !fields.is_empty())
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+/// Publishes the repo to a bucket of S3, or of a service which is compatible with it, such as
+/// MinIO or Ceph. Requests are signed with the credentials of the `AWS_ACCESS_KEY_ID` and
+/// `AWS_SECRET_ACCESS_KEY` environment variables, along with `AWS_SESSION_TOKEN` if it is set.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct S3Bucket {
+    pub bucket: String,
+    /// The URL of the service, which defaults to the AWS endpoint of the region. Buckets are
+    /// addressed by path, as in `https://minio.example.com/<bucket>/<key>`.
+    pub endpoint: Option<String>,
+    /// The region that requests are signed for, which defaults to `us-east-1`.
+    pub region: Option<String>,
+    /// Publishes the repo beneath this prefix of the bucket, such as `ubuntu`.
+    pub prefix: Option<String>,
+    /// Deletes the objects which are no longer in the repo, once everything else is uploaded.
+    #[serde(default)]
+    pub delete: bool,
+    /// The `Cache-Control` of the indices, which defaults to `no-cache`.
+    pub index_cache_control: Option<String>,
+    /// The `Cache-Control` of the pool and of the `by-hash` indices, which never change once
+    /// they are published, and default to `public, max-age=31536000, immutable`.
+    pub pool_cache_control: Option<String>,
+}
+
+impl S3Bucket {
+    pub fn region(&self) -> &str {
+        self.region.as_ref().map_or("us-east-1", |x| x.as_str())
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.endpoint.as_ref()
+            .map(|endpoint| endpoint.trim_right_matches('/').to_owned())
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", self.region()))
+    }
+
+    /// The prefix of every key, which ends with a `/` unless it is empty.
+    pub fn prefix(&self) -> String {
+        match self.prefix.as_ref().map(|x| x.trim_matches('/')) {
+            Some(prefix) if !prefix.is_empty() => [prefix, "/"].concat(),
+            _ => String::new()
+        }
+    }
+
+    pub fn index_cache_control(&self) -> &str {
+        self.index_cache_control.as_ref().map_or("no-cache", |x| x.as_str())
+    }
+
+    pub fn pool_cache_control(&self) -> &str {
+        self.pool_cache_control.as_ref()
+            .map_or("public, max-age=31536000, immutable", |x| x.as_str())
+    }
+}
+
+/// Publishes the repo into a directory with rsync, which is on another host if one is given.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct RsyncTarget {
+    /// The host to publish to, such as `deploy@apt.example.com`, which is connected to by ssh.
+    pub host: Option<String>,
+    /// The directory that the dists and pool are published within.
+    pub path: String,
+    /// The identity file that ssh authenticates with.
+    pub identity: Option<PathBuf>,
+    /// Arguments which are given to rsync in addition to `-az`, such as `--delete` or
+    /// `--bwlimit=1000`.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl RsyncTarget {
+    /// The destination of rsync, such as `host:path/`.
+    pub fn destination(&self) -> String {
+        let path = [self.path.trim_right_matches('/'), "/"].concat();
+        self.host.as_ref().map_or(path.clone(), |host| [host, ":", &path].concat())
+    }
+}
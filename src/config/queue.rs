@@ -0,0 +1,23 @@
+use super::mirror::matches_glob;
+
+/// Holds packages in the queue of the suite for review, rather than moving them into the pool,
+/// until they are approved with `debrep queue approve`.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Queue {
+    /// Holds packages which have never been published in their component of the suite, which
+    /// is the default.
+    pub new: Option<bool>,
+    /// Globs of the sources which are always held, whether or not they are new.
+    pub packages: Option<Vec<String>>,
+}
+
+impl Queue {
+    pub fn holds_new(&self) -> bool {
+        self.new.unwrap_or(true)
+    }
+
+    /// Whether the source matches a glob of the sources which are always held.
+    pub fn holds(&self, source: &str) -> bool {
+        self.packages.as_ref().map_or(false, |globs| globs.iter().any(|glob| matches_glob(glob, source)))
+    }
+}
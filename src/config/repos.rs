@@ -6,7 +6,9 @@ pub struct Repo {
     pub repo: String,
     pub version: Option<RepoPattern>,
     pub arch: Option<RepoPattern>,
-    pub name: Option<RepoPattern>
+    pub name: Option<RepoPattern>,
+    /// The component that packages from this repo are stored in, instead of the default.
+    pub component: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
@@ -55,7 +57,7 @@ fn match_regex(regex: &str, input: &str) -> bool {
     match Regex::new(regex) {
         Ok(regex) => regex.is_match(input),
         Err(why) => {
-            eprintln!("invalid regex: '{}': {}", regex, why);
+            warn!("invalid regex: '{}': {}", regex, why);
             false
         }
     }
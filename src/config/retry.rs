@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+/// Configures how often, and how patiently, downloads and rsync transfers are retried after a
+/// transient failure.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of attempts that are made before giving up, including the first.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// The delay in milliseconds before the first retry, which doubles after every attempt.
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+    /// The longest delay in milliseconds between two attempts.
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+    /// Randomizes each delay to between half and all of its length, so that concurrent
+    /// downloads do not retry in lockstep.
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: default_attempts(),
+            backoff: default_backoff(),
+            max_backoff: default_max_backoff(),
+            jitter: default_jitter(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before the given retry, where the first retry is `1`. The `random` value, from
+    /// `0` to `1`, selects the jitter of the delay.
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let exponent = retry.saturating_sub(1).min(32);
+        let millis = self.backoff.saturating_mul(1u64 << exponent).min(self.max_backoff);
+        let millis = if self.jitter {
+            millis / 2 + (millis as f64 / 2.0 * random.max(0.0).min(1.0)) as u64
+        } else {
+            millis
+        };
+
+        Duration::from_millis(millis)
+    }
+}
+
+fn default_attempts() -> u32 { 3 }
+fn default_backoff() -> u64 { 1000 }
+fn default_max_backoff() -> u64 { 30_000 }
+fn default_jitter() -> bool { true }
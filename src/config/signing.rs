@@ -0,0 +1,29 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The implementation which signs the dist files.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningBackend {
+    /// Shells out to `gpg`, using the keys of the user's keyring.
+    Gpg,
+    /// Signs natively with sequoia-openpgp, using the key in a key file.
+    Native,
+}
+
+impl Default for SigningBackend {
+    fn default() -> Self { SigningBackend::Gpg }
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Signing {
+    #[serde(default)]
+    pub backend: SigningBackend,
+    /// The key file, or keyring, containing the secret key for the native backend.
+    pub key: Option<PathBuf>,
+    /// Named keys, such as a `staging` and a `production` key, which each suite selects by its
+    /// `signing_key` or its pocket. Each is the ID of a gpg key, or a key file for the native
+    /// backend.
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+}
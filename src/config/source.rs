@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use super::{Builder, Lintian, SourceHooks};
 
 // Files that we want to cache and re-use between runs. These files will be symlinked.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -12,19 +14,42 @@ pub struct SourceAsset {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum DebianPath {
-    /// Fetches the debian directory from a separate URL.
-    URL { url: String, checksum: String },
+    /// Fetches the debian directory from a separate URL, which is verified by its SHA256 digest.
+    URL {
+        url: String,
+        #[serde(alias = "checksum")]
+        sha256: String
+    },
     /// Fetches the debian directory from a separate branch.
     Branch { url: String, branch: String }
 }
 
+/// The detached signature of an upstream tarball, and the upstream key which must have made it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SourceSignature {
+    /// The URL of the `.asc` or `.sig` signature of the tarball.
+    pub url: String,
+    /// The fingerprint of the upstream key.
+    pub fingerprint: String,
+    /// A keyring containing the upstream key, which is otherwise fetched from the keyserver.
+    pub keyring: Option<PathBuf>,
+    /// The keyserver that the upstream key is fetched from, which defaults to keys.openpgp.org.
+    pub keyserver: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum SourceLocation {
-    /// Fetch the source from a remote tarball.
-    URL { url: String, checksum: String },
-    /// Fetch the source by the git repository where it can be reached.
-    Git { git: String, branch: Option<String>, commit: Option<String> },
+    /// Fetch the source from a remote tarball, which is verified by its SHA256 digest. Configs
+    /// which predate the `sha256` key may still give it as `checksum`.
+    URL {
+        url: String,
+        #[serde(alias = "checksum")]
+        sha256: String
+    },
+    /// Fetch the source by the git repository where it can be reached, at a commit, tag, or
+    /// branch, in that order of precedence.
+    Git { git: String, branch: Option<String>, tag: Option<String>, commit: Option<String> },
     /// Fetch the source by an existing remote debian `.dsc` file.
     Dsc { dsc: String }
 }
@@ -47,8 +72,66 @@ pub struct Source {
     pub retain:           usize,
     #[serde(default = "default_requires_extract")]
     pub extract: bool,
+    /// The component that the packages of this source are stored in, instead of the default.
+    pub component: Option<String>,
+    /// Patches applied in order to the extracted source before it is built, which are stored
+    /// in `patches/<suite>/<name>/`.
+    pub patches: Option<Vec<String>>,
+    /// Overrides the repo's builder for this source.
+    pub builder: Option<Builder>,
+    /// Overrides the repo's lintian thresholds for this source, or enables lintian for it alone.
+    pub lintian: Option<Lintian>,
+    /// Environment variables that the builder of this source is run with.
+    pub env: Option<BTreeMap<String, String>>,
+    /// Flags which are given to dpkg-buildpackage, such as `-j4`.
+    pub build_flags: Option<Vec<String>>,
+    /// The options of `DEB_BUILD_OPTIONS`, such as `nocheck` or `parallel=4`.
+    pub build_options: Option<Vec<String>>,
+    /// The build profiles of the source, such as `nocheck` or `nodoc`, which also select the
+    /// build dependencies that are installed.
+    pub build_profiles: Option<Vec<String>>,
+    /// Scripts which are run before and after the source is built.
+    pub hooks: Option<SourceHooks>,
+    /// Restricts the architectures that the source is built for, which are otherwise every
+    /// architecture of its builder, to those of this list.
+    pub archs: Option<Vec<String>>,
+    /// Verifies the tarball of a `url` location by its detached upstream signature, refusing to
+    /// build the source if it was not signed by the upstream key.
+    pub signature: Option<SourceSignature>,
+}
+
+impl Source {
+    /// Whether the source may be built for this architecture.
+    pub fn builds_for(&self, arch: &str) -> bool {
+        self.archs.as_ref().map_or(true, |archs| archs.iter().any(|x| x == arch))
+    }
 }
 
 fn default_build_source() -> bool { true }
 fn default_retain() -> usize { 3 }
 fn default_requires_extract() -> bool { true }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    #[test]
+    fn checksum_alias() {
+        let source: Source = toml::from_str(r#"
+            name = "foo"
+            location = { url = "https://example.com/foo.tar.gz", checksum = "abc123" }
+            debian = { url = "https://example.com/foo-debian.tar.gz", checksum = "def456" }
+        "#).unwrap();
+
+        match source.location {
+            Some(SourceLocation::URL { ref sha256, .. }) => assert_eq!(sha256, "abc123"),
+            ref other => panic!("expected a URL location, found {:?}", other)
+        }
+
+        match source.debian {
+            Some(DebianPath::URL { ref sha256, .. }) => assert_eq!(sha256, "def456"),
+            ref other => panic!("expected a URL debian path, found {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use toml::Value;
+use toml::value::Table;
+
+/// Expands a config which defines several suites into the config of each suite.
+///
+/// Each table of the `suites` array is the keys of one suite, such as its `archive` and
+/// `version`, which are merged over the rest of the config, so that the sources and packages of
+/// the config are shared by every suite. Tables are merged key by key, and any other value
+/// replaces that of the config. The `sources` table of a suite is merged over each source of the
+/// config by its name, such as to give a source another `version` or `patches` in that suite.
+///
+/// A config without a `suites` array is the config of a single suite.
+pub fn expand_suites(mut config: Value) -> Result<Vec<Value>, String> {
+    let suites = match config.as_table_mut().and_then(|table| table.remove("suites")) {
+        Some(Value::Array(suites)) => suites,
+        Some(_) => return Err("`suites` must be an array of tables".into()),
+        None => return Ok(vec![config]),
+    };
+
+    if suites.is_empty() {
+        return Err("`suites` does not define any suite".into());
+    }
+
+    let mut configs = Vec::new();
+    for (id, suite) in suites.into_iter().enumerate() {
+        let mut suite = match suite {
+            Value::Table(suite) => suite,
+            _ => return Err(format!("suite {} of `suites` is not a table", id + 1)),
+        };
+
+        let mut expanded = config.clone();
+        if let Some(sources) = suite.remove("sources") {
+            merge_sources(&mut expanded, sources).map_err(|why| {
+                let archive = suite.get("archive").and_then(|x| x.as_str()).unwrap_or("");
+                format!("suite {} ({}): {}", id + 1, archive, why)
+            })?;
+        }
+
+        merge(&mut expanded, Value::Table(suite));
+        configs.push(expanded);
+    }
+
+    Ok(configs)
+}
+
+/// Merges the keys of one table over another, where the values of both which are tables are
+/// merged in turn, and any other value is replaced.
+pub(crate) fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (&mut Value::Table(ref mut base), Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing @ &mut Value::Table(_)) if value.is_table() => {
+                        merge(existing, value);
+                        continue
+                    }
+                    _ => ()
+                }
+
+                base.insert(key, value);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Merges each table of the `sources` of a suite over the source of the config with its name.
+fn merge_sources(config: &mut Value, sources: Value) -> Result<(), String> {
+    let sources: Table = match sources {
+        Value::Table(sources) => sources,
+        _ => return Err("`sources` must be a table of sources by their name".into()),
+    };
+
+    let defined = config.as_table_mut()
+        .and_then(|table| table.get_mut("source"))
+        .and_then(|sources| sources.as_array_mut());
+
+    let defined = match defined {
+        Some(defined) => defined,
+        None => return Err("`sources` is given, but no source is defined".into()),
+    };
+
+    for (name, overrides) in sources {
+        let source = defined.iter_mut()
+            .find(|source| source.get("name").and_then(|x| x.as_str()) == Some(name.as_str()))
+            .ok_or_else(|| format!("`sources.{}` does not name a source of the config", name))?;
+
+        merge(source, overrides);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    #[test]
+    fn multi_suite_config() {
+        let config: Value = toml::from_str(r#"
+            origin = "pop-os"
+            builder = { backend = "sbuild", mirror = "http://archive.ubuntu.com/ubuntu" }
+
+            [[source]]
+            name = "foo"
+            version = "1.0"
+            patches = ["fix-build.patch"]
+
+            [[suites]]
+            archive = "bionic"
+            version = "18.04"
+
+            [[suites]]
+            archive = "cosmic"
+            version = "18.10"
+            builder = { chroot = "cosmic-amd64-custom" }
+
+            [suites.sources.foo]
+            version = "1.1"
+            patches = ["fix-build.patch", "cosmic.patch"]
+        "#).unwrap();
+
+        let suites = expand_suites(config).unwrap();
+        assert_eq!(suites.len(), 2);
+        assert!(suites.iter().all(|suite| suite.get("suites").is_none()));
+
+        let bionic = &suites[0];
+        assert_eq!(bionic["archive"].as_str(), Some("bionic"));
+        assert_eq!(bionic["origin"].as_str(), Some("pop-os"));
+        assert_eq!(bionic["source"][0]["version"].as_str(), Some("1.0"));
+
+        let cosmic = &suites[1];
+        assert_eq!(cosmic["version"].as_str(), Some("18.10"));
+        assert_eq!(cosmic["builder"]["backend"].as_str(), Some("sbuild"));
+        assert_eq!(cosmic["builder"]["chroot"].as_str(), Some("cosmic-amd64-custom"));
+        assert_eq!(cosmic["source"][0]["version"].as_str(), Some("1.1"));
+        assert_eq!(cosmic["source"][0]["patches"].as_array().map(|x| x.len()), Some(2));
+
+        let unknown = toml::from_str::<Value>(r#"
+            [[source]]
+            name = "foo"
+
+            [[suites]]
+            archive = "bionic"
+
+            [suites.sources.bar]
+            version = "1.0"
+        "#).unwrap();
+
+        assert_eq!(
+            expand_suites(unknown).unwrap_err(),
+            "suite 1 (bionic): `sources.bar` does not name a source of the config"
+        );
+
+        let single = toml::from_str::<Value>("archive = \"bionic\"\n").unwrap();
+        assert_eq!(expand_suites(single).unwrap().len(), 1);
+    }
+}
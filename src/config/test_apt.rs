@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+use super::Upstream;
+
+/// Smoke tests the published suite with `debrep test-apt`, by installing packages from it with
+/// apt, within an apt root of its own.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct TestApt {
+    /// The packages which are downloaded by `apt-get install`, along with their dependencies.
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// The architectures which are tested, which default to those of the suite.
+    pub architectures: Option<Vec<String>>,
+    /// The keyring which verifies the signature of the suite, which is otherwise exported from
+    /// gpg by the signing key.
+    pub keyring: Option<PathBuf>,
+    /// The repositories which satisfy the dependencies that the suite does not, which default to
+    /// the upstream repositories of the dependency closure.
+    pub upstream: Option<Vec<Upstream>>,
+}
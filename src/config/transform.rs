@@ -0,0 +1,34 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Emits a modified copy of an existing package, such as to rebrand a vendor package without
+/// rebuilding it. The copy is placed into the pool, and the input is left as it is.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Transform {
+    /// The archive of the package which is transformed.
+    pub input: PathBuf,
+    /// Renames the package.
+    pub name: Option<String>,
+    /// Appended to the version of the package, such as `~pop1`.
+    pub version_suffix: Option<String>,
+    /// Renames packages within the relationship fields of the package, such as `Depends` and
+    /// `Conflicts`, by the package that they currently name.
+    #[serde(default)]
+    pub relations: BTreeMap<String, String>,
+    /// Replaces or adds control fields of the package. A field which is given an empty value is
+    /// removed.
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    /// Files which are injected into the package, or replace the files at their destination.
+    #[serde(default)]
+    pub files: Vec<TransformFile>,
+    /// The component that the package is stored in, instead of the default.
+    pub component: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct TransformFile {
+    pub src: PathBuf,
+    /// The absolute path that the file is installed to.
+    pub dst: PathBuf,
+}
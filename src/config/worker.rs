@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+/// A host which builds are dispatched to over ssh, rather than being run by this host. The
+/// source of each build is pushed to the worker, and its packages are pulled back and verified
+/// against the `.changes` of the build before they are moved into the build directory.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct Worker {
+    pub name: String,
+    /// The host that ssh connects to, such as `builder@arm64.example.com`.
+    pub host: String,
+    pub port: Option<u16>,
+    /// The identity file that ssh authenticates with.
+    pub identity: Option<PathBuf>,
+    /// The directory of the worker that builds are pushed to, which defaults to `debrep` within
+    /// the home of the user that ssh connects as.
+    pub directory: Option<String>,
+    /// The architectures which the worker builds, which default to all of them.
+    pub architectures: Option<Vec<String>>,
+    /// The sources which the worker builds, which default to all of them.
+    pub packages: Option<Vec<String>>,
+}
+
+impl Worker {
+    /// Whether the worker builds this source for the architecture.
+    pub fn builds(&self, source: &str, arch: &str) -> bool {
+        self.architectures.as_ref().map_or(true, |archs| archs.iter().any(|x| x == arch))
+            && self.packages.as_ref().map_or(true, |packages| packages.iter().any(|x| x == source))
+    }
+
+    pub fn directory(&self) -> &str {
+        self.directory.as_ref().map_or("debrep", |x| x.trim_right_matches('/'))
+    }
+
+    /// The arguments that ssh is given, before the host, to connect to the worker. Builds are
+    /// never interactive, so ssh fails rather than prompting for a password.
+    pub fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec!["-o".to_owned(), "BatchMode=yes".to_owned()];
+        if let Some(port) = self.port {
+            args.push("-p".into());
+            args.push(port.to_string());
+        }
+
+        if let Some(ref identity) = self.identity {
+            args.push("-i".into());
+            args.push(identity.display().to_string());
+        }
+
+        args
+    }
+}
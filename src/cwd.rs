@@ -0,0 +1,87 @@
+//! The current directory of the process, which the steps of a run, and most functions of
+//! `repo`, resolve their paths against and change into while they run.
+//!
+//! As every thread of the process shares one current directory, those entry points hold the
+//! lock of this module while they run, so that callers which drive several projects from
+//! different threads are serialized, rather than changing the directory from under each other.
+
+use std::marker::PhantomData;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+lazy_static! {
+    /// The thread which holds the current directory, and how many of its guards are alive.
+    static ref HOLDER: Mutex<Option<(ThreadId, usize)>> = Mutex::new(None);
+    static ref RELEASED: Condvar = Condvar::new();
+}
+
+/// The current directory of the process, held by the thread which took it until dropped.
+pub struct Guard {
+    // The guard must be released by the thread which took it.
+    _thread: PhantomData<*const ()>,
+}
+
+/// Takes the current directory for the calling thread, which blocks while another thread holds
+/// it. The lock is reentrant, so an entry point may call others which also take it.
+pub fn lock() -> Guard {
+    let id = thread::current().id();
+    let mut holder = holder();
+    while holder.map_or(false, |(owner, _)| owner != id) {
+        holder = RELEASED.wait(holder).unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+
+    let depth = holder.map_or(0, |(_, depth)| depth);
+    *holder = Some((id, depth + 1));
+    Guard { _thread: PhantomData }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut holder = holder();
+        *holder = match *holder {
+            Some((owner, depth)) if depth > 1 => Some((owner, depth - 1)),
+            _ => None
+        };
+
+        if holder.is_none() {
+            RELEASED.notify_all();
+        }
+    }
+}
+
+// A thread which panicked while holding the lock leaves nothing inconsistent behind.
+fn holder() -> MutexGuard<'static, Option<(ThreadId, usize)>> {
+    HOLDER.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn reentrant_and_exclusive() {
+        let outer = lock();
+        let inner = lock();
+
+        let entered = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let entered = entered.clone();
+            thread::spawn(move || {
+                let _guard = lock();
+                entered.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+
+        // The other thread waits until every guard of this thread has been dropped.
+        drop(inner);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(entered.load(Ordering::SeqCst), 0);
+
+        drop(outer);
+        handle.join().unwrap();
+        assert_eq!(entered.load(Ordering::SeqCst), 1);
+    }
+}
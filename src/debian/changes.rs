@@ -0,0 +1,368 @@
+use checksum::multi_hasher;
+use digest::Digest;
+use hex_view::HexView;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use super::parse_control;
+
+/// The outcome of verifying an upload against its `.changes` file.
+#[derive(Debug, PartialEq)]
+pub enum AcceptResult {
+    /// Every file listed by the `.changes` was received, and matched its checksum.
+    Accepted(Vec<String>),
+    /// The upload was rejected at the first file which failed verification.
+    Rejected { file: String, reason: String },
+}
+
+/// A file that a `.changes` lists, with its digests from each of the checksum fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangesFile {
+    pub name: String,
+    pub size: u64,
+    pub section: String,
+    pub priority: String,
+    pub md5: String,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+/// The fields of a `.changes` file which describe an upload, and the files which belong to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changes {
+    pub source: String,
+    pub version: String,
+    /// The suites that the upload is for.
+    pub distribution: Vec<String>,
+    pub maintainer: String,
+    pub changed_by: Option<String>,
+    pub architecture: Vec<String>,
+    /// The files of the `Files` field, in the order in which they are listed.
+    pub files: Vec<ChangesFile>,
+}
+
+impl Changes {
+    /// Parses a `.changes` file, which may be wrapped in a cleartext signature.
+    ///
+    /// The signature is not verified, so the fields of a signed file should only be trusted once
+    /// it has been, such as by `gpg::verify_signed`.
+    pub fn parse(data: &str) -> io::Result<Changes> {
+        let control = parse_control(strip_signature(data));
+        let field = |name: &str| control.get(name).cloned().ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(".changes does not contain a {} field", name)
+        ));
+
+        let malformed = |name: &str, line: &str| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed {} entry in .changes: {}", name, line.trim())
+        );
+
+        let mut files = Vec::new();
+        for line in field("Files")?.lines().filter(|line| !line.trim().is_empty()) {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            match (fields.len(), fields.get(1).and_then(|x| x.parse::<u64>().ok())) {
+                // Files are joined onto the directory of the upload, so they must not lead out of it.
+                (5, Some(_)) if !is_upload_filename(fields[4]) => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(".changes lists {}, which is not a valid filename", fields[4])
+                )),
+                (5, Some(size)) => files.push(ChangesFile {
+                    name: fields[4].to_owned(),
+                    size,
+                    section: fields[2].to_owned(),
+                    priority: fields[3].to_owned(),
+                    md5: fields[0].to_owned(),
+                    sha1: None,
+                    sha256: None,
+                }),
+                _ => return Err(malformed("Files", line))
+            }
+        }
+
+        for &name in &["Checksums-Sha1", "Checksums-Sha256"] {
+            let lines = control.get(name).into_iter().flat_map(|x| x.lines());
+            for line in lines.filter(|line| !line.trim().is_empty()) {
+                let fields = line.split_whitespace().collect::<Vec<&str>>();
+                if fields.len() != 3 {
+                    return Err(malformed(name, line));
+                }
+
+                let file = files.iter_mut().find(|file| file.name == fields[2]).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} lists {}, which is not listed by Files", name, fields[2])
+                    )
+                })?;
+
+                let digest = Some(fields[0].to_owned());
+                if name == "Checksums-Sha1" { file.sha1 = digest } else { file.sha256 = digest }
+            }
+        }
+
+        let words = |name: &str| control.get(name)
+            .map(|x| x.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
+
+        Ok(Changes {
+            source: field("Source")?,
+            version: field("Version")?,
+            distribution: words("Distribution"),
+            maintainer: field("Maintainer")?,
+            changed_by: control.get("Changed-By").cloned(),
+            architecture: words("Architecture"),
+            files,
+        })
+    }
+
+    /// Verifies that each file of the `.changes` is within the directory, with the size and
+    /// digests that it is listed with.
+    pub fn verify(&self, directory: &Path) -> io::Result<()> {
+        for file in &self.files {
+            let path = directory.join(&file.name);
+            let size = path.metadata().map_err(|why| io::Error::new(
+                why.kind(),
+                format!("{} is listed by the .changes, but is missing: {}", file.name, why)
+            ))?.len();
+
+            if size != file.size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} has {} bytes, but is listed with {}", file.name, size, file.size)
+                ));
+            }
+
+            let digests = multi_hasher(File::open(&path)?)?;
+            let listed = [
+                ("MD5", Some(&file.md5), &digests.md5),
+                ("SHA1", file.sha1.as_ref(), &digests.sha1),
+                ("SHA256", file.sha256.as_ref(), &digests.sha256),
+            ];
+
+            for &(kind, expected, actual) in &listed {
+                match expected {
+                    Some(expected) if expected != actual => return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} has the {} digest {}, but is listed with {}",
+                            file.name, kind, actual, expected
+                        )
+                    )),
+                    _ => ()
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a file named by an upload is a plain filename, which can neither lead out of the
+/// directory that it is joined onto, nor be hidden within it.
+pub fn is_upload_filename(name: &str) -> bool {
+    !name.is_empty() && !name.contains('/') && !name.starts_with('.')
+}
+
+/// The contents of a cleartext signed file, without the signature around them.
+fn strip_signature(data: &str) -> &str {
+    if !data.starts_with("-----BEGIN PGP SIGNED MESSAGE-----") {
+        return data;
+    }
+
+    // The armor headers, such as `Hash`, end at the first empty line.
+    let start = data.find("\n\n").map_or(data.len(), |pos| pos + 2);
+    let end = data[start..].find("\n-----BEGIN PGP SIGNATURE-----")
+        .map_or(data.len(), |pos| start + pos + 1);
+    &data[start..end]
+}
+
+/// The files, and their SHA256 digests and sizes, listed by the `Checksums-Sha256` field.
+pub fn changes_checksums(changes: &str) -> io::Result<HashMap<String, (String, u64)>> {
+    let control = parse_control(changes);
+    let checksums = control.get("Checksums-Sha256").ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        ".changes does not contain a Checksums-Sha256 field"
+    ))?;
+
+    let mut files = HashMap::new();
+    for line in checksums.lines().filter(|line| !line.trim().is_empty()) {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next().and_then(|x| x.parse::<u64>().ok()), fields.next()) {
+            (Some(digest), Some(size), Some(name)) => {
+                files.insert(name.to_owned(), (digest.to_owned(), size));
+            }
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed Checksums-Sha256 entry in .changes: {}", line.trim())
+            ))
+        }
+    }
+
+    Ok(files)
+}
+
+/// Verifies the files of an upload against the checksums of its `.changes` as they are streamed.
+///
+/// Each file is hashed as it is read, so nothing needs to be written to disk beforehand. The
+/// upload is rejected as soon as a file is unexpected, exceeds its declared size, or fails to
+/// match its digest. The remaining files are then left unread.
+pub fn verify_upload_streaming<I, R>(changes: &str, files: I) -> io::Result<AcceptResult>
+    where I: IntoIterator<Item = (String, R)>,
+          R: Read,
+{
+    let mut expected = changes_checksums(changes)?;
+    let mut accepted = Vec::new();
+    let mut buffer = [0u8; 8 * 1024];
+
+    for (name, mut reader) in files {
+        let (digest, size) = match expected.remove(&name) {
+            Some(checksum) => checksum,
+            None => return Ok(AcceptResult::Rejected {
+                reason: if accepted.contains(&name) {
+                    "file was uploaded more than once".into()
+                } else {
+                    "file is not listed in the .changes".into()
+                },
+                file: name,
+            })
+        };
+
+        let mut hasher = Sha256::new();
+        let mut read = 0u64;
+        loop {
+            let bytes = reader.read(&mut buffer)?;
+            if bytes == 0 { break }
+
+            read += bytes as u64;
+            if read > size {
+                return Ok(AcceptResult::Rejected {
+                    file: name,
+                    reason: format!("file exceeds its declared size of {} bytes", size),
+                });
+            }
+
+            hasher.input(&buffer[..bytes]);
+        }
+
+        let actual = format!("{:x}", HexView::from(hasher.result().as_slice()));
+        if read != size || actual != digest {
+            return Ok(AcceptResult::Rejected {
+                file: name,
+                reason: format!("expected {} ({} bytes), found {} ({} bytes)", digest, size, actual, read),
+            });
+        }
+
+        accepted.push(name);
+    }
+
+    let mut missing = expected.into_iter().map(|(name, _)| name).collect::<Vec<String>>();
+    missing.sort();
+    if let Some(file) = missing.into_iter().next() {
+        return Ok(AcceptResult::Rejected { file, reason: "file was not uploaded".into() });
+    }
+
+    Ok(AcceptResult::Accepted(accepted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tempfile::tempdir;
+
+    /// A reader of zeroes which records how many bytes have been consumed from it.
+    struct CountingReader {
+        remaining: usize,
+        consumed: Rc<Cell<usize>>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let bytes = buf.len().min(self.remaining);
+            for byte in &mut buf[..bytes] { *byte = 0; }
+            self.remaining -= bytes;
+            self.consumed.set(self.consumed.get() + bytes);
+            Ok(bytes)
+        }
+    }
+
+    // SHA256 of four zero bytes.
+    const ZEROES: &str = "df3f619804a92fdb4057192dc43dd748ea778adc52bc498ce80524c014b81119";
+
+    fn changes() -> String {
+        format!(
+            "Format: 1.8\nSource: foo\nChecksums-Sha256:\n {} 4 foo_1.0.dsc\n {} 4 foo_1.0_amd64.deb\n",
+            ZEROES, ZEROES
+        )
+    }
+
+    #[test]
+    fn streaming_upload_verification() {
+        let consumed = Rc::new(Cell::new(0));
+        let file = |name: &str, remaining| (name.to_owned(), CountingReader { remaining, consumed: consumed.clone() });
+
+        assert_eq!(
+            verify_upload_streaming(&changes(), vec![file("foo_1.0.dsc", 4), file("foo_1.0_amd64.deb", 4)]).unwrap(),
+            AcceptResult::Accepted(vec!["foo_1.0.dsc".into(), "foo_1.0_amd64.deb".into()])
+        );
+
+        consumed.set(0);
+        let result = verify_upload_streaming(
+            &changes(),
+            vec![file("foo_1.0.dsc", 1024 * 1024), file("foo_1.0_amd64.deb", 4)]
+        ).unwrap();
+
+        assert_eq!(result, AcceptResult::Rejected {
+            file: "foo_1.0.dsc".into(),
+            reason: "file exceeds its declared size of 4 bytes".into()
+        });
+        assert!(consumed.get() <= 8 * 1024);
+
+        match verify_upload_streaming(&changes(), vec![file("foo_1.0.dsc", 4)]).unwrap() {
+            AcceptResult::Rejected { file, .. } => assert_eq!(file, "foo_1.0_amd64.deb"),
+            result => panic!("upload with a missing file was accepted: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn changes_parsing() {
+        let signed = [
+            "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA256\n\n",
+            "Format: 1.8\nSource: foo\nVersion: 1.0\nDistribution: bionic\nArchitecture: source amd64\n",
+            "Maintainer: Foo <foo@example.com>\n",
+            "Checksums-Sha256:\n ", ZEROES, " 4 foo_1.0_amd64.deb\n",
+            "Files:\n f1d3ff8443297732862df21dc4e57262 4 utils optional foo_1.0_amd64.deb\n",
+            "-----BEGIN PGP SIGNATURE-----\n\niQEzBAEBCAAdFiEE\n-----END PGP SIGNATURE-----\n",
+        ].concat();
+
+        let changes = Changes::parse(&signed).unwrap();
+        assert_eq!(changes.distribution, vec!["bionic".to_owned()]);
+        assert_eq!(changes.architecture, vec!["source".to_owned(), "amd64".to_owned()]);
+        assert_eq!(changes.maintainer, "Foo <foo@example.com>");
+        assert_eq!(changes.files, vec![ChangesFile {
+            name: "foo_1.0_amd64.deb".into(),
+            size: 4,
+            section: "utils".into(),
+            priority: "optional".into(),
+            md5: "f1d3ff8443297732862df21dc4e57262".into(),
+            sha1: None,
+            sha256: Some(ZEROES.into()),
+        }]);
+
+        let dir = tempdir().unwrap();
+        assert_eq!(changes.verify(dir.path()).unwrap_err().kind(), io::ErrorKind::NotFound);
+        misc::write(dir.path().join("foo_1.0_amd64.deb"), &[0u8; 4]).unwrap();
+        changes.verify(dir.path()).unwrap();
+        misc::write(dir.path().join("foo_1.0_amd64.deb"), &[1u8; 4]).unwrap();
+        assert!(changes.verify(dir.path()).unwrap_err().to_string().contains("MD5 digest"));
+
+        let traversal = signed.replace(" foo_1.0_amd64.deb\n", " ../../foo_1.0_amd64.deb\n");
+        let why = Changes::parse(&traversal).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+        assert!(why.to_string().contains("../../foo_1.0_amd64.deb"));
+    }
+}
@@ -0,0 +1,170 @@
+use command::Command;
+use std::io;
+use std::path::Path;
+use super::{Control, DebFile};
+
+/// A control field whose value differs between `dpkg-deb` and the native control parser.
+#[derive(Debug, PartialEq)]
+pub struct FieldDiff {
+    pub field:  String,
+    pub dpkg:   Option<String>,
+    pub native: Option<String>,
+}
+
+/// Parses the fields of a control paragraph, folding continuation lines into their field.
+pub fn parse_control(data: &str) -> Control {
+    let mut control = Control::new();
+    let mut current: Option<(String, String)> = None;
+
+    for line in data.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, ref mut value)) = current {
+                value.push('\n');
+                value.push_str(line);
+            }
+        } else if let Some(pos) = line.find(':') {
+            if let Some((key, value)) = current.take() {
+                control.insert(key, value);
+            }
+
+            current = Some((line[..pos].trim().to_owned(), line[pos + 1..].trim().to_owned()));
+        }
+    }
+
+    if let Some((key, value)) = current {
+        control.insert(key, value);
+    }
+
+    control
+}
+
+/// The names of the packages within a relationship field, such as `Depends` or `Build-Depends`.
+///
+/// Every alternative is included, and version constraints, architecture qualifiers, build
+/// profiles, and substitution variables are discarded.
+pub fn relation_names(value: &str) -> Vec<String> {
+    value.split(|c| c == ',' || c == '|')
+        .filter_map(|relation| {
+            let relation = relation.trim();
+            let end = relation.find(|c: char| c.is_whitespace() || c == '(' || c == '[' || c == '<')
+                .unwrap_or_else(|| relation.len());
+            let name = &relation[..end];
+            let name = &name[..name.find(':').unwrap_or_else(|| name.len())];
+            if name.is_empty() || name.starts_with('$') { None } else { Some(name.to_owned()) }
+        })
+        .collect()
+}
+
+/// Normalizes multi-line values so that indentation differences between parsers are ignored.
+fn normalize(value: &str) -> String {
+    value.lines().map(|line| line.trim()).collect::<Vec<&str>>().join("\n")
+}
+
+/// Compares the control data reported by `dpkg-deb --field` against the native control parser.
+///
+/// Each field whose value differs, or which is only reported by one of the two, is returned.
+pub fn compare_control(deb: &Path) -> io::Result<Vec<FieldDiff>> {
+    let dpkg = Command::new("dpkg-deb")
+        .arg("--field")
+        .arg(deb)
+        .run_checked_with_stdout()
+        .map(|output| parse_control(&output))?;
+
+    let native = DebFile::open(deb)?.control()?.fields;
+
+    let mut diffs = Vec::new();
+    for (field, value) in &dpkg {
+        let native_value = native.get(field);
+        if native_value.map(|x| normalize(x)) != Some(normalize(value)) {
+            diffs.push(FieldDiff {
+                field: field.clone(),
+                dpkg: Some(value.clone()),
+                native: native_value.cloned(),
+            });
+        }
+    }
+
+    for (field, value) in &native {
+        if !dpkg.contains_key(field) {
+            diffs.push(FieldDiff {
+                field: field.clone(),
+                dpkg: None,
+                native: Some(value.clone()),
+            });
+        }
+    }
+
+    diffs.sort_by(|a, b| a.field.cmp(&b.field));
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const CONTROL: &str = "Package: debrep-sample
+Version: 1.0.0
+Architecture: all
+Maintainer: System76 <info@system76.com>
+Installed-Size: 1
+Section: misc
+Priority: optional
+Depends: libc6 (>= 2.27),
+ libssl1.1
+Description: sample package for control parsing
+ A longer description which spans
+ .
+ multiple paragraphs.
+";
+
+    #[test]
+    fn control_parsing() {
+        let control = parse_control(CONTROL);
+        assert_eq!(control.get("Package").map(|x| x.as_str()), Some("debrep-sample"));
+        assert_eq!(control.get("Depends").map(|x| normalize(x)), Some("libc6 (>= 2.27),\nlibssl1.1".into()));
+        assert_eq!(control.len(), 9);
+    }
+
+    #[test]
+    fn relation_parsing() {
+        assert_eq!(
+            relation_names("debhelper (>= 9),\n libfoo-dev [amd64] | libbar-dev:any,\n python3:native <!nocheck>, ${misc:Depends}"),
+            vec!["debhelper", "libfoo-dev", "libbar-dev", "python3"]
+        );
+    }
+
+    #[test]
+    fn control_matches_dpkg() {
+        if Command::new("dpkg-deb").arg("--version").run_with_stdout().is_err() {
+            return;
+        }
+
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("DEBIAN")).unwrap();
+        fs::create_dir_all(data.join("usr/share/doc/debrep-sample")).unwrap();
+        misc::write(data.join("DEBIAN/control"), CONTROL).unwrap();
+        misc::write(data.join("usr/share/doc/debrep-sample/README"), "sample").unwrap();
+
+        let deb = dir.path().join("debrep-sample_1.0.0_all.deb");
+        Command::new("dpkg-deb").arg("-b").arg(&data).arg(&deb).run().unwrap();
+
+        assert_eq!(compare_control(&deb).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn corrupt_archive_fails() {
+        if Command::new("dpkg-deb").arg("--version").run_with_stdout().is_err() {
+            return;
+        }
+
+        let dir = tempdir().unwrap();
+        let deb = dir.path().join("debrep-corrupt_1.0.0_all.deb");
+        misc::write(&deb, "not an archive").unwrap();
+
+        assert!(compare_control(&deb).is_err());
+    }
+}
@@ -0,0 +1,448 @@
+use bzip2::read::BzDecoder;
+use deflate::Compression;
+use deflate::write::GzEncoder;
+use libflate::gzip::Decoder as GzDecoder;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use tar::{Archive as TarArchive, Builder as TarBuilder, Entry as TarEntry, EntryType, Header};
+use xz2::read::XzDecoder;
+use xz2::stream::Stream;
+use xz2::write::XzEncoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use super::{parse_control, Control};
+
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+const AR_HEADER_LEN: usize = 60;
+
+/// A member of the `ar` archive which contains a Debian package.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArMember {
+    pub name: String,
+    /// The offset of the member's data from the start of the archive.
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// The fields and conffiles of the control member of a Debian package.
+#[derive(Debug, PartialEq)]
+pub struct DebControl {
+    pub fields: Control,
+    pub conffiles: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DataKind {
+    File,
+    Directory,
+    Symlink(PathBuf),
+    Hardlink(PathBuf),
+    Other,
+}
+
+/// A file that is shipped within the data member of a Debian package.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub mode: u32,
+    pub kind: DataKind,
+}
+
+/// A Debian package, whose members are read directly from its `ar` archive.
+pub struct DebFile {
+    path: PathBuf,
+    members: Vec<ArMember>,
+}
+
+impl DebFile {
+    /// Opens the package, and reads the header of each of its members.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let members = File::open(path)
+            .and_then(read_members)
+            .map_err(|why| io::Error::new(
+                why.kind(),
+                format!("failed to read {} as a Debian package: {}", path.display(), why)
+            ))?;
+
+        if !members.first().map_or(false, |member| member.name == "debian-binary") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not begin with a debian-binary member", path.display())
+            ));
+        }
+
+        Ok(DebFile { path: path.to_path_buf(), members })
+    }
+
+    pub fn members(&self) -> &[ArMember] { &self.members }
+
+    /// The format version which is stored in the `debian-binary` member.
+    pub fn format(&self) -> io::Result<String> {
+        let mut format = String::new();
+        self.member_reader(&self.members[0])?.read_to_string(&mut format)?;
+        Ok(format.trim().to_owned())
+    }
+
+    /// Parses the `control` and `conffiles` files of the control member.
+    pub fn control(&self) -> io::Result<DebControl> {
+        let mut control = None;
+        let mut conffiles = Vec::new();
+
+        let mut archive = TarArchive::new(self.tarball("control.tar")?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_path_buf();
+            let name = path.strip_prefix(".").unwrap_or(&path).to_path_buf();
+
+            if name == Path::new("control") {
+                let mut data = String::new();
+                entry.read_to_string(&mut data)?;
+                control = Some(parse_control(&data));
+            } else if name == Path::new("conffiles") {
+                let mut data = String::new();
+                entry.read_to_string(&mut data)?;
+                conffiles = data.lines()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .collect();
+            }
+        }
+
+        let fields = control.ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not have a control file", self.path.display())
+        ))?;
+
+        Ok(DebControl { fields, conffiles })
+    }
+
+    /// Lists the files of the data member, in the order that they are stored.
+    pub fn data(&self) -> io::Result<Vec<DataEntry>> {
+        let mut entries = Vec::new();
+        self.each_data(|entry| {
+            let header = entry.header();
+            let link = || entry.link_name().map(|link| link.map_or_else(PathBuf::new, |link| link.to_path_buf()));
+            let kind = match header.entry_type() {
+                EntryType::Regular | EntryType::Continuous => DataKind::File,
+                EntryType::Directory => DataKind::Directory,
+                EntryType::Symlink => DataKind::Symlink(link()?),
+                EntryType::Link => DataKind::Hardlink(link()?),
+                _ => DataKind::Other,
+            };
+
+            entries.push(DataEntry {
+                path: entry.path()?.to_path_buf(),
+                size: header.size()?,
+                mode: header.mode()?,
+                kind,
+            });
+
+            Ok(())
+        })?;
+
+        Ok(entries)
+    }
+
+    /// Visits each entry of the data member in the order that they are stored, so that the
+    /// contents of the entries may be read as the member is decompressed.
+    pub fn each_data<F>(&self, mut visit: F) -> io::Result<()>
+        where F: FnMut(&mut TarEntry<Box<Read>>) -> io::Result<()>
+    {
+        let mut archive = TarArchive::new(self.tarball("data.tar")?);
+        for entry in archive.entries()? {
+            visit(&mut entry?)?;
+        }
+
+        Ok(())
+    }
+
+    /// The decompressed tarball of the first member with the given prefix, such as `data.tar`.
+    pub fn member_tarball(&self, prefix: &str) -> io::Result<Vec<u8>> {
+        let mut tarball = Vec::new();
+        self.tarball(prefix)?.read_to_end(&mut tarball)?;
+        Ok(tarball)
+    }
+
+    /// Opens the first member with the given prefix, decompressing it by its extension.
+    fn tarball(&self, prefix: &str) -> io::Result<Box<Read>> {
+        let member = self.members.iter()
+            .find(|member| member.name.starts_with(prefix))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not have a {} member", self.path.display(), prefix)
+            ))?;
+
+        let reader = self.member_reader(member)?;
+        let reader: Box<Read> = match &member.name[prefix.len()..] {
+            "" => Box::new(reader),
+            ".gz" => Box::new(GzDecoder::new(reader)?),
+            ".bz2" => Box::new(BzDecoder::new(reader)),
+            ".lzma" => Box::new(XzDecoder::new_stream(reader, Stream::new_lzma_decoder(u64::max_value())?)),
+            ".xz" => Box::new(XzDecoder::new(reader)),
+            ".zst" => Box::new(ZstdDecoder::new(reader)?),
+            compression => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has a {} member compressed as {}, which is not supported: only gzip, bzip2, \
+                     lzma, xz, and zstd members may be read",
+                    self.path.display(),
+                    prefix,
+                    compression.trim_left_matches('.')
+                )
+            )),
+        };
+
+        Ok(reader)
+    }
+
+    fn member_reader(&self, member: &ArMember) -> io::Result<io::Take<File>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(member.offset))?;
+        Ok(file.take(member.size))
+    }
+}
+
+/// Writes a Debian package of the control and data tarballs, which are compressed with gzip and
+/// xz respectively.
+///
+/// Every member has a zero timestamp, so that the same package is always written the same way.
+pub fn write_deb<P: AsRef<Path>>(path: P, control: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+    encoder.write_all(control)?;
+    let control = encoder.finish()?;
+
+    let mut encoder = XzEncoder::new(Vec::new(), 6);
+    encoder.write_all(data)?;
+    let data = encoder.finish()?;
+
+    let members: &[(&str, &[u8])] = &[
+        ("debian-binary", b"2.0\n"),
+        ("control.tar.gz", &control),
+        ("data.tar.xz", &data),
+    ];
+
+    let mut archive = AR_MAGIC.to_vec();
+    for &(name, member) in members {
+        write!(archive, "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 100644, member.len())?;
+        archive.extend_from_slice(member);
+        if member.len() % 2 != 0 {
+            archive.push(b'\n');
+        }
+    }
+
+    File::create(path)?.write_all(&archive)
+}
+
+/// A tarball of the files, by their paths relative to the root, along with each of their parent
+/// directories, as dpkg expects of the members of a package.
+pub fn tarball(files: &[(&str, &[u8])]) -> io::Result<Vec<u8>> {
+    let mut directories = BTreeSet::new();
+    for &(path, _) in files {
+        let mut parent = Path::new(path).parent();
+        while let Some(directory) = parent.filter(|x| x != &Path::new("")) {
+            directories.insert(directory.to_path_buf());
+            parent = directory.parent();
+        }
+    }
+
+    let mut builder = TarBuilder::new(Vec::new());
+    append_tar_entry(&mut builder, "./", EntryType::Directory, 0o755, b"")?;
+    for directory in &directories {
+        let path = format!("./{}/", directory.display());
+        append_tar_entry(&mut builder, &path, EntryType::Directory, 0o755, b"")?;
+    }
+
+    for &(path, data) in files {
+        append_tar_entry(&mut builder, &["./", path].concat(), EntryType::Regular, 0o644, data)?;
+    }
+
+    builder.into_inner()
+}
+
+/// Appends an entry to the tarball, which is owned by root, and has a zero timestamp.
+pub fn append_tar_entry<W: Write>(
+    builder: &mut TarBuilder<W>,
+    path: &str,
+    entry_type: EntryType,
+    mode: u32,
+    data: &[u8]
+) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_path(path)?;
+    header.set_entry_type(entry_type);
+    header.set_size(data.len() as u64);
+    header.set_mode(mode);
+    header.set_mtime(0);
+    header.set_cksum();
+    builder.append(&header, data)
+}
+
+/// Reads the header of each member of an `ar` archive.
+fn read_members<R: Read + Seek>(mut reader: R) -> io::Result<Vec<ArMember>> {
+    let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_owned());
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != AR_MAGIC {
+        return Err(invalid("not an ar archive"));
+    }
+
+    let mut members = Vec::new();
+    let mut offset = AR_MAGIC.len() as u64;
+    let mut header = [0u8; AR_HEADER_LEN];
+
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(ref why) if why.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(why) => return Err(why),
+        }
+
+        if &header[58..60] != b"`\n" {
+            return Err(invalid("invalid ar member header"));
+        }
+
+        let field = |range: &[u8]| str::from_utf8(range).map(|x| x.trim_right().to_owned())
+            .map_err(|_| invalid("ar member header is not ASCII"));
+
+        // GNU ar terminates names with a slash.
+        let name = field(&header[..16])?;
+        let name = name.trim_right_matches('/').to_owned();
+        let size = field(&header[48..58])?.parse::<u64>()
+            .map_err(|_| invalid("invalid ar member size"))?;
+
+        offset += AR_HEADER_LEN as u64;
+        members.push(ArMember { name, offset, size });
+
+        // Members are aligned to an even offset.
+        offset += size + (size % 2);
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use tar::Builder;
+    use tempfile::tempdir;
+    use zstd;
+
+    fn tarball(entries: &[(&str, EntryType, &[u8], Option<&str>)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for &(path, entry_type, data, link) in entries {
+            let mut header = Header::new_gnu();
+            header.set_path(path).unwrap();
+            header.set_entry_type(entry_type);
+            header.set_size(data.len() as u64);
+            header.set_mode(if entry_type == EntryType::Directory { 0o755 } else { 0o644 });
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn ar_member(archive: &mut Vec<u8>, name: &str, data: &[u8]) {
+        write!(archive, "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 100644, data.len()).unwrap();
+        archive.extend_from_slice(data);
+        if data.len() % 2 != 0 {
+            archive.push(b'\n');
+        }
+    }
+
+    #[test]
+    fn native_deb_reader() {
+        let control = {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+            encoder.write_all(&tarball(&[
+                ("./", EntryType::Directory, b"", None),
+                ("./control", EntryType::Regular, b"Package: foo\nVersion: 1.0\nDescription: foo\n bar\n", None),
+                ("./conffiles", EntryType::Regular, b"/etc/foo.conf\n", None),
+            ])).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        let files = tarball(&[
+            ("./etc/", EntryType::Directory, b"", None),
+            ("./etc/foo.conf", EntryType::Regular, b"key=value", None),
+            ("./etc/bar.conf", EntryType::Symlink, b"", Some("foo.conf")),
+        ]);
+
+        let data = {
+            let mut encoder = XzEncoder::new(Vec::new(), 6);
+            encoder.write_all(&files).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        let mut archive = AR_MAGIC.to_vec();
+        ar_member(&mut archive, "debian-binary", b"2.0\n");
+        ar_member(&mut archive, "control.tar.gz/", &control);
+        ar_member(&mut archive, "data.tar.xz", &data);
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("foo_1.0_all.deb");
+        misc::write(&path, &archive).unwrap();
+
+        let deb = DebFile::open(&path).unwrap();
+        let names = deb.members().iter().map(|member| member.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["debian-binary", "control.tar.gz", "data.tar.xz"]);
+        assert_eq!(deb.format().unwrap(), "2.0");
+
+        let control = deb.control().unwrap();
+        assert_eq!(control.fields["Package"], "foo");
+        assert_eq!(control.fields["Description"], "foo\n bar");
+        assert_eq!(control.conffiles, vec![PathBuf::from("/etc/foo.conf")]);
+
+        let data = deb.data().unwrap();
+        assert_eq!(data.len(), 3);
+        assert_eq!(data[0].kind, DataKind::Directory);
+        assert_eq!(data[1], DataEntry {
+            path: PathBuf::from("./etc/foo.conf"),
+            size: 9,
+            mode: 0o644,
+            kind: DataKind::File,
+        });
+        assert_eq!(data[2].kind, DataKind::Symlink(PathBuf::from("foo.conf")));
+
+        // Newer versions of dpkg compress the members with zstd.
+        let zstd_deb = |name: &str| {
+            let mut archive = AR_MAGIC.to_vec();
+            ar_member(&mut archive, "debian-binary", b"2.0\n");
+            ar_member(&mut archive, "control.tar.zst", &zstd::stream::encode_all(&tarball(&[
+                ("./control", EntryType::Regular, b"Package: foo\nVersion: 1.0\n", None),
+            ])[..], 3).unwrap());
+            ar_member(&mut archive, name, &zstd::stream::encode_all(&files[..], 3).unwrap());
+            misc::write(&path, &archive).unwrap();
+            DebFile::open(&path).unwrap()
+        };
+
+        let deb = zstd_deb("data.tar.zst");
+        assert_eq!(deb.control().unwrap().fields["Version"], "1.0");
+        let mut contents = String::new();
+        deb.each_data(|entry| {
+            if entry.path()? == Path::new("./etc/foo.conf") {
+                entry.read_to_string(&mut contents)?;
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(contents, "key=value");
+
+        let why = zstd_deb("data.tar.lz").data().unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+        assert!(why.to_string().contains("has a data.tar member compressed as lz, which is not supported"));
+
+        misc::write(&path, b"!<arch>\n").unwrap();
+        assert_eq!(DebFile::open(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,481 @@
+use compress::*;
+use config::AppStream;
+use logging::json_string;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tar::{Builder as TarBuilder, EntryType};
+use debian::append_tar_entry;
+
+/// The files of a package which describe its applications, by their paths within the package.
+#[derive(Clone, Debug, Default)]
+pub struct AppStreamFiles {
+    pub package: String,
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+/// An application of a package, as it is described by the DEP-11 metadata.
+#[derive(Debug, Default, PartialEq)]
+struct AppComponent {
+    kind: String,
+    id: String,
+    package: String,
+    name: String,
+    summary: String,
+    description: Option<String>,
+    license: Option<String>,
+    homepage: Option<String>,
+    categories: Vec<String>,
+    launchable: Option<String>,
+    icon: Option<String>,
+    /// The size and filename of each icon of the application which is cached.
+    cached: Vec<(String, String)>,
+}
+
+/// An element of an XML document, with its attributes and its contents.
+struct Element<'a> {
+    offset: usize,
+    attributes: &'a str,
+    contents: &'a str,
+}
+
+impl<'a> Element<'a> {
+    fn attribute(&self, name: &str) -> Option<&'a str> {
+        let attributes = self.attributes;
+        let start = attributes.find(&[name, "="].concat())? + name.len() + 1;
+        let quote = attributes[start..].chars().next()?;
+        let value = &attributes[start + 1..];
+        Some(&value[..value.find(quote)?])
+    }
+
+    fn translated(&self) -> bool { self.attributes.contains("xml:lang") }
+
+    fn text(&self) -> String { decode(self.contents) }
+}
+
+/// Whether the file of a package is a metainfo file, desktop entry, or icon of an application.
+pub fn is_appstream_file(path: &str, settings: &AppStream) -> bool {
+    let path = path.trim_left_matches("./");
+    if path.starts_with("usr/share/metainfo/") || path.starts_with("usr/share/appdata/") {
+        return path.ends_with(".xml");
+    }
+
+    if path.starts_with("usr/share/applications/") {
+        return path.ends_with(".desktop");
+    }
+
+    settings.icon_sizes.iter().any(|size| {
+        let icons = ["usr/share/icons/hicolor/", size, "/apps/"].concat();
+        path.starts_with(&icons) && path.ends_with(".png")
+    })
+}
+
+/// Writes the DEP-11 metadata of the applications of a component, for each architecture, to its
+/// `dep11/Components-<arch>.yml.gz`, along with an icon tarball for each size of icon, whose
+/// icons are named by their package and icon.
+pub fn write_dep11(
+    path: &Path,
+    origin: &str,
+    settings: &AppStream,
+    architectures: &BTreeMap<String, Vec<AppStreamFiles>>,
+    level: Option<u32>,
+) -> io::Result<()> {
+    let dep11 = path.join("dep11");
+    fs::create_dir_all(&dep11)?;
+
+    let mut icons: BTreeMap<&str, BTreeMap<String, &[u8]>> = BTreeMap::new();
+    for (arch, packages) in architectures {
+        let mut yaml = header(origin, settings);
+        for package in packages {
+            for component in components(package, settings) {
+                yaml.push_str(&component_yaml(&component));
+            }
+
+            for (size, name, data) in cached_icons(package, settings) {
+                icons.entry(size).or_insert_with(BTreeMap::new).insert(name, data);
+            }
+        }
+
+        let name = ["Components-", arch, ".yml"].concat();
+        compress_level(&name, &dep11, yaml.as_bytes(), GZ_COMPRESS, level)?;
+    }
+
+    for size in &settings.icon_sizes {
+        let mut builder = TarBuilder::new(Vec::new());
+        for (name, data) in icons.get(size.as_str()).iter().flat_map(|x| x.iter()) {
+            append_tar_entry(&mut builder, name, EntryType::Regular, 0o644, data)?;
+        }
+
+        let tarball = builder.into_inner()?;
+        let name = ["icons-", size, ".tar"].concat();
+        compress_level(&name, &dep11, tarball.as_slice(), GZ_COMPRESS, level)?;
+    }
+
+    Ok(())
+}
+
+fn header(origin: &str, settings: &AppStream) -> String {
+    let mut header = format!("---\nFile: DEP-11\nVersion: '0.12'\nOrigin: {}\n", string(origin));
+    if let Some(ref url) = settings.media_base_url {
+        header.push_str(&format!("MediaBaseUrl: {}\n", string(url)));
+    }
+
+    header
+}
+
+/// The applications that the metainfo files of the package describe, which are given the icon
+/// of their desktop entry if they do not name one.
+fn components(package: &AppStreamFiles, settings: &AppStream) -> Vec<AppComponent> {
+    let file = |path: &str| {
+        package.files.iter()
+            .find(|&&(ref name, _)| name.trim_left_matches("./") == path)
+            .map(|&(_, ref data)| String::from_utf8_lossy(data).into_owned())
+    };
+
+    let metainfo = package.files.iter()
+        .filter(|&&(ref name, _)| name.ends_with(".xml"))
+        .map(|&(_, ref data)| String::from_utf8_lossy(data).into_owned());
+
+    let mut components = Vec::new();
+    for xml in metainfo {
+        let mut component = match parse_metainfo(&xml) {
+            Some(component) => component,
+            None => continue
+        };
+
+        component.package = package.package.clone();
+        let desktop_id = component.launchable.clone().or_else(|| {
+            if component.id.ends_with(".desktop") { Some(component.id.clone()) } else { None }
+        });
+
+        let desktop = desktop_id.and_then(|id| file(&["usr/share/applications/", &id].concat()));
+        if let Some(entry) = desktop.as_ref().map(|entry| desktop_entry(entry)) {
+            if component.icon.is_none() {
+                component.icon = entry.get("Icon").cloned();
+            }
+
+            if component.categories.is_empty() {
+                component.categories = entry.get("Categories").map_or_else(Vec::new, |x| {
+                    x.split(';').filter(|x| !x.is_empty()).map(|x| x.to_owned()).collect()
+                });
+            }
+        }
+
+        if let Some(icon) = component.icon.clone() {
+            for size in &settings.icon_sizes {
+                let path = ["usr/share/icons/hicolor/", size, "/apps/", &icon, ".png"].concat();
+                if file(&path).is_some() {
+                    let name = [&component.package, "_", &icon, ".png"].concat();
+                    component.cached.push((size.clone(), name));
+                }
+            }
+        }
+
+        components.push(component);
+    }
+
+    components
+}
+
+/// The icons of the package which are cached, by their size, and the name they are cached as.
+fn cached_icons<'a>(
+    package: &'a AppStreamFiles,
+    settings: &'a AppStream
+) -> Vec<(&'a str, String, &'a [u8])> {
+    let mut cached = Vec::new();
+    for size in &settings.icon_sizes {
+        let icons = ["usr/share/icons/hicolor/", size, "/apps/"].concat();
+        for &(ref path, ref data) in &package.files {
+            let path = path.trim_left_matches("./");
+            if path.starts_with(&icons) && path.ends_with(".png") {
+                let icon = &path[icons.len()..];
+                cached.push((size.as_str(), [&package.package, "_", icon].concat(), data.as_slice()));
+            }
+        }
+    }
+
+    cached
+}
+
+/// Parses the untranslated fields of a metainfo file, which must give an ID, name, and summary.
+fn parse_metainfo(xml: &str) -> Option<AppComponent> {
+    let root = elements(xml, "component").into_iter().next()?;
+    let xml = root.contents;
+    let field = |tag: &str| {
+        elements(xml, tag).into_iter().find(|element| !element.translated()).map(|x| x.text())
+    };
+
+    let kind = match root.attribute("type") {
+        Some("desktop") | Some("desktop-application") => "desktop-application",
+        Some(kind) => kind,
+        None => "generic",
+    };
+
+    let homepage = elements(xml, "url").into_iter()
+        .find(|url| url.attribute("type") == Some("homepage"))
+        .map(|url| url.text());
+
+    let launchable = elements(xml, "launchable").into_iter()
+        .find(|launchable| launchable.attribute("type") == Some("desktop-id"))
+        .map(|launchable| launchable.text());
+
+    let icon = elements(xml, "icon").into_iter()
+        .find(|icon| icon.attribute("type") == Some("stock"))
+        .map(|icon| icon.text());
+
+    let categories = elements(xml, "categories").into_iter().next().map_or_else(Vec::new, |x| {
+        elements(x.contents, "category").into_iter().map(|x| x.text()).collect()
+    });
+
+    let description = elements(xml, "description").into_iter()
+        .find(|element| !element.translated())
+        .map(|element| description(element.contents))
+        .filter(|description| !description.is_empty());
+
+    Some(AppComponent {
+        kind: kind.to_owned(),
+        id: field("id")?,
+        name: field("name")?,
+        summary: field("summary")?,
+        description,
+        license: field("project_license"),
+        homepage,
+        categories,
+        launchable,
+        icon,
+        ..AppComponent::default()
+    })
+}
+
+/// The untranslated paragraphs and lists of a description, as the markup of DEP-11.
+fn description(xml: &str) -> String {
+    let mut blocks = Vec::new();
+    for element in elements(xml, "p").into_iter().filter(|x| !x.translated()) {
+        blocks.push((element.offset, ["<p>", &collapse(element.contents), "</p>"].concat()));
+    }
+
+    for &list in &["ul", "ol"] {
+        for element in elements(xml, list) {
+            let items = elements(element.contents, "li").into_iter()
+                .filter(|x| !x.translated())
+                .map(|x| ["<li>", &collapse(x.contents), "</li>"].concat())
+                .collect::<String>();
+
+            blocks.push((element.offset, ["<", list, ">", &items, "</", list, ">"].concat()));
+        }
+    }
+
+    blocks.sort_by_key(|&(offset, _)| offset);
+    blocks.into_iter().map(|(_, block)| block).collect()
+}
+
+/// The elements of a tag within the XML, which are not nested within another of the same tag.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<Element<'a>> {
+    let open = ["<", tag].concat();
+    let close = ["</", tag, ">"].concat();
+    let mut found = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = xml[offset..].find(&open) {
+        let start = offset + start;
+        let after = start + open.len();
+        let end = match xml[after..].find('>') {
+            Some(end) => after + end,
+            None => break
+        };
+
+        // The tag must be followed by its attributes, or by its end, as `<name>` is not `<names>`.
+        let boundary = xml[after..].chars().next().unwrap_or('>');
+        if !(boundary == '>' || boundary == '/' || boundary.is_whitespace()) {
+            offset = after;
+            continue
+        }
+
+        let attributes = xml[after..end].trim();
+        if attributes.ends_with('/') {
+            let attributes = attributes.trim_right_matches('/');
+            found.push(Element { offset: start, attributes, contents: "" });
+            offset = end + 1;
+            continue
+        }
+
+        match xml[end + 1..].find(&close) {
+            Some(stop) => {
+                let contents = &xml[end + 1..end + 1 + stop];
+                found.push(Element { offset: start, attributes, contents });
+                offset = end + 1 + stop + close.len();
+            }
+            None => break
+        }
+    }
+
+    found
+}
+
+/// The keys of the `[Desktop Entry]` group of a desktop entry, without their translations.
+fn desktop_entry(entry: &str) -> BTreeMap<String, String> {
+    let mut keys = BTreeMap::new();
+    let mut group = false;
+    for line in entry.lines().map(|x| x.trim()) {
+        if line.starts_with('[') {
+            group = line == "[Desktop Entry]";
+        } else if let (true, Some(pos)) = (group, line.find('=')) {
+            let key = line[..pos].trim();
+            if !key.contains('[') {
+                keys.insert(key.to_owned(), line[pos + 1..].trim().to_owned());
+            }
+        }
+    }
+
+    keys
+}
+
+fn component_yaml(component: &AppComponent) -> String {
+    let mut yaml = format!(
+        "---\nType: {}\nID: {}\nPackage: {}\nName:\n  C: {}\nSummary:\n  C: {}\n",
+        component.kind,
+        string(&component.id),
+        string(&component.package),
+        string(&component.name),
+        string(&component.summary)
+    );
+
+    if let Some(ref description) = component.description {
+        yaml.push_str(&format!("Description:\n  C: {}\n", string(description)));
+    }
+
+    if let Some(ref license) = component.license {
+        yaml.push_str(&format!("ProjectLicense: {}\n", string(license)));
+    }
+
+    if !component.categories.is_empty() {
+        yaml.push_str("Categories:\n");
+        for category in &component.categories {
+            yaml.push_str(&format!("  - {}\n", string(category)));
+        }
+    }
+
+    if let Some(ref icon) = component.icon {
+        yaml.push_str(&format!("Icon:\n  stock: {}\n", string(icon)));
+        if !component.cached.is_empty() {
+            yaml.push_str("  cached:\n");
+            for &(ref size, ref name) in &component.cached {
+                let width = size.split('x').next().unwrap_or("");
+                let height = size.split('x').nth(1).unwrap_or(width);
+                yaml.push_str(&format!(
+                    "    - name: {}\n      width: {}\n      height: {}\n",
+                    string(name),
+                    width,
+                    height
+                ));
+            }
+        }
+    }
+
+    if let Some(ref launchable) = component.launchable {
+        yaml.push_str(&format!("Launchable:\n  desktop-id:\n    - {}\n", string(launchable)));
+    }
+
+    if let Some(ref homepage) = component.homepage {
+        yaml.push_str(&format!("Url:\n  homepage: {}\n", string(homepage)));
+    }
+
+    yaml
+}
+
+/// A double-quoted YAML scalar, which is written as a JSON string, as YAML is its superset.
+fn string(value: &str) -> String {
+    json_string(value)
+}
+
+/// Collapses the whitespace of markup, which is insignificant in a metainfo file.
+fn collapse(markup: &str) -> String {
+    markup.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The text of an element, with its entities decoded.
+fn decode(text: &str) -> String {
+    collapse(text)
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dep11_metadata() {
+        let metainfo = r#"<?xml version="1.0" encoding="UTF-8"?>
+<component type="desktop-application">
+  <id>com.system76.Popsicle</id>
+  <name>Popsicle</name>
+  <name xml:lang="de">Eis am Stiel</name>
+  <summary>Flash multiple USB devices in parallel</summary>
+  <project_license>MIT</project_license>
+  <description>
+    <p>Writes an image &amp; verifies it.</p>
+    <p xml:lang="de">Schreibt ein Abbild.</p>
+    <ul>
+      <li>Parallel</li>
+      <li xml:lang="de">Parallel</li>
+    </ul>
+  </description>
+  <launchable type="desktop-id">com.system76.Popsicle.desktop</launchable>
+  <url type="homepage">https://github.com/pop-os/popsicle</url>
+</component>
+"#;
+
+        let desktop = "[Desktop Entry]\nName=Popsicle\nIcon=popsicle\nCategories=System;Utility;\n\
+            \n[Desktop Action Write]\nIcon=other\n";
+
+        let settings = AppStream { icon_sizes: vec!["64x64".into()], media_base_url: None };
+        let package = AppStreamFiles {
+            package: "popsicle-gtk".into(),
+            files: vec![
+                ("./usr/share/metainfo/com.system76.Popsicle.appdata.xml".into(), metainfo.into()),
+                ("./usr/share/applications/com.system76.Popsicle.desktop".into(), desktop.into()),
+                ("./usr/share/icons/hicolor/64x64/apps/popsicle.png".into(), b"png".to_vec()),
+            ],
+        };
+
+        assert!(package.files.iter().all(|&(ref path, _)| is_appstream_file(path, &settings)));
+        assert!(!is_appstream_file("./usr/share/icons/hicolor/32x32/apps/popsicle.png", &settings));
+
+        let components = components(&package, &settings);
+        assert_eq!(components.len(), 1);
+        assert_eq!(component_yaml(&components[0]), "---
+Type: desktop-application
+ID: \"com.system76.Popsicle\"
+Package: \"popsicle-gtk\"
+Name:
+  C: \"Popsicle\"
+Summary:
+  C: \"Flash multiple USB devices in parallel\"
+Description:
+  C: \"<p>Writes an image &amp; verifies it.</p><ul><li>Parallel</li></ul>\"
+ProjectLicense: \"MIT\"
+Categories:
+  - \"System\"
+  - \"Utility\"
+Icon:
+  stock: \"popsicle\"
+  cached:
+    - name: \"popsicle-gtk_popsicle.png\"
+      width: 64
+      height: 64
+Launchable:
+  desktop-id:
+    - \"com.system76.Popsicle.desktop\"
+Url:
+  homepage: \"https://github.com/pop-os/popsicle\"
+");
+
+        let icons = cached_icons(&package, &settings);
+        assert_eq!(icons, vec![("64x64", "popsicle-gtk_popsicle.png".to_owned(), &b"png"[..])]);
+    }
+}
@@ -1,15 +1,22 @@
+mod appstream;
 mod package;
+mod source;
+mod translation;
 
 use config::Config;
 use iter_reader::IteratorReader;
 use itertools::Itertools;
 use rayon;
 use rayon::prelude::*;
+use std::collections::{btree_map, BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{self, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+pub use self::appstream::*;
 pub use self::package::*;
+pub use self::source::*;
+pub use self::translation::*;
 use super::*;
 
 pub struct DistFiles<'a> {
@@ -43,49 +50,55 @@ impl<'a> DistFiles<'a> {
         let entries = self.entries;
         let path = self.path;
 
+        // Descriptions are shared by every architecture of a component, so they are collected beforehand.
+        let mut translations: HashMap<String, Translations> = HashMap::new();
+        for components in entries.values() {
+            for (component, &(ref packages, _)) in components {
+                // Installer packages are not translated.
+                if is_installer_component(component) {
+                    continue
+                }
+
+                let translations = translations.entry(component.clone()).or_insert_with(Translations::default);
+                for package in packages {
+                    translations.insert(&package.control);
+                }
+            }
+        }
+
         // Processes each architecture in parallel, including the contents archives for each arch.
-        entries.into_par_iter().map(|(arch, (packages, contents))| {
+        entries.into_par_iter().map(|(arch, components)| {
             let arch: &str = &arch;
-            let (contents_res, packages_res) = rayon::join(
-                // Generate and compress the Contents archive for each architecture in parallel.
-                // Contents are processed in a per-architecture manner, rather than per-component.
-                || {
-                    // Sort the files beforehand, so files are easy to track down.
-                    // This will require that we generate the contents archive in advance, sadly.
-                    let mut contents = ContentsIterator::new(contents).collect::<Vec<Vec<u8>>>();
-                    contents.par_sort_unstable_by(|a, b| a.cmp(&b));
-
-                    let contents_reader = IteratorReader::new(
-                        contents.into_iter(),
-                        Vec::with_capacity(64 * 1024)
-                    );
-
-                    // Similar to the Packages archives, we also need an uncompressed variant of
-                    // the compressed archives to satisfy APT's detection capabilities.
-                    compress(&["Contents-", &arch].concat(), path, contents_reader, UNCOMPRESSED | GZ_COMPRESS | XZ_COMPRESS)
-                },
-                // Generate & compress each Packages archive for each architecture & component in parallel.
-                // Packages archives are processed in a per-architecture, per-component manner.
-                || {
-                    let arch_dir = match arch {
-                        "amd64" => "binary-amd64",
-                        "arm64" => "binary-arm64",
-                        "armel" => "binary-armel",
-                        "armhf" => "binary-armhf",
-                        "i386" => "binary-i386",
-                        "mips" => "binary-mips",
-                        "mipsel" => "binary-mipsel",
-                        "mips64el" => "binary-mips64el",
-                        "ppc64el" => "binary-ppc64el",
-                        "s390x" => "binary-s390x",
-                        "all" => "binary-all",
-                        arch => panic!("unsupported architecture: {}", arch),
-                    };
-
-                    // Processes the packages of each component in parallel, for this architecture.
-                    packages.into_par_iter().map(|(component, mut packages)| {
+            let arch_dir = &["binary-", arch].concat();
+
+            // Processes the packages and contents of each component in parallel, for this architecture.
+            components.into_par_iter().map(|(component, (mut packages, contents))| {
+                let component_path = &path.join(&component);
+
+                // The contents of installer packages are listed beside those of their component.
+                let (contents_path, contents_name) = if is_installer_component(&component) {
+                    let parent = &component[..component.len() - DEBIAN_INSTALLER.len() - 1];
+                    (path.join(parent), ["Contents-udeb-", arch].concat())
+                } else {
+                    (component_path.clone(), ["Contents-", arch].concat())
+                };
+
+                let (contents_res, packages_res) = rayon::join(
+                    // Generate and compress the Contents archive of this component's architecture.
+                    || {
+                        // The iterator merges and sorts the files beforehand, so files are easy to track down.
+                        let contents_reader = IteratorReader::new(
+                            ContentsIterator::new(contents),
+                            Vec::with_capacity(64 * 1024)
+                        );
+
+                        // Similar to the Packages archives, these are written with the configured compressions.
+                        compress_level(&contents_name, &contents_path, contents_reader, config.compression(), config.compression_level)
+                    },
+                    // Generate & compress the Packages archive of this component's architecture.
+                    || {
                         // Construct the path where the Packages archives will be written.
-                        let binary_path = &path.join(&component).join(arch_dir);
+                        let binary_path = &component_path.join(arch_dir);
 
                         // Sort the packages that were collected before we generate them for writing.
                         packages.par_sort_unstable_by(|a, b| a.filename.cmp(&b.filename));
@@ -103,9 +116,9 @@ impl<'a> DistFiles<'a> {
                             Vec::with_capacity(64 * 1024)
                         );
 
-                        // Although we will generate a compressed GZ and XZ archive for our
-                        // repository, APT still requires that we also write an uncompressed variant.
-                        compress("Packages", binary_path, packages_reader, UNCOMPRESSED | GZ_COMPRESS | XZ_COMPRESS)
+                        // The configured compressions are written, which by default also includes the
+                        // uncompressed variant that older releases of APT require.
+                        compress_level("Packages", binary_path, packages_reader, config.compression(), config.compression_level)
                             .map_err(|why| io::Error::new(
                                 io::ErrorKind::Other,
                                 format!("failed to generate content archive at {}: {}", path.display(), why)
@@ -113,36 +126,63 @@ impl<'a> DistFiles<'a> {
 
                         // A release file also needs to be stored in the same location, after the
                         // archives have been written. This contains the checksums for each file.
-                        inner_write_release_file(config, binary_path, arch_dir, &component).map_err(|why| io::Error::new(
+                        inner_write_release_file(config, binary_path, arch, &component).map_err(|why| io::Error::new(
                             io::ErrorKind::Other,
                             format!("failed to create release file for {}: {}", binary_path.display(), why)
                         ))
-                    }).collect::<io::Result<()>>()
-                }
-            );
-
-            // Check the results to see if we passed.
-            contents_res.map_err(|why| io::Error::new(
+                    }
+                );
+
+                // Check the results to see if we passed.
+                contents_res.map_err(|why| io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("failed to generate content archive at {}: {}", component_path.display(), why)
+                ))?;
+
+                packages_res
+            }).collect::<io::Result<()>>()
+        }).collect::<io::Result<()>>()?;
+
+        // Then the Translation-en index of each component is written alongside its architectures.
+        translations.into_par_iter().map(|(component, translations)| {
+            translations.write(&path.join(&component)).map_err(|why| io::Error::new(
                 io::ErrorKind::Other,
-                format!("failed to generate content archive at {}: {}", path.display(), why)
-            ))?;
-
-            packages_res
+                format!("failed to generate translation index for {}: {}", component, why)
+            ))
         }).collect::<io::Result<()>>()
     }
 }
 
 /// Efficiently generate each line of the Contents file, in style.
+///
+/// Lines are sorted by path, and a path which is shipped by more than one package lists each
+/// of the `section/package` entries that ship it, separated by commas.
 pub struct ContentsIterator {
-    contents: Vec<ContentsEntry>,
+    packages: Vec<String>,
+    files: btree_map::IntoIter<Vec<u8>, Vec<usize>>,
     buffer: Vec<u8>,
-    package: usize,
-    file: usize
 }
 
 impl ContentsIterator {
     pub fn new(contents: Vec<ContentsEntry>) -> Self {
-        ContentsIterator { contents, buffer: Vec::with_capacity(512), package: 0, file: 0 }
+        let mut packages = Vec::with_capacity(contents.len());
+        let mut files: BTreeMap<Vec<u8>, Vec<usize>> = BTreeMap::new();
+
+        for (id, entry) in contents.into_iter().enumerate() {
+            for path in &entry.files {
+                let path = path.as_os_str().as_bytes();
+                let path = if path.starts_with(b"./") { &path[2..] } else { path };
+                if path.is_empty() {
+                    continue
+                }
+
+                files.entry(path.to_owned()).or_insert_with(Vec::new).push(id);
+            }
+
+            packages.push(entry.package);
+        }
+
+        ContentsIterator { packages, files: files.into_iter(), buffer: Vec::with_capacity(512) }
     }
 }
 
@@ -150,30 +190,30 @@ impl Iterator for ContentsIterator {
     type Item = Vec<u8>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let contents_entry = self.contents.get(self.package)?;
-            match contents_entry.files.get(self.file) {
-                Some(path) => {
-                    self.file += 1;
-                    let path = path.as_os_str().as_bytes();
-                    self.buffer.extend_from_slice(if &path[..2] == b"./" { &path[2..] } else { path });
-                    self.buffer.extend_from_slice(b"  ");
-                    self.buffer.extend_from_slice(contents_entry.package.as_bytes());
-                    self.buffer.push(b'\n');
-                    let mut serialized = self.buffer.clone();
-                    serialized.shrink_to_fit();
-                    self.buffer.clear();
-                    return Some(serialized);
-                }
-                None => {
-                    self.file = 0;
-                    self.package += 1;
-                }
+        let (path, mut owners) = self.files.next()?;
+        let packages = &self.packages;
+        owners.sort_by(|&a, &b| packages[a].cmp(&packages[b]));
+        owners.dedup_by(|a, b| packages[*a] == packages[*b]);
+
+        self.buffer.extend_from_slice(&path);
+        self.buffer.extend_from_slice(b"  ");
+        for (pos, &owner) in owners.iter().enumerate() {
+            if pos != 0 {
+                self.buffer.push(b',');
             }
+            self.buffer.extend_from_slice(packages[owner].as_bytes());
         }
+        self.buffer.push(b'\n');
+
+        let mut serialized = self.buffer.clone();
+        serialized.shrink_to_fit();
+        self.buffer.clear();
+        Some(serialized)
     }
 }
 
+/// The files within the data archive of a package, and the `section/package` that owns them.
+#[derive(Clone)]
 pub struct ContentsEntry {
     pub package: String,
     pub files: Vec<PathBuf>
@@ -181,10 +221,34 @@ pub struct ContentsEntry {
 
 fn inner_write_release_file(config: &Config, destination: &Path, arch: &str, component: &str) -> io::Result<()> {
     let mut release = File::create(destination.join("Release"))?;
-    writeln!(&mut release, "Archive: {}", config.archive)?;
+    writeln!(&mut release, "Archive: {}", config.release_suite())?;
     writeln!(&mut release, "Version: {}", config.version)?;
     writeln!(&mut release, "Component: {}", component)?;
     writeln!(&mut release, "Origin: {}", config.origin)?;
     writeln!(&mut release, "Label: {}", config.label)?;
     writeln!(&mut release, "Architecture: {}", arch)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_merging() {
+        let entry = |package: &str, files: &[&str]| ContentsEntry {
+            package: package.into(),
+            files: files.iter().map(PathBuf::from).collect()
+        };
+
+        let contents = ContentsIterator::new(vec![
+            entry("utils/foo", &["./", "./usr/bin/foo", "./usr/share/doc/shared"]),
+            entry("libs/bar", &["./usr/lib/libbar.so", "./usr/share/doc/shared"]),
+        ]).collect::<Vec<Vec<u8>>>();
+
+        assert_eq!(String::from_utf8(contents.concat()).unwrap(), "\
+usr/bin/foo  utils/foo
+usr/lib/libbar.so  libs/bar
+usr/share/doc/shared  libs/bar,utils/foo
+");
+    }
+}
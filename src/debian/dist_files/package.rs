@@ -1,8 +1,16 @@
 use std::collections::BTreeMap;
 use std::io;
+use std::mem;
 use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
+use super::{description_md5, full_description};
 
+/// Fields of the control which are replaced by the `Packages` entry.
+const REPLACED: &[&str] = &[
+    "Bugs", "Description-md5", "Filename", "MD5sum", "Origin", "SHA1", "SHA256", "SHA512", "Size",
+];
+
+#[derive(Clone)]
 pub struct PackageEntry {
     pub control: BTreeMap<String, String>,
     pub filename: PathBuf,
@@ -17,6 +25,7 @@ impl PackageEntry {
     pub fn generate_entry(mut self, origin: &str, bugs: Option<&str>) -> io::Result<Vec<u8>> {
         let mut output = Vec::with_capacity(1024);
         let control = &mut self.control;
+        let description = full_description(control);
 
         fn get_key(map: &mut BTreeMap<String, String>, key: &str) -> io::Result<String> {
             map.remove(key).ok_or_else(|| io::Error::new(
@@ -72,16 +81,87 @@ impl PackageEntry {
         }
         write_entry(&mut output, b"Filename", self.filename.as_os_str().as_bytes());
         write_entry(&mut output, b"Size", self.size.to_string().as_bytes());
-        write_entry(&mut output, b"MD5sum", self.md5sum.as_bytes());
-        write_entry(&mut output, b"SHA1", self.sha1.as_bytes());
-        write_entry(&mut output, b"SHA256", self.sha256.as_bytes());
-        write_entry(&mut output, b"SHA512", self.sha512.as_bytes());
+        // Digests which were not selected are not computed, and so are not listed.
+        for &(key, digest) in &[
+            (&b"MD5sum"[..], &self.md5sum),
+            (&b"SHA1"[..], &self.sha1),
+            (&b"SHA256"[..], &self.sha256),
+            (&b"SHA512"[..], &self.sha512),
+        ] {
+            if !digest.is_empty() {
+                write_entry(&mut output, key, digest.as_bytes());
+            }
+        }
         optional_map!("Homepage");
-        optional_map!("Description");
+        // The long description is shipped in the Translation-en index, which is keyed by its MD5.
+        if let Some(ref description) = description {
+            write_entry(&mut output, b"Description", description.lines().next().unwrap_or("").as_bytes());
+            write_entry(&mut output, b"Description-md5", description_md5(description).as_bytes());
+        }
+        control.remove("Description");
         optional_map!("License");
         optional_map!("Vendor");
         optional_map!("Build-Ids");
 
+        // Any other fields, such as `Source`, `Built-Using`, or `Phased-Update-Percentage`, follow
+        // in the order of their names, so that an entry is written the same way on every run.
+        for key in REPLACED {
+            control.remove(*key);
+        }
+
+        for (key, value) in mem::replace(control, BTreeMap::new()) {
+            if let Some(value) = value.lines().next() {
+                write_entry(&mut output, key.as_bytes(), value.as_bytes());
+            }
+        }
+
         Ok(output)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packages_entry_order() {
+        let control = [
+            ("Source", "foo-src"),
+            ("Phased-Update-Percentage", "10"),
+            ("Size", "1"),
+            ("Installed-Size", "10"),
+            ("Maintainer", "Pop <info@example.com>"),
+            ("Section", "utils"),
+            ("Priority", "optional"),
+            ("Version", "1.0"),
+            ("Architecture", "amd64"),
+            ("Package", "foo"),
+        ].iter().map(|&(key, value)| (key.to_owned(), value.to_owned())).collect();
+
+        let entry = PackageEntry {
+            control,
+            filename: PathBuf::from("pool/main/f/foo/foo_1.0_amd64.deb"),
+            size: 100,
+            md5sum: String::new(),
+            sha1: String::new(),
+            sha256: "abc".into(),
+            sha512: String::new(),
+        };
+
+        assert_eq!(String::from_utf8(entry.generate_entry("Pop", None).unwrap()).unwrap(), "\
+Package: foo
+Architecture: amd64
+Version: 1.0
+Priority: optional
+Section: utils
+Origin: Pop
+Maintainer: Pop <info@example.com>
+Installed-Size: 10
+Filename: pool/main/f/foo/foo_1.0_amd64.deb
+Size: 100
+SHA256: abc
+Phased-Update-Percentage: 10
+Source: foo-src
+");
+    }
+}
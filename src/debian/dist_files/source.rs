@@ -0,0 +1,218 @@
+use checksum::DigestCache;
+use debian::{parse_control, Control};
+use misc;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// Fields which are written in this order at the top of each `Sources` entry, when present.
+const ORDERED: &[&str] = &[
+    "Format", "Binary", "Architecture", "Version", "Maintainer", "Uploaders", "Homepage",
+    "Standards-Version", "Vcs-Browser", "Vcs-Git", "Testsuite", "Build-Depends",
+    "Build-Depends-Indep", "Build-Depends-Arch", "Build-Conflicts", "Build-Conflicts-Indep",
+    "Build-Conflicts-Arch", "Package-List",
+];
+
+/// Fields of the `.dsc` which are replaced by the `Sources` entry.
+const REPLACED: &[&str] = &["Source", "Files", "Checksums-Sha1", "Checksums-Sha256", "Checksums-Sha512"];
+
+/// A file of a source package, with the digests that were computed from the pool.
+pub struct SourceFile {
+    pub name: String,
+    pub size: u64,
+    pub md5sum: String,
+    pub sha1: String,
+    pub sha256: String,
+    pub sha512: String,
+}
+
+pub struct SourceEntry {
+    pub control: Control,
+    pub directory: PathBuf,
+    /// The `.dsc` itself, followed by each of the files that it lists.
+    pub files: Vec<SourceFile>,
+}
+
+impl SourceEntry {
+    /// Reads the control paragraph of a `.dsc`, and the sizes and digests of its files, which
+    /// must be stored beside it.
+    pub fn from_dsc(dsc: &Path, cache: &DigestCache) -> io::Result<Self> {
+        let data = misc::read_to_string(dsc)?;
+        let control = parse_control(&strip_signature(&data));
+        if !control.contains_key("Source") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not have a Source field", dsc.display())
+            ));
+        }
+
+        let directory = dsc.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let mut files = vec![source_file(dsc, cache)?];
+
+        for line in control.get("Files").map_or("", |x| x.as_str()).lines() {
+            let mut fields = line.split_whitespace();
+            let (md5sum, size, name) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(md5sum), Some(size), Some(name)) => (md5sum, size, name),
+                _ => continue
+            };
+
+            let file = source_file(&directory.join(name), cache)?;
+            if file.md5sum != md5sum || file.size.to_string() != size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} does not match the checksum listed by {}", name, dsc.display())
+                ));
+            }
+
+            files.push(file);
+        }
+
+        Ok(SourceEntry { control, directory, files })
+    }
+
+    pub fn generate_entry(mut self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(2048);
+        let control = &mut self.control;
+
+        fn write_entry(output: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+            output.extend_from_slice(key);
+            output.push(b':');
+            if !value.is_empty() && value[0] != b'\n' {
+                output.push(b' ');
+            }
+            output.extend_from_slice(value);
+            output.push(b'\n');
+        }
+
+        fn write_files<F: Fn(&SourceFile) -> &str>(output: &mut Vec<u8>, key: &[u8], files: &[SourceFile], digest: F) {
+            // Digests which were not selected are not computed, and so are not listed.
+            if files.iter().any(|file| digest(file).is_empty()) {
+                return
+            }
+
+            output.extend_from_slice(key);
+            output.extend_from_slice(b":\n");
+            for file in files {
+                output.extend_from_slice(format!(" {} {} {}\n", digest(file), file.size, file.name).as_bytes());
+            }
+        }
+
+        if let Some(source) = control.get("Source") {
+            write_entry(&mut output, b"Package", source.as_bytes());
+        }
+
+        for key in ORDERED {
+            if let Some(value) = control.remove(*key) {
+                write_entry(&mut output, key.as_bytes(), value.as_bytes());
+            }
+        }
+
+        for (key, value) in control.iter().filter(|&(key, _)| !REPLACED.contains(&key.as_str())) {
+            write_entry(&mut output, key.as_bytes(), value.as_bytes());
+        }
+
+        write_entry(&mut output, b"Directory", self.directory.as_os_str().as_bytes());
+        write_files(&mut output, b"Files", &self.files, |file| file.md5sum.as_str());
+        write_files(&mut output, b"Checksums-Sha1", &self.files, |file| file.sha1.as_str());
+        write_files(&mut output, b"Checksums-Sha256", &self.files, |file| file.sha256.as_str());
+        write_files(&mut output, b"Checksums-Sha512", &self.files, |file| file.sha512.as_str());
+
+        output
+    }
+}
+
+fn source_file(path: &Path, cache: &DigestCache) -> io::Result<SourceFile> {
+    let name = path.file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} does not have a valid file name", path.display())
+        ))?
+        .to_owned();
+
+    let size = path.metadata().map_err(|why| io::Error::new(
+        why.kind(),
+        format!("failed to open {}: {}", path.display(), why)
+    ))?.len();
+
+    let digests = cache.digests(path)?;
+
+    Ok(SourceFile {
+        name,
+        size,
+        md5sum: digests.md5,
+        sha1: digests.sha1,
+        sha256: digests.sha256,
+        sha512: digests.sha512,
+    })
+}
+
+/// The message of a clearsigned `.dsc`, without its armor and signature.
+fn strip_signature(data: &str) -> String {
+    if !data.starts_with("-----BEGIN PGP SIGNED MESSAGE-----") {
+        return data.to_owned();
+    }
+
+    data.lines()
+        // The armor headers are separated from the message by an empty line.
+        .skip_while(|line| !line.trim().is_empty())
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----BEGIN PGP SIGNATURE-----"))
+        .map(|line| if line.starts_with("- ") { &line[2..] } else { line })
+        .fold(String::with_capacity(data.len()), |mut acc, line| {
+            acc.push_str(line);
+            acc.push('\n');
+            acc
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::ALL_DIGESTS;
+    use misc;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn sources_entry_from_dsc() {
+        let cache = || DigestCache::new(BTreeMap::new(), ALL_DIGESTS);
+        let dir = tempdir().unwrap();
+        let pool = dir.path().join("pool/bionic/main/source/f/foo");
+        fs::create_dir_all(&pool).unwrap();
+        misc::write(pool.join("foo_1.0.tar.xz"), b"foo").unwrap();
+        misc::write(pool.join("foo_1.0.dsc"), [
+            "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA256\n\n",
+            "Format: 3.0 (native)\nSource: foo\nBinary: foo\nArchitecture: any\nVersion: 1.0\n",
+            "Maintainer: System76 <info@system76.com>\nBuild-Depends: debhelper (>= 9),\n libc6-dev\n",
+            "Files:\n acbd18db4cc2f85cedef654fccc4a4d8 3 foo_1.0.tar.xz\n",
+            "-----BEGIN PGP SIGNATURE-----\n\nsignature\n-----END PGP SIGNATURE-----\n",
+        ].concat()).unwrap();
+
+        assert!(SourceEntry::from_dsc(&pool.join("foo_1.0.tar.xz"), &cache()).is_err());
+
+        let entry = SourceEntry::from_dsc(&pool.join("foo_1.0.dsc"), &cache()).unwrap();
+        assert_eq!(entry.files.len(), 2);
+        assert_eq!(entry.files[1].sha256, "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+
+        let dsc_size = entry.files[0].size;
+        let generated = String::from_utf8(entry.generate_entry()).unwrap();
+        let expected = format!(
+            "Package: foo\nFormat: 3.0 (native)\nBinary: foo\nArchitecture: any\nVersion: 1.0\n\
+             Maintainer: System76 <info@system76.com>\nBuild-Depends: debhelper (>= 9),\n libc6-dev\n\
+             Directory: {}\nFiles:\n",
+            pool.display()
+        );
+
+        assert!(generated.starts_with(&expected), "{}", generated);
+        assert!(generated.contains(&format!(" {} foo_1.0.dsc\n", dsc_size)));
+        assert!(generated.contains(" acbd18db4cc2f85cedef654fccc4a4d8 3 foo_1.0.tar.xz\n"));
+        assert!(!generated.contains("Source:"));
+        assert!(!generated.contains("PGP"));
+
+        // A file which does not match the listed checksum is rejected.
+        misc::write(pool.join("foo_1.0.tar.xz"), b"bar").unwrap();
+        assert!(SourceEntry::from_dsc(&pool.join("foo_1.0.dsc"), &cache()).is_err());
+    }
+}
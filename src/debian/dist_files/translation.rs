@@ -0,0 +1,104 @@
+use checksum::hasher;
+use compress::*;
+use debian::Control;
+use iter_reader::IteratorReader;
+use itertools::Itertools;
+use md5::Md5;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The full description of a package: the synopsis, followed by each line of the long description.
+pub fn full_description(control: &Control) -> Option<String> {
+    let description = control.get("Description")?;
+    let mut lines = description.lines();
+    let mut full = lines.next()?.trim().to_owned();
+
+    for line in lines {
+        full.push('\n');
+        // Continuation lines must be indented, though a parser may have already trimmed them.
+        if !line.starts_with(' ') && !line.starts_with('\t') {
+            full.push(' ');
+        }
+        full.push_str(line.trim_right());
+    }
+
+    Some(full)
+}
+
+/// The `Description-md5` key which links a `Packages` entry to its `Translation-en` entry.
+pub fn description_md5(description: &str) -> String {
+    hasher::<Md5, _>([description, "\n"].concat().as_bytes())
+        .expect("hashing an in-memory description cannot fail")
+}
+
+/// Collects the descriptions of a component's packages for its `Translation-en` index.
+#[derive(Default)]
+pub struct Translations {
+    /// Keyed by package and `Description-md5`, as descriptions are shared between architectures.
+    entries: BTreeMap<(String, String), String>,
+}
+
+impl Translations {
+    pub fn insert(&mut self, control: &Control) {
+        if let (Some(package), Some(description)) = (control.get("Package"), full_description(control)) {
+            let md5 = description_md5(&description);
+            self.entries.entry((package.clone(), md5)).or_insert(description);
+        }
+    }
+
+    /// Writes the `i18n/Translation-en` index of the component at the given path.
+    pub fn write(self, component: &Path) -> io::Result<()> {
+        let i18n = component.join("i18n");
+        fs::create_dir_all(&i18n)?;
+
+        let generated = self.entries.into_iter()
+            .map(|((package, md5), description)| {
+                format!("Package: {}\nDescription-md5: {}\nDescription-en: {}\n", package, md5, description)
+                    .into_bytes()
+            })
+            .intersperse(vec![b'\n']);
+
+        let reader = IteratorReader::new(generated, Vec::with_capacity(64 * 1024));
+        compress("Translation-en", &i18n, reader, GZ_COMPRESS | XZ_COMPRESS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libflate::gzip::Decoder;
+    use std::fs::File;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    #[test]
+    fn translation_index() {
+        let mut control = Control::new();
+        control.insert("Package".into(), "foo".into());
+        control.insert("Description".into(), "A utility for foo\nFoo does bar.\n .\n It is a baz.".into());
+
+        let description = full_description(&control).unwrap();
+        assert_eq!(description, "A utility for foo\n Foo does bar.\n .\n It is a baz.");
+        assert_eq!(description_md5(&description), "292155fe335732c2ad11560f529a02c0");
+
+        let mut translations = Translations::default();
+        translations.insert(&control);
+        // The same package of another architecture shares its entry.
+        translations.insert(&control);
+
+        let dir = tempdir().unwrap();
+        translations.write(dir.path()).unwrap();
+        assert!(dir.path().join("i18n/Translation-en.xz").exists());
+
+        let mut index = String::new();
+        File::open(dir.path().join("i18n/Translation-en.gz"))
+            .and_then(Decoder::new)
+            .and_then(|mut decoder| decoder.read_to_string(&mut index))
+            .unwrap();
+
+        assert_eq!(index, "Package: foo\nDescription-md5: 292155fe335732c2ad11560f529a02c0\n\
+Description-en: A utility for foo\n Foo does bar.\n .\n It is a baz.\n");
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::path::Path;
+use super::Control;
+
+/// Fields which apt requires of every binary package entry.
+pub const REQUIRED_FIELDS: &[&str] = &["Package", "Version", "Architecture", "Maintainer", "Description"];
+
+/// The values which dpkg accepts for the `Multi-Arch` field.
+pub const MULTI_ARCH_VALUES: &[&str] = &["same", "foreign", "allowed", "no"];
+
+/// An issue found in the control file of a package, which would break its index entry.
+#[derive(Debug, PartialEq)]
+pub enum PackageLint {
+    /// A required field is absent.
+    MissingField(&'static str),
+    /// The `Description` field has no synopsis.
+    EmptyDescription,
+    /// The `Version` field is not a valid Debian version.
+    InvalidVersion(String),
+    /// The `Multi-Arch` field is not one of the values which dpkg accepts.
+    InvalidMultiArch(String),
+    /// `Multi-Arch: same` is declared by an `Architecture: all` package, which dpkg refuses.
+    MultiArchSameForAll,
+    /// A package which ships a shared library of its architecture is not `Multi-Arch: same`,
+    /// so that its builds for other architectures cannot be installed beside it.
+    LibraryNotMultiArchSame(String),
+}
+
+impl fmt::Display for PackageLint {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PackageLint::MissingField(field) => write!(fmt, "missing required field: {}", field),
+            PackageLint::EmptyDescription => write!(fmt, "description has no synopsis"),
+            PackageLint::InvalidVersion(ref version) => write!(fmt, "invalid version: {}", version),
+            PackageLint::InvalidMultiArch(ref value) => write!(
+                fmt,
+                "invalid Multi-Arch: {}, which must be one of {}",
+                value,
+                MULTI_ARCH_VALUES.join(", ")
+            ),
+            PackageLint::MultiArchSameForAll => {
+                write!(fmt, "Multi-Arch: same is declared by an Architecture: all package")
+            }
+            PackageLint::LibraryNotMultiArchSame(ref library) => write!(
+                fmt,
+                "ships the shared library {}, but is not Multi-Arch: same, so it cannot be co-installed \
+                 with its other architectures (an override may set its multi_arch)",
+                library
+            ),
+        }
+    }
+}
+
+/// Checks the control file of a binary package for common issues.
+pub fn lint_package(control: &Control) -> Vec<PackageLint> {
+    let mut lints = REQUIRED_FIELDS.iter()
+        .filter(|field| !control.contains_key(**field))
+        .map(|field| PackageLint::MissingField(field))
+        .collect::<Vec<PackageLint>>();
+
+    if let Some(description) = control.get("Description") {
+        if description.lines().next().map_or(true, |synopsis| synopsis.trim().is_empty()) {
+            lints.push(PackageLint::EmptyDescription);
+        }
+    }
+
+    if let Some(version) = control.get("Version") {
+        if !is_valid_version(version.trim()) {
+            lints.push(PackageLint::InvalidVersion(version.clone()));
+        }
+    }
+
+    lints
+}
+
+/// Checks the `Multi-Arch` field of a binary package against its files, which are the paths of
+/// its data archive.
+pub fn lint_multi_arch<P: AsRef<Path>>(control: &Control, files: &[P]) -> Vec<PackageLint> {
+    let mut lints = Vec::new();
+    let multi_arch = control.get("Multi-Arch").map(|value| value.trim());
+    let arch = control.get("Architecture").map_or("", |arch| arch.trim());
+
+    match multi_arch {
+        Some(value) if !MULTI_ARCH_VALUES.contains(&value) => {
+            lints.push(PackageLint::InvalidMultiArch(value.to_owned()));
+        }
+        Some("same") if arch == "all" => lints.push(PackageLint::MultiArchSameForAll),
+        Some("same") | Some("foreign") | Some("allowed") => (),
+        _ if arch == "all" => (),
+        _ => {
+            let library = files.iter()
+                .map(|file| file.as_ref())
+                .find(|file| is_public_library(file));
+
+            if let Some(library) = library {
+                let library = library.strip_prefix(".").unwrap_or(library);
+                lints.push(PackageLint::LibraryNotMultiArchSame(library.display().to_string()));
+            }
+        }
+    }
+
+    lints
+}
+
+/// A versioned shared object within `lib` or `usr/lib`, or their multiarch directories, which
+/// is linked against by the packages of its architecture, unlike the plugins of a program.
+fn is_public_library(path: &Path) -> bool {
+    let path = path.strip_prefix(".").unwrap_or(path);
+    let directory = match path.parent().and_then(|parent| parent.to_str()) {
+        Some(directory) => directory.trim_left_matches('/'),
+        None => return false
+    };
+
+    // Only the multiarch directories of Linux, such as `x86_64-linux-gnu`, are recognized.
+    let mut parts = directory.trim_left_matches("usr/").splitn(2, '/');
+    let public = parts.next() == Some("lib") && parts.next().map_or(true, |triplet| {
+        !triplet.contains('/') && triplet.contains("-linux-")
+    });
+
+    public && path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| name.contains(".so."))
+}
+
+/// Validates a version against the `[epoch:]upstream_version[-debian_revision]` format.
+fn is_valid_version(version: &str) -> bool {
+    let (epoch, rest) = match version.find(':') {
+        Some(pos) => (&version[..pos], &version[pos + 1..]),
+        None => ("", version),
+    };
+
+    if !epoch.chars().all(|c| c.is_ascii_digit()) || (version.contains(':') && epoch.is_empty()) {
+        return false;
+    }
+
+    let (upstream, revision) = match rest.rfind('-') {
+        Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+        None => (rest, None),
+    };
+
+    let upstream_valid = upstream.chars().next().map_or(false, |c| c.is_ascii_digit())
+        && upstream.chars().all(|c| c.is_ascii_alphanumeric() || ".+~-:".contains(c));
+
+    let revision_valid = revision.map_or(true, |revision| {
+        !revision.is_empty() && revision.chars().all(|c| c.is_ascii_alphanumeric() || "+.~".contains(c))
+    });
+
+    upstream_valid && revision_valid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use debian::parse_control;
+
+    #[test]
+    fn missing_maintainer() {
+        let control = parse_control("Package: sample\nVersion: 1:2.0-1ubuntu1\nArchitecture: amd64\nDescription: sample\n");
+        assert_eq!(lint_package(&control), vec![PackageLint::MissingField("Maintainer")]);
+    }
+
+    #[test]
+    fn multi_arch_lints() {
+        let control = |fields: &str| parse_control(&["Package: libfoo1\nVersion: 1.0-1\n", fields].concat());
+        let library = ["./usr/lib/x86_64-linux-gnu/libfoo.so.1.0.0", "./usr/share/doc/libfoo1/copyright"];
+
+        assert_eq!(
+            lint_multi_arch(&control("Architecture: amd64\n"), &library),
+            vec![PackageLint::LibraryNotMultiArchSame("usr/lib/x86_64-linux-gnu/libfoo.so.1.0.0".into())]
+        );
+        assert!(lint_multi_arch(&control("Architecture: amd64\nMulti-Arch: same\n"), &library).is_empty());
+        assert!(lint_multi_arch(&control("Architecture: amd64\n"), &["./usr/lib/foo/plugins/libbar.so.1"]).is_empty());
+        assert_eq!(
+            lint_multi_arch(&control("Architecture: amd64\nMulti-Arch: Same\n"), &library),
+            vec![PackageLint::InvalidMultiArch("Same".into())]
+        );
+        assert_eq!(
+            lint_multi_arch(&control("Architecture: all\nMulti-Arch: same\n"), &[] as &[&str]),
+            vec![PackageLint::MultiArchSameForAll]
+        );
+    }
+
+    #[test]
+    fn version_validation() {
+        assert!(is_valid_version("1.0"));
+        assert!(is_valid_version("2:1.0~rc1-0ubuntu1"));
+        assert!(!is_valid_version("v1.0"));
+        assert!(!is_valid_version(":1.0"));
+        assert!(!is_valid_version("1.0-"));
+    }
+}
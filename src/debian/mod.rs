@@ -1,29 +1,47 @@
 pub mod archive;
+pub mod changes;
+pub mod control;
+pub mod deb;
 pub mod dist_files;
 pub mod missing;
 pub mod info;
+pub mod lint;
+pub mod version;
 
+pub use self::changes::*;
+pub use self::control::*;
+pub use self::deb::*;
 pub use self::dist_files::*;
 pub use self::missing::*;
 pub use self::info::*;
+pub use self::lint::*;
+pub use self::version::*;
 
 use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use compress::*;
 
-pub const DEB_SOURCE_EXTENSIONS: &[&str] = &[".tar.gz", ".tar.xz", ".dsc"];
+pub const DEB_SOURCE_EXTENSIONS: &[&str] = &[".tar.gz", ".tar.xz", ".tar.bz2", ".dsc"];
 pub const DEB_DEBUG_EXTENSION: &str = ".ddeb";
 pub const DEB_EXTENSION: &str = ".deb";
+pub const DEB_INSTALLER_EXTENSION: &str = ".udeb";
+/// The subcomponent of each component that debian-installer packages are indexed in.
+pub const DEBIAN_INSTALLER: &str = "debian-installer";
 
 pub type Arch = String;
 pub type Component = String;
 pub type Package = String;
 
 pub type Control = BTreeMap<String, String>;
-pub type Entries = HashMap<Arch, (HashMap<Component, Vec<PackageEntry>>, Vec<ContentsEntry>)>;
+pub type Entries = HashMap<Arch, HashMap<Component, (Vec<PackageEntry>, Vec<ContentsEntry>)>>;
 
 pub type ContentList = Vec<(PathBuf, String)>;
 
+/// Whether the component is the `debian-installer` subcomponent of a component.
+pub fn is_installer_component(component: &str) -> bool {
+    component.ends_with(&["/", DEBIAN_INSTALLER].concat())
+}
+
 pub fn gen_filename(name: &str, version: &str, arch: &str, ext: &str) -> String {
     let (name, dbg_mon, ext) = if name.ends_with("-dbg") {
         (&name[..name.len()-4], "-dbgsym", "ddeb")
@@ -0,0 +1,187 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum VersionError {
+    #[fail(display = "version is empty")]
+    Empty,
+    #[fail(display = "version has an invalid epoch: {}", version)]
+    Epoch { version: String },
+}
+
+/// A `[epoch:]upstream_version[-debian_revision]` version, which is ordered as dpkg orders them.
+///
+/// Versions which only differ in how they are written, such as `1.0` and `1.0-0`, are equal.
+#[derive(Clone, Debug, Eq)]
+pub struct Version {
+    pub epoch: u32,
+    pub upstream: String,
+    pub revision: String,
+}
+
+impl FromStr for Version {
+    type Err = VersionError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let version = version.trim();
+        if version.is_empty() {
+            return Err(VersionError::Empty);
+        }
+
+        let (epoch, rest) = match version.find(':') {
+            Some(pos) => {
+                let epoch = version[..pos].parse::<u32>()
+                    .map_err(|_| VersionError::Epoch { version: version.to_owned() })?;
+                (epoch, &version[pos + 1..])
+            }
+            None => (0, version)
+        };
+
+        let (upstream, revision) = match rest.rfind('-') {
+            Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+            None => (rest, "")
+        };
+
+        Ok(Version { epoch, upstream: upstream.to_owned(), revision: revision.to_owned() })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.epoch != 0 {
+            write!(fmt, "{}:", self.epoch)?;
+        }
+
+        fmt.write_str(&self.upstream)?;
+        if !self.revision.is_empty() {
+            write!(fmt, "-{}", self.revision)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.epoch.cmp(&other.epoch)
+            .then_with(|| compare_part(self.upstream.as_bytes(), other.upstream.as_bytes()))
+            .then_with(|| compare_part(self.revision.as_bytes(), other.revision.as_bytes()))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+/// Compares two version strings as dpkg does.
+///
+/// A version with an invalid epoch is compared as if the whole version were its upstream version.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |version: &str| version.parse::<Version>().unwrap_or_else(|_| Version {
+        epoch: 0,
+        upstream: version.to_owned(),
+        revision: String::new(),
+    });
+
+    parse(a).cmp(&parse(b))
+}
+
+/// The weight of a character within the non-digit segment of a version.
+///
+/// A `~` sorts before anything, even the end of the segment, and letters sort before symbols.
+fn order(c: u8) -> i32 {
+    match c {
+        b'~' => -1,
+        b'0'...b'9' => 0,
+        b'a'...b'z' | b'A'...b'Z' => i32::from(c),
+        _ => i32::from(c) + 256,
+    }
+}
+
+/// Compares the upstream versions, or revisions, of two versions by alternating between their
+/// non-digit segments, which are compared lexically, and their digit segments, which are compared
+/// numerically.
+fn compare_part(a: &[u8], b: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !a[i].is_ascii_digit()) || (j < b.len() && !b[j].is_ascii_digit()) {
+            let ac = a.get(i).map_or(0, |&c| order(c));
+            let bc = b.get(j).map_or(0, |&c| order(c));
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+
+            i += 1;
+            j += 1;
+        }
+
+        while i < a.len() && a[i] == b'0' { i += 1; }
+        while j < b.len() && b[j] == b'0' { j += 1; }
+
+        let mut first_diff = Ordering::Equal;
+        while i < a.len() && a[i].is_ascii_digit() && j < b.len() && b[j].is_ascii_digit() {
+            if first_diff == Ordering::Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+
+            i += 1;
+            j += 1;
+        }
+
+        // The longer number is the greater, regardless of its digits.
+        if i < a.len() && a[i].is_ascii_digit() {
+            return Ordering::Greater;
+        } else if j < b.len() && b[j].is_ascii_digit() {
+            return Ordering::Less;
+        } else if first_diff != Ordering::Equal {
+            return first_diff;
+        }
+    }
+
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_ordering() {
+        let ordered = [
+            "1.0~rc1",
+            "1.0",
+            "1.0-1",
+            "1.0-1ubuntu1",
+            "1.0a",
+            "1.0+b1",
+            "1.9",
+            "1.10",
+            "1.10.0",
+            "1:0.1",
+        ];
+
+        for pair in ordered.windows(2) {
+            assert_eq!(compare_versions(pair[0], pair[1]), Ordering::Less, "{} < {}", pair[0], pair[1]);
+            assert_eq!(compare_versions(pair[1], pair[0]), Ordering::Greater, "{} > {}", pair[1], pair[0]);
+        }
+
+        assert_eq!(compare_versions("1.0", "1.0-0"), Ordering::Equal);
+        assert_eq!(compare_versions("0:1.01", "1.1"), Ordering::Equal);
+
+        let version = "2:1.2.3-0ubuntu1-2".parse::<Version>().unwrap();
+        assert_eq!(version.epoch, 2);
+        assert_eq!(version.upstream, "1.2.3-0ubuntu1");
+        assert_eq!(version.revision, "2");
+        assert_eq!(version.to_string(), "2:1.2.3-0ubuntu1-2");
+        assert_eq!("x:1.0".parse::<Version>(), Err(VersionError::Epoch { version: "x:1.0".into() }));
+    }
+}
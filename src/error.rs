@@ -0,0 +1,70 @@
+use failure::Fail;
+use std::io;
+use std::path::PathBuf;
+
+/// The errors of each stage of a run, which name the file, or the command, that failed.
+///
+/// Each is converted into an `io::Error` of the same kind as its cause, so that the stages which
+/// return an `io::Result` may give their errors the context that they have.
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "failed to run {}: {}", command, why)]
+    Command { command: String, why: io::Error },
+    #[fail(display = "failed to download {} to {:?}: {}", url, file, why)]
+    Download { url: String, file: PathBuf, why: io::Error },
+    #[fail(display = "failed to extract {:?}: {}", member, why)]
+    Extract { member: PathBuf, why: io::Error },
+    #[fail(display = "failed to index {:?}: {}", file, why)]
+    Index { file: PathBuf, why: io::Error },
+    #[fail(display = "failed to move {:?} into the pool at {:?}: {}", file, destination, why)]
+    Pool { file: PathBuf, destination: PathBuf, why: io::Error },
+    #[fail(display = "`{}` failed to sign {:?}: {}", command, file, why)]
+    Sign { command: &'static str, file: PathBuf, why: io::Error },
+    #[fail(display = "failed to walk {:?}: {}", directory, why)]
+    Walk { directory: PathBuf, why: io::Error },
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        let kind = match error {
+            Error::Command { ref why, .. }
+            | Error::Download { ref why, .. }
+            | Error::Extract { ref why, .. }
+            | Error::Index { ref why, .. }
+            | Error::Pool { ref why, .. }
+            | Error::Sign { ref why, .. }
+            | Error::Walk { ref why, .. } => why.kind(),
+        };
+
+        io::Error::new(kind, error.compat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contextual_errors() {
+        let error = io::Error::from(Error::Pool {
+            file: PathBuf::from("build/bionic/foo_1.0_amd64.deb"),
+            destination: PathBuf::from("repo/pool/bionic/main/binary-amd64/f/foo"),
+            why: io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"),
+        });
+
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            error.to_string(),
+            "failed to move \"build/bionic/foo_1.0_amd64.deb\" into the pool at \
+             \"repo/pool/bionic/main/binary-amd64/f/foo\": permission denied"
+        );
+
+        let error = io::Error::from(Error::Command {
+            command: "\"dpkg-deb\" \"--field\"".into(),
+            why: io::Error::new(io::ErrorKind::NotFound, "No such file or directory"),
+        });
+
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(error.to_string(), "failed to run \"dpkg-deb\" \"--field\": No such file or directory");
+    }
+}
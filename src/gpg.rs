@@ -0,0 +1,444 @@
+use command::Command;
+use debian::parse_control;
+use misc;
+use reqwest;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::{self, NamedTempFile};
+
+/// Fetches a key from a keyserver, and exports it to a standalone keyring at `dst`.
+///
+/// The key is received into a throwaway GPG home, so the user's keyring is never modified. The
+/// fingerprint of the received key is validated against the requested fingerprint, to prevent a
+/// malicious keyserver from substituting a key of its own.
+pub fn fetch_key(fingerprint: &str, keyserver: &str, dst: &Path) -> io::Result<()> {
+    let fingerprint = normalize_fingerprint(fingerprint)?;
+    let home = tempfile::tempdir()?;
+    let home = home.path().to_str().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "temporary GPG home is not valid UTF-8"
+    ))?;
+
+    info!("fetching key {} from {}", fingerprint, keyserver);
+    gpg(home)
+        .args(&["--keyserver", keyserver, "--recv-keys", fingerprint.as_str()])
+        .run()?;
+
+    let listing = gpg(home)
+        .args(&["--with-colons", "--fingerprint", "--list-keys"])
+        .run_with_stdout()?;
+
+    validate_fingerprints(&listing, &fingerprint)?;
+
+    gpg(home)
+        .args(&["--yes", "--output"])
+        .arg(dst)
+        .args(&["--export", fingerprint.as_str()])
+        .run()
+}
+
+fn gpg(home: &str) -> Command {
+    let mut command = Command::new("gpg");
+    command.env("GNUPGHOME", home);
+    command.arg("--batch");
+    command
+}
+
+/// Strips whitespace from a fingerprint, and ensures that it is a full hexadecimal fingerprint.
+fn normalize_fingerprint(fingerprint: &str) -> io::Result<String> {
+    let fingerprint = fingerprint.split_whitespace().collect::<String>().to_uppercase();
+    let fingerprint = fingerprint.trim_left_matches("0X");
+
+    if fingerprint.len() < 40 || !fingerprint.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a full key fingerprint", fingerprint)
+        ));
+    }
+
+    Ok(fingerprint.to_owned())
+}
+
+/// Collects the fingerprints of each primary key in a `gpg --with-colons` listing.
+fn primary_fingerprints(listing: &str) -> Vec<&str> {
+    let mut fingerprints = Vec::new();
+    let mut primary = false;
+
+    for line in listing.lines() {
+        let mut fields = line.split(':');
+        match fields.next() {
+            Some("pub") => primary = true,
+            Some("fpr") if primary => {
+                primary = false;
+                if let Some(fingerprint) = fields.nth(8) {
+                    fingerprints.push(fingerprint);
+                }
+            }
+            Some("sub") | Some("ssb") => primary = false,
+            _ => ()
+        }
+    }
+
+    fingerprints
+}
+
+fn validate_fingerprints(listing: &str, expected: &str) -> io::Result<()> {
+    let received = primary_fingerprints(listing);
+    if received.len() == 1 && received[0].eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("keyserver returned {:?} when {} was requested", received, expected)
+        ))
+    }
+}
+
+/// The outcome of evaluating whether an APT client would trust a repository.
+#[derive(Debug, Default, PartialEq)]
+pub struct TrustReport {
+    /// The `InRelease` file which was fetched and verified.
+    pub inrelease_url: String,
+    /// Fingerprint of the key which signed the `InRelease` file, if the signature was valid.
+    pub signer: Option<String>,
+    /// The trusted keyring which contains the signing key.
+    pub keyring: Option<PathBuf>,
+    /// The `Signed-By` option of the matching sources entry, if it has one.
+    pub signed_by: Option<String>,
+    /// Whether the `Signed-By` option references the signing key, or the keyring containing it.
+    pub signed_by_matches: bool,
+}
+
+impl TrustReport {
+    /// Whether the `InRelease` file was signed by a key within the trusted keyrings.
+    pub fn trusted(&self) -> bool { self.keyring.is_some() }
+}
+
+/// A `deb` entry from a sources list, in either the one-line or deb822 format.
+#[derive(Debug, PartialEq)]
+struct SourceEntry {
+    uri:       String,
+    suite:     String,
+    signed_by: Option<String>,
+}
+
+/// Reproduces APT's trust evaluation of a repository for the given sources list and keyrings.
+///
+/// The `InRelease` file of the suite that the sources list configures for `repo_url` is fetched,
+/// and verified against each keyring within `trusted_dir`. The report states which key signed
+/// it, and whether that key is referenced by the entry's `Signed-By` option.
+pub fn validate_trust(sources_list: &Path, trusted_dir: &Path, repo_url: &str) -> io::Result<TrustReport> {
+    let sources = misc::read_to_string(sources_list)?;
+    let entry = source_entries(&sources)
+        .into_iter()
+        .find(|entry| entry.uri.trim_right_matches('/') == repo_url.trim_right_matches('/'))
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not contain an entry for {}", sources_list.display(), repo_url)
+        ))?;
+
+    let inrelease_url = [repo_url.trim_right_matches('/'), "/dists/", &entry.suite, "/InRelease"].concat();
+    info!("fetching {}", inrelease_url);
+    let inrelease = reqwest::get(inrelease_url.as_str())
+        .and_then(|response| response.error_for_status())
+        .and_then(|mut response| response.text())
+        .map_err(|why| io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to fetch {}: {}", inrelease_url, why)
+        ))?;
+
+    let mut inrelease_file = NamedTempFile::new()?;
+    inrelease_file.write_all(inrelease.as_bytes())?;
+
+    let mut report = TrustReport {
+        inrelease_url,
+        signed_by: entry.signed_by.clone(),
+        .. TrustReport::default()
+    };
+
+    let mut keyrings = trusted_dir.read_dir()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |e| e == "gpg" || e == "asc"))
+        .collect::<Vec<PathBuf>>();
+    keyrings.sort();
+
+    for keyring in keyrings {
+        if let Some(signer) = verify_with_keyring(inrelease_file.path(), &keyring)? {
+            report.signer = Some(signer);
+            report.keyring = Some(keyring);
+            break
+        }
+    }
+
+    report.signed_by_matches = match (&entry.signed_by, &report.signer, &report.keyring) {
+        (&Some(ref signed_by), &Some(ref signer), &Some(ref keyring)) => {
+            signed_by_references(signed_by, signer, keyring)
+        }
+        _ => false
+    };
+
+    Ok(report)
+}
+
+/// Verifies a signed file, such as the `.changes` of an upload, against a keyring, returning the
+/// fingerprint of its signer and the contents which were signed, without the signature.
+///
+/// Only the signed contents should be trusted, as a cleartext signature may be surrounded by
+/// text which it does not cover.
+pub fn verify_signed(signed: &Path, keyring: &Path) -> io::Result<Option<(String, String)>> {
+    // gpgv refuses to overwrite an existing file, so the output is written within a directory.
+    let directory = tempfile::tempdir()?;
+    let output = directory.path().join("signed");
+    match verify_with_keyring_to(signed, keyring, Some(&output))? {
+        Some(signer) => Ok(Some((signer, misc::read_to_string(&output)?))),
+        None => Ok(None)
+    }
+}
+
+/// Verifies the signed file against a single keyring, returning the signer's fingerprint.
+fn verify_with_keyring(signed: &Path, keyring: &Path) -> io::Result<Option<String>> {
+    verify_with_keyring_to(signed, keyring, None)
+}
+
+fn verify_with_keyring_to(
+    signed: &Path,
+    keyring: &Path,
+    output: Option<&Path>
+) -> io::Result<Option<String>> {
+    let status = gpgv(keyring, output, &[signed])?;
+
+    Ok(valid_signer(&status))
+}
+
+/// Verifies the detached signature of a file, such as the `Release.gpg` of an upstream apt
+/// repository, against a keyring, returning the fingerprint of its signer.
+pub fn verify_detached_by(signature: &Path, file: &Path, keyring: &Path) -> io::Result<Option<String>> {
+    gpgv(keyring, None, &[signature, file]).map(|status| valid_signer(&status))
+}
+
+/// Verifies the detached signature of a file, such as an upstream tarball, against a keyring,
+/// and ensures that it was made by the key of the fingerprint, or by one of its subkeys.
+pub fn verify_detached(signature: &Path, file: &Path, keyring: &Path, fingerprint: &str) -> io::Result<()> {
+    let fingerprint = normalize_fingerprint(fingerprint)?;
+    let status = gpgv(keyring, None, &[signature, file])?;
+
+    if signed_by_key(&status, &fingerprint) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not have a valid signature by {}", file.display(), fingerprint)
+        ))
+    }
+}
+
+/// Runs `gpgv` on the files with a single keyring, returning its status output.
+fn gpgv(keyring: &Path, output: Option<&Path>, files: &[&Path]) -> io::Result<String> {
+    // Armored keyrings must be dearmored before gpgv is able to read them.
+    let dearmored;
+    let keyring = if keyring.extension().map_or(false, |e| e == "asc") {
+        dearmored = NamedTempFile::new()?;
+        Command::new("gpg")
+            .args(&["--batch", "--yes", "--dearmor", "-o"])
+            .arg(dearmored.path())
+            .arg(keyring)
+            .run()?;
+        dearmored.path()
+    } else {
+        keyring
+    };
+
+    let mut command = Command::new("gpgv");
+    command.args(&["--status-fd", "1", "--keyring"]).arg(keyring);
+    if let Some(output) = output {
+        command.arg("--output").arg(output);
+    }
+
+    command.args(files).run_with_stdout()
+}
+
+/// Parses the status output of `gpgv` for the fingerprint of a valid signature.
+fn valid_signer(status: &str) -> Option<String> {
+    status.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("[GNUPG:]"), Some("VALIDSIG"), Some(fingerprint)) => Some(fingerprint.to_owned()),
+                _ => None
+            }
+        })
+        .next()
+}
+
+/// Whether the status output of `gpgv` has a valid signature by the key, or by one of its subkeys,
+/// whose primary key is given after the signature's class.
+fn signed_by_key(status: &str, fingerprint: &str) -> bool {
+    status.lines().any(|line| {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        match (fields.get(0), fields.get(1)) {
+            (Some(&"[GNUPG:]"), Some(&"VALIDSIG")) => [2, 11].iter().any(|&field| {
+                fields.get(field).map_or(false, |key| key.eq_ignore_ascii_case(fingerprint))
+            }),
+            _ => false
+        }
+    })
+}
+
+/// Whether a `Signed-By` value references the signing key, by fingerprint or by keyring path.
+fn signed_by_references(signed_by: &str, signer: &str, keyring: &Path) -> bool {
+    let keyring = keyring.canonicalize().unwrap_or_else(|_| keyring.to_path_buf());
+    signed_by.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .any(|value| {
+            let value = value.trim_right_matches('!');
+            value.eq_ignore_ascii_case(signer)
+                || Path::new(value).canonicalize().map_or(false, |path| path == keyring)
+        })
+}
+
+/// Collects the `deb` entries of a sources list, supporting both the one-line and deb822 formats.
+fn source_entries(sources: &str) -> Vec<SourceEntry> {
+    let mut entries = Vec::new();
+
+    if sources.lines().any(|line| line.starts_with("Types:")) {
+        for paragraph in sources.split("\n\n") {
+            let control = parse_control(paragraph);
+            let is_deb = control.get("Types")
+                .map_or(false, |types| types.split_whitespace().any(|t| t == "deb"));
+
+            if !is_deb {
+                continue
+            }
+
+            if let (Some(uris), Some(suites)) = (control.get("URIs"), control.get("Suites")) {
+                for uri in uris.split_whitespace() {
+                    for suite in suites.split_whitespace() {
+                        entries.push(SourceEntry {
+                            uri: uri.to_owned(),
+                            suite: suite.to_owned(),
+                            signed_by: control.get("Signed-By").cloned(),
+                        });
+                    }
+                }
+            }
+        }
+
+        return entries;
+    }
+
+    for line in sources.lines() {
+        let line = line.find('#').map_or(line, |pos| &line[..pos]).trim();
+        if !line.starts_with("deb ") {
+            continue
+        }
+
+        let mut line = line[4..].trim_left();
+        let mut signed_by = None;
+        if line.starts_with('[') {
+            if let Some(end) = line.find(']') {
+                for option in line[1..end].split_whitespace() {
+                    if option.starts_with("signed-by=") {
+                        signed_by = Some(option[10..].to_owned());
+                    }
+                }
+                line = line[end + 1..].trim_left();
+            }
+        }
+
+        let mut fields = line.split_whitespace();
+        if let (Some(uri), Some(suite)) = (fields.next(), fields.next()) {
+            entries.push(SourceEntry { uri: uri.to_owned(), suite: suite.to_owned(), signed_by });
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const LISTING: &str = "tru::1:1539000000:0:3:1:5
+pub:-:4096:1:204DD8AEC33A7AFF:1506700000:::-:::scESC::::::23::0:
+fpr:::::::::63C46DF0140D738961429F4E204DD8AEC33A7AFF:
+uid:-::::1506700000::::Pop OS (ISO Signing Key) <info@system76.com>::::::::::0:
+sub:-:4096:1:1C2A5B3AE97DEE04:1506700000::::::e::::::23:
+fpr:::::::::2A0C2D4E0C1F2B1DA6B1C05E1C2A5B3AE97DEE04:
+";
+
+    #[test]
+    fn fingerprint_validation() {
+        let expected = normalize_fingerprint("63C4 6DF0 140D 7389 6142  9F4E 204D D8AE C33A 7AFF")
+            .unwrap();
+
+        assert_eq!(primary_fingerprints(LISTING), vec![expected.as_str()]);
+        assert!(validate_fingerprints(LISTING, &expected).is_ok());
+
+        let substituted = "2A0C2D4E0C1F2B1DA6B1C05E1C2A5B3AE97DEE04";
+        assert!(validate_fingerprints(LISTING, substituted).is_err());
+        assert!(normalize_fingerprint("C33A7AFF").is_err());
+    }
+
+    #[test]
+    fn short_fingerprint_not_fetched() {
+        let dir = tempdir().unwrap();
+        let dst = dir.path().join("key.gpg");
+
+        let why = fetch_key("C33A7AFF", "hkp://127.0.0.1:1", &dst).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidInput);
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn sources_list_parsing() {
+        let sources = "# comment\ndeb-src http://apt.pop-os.org/proprietary bionic main\n\
+            deb [arch=amd64 signed-by=/usr/share/keyrings/pop.gpg] http://apt.pop-os.org/proprietary bionic main\n";
+
+        assert_eq!(source_entries(sources), vec![SourceEntry {
+            uri: "http://apt.pop-os.org/proprietary".into(),
+            suite: "bionic".into(),
+            signed_by: Some("/usr/share/keyrings/pop.gpg".into()),
+        }]);
+
+        let deb822 = "Types: deb deb-src\nURIs: http://apt.pop-os.org/proprietary\nSuites: bionic\n\
+            Components: main\nSigned-By: 63C46DF0140D738961429F4E204DD8AEC33A7AFF\n";
+
+        assert_eq!(source_entries(deb822)[0].signed_by.as_ref().map(|x| x.as_str()),
+            Some("63C46DF0140D738961429F4E204DD8AEC33A7AFF"));
+    }
+
+    #[test]
+    fn trust_evaluation() {
+        let trusted = "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 204DD8AEC33A7AFF Pop OS\n\
+            [GNUPG:] VALIDSIG 63C46DF0140D738961429F4E204DD8AEC33A7AFF 2018-10-14 1539500000\n";
+        let untrusted = "[GNUPG:] NEWSIG\n[GNUPG:] ERRSIG 204DD8AEC33A7AFF 1 10 01 1539500000 9\n\
+            [GNUPG:] NO_PUBKEY 204DD8AEC33A7AFF\n";
+
+        let signer = valid_signer(trusted).unwrap();
+        assert_eq!(signer, "63C46DF0140D738961429F4E204DD8AEC33A7AFF");
+        assert_eq!(valid_signer(untrusted), None);
+
+        let dir = tempdir().unwrap();
+        let keyring = dir.path().join("pop.gpg");
+        misc::write(&keyring, b"").unwrap();
+        let other = dir.path().join("other.gpg");
+        misc::write(&other, b"").unwrap();
+
+        assert!(signed_by_references(keyring.to_str().unwrap(), &signer, &keyring));
+        assert!(signed_by_references(&signer.to_lowercase(), &signer, &keyring));
+        assert!(!signed_by_references(other.to_str().unwrap(), &signer, &keyring));
+    }
+
+    #[test]
+    fn detached_signer() {
+        let primary = "63C46DF0140D738961429F4E204DD8AEC33A7AFF";
+        let subkey = "[GNUPG:] VALIDSIG 2A0C2D4E0C1F2B1DA6B1C05E1C2A5B3AE97DEE04 2018-10-14 1539500000 \
+            0 4 0 1 10 00 63C46DF0140D738961429F4E204DD8AEC33A7AFF\n";
+
+        assert!(signed_by_key(subkey, primary));
+        assert!(signed_by_key(subkey, "2A0C2D4E0C1F2B1DA6B1C05E1C2A5B3AE97DEE04"));
+        assert!(!signed_by_key(subkey, "0000000000000000000000000000000000000000"));
+        assert!(!signed_by_key("[GNUPG:] BADSIG 204DD8AEC33A7AFF Pop OS\n", primary));
+    }
+}
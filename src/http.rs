@@ -0,0 +1,127 @@
+use config::HttpSettings;
+use reqwest::{Certificate, Client, Proxy, Url};
+use std::env;
+use std::fs;
+use std::io;
+
+/// The proxies of each scheme, and the hosts which are fetched without one.
+#[derive(Debug, PartialEq)]
+struct Proxies {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<String>,
+}
+
+impl Proxies {
+    /// The proxies of the settings, or else of the environment, whose variables are read by `var`.
+    fn new<F: Fn(&str) -> Option<String>>(settings: &HttpSettings, var: F) -> io::Result<Proxies> {
+        let variable = |name: &str| var(name)
+            .or_else(|| var(&name.to_uppercase()))
+            .filter(|value| !value.trim().is_empty());
+
+        let parse = |proxy: Option<String>| -> io::Result<Option<Url>> {
+            match proxy {
+                Some(proxy) => Url::parse(proxy.trim()).map(Some).map_err(|why| io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid proxy {}: {}", proxy, why)
+                )),
+                None => Ok(None)
+            }
+        };
+
+        let mut no_proxy = settings.no_proxy.clone();
+        no_proxy.extend(variable("no_proxy").iter()
+            .flat_map(|hosts| hosts.split(','))
+            .map(|host| host.trim().to_owned())
+            .filter(|host| !host.is_empty()));
+
+        Ok(Proxies {
+            http: parse(settings.http_proxy.clone().or_else(|| variable("http_proxy")))?,
+            https: parse(settings.https_proxy.clone().or_else(|| variable("https_proxy")))?,
+            no_proxy,
+        })
+    }
+
+    /// The proxy that the URL is fetched through, if any.
+    fn proxy(&self, url: &Url) -> Option<Url> {
+        let host = url.host_str().unwrap_or("");
+        let bypassed = self.no_proxy.iter().any(|pattern| match pattern.as_str() {
+            "*" => true,
+            pattern if pattern.starts_with('.') => host.ends_with(pattern) || host == &pattern[1..],
+            pattern => host == pattern || host.ends_with(&[".", pattern].concat())
+        });
+
+        if bypassed {
+            return None;
+        }
+
+        match url.scheme() {
+            "http" => self.http.clone(),
+            "https" => self.https.clone(),
+            _ => None
+        }
+    }
+}
+
+/// A client which fetches through the configured proxies, and trusts the configured CA
+/// certificates as roots in addition to those of the system.
+pub fn client(settings: &HttpSettings) -> io::Result<Client> {
+    let mut builder = Client::builder();
+
+    let proxies = Proxies::new(settings, |name| env::var(name).ok())?;
+    if proxies.http.is_some() || proxies.https.is_some() {
+        builder = builder.proxy(Proxy::custom(move |url| proxies.proxy(url)));
+    }
+
+    for path in &settings.certificates {
+        let data = fs::read(path)?;
+        let certificate = if data.starts_with(b"-----BEGIN") {
+            Certificate::from_pem(&data)
+        } else {
+            Certificate::from_der(&data)
+        };
+
+        let certificate = certificate.map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid certificate at {}: {}", path.display(), why)
+        ))?;
+
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    builder.build().map_err(|why| io::Error::new(io::ErrorKind::Other, why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_selection() {
+        let settings = HttpSettings {
+            https_proxy: Some("http://proxy.corp:3128".into()),
+            no_proxy: vec![".internal".into()],
+            .. HttpSettings::default()
+        };
+
+        let environment = |name: &str| match name {
+            "HTTP_PROXY" => Some("http://env.corp:8080".to_owned()),
+            "https_proxy" => Some("http://ignored.corp:8080".to_owned()),
+            "no_proxy" => Some("localhost, apt.example.com".to_owned()),
+            _ => None
+        };
+
+        let proxies = Proxies::new(&settings, environment).unwrap();
+        let proxy = |url: &str| proxies.proxy(&Url::parse(url).unwrap()).map(|url| url.to_string());
+
+        assert_eq!(proxy("https://github.com/foo.tar.gz").as_ref().map(|x| x.as_str()), Some("http://proxy.corp:3128/"));
+        assert_eq!(proxy("http://deb.debian.org/debian").as_ref().map(|x| x.as_str()), Some("http://env.corp:8080/"));
+        assert_eq!(proxy("https://apt.example.com/dists"), None);
+        assert_eq!(proxy("https://mirror.apt.example.com/dists"), None);
+        assert_eq!(proxy("https://git.internal/foo.tar.gz"), None);
+        assert_eq!(proxy("http://localhost:8000/foo.deb"), None);
+
+        let invalid = HttpSettings { http_proxy: Some("not a url".into()), .. HttpSettings::default() };
+        assert!(Proxies::new(&invalid, |_| None).is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use libc;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static SIGNAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Handles SIGINT and SIGTERM by interrupting the run, rather than exiting at once, so that no
+/// further builds are started, the running builders are terminated, and the pool is not left
+/// half-updated. A second signal exits at once, leaving the workspace of the run to
+/// `debrep clean --stale`.
+pub fn handle_signals() {
+    unsafe {
+        libc::signal(libc::SIGINT, on_signal as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_signal as libc::sighandler_t);
+    }
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(128 + signal); }
+    }
+
+    SIGNAL.store(signal as usize, Ordering::SeqCst);
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The exit status of a run which was interrupted, by the convention of the shell.
+pub fn exit_status() -> i32 {
+    128 + SIGNAL.load(Ordering::SeqCst) as i32
+}
+
+/// Fails once the run has been interrupted, so that work which has not been started yet is not.
+pub fn check() -> io::Result<()> {
+    if !is_interrupted() {
+        return Ok(());
+    }
+
+    let signal = if SIGNAL.load(Ordering::SeqCst) as libc::c_int == libc::SIGTERM { "SIGTERM" } else { "SIGINT" };
+    Err(io::Error::new(io::ErrorKind::Interrupted, format!("the run was interrupted by {}", signal)))
+}
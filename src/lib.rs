@@ -0,0 +1,92 @@
+//! Builds and maintains Debian repositories from a TOML config.
+//!
+//! The `debrep` binary is a command line interface to this library, which other services may
+//! use to drive the generation of their repos directly. A config is parsed into the `Config` of
+//! each of its suites by `config::parse_suites`, and the repo of a suite is then updated by the
+//! steps of a `repo::Repo`, which fetch and build its packages, move them into its pool, and
+//! generate its signed dist files. The other operations of the binary, such as migrations,
+//! snapshots, and publishing, are the functions of the `repo` module.
+//!
+//! The steps of a run, and the functions of `repo`, operate on the current directory, which must
+//! be the directory of the config, and return their errors rather than exiting. As the current
+//! directory belongs to the whole process, and some of them change it while they run, they are
+//! not safe to run concurrently from several threads: each of them holds the lock of `cwd::lock`
+//! while it runs, so concurrent calls are serialized, and a caller which changes the directory
+//! itself should hold it as well. A caller which runs them from several processes should hold the
+//! lock of `lock::lock` for each project, as the binary does, and may make them only report their
+//! actions with `action::set_dry_run`.
+
+extern crate apt_repo_crawler;
+extern crate bus_writer;
+extern crate bzip2;
+extern crate chrono;
+#[macro_use]
+extern crate cascade;
+extern crate crossbeam_channel;
+extern crate debarchive;
+extern crate deflate;
+extern crate digest;
+extern crate failure;
+extern crate fern;
+extern crate glob;
+extern crate hex_view;
+extern crate itertools;
+extern crate libc;
+extern crate libflate;
+extern crate md5;
+extern crate parallel_getter;
+extern crate rayon;
+extern crate regex;
+extern crate reqwest;
+extern crate select;
+#[cfg(feature = "native-signing")]
+extern crate sequoia_openpgp;
+extern crate serde;
+extern crate sha1;
+extern crate sha2;
+extern crate subprocess;
+extern crate tar;
+extern crate tempfile;
+extern crate toml;
+extern crate utime;
+extern crate walkdir;
+extern crate xz2;
+extern crate zip;
+extern crate zstd;
+
+#[macro_use]
+extern crate failure_derive;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+
+pub mod action;
+pub mod checksum;
+pub mod command;
+pub mod compress;
+pub mod config;
+pub mod cwd;
+pub mod debian;
+pub mod error;
+pub mod gpg;
+pub mod http;
+pub mod interrupt;
+pub mod iter_reader;
+pub mod lock;
+pub mod logging;
+pub mod misc;
+pub mod notify;
+pub mod progress;
+pub mod repo;
+pub mod retry;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod throttle;
+pub mod url;
+pub mod workspace;
+
+pub const SHARED_ASSETS: &str = "assets/share/";
+pub const PACKAGE_ASSETS: &str = "assets/packages/";
@@ -0,0 +1,102 @@
+use libc;
+use misc;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+
+/// The lock file of a project, which is kept in the directory that debrep is run from.
+pub const LOCK_FILE: &str = ".debrep.lock";
+
+/// An advisory lock on a project, which is held until it is dropped, or the process exits.
+///
+/// As the lock is taken with `flock`, it is released by the kernel even if the process which
+/// held it was killed, so a stale lock file never needs to be removed by hand.
+pub struct Lock {
+    _file: File,
+}
+
+/// Takes the lock of the project, which fails if another process holds it, unless `wait` is
+/// given, in which case this blocks until the other process releases it.
+///
+/// The lock is kept within the canonical root of the project, so that every path which leads to
+/// the same project takes the same lock.
+pub fn lock(project: &Path, wait: bool) -> io::Result<Lock> {
+    let path = &project.canonicalize()?.join(LOCK_FILE);
+    let mut file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+
+    if let Err(why) = flock(&file, libc::LOCK_EX | libc::LOCK_NB) {
+        if why.kind() != io::ErrorKind::WouldBlock {
+            return Err(why);
+        }
+
+        let holder = misc::read_to_string(path)
+            .ok()
+            .map(|pid| pid.trim().to_owned())
+            .filter(|pid| !pid.is_empty())
+            .map_or(String::new(), |pid| format!(" (pid {})", pid));
+
+        if !wait {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!(
+                    "another debrep instance{} is running against this repository; \
+                     give --wait to wait for it to finish",
+                    holder
+                )
+            ));
+        }
+
+        info!("waiting for another debrep instance{} to finish", holder);
+        flock(&file, libc::LOCK_EX)?;
+    }
+
+    // The PID of the holder is recorded for the error of the next instance.
+    file.set_len(0)?;
+    write!(file, "{}", process::id())?;
+
+    Ok(Lock { _file: file })
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+
+        let why = io::Error::last_os_error();
+        if why.kind() != io::ErrorKind::Interrupted {
+            return Err(why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn exclusive_lock() {
+        let dir = tempdir().unwrap();
+        let project = dir.path().join("project");
+        fs::create_dir(&project).unwrap();
+
+        let held = lock(&project, false).unwrap();
+        let why = lock(&project, false).err().unwrap();
+        assert_eq!(why.kind(), io::ErrorKind::WouldBlock);
+        assert!(why.to_string().contains(&format!("(pid {})", process::id())));
+
+        // Another path to the same project is excluded by the same lock.
+        let link = dir.path().join("link");
+        symlink(&project, &link).unwrap();
+        assert_eq!(lock(&link.join("."), false).err().unwrap().kind(), io::ErrorKind::WouldBlock);
+
+        drop(held);
+        lock(&project, false).unwrap();
+        lock(&link, true).unwrap();
+    }
+}
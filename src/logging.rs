@@ -0,0 +1,124 @@
+use chrono::Utc;
+use fern;
+use log::LevelFilter;
+use std::io;
+
+/// How each log record is written to stderr.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable lines of the form `[LEVEL] target: message`.
+    Text,
+    /// One JSON object per line, with the time, level, target, and message of the record, for
+    /// CI systems to parse.
+    Json,
+}
+
+/// The level of the logs, given the number of times that `--verbose` and `--quiet` were passed.
+///
+/// Informational messages are logged by default. Each `--verbose` lowers the level, and each
+/// `--quiet` raises it, down to `trace` and up to `error`.
+pub fn log_level(verbose: u64, quiet: u64) -> LevelFilter {
+    match verbose as i64 - quiet as i64 {
+        level if level <= -2 => LevelFilter::Error,
+        -1 => LevelFilter::Warn,
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// The targets of the binary, and of the library that it is built on, whose logs are written.
+const TARGETS: &[&str] = &["debrep", "debrepbuild"];
+
+pub fn setup_logger(level: LevelFilter, format: LogFormat) -> Result<(), fern::InitError> {
+    dispatch(level, format)
+        .chain(io::stderr())
+        .apply()?;
+    Ok(())
+}
+
+fn dispatch(level: LevelFilter, format: LogFormat) -> fern::Dispatch {
+    // Exclude logs for crates that we use, and include only those of our own targets.
+    TARGETS.iter().fold(fern::Dispatch::new().level(LevelFilter::Off), |dispatch, &target| {
+        dispatch.level_for(target, level)
+    })
+        .format(move |out, message, record| match format {
+            LogFormat::Text => out.finish(format_args!(
+                "[{}] {}: {}",
+                record.level(),
+                {
+                    let target = record.target();
+                    target.find(':').map_or(target, |pos| &target[..pos])
+                },
+                message
+            )),
+            LogFormat::Json => out.finish(format_args!(
+                "{{\"time\":\"{}\",\"level\":\"{}\",\"target\":\"{}\",\"message\":\"{}\"}}",
+                Utc::now().to_rfc3339(),
+                record.level(),
+                json_escape(record.target()),
+                json_escape(&message.to_string())
+            )),
+        })
+}
+
+/// Escapes a string for use within a JSON string literal.
+pub fn json_escape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for character in input.chars() {
+        match character {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// A JSON string literal of the string, with its quotes.
+pub fn json_string(input: &str) -> String {
+    ["\"", &json_escape(input), "\""].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, Metadata};
+
+    #[test]
+    fn json_escaping() {
+        assert_eq!(json_escape("plain"), "plain");
+        assert_eq!(
+            json_escape("moved \"foo\" to C:\\pool\n\u{1}"),
+            "moved \\\"foo\\\" to C:\\\\pool\\n\\u0001"
+        );
+    }
+
+    #[test]
+    fn library_records() {
+        let sink: Box<io::Write + Send> = Box::new(io::sink());
+        let (_, logger) = dispatch(LevelFilter::Info, LogFormat::Text).chain(sink).into_log();
+        let enabled = |target: &str, level: Level| {
+            logger.enabled(&Metadata::builder().target(target).level(level).build())
+        };
+
+        assert!(enabled("debrep", Level::Info));
+        assert!(enabled("debrepbuild::repo::pool", Level::Info));
+        assert!(!enabled("debrepbuild::repo::pool", Level::Debug));
+        assert!(!enabled("reqwest::async_impl::client", Level::Info));
+    }
+
+    #[test]
+    fn verbosity_levels() {
+        assert_eq!(log_level(0, 0), LevelFilter::Info);
+        assert_eq!(log_level(1, 0), LevelFilter::Debug);
+        assert_eq!(log_level(3, 0), LevelFilter::Trace);
+        assert_eq!(log_level(0, 1), LevelFilter::Warn);
+        assert_eq!(log_level(0, 2), LevelFilter::Error);
+    }
+}
@@ -1,93 +1,35 @@
-extern crate apt_repo_crawler;
-extern crate bus_writer;
-#[macro_use]
-extern crate cascade;
-extern crate crossbeam_channel;
-extern crate deb_version;
-extern crate debarchive;
-extern crate deflate;
-extern crate digest;
-extern crate failure;
-extern crate fern;
-extern crate glob;
-extern crate hex_view;
-extern crate itertools;
-extern crate libc;
-extern crate libflate;
-extern crate md5;
-extern crate parallel_getter;
-extern crate rayon;
-extern crate regex;
-extern crate reqwest;
-extern crate select;
-extern crate serde;
-extern crate sha1;
-extern crate sha2;
-extern crate subprocess;
-extern crate tempfile;
-extern crate toml;
-extern crate utime;
-extern crate walkdir;
-extern crate xz2;
-
 #[macro_use]
 extern crate clap;
-#[macro_use]
-extern crate failure_derive;
+extern crate debrepbuild;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate serde_derive;
+extern crate rayon;
 
 mod cli;
-pub mod checksum;
-pub mod command;
-pub mod compress;
-pub mod config;
-pub mod debian;
-pub mod iter_reader;
-pub mod misc;
-mod repo;
-pub mod url;
 
 use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
 use cli::Action;
-use config::{Config, ConfigFetch, SourceLocation};
-use repo::{Packages, Repo};
+use debrepbuild::{action, checksum, config, debian, interrupt, lock, logging, misc, notify, progress, repo, url, workspace};
+#[cfg(feature = "serve")]
+use debrepbuild::serve;
+use logging::LogFormat;
+use notify::{notify, Notification};
+use config::{Builder, Config, ConfigFetch, SourceLocation};
+use repo::{Packages, Repo, RepoError};
 use std::{env, fs, io};
-use std::path::PathBuf;
+use std::io::Write;
+#[cfg(feature = "serve")]
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+#[cfg(feature = "serve")]
+use std::sync::Arc;
+use std::time::Duration;
 use url::UrlTokenizer;
 
-pub const SHARED_ASSETS: &str = "assets/share/";
-pub const PACKAGE_ASSETS: &str = "assets/packages/";
-
 include!(concat!(env!("OUT_DIR"), "/version.rs"));
 
-fn setup_logger() -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
-        // Exclude logs for crates that we use
-        .level(log::LevelFilter::Off)
-        // Include only the logs for this binary
-        .level_for("debrep", log::LevelFilter::Debug)
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{}] {}: {}",
-                record.level(),
-                {
-                    let target = record.target();
-                    target.find(':').map_or(target, |pos| &target[..pos])
-                },
-                message
-            ))
-        })
-        .chain(std::io::stderr())
-        .apply()?;
-    Ok(())
-}
-
 fn main() {
-    setup_logger().unwrap();
     let version = format!("{} ({})", crate_version!(), short_sha());
 
     let matches = App::new("Debian Repository Builder")
@@ -100,8 +42,75 @@ fn main() {
         .arg(Arg::with_name("suites")
             .help("define which suite(s) to operate on [default is all]")
             .long("suites")
+            .alias("suite")
             .global(true)
             .value_delimiter(","))
+        .arg(Arg::with_name("output")
+            .help("the directory to generate the repository in [overrides the repo_path config key]")
+            .long("output")
+            .short("o")
+            .global(true)
+            .takes_value(true))
+        .arg(Arg::with_name("set")
+            .help("overrides a value of the configs for this run, as `key=value`, after any DEBREP_* variables")
+            .long("set")
+            .global(true)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("verbose")
+            .help("logs more detail, which may be given twice to trace every step")
+            .long("verbose")
+            .short("v")
+            .global(true)
+            .multiple(true))
+        .arg(Arg::with_name("quiet")
+            .help("logs only warnings, or only errors if given twice")
+            .long("quiet")
+            .short("q")
+            .global(true)
+            .multiple(true)
+            .conflicts_with("verbose"))
+        .arg(Arg::with_name("log-format")
+            .help("the format of the logs written to stderr")
+            .long("log-format")
+            .global(true)
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .default_value("text"))
+        .arg(Arg::with_name("dry-run")
+            .help("prints each action that would be taken, without modifying files or using the network")
+            .long("dry-run")
+            .global(true))
+        .arg(Arg::with_name("allow-downgrade")
+            .help("pools built packages whose version is lower than the version already published")
+            .long("allow-downgrade")
+            .global(true))
+        .arg(Arg::with_name("wait")
+            .help("waits for another instance which is modifying the repository to finish, rather than failing")
+            .long("wait")
+            .global(true))
+        .arg(Arg::with_name("jobs")
+            .help("the number of packages to build concurrently [overrides the jobs config key]")
+            .long("jobs")
+            .short("j")
+            .global(true)
+            .takes_value(true))
+        .arg(Arg::with_name("summary")
+            .help("writes a JSON summary of the builds of each suite to this file")
+            .long("summary")
+            .global(true)
+            .takes_value(true))
+        .arg(Arg::with_name("tmp-dir")
+            .help("the directory that the workspace of each run is created within [default is tmp/]")
+            .long("tmp-dir")
+            .global(true)
+            .takes_value(true))
+        .arg(Arg::with_name("threads")
+            .help("the number of threads which hash and index the pool [default is one per CPU]")
+            .long("threads")
+            .global(true)
+            .takes_value(true))
         .subcommand(SubCommand::with_name("build")
             .about("Builds a new repo, or updates an existing one")
             .alias("b")
@@ -113,7 +122,7 @@ fn main() {
                     .short("f")
                     .long("force")
                     .group("action")
-                    .help("forces the package to be built"))
+                    .help("forces the packages to be built, replacing their files in the pool"))
             )
             .subcommand(SubCommand::with_name("pool")
                 .alias("p")
@@ -121,55 +130,352 @@ fn main() {
             .subcommand(SubCommand::with_name("dist")
                 .alias("d")
                 .about("only builds the dist files"))
+        ).subcommand(SubCommand::with_name("chroot")
+            .about("Creates and refreshes the build environments of the builders")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("create")
+                .about("creates the chroot of a suite's architecture")
+                .arg(Arg::with_name("suite").required(true))
+                .arg(Arg::with_name("arch").required(true)))
+            .subcommand(SubCommand::with_name("update")
+                .about("updates the chroots that each suite's sources are built within"))
         ).subcommand(SubCommand::with_name("clean")
-            .about("cleans excess packages from the repository")
+            .about("cleans excess packages from the repository, or the given intermediate artifacts")
+            .arg(Arg::with_name("sources")
+                .help("removes the cached trees of prepared sources")
+                .long("sources"))
+            .arg(Arg::with_name("builds")
+                .help("removes the build directories")
+                .long("builds"))
+            .arg(Arg::with_name("cache")
+                .help("removes the downloaded tarballs, keys, git mirrors, and assets")
+                .long("cache"))
+            .arg(Arg::with_name("logs")
+                .help("removes the build logs")
+                .long("logs"))
+            .arg(Arg::with_name("stale")
+                .help("removes the workspaces of runs which crashed, and the files that they left staged")
+                .long("stale"))
+            .arg(Arg::with_name("all")
+                .help("removes every intermediate artifact")
+                .long("all"))
         ).subcommand(SubCommand::with_name("config")
             .about("Gets or sets fields within the repo config")
             .alias("c")
             .arg(Arg::with_name("key").required(false))
             .arg(Arg::with_name("value").required(false))
+        ).subcommand(SubCommand::with_name("phase")
+            .about("Stages the rollout of package versions to clients which honor phased updates")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("bump")
+                .about("advances the Phased-Update-Percentage of a version, and regenerates the dist files")
+                .arg(Arg::with_name("package").required(true))
+                .arg(Arg::with_name("version")
+                    .help("the version to advance, which defaults to the newest published version")
+                    .long("version")
+                    .takes_value(true))
+                .arg(Arg::with_name("by")
+                    .help("the percentage to advance the rollout by")
+                    .long("by")
+                    .takes_value(true)
+                    .default_value("10")))
+        ).subcommand(SubCommand::with_name("plan")
+            .about("Reviews the changes that publishing an incoming directory will make, before they are made")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("create")
+                .about("writes the pool moves and index changes of publishing the directory into the suite to stdout")
+                .arg(Arg::with_name("suite").required(true))
+                .arg(Arg::with_name("directory").required(true)))
+            .subcommand(SubCommand::with_name("apply")
+                .about("performs the pool moves of a plan, and regenerates the dist files of its suite")
+                .arg(Arg::with_name("plan").required(true)))
+        ).subcommand(SubCommand::with_name("pool")
+            .about("Maintains the layout of the pool")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("repair")
+                .about("moves each file of the pool to where its metadata places it, and regenerates the dist files")
+                .arg(Arg::with_name("check")
+                    .help("only reports the misplaced files, failing if there are any")
+                    .long("check")))
+        ).subcommand(SubCommand::with_name("queue")
+            .about("Reviews the packages which the queue of each suite holds back from the pool")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("list")
+                .about("lists the held sources, and the reason that each was held"))
+            .subcommand(SubCommand::with_name("approve")
+                .about("moves the held sources into the pool, and regenerates the dist files")
+                .arg(Arg::with_name("packages").multiple(true).required(true)))
+            .subcommand(SubCommand::with_name("reject")
+                .about("removes the held sources, without publishing them")
+                .arg(Arg::with_name("packages").multiple(true).required(true)))
+        ).subcommand(SubCommand::with_name("prune")
+            .about("removes superseded versions of packages from the pool, and regenerates the dist files")
+            .arg(Arg::with_name("keep")
+                .help("the number of versions of each package to keep [overrides the keep_versions config key]")
+                .long("keep")
+                .takes_value(true))
+        ).subcommand(SubCommand::with_name("publish")
+            .about("Pushes the dists and pool of each suite to the S3 bucket and rsync targets of its config")
         ).subcommand(SubCommand::with_name("remove")
-            .about("removes the specified packages from the repository")
+            .about("removes the specified packages from the repository, and regenerates its dist files")
             .alias("r")
             .arg(Arg::with_name("packages").multiple(true).required(true))
+            .arg(Arg::with_name("version")
+                .help("only removes the files of this version")
+                .long("version")
+                .takes_value(true))
+        ).subcommand(SubCommand::with_name("resign")
+            .about("Regenerates and re-signs the Release files of each suite, renewing their Valid-Until without building")
+        ).subcommand(SubCommand::with_name("snapshot")
+            .about("Captures, lists, and publishes frozen states of the repository")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("create")
+                .about("captures the dists and pool of each suite into snapshots/<name>")
+                .arg(Arg::with_name("name").required(true)))
+            .subcommand(SubCommand::with_name("list")
+                .about("lists the snapshots, and the suites within them"))
+            .subcommand(SubCommand::with_name("publish")
+                .about("replaces the dists and pool of each suite with those of the snapshot")
+                .arg(Arg::with_name("name").required(true)))
+            .subcommand(SubCommand::with_name("serve")
+                .about("serves the snapshot over HTTP")
+                .arg(Arg::with_name("name").required(true))
+                .arg(Arg::with_name("address")
+                    .help("the address to listen on")
+                    .long("address")
+                    .takes_value(true)
+                    .default_value("127.0.0.1:8080")))
         ).subcommand(SubCommand::with_name("update")
             .about("Updates direct download-based packages in the configuration")
             .alias("u")
+        ).subcommand(SubCommand::with_name("init")
+            .about("Creates a new repository, with a starter config, its directories, and a .gitignore")
+            .arg(Arg::with_name("name")
+                .help("the directory to create the repository in, which also names its origin and label")
+                .required(true))
+            .arg(Arg::with_name("suite")
+                .help("the suite of the starter config [default is the name of the repository]")
+                .long("suite")
+                .takes_value(true))
+        ).subcommand(SubCommand::with_name("archive")
+            .about("Retires an end-of-life suite into the old releases, leaving a tombstone Release in its place")
+            .arg(Arg::with_name("suite").required(true))
+        ).subcommand(SubCommand::with_name("benchmark")
+            .about("Compares hashing the files by reading them with hashing them from a memory map")
+            .arg(Arg::with_name("files")
+                .help("large files, such as those of the pool, to find the `mmap_threshold` of [hashing] with")
+                .multiple(true)
+                .required(true))
+        ).subcommand(SubCommand::with_name("key")
+            .about("Manages the keys which sign the suites")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("export")
+                .about("writes the public signing keys of each repo into its root, as <name>.gpg and <name>.asc")
+                .arg(Arg::with_name("name")
+                    .help("the name of the keyrings [default is the name of the project directory]")
+                    .long("name")
+                    .takes_value(true)))
+        ).subcommand(SubCommand::with_name("check")
+            .about("Checks each config for unknown keys, missing fields, and conflicting or duplicate packages")
+            .arg(Arg::with_name("urls")
+                .help("also checks that the URL of each direct package and source is reachable")
+                .long("urls"))
+        ).subcommand(SubCommand::with_name("list")
+            .about("Lists every package and version that the dist files of each suite publish")
+            .arg(Arg::with_name("arch")
+                .help("only lists the packages of this architecture, such as amd64, all, or source")
+                .long("arch")
+                .takes_value(true))
+        ).subcommand(SubCommand::with_name("export")
+            .about("Writes a manifest of every package that the dist files of each suite publish to stdout")
+            .arg(Arg::with_name("format")
+                .help("the format of the manifest")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["json", "packages"])
+                .default_value("json"))
+        ).subcommand(SubCommand::with_name("copy")
+            .about("Copies packages from the pool of one suite to another, without rebuilding them")
+            .arg(Arg::with_name("packages")
+                .multiple(true)
+                .required(true))
+            .arg(Arg::with_name("from")
+                .help("the suite, or pocket, which packages are copied from")
+                .long("from")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("to")
+                .help("the suite, or pocket, which packages are copied to")
+                .long("to")
+                .takes_value(true)
+                .required(true))
+        ).subcommand(SubCommand::with_name("diff")
+            .about("Lists the packages which were added, removed, or given another version between two suites")
+            .arg(Arg::with_name("from")
+                .help("the suite, or pocket, to compare from, or a suite of a snapshot as <snapshot>/<suite>")
+                .required(true))
+            .arg(Arg::with_name("to")
+                .help("the suite, or pocket, to compare to, or a suite of a snapshot as <snapshot>/<suite>")
+                .required(true))
+            .arg(Arg::with_name("format")
+                .help("the format of the changes")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text"))
+        ).subcommand(SubCommand::with_name("import")
+            .about("adopts the packages of an existing repository, such as one managed by reprepro or aptly")
+            .arg(Arg::with_name("path").required(true))
+            .arg(Arg::with_name("suite")
+                .help("the suite of the existing repository to import [defaults to the suite of the same name]")
+                .long("suite")
+                .takes_value(true))
+        ).subcommand(SubCommand::with_name("incoming")
+            .about("Accepts signed uploads into the suites which have an incoming keyring")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("process")
+                .about("verifies the uploads of the directory, and moves those which pass into the pool")
+                .arg(Arg::with_name("directory").required(true))
+                .arg(Arg::with_name("watch")
+                    .help("keeps processing the directory as uploads arrive, until interrupted")
+                    .long("watch")))
+        ).subcommand(SubCommand::with_name("info")
+            .about("Shows each published version of a package, with the paths, sizes, and digests of its files")
+            .arg(Arg::with_name("package").required(true))
+        ).subcommand(SubCommand::with_name("search")
+            .about("Searches the names and descriptions of the packages that the dist files of each suite publish")
+            .arg(Arg::with_name("pattern")
+                .help("a regular expression, which ignores case")
+                .required(true))
+            .arg(Arg::with_name("snapshots")
+                .help("also searches the suites of each snapshot")
+                .long("snapshots"))
+        ).subcommand(SubCommand::with_name("verify")
+            .about("Re-hashes the pool, and checks it against the sizes and digests recorded by the dist files")
+            .arg(Arg::with_name("keyring")
+                .help("also checks the dist files against the signed .repo-state of each suite, which must be signed by a key of this keyring")
+                .long("keyring")
+                .takes_value(true))
+        ).subcommand(SubCommand::with_name("test-apt")
+            .about("Serves each suite, and installs packages from it with apt, to test it end to end")
+            .arg(Arg::with_name("packages")
+                .help("the packages to install [overrides the test_apt.packages config key]")
+                .multiple(true))
+        ).subcommand(SubCommand::with_name("watch")
+            .about("Rebuilds the sources, and regenerates the dist files, of each suite as its files change")
+            .arg(Arg::with_name("metrics")
+                .help("serves the metrics of each suite at /metrics of the given ADDRESS:PORT while watching")
+                .long("metrics")
+                .takes_value(true))
+        ).subcommand(SubCommand::with_name("serve")
+            .about("Serves the repository over HTTP, so that machines may install from it while testing")
+            .arg(Arg::with_name("address")
+                .help("the address to listen on")
+                .long("address")
+                .takes_value(true)
+                .default_value("0.0.0.0"))
+            .arg(Arg::with_name("port")
+                .help("the port to listen on")
+                .long("port")
+                .short("p")
+                .takes_value(true)
+                .default_value("8080"))
+            .arg(Arg::with_name("auth")
+                .help("requires basic authentication with the given credentials, as USER:PASSWORD")
+                .long("auth")
+                .env("DEBREP_SERVE_AUTH")
+                .hide_env_values(true)
+                .takes_value(true))
         ).subcommand(SubCommand::with_name("migrate")
-            .about("Moves a package from one component to another, updating both components in the process")
+            .about("Moves packages from one component, or pocket, to another, updating both in the process")
             .alias("m")
             .arg(Arg::with_name("packages")
                 .multiple(true)
-                .required(true))
+                .required_unless("all"))
+            .arg(Arg::with_name("all")
+                .help("migrates every package")
+                .long("all")
+                .conflicts_with("packages"))
             .arg(Arg::with_name("from")
-                .help("specifies the component which packages are being moved from")
+                .help("specifies the component, or the pocket or suite, which packages are being moved from")
                 .long("from")
                 .takes_value(true)
                 .required(true))
             .arg(Arg::with_name("to")
-                .help("specifies the component which packages are being moved to")
+                .help("specifies the component, or the pocket or suite, which packages are being moved to")
                 .long("to")
                 .takes_value(true)
                 .required(true))
+            .arg(Arg::with_name("copy")
+                .help("keeps the packages in the pocket that they are migrated from")
+                .long("copy"))
         ).get_matches();
 
+    let level = logging::log_level(matches.occurrences_of("verbose"), matches.occurrences_of("quiet"));
+    let format = match matches.value_of("log-format") {
+        Some("json") => LogFormat::Json,
+        _ => LogFormat::Text,
+    };
+
+    logging::setup_logger(level, format).unwrap();
+
+    // Progress bars would be interleaved with machine-readable logs, and are noise when quiet.
+    if format == LogFormat::Json || matches.occurrences_of("quiet") != 0 {
+        progress::disable();
+    }
+
+    if matches.is_present("dry-run") {
+        action::set_dry_run();
+    }
+
+    if let Some(threads) = matches.value_of("threads") {
+        let threads = match threads.parse::<usize>() {
+            Ok(threads) if threads > 0 => threads,
+            _ => {
+                error!("--threads must be a positive number of threads: {}", threads);
+                exit(1);
+            }
+        };
+
+        if let Err(why) = rayon::ThreadPoolBuilder::new().num_threads(threads).build_global() {
+            error!("failed to create a pool of {} threads: {}", threads, why);
+            exit(1);
+        }
+    }
+
     if let Err(why) = read_configs(&matches) {
-        eprintln!("failed to apply configs: {}", why);
+        error!("failed to apply configs: {}", why);
         exit(1);
     }
 }
 
 fn read_configs(matches: &ArgMatches) -> io::Result<()> {
+    // A new repository has no configs to read.
+    if let Action::Init(name, suite) = Action::new(matches) {
+        let root = Path::new(name);
+        let name = root.file_name().and_then(|x| x.to_str()).unwrap_or(name);
+        return repo::init(root, name, suite.unwrap_or(name));
+    }
+
+    // Hashing is benchmarked on the given files, rather than on those of a config.
+    if let Action::Benchmark(files) = Action::new(matches) {
+        return benchmark_hashing(&files);
+    }
+
     let base_directory = env::current_dir()?;
     let mut configs = Vec::new();
 
-    let suites: Vec<PathBuf> = match matches.values_of("suites") {
-        Some(suites) => {
-            suites
-                .map(|x| PathBuf::from(["suites/", &x, ".toml"].concat()))
-                .collect()
-        }
-        None => {
+    // A suite may have a config of its own, or be one of several defined by a shared config.
+    let selected: Option<Vec<&str>> = matches.values_of("suites").map(|suites| suites.collect());
+    let own_configs = selected.as_ref().map(|selected| {
+        selected.iter()
+            .map(|x| PathBuf::from(["suites/", &x, ".toml"].concat()))
+            .collect::<Vec<PathBuf>>()
+    });
+
+    let suites: Vec<PathBuf> = match own_configs {
+        Some(ref suites) if suites.iter().all(|suite| suite.exists()) => suites.clone(),
+        _ => {
             let mut suites = Vec::new();
             for file in fs::read_dir("suites")? {
                 let file = match file {
@@ -192,12 +498,48 @@ fn read_configs(matches: &ArgMatches) -> io::Result<()> {
         }
     };
 
+    // Configs are checked before they are parsed, so that every problem may be reported.
+    if let Action::Check(urls) = Action::new(matches) {
+        return check_configs(&suites, urls);
+    }
+
+    // Values which are given on the command line are assigned after those of the environment.
+    let mut assignments = config::environment_assignments(env::vars_os().filter_map(|(name, value)| {
+        Some((name.into_string().ok()?, value.into_string().ok()?))
+    }));
+    for assignment in matches.values_of("set").into_iter().flat_map(|x| x) {
+        assignments.push(config::Assignment::parse(assignment)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?);
+    }
+
+    let mut parsed = Vec::new();
     for suite in suites {
-        let mut config = config::parse(suite).map_err(|why| io::Error::new(
+        parsed.extend(config::parse_suites(suite, &assignments).map_err(|why| io::Error::new(
             io::ErrorKind::Other,
             format!("configuration parsing error: {}", why)
-        ))?;
+        ))?);
+    }
+
+    if let Some(ref selected) = selected {
+        let name = |config: &Config| -> String {
+            if config.shared {
+                config.archive.clone()
+            } else {
+                config.path.file_stem().and_then(|x| x.to_str()).unwrap_or("").to_owned()
+            }
+        };
+
+        if let Some(missing) = selected.iter().find(|&&x| !parsed.iter().any(|c| name(c) == x)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a suite of any config", missing)
+            ));
+        }
+
+        parsed.retain(|config| selected.contains(&name(config).as_str()));
+    }
 
+    for mut config in parsed {
         if let Some(ref mut sources) = config.source {
             for source in sources {
                 if let Some(ref version) = source.version {
@@ -214,10 +556,350 @@ fn read_configs(matches: &ArgMatches) -> io::Result<()> {
             }
         }
 
+        config.output = matches.value_of("output").map(PathBuf::from);
+        config.summary = matches.value_of("summary").map(|path| base_directory.join(path));
+        config.allow_downgrade = matches.is_present("allow-downgrade");
         configs.push(config);
     }
 
+    // Overlapping runs, such as cron jobs, would otherwise interleave their changes to the pool
+    // and dists, so the project is locked until any command which modifies it exits.
+    let _lock = if Action::new(matches).is_mutating() && !action::is_dry_run() {
+        Some(lock::lock(&base_directory, matches.is_present("wait"))?)
+    } else {
+        None
+    };
+
+    // Intermediate files are written within a workspace of this run, which is removed as it exits.
+    // Commands which modify the repository are interrupted, rather than killed, by a signal.
+    let tmp_dir = base_directory.join(matches.value_of("tmp-dir").unwrap_or(workspace::TMP_DIR));
+    if Action::new(matches).is_mutating() && !action::is_dry_run() {
+        workspace::start(&tmp_dir)?;
+        interrupt::handle_signals();
+    }
+
+    // The summary of each suite is appended, so the summaries of a previous run are removed.
+    if let Some(path) = matches.value_of("summary") {
+        if !action::is_dry_run() {
+            misc::write(base_directory.join(path), b"")?;
+        }
+    }
+
+    match Action::new(matches) {
+        // A chroot may be created for a suite which does not yet have a configuration.
+        Action::ChrootCreate(suite, arch) => {
+            if !configs.iter().any(|config| config.archive == suite) {
+                return action::perform(format_args!("create the {} {} chroot", suite, arch), || {
+                    repo::create_chroot(&Builder::default(), suite, arch)
+                });
+            }
+        }
+        Action::SnapshotList => {
+            for snapshot in repo::list_snapshots(&base_directory)? {
+                println!("{}: {}", snapshot.name, snapshot.suites.join(", "));
+            }
+
+            return Ok(());
+        }
+        Action::SnapshotServe(name, address) => return serve_snapshot(&base_directory, name, address),
+        Action::List(arch) => {
+            let mut rows = vec![["PACKAGE", "VERSION", "ARCH", "SUITE", "COMPONENT"].iter()
+                .map(|&x| x.to_owned())
+                .collect::<Vec<String>>()];
+
+            for config in &configs {
+                for package in repo::published(config.repo_path(), &config.archive)? {
+                    if arch.map_or(true, |arch| arch == package.architecture) {
+                        rows.push(vec![
+                            package.package,
+                            package.version,
+                            package.architecture,
+                            package.suite,
+                            package.component,
+                        ]);
+                    }
+                }
+            }
+
+            print!("{}", repo::table(&rows));
+            return Ok(());
+        }
+        Action::Clean(ref targets) if !targets.is_empty() => {
+            let mut reclaimed = repo::clean(&base_directory, targets)?.into_iter()
+                .map(|(directory, bytes)| (PathBuf::from(directory), bytes))
+                .collect::<Vec<(PathBuf, u64)>>();
+
+            if targets.contains(&"stale") || targets.contains(&"all") {
+                reclaimed.extend(repo::clean_stale(&tmp_dir)?);
+            }
+
+            for &(ref directory, bytes) in &reclaimed {
+                info!("removed {} ({} bytes)", directory.display(), bytes);
+            }
+
+            let total = reclaimed.iter().map(|&(_, bytes)| bytes).sum::<u64>();
+            if action::is_dry_run() {
+                println!("would reclaim {} bytes", total);
+            } else {
+                println!("reclaimed {} bytes", total);
+            }
+
+            return Ok(());
+        }
+        Action::KeyExport(name) => {
+            let project = base_directory.file_name().and_then(|x| x.to_str()).unwrap_or("repo");
+            let keyrings = repo::export_keys(&configs, name.unwrap_or(project))?;
+            if !action::is_dry_run() {
+                for keyring in keyrings {
+                    info!("exported the signing keys to {}", keyring.display());
+                }
+            }
+
+            return Ok(());
+        }
+        // The combined index covers every suite of a repo, so each repo is written once.
+        Action::Export("packages") => {
+            let mut repos: Vec<&Path> = Vec::new();
+            for config in &configs {
+                if !repos.contains(&config.repo_path()) {
+                    repos.push(config.repo_path());
+                }
+            }
+
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            for (id, repo) in repos.into_iter().enumerate() {
+                if id != 0 {
+                    stdout.write_all(b"\n")?;
+                }
+
+                repo::generate_combined_index(repo, &mut stdout)?;
+            }
+
+            return Ok(());
+        }
+        Action::Export(_) => {
+            let mut suites = Vec::new();
+            for config in &configs {
+                let packages = repo::published(config.repo_path(), &config.archive)?;
+                suites.push((config.archive.clone(), packages));
+            }
+
+            print!("{}", repo::manifest(&suites));
+            return Ok(());
+        }
+        Action::Diff(from, to, format) => {
+            let published = |state: &str| -> io::Result<Vec<repo::Published>> {
+                let (repo, suite) = diff_state(&base_directory, &configs, state)?;
+                repo::published(&repo, suite)
+            };
+
+            let diffs = repo::diff(&published(from)?, &published(to)?);
+            match format {
+                "json" => print!("{}", repo::diff_json(from, to, &diffs)),
+                _ => print!("{}", repo::diff_table(&diffs)),
+            }
+
+            return Ok(());
+        }
+        Action::Info(name) => {
+            let mut found = false;
+            for config in &configs {
+                let packages = repo::published(config.repo_path(), &config.archive)?;
+                for package in packages.into_iter().filter(|package| package.package == name) {
+                    found = true;
+                    println!(
+                        "{} {} {} ({}/{})",
+                        package.package,
+                        package.version,
+                        package.architecture,
+                        package.suite,
+                        package.component
+                    );
+
+                    for (path, size, sha256) in package.files {
+                        println!("  {}\n    size: {}\n    sha256: {}", path, size, sha256);
+                    }
+                }
+            }
+
+            if !found {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not published by any suite", name)
+                ));
+            }
+
+            return Ok(());
+        }
+        Action::Search(pattern, snapshots) => {
+            let mut published = Vec::new();
+            for config in &configs {
+                published.push((None, repo::published(config.repo_path(), &config.archive)?));
+            }
+
+            if snapshots {
+                for snapshot in repo::list_snapshots(&base_directory)? {
+                    let path = repo::snapshot_path(&base_directory, &snapshot.name)?;
+                    for suite in &snapshot.suites {
+                        published.push((Some(snapshot.name.clone()), repo::published(&path, suite)?));
+                    }
+                }
+            }
+
+            let mut header = vec!["PACKAGE", "VERSION", "ARCH", "SUITE", "COMPONENT"];
+            if snapshots {
+                header.push("SNAPSHOT");
+            }
+
+            header.push("DESCRIPTION");
+            let mut rows = vec![header.into_iter().map(String::from).collect::<Vec<String>>()];
+            for &(ref snapshot, ref packages) in &published {
+                for package in repo::search(packages, pattern)? {
+                    let mut row = vec![
+                        package.package.clone(),
+                        package.version.clone(),
+                        package.architecture.clone(),
+                        package.suite.clone(),
+                        package.component.clone(),
+                    ];
+
+                    if snapshots {
+                        row.push(snapshot.clone().unwrap_or_else(|| "-".to_owned()));
+                    }
+
+                    row.push(package.description.lines().next().unwrap_or("").to_owned());
+                    rows.push(row);
+                }
+            }
+
+            if rows.len() == 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no published package matches {}", pattern)
+                ));
+            }
+
+            print!("{}", repo::table(&rows));
+            return Ok(());
+        }
+        // Suites commonly share a repo, so the repo of the first suite is served.
+        Action::Serve(address, port, credentials) => {
+            let repo = configs.first().map_or(Path::new("repo"), |config| config.repo_path());
+            return serve_directory(repo, &[address, ":", port].concat(), credentials, Some(&configs[..]));
+        }
+        // Each rebuild is given the same options as the watch.
+        Action::Watch(metrics) => return watch(&configs, &forwarded_args(matches), metrics),
+        Action::Incoming(directory, true) => {
+            // Each run is given the suites of the watch, and waits for any build to finish.
+            let mut args = forwarded_args(matches);
+            if let Some(suites) = matches.values_of("suites") {
+                args.push(["--suites=", &suites.collect::<Vec<&str>>().join(",")].concat());
+            }
+
+            if !matches.is_present("wait") {
+                args.push("--wait".to_owned());
+            }
+
+            return repo::watch_incoming(Path::new(directory), &args);
+        }
+        Action::Incoming(directory, false) => return repo::process_incoming(&configs, Path::new(directory)),
+        Action::QueueList => {
+            let mut rows = vec![["SOURCE", "SUITE", "COMPONENT", "FILES", "REASON"].iter()
+                .map(|&x| x.to_owned())
+                .collect::<Vec<String>>()];
+
+            for config in &configs {
+                for held in repo::queued(config)? {
+                    rows.push(vec![held.name, held.suite, held.component, held.files.len().to_string(), held.reason]);
+                }
+            }
+
+            print!("{}", repo::table(&rows));
+            return Ok(());
+        }
+        Action::QueueApprove(ref packages) => return repo::approve(&configs, packages),
+        Action::QueueReject(ref packages) => return repo::reject(&configs, packages),
+        // Copies operate on two suites, rather than on each suite.
+        Action::Copy(ref packages, from, to) => {
+            let find = |name: &str| configs.iter().find(|config| {
+                config.archive == name || config.pocket.as_ref().map_or(false, |pocket| pocket == name)
+            }).ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a configured pocket or suite", name)
+            ));
+
+            let (from, to) = (find(from)?, find(to)?);
+            if from.archive == to.archive {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("packages cannot be copied from {} to itself", from.archive)
+                ));
+            }
+
+            return repo::copy_packages(from, to, packages)
+                .map_err(|why| io::Error::new(io::ErrorKind::Other, format!("copy failed: {}", why)));
+        }
+        // A plan is created for, and applied to, only the suite that it names.
+        Action::PlanCreate(suite, directory) => {
+            let config = configs.iter().find(|config| config.archive == suite).ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a configured suite", suite)
+            ))?;
+
+            let plan = repo::plan_publish(config.repo_path(), Path::new(directory), config)?;
+            print!("{}", plan.to_toml()?);
+            return Ok(());
+        }
+        Action::PlanApply(path) => {
+            let plan = repo::PublishPlan::open(Path::new(path))?;
+            let config = configs.into_iter().find(|config| config.archive == plan.suite).ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a configured suite", plan.suite)
+            ))?;
+
+            repo::apply_plan(&plan)?;
+            exit_on_error(Repo::prepare(config, Packages::All).and_then(Repo::generate));
+            return Ok(());
+        }
+        // Only the named suite is archived, rather than each suite.
+        Action::Archive(suite) => {
+            let config = configs.iter().find(|config| config.archive == suite).ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a configured suite", suite)
+            ))?;
+
+            let archived = repo::archive_suite(config)?;
+            info!("archived {}, and moved its config to {}", suite, archived.display());
+            return Ok(());
+        }
+        // Migrations between pockets operate on two suites, rather than on each suite.
+        Action::Migrate(ref packages, from, to, copy) => {
+            let find = |name: &str| configs.iter().find(|config| {
+                config.archive == name || config.pocket.as_ref().map_or(false, |pocket| pocket == name)
+            });
+
+            match (find(from), find(to)) {
+                (Some(from), Some(to)) => {
+                    return repo::migrate_suite(from, to, packages.as_ref().map(|x| x.as_slice()), copy)
+                        .map_err(|why| io::Error::new(io::ErrorKind::Other, format!("migration failed: {}", why)));
+                }
+                (None, None) => (),
+                _ => return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("both {} and {} must be configured pockets or suites", from, to)
+                ))
+            }
+        }
+        _ => ()
+    }
+
     for config in configs {
+        if let Err(why) = interrupt::check() {
+            error!("{}, before {} was applied", why, config.archive);
+            exit(interrupt::exit_status());
+        }
+
         apply_config(config, matches);
         env::set_current_dir(&base_directory)?;
     }
@@ -225,20 +907,251 @@ fn read_configs(matches: &ArgMatches) -> io::Result<()> {
     Ok(())
 }
 
+fn check_configs(suites: &[PathBuf], urls: bool) -> io::Result<()> {
+    let mut failed = false;
+    for suite in suites {
+        let diagnostics = config::check(suite, urls).map_err(|why| io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to check config: {}", why)
+        ))?;
+
+        for diagnostic in &diagnostics {
+            match diagnostic.position {
+                Some(_) => println!("{}:{}", suite.display(), diagnostic),
+                None => println!("{}: {}", suite.display(), diagnostic),
+            }
+        }
+
+        failed |= !diagnostics.is_empty();
+    }
+
+    if failed {
+        exit(1);
+    }
+
+    info!("every config is valid");
+    Ok(())
+}
+
+/// Hashes each file by reading it, and then from a memory map, so that the `mmap_threshold` of
+/// `[hashing]` may be set to the size from which mapping is faster on this machine. Each method
+/// is timed twice, and the faster run is kept, so that both hash the file from the page cache.
+fn benchmark_hashing(files: &[&str]) -> io::Result<()> {
+    let fastest = |path: &Path, mapped: bool| -> io::Result<Duration> {
+        Ok(checksum::time_hashing(path, mapped)?.min(checksum::time_hashing(path, mapped)?))
+    };
+
+    println!("{:>12} {:>12} {:>12}  FILE", "SIZE", "READ MiB/s", "MMAP MiB/s");
+    for file in files {
+        let path = Path::new(file);
+        let size = fs::metadata(path)?.len();
+        let rate = |duration: Duration| {
+            let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+            size as f64 / 1_048_576.0 / seconds.max(1e-9)
+        };
+
+        let read = rate(fastest(path, false)?);
+        let mapped = rate(fastest(path, true)?);
+        println!("{:>12} {:>12.1} {:>12.1}  {}", size, read, mapped, path.display());
+    }
+
+    Ok(())
+}
+
+/// The options of this run which are given to the runs that it spawns.
+fn forwarded_args(matches: &ArgMatches) -> Vec<String> {
+    let mut args = Vec::new();
+    let options = [
+        ("output", "--output"),
+        ("jobs", "--jobs"),
+        ("threads", "--threads"),
+        ("tmp-dir", "--tmp-dir"),
+        ("log-format", "--log-format"),
+    ];
+
+    for &(arg, flag) in &options {
+        if let Some(value) = matches.value_of(arg) {
+            args.push(flag.to_owned());
+            args.push(value.to_owned());
+        }
+    }
+
+    for value in matches.values_of("set").into_iter().flat_map(|x| x) {
+        args.push("--set".to_owned());
+        args.push(value.to_owned());
+    }
+
+    let flags = [("verbose", "--verbose"), ("quiet", "--quiet"), ("dry-run", "--dry-run"), ("wait", "--wait")];
+    for &(arg, flag) in &flags {
+        for _ in 0..matches.occurrences_of(arg) {
+            args.push(flag.to_owned());
+        }
+    }
+
+    args
+}
+
+/// The repo and suite of a state which `diff` compares, which is a configured suite or pocket, or
+/// the suite of a snapshot, as `<snapshot>/<suite>`.
+fn diff_state<'a>(
+    root: &Path,
+    configs: &'a [Config],
+    state: &'a str
+) -> io::Result<(PathBuf, &'a str)> {
+    if let Some(pos) = state.find('/') {
+        return Ok((repo::snapshot_path(root, &state[..pos])?, &state[pos + 1..]));
+    }
+
+    configs.iter()
+        .find(|config| config.archive == state || config.pocket.as_ref().map_or(false, |x| x == state))
+        .map(|config| (config.repo_path().to_path_buf(), config.archive.as_str()))
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not a configured pocket or suite", state)
+        ))
+}
+
+fn serve_snapshot(root: &Path, name: &str, address: &str) -> io::Result<()> {
+    let snapshot = repo::snapshot_path(root, name)?;
+    if !snapshot.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("snapshot {} does not exist", name)));
+    }
+
+    serve_directory(&snapshot, address, None, None)
+}
+
+/// Serves the repo, along with the metrics of the suites of the configs, if they are given.
+#[cfg(feature = "serve")]
+fn serve_directory(
+    repo: &Path,
+    address: &str,
+    credentials: Option<&str>,
+    configs: Option<&[Config]>
+) -> io::Result<()> {
+    if !repo.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", repo.display())));
+    }
+
+    if credentials.map_or(false, |credentials| !credentials.contains(':')) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "credentials must be given as USER:PASSWORD"));
+    }
+
+    let metrics = match configs {
+        Some(configs) => Some(project_metrics(configs)?),
+        None => None
+    };
+
+    serve::serve(repo, parse_address(address)?, credentials, metrics)?.wait();
+    Ok(())
+}
+
+#[cfg(not(feature = "serve"))]
+fn serve_directory(
+    _repo: &Path,
+    _address: &str,
+    _credentials: Option<&str>,
+    _configs: Option<&[Config]>
+) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "debrep was built without the serve feature"))
+}
+
+/// Watches the suites, serving their metrics at the address while it watches, if one is given.
+#[cfg(feature = "serve")]
+fn watch(configs: &[Config], args: &[String], metrics: Option<&str>) -> io::Result<()> {
+    let _server = match metrics {
+        Some(address) => Some(serve::serve_metrics(parse_address(address)?, project_metrics(configs)?)?),
+        None => None
+    };
+
+    repo::watch(configs, args)
+}
+
+#[cfg(not(feature = "serve"))]
+fn watch(configs: &[Config], args: &[String], metrics: Option<&str>) -> io::Result<()> {
+    if metrics.is_some() {
+        return Err(io::Error::new(io::ErrorKind::Other, "debrep was built without the serve feature"));
+    }
+
+    repo::watch(configs, args)
+}
+
+/// The metrics of the suites of the configs, and of the repo of the first, as it is served.
+/// They are collected from the project on each scrape, as each rebuild of a watch runs in a
+/// process of its own.
+#[cfg(feature = "serve")]
+fn project_metrics(configs: &[Config]) -> io::Result<serve::Metrics> {
+    let root = env::current_dir()?;
+    let repo = root.join(configs.first().map_or(Path::new("repo"), |config| config.repo_path()));
+    let suites = configs.iter().map(|config| config.archive.clone()).collect::<Vec<String>>();
+    Ok(Arc::new(move |out: &mut Vec<u8>| repo::project_metrics(&root, &repo, &suites, out)))
+}
+
+#[cfg(feature = "serve")]
+fn parse_address(address: &str) -> io::Result<SocketAddr> {
+    address.parse().map_err(|why| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid address {}: {}", address, why)
+    ))
+}
+
+/// Exits with the status of the error which a run of the repo failed with, once it is logged.
+fn exit_on_error<T>(result: Result<T, RepoError>) {
+    if let Err(why) = result {
+        error!("{}", why);
+        exit(why.exit_status());
+    }
+}
+
 fn apply_config(mut config: Config, matches: &ArgMatches) {
     info!("Building from config at {}", config.path.display());
+    if let Some(jobs) = matches.value_of("jobs") {
+        match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => config.jobs = Some(jobs),
+            _ => {
+                error!("--jobs must be a positive number of builds: {}", jobs);
+                exit(1);
+            }
+        }
+    }
+
     match Action::new(&matches) {
         Action::Build(packages, force) => {
-            Repo::prepare(config, Packages::Select(&packages, force))
-                .download()
-                .build()
-                .generate();
+            exit_on_error(Repo::prepare(config, Packages::Select(&packages, force))
+                .and_then(Repo::download)
+                .and_then(Repo::build)
+                .and_then(Repo::generate));
+        },
+        Action::ChrootCreate(suite, arch) => {
+            if config.archive != suite {
+                return;
+            }
+
+            let builder = config.builder.clone().unwrap_or_default();
+            let distribution = builder.distribution.clone().unwrap_or_else(|| config.archive.clone());
+            let created = action::perform(format_args!("create the {} {} chroot", distribution, arch), || {
+                repo::create_chroot(&builder, &distribution, arch)
+            });
+
+            if let Err(why) = created {
+                error!("failed to create chroot: {}", why);
+                exit(1);
+            }
         },
-        Action::Clean => {
-            Repo::prepare(config, Packages::All).clean();
+        Action::ChrootUpdate => {
+            let updated = action::perform(format_args!("update the chroots of {}", config.archive), || {
+                repo::update_chroots(&config)
+            });
+
+            if let Err(why) = updated {
+                error!("failed to update chroots: {}", why);
+                exit(1);
+            }
+        },
+        Action::Clean(_) => {
+            exit_on_error(Repo::prepare(config, Packages::All).and_then(Repo::clean));
         },
         Action::Dist => {
-            Repo::prepare(config, Packages::All).generate();
+            exit_on_error(Repo::prepare(config, Packages::All).and_then(Repo::generate));
         },
         Action::Fetch(key) => match config.fetch(&key) {
             Some(value) => println!("{}: {}", key, value),
@@ -248,19 +1161,211 @@ fn apply_config(mut config: Config, matches: &ArgMatches) {
             }
         },
         Action::FetchConfig => println!("{}: {:#?}", config.path.display(), &config),
-        Action::Migrate(packages, from_component, to_component) => {
-            if let Err(why) = repo::migrate(&config, &packages, from_component, to_component) {
+        Action::Import(path, from) => {
+            let repo_path = config.repo_path();
+            let imported = action::perform(format_args!("import {} into {}", path, config.archive), || {
+                repo::import_repo(Path::new(path), from, repo_path, &config.archive)
+            });
+
+            let report = match imported {
+                Ok(report) => report,
+                Err(why) => {
+                    error!("failed to import {}: {}", path, why);
+                    exit(1);
+                }
+            };
+
+            info!(
+                "imported {} files into {}, {} of which were already in the pool, and skipped {}",
+                report.imported.len() + report.existing.len(),
+                config.archive,
+                report.existing.len(),
+                report.skipped.len()
+            );
+
+            match repo::record_imported(&config, Path::new("."), &report) {
+                Ok(ref recorded) if !recorded.is_empty() => {
+                    info!("recorded {} as built at their imported versions", recorded.join(", "));
+                }
+                Ok(_) => (),
+                Err(why) => {
+                    error!("failed to record the imported sources: {}", why);
+                    exit(1);
+                }
+            }
+
+            // The suite is reindexed once the import has reached its pool, to publish the imported files.
+            if report.suites.contains(&config.archive) {
+                exit_on_error(Repo::prepare(config, Packages::All).and_then(Repo::generate));
+            }
+        },
+        Action::Migrate(packages, from_component, to_component, _) => {
+            let packages = packages.as_ref().map(|x| x.as_slice());
+            if let Err(why) = repo::migrate(&config, packages, from_component, to_component) {
                 error!("migration failed: {}", why);
                 exit(1);
             }
         },
         Action::Pool => {
-            Repo::prepare(config, Packages::All).download();
+            exit_on_error(Repo::prepare(config, Packages::All).and_then(Repo::download));
+        },
+        Action::PhaseBump(package, version, by) => {
+            let by = match by.parse::<u32>() {
+                Ok(by) if by > 0 && by <= 100 => by,
+                _ => {
+                    error!("--by must be a percentage from 1 to 100: {}", by);
+                    exit(1);
+                }
+            };
+
+            let version = match version {
+                Some(version) => version.to_owned(),
+                None => {
+                    let newest = repo::published(config.repo_path(), &config.archive).map(|packages| {
+                        packages.into_iter()
+                            .filter(|published| published.package == package && published.architecture != "source")
+                            .map(|published| published.version)
+                            .max_by(|a, b| debian::compare_versions(a, b))
+                    });
+
+                    match newest {
+                        Ok(Some(version)) => version,
+                        Ok(None) => {
+                            error!("{} is not published by {}", package, config.archive);
+                            exit(1);
+                        }
+                        Err(why) => {
+                            error!("failed to read the indices of {}: {}", config.archive, why);
+                            exit(1);
+                        }
+                    }
+                }
+            };
+
+            let percentage = config.overrides.get_or_insert_with(Default::default)
+                .bump_phase(package, &version, by);
+
+            if action::is_dry_run() {
+                action::report(format_args!("phase {} {} to {} percent", package, version, percentage));
+            } else if let Err(why) = config.write_to_disk() {
+                error!("failed to write config changes: {}", why);
+                exit(1);
+            } else if percentage == 100 {
+                info!("{} {} is rolled out to every client", package, version);
+            } else {
+                info!("{} {} is rolled out to {} percent of clients", package, version, percentage);
+            }
+
+            exit_on_error(Repo::prepare(config, Packages::All).and_then(Repo::generate));
         },
-        Action::Remove(packages) => {
-            Repo::prepare(config, Packages::Select(&packages, false)).remove();
+        Action::PoolRepair(true) => {
+            exit_on_error(Repo::prepare(config, Packages::All)
+                .and_then(|repo| repo.repair(false))
+                .map(|_| ()));
+        },
+        Action::PoolRepair(false) => {
+            exit_on_error(Repo::prepare(config, Packages::All)
+                .and_then(|repo| repo.repair(true))
+                .and_then(Repo::generate));
+        },
+        Action::Prune(keep) => {
+            let keep = match keep {
+                Some(keep) => match keep.parse::<usize>() {
+                    Ok(keep) if keep > 0 => keep,
+                    _ => {
+                        error!("--keep must be a positive number of versions: {}", keep);
+                        exit(1);
+                    }
+                },
+                None => match config.keep_versions {
+                    Some(keep) if keep > 0 => keep,
+                    _ => {
+                        error!("the number of versions to keep must be given by --keep, or the keep_versions config key");
+                        exit(1);
+                    }
+                }
+            };
+
+            exit_on_error(Repo::prepare(config, Packages::All)
+                .and_then(|repo| repo.prune(keep))
+                .and_then(Repo::generate));
+        },
+        Action::Publish => {
+            let published = repo::publish(&config);
+            if let Some(ref notifications) = config.notify {
+                let subject = match published {
+                    Ok(()) => format!("{} was published", config.archive),
+                    Err(ref why) => format!("{} failed to publish: {}", config.archive, why),
+                };
+
+                let body = format!("version {} of {}\n", config.version, config.archive);
+                notify(notifications, &config.email, &Notification { subject, body, summary: None });
+            }
+
+            if let Err(why) = published {
+                error!("failed to publish {}: {}", config.archive, why);
+                exit(1);
+            }
+        },
+        Action::Remove(packages, version) => {
+            exit_on_error(Repo::prepare(config, Packages::Select(&packages, false))
+                .and_then(|repo| repo.remove(version))
+                .and_then(Repo::generate));
+        },
+        Action::Resign => {
+            if let Err(why) = repo::resign(&config) {
+                error!("failed to re-sign {}: {}", config.archive, why);
+                exit(1);
+            }
+        }
+        Action::SnapshotCreate(name) => {
+            let created = action::perform(format_args!("create the {} snapshot of {}", name, config.archive), || {
+                repo::create_snapshot(Path::new("."), config.repo_path(), &config.archive, name)
+            });
+
+            if let Err(why) = created {
+                error!("failed to create snapshot: {}", why);
+                exit(1);
+            }
+        },
+        Action::SnapshotPublish(name) => {
+            let published = action::perform(format_args!("publish the {} snapshot of {}", name, config.archive), || {
+                repo::publish_snapshot(Path::new("."), config.repo_path(), &config.archive, name)
+            });
+
+            if let Err(why) = published {
+                error!("failed to publish snapshot: {}", why);
+                exit(1);
+            }
+        },
+        Action::Archive(_) | Action::Benchmark(_) | Action::Check(_) | Action::Copy(..) | Action::Diff(..) | Action::Export(_) | Action::Incoming(..)
+            | Action::Info(_) | Action::Init(..) | Action::KeyExport(_) | Action::List(_) | Action::PlanApply(_)
+            | Action::PlanCreate(..) | Action::Search(..)
+            | Action::QueueApprove(_) | Action::QueueList | Action::QueueReject(_) | Action::Serve(..) | Action::SnapshotList
+            | Action::SnapshotServe(..) | Action::Watch(_) => {
+            unreachable!()
+        }
+        Action::TestApt(ref packages) => match repo::test_apt(&config, packages) {
+            Ok(steps) => {
+                for step in &steps {
+                    println!("{}: {}", config.archive, step);
+                }
+
+                if steps.iter().any(|step| step.error.is_some()) {
+                    exit(1);
+                }
+
+                info!("{} installs with apt", config.archive);
+            }
+            Err(why) => {
+                error!("failed to test {} with apt: {}", config.archive, why);
+                exit(1);
+            }
         },
         Action::Update(key, value) => match config.update(key, value.to_owned()) {
+            Ok(()) if action::is_dry_run() => {
+                action::report(format_args!("write the config changes to {}", config.path.display()))
+            }
             Ok(()) => match config.write_to_disk() {
                 Ok(()) => info!("successfully wrote config changes to disk"),
                 Err(why) => {
@@ -274,10 +1379,75 @@ fn apply_config(mut config: Config, matches: &ArgMatches) {
             }
         },
         Action::UpdateRepository => {
-            Repo::prepare(config, Packages::All)
-                .download()
-                .build()
-                .generate();
+            exit_on_error(Repo::prepare(config, Packages::All)
+                .and_then(Repo::download)
+                .and_then(Repo::build)
+                .and_then(Repo::generate));
+        }
+        Action::Verify(keyring) => {
+            let suites = Some(config.archive.clone()).into_iter()
+                .chain(config.debug_suite().map(|debug| debug.archive));
+
+            let store = match repo::Store::open(Path::new(repo::STORE)) {
+                Ok(store) => store,
+                Err(why) => {
+                    error!("failed to open the build state: {}", why);
+                    exit(1);
+                }
+            };
+
+            let mut drifted = false;
+            for suite in suites {
+                match repo::verify(config.repo_path(), &suite, &store) {
+                    Ok(drift) => {
+                        for drift in &drift {
+                            println!("{}: {}", suite, drift);
+                        }
+
+                        drifted |= !drift.is_empty();
+                    }
+                    Err(why) => {
+                        error!("failed to verify {}: {}", suite, why);
+                        exit(1);
+                    }
+                }
+
+                if let Some(keyring) = keyring {
+                    match repo::detect_tampering(config.repo_path(), &suite, Path::new(keyring)) {
+                        Ok(tampered) => {
+                            for path in &tampered {
+                                println!("{}: {} has changed since it was last published", suite, path.display());
+                            }
+
+                            drifted |= !tampered.is_empty();
+                        }
+                        Err(why) => {
+                            error!("failed to check the repo state of {}: {}", suite, why);
+                            exit(1);
+                        }
+                    }
+                }
+            }
+
+            match repo::verify_dedup_integrity(&config.pool_root()) {
+                Ok(flagged) => {
+                    for path in &flagged {
+                        println!("{} is linked inconsistently within the pool", path.display());
+                    }
+
+                    drifted |= !flagged.is_empty();
+                }
+                Err(why) => {
+                    error!("failed to check the hard links of the pool: {}", why);
+                    exit(1);
+                }
+            }
+
+            if drifted {
+                exit(1);
+            }
+
+            info!("{} matches its indices", config.archive);
         }
     }
 }
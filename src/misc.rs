@@ -1,8 +1,11 @@
+use std::collections::HashSet;
 use std::ffi::CString;
 use std::fs::{self, File};
 use std::io::{self, Error, ErrorKind, Read, Write};
 use std::os::unix::ffi::OsStringExt;
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use action;
 use debian::DEB_SOURCE_EXTENSIONS;
 
 use libc;
@@ -35,7 +38,8 @@ pub fn remove_empty_directories_from(directory: &Path) -> io::Result<bool> {
         ))?;
 
         let path = entry.path();
-        if path.is_dir() {
+        // Symlinks are not followed, as a link to an ancestor would recurse forever.
+        if entry.file_type().map(|x| x.is_dir()).unwrap_or(false) {
             if !remove_empty_directories_from(&path)? {
                 empty = false;
             }
@@ -46,7 +50,7 @@ pub fn remove_empty_directories_from(directory: &Path) -> io::Result<bool> {
 
     if empty {
         info!("removing {} because it is empty", directory.display());
-        fs::remove_dir(directory).map_err(|why| Error::new(
+        action::remove_dir(directory).map_err(|why| Error::new(
             ErrorKind::Other,
             format!("unable to remove entry at {:?}: {}", directory, why)
         ))?;
@@ -57,7 +61,7 @@ pub fn remove_empty_directories_from(directory: &Path) -> io::Result<bool> {
 
 pub fn is_deb(entry: &DirEntry, flags: u8) -> bool {
     entry.file_name().to_str().map_or(false, |e| {
-        e.ends_with(".deb") || {
+        e.ends_with(".deb") || e.ends_with(".udeb") || {
             if flags & INCLUDE_DDEB != 0 { e.ends_with(".ddeb") } else { false }
         } || {
             if flags & INCLUDE_SRCS != 0 {
@@ -69,15 +73,109 @@ pub fn is_deb(entry: &DirEntry, flags: u8) -> bool {
     })
 }
 
+/// Walks the debian archives within a path, following symlinks.
+///
+/// Symlinks which loop back to an ancestor directory are reported and skipped, rather than
+/// being followed endlessly. Directories and archives which are reachable through several links
+/// are only walked once, so that an archive will not be indexed twice.
 pub fn walk_debs(path: &Path, ddeb: bool) -> Box<Iterator<Item = DirEntry>> {
+    let mut visited = HashSet::new();
     Box::new(
         WalkDir::new(path)
+            .follow_links(true)
             .into_iter()
-            .filter_entry(move |e| if e.path().is_dir() { true } else { is_deb(e, ddeb as u8) })
-            .flat_map(|e| e.ok())
+            .filter_entry(move |e| {
+                if !e.path().is_dir() && !is_deb(e, ddeb as u8) {
+                    return false;
+                }
+
+                // A link shares the device and inode of its target.
+                e.metadata().ok().map_or(true, |meta| visited.insert((meta.dev(), meta.ino())))
+            })
+            .filter_map(|e| match e {
+                Ok(e) => Some(e),
+                Err(why) => {
+                    warn!("skipping entry while walking archives: {}", why);
+                    None
+                }
+            })
     )
 }
 
+/// Locates symlinks within the repo which are dangling, or which form a cycle.
+pub fn check_symlinks(repo: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut flagged = Vec::new();
+
+    for entry in WalkDir::new(repo) {
+        let entry = entry.map_err(|why| Error::new(
+            ErrorKind::Other,
+            format!("failed to walk {}: {}", repo.display(), why)
+        ))?;
+
+        if !entry.file_type().is_symlink() {
+            continue
+        }
+
+        let path = entry.path();
+        let cyclic = match fs::canonicalize(path) {
+            // A link to one of its own ancestors would be walked endlessly when followed.
+            Ok(target) => path.parent()
+                .and_then(|parent| fs::canonicalize(parent).ok())
+                .map_or(false, |parent| parent.starts_with(&target)),
+            // The target is missing, or resolving it loops (ELOOP).
+            Err(_) => true
+        };
+
+        if cyclic {
+            warn!("{} is a dangling or cyclic symlink", path.display());
+            flagged.push(path.to_path_buf());
+        }
+    }
+
+    flagged.sort();
+    Ok(flagged)
+}
+
+/// Removes each symlink within the directory whose target no longer exists, or cannot be
+/// resolved, returning the links which were removed.
+pub fn remove_dangling_symlinks(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+
+    for entry in WalkDir::new(directory) {
+        let entry = entry.map_err(|why| Error::new(
+            ErrorKind::Other,
+            format!("failed to walk {}: {}", directory.display(), why)
+        ))?;
+
+        let path = entry.path();
+        if entry.file_type().is_symlink() && fs::metadata(path).is_err() {
+            info!("removing {}, as its target no longer exists", path.display());
+            action::remove_file(path)?;
+            removed.push(path.to_path_buf());
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Sweeps the directory of the dangling symlinks, and then of the empty directories, which are
+/// left behind once packages have been removed. The directory itself is kept.
+pub fn sweep(directory: &Path) -> io::Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+
+    remove_dangling_symlinks(directory)?;
+    for entry in directory.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_empty_directories_from(&entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn match_deb(entry: &DirEntry, packages: &[String]) -> Option<(String, usize)> {
     let path = entry.path();
     if path.is_dir() {
@@ -85,22 +183,25 @@ pub fn match_deb(entry: &DirEntry, packages: &[String]) -> Option<(String, usize
     }
 
     entry.file_name().to_str().and_then(|package| {
-        let package = &package[..package.find('_').expect("debian package lacks _ character")];
+        let package = &package[..package.find('_')?];
 
         packages.iter().position(|x| x.as_str() == package)
             .and_then(|pos| path.to_str().map(|path| (path.to_owned(), pos)))
     })
 }
 
-pub fn copy_here<S>(source: S) -> io::Result<()>
+/// Copies each file within the source directory into the destination directory.
+pub fn copy_files<S, D>(source: S, destination: D) -> io::Result<()>
     where S: AsRef<Path>,
+          D: AsRef<Path>,
 {
     for entry in source.as_ref().read_dir()? {
         let entry = entry?;
         if entry.path().is_file() {
             let source = &entry.path();
-            if let Some(dest) = source.file_name() {
-                eprintln!("copying {:?} to {:?}", source, dest);
+            if let Some(filename) = source.file_name() {
+                let dest = destination.as_ref().join(filename);
+                debug!("copying {:?} to {:?}", source, dest);
                 io::copy(&mut File::open(source)?, &mut File::create(dest)?)?;
             }
         }
@@ -109,10 +210,55 @@ pub fn copy_here<S>(source: S) -> io::Result<()>
     Ok(())
 }
 
-pub fn unlink(link: &Path) -> io::Result<()> {
-    CString::new(link.to_path_buf().into_os_string().into_vec())
+/// Derives the pool bucket of a package, which is the first character of its name.
+///
+/// As with Debian's archive, libraries are bucketed by their first four characters instead, so
+/// `libssl1.1` is stored under `libs`. A malformed filename may yield an empty package name,
+/// which has no bucket.
+pub fn pool_bucket(package: &str) -> io::Result<&str> {
+    if package.starts_with("lib") && package.len() > 3 && package.is_char_boundary(4) {
+        return Ok(&package[..4]);
+    }
+
+    package.chars().next()
+        .map(|c| &package[..c.len_utf8()])
+        .ok_or_else(|| Error::new(
+            ErrorKind::InvalidInput,
+            "unable to derive a pool bucket from an empty package name"
+        ))
+}
+
+/// Atomically exchanges two paths, so that each is found at the other's place, and neither is
+/// ever missing. Filesystems which do not support this fail with `EINVAL`.
+pub fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    // From linux/fs.h, as the libc crate does not yet define it.
+    const RENAME_EXCHANGE: libc::c_uint = 1 << 1;
+
+    let path = |path: &Path| CString::new(path.to_path_buf().into_os_string().into_vec())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, format!("{}", why)));
+    let (a, b) = (path(a)?, path(b)?);
+
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            RENAME_EXCHANGE
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error())
+    }
+}
+
+pub fn chown(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    CString::new(path.to_path_buf().into_os_string().into_vec())
         .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, format!("{}", why)))
-        .and_then(|link| match unsafe { libc::unlink(link.as_ptr()) } {
+        .and_then(|path| match unsafe { libc::chown(path.as_ptr(), uid, gid) } {
             0 => Ok(()),
             _ => Err(io::Error::last_os_error())
         })
@@ -155,3 +301,100 @@ pub fn copy<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> io::Result<()> {
     io::copy(&mut File::open(src)?, &mut File::create(dst)?)?;
     Ok(())
 }
+
+/// The MIME type that the file is served with, by its extension, or its name for the indices
+/// which have none.
+pub fn content_type(path: &Path) -> &'static str {
+    let filename = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+    let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+    match extension {
+        "deb" | "ddeb" | "udeb" => "application/vnd.debian.binary-package",
+        "gz" => "application/gzip",
+        "xz" => "application/x-xz",
+        "bz2" => "application/x-bzip2",
+        "zst" => "application/zstd",
+        "gpg" => "application/pgp-signature",
+        "dsc" => "text/prs.lines.tag",
+        "changes" | "buildinfo" => "text/plain",
+        "" if ["Release", "InRelease", "Packages", "Sources", "Index"].contains(&filename) => "text/plain",
+        "" if filename.starts_with("Contents-") || filename.starts_with("Translation-") => "text/plain",
+        _ => "application/octet-stream"
+    }
+}
+
+/// Encodes bytes as standard, padded base64, as the credentials of basic authentication, and the
+/// `Content-MD5` of uploads, are.
+pub fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+    for chunk in input.chunks(3) {
+        let byte = |index: usize| u32::from(chunk.get(index).cloned().unwrap_or(0));
+        let triple = byte(0) << 16 | byte(1) << 8 | byte(2);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                output.push(ALPHABET[(triple >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_package_bucket() {
+        assert_eq!(pool_bucket("foo").unwrap(), "f");
+        assert_eq!(pool_bucket("libssl1.1").unwrap(), "libs");
+        assert_eq!(pool_bucket("lib").unwrap(), "l");
+        assert!(pool_bucket("").is_err());
+    }
+
+    #[test]
+    fn symlink_cycles() {
+        use std::os::unix::fs::symlink;
+        use tempfile::tempdir;
+
+        let dir = tempdir().unwrap();
+        let dists = dir.path().join("dists");
+        let pool = dir.path().join("pool/main/f/foo");
+        fs::create_dir_all(&dists).unwrap();
+        fs::create_dir_all(&pool).unwrap();
+        write(pool.join("foo_1.0_amd64.deb"), b"").unwrap();
+
+        symlink(dir.path(), dists.join("loop")).unwrap();
+        symlink(dists.join("missing"), dists.join("dangling")).unwrap();
+        symlink(dists.join("self"), dists.join("self")).unwrap();
+        symlink(&pool, dists.join("foo")).unwrap();
+
+        assert_eq!(
+            check_symlinks(dir.path()).unwrap(),
+            vec![dists.join("dangling"), dists.join("loop"), dists.join("self")]
+        );
+
+        assert_eq!(walk_debs(&dir.path().join("pool"), false).filter(|e| e.file_type().is_file()).count(), 1);
+        // The package is also reachable through the `dists/foo` link, but is only walked once.
+        assert_eq!(walk_debs(dir.path(), false).filter(|e| e.file_type().is_file()).count(), 1);
+
+        // Once the package is removed, its links and directories are swept from the repo.
+        fs::create_dir_all(dir.path().join("pool/main/b/bar")).unwrap();
+        fs::remove_file(pool.join("foo_1.0_amd64.deb")).unwrap();
+        symlink(pool.join("foo_1.0_amd64.deb"), pool.join("foo.deb")).unwrap();
+        for directory in &[&dir.path().join("pool"), &dists] {
+            sweep(directory).unwrap();
+        }
+
+        for link in &["dangling", "foo", "self"] {
+            assert!(dists.join(link).symlink_metadata().is_err());
+        }
+
+        assert!(dists.join("loop").symlink_metadata().is_ok());
+        assert!(!dir.path().join("pool/main").exists());
+        assert!(dir.path().join("pool").is_dir());
+    }
+}
@@ -0,0 +1,262 @@
+use action;
+use chrono::Utc;
+use config::{EmailNotify, Notify};
+use logging::json_escape;
+use reqwest::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+/// What happened to a suite, such as a failed build, along with the summary of its run.
+pub struct Notification {
+    pub subject: String,
+    /// The plain text of the notification, such as a table of the builds of the run.
+    pub body: String,
+    /// The summary of the run as a JSON object, which is attached to each notification.
+    pub summary: Option<String>,
+}
+
+/// Sends the notification to each webhook and email address of the config. A notification
+/// which fails to send is only warned of, so that it never fails the run that it is about.
+pub fn notify(notify: &Notify, sender: &str, notification: &Notification) {
+    for url in &notify.webhooks {
+        let subject = &notification.subject;
+        let posted = action::perform(format_args!("notify {} of {}", url, subject), || {
+            post_webhook(url, notification)
+        });
+
+        if let Err(why) = posted {
+            warn!("failed to notify {}: {}", url, why);
+        }
+    }
+
+    if let Some(ref email) = notify.email {
+        let from = email.from.as_ref().map_or(sender, |x| x.as_str());
+        let to = email.to.join(", ");
+        let mailed = action::perform(format_args!("mail {} of {}", to, notification.subject), || {
+            mail(email, from, notification)
+        });
+
+        if let Err(why) = mailed {
+            warn!("failed to mail {} through {}: {}", to, email.server(), why);
+        }
+    }
+}
+
+fn post_webhook(url: &str, notification: &Notification) -> io::Result<()> {
+    Client::new()
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(webhook_payload(notification))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map(|_| ())
+        .map_err(|why| io::Error::new(io::ErrorKind::Other, format!("{}", why)))
+}
+
+/// A payload which Slack and Mattermost display as the subject, followed by the body as a code
+/// block, with the summary beside it for any other service which reads the payload.
+fn webhook_payload(notification: &Notification) -> String {
+    let text = format!("*{}*\n```\n{}```", notification.subject, notification.body);
+    let text = json_escape(&text);
+    match notification.summary {
+        Some(ref summary) => format!("{{\"text\":\"{}\",\"summary\":{}}}", text, summary),
+        None => format!("{{\"text\":\"{}\"}}", text),
+    }
+}
+
+/// Mails the notification through the SMTP relay of the config, with the summary attached as
+/// `summary.json`.
+fn mail(email: &EmailNotify, from: &str, notification: &Notification) -> io::Result<()> {
+    let stream = TcpStream::connect(email.server())?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+
+    reply(&mut reader)?;
+    command(&mut stream, &mut reader, "EHLO localhost")?;
+    command(&mut stream, &mut reader, &format!("MAIL FROM:<{}>", from))?;
+    for to in &email.to {
+        command(&mut stream, &mut reader, &format!("RCPT TO:<{}>", to))?;
+    }
+
+    command(&mut stream, &mut reader, "DATA")?;
+    stream.write_all(message(&email.to, from, notification).as_bytes())?;
+    command(&mut stream, &mut reader, ".")?;
+    command(&mut stream, &mut reader, "QUIT")
+}
+
+fn command<W: Write, R: BufRead>(stream: &mut W, reader: &mut R, line: &str) -> io::Result<()> {
+    write!(stream, "{}\r\n", line)?;
+    stream.flush()?;
+    reply(reader)
+}
+
+/// Reads each line of a reply, which fails unless its code is of success or of an
+/// intermediate step, such as the `354` which follows `DATA`.
+fn reply<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    let line = &mut String::new();
+    loop {
+        line.clear();
+        if reader.read_line(line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the SMTP server closed the connection"
+            ));
+        }
+
+        // Each line but the last of a reply has a `-` after its code.
+        if line.len() > 3 && line.as_bytes()[3] == b'-' {
+            continue
+        }
+
+        return match line.as_bytes().first() {
+            Some(&b'2') | Some(&b'3') => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("the SMTP server replied: {}", line.trim())
+            ))
+        };
+    }
+}
+
+/// A header value with each line break replaced by a space, so that a value can't end its
+/// header, and inject headers of its own.
+fn header(value: &str) -> String {
+    value.replace(|c: char| c == '\r' || c == '\n', " ")
+}
+
+/// The headers and body of the mail, where the lines of the body are dot-stuffed, and end with
+/// a CRLF, as the `DATA` of SMTP requires.
+fn message(to: &[String], from: &str, notification: &Notification) -> String {
+    const BOUNDARY: &str = "debrep-summary";
+
+    let mut message = format!(
+        "From: <{}>\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n",
+        header(from),
+        to.iter().map(|to| format!("<{}>", header(to))).collect::<Vec<String>>().join(", "),
+        header(&notification.subject),
+        Utc::now().to_rfc2822()
+    );
+
+    let body = match notification.summary {
+        Some(ref summary) => {
+            message.push_str(&format!(
+                "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+                BOUNDARY
+            ));
+
+            format!(
+                "--{0}\nContent-Type: text/plain; charset=utf-8\n\n{1}\n--{0}\n\
+                 Content-Type: application/json\n\
+                 Content-Disposition: attachment; filename=\"summary.json\"\n\n{2}\n--{0}--\n",
+                BOUNDARY,
+                notification.body,
+                summary
+            )
+        }
+        None => {
+            message.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+            notification.body.clone()
+        }
+    };
+
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn mail_through_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let email = EmailNotify {
+            to: vec!["ops@example.com".into()],
+            from: None,
+            server: Some(listener.local_addr().unwrap().to_string()),
+        };
+
+        // Replies to each command as a relay would, and returns what was sent to it.
+        let relay = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let mut received = String::new();
+            stream.write_all(b"220 relay ready\r\n").unwrap();
+
+            let line = &mut String::new();
+            let mut data = false;
+            loop {
+                line.clear();
+                reader.read_line(line).unwrap();
+                received.push_str(line);
+                let reply: &[u8] = if data {
+                    if line != ".\r\n" { continue }
+                    data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-relay\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("DATA") {
+                    data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    return received;
+                } else {
+                    b"250 ok\r\n"
+                };
+
+                stream.write_all(reply).unwrap();
+            }
+        });
+
+        let notification = Notification {
+            subject: "bionic: 1 of 2 sources failed to build".into(),
+            body: "foo built\n.bar failed\n".into(),
+            summary: Some("{\"suite\":\"bionic\"}".into()),
+        };
+
+        mail(&email, "repo@example.com", &notification).unwrap();
+        let received = relay.join().unwrap();
+        assert!(received.starts_with(concat!(
+            "EHLO localhost\r\n",
+            "MAIL FROM:<repo@example.com>\r\n",
+            "RCPT TO:<ops@example.com>\r\n",
+            "DATA\r\n",
+        )));
+        assert!(received.contains("Subject: bionic: 1 of 2 sources failed to build\r\n"));
+        assert!(received.contains("\r\nfoo built\r\n..bar failed\r\n"));
+        assert!(received.contains(
+            "filename=\"summary.json\"\r\n\r\n{\"suite\":\"bionic\"}\r\n"
+        ));
+        assert!(received.ends_with("--debrep-summary--\r\n.\r\nQUIT\r\n"));
+
+        assert_eq!(
+            webhook_payload(&notification),
+            concat!(
+                "{\"text\":\"*bionic: 1 of 2 sources failed to build*\\n",
+                "```\\nfoo built\\n.bar failed\\n```\",",
+                "\"summary\":{\"suite\":\"bionic\"}}",
+            )
+        );
+
+        let injected = Notification {
+            subject: "bionic\r\nBcc: attacker@example.com".into(),
+            .. notification
+        };
+        let headers = message(&email.to, "repo@example.com", &injected);
+        assert!(headers.contains("Subject: bionic  Bcc: attacker@example.com\r\n"));
+        assert!(!headers.contains("\r\nBcc:"));
+    }
+}
@@ -0,0 +1,158 @@
+use libc;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// The least time between two redraws of a progress bar.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const BAR_WIDTH: usize = 30;
+
+/// Disables progress bars for the rest of the run, such as when the logs are machine-readable.
+pub fn disable() {
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// What a progress bar counts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    Bytes,
+    Items,
+}
+
+struct State {
+    current: u64,
+    total: Option<u64>,
+    drawn: Option<Instant>,
+}
+
+/// A progress bar which is drawn on stderr, if stderr is a terminal.
+///
+/// Progress may be reported from several threads at once, and the bar is redrawn at most every
+/// 100 milliseconds. When the total is unknown, only the current amount is drawn.
+pub struct Progress {
+    label: String,
+    unit: Unit,
+    enabled: bool,
+    state: Mutex<State>,
+}
+
+impl Progress {
+    pub fn new<S: Into<String>>(label: S, total: Option<u64>, unit: Unit) -> Progress {
+        let enabled = !DISABLED.load(Ordering::SeqCst)
+            && unsafe { libc::isatty(libc::STDERR_FILENO) == 1 };
+
+        Progress {
+            label: label.into(),
+            unit,
+            enabled,
+            state: Mutex::new(State { current: 0, total, drawn: None }),
+        }
+    }
+
+    /// Whether the bar is drawn, so that callers may log their progress instead when it is not.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_total(&self, total: u64) {
+        self.update(|state| state.total = Some(total));
+    }
+
+    /// Increases the total, for work which is discovered as it is done.
+    pub fn add_total(&self, amount: u64) {
+        self.update(|state| state.total = Some(state.total.unwrap_or(0) + amount));
+    }
+
+    pub fn set(&self, current: u64) {
+        self.update(|state| state.current = current);
+    }
+
+    pub fn inc(&self, amount: u64) {
+        self.update(|state| state.current += amount);
+    }
+
+    /// Draws the final state of the bar, and moves past it.
+    pub fn finish(&self) {
+        if !self.enabled {
+            return
+        }
+
+        let state = self.state.lock().unwrap();
+        let line = render(&self.label, state.current, state.total, self.unit);
+        let _ = writeln!(io::stderr(), "\r{}\x1b[K", line);
+    }
+
+    fn update<F: FnOnce(&mut State)>(&self, update: F) {
+        if !self.enabled {
+            return
+        }
+
+        let mut state = self.state.lock().unwrap();
+        update(&mut state);
+
+        let now = Instant::now();
+        if state.drawn.map_or(false, |drawn| now.duration_since(drawn) < REDRAW_INTERVAL) {
+            return
+        }
+
+        state.drawn = Some(now);
+        let line = render(&self.label, state.current, state.total, self.unit);
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+}
+
+fn render(label: &str, current: u64, total: Option<u64>, unit: Unit) -> String {
+    match total {
+        Some(total) if total > 0 => {
+            let current = current.min(total);
+            let filled = (current * BAR_WIDTH as u64 / total) as usize;
+            format!(
+                "{} [{}{}] {:>3}% {}/{}",
+                label,
+                "=".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                current * 100 / total,
+                amount(current, unit),
+                amount(total, unit)
+            )
+        }
+        _ => format!("{} {}", label, amount(current, unit)),
+    }
+}
+
+fn amount(amount: u64, unit: Unit) -> String {
+    match unit {
+        Unit::Bytes if amount >= 1024 * 1024 => {
+            format!("{:.1} MiB", amount as f64 / (1024.0 * 1024.0))
+        }
+        Unit::Bytes if amount >= 1024 => format!("{:.1} KiB", amount as f64 / 1024.0),
+        Unit::Bytes => format!("{} B", amount),
+        Unit::Items => amount.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_rendering() {
+        assert_eq!(
+            render("building", 1, Some(4), Unit::Items),
+            ["building [", &"=".repeat(7), &" ".repeat(23), "]  25% 1/4"].concat()
+        );
+
+        assert_eq!(
+            render("foo", 3 * 1024 * 1024, Some(6 * 1024 * 1024), Unit::Bytes),
+            ["foo [", &"=".repeat(15), &" ".repeat(15), "]  50% 3.0 MiB/6.0 MiB"].concat()
+        );
+
+        assert_eq!(render("hashing", 512, None, Unit::Bytes), "hashing 512 B");
+        assert_eq!(render("hashing", 2048, Some(0), Unit::Bytes), "hashing 2.0 KiB");
+    }
+}
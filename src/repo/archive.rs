@@ -0,0 +1,333 @@
+use action;
+use chrono::Utc;
+use config::{Config, OldReleases};
+use debian::parse_control;
+use misc;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::generate::{release_date, swap_dists};
+use super::publish::{self, rsync};
+use super::query::published;
+use super::sign::Signer;
+use super::snapshot::link_tree;
+use throttle::Bandwidth;
+
+/// The directories of the repo which hold the files of each suite, beside its pool.
+const SUITE_DIRECTORIES: &[&str] = &["dists", "deltas", "changelogs"];
+
+/// How the `Description` of a tombstone `Release` ends, once it names the suite.
+const TOMBSTONE: &str = "has reached its end of life";
+
+/// Retires a suite which has reached its end of life, along with its debug suite, moving its
+/// dists, and the files of the pool that they list, into the root of the old releases, or
+/// pushing them to its rsync target. The dists of the suite are replaced by a tombstone
+/// `Release` which points users at the archive, and is published to the publish targets of the
+/// suite, if it has any. The config of the suite is moved into `suites/old`, so that it is no
+/// longer built, returning where it was moved to.
+///
+/// A run which fails before the tombstones are signed leaves nothing archived, and a run which
+/// fails after, such as to remove the retired files or to publish the tombstone, is resumed by
+/// archiving the suite again.
+pub fn archive_suite(config: &Config) -> io::Result<PathBuf> {
+    if config.flat {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is a flat repository, which has no suite to archive", config.archive)
+        ));
+    }
+
+    if config.shared {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is one of the suites of {}, so give it a config of its own before archiving it",
+                config.archive,
+                config.path.display()
+            )
+        ));
+    }
+
+    let repo = config.repo_path();
+    let old_releases = config.old_releases.clone().unwrap_or_default();
+    let old = old_releases.path();
+
+    let mut suites = vec![config.clone()];
+    suites.extend(config.debug_suite().filter(|debug| repo.join("dists").join(&debug.archive).exists()));
+
+    let resumed = suites.iter().all(|suite| is_tombstone(repo, &suite.archive));
+    for suite in &suites {
+        if resumed {
+            break
+        }
+
+        if !repo.join("dists").join(&suite.archive).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no dists to archive", suite.archive)
+            ));
+        }
+
+        if old.join("dists").join(&suite.archive).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} is already archived in {}", suite.archive, old.display())
+            ));
+        }
+    }
+
+    let names = suites.iter().map(|suite| suite.archive.clone()).collect::<Vec<String>>();
+    let location = old_releases.location();
+    let archived = config.path.parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("old")
+        .join(config.path.file_name().unwrap_or_default());
+
+    if action::is_dry_run() {
+        action::report(format_args!("archive {} to {}", names.join(", "), location));
+        action::report(format_args!("move {} to {}", config.path.display(), archived.display()));
+        return Ok(archived);
+    }
+
+    if resumed {
+        info!("{} was already replaced by its tombstone, so its archive is resumed", names.join(", "));
+    } else {
+        retire(config, &suites, &old_releases)?;
+    }
+
+    bury(repo, &names, &old_releases)?;
+
+    if config.s3.is_some() || config.rsync.as_ref().map_or(false, |targets| !targets.is_empty()) {
+        info!("publishing the tombstone of {}", config.archive);
+        publish::publish(config)?;
+    }
+
+    fs::create_dir_all(archived.parent().unwrap())?;
+    fs::rename(&config.path, &archived)?;
+    Ok(archived)
+}
+
+/// Archives the suites, and stages a signed tombstone beside the dists of each. Until every suite
+/// is archived, and every tombstone is signed, a failure removes what was archived again, so that
+/// the archive may be retried.
+fn retire(config: &Config, suites: &[Config], old_releases: &OldReleases) -> io::Result<()> {
+    let repo = config.repo_path();
+    let old = old_releases.path();
+
+    if let Err(why) = archive_and_sign(config, suites, old_releases) {
+        for suite in suites {
+            let suite = &suite.archive;
+            if let Err(why) = remove_suite(old, suite).and_then(|_| remove_tree(&staged(repo, suite))) {
+                error!("failed to remove the partial archive of {}: {}", suite, why);
+            }
+        }
+
+        return Err(why);
+    }
+
+    Ok(())
+}
+
+/// Moves the staged tombstone of each suite into the place of its dists, and then removes every
+/// other file of the suite from the repo. As each tombstone is in place before anything is
+/// removed, a run which fails here is resumed, rather than finding a suite with no dists.
+fn bury(repo: &Path, names: &[String], old_releases: &OldReleases) -> io::Result<()> {
+    for suite in names {
+        let tombstone = staged(repo, suite);
+        if tombstone.exists() {
+            swap_dists(&repo.join("dists").join(suite), &tombstone)?;
+        }
+    }
+
+    for suite in names {
+        remove_retired(repo, suite)?;
+    }
+
+    if old_releases.rsync.is_some() {
+        for suite in names {
+            remove_suite(old_releases.path(), suite)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the tombstone of a suite is signed, before it replaces the dists of the suite.
+fn staged(repo: &Path, suite: &str) -> PathBuf {
+    repo.join("dists").join([suite, ".tombstone"].concat())
+}
+
+/// Links the suites into the root of the old releases, pushing them to its rsync target if it
+/// has one, and signs the tombstone of each suite within its staged dist.
+fn archive_and_sign(config: &Config, suites: &[Config], old_releases: &OldReleases) -> io::Result<()> {
+    let (repo, old, location) = (config.repo_path(), old_releases.path(), old_releases.location());
+    let names = suites.iter().map(|suite| suite.archive.clone()).collect::<Vec<String>>();
+    for suite in &names {
+        info!("archiving {} to {}", suite, old.display());
+        archive_files(repo, old, suite)?;
+    }
+
+    if let Some(ref target) = old_releases.rsync {
+        let bandwidth = Bandwidth::new(&config.network_limits());
+        rsync::push(old, &names, target, &config.retry_policy(), &bandwidth)?;
+    }
+
+    let date = release_date(config.source_date_epoch, env::var("SOURCE_DATE_EPOCH").ok())?
+        .unwrap_or_else(|| Utc::now().format("%a, %d %b %Y %H:%M:%S UTC").to_string());
+    for suite in suites {
+        let dist = staged(repo, &suite.archive);
+        remove_tree(&dist)?;
+        fs::create_dir_all(&dist)?;
+
+        let release = dist.join("Release");
+        misc::write(&release, tombstone(suite, &location, &date))?;
+        let signer = Signer::new(suite)?;
+        signer.in_release(&release, &dist.join("InRelease"))?;
+        signer.detached(&release, &dist.join("Release.gpg"))?;
+    }
+
+    Ok(())
+}
+
+/// Whether the dists of the suite were already replaced by its tombstone, or its tombstone was
+/// signed, and is staged to replace them.
+fn is_tombstone(repo: &Path, suite: &str) -> bool {
+    let is_release = |dist: &Path| misc::read_to_string(dist.join("Release")).ok().map_or(false, |release| {
+        let release = parse_control(&release);
+        let description = release.get("Description").map_or("", |x| x.as_str());
+        description.starts_with(&[suite, " ", TOMBSTONE].concat()) && !release.contains_key("SHA256")
+    });
+
+    // The detached signature of a staged tombstone is written last.
+    let tombstone = staged(repo, suite);
+    is_release(&repo.join("dists").join(suite)) || (tombstone.join("Release.gpg").exists() && is_release(&tombstone))
+}
+
+fn remove_tree(path: &Path) -> io::Result<()> {
+    if path.exists() { fs::remove_dir_all(path) } else { Ok(()) }
+}
+
+/// Links the dists of the suite, and the files of the pool which they list, into the root of the
+/// old releases at the same paths, before the suite is removed from the repo. The files of the
+/// pool which the dists no longer list are not archived.
+fn archive_files(repo: &Path, old: &Path, suite: &str) -> io::Result<()> {
+    let files = published(repo, suite)?.into_iter()
+        .flat_map(|package| package.files.into_iter().map(|(path, _, _)| path))
+        .collect::<BTreeSet<String>>();
+
+    for file in &files {
+        let (source, destination) = (repo.join(file), old.join(file));
+        if !source.exists() {
+            warn!("{} is listed by {}, but is not in the pool", file, suite);
+            continue
+        }
+
+        fs::create_dir_all(destination.parent().unwrap())?;
+        if fs::hard_link(&source, &destination).is_err() {
+            misc::copy(&source, &destination)?;
+        }
+    }
+
+    for directory in SUITE_DIRECTORIES {
+        let source = repo.join(directory).join(suite);
+        if source.exists() {
+            link_tree(&source, &old.join(directory).join(suite))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the dists, pool, and other files of the suite from a repo.
+fn remove_suite(repo: &Path, suite: &str) -> io::Result<()> {
+    remove_tree(&repo.join("dists").join(suite))?;
+    remove_retired(repo, suite)
+}
+
+/// Removes every file of the suite from a repo but its dists, including the previous and staged
+/// dists which are kept beside them, and would otherwise still be served.
+fn remove_retired(repo: &Path, suite: &str) -> io::Result<()> {
+    for directory in SUITE_DIRECTORIES.iter().filter(|&&directory| directory != "dists").chain(&["pool"]) {
+        remove_tree(&repo.join(directory).join(suite))?;
+    }
+
+    for dist in &[".old", ".new", ".tombstone"] {
+        remove_tree(&repo.join("dists").join([suite, dist].concat()))?;
+    }
+
+    Ok(())
+}
+
+/// The `Release` which replaces the dists of an archived suite. As it lists no indices, apt
+/// finds no packages in the suite, rather than the packages which it once had.
+fn tombstone(config: &Config, location: &str, date: &str) -> String {
+    format!(
+        "Origin: {}\nLabel: {}\nSuite: {}\nCodename: {}\nDate: {}\n\
+         Description: {} {}, and is archived at {}\n",
+        config.origin,
+        config.label,
+        config.release_suite(),
+        config.release_codename(),
+        date,
+        config.archive,
+        TOMBSTONE,
+        location
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn archived_files() {
+        let dir = tempdir().unwrap();
+        let (repo, old) = (dir.path().join("repo"), dir.path().join("old"));
+        let binary = repo.join("dists/bionic/main/binary-amd64");
+        let pool = repo.join("pool/bionic/main/binary-amd64/f/foo");
+        fs::create_dir_all(&binary).unwrap();
+        fs::create_dir_all(&pool).unwrap();
+
+        misc::write(
+            binary.join("Packages"),
+            b"Package: foo\nVersion: 1.1\nArchitecture: amd64\n\
+              Filename: pool/bionic/main/binary-amd64/f/foo/foo_1.1_amd64.deb\nSize: 3\nSHA256: 0\n"
+        ).unwrap();
+
+        misc::write(pool.join("foo_1.1_amd64.deb"), b"foo").unwrap();
+        misc::write(pool.join("foo_1.0_amd64.deb"), b"old").unwrap();
+        fs::create_dir_all(repo.join("changelogs/bionic")).unwrap();
+        fs::create_dir_all(repo.join("dists/bionic.old/main")).unwrap();
+
+        archive_files(&repo, &old, "bionic").unwrap();
+        assert!(old.join("dists/bionic/main/binary-amd64/Packages").exists());
+        assert!(old.join("pool/bionic/main/binary-amd64/f/foo/foo_1.1_amd64.deb").exists());
+        assert!(!old.join("pool/bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb").exists());
+        assert!(old.join("changelogs/bionic").is_dir());
+
+        remove_suite(&repo, "bionic").unwrap();
+        assert!(!repo.join("dists/bionic").exists() && !repo.join("pool/bionic").exists());
+        assert!(!repo.join("dists/bionic.old").exists());
+        assert_eq!(misc::read_to_string(old.join("pool/bionic/main/binary-amd64/f/foo/foo_1.1_amd64.deb")).unwrap(), "foo");
+
+        // A suite whose dists are its tombstone resumes its archive, rather than being archived again.
+        fs::create_dir_all(repo.join("dists/bionic")).unwrap();
+        let release = "Suite: bionic\nDescription: bionic has reached its end of life, and is archived at old\n";
+        misc::write(repo.join("dists/bionic/Release"), release).unwrap();
+        assert!(is_tombstone(&repo, "bionic"));
+        misc::write(repo.join("dists/bionic/Release"), [release, "SHA256:\n 0 0 main/binary-amd64/Packages\n"].concat()).unwrap();
+        assert!(!is_tombstone(&repo, "bionic"));
+        assert!(!is_tombstone(&repo, "cosmic"));
+
+        // A tombstone which is staged is only resumed once it has been signed.
+        fs::create_dir_all(repo.join("dists/cosmic.tombstone")).unwrap();
+        let release = "Suite: cosmic\nDescription: cosmic has reached its end of life, and is archived at old\n";
+        misc::write(repo.join("dists/cosmic.tombstone/Release"), release).unwrap();
+        assert!(!is_tombstone(&repo, "cosmic"));
+        misc::write(repo.join("dists/cosmic.tombstone/Release.gpg"), "").unwrap();
+        assert!(is_tombstone(&repo, "cosmic"));
+    }
+}
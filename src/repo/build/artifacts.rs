@@ -2,7 +2,6 @@ use std::borrow::Cow;
 use std::{fs, io};
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use misc::unlink;
 
 pub struct LinkedArtifact(PathBuf);
 
@@ -20,7 +19,7 @@ impl LinkError {
 
 impl Drop for LinkedArtifact {
     fn drop(&mut self) {
-        let _ = unlink(&self.0);
+        let _ = fs::remove_file(&self.0);
     }
 }
 
@@ -40,7 +39,7 @@ pub fn link_artifact(src: &Path, dst: &Path) -> Result<LinkedArtifact, LinkError
                 return Ok(LinkedArtifact(dst.to_owned().to_path_buf()));
             } else {
                 info!("removing link at {}", dst.display());
-                unlink(&dst).map_err(|why| LinkError::new(src, &dst, why))?;
+                fs::remove_file(&dst).map_err(|why| LinkError::new(src, &dst, why))?;
             }
         }
     }
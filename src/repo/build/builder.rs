@@ -0,0 +1,323 @@
+use config::{Builder, BuilderBackend, CompilerCache};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use super::BuildError;
+use super::cache::{PBUILDER_CONFIG, SBUILD_CONFIG};
+
+/// Everything a backend needs to know to build a source for one architecture.
+pub(crate) struct BuildRequest<'a> {
+    pub name: &'a str,
+    /// The unpacked source directory, or the `.dsc` of the source.
+    pub path: &'a Path,
+    /// Where the built packages are written to.
+    pub build_dir: &'a Path,
+    pub arch: &'a str,
+    /// Whether the architecture independent packages are built with this architecture. They are
+    /// built only once, by the first architecture, rather than by the build of every architecture.
+    pub arch_all: bool,
+    pub distribution: &'a str,
+    /// Packages from the pool which must be made available to the build.
+    pub extra_packages: &'a [String],
+    /// The local repo of this run's builds, which the source build-depends on.
+    pub local: Option<&'a Path>,
+    pub extra_repos: &'a [String],
+    pub extra_keys: &'a [PathBuf],
+    pub prebuild: &'a [String],
+    pub starting_build: &'a [String],
+    /// Environment variables of the source, which the backend is run with.
+    pub env: Option<&'a BTreeMap<String, String>>,
+    /// Flags which are given to dpkg-buildpackage by the backend.
+    pub build_flags: &'a [String],
+    /// The options of `DEB_BUILD_OPTIONS`.
+    pub build_options: &'a [String],
+    pub build_profiles: &'a [String],
+    /// The compiler cache which the build shares with the other builds of the suite.
+    pub compiler_cache: Option<&'a CompilerCache>,
+}
+
+/// A command which performs a build, as a program and its arguments.
+#[derive(Debug, PartialEq)]
+pub(crate) struct BuilderCommand {
+    pub program: &'static str,
+    pub args: Vec<String>,
+    pub cwd: PathBuf,
+    pub env: Vec<(String, String)>,
+}
+
+/// The program that a backend executes.
+pub(crate) fn program(backend: BuilderBackend, dsc: bool) -> &'static str {
+    match backend {
+        BuilderBackend::Sbuild => "sbuild",
+        BuilderBackend::Pbuilder if dsc => "pbuilder",
+        BuilderBackend::Pbuilder => "pdebuild",
+        BuilderBackend::Dpkg => "dpkg-buildpackage",
+    }
+}
+
+/// Translates a build request into the command of the builder's backend.
+pub(crate) fn command(builder: &Builder, request: &BuildRequest) -> Result<BuilderCommand, BuildError> {
+    let backend = builder.backend.unwrap_or_default();
+    let dsc = request.path.extension().map_or(false, |ext| ext == "dsc");
+    let program = program(backend, dsc);
+    let mut args = Vec::new();
+    let profiles = request.build_profiles.join(",");
+
+    // sbuild and pbuilder pass the `DEB_*` variables through to the chroot, though sbuild drops
+    // any others which its `$environment_filter` does not allow.
+    let mut env = request.env.iter()
+        .flat_map(|env| env.iter())
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect::<Vec<(String, String)>>();
+
+    if !request.build_options.is_empty() {
+        env.push(("DEB_BUILD_OPTIONS".into(), request.build_options.join(" ")));
+    }
+
+    // Warns about the options of a source which this backend is unable to honor.
+    let unsupported = |option: &str, given: bool| if given {
+        warn!("{}: {} does not support {}, which will be ignored", request.name, program, option);
+    };
+
+    match backend {
+        BuilderBackend::Sbuild => {
+            args.extend_from_slice(&[
+                "-v".into(),
+                "--log-external-command-output".into(),
+                "--log-external-command-error".into(),
+                format!("--arch={}", request.arch),
+                if request.arch_all { "--arch-all" } else { "--no-arch-all" }.into(),
+                "-d".into(),
+                request.distribution.into(),
+            ]);
+
+            if let Some(ref chroot) = builder.chroot {
+                args.push(format!("--chroot={}", chroot));
+            }
+
+            args.extend(request.extra_packages.iter().map(|deb| format!("--extra-package={}", deb)));
+            if let Some(local) = request.local {
+                args.push(format!("--extra-package={}", local.display()));
+            }
+
+            args.extend(request.extra_keys.iter().map(|key| format!("--extra-repository-key={}", key.display())));
+            args.extend(request.extra_repos.iter().map(|repo| format!("--extra-repository={}", repo)));
+            args.extend(request.prebuild.iter().map(|cmd| format!("--pre-build-commands={}", cmd)));
+            args.extend(request.starting_build.iter().map(|cmd| format!("--starting-build-commands={}", cmd)));
+            args.extend(request.build_flags.iter().map(|flag| format!("--debbuildopt={}", flag)));
+            if !profiles.is_empty() {
+                args.push(format!("--profiles={}", profiles));
+            }
+
+            // The cache is mounted by the fstab of the schroot, and configured by a config which
+            // sbuild reads in addition to its own.
+            if let Some(cache) = request.compiler_cache {
+                args.push(format!("--add-depends={}", cache.tool.name()));
+                env.push(("SBUILD_CONFIG".into(), request.build_dir.join(SBUILD_CONFIG).display().to_string()));
+            }
+
+            args.push(request.path.display().to_string());
+
+            Ok(BuilderCommand { program, args, cwd: request.build_dir.to_path_buf(), env })
+        }
+        BuilderBackend::Pbuilder => {
+            unsupported("extra packages", !request.extra_packages.is_empty());
+            unsupported("extra repository keys", !request.extra_keys.is_empty());
+            unsupported("build commands", !request.prebuild.is_empty() || !request.starting_build.is_empty());
+
+            if dsc {
+                args.push("build".into());
+            }
+
+            let mut options = vec![
+                "--buildresult".to_owned(),
+                request.build_dir.display().to_string(),
+                "--distribution".into(),
+                request.distribution.into(),
+                "--architecture".into(),
+                request.arch.into(),
+            ];
+
+            if !request.arch_all {
+                options.push("--binary-arch".into());
+            }
+
+            if let Some(ref chroot) = builder.chroot {
+                options.push("--basetgz".into());
+                options.push(chroot.clone());
+            }
+
+            // The local repo is mounted within the chroot, and enabled as a trusted mirror.
+            let mut mirrors = request.extra_repos.to_vec();
+            if let Some(local) = request.local {
+                mirrors.push(format!("deb [trusted=yes] file://{} ./", local.display()));
+                options.push("--bindmounts".into());
+                options.push(local.display().to_string());
+            }
+
+            if !mirrors.is_empty() {
+                options.push("--othermirror".into());
+                options.push(mirrors.join("|"));
+            }
+
+            if !request.build_flags.is_empty() {
+                options.push("--debbuildopts".into());
+                options.push(request.build_flags.join(" "));
+            }
+
+            if !profiles.is_empty() {
+                options.push("--profiles".into());
+                options.push(profiles);
+            }
+
+            if request.compiler_cache.is_some() {
+                options.push("--configfile".into());
+                options.push(request.build_dir.join(PBUILDER_CONFIG).display().to_string());
+            }
+
+            if dsc {
+                args.extend(options);
+                args.push(request.path.display().to_string());
+                Ok(BuilderCommand { program, args, cwd: request.build_dir.to_path_buf(), env })
+            } else {
+                // The options of pdebuild are passed through to pbuilder.
+                args.push("--buildresult".into());
+                args.push(request.build_dir.display().to_string());
+                args.push("--".into());
+                args.extend(options);
+                Ok(BuilderCommand { program, args, cwd: request.path.to_path_buf(), env })
+            }
+        }
+        BuilderBackend::Dpkg => {
+            if dsc {
+                return Err(BuildError::UnsupportedBuilder {
+                    package: request.name.to_owned(),
+                    why: "dpkg-buildpackage must be given an unpacked source, not a dsc"
+                });
+            }
+
+            unsupported("a chroot", builder.chroot.is_some());
+            unsupported("extra packages", !request.extra_packages.is_empty() || request.local.is_some());
+            unsupported("extra repositories", !request.extra_repos.is_empty());
+            unsupported("build commands", !request.prebuild.is_empty() || !request.starting_build.is_empty());
+
+            // Packages are written to the parent of the source, which is the build directory. The
+            // source package has already been built.
+            args.extend_from_slice(&[
+                "-us".into(),
+                "-uc".into(),
+                if request.arch_all { "--build=any,all" } else { "--build=any" }.into(),
+                format!("--host-arch={}", request.arch),
+            ]);
+
+            if !profiles.is_empty() {
+                args.push(format!("--build-profiles={}", profiles));
+            }
+
+            if let Some(cache) = request.compiler_cache {
+                env.extend(cache.env(&env::var("PATH").unwrap_or_default()));
+            }
+
+            args.extend_from_slice(request.build_flags);
+            Ok(BuilderCommand { program, args, cwd: request.path.to_path_buf(), env })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::CompilerCacheTool;
+
+    #[test]
+    fn builder_backends() {
+        let local = PathBuf::from("/srv/build/bionic.local");
+        let repos = vec!["deb http://example.com/ubuntu bionic main".to_owned()];
+        let request = BuildRequest {
+            name: "foo",
+            path: Path::new("/srv/build/bionic/foo"),
+            build_dir: Path::new("/srv/build/bionic"),
+            arch: "amd64",
+            arch_all: true,
+            distribution: "bionic",
+            extra_packages: &[],
+            local: Some(&local),
+            extra_repos: &repos,
+            extra_keys: &[],
+            prebuild: &[],
+            starting_build: &[],
+            env: None,
+            build_flags: &[],
+            build_options: &[],
+            build_profiles: &[],
+            compiler_cache: None,
+        };
+
+        let sbuild = command(&Builder { chroot: Some("bionic-amd64".into()), .. Builder::default() }, &request).unwrap();
+        assert_eq!(sbuild.program, "sbuild");
+        assert_eq!(sbuild.cwd, Path::new("/srv/build/bionic"));
+        assert!(sbuild.args.contains(&"--chroot=bionic-amd64".into()));
+        assert!(sbuild.args.contains(&"--arch=amd64".into()) && sbuild.args.contains(&"--arch-all".into()));
+        assert!(sbuild.args.contains(&"--extra-package=/srv/build/bionic.local".into()));
+        assert_eq!(sbuild.args.last().unwrap(), "/srv/build/bionic/foo");
+
+        let pbuilder = command(&Builder { backend: Some(BuilderBackend::Pbuilder), .. Builder::default() }, &request).unwrap();
+        assert_eq!(pbuilder.program, "pdebuild");
+        assert_eq!(pbuilder.cwd, Path::new("/srv/build/bionic/foo"));
+        assert_eq!(pbuilder.args.last().unwrap(), &format!(
+            "{}|deb [trusted=yes] file:///srv/build/bionic.local ./", repos[0]
+        ));
+
+        let i386 = BuildRequest { arch: "i386", arch_all: false, .. request };
+        let sbuild = command(&Builder::default(), &i386).unwrap();
+        assert!(sbuild.args.contains(&"--arch=i386".into()) && sbuild.args.contains(&"--no-arch-all".into()));
+
+        let dsc = BuildRequest { path: Path::new("/srv/build/bionic/foo/foo_1.0.dsc"), .. i386 };
+        let dpkg = Builder { backend: Some(BuilderBackend::Dpkg), .. Builder::default() };
+        assert!(command(&dpkg, &dsc).is_err());
+
+        let pbuilder = command(&Builder { backend: Some(BuilderBackend::Pbuilder), .. Builder::default() }, &dsc).unwrap();
+        assert_eq!(pbuilder.program, "pbuilder");
+        assert_eq!(pbuilder.args[0], "build");
+
+        let mut env = BTreeMap::new();
+        env.insert("FOO".to_owned(), "bar".to_owned());
+        let flags = vec!["-j4".to_owned()];
+        let options = vec!["nocheck".to_owned(), "parallel=4".to_owned()];
+        let profiles = vec!["nocheck".to_owned(), "nodoc".to_owned()];
+        let tuned = BuildRequest {
+            local: None,
+            extra_repos: &[],
+            env: Some(&env),
+            build_flags: &flags,
+            build_options: &options,
+            build_profiles: &profiles,
+            .. i386
+        };
+
+        let sbuild = command(&Builder::default(), &tuned).unwrap();
+        assert!(sbuild.args.contains(&"--debbuildopt=-j4".into()));
+        assert!(sbuild.args.contains(&"--profiles=nocheck,nodoc".into()));
+        assert_eq!(sbuild.env, vec![
+            ("FOO".to_owned(), "bar".to_owned()),
+            ("DEB_BUILD_OPTIONS".to_owned(), "nocheck parallel=4".to_owned()),
+        ]);
+
+        let dpkg = command(&dpkg, &tuned).unwrap();
+        assert!(dpkg.args.ends_with(&["--build-profiles=nocheck,nodoc".into(), "-j4".into()]));
+
+        let cache = CompilerCache {
+            tool: CompilerCacheTool::Ccache,
+            directory: PathBuf::from("/var/cache/debrep/ccache"),
+            max_size: None,
+        };
+
+        let cached = BuildRequest { compiler_cache: Some(&cache), .. tuned };
+        let sbuild = command(&Builder::default(), &cached).unwrap();
+        assert!(sbuild.args.contains(&"--add-depends=ccache".into()));
+        assert!(sbuild.env.contains(&("SBUILD_CONFIG".into(), "/srv/build/bionic/.sbuild-cache.conf".into())));
+
+        let dpkg = command(&Builder { backend: Some(BuilderBackend::Dpkg), .. Builder::default() }, &cached).unwrap();
+        assert!(dpkg.env.contains(&("CCACHE_DIR".into(), "/var/cache/debrep/ccache".into())));
+    }
+}
@@ -0,0 +1,203 @@
+use action;
+use config::{BuilderBackend, CompilerCache, CompilerCacheTool, Config};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use super::summary::{directory_bytes, CacheSummary};
+
+/// The sbuild config within the build directory which enables the compiler cache. Given by
+/// `SBUILD_CONFIG`, sbuild reads it instead of `~/.sbuildrc`, so it requires that file first.
+pub const SBUILD_CONFIG: &str = ".sbuild-cache.conf";
+
+/// The pbuilder config within the build directory which enables the compiler cache, which is
+/// given to pbuilder by `--configfile`.
+pub const PBUILDER_CONFIG: &str = ".pbuilder-cache.conf";
+
+/// The `PATH` which sbuild and pbuilder build with, which the compilers are found by.
+const CHROOT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// The size and counters of the cache before or after a run.
+#[derive(Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub bytes: u64,
+    /// Cache hits and misses, which are only counted by ccache.
+    pub counters: Option<(u64, u64)>,
+}
+
+/// Creates the cache directory, and writes the config of each chroot backend that the sources of
+/// the suite are built with into the build directory.
+pub fn prepare_cache(config: &Config, cache: &CompilerCache, build_dir: &Path) -> io::Result<()> {
+    action::create_dir_all(&cache.directory)?;
+
+    let backends = config.source.iter()
+        .flat_map(|sources| sources.iter())
+        .map(|source| config.builder_for(source).backend.unwrap_or_default())
+        .collect::<Vec<BuilderBackend>>();
+
+    if backends.contains(&BuilderBackend::Sbuild) {
+        action::write(build_dir.join(SBUILD_CONFIG), sbuild_config(cache))?;
+    }
+
+    if backends.contains(&BuilderBackend::Pbuilder) {
+        action::write(build_dir.join(PBUILDER_CONFIG), pbuilder_config(cache))?;
+    }
+
+    Ok(())
+}
+
+/// Sets the variables of the cache in the environment of each build, as sbuild drops those of
+/// its own environment which are not in its `$environment_filter`.
+///
+/// The user's `~/.sbuildrc` is read first, so that its settings still apply, and the variables
+/// of the cache are added to its `$build_environment`, rather than replacing it.
+fn sbuild_config(cache: &CompilerCache) -> String {
+    let quote = |value: &str| ["'", &value.replace('\\', "\\\\").replace('\'', "\\'"), "'"].concat();
+
+    let mut config = String::from("\
+my $sbuildrc = \"$ENV{'HOME'}/.sbuildrc\";
+require $sbuildrc if defined $ENV{'HOME'} && -f $sbuildrc;
+$build_environment = {
+    %{$build_environment || {}},
+");
+    let mut path = None;
+    for (key, value) in cache.env(CHROOT_PATH) {
+        if key == "PATH" {
+            path = Some(value);
+        } else {
+            config.push_str(&["    ", &quote(&key), " => ", &quote(&value), ",\n"].concat());
+        }
+    }
+
+    config.push_str("};\n");
+    if let Some(path) = path {
+        config.push_str(&["$path = ", &quote(&path), ";\n"].concat());
+    }
+
+    config.push_str("1;\n");
+    config
+}
+
+/// Mounts the cache within the chroot, installs the tool, and exports the variables of the cache.
+fn pbuilder_config(cache: &CompilerCache) -> String {
+    // Escapes a value within double quotes, where the variables of pbuilder are expanded.
+    let escape = |value: &str| {
+        let mut escaped = String::with_capacity(value.len());
+        for character in value.chars() {
+            match character {
+                '"' | '\\' | '$' | '`' => escaped.push('\\'),
+                _ => ()
+            }
+
+            escaped.push(character);
+        }
+
+        escaped
+    };
+
+    let mut config = format!(
+        "BINDMOUNTS=\"$BINDMOUNTS {}\"\nEXTRAPACKAGES=\"$EXTRAPACKAGES {}\"\n",
+        escape(&cache.directory.display().to_string()),
+        cache.tool.name()
+    );
+
+    for (key, value) in cache.env(CHROOT_PATH) {
+        config.push_str(&["export ", &key, "=\"", &escape(&value), "\"\n"].concat());
+    }
+
+    config
+}
+
+/// The size of the cache, and its counters, which are compared once the builds have finished.
+pub fn cache_stats(cache: &CompilerCache) -> CacheStats {
+    let counters = match cache.tool {
+        CompilerCacheTool::Ccache => Command::new("ccache")
+            .arg("--print-stats")
+            .env("CCACHE_DIR", &cache.directory)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| ccache_counters(&String::from_utf8_lossy(&output.stdout))),
+        CompilerCacheTool::Sccache => None
+    };
+
+    CacheStats { bytes: directory_bytes(&cache.directory), counters }
+}
+
+/// Summarizes the use of the cache by this run.
+pub fn summarize_cache(cache: &CompilerCache, before: &CacheStats) -> CacheSummary {
+    let after = cache_stats(cache);
+    let counters = match (before.counters, after.counters) {
+        (Some((hits, misses)), Some((hits_after, misses_after))) => {
+            Some((hits_after.saturating_sub(hits), misses_after.saturating_sub(misses)))
+        }
+        _ => None
+    };
+
+    CacheSummary {
+        tool: cache.tool.name(),
+        bytes_before: before.bytes,
+        bytes_after: after.bytes,
+        counters,
+    }
+}
+
+/// The hits and misses of the tab-separated counters of `ccache --print-stats`.
+fn ccache_counters(stats: &str) -> Option<(u64, u64)> {
+    let (mut hits, mut misses, mut found) = (0, 0, false);
+    for line in stats.lines() {
+        let mut fields = line.split('\t');
+        let (key, value) = match (fields.next(), fields.next().and_then(|x| x.trim().parse::<u64>().ok())) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue
+        };
+
+        match key {
+            "direct_cache_hit" | "preprocessed_cache_hit" => hits += value,
+            "cache_miss" => misses += value,
+            _ => continue
+        }
+
+        found = true;
+    }
+
+    if found { Some((hits, misses)) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn compiler_cache_configs() {
+        let cache = CompilerCache {
+            tool: CompilerCacheTool::Ccache,
+            directory: PathBuf::from("/var/cache/debrep/ccache"),
+            max_size: Some("20G".into()),
+        };
+
+        assert_eq!(sbuild_config(&cache), "\
+my $sbuildrc = \"$ENV{'HOME'}/.sbuildrc\";
+require $sbuildrc if defined $ENV{'HOME'} && -f $sbuildrc;
+$build_environment = {
+    %{$build_environment || {}},
+    'CCACHE_DIR' => '/var/cache/debrep/ccache',
+    'CCACHE_MAXSIZE' => '20G',
+};
+$path = '/usr/lib/ccache:/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin';
+1;
+");
+
+        let sccache = CompilerCache { tool: CompilerCacheTool::Sccache, max_size: None, .. cache };
+        assert_eq!(pbuilder_config(&sccache), "\
+BINDMOUNTS=\"$BINDMOUNTS /var/cache/debrep/ccache\"
+EXTRAPACKAGES=\"$EXTRAPACKAGES sccache\"
+export SCCACHE_DIR=\"/var/cache/debrep/ccache\"
+export RUSTC_WRAPPER=\"sccache\"
+");
+
+        let stats = "stats_updated_timestamp\t1540000000\ndirect_cache_hit\t120\npreprocessed_cache_hit\t30\ncache_miss\t50\n";
+        assert_eq!(ccache_counters(stats), Some((150, 50)));
+        assert_eq!(ccache_counters("Summary:\n  Hits: 150 / 200 (75.00 %)\n"), None);
+    }
+}
@@ -0,0 +1,86 @@
+use command::Command;
+use misc;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::orig::changelog_head;
+
+/// Builds the source package of an unpacked project with `dpkg-source`, returning its `.dsc`.
+///
+/// The source package is written beside the project, in the build directory, where it is moved
+/// into the source pool along with the binaries. A `3.0 (quilt)` source is packed as its orig
+/// tarball, which must already be beside the project, and a `.debian.tar.xz`.
+pub fn build_source_package(project: &Path) -> io::Result<PathBuf> {
+    let dsc = dsc_path(project)?;
+    let directory = project.parent().unwrap_or_else(|| Path::new("."));
+    let name = project.file_name().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} is not a project directory", project.display())
+    ))?;
+
+    info!("building the source package of {}", project.display());
+
+    // Version control files of git sources are kept out of the source package.
+    Command::new("dpkg-source")
+        .current_dir(directory)
+        .args(&["--build", "-I", "-i"])
+        .arg(name)
+        .run()?;
+
+    if !dsc.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("dpkg-source did not generate {}", dsc.display())
+        ));
+    }
+
+    Ok(dsc)
+}
+
+/// Generates the `.changes` of the source package of a project with `dpkg-genchanges`, as a
+/// source-only upload would, so that the files of the source package are verified and recorded
+/// as those of a binary build are.
+pub fn source_changes(project: &Path) -> io::Result<PathBuf> {
+    let dsc = dsc_path(project)?;
+    let name = dsc.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+    let changes = dsc.with_file_name([name, "_source.changes"].concat());
+
+    // The orig tarball is always listed, as the suite is the canonical archive of the source.
+    Command::new("dpkg-genchanges")
+        .current_dir(project)
+        .args(&["-S", "-sa"])
+        .arg(format!("-O{}", changes.display()))
+        .run()?;
+
+    Ok(changes)
+}
+
+/// The `.dsc` that `dpkg-source` generates for the project, which is named by the source and
+/// version of the project's changelog, without its epoch.
+fn dsc_path(project: &Path) -> io::Result<PathBuf> {
+    let changelog = misc::read_to_string(project.join("debian/changelog"))?;
+    let (source, version) = changelog_head(&changelog).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("no source and version in the changelog of {}", project.display())
+    ))?;
+
+    let version = version.find(':').map_or(version, |pos| &version[pos + 1..]);
+    let directory = project.parent().unwrap_or_else(|| Path::new("."));
+    Ok(directory.join([source, "_", version, ".dsc"].concat()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn source_package_name() {
+        let dir = tempdir().unwrap();
+        let project = dir.path().join("build/bionic/foo");
+        fs::create_dir_all(project.join("debian")).unwrap();
+        misc::write(project.join("debian/changelog"), "foo (1:2.0-1) bionic; urgency=medium\n").unwrap();
+
+        assert_eq!(dsc_path(&project).unwrap(), dir.path().join("build/bionic/foo_2.0-1.dsc"));
+    }
+}
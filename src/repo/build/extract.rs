@@ -1,49 +1,313 @@
+use bzip2::read::BzDecoder;
+use error::Error;
+use libflate::gzip::Decoder as GzDecoder;
 use std::{fs, io};
-use std::path::Path;
-use command::Command;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive as TarArchive, EntryType};
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 pub fn extract(src: &Path, dst: &Path) -> io::Result<()>  {
-    match src.file_name().and_then(|x| x.to_str()) {
-        Some(filename) => {
-            if filename.ends_with(".zip") {
-                unzip(src, dst)
-            } else if filename.ends_with(".tar.gz") || filename.ends_with(".tar.xz") {
-                untar(src, dst)
-            } else {
-                unimplemented!()
-            }
+    let filename = src.file_name().and_then(|x| x.to_str()).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} does not have a valid file name", src.display())
+    ))?;
+
+    let result = if filename.ends_with(".zip") {
+        unzip(src, dst)
+    } else if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
+        File::open(src).and_then(GzDecoder::new).and_then(|reader| untar(reader, dst))
+    } else if filename.ends_with(".tar.xz") {
+        File::open(src).and_then(|file| untar(XzDecoder::new(file), dst))
+    } else if filename.ends_with(".tar.bz2") || filename.ends_with(".tbz2") {
+        File::open(src).and_then(|file| untar(BzDecoder::new(file), dst))
+    } else if filename.ends_with(".tar.zst") || filename.ends_with(".tzst") {
+        File::open(src).and_then(ZstdDecoder::new).and_then(|reader| untar(reader, dst))
+    } else if filename.ends_with(".tar") {
+        File::open(src).and_then(|file| untar(file, dst))
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a supported archive format", src.display())
+        ));
+    };
+
+    result.map_err(|why| io::Error::new(
+        why.kind(),
+        format!("failed to extract {} to {}: {}", src.display(), dst.display(), why)
+    ))
+}
+
+/// Ensures that an archive member is relative, and can't escape the destination directory.
+fn sanitize(path: &Path) -> io::Result<&Path> {
+    let safe = path.components().all(|component| match component {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false
+    });
+
+    if safe {
+        Ok(path)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("archive member has an unsafe path: {}", path.display())
+        ))
+    }
+}
+
+/// Ensures that a symlink within the archive can't point outside of the destination directory,
+/// which would allow a later member to be written through it.
+fn sanitize_link(path: &Path, target: &Path) -> io::Result<()> {
+    let mut depth = path.parent().map_or(0, |parent| parent.components().count() as isize);
+    let escapes = target.components().any(|component| {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => (),
+            Component::ParentDir => depth -= 1,
+            _ => return true
         }
-        None => unimplemented!()
+
+        depth < 0
+    });
+
+    if escapes {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("archive member {} links outside of the archive: {}", path.display(), target.display())
+        ))
+    } else {
+        Ok(())
     }
 }
 
-fn unzip(path: &Path, dst: &Path) -> io::Result<()> {
-    if dst.exists() {
-        fs::remove_dir_all(dst)?;
+/// Ensures that an archive member is not written through a symlink which was extracted before it,
+/// as a chain of links which each stay within the destination may still lead out of it together.
+fn check_unlinked(root: &Path, path: &Path) -> io::Result<()> {
+    let mut prefix = root.to_path_buf();
+    for component in path.components() {
+        prefix.push(component);
+        if prefix.symlink_metadata().map_or(false, |metadata| metadata.file_type().is_symlink()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("archive member {} would be written through a symlink", path.display())
+            ));
+        }
     }
 
-    fs::create_dir_all(dst)
-        .and_then(|_| Command::new("unzip")
-            .arg("-qq")
-            .arg(path)
-            .arg("-d")
-            .arg(dst)
-            .run()
-        )
+    Ok(())
+}
+
+/// Removes the leading directory from an archive member, as `tar --strip-components 1` would.
+fn strip_first(path: &Path) -> PathBuf {
+    path.components().skip(1).collect()
 }
 
-fn untar(path: &Path, dst: &Path) -> io::Result<()> {
+fn clean_destination(dst: &Path) -> io::Result<()> {
     if dst.exists() {
         fs::remove_dir_all(dst)?;
     }
 
     fs::create_dir_all(dst)
-        .and_then(|_| Command::new("tar")
-            .arg("-pxf")
-            .arg(path)
-            .arg("-C")
-            .arg(dst)
-            .args(&["--strip-components", "1"])
-            .run()
-        )
+}
+
+fn unzip(path: &Path, dst: &Path) -> io::Result<()> {
+    clean_destination(dst)?;
+
+    let mut archive = ZipArchive::new(File::open(path)?)
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, format!("invalid zip archive: {}", why)))?;
+
+    for id in 0..archive.len() {
+        let mut file = archive.by_index(id)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, format!("invalid zip entry: {}", why)))?;
+
+        let name = file.name().to_owned();
+        let target = dst.join(sanitize(Path::new(&name))?);
+
+        if name.ends_with('/') {
+            fs::create_dir_all(&target)?;
+            continue
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mode = file.unix_mode();
+        File::create(&target)
+            .and_then(|mut output| io::copy(&mut file, &mut output))
+            .and_then(|_| match mode {
+                Some(mode) => fs::set_permissions(&target, fs::Permissions::from_mode(mode)),
+                None => Ok(())
+            })
+            .map_err(|why| Error::Extract { member: PathBuf::from(&name), why })?;
+    }
+
+    Ok(())
+}
+
+fn untar<R: Read>(reader: R, dst: &Path) -> io::Result<()> {
+    clean_destination(dst)?;
+    let root = dst.canonicalize()?;
+
+    let mut archive = TarArchive::new(reader);
+    archive.set_preserve_permissions(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = strip_first(sanitize(&entry.path()?)?);
+        if path.as_os_str().is_empty() {
+            continue
+        }
+
+        check_unlinked(&root, &path)?;
+        let target = root.join(&path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+
+            // As `Entry::unpack_in` does, which can't be given the member's stripped path.
+            if !parent.canonicalize()?.starts_with(&root) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("archive member {} is outside of the destination", path.display())
+                ));
+            }
+        }
+
+        // Hard links refer to other members of the archive, which have also been stripped.
+        if entry.header().entry_type() == EntryType::Link {
+            let link = entry.link_name()?.ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("hard link {} has no target", path.display())
+            ))?;
+
+            let source = strip_first(sanitize(&link)?);
+            check_unlinked(&root, &source)?;
+            let source = root.join(source);
+            fs::hard_link(&source, &target).map_err(|why| Error::Extract { member: path, why })?;
+            continue
+        }
+
+        if entry.header().entry_type() == EntryType::Symlink {
+            if let Some(link) = entry.link_name()? {
+                sanitize_link(&path, &link)?;
+            }
+        }
+
+        entry.unpack(&target).map_err(|why| Error::Extract { member: path, why })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2;
+    use libflate::gzip::Encoder as GzEncoder;
+    use misc;
+    use std::io::Write;
+    use tar::{Builder, Header};
+    use tempfile::tempdir;
+    use zstd;
+
+    #[test]
+    fn native_untar() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("project.tar.gz");
+
+        let tarball = {
+            let mut builder = Builder::new(Vec::new());
+            let data = b"#!/bin/sh\n";
+            let mut header = Header::new_gnu();
+            header.set_path("project-1.0/debian/rules").unwrap();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append(&header, &data[..]).unwrap();
+            builder.into_inner().unwrap()
+        };
+
+        {
+            let mut encoder = GzEncoder::new(File::create(&src).unwrap()).unwrap();
+            encoder.write_all(&tarball).unwrap();
+            encoder.finish().into_result().unwrap();
+        }
+
+        let dst = dir.path().join("project");
+        extract(&src, &dst).unwrap();
+
+        let rules = dst.join("debian/rules");
+        assert_eq!(misc::read_to_string(&rules).unwrap(), "#!/bin/sh\n");
+        assert_eq!(fs::metadata(&rules).unwrap().permissions().mode() & 0o777, 0o755);
+
+        for &(name, ref compressed) in &[
+            ("project.tar.bz2", bzip2::read::BzEncoder::new(&tarball[..], bzip2::Compression::Best)
+                .bytes().collect::<io::Result<Vec<u8>>>().unwrap()),
+            ("project.tar.zst", zstd::stream::encode_all(&tarball[..], 3).unwrap()),
+            ("project.tar", tarball.clone()),
+        ] {
+            let src = dir.path().join(name);
+            misc::write(&src, compressed).unwrap();
+            extract(&src, &dst).unwrap();
+            assert_eq!(misc::read_to_string(&rules).unwrap(), "#!/bin/sh\n");
+        }
+
+        let unsupported = extract(&dir.path().join("project.rar"), &dst).unwrap_err();
+        assert_eq!(unsupported.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    fn tarball_of(entries: &[(&[u8], EntryType, Option<&str>)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for &(path, entry_type, link) in entries {
+            let mut header = Header::new_gnu();
+            // Written directly, as `set_path` refuses to create these malicious paths.
+            header.as_old_mut().name[..path.len()].copy_from_slice(path);
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, io::empty()).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn tarball_with(path: &[u8], entry_type: EntryType, link: Option<&str>) -> Vec<u8> {
+        tarball_of(&[(path, entry_type, link)])
+    }
+
+    #[test]
+    fn path_traversal() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("evil.tar");
+        let dst = dir.path().join("project/extracted");
+
+        for tarball in &[
+            tarball_with(b"project/../../../evil", EntryType::Regular, None),
+            tarball_with(b"/etc/evil", EntryType::Regular, None),
+            tarball_with(b"project/escape", EntryType::Symlink, Some("../../..")),
+            tarball_with(b"project/escape", EntryType::Symlink, Some("/etc")),
+            // Each link stays within the destination by itself, but `a/a/a` resolves to the
+            // destination, from which `b` leads three directories out of it.
+            tarball_of(&[
+                (&b"project/a"[..], EntryType::Symlink, Some(".")),
+                (&b"project/a/a/a/b"[..], EntryType::Symlink, Some("../../..")),
+                (&b"project/a/a/a/b/evil"[..], EntryType::Regular, None),
+            ]),
+        ] {
+            misc::write(&src, tarball).unwrap();
+            let why = extract(&src, &dst).unwrap_err();
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+            assert!(!dir.path().join("evil").exists());
+        }
+
+        misc::write(&src, tarball_with(b"project/link", EntryType::Symlink, Some("debian/rules"))).unwrap();
+        extract(&src, &dst).unwrap();
+    }
 }
@@ -0,0 +1,139 @@
+use config::Source;
+use debian::{parse_control, relation_names};
+use misc;
+use std::path::Path;
+
+/// The binary packages that a source produces, and the packages that are required to build it.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SourceRelations {
+    pub binaries: Vec<String>,
+    pub build_depends: Vec<String>,
+}
+
+/// Reads the relations of a source from the paragraphs of its `debian/control` file.
+pub(crate) fn source_relations(control: &str) -> SourceRelations {
+    let mut relations = SourceRelations::default();
+    let paragraphs = control.split("\n\n")
+        .map(|paragraph| parse_control(paragraph))
+        .filter(|paragraph| !paragraph.is_empty());
+
+    for paragraph in paragraphs {
+        if let Some(binary) = paragraph.get("Package") {
+            relations.binaries.push(binary.clone());
+        }
+
+        for field in &["Build-Depends", "Build-Depends-Arch", "Build-Depends-Indep"] {
+            if let Some(value) = paragraph.get(*field) {
+                relations.build_depends.extend(relation_names(value));
+            }
+        }
+    }
+
+    relations
+}
+
+/// Locates and reads the relations of a source, if its debian directory is available before
+/// the build. This is either the debian directory kept in the repo, or the one in a source which
+/// was already fetched.
+pub(crate) fn read_relations(pwd: &Path, suite: &str, source: &Source) -> SourceRelations {
+    let candidates = [
+        pwd.join(["debian/", suite, "/", &source.name, "/control"].concat()),
+        pwd.join(["build/", suite, "/", &source.name, "/debian/control"].concat()),
+    ];
+
+    candidates.iter()
+        .find(|path| path.exists())
+        .and_then(|path| match misc::read_to_string(path) {
+            Ok(control) => Some(source_relations(&control)),
+            Err(why) => {
+                warn!("failed to read {}: {}", path.display(), why);
+                None
+            }
+        })
+        .unwrap_or_default()
+}
+
+/// For each source, the other sources that must be built before it.
+///
+/// A source depends on another when its `depends`, or its `Build-Depends`, names one of the
+/// binaries of the other source. A source without known binaries is assumed to produce a binary
+/// of the same name.
+pub(crate) fn dependencies(sources: &[Source], relations: &[SourceRelations]) -> Vec<Vec<usize>> {
+    let provides = |id: usize, name: &str| {
+        sources[id].name == name
+            || relations.get(id).map_or(false, |relations| relations.binaries.iter().any(|x| x == name))
+    };
+
+    sources.iter().enumerate()
+        .map(|(id, source)| {
+            let mut required: Vec<&str> = source.depends.iter()
+                .flat_map(|depends| depends.iter().map(|x| x.as_str()))
+                .collect();
+
+            if let Some(relations) = relations.get(id) {
+                required.extend(relations.build_depends.iter().map(|x| x.as_str()));
+            }
+
+            (0..sources.len())
+                .filter(|&other| other != id && required.iter().any(|name| provides(other, name)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Finds sources which can never be built, because they depend upon each other.
+pub(crate) fn cycles(depends: &[Vec<usize>]) -> Vec<usize> {
+    let mut resolved = vec![false; depends.len()];
+    loop {
+        let ready = (0..depends.len())
+            .filter(|&id| !resolved[id] && depends[id].iter().all(|&dep| resolved[dep]))
+            .collect::<Vec<usize>>();
+
+        if ready.is_empty() {
+            break
+        }
+
+        for id in ready {
+            resolved[id] = true;
+        }
+    }
+
+    (0..depends.len()).filter(|&id| !resolved[id]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    #[test]
+    fn build_dependency_graph() {
+        #[derive(Deserialize)]
+        struct Sources { source: Vec<Source> }
+        let sources = toml::from_str::<Sources>(r#"
+            [[source]]
+            name = "app"
+
+            [[source]]
+            name = "foo"
+
+            [[source]]
+            name = "tool"
+            depends = ["app"]
+        "#).unwrap().source;
+
+        let relations = vec![
+            source_relations("Source: app\nBuild-Depends: debhelper (>= 9), libfoo-dev\n\nPackage: app\nArchitecture: any\n"),
+            source_relations("Source: foo\nBuild-Depends: debhelper\n\nPackage: libfoo1\n\nPackage: libfoo-dev\nDepends: libfoo1\n"),
+            SourceRelations::default(),
+        ];
+
+        assert_eq!(relations[1].binaries, vec!["libfoo1", "libfoo-dev"]);
+        assert_eq!(relations[0].build_depends, vec!["debhelper", "libfoo-dev"]);
+
+        let depends = dependencies(&sources, &relations);
+        assert_eq!(depends, vec![vec![1], vec![], vec![0]]);
+        assert!(cycles(&depends).is_empty());
+        assert_eq!(cycles(&[vec![1], vec![0], vec![]]), vec![0, 1]);
+    }
+}
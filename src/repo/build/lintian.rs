@@ -0,0 +1,150 @@
+use config::{Lintian, LintianSeverity};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A tag which lintian emitted for one of the packages of a source.
+#[derive(Debug, PartialEq)]
+pub struct LintianTag {
+    pub severity: LintianSeverity,
+    /// The package, which is followed by its type if it is not a binary package.
+    pub package: String,
+    pub tag: String,
+    pub info: String,
+}
+
+impl fmt::Display for LintianTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}: {}", self.severity, self.package, self.tag)?;
+        if !self.info.is_empty() {
+            write!(f, " {}", self.info)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The tags of a source which reached its warning threshold, and whether any reached its failure
+/// threshold.
+#[derive(Debug)]
+pub struct LintianReport {
+    pub source: String,
+    pub tags: Vec<LintianTag>,
+    pub failed: bool,
+}
+
+/// Runs lintian on the `.changes` files that the build of a source produced.
+///
+/// Tags which reach the warning threshold are logged and kept in the report, and the report is
+/// marked as failed if any tag reaches the failure threshold.
+pub fn lintian(build_path: &Path, source: &str, config: &Lintian) -> io::Result<LintianReport> {
+    let prefix = [source, "_"].concat();
+    let mut changes = build_path.read_dir()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|x| x.to_str()).map_or(false, |name| {
+            name.starts_with(&prefix) && name.ends_with(".changes")
+        }))
+        .collect::<Vec<PathBuf>>();
+
+    changes.sort();
+    if changes.is_empty() {
+        warn!("{} has no .changes file for lintian to check", source);
+    }
+
+    let mut report = LintianReport { source: source.to_owned(), tags: Vec::new(), failed: false };
+    for path in changes {
+        info!("running lintian on {}", path.display());
+        let output = Command::new("lintian")
+            .args(&["--display-info", "--pedantic", "--no-tag-display-limit"])
+            .arg(&path)
+            .output()?;
+
+        // Lintian exits with 1 when it finds errors, and with 2 when it fails to run.
+        match output.status.code() {
+            Some(0) | Some(1) => (),
+            _ => return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "lintian failed to check {}: {}",
+                    path.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+            ))
+        }
+
+        let tags = parse_tags(&String::from_utf8_lossy(&output.stdout));
+        for tag in tags.into_iter().filter(|tag| tag.severity >= config.warn_on()) {
+            if tag.severity >= config.fail_on() {
+                error!("lintian: {}", tag);
+                report.failed = true;
+            } else {
+                warn!("lintian: {}", tag);
+            }
+
+            report.tags.push(tag);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Parses the tags of lintian's output, which are of the form `E: package [type]: tag [info]`.
+///
+/// Overridden, experimental, and classification tags, and lintian's own notes, are skipped.
+fn parse_tags(output: &str) -> Vec<LintianTag> {
+    output.lines()
+        .filter_map(|line| {
+            let severity = match line.get(..3)? {
+                "E: " => LintianSeverity::Error,
+                "W: " => LintianSeverity::Warning,
+                "I: " => LintianSeverity::Info,
+                "P: " => LintianSeverity::Pedantic,
+                _ => return None
+            };
+
+            let line = &line[3..];
+            let pos = line.find(": ")?;
+            let mut tag = line[pos + 2..].splitn(2, ' ');
+            Some(LintianTag {
+                severity,
+                package: line[..pos].to_owned(),
+                tag: tag.next()?.to_owned(),
+                info: tag.next().unwrap_or("").trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lintian_tags() {
+        let output = "E: foo: binary-without-manpage usr/bin/foo\n\
+            W: foo source: out-of-date-standards-version 3.9.8 (released 2016-04-06)\n\
+            N: 2 tags overridden (1 error, 1 warning)\n\
+            O: foo: spelling-error-in-binary\n\
+            P: foo-doc: no-homepage-field\n";
+
+        let tag = |severity, package: &str, tag: &str, info: &str| LintianTag {
+            severity,
+            package: package.into(),
+            tag: tag.into(),
+            info: info.into(),
+        };
+
+        assert_eq!(parse_tags(output), vec![
+            tag(LintianSeverity::Error, "foo", "binary-without-manpage", "usr/bin/foo"),
+            tag(
+                LintianSeverity::Warning,
+                "foo source",
+                "out-of-date-standards-version",
+                "3.9.8 (released 2016-04-06)"
+            ),
+            tag(LintianSeverity::Pedantic, "foo-doc", "no-homepage-field", ""),
+        ]);
+    }
+}
@@ -0,0 +1,84 @@
+use checksum::multi_hasher;
+use debian::{DebFile, PackageEntry};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use misc;
+
+/// The `Origin` of the packages within the local repository.
+const LOCAL_ORIGIN: &str = "debrep-local";
+
+/// A flat repository of the packages built during this run, which is given to the builds of
+/// sources that build-depend on them.
+pub(crate) struct LocalRepo {
+    path: PathBuf,
+    /// Concurrent builds may finish together, but the index must be regenerated by one at a time.
+    lock: Mutex<()>,
+}
+
+impl LocalRepo {
+    /// Creates an empty repository at the given path, discarding the packages of a previous run.
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+
+        fs::create_dir_all(&path)?;
+        Ok(LocalRepo { path, lock: Mutex::new(()) })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Adds the pooled packages of the given binaries, and then regenerates the `Packages` index
+    /// of the repository. Packages are taken from the pool, so that the binaries of a source
+    /// which was already built by a previous run are also available.
+    pub fn publish(&self, pool: &Path, binaries: &[String]) -> io::Result<()> {
+        let _lock = self.lock.lock().unwrap();
+
+        let debs = misc::walk_debs(pool, false)
+            .flat_map(|deb| misc::match_deb(&deb, binaries))
+            .map(|(deb, _)| PathBuf::from(deb));
+
+        for deb in debs {
+            let destination = match deb.file_name() {
+                Some(filename) => self.path.join(filename),
+                None => continue
+            };
+
+            if destination.exists() {
+                fs::remove_file(&destination)?;
+            }
+
+            if fs::hard_link(&deb, &destination).is_err() {
+                misc::copy(&deb, &destination)?;
+            }
+        }
+
+        let mut debs = fs::read_dir(&self.path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        debs.retain(|deb| deb.extension().map_or(false, |ext| ext == "deb"));
+        debs.sort();
+
+        let mut packages = Vec::with_capacity(debs.len());
+        for deb in debs {
+            let control = DebFile::open(&deb)?.control()?.fields;
+            let digests = File::open(&deb).and_then(multi_hasher)?;
+            let entry = PackageEntry {
+                control,
+                // Paths are relative to the root of the flat repository.
+                filename: Path::new(".").join(deb.file_name().unwrap()),
+                size: deb.metadata()?.len(),
+                md5sum: digests.md5,
+                sha1: digests.sha1,
+                sha256: digests.sha256,
+                sha512: digests.sha512,
+            };
+
+            packages.push(entry.generate_entry(LOCAL_ORIGIN, None)?);
+        }
+
+        misc::write(self.path.join("Packages"), packages.join(&b'\n'))
+    }
+}
@@ -0,0 +1,113 @@
+use action;
+use chrono::Utc;
+use misc;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use super::orig::changelog_head;
+
+/// The number of logs of each package which are kept, unless `keep_logs` is configured.
+pub const DEFAULT_KEEP_LOGS: usize = 5;
+
+/// The number of lines at the end of a log which are printed when its build fails.
+const TAIL_LINES: usize = 40;
+
+/// The path of a new log of the build of a package for an architecture, which is stored at
+/// `logs/<suite>/<package>/<version>-<arch>-<timestamp>.log`, so that each build keeps its own log.
+pub fn log_path(pwd: &Path, suite: &str, package: &str, version: &str, arch: &str) -> io::Result<PathBuf> {
+    let directory = pwd.join(["logs/", suite, "/", package].concat());
+    fs::create_dir_all(&directory)?;
+
+    // Epochs are dropped, as colons are not permitted in the filenames of some filesystems.
+    let version = version.find(':').map_or(version, |pos| &version[pos + 1..]);
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S");
+    Ok(directory.join(format!("{}-{}-{}.log", version, arch, timestamp)))
+}
+
+/// The version of a source which names the logs of its builds, from its `.dsc` if it is built
+/// from one, or else from the changelog of its project.
+pub fn build_version(project: &Path, dsc: Option<&str>) -> String {
+    let version = match dsc {
+        Some(dsc) => dsc.trim_right_matches(".dsc").splitn(2, '_').nth(1).map(String::from),
+        None => misc::read_to_string(project.join("debian/changelog")).ok()
+            .and_then(|changelog| changelog_head(&changelog).map(|(_, version)| version.to_owned()))
+    };
+
+    version.unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Removes the oldest logs of the package in `directory`, keeping the newest `keep` logs.
+pub fn prune_logs(directory: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut logs = Vec::new();
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+        let timestamp = path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.rfind('-').map(|pos| stem[pos + 1..].to_owned()));
+
+        if let (Some(timestamp), true) = (timestamp, path.extension().map_or(false, |x| x == "log")) {
+            logs.push((timestamp, path));
+        }
+    }
+
+    // Newest first, as the timestamps sort in the order they were written.
+    logs.sort_by(|a, b| b.cmp(a));
+    let mut removed = Vec::new();
+    for (_, path) in logs.into_iter().skip(keep) {
+        action::remove_file(&path)?;
+        removed.push(path);
+    }
+
+    Ok(removed)
+}
+
+/// The last lines of a log, which are printed in place of the whole log when its build fails.
+pub fn tail(path: &Path) -> io::Result<String> {
+    let mut lines = Vec::new();
+    for line in BufReader::new(fs::File::open(path)?).split(b'\n') {
+        lines.push(String::from_utf8_lossy(&line?).into_owned());
+        if lines.len() > TAIL_LINES {
+            lines.remove(0);
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn log_retention() {
+        let dir = tempdir().unwrap();
+        let logs = dir.path();
+        for name in &[
+            "1.0-1-amd64-20181001T120000.log",
+            "1.0-2-amd64-20181003T120000.log",
+            "1.0-1-i386-20181002T120000.log",
+            "0.9-1-amd64-20181004T120000.log",
+            "notes.txt",
+        ] {
+            misc::write(logs.join(name), name.as_bytes()).unwrap();
+        }
+
+        assert_eq!(
+            prune_logs(logs, 2).unwrap(),
+            vec![logs.join("1.0-1-i386-20181002T120000.log"), logs.join("1.0-1-amd64-20181001T120000.log")]
+        );
+
+        assert!(logs.join("0.9-1-amd64-20181004T120000.log").exists());
+        assert!(logs.join("1.0-2-amd64-20181003T120000.log").exists());
+        assert!(logs.join("notes.txt").exists());
+        assert!(prune_logs(logs, 2).unwrap().is_empty());
+
+        let log = logs.join("2.0-1-amd64-20181005T120000.log");
+        let output = (0..50).map(|line| line.to_string()).collect::<Vec<String>>().join("\n");
+        misc::write(&log, output.as_bytes()).unwrap();
+        let tail = tail(&log).unwrap();
+        assert_eq!(tail.lines().count(), TAIL_LINES);
+        assert!(tail.starts_with("10\n") && tail.ends_with("\n49"));
+    }
+}
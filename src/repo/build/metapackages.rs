@@ -1,12 +1,15 @@
-use std::env;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{self, Error, ErrorKind};
 use command::Command;
+use config::{Config, Metapackage, PoolPolicy};
+use debian::{tarball, write_deb};
 use std::path::Path;
+use tempfile::tempdir;
 use walkdir::{DirEntry, WalkDir};
-use super::super::pool::{mv_to_pool, ARCHIVES_ONLY};
+use super::super::pool::{cp_to_pool, mv_to_pool, ARCHIVES_ONLY, KEEP_PUBLISHED};
 
-pub fn generate(suite: &str, component: &str) -> io::Result<()> {
+pub fn generate(pool: &Path, suite: &str, component: &str, policy: &PoolPolicy) -> io::Result<()> {
     let metapackages = &Path::new("metapackages").join(suite);
     if !metapackages.exists() {
         return Ok(());
@@ -34,7 +37,93 @@ pub fn generate(suite: &str, component: &str) -> io::Result<()> {
         })
         .collect::<io::Result<()>>()?;
 
-    mv_to_pool(&metapackages, suite, component, ARCHIVES_ONLY, None)
+    // Metapackages are regenerated on each run, so a metapackage whose version is unchanged is
+    // not published again.
+    mv_to_pool(&metapackages, pool, suite, component, ARCHIVES_ONLY | KEEP_PUBLISHED, None, policy)
+}
+
+/// Synthesizes the metapackages which are declared by the config, and places them into the pool
+/// of each of their components.
+pub fn synthesize(config: &Config) -> io::Result<()> {
+    let metapackages = match config.metapackage {
+        Some(ref metapackages) if !metapackages.is_empty() => metapackages,
+        _ => return Ok(())
+    };
+
+    info!("synthesizing metapackages");
+    let mut components = BTreeMap::new();
+    for metapackage in metapackages {
+        let component = config.component(&metapackage.component);
+        components.entry(component).or_insert_with(Vec::new).push(metapackage);
+    }
+
+    let maintainer = format!("{} <{}>", config.label, config.email);
+    for (component, metapackages) in components {
+        let dir = tempdir()?;
+        for metapackage in metapackages {
+            // The epoch of a version is not a part of the filename.
+            let version = metapackage.version.splitn(2, ':').last().unwrap_or("");
+            let arch = metapackage.architecture.as_ref().map_or("all", |x| x.as_str());
+            let filename = [&metapackage.name, "_", version, "_", arch, ".deb"].concat();
+
+            info!("synthesizing metapackage {}", filename);
+            let control = control(metapackage, &maintainer);
+            let path = dir.path().join(&filename);
+            write_deb(path, &tarball(&[("control", control.as_bytes())])?, &tarball(&[])?)?;
+        }
+
+        // The same metapackage is always synthesized the same way, so a metapackage whose
+        // version is unchanged is not published again.
+        let flags = ARCHIVES_ONLY | KEEP_PUBLISHED;
+        let policy = config.pool_policy();
+        let pool = config.pool_root();
+        cp_to_pool(dir.path(), &pool, &config.archive, component, flags, None, &policy, &config.pooled)?;
+    }
+
+    Ok(())
+}
+
+/// The control file of the metapackage.
+fn control(metapackage: &Metapackage, maintainer: &str) -> String {
+    let mut control = format!(
+        "Package: {}\nVersion: {}\nArchitecture: {}\nMaintainer: {}\nInstalled-Size: 0\n",
+        metapackage.name,
+        metapackage.version,
+        metapackage.architecture.as_ref().map_or("all", |x| x.as_str()),
+        metapackage.maintainer.as_ref().map_or(maintainer, |x| x.as_str())
+    );
+
+    let relations = [
+        ("Depends", &metapackage.depends),
+        ("Recommends", &metapackage.recommends),
+        ("Suggests", &metapackage.suggests),
+        ("Conflicts", &metapackage.conflicts),
+        ("Provides", &metapackage.provides),
+    ];
+
+    for &(field, packages) in &relations {
+        if !packages.is_empty() {
+            control.push_str(&format!("{}: {}\n", field, packages.join(", ")));
+        }
+    }
+
+    control.push_str(&format!(
+        "Section: {}\nPriority: {}\n",
+        metapackage.section.as_ref().map_or("metapackages", |x| x.as_str()),
+        metapackage.priority.as_ref().map_or("optional", |x| x.as_str())
+    ));
+
+    // Each line of the extended description is indented, and an empty line is written as `.`.
+    let mut lines = metapackage.description.trim().lines();
+    control.push_str(&format!("Description: {}\n", lines.next().unwrap_or("").trim()));
+    for line in lines {
+        let line = line.trim_right();
+        control.push(' ');
+        control.push_str(if line.is_empty() { "." } else { line });
+        control.push('\n');
+    }
+
+    control
 }
 
 fn is_cfg(entry: &DirEntry) -> bool {
@@ -51,13 +140,49 @@ fn inner_generate(entry: &DirEntry) -> io::Result<()> {
         format!("parent path not found from {}", path.display())
     ))?;
 
-    directory_scope(parent, move || Command::new("equivs-build").arg(filename).run())
+    Command::new("equivs-build").arg(filename).current_dir(parent).run()
 }
 
-pub fn directory_scope<T, F: FnMut() -> io::Result<T>>(path: &Path, mut scope: F) -> io::Result<T> {
-    let previous = env::current_dir()?;
-    env::set_current_dir(path)?;
-    let result = scope()?;
-    env::set_current_dir(previous)?;
-    Ok(result)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use debian::DebFile;
+
+    #[test]
+    fn synthesized_metapackage() {
+        let metapackage = Metapackage {
+            name: "pop-desktop".into(),
+            version: "1:1.2.0".into(),
+            description: "Pop!_OS desktop\nInstalls the desktop.\n\nAnd its themes.\n".into(),
+            depends: vec!["gnome-shell".into(), "pop-theme (>= 4.0)".into()],
+            recommends: Vec::new(),
+            suggests: Vec::new(),
+            conflicts: vec!["ubuntu-desktop".into()],
+            provides: Vec::new(),
+            architecture: None,
+            section: None,
+            priority: None,
+            maintainer: None,
+            component: None,
+        };
+
+        let control = control(&metapackage, "Pop <info@example.com>");
+        assert_eq!(control, concat!(
+            "Package: pop-desktop\nVersion: 1:1.2.0\nArchitecture: all\n",
+            "Maintainer: Pop <info@example.com>\nInstalled-Size: 0\n",
+            "Depends: gnome-shell, pop-theme (>= 4.0)\nConflicts: ubuntu-desktop\n",
+            "Section: metapackages\nPriority: optional\n",
+            "Description: Pop!_OS desktop\n Installs the desktop.\n .\n And its themes.\n",
+        ));
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("pop-desktop_1.2.0_all.deb");
+        let control_tarball = tarball(&[("control", control.as_bytes())]).unwrap();
+        write_deb(&path, &control_tarball, &tarball(&[]).unwrap()).unwrap();
+
+        let deb = DebFile::open(&path).unwrap();
+        assert_eq!(deb.format().unwrap(), "2.0");
+        assert_eq!(deb.control().unwrap().fields["Depends"], "gnome-shell, pop-theme (>= 4.0)");
+        assert_eq!(deb.data().unwrap().len(), 1);
+    }
 }
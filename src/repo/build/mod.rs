@@ -1,113 +1,533 @@
 mod artifacts;
+mod builder;
+mod cache;
+mod dsc;
 mod extract;
+mod graph;
+mod lintian;
+mod local;
+mod logs;
 mod metapackages;
-mod rsync;
-
+mod orig;
+mod overlay;
+mod patches;
+mod remote;
+mod report;
+mod schedule;
+mod state;
+mod summary;
+mod transform;
+mod trees;
+
+use action;
 use command::Command;
-use config::{Config, DebianPath, Direct, Source, SourceLocation};
-use deb_version;
+use config::{Builder, BuilderBackend, Config, DebianPath, Direct, RetryPolicy, Source, SourceLocation};
 use debarchive::Archive as DebArchive;
-use debian;
+use debian::{self, Changes};
 use glob::glob;
+use interrupt;
 use misc;
+use notify::notify;
 use self::artifacts::{link_artifact, LinkedArtifact, LinkError};
-use self::rsync::rsync;
+use self::cache::{cache_stats, prepare_cache, summarize_cache};
+use self::dsc::{build_source_package, source_changes as generate_source_changes};
+use self::lintian::{lintian, LintianReport};
+use self::local::LocalRepo;
+use self::logs::{build_version, log_path, prune_logs, tail, DEFAULT_KEEP_LOGS};
+use self::overlay::overlay_debian;
+use self::patches::apply_patches;
+use self::remote::RemoteBuild;
+use self::report::write_report;
+use self::schedule::schedule;
+use self::state::BuildState;
+use self::summary::{Outcome, RunSummary, SourceSummary};
+pub(super) use self::summary::directory_bytes;
+use self::trees::{cacheable, prune_trees, restore_tree, store_tree, tree_path};
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs::{self, OpenOptions};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use subprocess::{self, Exec, Redirection};
-use super::pool::{mv_to_pool, KEEP_SOURCE};
+use super::pool::{mv_to_pool, ALLOW_DOWNGRADE, KEEP_SOURCE, REPLACE};
+use super::rsync::rsync;
+use super::prepare::cached_source;
+use super::queue::hold;
+use super::chroot::chroot_name;
+use super::hook::run_hook;
+use super::store::{BuildRecord, Store, STORE};
+use super::RepoError;
 use super::super::SHARED_ASSETS;
 use super::version::{changelog, git};
+use tempfile::tempdir;
 use walkdir::WalkDir;
+use workspace;
 
-pub fn all(config: &Config) {
-    let pwd = env::current_dir().unwrap();
+pub fn all(config: &Config) -> Result<(), RepoError> {
     let suite = &config.archive;
     let component = &config.default_component;
 
     if let Some(ref sources) = config.source {
-        migrate_to_pool(config, sources.iter());
-        let build_path = ["build/", &config.archive].concat();
-        for source in sources {
-            if let Err(why) = build(config, source, &pwd, suite, component, false) {
-                error!("package '{}' failed to build: {}", source.name, why);
-                exit(1);
-            }
+        migrate_to_pool(config, sources.iter(), false)?;
+        build_sources(config, sources.clone(), false)?;
 
-            if let Err(why) = mv_to_pool(
-                &build_path,
-                &config.archive,
-                &config.default_component,
-                if source.keep_source { KEEP_SOURCE } else { 0 },
-                Some(&source.name)
-            ) {
-                error!("package '{}' failed to migrate to pool: {}", source.name, why);
-                exit(1);
-            }
+        let pwd = env::current_dir().unwrap();
+        if let Err(why) = prune_trees(config, &pwd) {
+            warn!("failed to prune the prepared sources of {}: {}", suite, why);
         }
     }
 
-    if let Err(why) = repackage_binaries(config.direct.as_ref(), suite, component) {
-        error!("binary repackage failure: {}", why);
-        exit(1);
+    // The binaries of a source-only suite are built elsewhere.
+    if config.source_only {
+        info!("{} publishes only source packages -- skipping its binary packages", suite);
+        return Ok(());
     }
 
-    if let Err(why) = metapackages::generate(&config.archive, &config.default_component) {
-        error!("metapackage generation failed: {}", why);
-        exit(1);
+    if let Err(why) = repackage_binaries(config.direct.as_ref(), &config.pool_root(), suite, component, &config.retry_policy()) {
+        return Err(RepoError::failed(format!("binary repackage failure: {}", why)));
     }
+
+    let transformed = action::perform(format_args!("transform the packages of {}", suite), || {
+        transform::transform_packages(config)
+    });
+
+    if let Err(why) = transformed {
+        return Err(RepoError::failed(format!("package transformation failed: {}", why)));
+    }
+
+    let generated = action::perform(format_args!("generate the metapackages of {}", config.archive), || {
+        metapackages::generate(
+            &config.pool_root(),
+            &config.archive,
+            &config.default_component,
+            &config.pool_policy()
+        )
+    });
+
+    if let Err(why) = generated {
+        return Err(RepoError::failed(format!("metapackage generation failed: {}", why)));
+    }
+
+    let synthesized = action::perform(format_args!("synthesize the metapackages of {}", suite), || {
+        metapackages::synthesize(config)
+    });
+
+    synthesized.map_err(|why| RepoError::failed(format!("metapackage synthesis failed: {}", why)))
 }
 
-pub fn packages(config: &Config, packages: &[&str], force: bool) {
-    let pwd = env::current_dir().unwrap();
-    let mut built = 0;
+pub fn packages(config: &Config, packages: &[&str], force: bool) -> Result<(), RepoError> {
     match config.source.as_ref() {
         Some(items) => {
             let sources = items.into_iter()
                 .filter(|item| packages.contains(&item.name.as_str()))
-                .collect::<Vec<&Source>>();
-
-            migrate_to_pool(config, sources.iter().cloned());
-            let build_path = ["build/", &config.archive].concat();
-            for source in &sources {
-                if let Err(why) = build(config, source, &pwd, &config.archive, &config.default_component, force) {
-                    error!("package '{}' failed to build: {}", source.name, why);
-                    exit(1);
-                }
+                .cloned()
+                .collect::<Vec<Source>>();
 
-                if let Err(why) = mv_to_pool(
-                    &build_path,
-                    &config.archive,
-                    &config.default_component,
-                    if source.keep_source { KEEP_SOURCE } else { 0 },
-                    Some(&source.name)
-                ) {
-                    error!("package '{}' failed to migrate to pool: {}", source.name, why);
-                    exit(1);
-                }
+            migrate_to_pool(config, sources.iter(), force)?;
+            build_sources(config, sources, force)
+        },
+        None => {
+            warn!("no packages built");
+            Ok(())
+        }
+    }
+}
 
-                built += 1;
-                if built == packages.len() {
-                    break
-                }
+/// How a source relates to the other sources being built in this run.
+struct LocalRelations {
+    binaries: Vec<String>,
+    /// The source build-depends on another source, so it is built with the local repo.
+    uses_local: bool,
+    /// Another source build-depends on this source, so its binaries are added to the local repo.
+    provides_local: bool,
+}
+
+/// Builds each source with the configured number of jobs, moving the packages of each source
+/// into the pool as soon as its build has finished.
+///
+/// Sources are built in the order of their build dependencies. Once a source that other sources
+/// depend upon has been built, its binaries are published to a local repo which is given to the
+/// builds of the sources that depend upon it.
+fn build_sources(config: &Config, sources: Vec<Source>, force: bool) -> Result<(), RepoError> {
+    // Sources which are restricted to architectures that the suite is not built for are skipped.
+    let sources = sources.into_iter()
+        .filter(|source| {
+            let builder = config.builder_for(source);
+            let builds = !build_architectures(config, &builder, source).is_empty();
+            if !builds {
+                let (name, suite) = (&source.name, &config.archive);
+                info!("{} is not built for any architecture of {} -- skipping", name, suite);
             }
-        },
-        None => warn!("no packages built")
+
+            builds
+        })
+        .collect::<Vec<Source>>();
+
+    if action::is_dry_run() {
+        for source in &sources {
+            action::report(format_args!("build {}, and move its packages to the pool", source.name));
+        }
+
+        return Ok(());
+    }
+
+    let pwd = env::current_dir().unwrap();
+    let jobs = config.jobs.unwrap_or(1);
+
+    let relations = sources.iter()
+        .map(|source| graph::read_relations(&pwd, &config.archive, source))
+        .collect::<Vec<_>>();
+    let depends = graph::dependencies(&sources, &relations);
+
+    let local_relations = sources.iter().zip(relations).enumerate()
+        .map(|(id, (source, relations))| {
+            let mut binaries = relations.binaries;
+            if binaries.is_empty() {
+                binaries.push(source.name.clone());
+            }
+
+            (source.name.clone(), LocalRelations {
+                binaries,
+                uses_local: !depends[id].is_empty(),
+                provides_local: depends.iter().any(|depends| depends.contains(&id)),
+            })
+        })
+        .collect::<HashMap<String, LocalRelations>>();
+
+    let local = match LocalRepo::new(pwd.join(["build/", &config.archive, ".local"].concat())) {
+        Ok(local) => local,
+        Err(why) => return Err(RepoError::failed(format!("failed to create the local repo: {}", why)))
+    };
+
+    let store_path = pwd.join(STORE);
+    let store = match Store::open(&store_path) {
+        Ok(store) => Arc::new(Mutex::new(store)),
+        Err(why) => return Err(RepoError::failed(format!("failed to open the build state: {}", why)))
+    };
+
+    let started = Instant::now();
+    let run_started = SystemTime::now();
+    let suite = config.archive.clone();
+    let notifications = config.notify.clone();
+    let sender = config.email.clone();
+    let names = sources.iter().map(|source| source.name.clone()).collect::<Vec<String>>();
+    let pool = pwd.join(config.pool_path());
+    let pool_bytes_before = directory_bytes(&pool);
+
+    let compiler_cache = config.compiler_cache.clone();
+    if let Some(ref cache) = compiler_cache {
+        if let Err(why) = prepare_cache(config, cache, &pwd.join(["build/", &config.archive].concat())) {
+            let directory = cache.directory.display();
+            return Err(RepoError::failed(format!("failed to prepare the compiler cache at {}: {}", directory, why)));
+        }
+    }
+
+    let cache_before = compiler_cache.as_ref().map(cache_stats);
+
+    let config = config.clone();
+    let summary_path = config.summary.clone();
+    let report_paths = if config.build_report {
+        Some((pwd.join(config.repo_path()), pwd.join(["logs/", &config.archive].concat())))
+    } else {
+        None
+    };
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let lintian_reports = reports.clone();
+    let records = store.clone();
+    let records_path = store_path.clone();
+
+    // Builds a source, and moves its packages into the pool, returning the combined size of its
+    // packages if it was built, rather than skipped as unchanged.
+    let build_source = move |source: &Source| -> Result<Option<u64>, BuildError> {
+        let relations = &local_relations[&source.name];
+        let local_repo = if relations.uses_local { Some(local.path()) } else { None };
+        let component = config.component(&source.component);
+        // An interrupted build is recorded, so that the next run builds the source again, rather
+        // than pooling the files that the build left behind.
+        let built = match build(&config, source, &pwd, &config.archive, force, local_repo) {
+            Err(BuildError::Interrupted { package }) => {
+                let mut store = store.lock().unwrap();
+                store.record_interruption(&config.archive, &package);
+                store.save(&store_path)
+                    .map_err(|why| BuildError::RecordUpdate { package: package.clone(), why })?;
+                return Err(BuildError::Interrupted { package });
+            }
+            result => result?
+        };
+
+        let build_path = pwd.join(["build/", &config.archive].concat());
+
+        // Every file that the build claims to have produced must exist, as it claims it to. A
+        // `.dsc` is republished as it was given by a source-only suite, without a `.changes`.
+        let republished = config.source_only && match source.location {
+            Some(SourceLocation::Dsc { .. }) => true,
+            _ => false
+        };
+
+        if built && !republished {
+            verify_artifacts(source, &build_path)
+                .map_err(|why| BuildError::Artifacts { package: source.name.clone(), why })?;
+        }
+
+        // Packages which fail the lintian gate are left in the build directory.
+        if let Some(thresholds) = config.lintian_for(source) {
+            let report = lintian(&build_path, &source.name, &thresholds)
+                .map_err(|why| BuildError::Lintian { package: source.name.clone(), why })?;
+            let failed = report.failed;
+            lintian_reports.lock().unwrap().push(report);
+            if failed {
+                return Err(BuildError::LintianFailure { package: source.name.clone() });
+            }
+        }
+
+        // The build is recorded before its artifacts are moved out of the build directory.
+        let mut artifact_bytes = None;
+        if built {
+            let record = build_record(&config, source, &pwd, &build_path)
+                .map_err(|why| BuildError::RecordUpdate { package: source.name.clone(), why })?;
+            artifact_bytes = Some(record.artifacts.keys()
+                .filter_map(|file| build_path.join(file).metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum());
+
+            let mut store = store.lock().unwrap();
+            store.record_build(&config.archive, &source.name, record);
+            store.save(&store_path)
+                .map_err(|why| BuildError::RecordUpdate { package: source.name.clone(), why })?;
+        }
+
+        // A held build is not pooled, nor given to the builds which depend on it, until it is approved.
+        let flags = pool_flags(config, source, force);
+        let held = hold(config, &build_path, component, flags, Some(&source.name), &source.name)
+            .map_err(|why| BuildError::Queue { package: source.name.clone(), why })?;
+
+        if !held {
+            mv_to_pool(
+                &build_path,
+                &pwd.join(config.pool_root()),
+                &config.archive,
+                component,
+                flags,
+                Some(&source.name),
+                &config.pool_policy(),
+            ).map_err(|why| BuildError::PoolMigration { why })?;
+        }
+
+        if relations.provides_local && !held {
+            let pool = pwd.join(config.pool_path()).join(component);
+            local.publish(&pool, &relations.binaries).map_err(|why| BuildError::LocalRepo { why })?;
+        }
+
+        Ok(artifact_bytes)
+    };
+
+    let summaries = Arc::new(Mutex::new(Vec::new()));
+    let source_summaries = summaries.clone();
+    let result = schedule(sources, depends, jobs, move |source| {
+        let started = Instant::now();
+        let result = build_source(source);
+        let (outcome, artifact_bytes) = match result {
+            Ok(Some(bytes)) => (Outcome::Built, bytes),
+            Ok(None) => (Outcome::Unchanged, 0),
+            Err(BuildError::Interrupted { .. }) => (Outcome::Cancelled, 0),
+            Err(_) => (Outcome::Failed, 0),
+        };
+
+        source_summaries.lock().unwrap().push(SourceSummary {
+            source: source.name.clone(),
+            outcome,
+            duration: started.elapsed(),
+            artifact_bytes,
+        });
+
+        result.map(|_| ())
+    });
+
+    report_lintian(&reports.lock().unwrap());
+
+    // Sources are summarized in the order that they were configured.
+    let mut summaries = summaries.lock().unwrap();
+    let summary = RunSummary {
+        suite,
+        sources: names.iter()
+            .map(|name| match summaries.iter().position(|summary| &summary.source == name) {
+                Some(id) => summaries.swap_remove(id),
+                None => SourceSummary {
+                    source: name.clone(),
+                    outcome: Outcome::Cancelled,
+                    duration: Duration::from_secs(0),
+                    artifact_bytes: 0,
+                },
+            })
+            .collect(),
+        duration: started.elapsed(),
+        pool_bytes_before,
+        pool_bytes_after: directory_bytes(&pool),
+        cache: compiler_cache.as_ref()
+            .and_then(|cache| cache_before.as_ref().map(|before| summarize_cache(cache, before))),
+    };
+
+    print!("{}", summary.table());
+    {
+        let count = |outcome: Outcome| summary.sources.iter().filter(|source| source.outcome == outcome).count() as u64;
+        let mut store = records.lock().unwrap();
+        store.record_outcomes(&summary.suite, count(Outcome::Built), count(Outcome::Failed));
+        if let Err(why) = store.save(&records_path) {
+            error!("failed to record the outcomes of the builds of {}: {}", summary.suite, why);
+        }
+    }
+
+    if let Some((repo, logs)) = report_paths {
+        let versions = records.lock().unwrap().builds.get(&summary.suite)
+            .map(|builds| {
+                builds.iter()
+                    .map(|(source, record)| (source.clone(), record.version.clone()))
+                    .collect::<BTreeMap<String, String>>()
+            })
+            .unwrap_or_default();
+
+        match write_report(&repo, &logs, &summary, &versions, &reports.lock().unwrap(), run_started) {
+            Ok(path) => info!("wrote the build report to {}", path.display()),
+            Err(why) => error!("failed to write the build report: {}", why)
+        }
+    }
+
+    if let Some(path) = summary_path {
+        if let Err(why) = summary.append_json(&path) {
+            return Err(RepoError::failed(format!("failed to write the build summary to {}: {}", path.display(), why)));
+        }
     }
+
+    if let Some(notifications) = notifications {
+        if result.is_err() || notifications.on_success {
+            notify(&notifications, &sender, &summary.notification());
+        }
+    }
+
+    if let Err(why) = interrupt::check() {
+        return Err(RepoError::Interrupted {
+            why: format!("{}; the cancelled builds of {} will be started by the next run", why, summary.suite)
+        });
+    }
+
+    result.map_err(|failure| RepoError::failed(format!("package '{}' failed to build: {}", failure.package, failure.why)))
 }
 
-fn repackage_binaries(packages: Option<&Vec<Direct>>, suite: &str, component: &str) -> io::Result<()> {
+/// The `.changes` files that the build of a source left in the build directory.
+fn source_changes(source: &Source, build_path: &Path) -> io::Result<Vec<Changes>> {
+    let prefix = [&source.name, "_"].concat();
+    let mut paths = build_path.read_dir()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|x| x.to_str()).map_or(false, |name| {
+            name.starts_with(&prefix) && name.ends_with(".changes")
+        }))
+        .collect::<Vec<PathBuf>>();
+
+    paths.sort();
+    paths.iter()
+        .map(|path| misc::read_to_string(path).and_then(|data| Changes::parse(&data)).map_err(|why| {
+            io::Error::new(why.kind(), format!("{}: {}", path.display(), why))
+        }))
+        .collect()
+}
+
+/// Verifies the files that each `.changes` of a source lists against the build directory,
+/// before they are moved into the pool.
+fn verify_artifacts(source: &Source, build_path: &Path) -> io::Result<()> {
+    let changes = source_changes(source, build_path)?;
+    if changes.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("the build left no .changes in {}", build_path.display())
+        ));
+    }
+
+    changes.iter().map(|changes| changes.verify(build_path)).collect()
+}
+
+/// Records the version, artifacts, and build environment of a source which was just built, from
+/// the `.changes` files that its build left in the build directory.
+fn build_record(
+    config: &Config,
+    source: &Source,
+    pwd: &Path,
+    build_path: &Path
+) -> io::Result<BuildRecord> {
+    let mut version = String::new();
+    let mut artifacts = BTreeMap::new();
+    for changes in source_changes(source, build_path)? {
+        version = changes.version;
+        for file in changes.files {
+            if let Some(sha256) = file.sha256 {
+                artifacts.insert(file.name, sha256);
+            }
+        }
+    }
+
+    let builder = config.builder_for(source);
+    let backend = builder.backend.unwrap_or_default();
+    let suite = builder.distribution.clone().unwrap_or_else(|| config.archive.clone());
+    let architectures = if config.source_only {
+        Vec::new()
+    } else {
+        build_architectures(config, &builder, source)
+    };
+
+    let chroots = architectures.into_iter()
+        .map(|arch| match backend {
+            BuilderBackend::Dpkg => "host".to_owned(),
+            _ => chroot_name(&builder, backend, &suite, arch),
+        })
+        .collect();
+
+    let state_path = pwd.join(["record/", &config.archive, "/", &source.name, ".toml"].concat());
+
+    Ok(BuildRecord {
+        version,
+        built: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs()),
+        inputs: BuildState::load(&state_path).map(|state| state.digest()),
+        backend,
+        chroots,
+        artifacts,
+    })
+}
+
+/// Summarizes the lintian tags of every source that was checked in this run.
+fn report_lintian(reports: &[LintianReport]) {
+    if reports.is_empty() {
+        return
+    }
+
+    info!("lintian report:");
+    for report in reports {
+        let status = if report.failed { "failed" } else { "passed" };
+        info!("  {} {} with {} tags", report.source, status, report.tags.len());
+        for tag in &report.tags {
+            info!("    {}", tag);
+        }
+    }
+}
+
+fn repackage_binaries(packages: Option<&Vec<Direct>>, pool: &Path, suite: &str, component: &str, policy: &RetryPolicy) -> io::Result<()> {
     if let Some(packages) = packages {
         for package in packages {
-            for destinations in package.get_destinations(suite, component).unwrap() {
+            for destinations in package.get_destinations(pool, suite, component).unwrap() {
                 let pool = &destinations.pool;
                 if let Some(&(ref files, ref source_deb)) = destinations.assets.as_ref() {
                     if needs_to_repackage(source_deb, files, pool)? {
-                        repackage(source_deb, files, pool)?;
+                        let repackaging = format!(
+                            "repackage {} as {}, rsyncing the files of {} into it",
+                            source_deb.display(),
+                            pool.display(),
+                            files.display()
+                        );
+
+                        action::perform(repackaging, || repackage(source_deb, files, pool, policy))?;
                     }
                 }
             }
@@ -134,7 +554,7 @@ fn needs_to_repackage(source: &Path, replace: &Path, pool: &Path) -> io::Result<
     Ok(false)
 }
 
-fn repackage(source: &Path, replace: &Path, pool: &Path) -> io::Result<()> {
+fn repackage(source: &Path, replace: &Path, pool: &Path, policy: &RetryPolicy) -> io::Result<()> {
     info!("repackaging {:?}", pool);
 
     debug!("source: {:?}", source);
@@ -147,16 +567,13 @@ fn repackage(source: &Path, replace: &Path, pool: &Path) -> io::Result<()> {
         fs::create_dir_all(&control_replace)?;
     }
 
-    let parent = source.parent().unwrap();
-    let data_dir = parent.join("data");
-    let control_dir = parent.join("data/DEBIAN");
-
-    if control_dir.exists() {
-        fs::remove_dir_all(&control_dir)?;
-    }
+    // The package is extracted and rebuilt within the workspace, and only then moved into the pool.
+    let extracted = workspace::stage(source);
+    let data_dir = extracted.join("data");
+    let control_dir = data_dir.join("DEBIAN");
 
-    if data_dir.exists() {
-        fs::remove_dir_all(&data_dir)?;
+    if extracted.exists() {
+        fs::remove_dir_all(&extracted)?;
     }
 
     fs::create_dir_all(&control_dir)?;
@@ -166,39 +583,102 @@ fn repackage(source: &Path, replace: &Path, pool: &Path) -> io::Result<()> {
     archive.control_extract(&control_dir)?;
 
     if data_replace.exists() {
-        rsync(&data_replace, &parent)?;
+        rsync(&data_replace, &extracted, policy)?;
     }
 
     if control_replace.exists() {
-        rsync(&control_replace, &data_dir)?;
+        rsync(&control_replace, &data_dir, policy)?;
     }
 
     fs::create_dir_all(pool.parent().unwrap())?;
-    debian::archive::build(&data_dir, pool)?;
+    let rebuilt = workspace::stage(pool);
+    debian::archive::build(&data_dir, &rebuilt)?;
+    workspace::persist(&rebuilt, pool)?;
+    fs::remove_dir_all(&extracted)
+}
 
-    Ok(())
+/// The flags that the packages of a source are moved into the pool with, which replace the
+/// published packages of the source when its build is forced.
+fn pool_flags(config: &Config, source: &Source, force: bool) -> u8 {
+    (if source.keep_source { KEEP_SOURCE } else { 0 })
+        | (if force { REPLACE } else { 0 })
+        | (if config.allow_downgrade { ALLOW_DOWNGRADE } else { 0 })
 }
 
-fn migrate_to_pool<'a , I: Iterator<Item = &'a Source>>(config: &Config, sources: I) {
+fn migrate_to_pool<'a , I: Iterator<Item = &'a Source>>(
+    config: &Config,
+    sources: I,
+    force: bool
+) -> Result<(), RepoError> {
     let build_path = ["build/", &config.archive].concat();
+    let mut store = Store::open(Path::new(STORE))
+        .map_err(|why| RepoError::failed(format!("failed to open the build state: {}", why)))?;
+
     for source in sources {
+        // The files which an interrupted build left behind may be incomplete, so they are removed,
+        // and the source is built again.
+        if store.resume(&config.archive, &source.name) {
+            info!("{} was interrupted while it was built, and will be built again", source.name);
+            let removed = remove_build_files(Path::new(&build_path), &source.name)
+                .and_then(|_| if action::is_dry_run() { Ok(()) } else { store.save(Path::new(STORE)) });
+
+            if let Err(why) = removed {
+                let name = &source.name;
+                return Err(RepoError::failed(format!("failed to remove the files of the interrupted build of {}: {}", name, why)));
+            }
+
+            continue
+        }
+
+        let component = config.component(&source.component);
+        let flags = pool_flags(config, source, force);
+        match hold(config, Path::new(&build_path), component, flags, Some(&source.name), &source.name) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(why) => return Err(RepoError::failed(format!("failed to hold '{}' in the queue: {}", source.name, why)))
+        }
+
         if let Err(why) = mv_to_pool(
             &build_path,
+            &config.pool_root(),
             &config.archive,
-            &config.default_component,
-            if source.keep_source { KEEP_SOURCE } else { 0 },
-            Some(&source.name)
+            component,
+            flags,
+            Some(&source.name),
+            &config.pool_policy(),
         ) {
-            error!("package '{}' failed to migrate to pool: {}", source.name, why);
-            exit(1);
+            return Err(RepoError::failed(format!("package '{}' failed to migrate to pool: {}", source.name, why)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the files of a source from the build directory, as they would be pooled.
+fn remove_build_files(build_path: &Path, source: &str) -> io::Result<()> {
+    if !build_path.is_dir() {
+        return Ok(());
+    }
+
+    let prefixes = [[source, "_"].concat(), [source, "-dbgsym_"].concat()];
+    for entry in build_path.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if entry.file_type()?.is_file() && prefixes.iter().any(|prefix| name.starts_with(prefix.as_str())) {
+            action::remove_file(entry.path())?;
         }
     }
+
+    Ok(())
 }
 
 #[derive(Debug, Fail)]
 pub enum BuildError {
-    #[fail(display = "command for {} failed due to {:?}", package, reason)]
-    Build { package: String, reason: subprocess::ExitStatus },
+    #[fail(display = "the artifacts of {} do not match its .changes: {}", package, why)]
+    Artifacts { package: String, why: io::Error },
+    #[fail(display = "`{}` failed to build {} with {:?}; see {:?}", command, package, reason, log)]
+    Build { package: String, command: String, log: PathBuf, reason: subprocess::ExitStatus },
     #[fail(display = "failed to get changelog for {}: {}", package, why)]
     Changelog { package: String, why: io::Error },
     #[fail(display = "{} command failed to execute: {}", cmd, why)]
@@ -209,6 +689,12 @@ pub enum BuildError {
     Debchange { why: io::Error },
     #[fail(display = "failed to create missing debian files for {:?}: {}", path, why)]
     DebFile { path: PathBuf, why: io::Error },
+    #[fail(display = "failed to overlay the debian directory of {}: {}", package, why)]
+    DebianOverlay { package: String, why: io::Error },
+    #[fail(display = "the debian directory of {} can't be fetched from {}; give it in debian/ or by a branch", package, url)]
+    DebianUrl { package: String, url: String },
+    #[fail(display = "packages depend on each other, and can't be built: {:?}", packages)]
+    DependencyCycle { packages: Vec<String> },
     #[fail(display = "failed to create directory for {:?}: {}", path, why)]
     Directory { path: PathBuf, why: io::Error },
     #[fail(display = "failed to move dsc files: {:?}", why)]
@@ -219,20 +705,42 @@ pub enum BuildError {
     GitBranch { package: String, branch: String, why: io::Error },
     #[fail(display = "failed to get git commit for {}: {}", package, why)]
     GitCommit { package: String, why: io::Error },
+    #[fail(display = "failed to run the {} hook of {}: {}", hook, package, why)]
+    Hook { package: String, hook: &'static str, why: io::Error },
+    #[fail(display = "the build of {} was interrupted", package)]
+    Interrupted { package: String },
     #[fail(display = "failed to link {:?} to {:?}: {}", src, dst, why)]
     Link { src: PathBuf, dst: PathBuf, why: io::Error },
+    #[fail(display = "failed to run lintian on {}: {}", package, why)]
+    Lintian { package: String, why: io::Error },
+    #[fail(display = "lintian tags of {} reached the failure threshold", package)]
+    LintianFailure { package: String },
+    #[fail(display = "failed to publish to the local repo: {}", why)]
+    LocalRepo { why: io::Error },
     #[fail(display = "failed due to missing dependencies")]
     MissingDependencies,
     #[fail(display = "no version listed in changelog for {}", package)]
     NoChangelogVersion { package: String },
     #[fail(display = "failed to open file at {:?}: {}", file, why)]
     Open { file: PathBuf, why: io::Error },
+    #[fail(display = "failed to generate the orig tarball of {}: {}", package, why)]
+    OrigTarball { package: String, why: io::Error },
+    #[fail(display = "failed to patch {}: {}", package, why)]
+    Patch { package: String, why: io::Error },
+    #[fail(display = "failed to migrate packages to the pool: {}", why)]
+    PoolMigration { why: io::Error },
+    #[fail(display = "failed to hold {} in the queue: {}", package, why)]
+    Queue { package: String, why: io::Error },
     #[fail(display = "failed to read file at {:?}: {}", file, why)]
     Read { file: PathBuf, why: io::Error },
     #[fail(display = "failed to update record for {}: {}", package, why)]
     RecordUpdate { package: String, why: io::Error },
-    #[fail(display = "rsyncing {:?} to {:?} failed: {}", src, dst, why)]
-    Rsync { src: PathBuf, dst: PathBuf, why: io::Error },
+    #[fail(display = "failed to build {} on the {} worker: {}", package, worker, why)]
+    Remote { package: String, worker: String, why: io::Error },
+    #[fail(display = "failed to build the source package of {}: {}", package, why)]
+    SourcePackage { package: String, why: io::Error },
+    #[fail(display = "unable to build {}: {}", package, why)]
+    UnsupportedBuilder { package: String, why: &'static str },
 }
 
 impl From<LinkError> for BuildError {
@@ -275,21 +783,66 @@ fn fetch_assets(
 }
 
 /// Attempts to build Debian packages from a given software repository.
-pub fn build(config: &Config, item: &Source, pwd: &Path, suite: &str, component: &str, force: bool) -> Result<(), BuildError> {
+///
+/// If a local repo is given, its packages are made available to the build. Returns whether the
+/// source was built, rather than skipped as unchanged.
+pub fn build(
+    config: &Config,
+    item: &Source,
+    pwd: &Path,
+    suite: &str,
+    force: bool,
+    local: Option<&Path>,
+) -> Result<bool, BuildError> {
     info!("attempting to build {}", &item.name);
     let project_directory = pwd.join(&["build/", suite, "/", &item.name].concat());
 
+    // Sources whose inputs are unchanged since their last successful build are not built again.
+    let state_path = pwd.join(["record/", suite, "/", &item.name, ".toml"].concat());
+    let state = BuildState::new(config, item, pwd).unwrap_or_else(|why| {
+        warn!("unable to determine the build state of {}: {}", item.name, why);
+        None
+    });
+
+    if let Some(ref state) = state {
+        if !force && BuildState::load(&state_path).as_ref() == Some(state) {
+            info!("{} is unchanged since its last build -- skipping", item.name);
+            return Ok(false);
+        }
+    }
+
     let mut dsc_file = None;
 
+    // The extracted, overlaid, and patched tree of a tarball is cached, and reused until any of
+    // its inputs change.
+    let tree = match state {
+        Some(ref state) if cacheable(item) => Some(tree_path(pwd, suite, state, item)),
+        _ => None
+    };
+
+    let restored = match tree {
+        Some(ref tree) if tree.exists() => {
+            restore_tree(tree, &project_directory).map_err(|why| BuildError::Extract {
+                src: tree.clone(),
+                dst: project_directory.clone(),
+                why
+            })?;
+
+            true
+        }
+        _ => false
+    };
+
     match item.location {
-        Some(SourceLocation::URL { ref url, .. }) => {
+        Some(SourceLocation::URL { .. }) if restored => (),
+        Some(SourceLocation::URL { ref url, ref sha256 }) => {
             if project_directory.exists() {
                 let _ = fs::remove_dir_all(&project_directory);
             }
 
             let _ = fs::create_dir_all(&project_directory);
             let filename = misc::filename_from_url(url);
-            let src = PathBuf::from(["assets/cache/", &item.name, "_", &filename].concat());
+            let src = cached_source(url, sha256);
             let result = if item.extract {
                 extract::extract(&src, &project_directory)
             } else {
@@ -312,13 +865,13 @@ pub fn build(config: &Config, item: &Source, pwd: &Path, suite: &str, component:
     // A list of hard-linked artifacts that will be removed at the end of the build.
     let mut linked: Vec<LinkedArtifact> = Vec::new();
 
-    if dsc_file.is_none() {
+    if dsc_file.is_none() && !restored {
         match item.debian {
-            Some(DebianPath::URL { ref url, ref checksum }) => {
-                unimplemented!()
+            Some(DebianPath::URL { ref url, .. }) => {
+                return Err(BuildError::DebianUrl { package: item.name.clone(), url: url.clone() });
             }
             Some(DebianPath::Branch { ref url, ref branch }) => {
-                merge_branch(url, branch)
+                merge_branch(url, branch, &project_directory)
                     .map_err(|why| BuildError::GitBranch {
                         package: item.name.clone(),
                         branch: branch.clone(),
@@ -328,14 +881,14 @@ pub fn build(config: &Config, item: &Source, pwd: &Path, suite: &str, component:
             None => {
                 let debian_path = pwd.join(&["debian/", suite, "/", &item.name, "/"].concat());
                 if debian_path.exists() {
-                    let project_debian_path = project_directory.join("debian/");
-                    rsync(&debian_path, &project_debian_path)
-                        .map_err(|why| BuildError::Rsync {
-                            src: debian_path,
-                            dst: project_debian_path.clone(),
+                    overlay_debian(&debian_path, &project_directory)
+                        .map_err(|why| BuildError::DebianOverlay {
+                            package: item.name.clone(),
                             why
                         })?;
 
+                    let project_debian_path = project_directory.join("debian/");
+
                     debian::create_missing_files(&project_debian_path)
                         .map_err(|why| BuildError::DebFile {
                             path: project_debian_path,
@@ -381,38 +934,95 @@ pub fn build(config: &Config, item: &Source, pwd: &Path, suite: &str, component:
         }
     }
 
-    let _ = env::set_current_dir(&["build/", suite].concat());
+    // The orig tarball of a source is generated, or linked from the upstream tarball that was
+    // extracted, once its debian files are in place.
+    let orig = match item.location {
+        Some(SourceLocation::Git { .. }) => orig::generate_orig(&project_directory),
+        Some(SourceLocation::URL { ref url, ref sha256 }) if item.extract => {
+            orig::link_orig(&cached_source(url, sha256), &project_directory)
+        }
+        _ => Ok(None)
+    };
+
+    orig.map_err(|why| BuildError::OrigTarball { package: item.name.clone(), why })?;
+
+    // Patches are applied after the orig tarball is generated, so that it remains pristine.
+    if let (Some(patches), false) = (item.patches.as_ref(), restored) {
+        let directory = pwd.join(["patches/", suite, "/", &item.name].concat());
+        apply_patches(&directory, patches, &project_directory).map_err(|why| BuildError::Patch {
+            package: item.name.clone(),
+            why
+        })?;
+    }
+
+    if let (Some(tree), false) = (tree.as_ref(), restored) {
+        if let Err(why) = store_tree(&project_directory, tree) {
+            warn!("failed to cache the prepared source of {}: {}", item.name, why);
+        }
+    }
+
+    let output_directory = pwd.join(["build/", suite].concat());
+    let source_dir = project_directory.to_string_lossy();
+    let output_dir = output_directory.to_string_lossy();
+    let hook_env = [
+        ("DEBREP_SUITE", suite),
+        ("DEBREP_SOURCE", item.name.as_str()),
+        ("DEBREP_SOURCE_DIR", &*source_dir),
+        ("DEBREP_OUTPUT_DIR", &*output_dir),
+    ];
+
+    let hooks = item.hooks.clone().unwrap_or_default();
+    if let Some(ref script) = hooks.prebuild {
+        run_hook("prebuild", script, pwd, &hook_env)
+            .map_err(|why| BuildError::Hook { package: item.name.clone(), hook: "prebuild", why })?;
+    }
 
     let skipped = pre_flight(
         config,
         item,
         &pwd,
         suite,
-        component,
         dsc_file,
         &project_directory,
         force,
+        local,
     )?;
 
     if !skipped && dsc_file.is_some() {
-        misc::copy_here(&item.name).map_err(|why| {
+        misc::copy_files(&project_directory, &output_directory).map_err(|why| {
             BuildError::DscMove { why }
         })?;
     }
 
-    let _ = env::set_current_dir("../..");
-    Ok(())
+    if let (false, Some(script)) = (skipped, hooks.postbuild.as_ref()) {
+        run_hook("postbuild", script, pwd, &hook_env).map_err(|why| {
+            BuildError::Hook { package: item.name.clone(), hook: "postbuild", why }
+        })?;
+    }
+
+    if let Some(state) = state {
+        state.save(&state_path).map_err(|why| BuildError::RecordUpdate { package: item.name.clone(), why })?;
+    }
+
+    Ok(!skipped)
 }
 
-fn merge_branch(url: &str, branch: &str) -> io::Result<()> {
-    fs::create_dir_all("/tmp/debrep")?;
-    fs::remove_dir_all("/tmp/debrep/repo")?;
+/// Copies the `debian` directory of a branch into the project, merging it with any that the
+/// project already has. The branch is cloned into a directory of its own, so concurrent builds
+/// do not share a checkout.
+fn merge_branch(url: &str, branch: &str, project: &Path) -> io::Result<()> {
+    let checkout = tempdir()?;
     Command::new("git")
-        .args(&["clone", "-b", branch, url, "/tmp/debrep/repo"])
+        .args(&["clone", "-b", branch, url])
+        .arg(checkout.path())
         .run()?;
 
+    // With `-T`, the destination is the `debian` directory itself, rather than a directory
+    // which the source would be copied into.
     Command::new("cp")
-        .args(&["-r", "/tmp/debrep/repo/debian", "."])
+        .arg("-rT")
+        .arg(checkout.path().join("debian"))
+        .arg(project.join("debian"))
         .run()
 }
 
@@ -421,13 +1031,13 @@ fn pre_flight(
     item: &Source,
     pwd: &Path,
     suite: &str,
-    component: &str,
     dsc: Option<&str>,
     dir: &Path,
-    force: bool
+    force: bool,
+    local: Option<&Path>,
 ) -> Result<bool, BuildError> {
     let name = &item.name;
-    let record_path = PathBuf::from(["../../record/", suite, "/", &name].concat());
+    let record_path = pwd.join(["record/", suite, "/", &name].concat());
 
     enum Record<'a> {
         Dsc(&'a str),
@@ -533,19 +1143,46 @@ fn pre_flight(
         return Ok(true)
     }
 
+    let builder = config.builder_for(item);
+
+    // The source package is built once, rather than by the backend for every architecture, and
+    // is given to each build. The source is moved into the pool alongside its binaries.
+    let project = dir;
     let path;
     let dir = match dsc {
         Some(dsc) => {
             path = dir.join(dsc);
             &path
         },
-        None => dir
+        None => {
+            path = build_source_package(dir).map_err(|why| BuildError::SourcePackage {
+                package: item.name.clone(),
+                why
+            })?;
+
+            // dpkg-buildpackage must be given the unpacked source instead.
+            if builder.backend.unwrap_or_default() == BuilderBackend::Dpkg { dir } else { &path }
+        }
     };
 
-    config
-        .architectures
-        .iter()
-        .try_for_each(|arch| sbuild(config, item, &pwd, suite, component, dir, arch))?;
+    // Only the source package is published by a source-only suite, whose binaries are built
+    // elsewhere.
+    if config.source_only {
+        if dsc.is_none() {
+            generate_source_changes(project).map_err(|why| BuildError::SourcePackage {
+                package: item.name.clone(),
+                why
+            })?;
+        }
+    } else {
+        let version = build_version(project, dsc);
+        build_architectures(config, &builder, item)
+            .into_iter()
+            .enumerate()
+            .try_for_each(|(id, arch)| {
+                run_builder(config, &builder, item, &pwd, suite, dir, &version, arch, id == 0, local)
+            })?;
+    }
 
     let result = match record {
         Some(Record::Dsc(dsc)) => {
@@ -570,35 +1207,41 @@ fn pre_flight(
     Ok(false)
 }
 
-fn sbuild<P: AsRef<Path>>(
+/// The architectures that the source is built for, which are those of its builder, or else of the
+/// suite, that its `archs` permit.
+fn build_architectures<'a>(
+    config: &'a Config,
+    builder: &'a Builder,
+    source: &Source
+) -> Vec<&'a str> {
+    builder.architectures.as_ref()
+        .unwrap_or(&config.architectures)
+        .iter()
+        .map(|arch| arch.as_str())
+        .filter(|arch| source.builds_for(arch))
+        .collect()
+}
+
+fn run_builder(
     config: &Config,
+    builder: &Builder,
     item: &Source,
     pwd: &Path,
     suite: &str,
-    component: &str,
-    path: P,
+    path: &Path,
+    version: &str,
     arch: &str,
+    arch_all: bool,
+    local: Option<&Path>,
 ) -> Result<(), BuildError> {
-    let log_path = pwd.join(["logs/", suite, "/", &format!("{}-{}", item.name, arch)].concat());
-    let mut command = Exec::cmd("sbuild")
-        .args(&[
-            "-v", "--log-external-command-output", "--log-external-command-error",
-            &format!("--host={}", arch),
-            // "--dpkg-source-opt=-Zgzip", // Use this when testing
-            "-d", suite
-        ])
-        .stdout(Redirection::Merge)
-        .stderr(Redirection::File(
-            fs::OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .create(true)
-                .open(&log_path)
-                .map_err(|why| BuildError::Open { file: log_path, why })?
-        ));
+    let log_path = log_path(pwd, suite, &item.name, version, arch).map_err(|why| BuildError::Open {
+        file: pwd.join(["logs/", suite, "/", &item.name].concat()),
+        why
+    })?;
 
+    let mut extra_packages = Vec::new();
     if let Some(ref depends) = item.depends {
-        let pool = pwd.join(&["repo/pool/", suite, "/", component].concat());
+        let pool = pwd.join(config.pool_path());
         let deb_iter = misc::walk_debs(&pool, false)
             .flat_map(|deb| misc::match_deb(&deb, depends));
 
@@ -611,7 +1254,7 @@ fn sbuild<P: AsRef<Path>>(
             for stored_dep in &mut temp {
                 if stored_dep.2 == name {
                     found = true;
-                    if deb_version::compare_versions(&stored_dep.3, &version) == Ordering::Less {
+                    if debian::compare_versions(&stored_dep.3, &version) == Ordering::Less {
                         stored_dep.0 = deb.clone();
                         stored_dep.1 = pos;
                         stored_dep.2 = name.clone();
@@ -629,7 +1272,7 @@ fn sbuild<P: AsRef<Path>>(
         if depends.len() != temp.len() {
             for dependency in depends {
                 if !temp.iter().any(|x| x.0.contains(dependency)) {
-                    error!("dependency for {} not found: {}", path.as_ref().display(), dependency)
+                    error!("dependency for {} not found: {}", path.display(), dependency)
                 }
             }
 
@@ -637,56 +1280,157 @@ fn sbuild<P: AsRef<Path>>(
         }
 
         temp.sort_by(|a, b| a.1.cmp(&b.1));
-        for &(ref p, _, _, _) in &temp {
-            command = command.arg(&["--extra-package=", &p].concat());
-        }
+        extra_packages.extend(temp.into_iter().map(|(deb, _, _, _)| deb));
     }
 
-    for key in &config.extra_keys {
-        command = command.arg(&format!("--extra-repository-key={}", key.display()));
-    }
+    let mut extra_repos = config.extra_repos.clone().unwrap_or_default();
+    extra_repos.extend(builder.extra_repos.iter().flat_map(|repos| repos.iter().cloned()));
 
-    if let Some(repos) = config.extra_repos.as_ref() {
-        for repo in repos {
-            command = command.arg(&["--extra-repository=", &repo].concat());
-        }
-    }
+    let build_dir = pwd.join(["build/", suite].concat());
+    let policy = config.retry_policy();
+    let remote = config.worker_for(&item.name, arch)
+        .map(|worker| RemoteBuild::new(worker, &policy, suite, &item.name, arch));
 
-    if let Some(commands) = item.prebuild.as_ref() {
-        for cmd in commands {
-            command = command.arg(&["--pre-build-commands=", &cmd].concat());
-        }
-    }
+    let remote_error = |remote: &RemoteBuild, why| BuildError::Remote {
+        package: item.name.clone(),
+        worker: remote.worker().to_owned(),
+        why
+    };
 
-    if let Some(commands) = item.starting_build.as_ref() {
-        for cmd in commands {
-            command = command.arg(&["--starting-build-commands=", &cmd].concat());
+    // A build which is dispatched to a worker is given the paths on the worker that its files
+    // were pushed to.
+    let pushed = match remote {
+        Some(ref remote) => {
+            info!("building {} for {} on the {} worker", item.name, arch, remote.worker());
+            let pushed = remote.push_build(path, &extra_packages, local, &config.extra_keys);
+            Some(pushed.map_err(|why| remote_error(remote, why))?)
         }
-    }
+        None => None
+    };
 
-    command = command.arg(path.as_ref());
+    let (path, remote_build_dir, packages, local, keys) = match pushed {
+        Some(ref pushed) => (
+            pushed.path.as_path(),
+            pushed.build_dir.as_path(),
+            &pushed.extra_packages,
+            pushed.local.as_ref().map(|local| local.as_path()),
+            &pushed.extra_keys
+        ),
+        None => (path, build_dir.as_path(), &extra_packages, local, &config.extra_keys)
+    };
+
+    // The compiler cache is shared by the builds of this host, and is not available to a worker.
+    let compiler_cache = config.compiler_cache.as_ref().filter(|_| remote.is_none());
+
+    let empty = Vec::new();
+    let request = builder::BuildRequest {
+        name: &item.name,
+        path,
+        build_dir: remote_build_dir,
+        arch,
+        arch_all,
+        distribution: builder.distribution.as_ref().map_or(suite, |x| x.as_str()),
+        extra_packages: packages,
+        local,
+        extra_repos: &extra_repos,
+        extra_keys: keys,
+        prebuild: item.prebuild.as_ref().unwrap_or(&empty),
+        starting_build: item.starting_build.as_ref().unwrap_or(&empty),
+        env: item.env.as_ref(),
+        build_flags: item.build_flags.as_ref().unwrap_or(&empty),
+        build_options: item.build_options.as_ref().unwrap_or(&empty),
+        build_profiles: item.build_profiles.as_ref().unwrap_or(&empty),
+        compiler_cache,
+    };
+
+    let builder_command = builder::command(builder, &request)?;
+    let program = builder_command.program;
+    let command_line = [program, " ", &builder_command.args.join(" ")].concat();
+
+    // Builds run concurrently, so the working directory is given to each build instead of
+    // being changed for the whole process.
+    let command = match remote {
+        Some(ref remote) => remote.command(&builder_command),
+        None => builder_command.env.iter()
+            .fold(Exec::cmd(program), |command, &(ref key, ref value)| command.env(key, value))
+            .args(&builder_command.args)
+            .cwd(&builder_command.cwd)
+    };
+
+    let command = command
+        .stdout(Redirection::Merge)
+        .stderr(Redirection::File(
+            fs::OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .create(true)
+                .open(&log_path)
+                .map_err(|why| BuildError::Open { file: log_path.clone(), why })?
+        ));
 
     debug!("executing {:#?}", command);
 
-    let exit_status = command.join()
+    let exit_status = command.popen()
+        .and_then(|mut process| wait_for_build(&mut process))
         .map_err(|why| BuildError::Command {
-            cmd: "sbuild",
+            cmd: program,
             why: io::Error::new(
                 io::ErrorKind::Other,
                 format!("{:?}", why)
             )
         })?;
 
-    if exit_status.success() {
-        Ok(())
+    if let Some(directory) = log_path.parent() {
+        if let Err(why) = prune_logs(directory, config.keep_logs.unwrap_or(DEFAULT_KEEP_LOGS)) {
+            warn!("failed to prune the build logs of {}: {}", item.name, why);
+        }
+    }
+
+    if interrupt::is_interrupted() && !exit_status.success() {
+        Err(BuildError::Interrupted { package: item.name.clone() })
+    } else if exit_status.success() {
+        match remote {
+            Some(ref remote) => remote.pull(&item.name, &build_dir).map_err(|why| remote_error(remote, why)),
+            None => Ok(())
+        }
     } else {
+        // Only the end of the log is printed, as the full logs of concurrent builds are unreadable
+        // when they are interleaved on the console.
+        match tail(&log_path) {
+            Ok(tail) => error!("the build of {} for {} failed:\n{}", item.name, arch, tail),
+            Err(why) => warn!("failed to read {}: {}", log_path.display(), why)
+        }
+
         Err(BuildError::Build {
             package: item.name.clone(),
+            command: command_line,
+            log: log_path,
             reason: exit_status
         })
     }
 }
 
+/// Waits for a builder to exit. Once the run is interrupted, the builder is terminated, so that it
+/// may end its chroot session, and it is killed if it has not exited within a minute of that.
+fn wait_for_build(process: &mut subprocess::Popen) -> subprocess::Result<subprocess::ExitStatus> {
+    loop {
+        if let Some(status) = process.wait_timeout(Duration::from_millis(250))? {
+            return Ok(status);
+        }
+
+        if interrupt::is_interrupted() {
+            process.terminate()?;
+            if let Some(status) = process.wait_timeout(Duration::from_secs(60))? {
+                return Ok(status);
+            }
+
+            warn!("the builder did not exit within a minute of being terminated, and will be killed");
+            process.kill()?;
+            return process.wait();
+        }
+    }
+}
+
 fn debchange_git(suite: &str, version: &str, project_directory: &Path, branch: &Option<String>, commit: &Option<String>) -> io::Result<()> {
     let commit_;
     let mut commit = match commit {
@@ -0,0 +1,133 @@
+use command::Command;
+use misc;
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use super::super::prepare::upstream;
+
+/// Generates the orig tarball of a `3.0 (quilt)` source from the checked-out revision of its git
+/// repository, beside the project directory where `dpkg-source` expects to find it.
+///
+/// The debian directory is excluded from the tarball. Native sources do not have an orig
+/// tarball, so `None` is returned for them.
+pub fn generate_orig(project: &Path) -> io::Result<Option<PathBuf>> {
+    let (source, upstream) = match quilt_upstream(project)? {
+        Some(names) => names,
+        None => return Ok(None)
+    };
+
+    let orig = orig_directory(project).join([&source, "_", &upstream, ".orig.tar.gz"].concat());
+
+    info!("generating {}", orig.display());
+    Command::new("git")
+        .arg("-C")
+        .arg(project)
+        .arg("archive")
+        .arg("--format=tar.gz")
+        .arg(["--prefix=", &source, "-", &upstream, "/"].concat())
+        .arg("-o")
+        .arg(&orig)
+        .args(&["HEAD", "--", ".", ":(exclude)debian"])
+        .run()?;
+
+    Ok(Some(orig))
+}
+
+/// Links the upstream tarball of a `3.0 (quilt)` source beside its extracted project, as the
+/// orig tarball which `dpkg-source` expects, so that the tarball does not need to be repacked.
+///
+/// Archives which `dpkg-source` can't accept as an orig tarball, such as zip archives, are
+/// instead repacked from the extracted project, without its debian directory.
+pub fn link_orig(tarball: &Path, project: &Path) -> io::Result<Option<PathBuf>> {
+    let (source, upstream) = match quilt_upstream(project)? {
+        Some(names) => names,
+        None => return Ok(None)
+    };
+
+    let filename = tarball.file_name().and_then(|x| x.to_str()).unwrap_or("");
+    let extension = ORIG_EXTENSIONS.iter()
+        .find(|&&(suffix, _)| filename.ends_with(suffix))
+        .map(|&(_, extension)| extension);
+
+    let orig = orig_directory(project)
+        .join([&source, "_", &upstream, ".orig", extension.unwrap_or(".tar.gz")].concat());
+
+    if orig.symlink_metadata().is_ok() {
+        fs::remove_file(&orig)?;
+    }
+
+    if extension.is_some() {
+        info!("linking {} to {}", tarball.display(), orig.display());
+        fs::hard_link(tarball, &orig).or_else(|_| misc::copy(tarball, &orig))?;
+    } else {
+        info!("repacking {} as {}", tarball.display(), orig.display());
+        Command::new("tar")
+            .args(&["--create", "--gzip", "--exclude=./debian", "--file"])
+            .arg(&orig)
+            .arg(["--transform=s,^\\.,", &source, "-", &upstream, ","].concat())
+            .arg("-C")
+            .arg(project)
+            .arg(".")
+            .run()?;
+    }
+
+    Ok(Some(orig))
+}
+
+/// The upstream archives which `dpkg-source` accepts as an orig tarball, and the extension that
+/// the orig tarball is named with.
+const ORIG_EXTENSIONS: &[(&str, &str)] = &[
+    (".tar.gz", ".tar.gz"),
+    (".tgz", ".tar.gz"),
+    (".tar.xz", ".tar.xz"),
+    (".tar.bz2", ".tar.bz2"),
+    (".tbz2", ".tar.bz2"),
+];
+
+/// The source name and upstream version which name the orig tarball of a `3.0 (quilt)` project,
+/// or `None` if the project has any other format.
+fn quilt_upstream(project: &Path) -> io::Result<Option<(String, String)>> {
+    let format = misc::read_to_string(project.join("debian/source/format")).unwrap_or_default();
+    if format.trim() != "3.0 (quilt)" {
+        return Ok(None);
+    }
+
+    let changelog = misc::read_to_string(project.join("debian/changelog"))?;
+    let (source, version) = changelog_head(&changelog).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("no source and version in the changelog of {}", project.display())
+    ))?;
+
+    let version = version.find(':').map_or(version, |pos| &version[pos + 1..]);
+    Ok(Some((source.to_owned(), upstream(version).to_owned())))
+}
+
+/// The directory beside the project, where `dpkg-source` looks for the orig tarball.
+fn orig_directory(project: &Path) -> &Path {
+    project.parent().unwrap_or_else(|| Path::new("."))
+}
+
+/// The source name and version of the most recent entry of a changelog.
+pub(crate) fn changelog_head(changelog: &str) -> Option<(&str, &str)> {
+    let line = changelog.lines().find(|line| !line.trim().is_empty())?;
+    let mut fields = line.split_whitespace();
+    let source = fields.next()?;
+    let version = fields.next()?;
+    if version.len() > 2 && version.starts_with('(') && version.ends_with(')') {
+        Some((source, &version[1..version.len() - 1]))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changelog_source_and_version() {
+        let changelog = "\nfoo (1:2.0-1~1540000000~18.04~abc123) bionic; urgency=medium\n\n  * Release\n";
+        assert_eq!(changelog_head(changelog), Some(("foo", "1:2.0-1~1540000000~18.04~abc123")));
+        assert_eq!(changelog_head("foo 2.0 bionic\n"), None);
+        assert_eq!(changelog_head(""), None);
+    }
+}
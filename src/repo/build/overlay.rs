@@ -0,0 +1,86 @@
+use std::{fs, io};
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// Overlays the debian directory of a source, from the config tree, onto its extracted project.
+///
+/// The files of the overlay are merged into the project's own debian directory, if it has one,
+/// and take precedence over any upstream files at the same paths. Upstream files which the
+/// overlay does not replace are kept.
+pub fn overlay_debian(debian: &Path, project: &Path) -> io::Result<()> {
+    let dst = project.join("debian");
+    info!("overlaying {} onto {}", debian.display(), dst.display());
+    fs::create_dir_all(&dst)?;
+
+    for entry in WalkDir::new(debian).min_depth(1) {
+        let entry = entry.map_err(|why| io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to walk {}: {}", debian.display(), why)
+        ))?;
+
+        let target = dst.join(entry.path().strip_prefix(debian).unwrap());
+        let file_type = entry.file_type();
+
+        if file_type.is_dir() {
+            if target.symlink_metadata().map(|x| !x.is_dir()).unwrap_or(false) {
+                fs::remove_file(&target)?;
+            }
+
+            fs::create_dir_all(&target)?;
+            continue
+        }
+
+        // A directory which would be replaced by a file is discarded, along with its contents.
+        match target.symlink_metadata() {
+            Ok(ref metadata) if metadata.is_dir() => fs::remove_dir_all(&target)?,
+            Ok(_) => fs::remove_file(&target)?,
+            Err(_) => ()
+        }
+
+        if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn debian_overlay_merges() {
+        let dir = tempdir().unwrap();
+        let debian = dir.path().join("debian/bionic/foo");
+        let project = dir.path().join("build/bionic/foo");
+        fs::create_dir_all(debian.join("source")).unwrap();
+        fs::create_dir_all(project.join("debian")).unwrap();
+
+        misc::write(debian.join("control"), b"Source: foo\n").unwrap();
+        misc::write(debian.join("source/format"), b"3.0 (quilt)\n").unwrap();
+        symlink("control", debian.join("control.link")).unwrap();
+        misc::write(project.join("debian/control"), b"Source: upstream\n").unwrap();
+        misc::write(project.join("debian/copyright"), b"upstream\n").unwrap();
+        misc::write(project.join("README"), b"readme\n").unwrap();
+
+        overlay_debian(&debian, &project).unwrap();
+        let read = |path: &str| misc::read_to_string(project.join(path)).unwrap();
+        assert_eq!(read("debian/control"), "Source: foo\n");
+        assert_eq!(read("debian/source/format"), "3.0 (quilt)\n");
+        assert_eq!(read("debian/copyright"), "upstream\n");
+        assert_eq!(read("README"), "readme\n");
+        assert_eq!(fs::read_link(project.join("debian/control.link")).unwrap(), Path::new("control"));
+
+        // Projects which ship no packaging are given the overlay as their debian directory.
+        let bare = dir.path().join("build/bionic/bare");
+        fs::create_dir_all(&bare).unwrap();
+        overlay_debian(&debian, &bare).unwrap();
+        assert_eq!(misc::read_to_string(bare.join("debian/control")).unwrap(), "Source: foo\n");
+    }
+}
@@ -0,0 +1,211 @@
+use misc;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Applies the patch series of a source to its extracted project, in the order that they are
+/// listed, before the project is built.
+///
+/// The strip level of each patch is detected from the files that it modifies, as quilt does,
+/// falling back to `-p1`. Each patch is tried before it is applied, so that a patch which
+/// conflicts fails with its offending hunks, rather than leaving its files partially patched.
+pub fn apply_patches(directory: &Path, patches: &[String], project: &Path) -> io::Result<()> {
+    for name in patches {
+        let path = directory.join(name);
+        let data = misc::read_to_string(&path).map_err(|why| io::Error::new(
+            why.kind(),
+            format!("failed to read the patch at {}: {}", path.display(), why)
+        ))?;
+
+        let strip = strip_level(&data, project);
+        info!("applying {} to {} with -p{}", name, project.display(), strip);
+
+        let dry_run = patch(project, &path, strip).arg("--dry-run").output()?;
+        if !dry_run.status.success() {
+            let output = String::from_utf8_lossy(&dry_run.stdout);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, conflict(name, &data, &output)));
+        }
+
+        let status = patch(project, &path, strip).arg("--silent").status()?;
+        if !status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} failed to apply with exit status: {}", name, status)
+            ));
+        }
+    }
+
+    // The changes are recorded by dpkg-source as a patch of the debian directory, instead of
+    // being rejected as unrecorded changes to the orig tarball.
+    if !patches.is_empty() && is_quilt(project) {
+        let options = project.join("debian/source/options");
+        let current = misc::read_to_string(&options).unwrap_or_default();
+        if !current.lines().any(|line| line.trim() == "auto-commit") {
+            let separator = if current.is_empty() || current.ends_with('\n') { "" } else { "\n" };
+            misc::write(&options, [current.as_str(), separator, "auto-commit\n"].concat())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn patch(project: &Path, path: &Path, strip: usize) -> Command {
+    let mut command = Command::new("patch");
+    command.arg("-d")
+        .arg(project)
+        .arg(format!("-p{}", strip))
+        .args(&["--forward", "--batch", "--no-backup-if-mismatch", "-i"])
+        .arg(path);
+    command
+}
+
+fn is_quilt(project: &Path) -> bool {
+    misc::read_to_string(project.join("debian/source/format"))
+        .map(|format| format.trim() == "3.0 (quilt)")
+        .unwrap_or(false)
+}
+
+/// The paths of the files which a patch modifies, as they are named in its headers.
+fn patched_files(patch: &str) -> Vec<&str> {
+    patch.lines()
+        .filter(|line| line.starts_with("--- ") || line.starts_with("+++ "))
+        .map(|line| line[4..].split('\t').next().unwrap_or("").trim())
+        .filter(|path| !path.is_empty() && *path != "/dev/null")
+        .collect()
+}
+
+/// Selects the strip level at which the files of the patch exist within the project. Patches
+/// which only create files are assumed to be `-p1` patches.
+fn strip_level(patch: &str, project: &Path) -> usize {
+    let files = patched_files(patch);
+    for &strip in &[1, 0] {
+        let exists = files.iter()
+            .filter_map(|path| strip_path(path, strip))
+            .any(|path| project.join(path).is_file());
+
+        if exists {
+            return strip;
+        }
+    }
+
+    1
+}
+
+fn strip_path(path: &str, strip: usize) -> Option<&str> {
+    let mut path = path;
+    for _ in 0..strip {
+        path = &path[path.find('/')? + 1..];
+    }
+
+    Some(path)
+}
+
+/// Describes the hunks that failed to apply, given the output of `patch`.
+fn conflict(name: &str, patch: &str, output: &str) -> String {
+    let mut message = format!("{} does not apply", name);
+    let mut file = "";
+
+    for line in output.lines() {
+        if line.starts_with("patching file ") {
+            file = line["patching file ".len()..].trim_matches(|c| c == '\'' || c == ' ');
+        } else if line.starts_with("Hunk #") && line.contains("FAILED") {
+            let number = line["Hunk #".len()..].split_whitespace().next()
+                .and_then(|number| number.parse::<usize>().ok());
+
+            message.push_str(&format!("\n{}: {}", file, line.trim()));
+            if let Some(hunk) = number.and_then(|number| find_hunk(patch, file, number)) {
+                message.push('\n');
+                message.push_str(&hunk);
+            }
+        } else if line.contains("can't find file")
+            || line.contains("No file to patch")
+            || line.contains("previously applied")
+        {
+            message.push_str(&format!("\n{}", line.trim()));
+        }
+    }
+
+    message
+}
+
+/// The text of the given hunk, counting from one, of the file in the patch.
+fn find_hunk(patch: &str, file: &str, number: usize) -> Option<String> {
+    let mut lines = patch.lines().peekable();
+    let mut in_file = false;
+    let mut hunk = 0;
+
+    while let Some(line) = lines.next() {
+        if line.starts_with("+++ ") {
+            let path = line[4..].split('\t').next().unwrap_or("").trim();
+            in_file = !file.is_empty() && (path == file || path.ends_with(&["/", file].concat()));
+            hunk = 0;
+        } else if in_file && line.starts_with("@@") {
+            hunk += 1;
+            if hunk == number {
+                let mut text = line.to_owned();
+                loop {
+                    let line = match lines.peek().cloned() {
+                        Some(line) if is_hunk_line(line) => line,
+                        _ => break
+                    };
+
+                    text.push('\n');
+                    text.push_str(line);
+                    lines.next();
+                }
+
+                return Some(text);
+            }
+        }
+    }
+
+    None
+}
+
+fn is_hunk_line(line: &str) -> bool {
+    let body = line.starts_with(|c| c == ' ' || c == '+' || c == '-' || c == '\\');
+    body && !line.starts_with("--- ") && !line.starts_with("+++ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const PATCH: &str = "Description: fixes foo\n\
+        --- a/src/foo.c\t2018-10-01\n\
+        +++ b/src/foo.c\t2018-10-02\n\
+        @@ -1,2 +1,2 @@\n \
+        int a;\n\
+        -int b;\n\
+        +long b;\n\
+        @@ -10 +10 @@\n\
+        -return 0;\n\
+        +return 1;\n";
+
+    #[test]
+    fn patch_strip_levels() {
+        assert_eq!(patched_files(PATCH), vec!["a/src/foo.c", "b/src/foo.c"]);
+        assert_eq!(strip_path("a/src/foo.c", 1), Some("src/foo.c"));
+        assert_eq!(strip_path("foo.c", 1), None);
+
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/src")).unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        assert_eq!(strip_level(PATCH, dir.path()), 1);
+        misc::write(dir.path().join("a/src/foo.c"), b"int a;\n").unwrap();
+        assert_eq!(strip_level(PATCH, dir.path()), 0);
+        misc::write(dir.path().join("src/foo.c"), b"int a;\n").unwrap();
+        assert_eq!(strip_level(PATCH, dir.path()), 1);
+    }
+
+    #[test]
+    fn conflicting_hunk() {
+        let output = "patching file src/foo.c\nHunk #2 FAILED at 10.\n1 out of 2 hunks FAILED\n";
+        assert_eq!(
+            conflict("fix-foo.patch", PATCH, output),
+            "fix-foo.patch does not apply\nsrc/foo.c: Hunk #2 FAILED at 10.\n@@ -10 +10 @@\n-return 0;\n+return 1;"
+        );
+    }
+}
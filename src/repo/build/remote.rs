@@ -0,0 +1,287 @@
+use config::{RetryPolicy, Worker};
+use debian::{parse_control, Changes};
+use misc;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use subprocess::Exec;
+use super::builder::BuilderCommand;
+use super::super::rsync::transfer;
+use workspace;
+
+/// The directories of a remote build which the files that it is given are pushed into.
+const DIRECTORIES: &[&str] = &["build", "extra", "keys", "local", "source"];
+
+/// The paths on the worker of the files that a remote build was given.
+pub(crate) struct Pushed {
+    pub path: PathBuf,
+    pub build_dir: PathBuf,
+    pub extra_packages: Vec<String>,
+    pub local: Option<PathBuf>,
+    pub extra_keys: Vec<PathBuf>,
+}
+
+/// The build of a source for one architecture which is dispatched to a worker. Each build has a
+/// directory of its own on the worker, at `<directory>/<suite>/<source>/<arch>`, that the source
+/// and the packages and keys of the build are pushed into, and which the backend builds within.
+pub(crate) struct RemoteBuild<'a> {
+    worker: &'a Worker,
+    policy: &'a RetryPolicy,
+    root: String,
+}
+
+impl<'a> RemoteBuild<'a> {
+    pub fn new(worker: &'a Worker, policy: &'a RetryPolicy, suite: &str, source: &str, arch: &str) -> Self {
+        RemoteBuild {
+            worker,
+            policy,
+            root: [worker.directory(), suite, source, arch].join("/"),
+        }
+    }
+
+    pub fn worker(&self) -> &str {
+        &self.worker.name
+    }
+
+    /// Pushes the source to the worker, along with the packages, local repo, and keys that its
+    /// build is given, into a directory which the previous build of the source may have left.
+    pub fn push_build(
+        &self,
+        path: &Path,
+        extra_packages: &[String],
+        local: Option<&Path>,
+        extra_keys: &[PathBuf]
+    ) -> io::Result<Pushed> {
+        self.prepare()?;
+        Ok(Pushed {
+            path: self.push_source(path)?,
+            build_dir: PathBuf::from([&self.root, "/build"].concat()),
+            extra_packages: self.push("extra", extra_packages)?
+                .into_iter()
+                .map(|deb| deb.display().to_string())
+                .collect(),
+            local: match local {
+                Some(local) => Some(self.push("local", &[local])?.remove(0)),
+                None => None
+            },
+            extra_keys: self.push("keys", extra_keys)?,
+        })
+    }
+
+    fn prepare(&self) -> io::Result<()> {
+        let directories = DIRECTORIES.iter()
+            .map(|directory| quote(&[&self.root, "/", directory].concat()))
+            .collect::<Vec<String>>();
+
+        self.ssh(&format!("rm -rf {} && mkdir -p {}", quote(&self.root), directories.join(" ")))
+    }
+
+    /// Pushes the source, returning its path on the worker. A `.dsc` is pushed along with the
+    /// files that it lists, and an unpacked source along with the orig tarballs beside it.
+    fn push_source(&self, path: &Path) -> io::Result<PathBuf> {
+        let parent = path.parent().unwrap_or_else(|| Path::new("."));
+        let beside = if path.is_dir() {
+            fs::read_dir(parent)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|file| file.is_file() && file.file_name().map_or(false, |name| name.to_string_lossy().contains(".orig")))
+                .collect()
+        } else {
+            dsc_files(path)?.into_iter().map(|file| parent.join(file)).collect::<Vec<PathBuf>>()
+        };
+
+        self.push("source", &beside)?;
+        Ok(self.push("source", &[path])?.remove(0))
+    }
+
+    /// Pushes files, or directories, into a directory of the build, returning their paths on
+    /// the worker.
+    fn push<P: AsRef<Path>>(&self, directory: &str, paths: &[P]) -> io::Result<Vec<PathBuf>> {
+        let mut pushed = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            let name = path.file_name().ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} can not be pushed to a worker", path.display())
+            ))?;
+
+            let remote = [&self.root, "/", directory, "/", &name.to_string_lossy()].concat();
+
+            // The contents of a directory are pushed into a directory of the same name.
+            let (source, destination) = if path.is_dir() {
+                ([&path.display().to_string(), "/"].concat(), [&remote, "/"].concat())
+            } else {
+                (path.display().to_string(), remote.clone())
+            };
+
+            debug!("pushing {} to {}", source, self.worker.name);
+            self.transfer(&source, &[&self.worker.host, ":", &destination].concat())?;
+            pushed.push(PathBuf::from(remote));
+        }
+
+        Ok(pushed)
+    }
+
+    /// The command which runs the backend's command on the worker. ssh is made to allocate a
+    /// terminal, so that the build is hung up on if ssh is terminated, as when the run is
+    /// interrupted.
+    pub fn command(&self, command: &BuilderCommand) -> Exec {
+        let mut script = format!("cd {} && env", quote(&command.cwd.display().to_string()));
+        for &(ref key, ref value) in &command.env {
+            script.push(' ');
+            script.push_str(&quote(&[key, "=", value].concat()));
+        }
+
+        script.push(' ');
+        script.push_str(command.program);
+        for arg in &command.args {
+            script.push(' ');
+            script.push_str(&quote(arg));
+        }
+
+        Exec::cmd("ssh")
+            .arg("-tt")
+            .args(&self.worker.ssh_args())
+            .arg(&self.worker.host)
+            .arg(script)
+    }
+
+    /// Pulls the build back from the worker, and moves the files that each `.changes` of the
+    /// source lists into the build directory, once every one of them matches its checksums.
+    pub fn pull(&self, source: &str, build_dir: &Path) -> io::Result<()> {
+        let staged = workspace::stage(&build_dir.join([source, ".remote"].concat()));
+        workspace::register(&staged)?;
+        fs::create_dir_all(&staged)?;
+
+        let remote = [&self.worker.host, ":", &self.root, "/build/"].concat();
+        info!("pulling the build of {} from {}", source, self.worker.name);
+        let result = self.transfer(&remote, &[&staged.display().to_string(), "/"].concat())
+            .and_then(|_| accept(&staged, source, build_dir));
+
+        let _ = fs::remove_dir_all(&staged);
+        result
+    }
+
+    fn ssh(&self, script: &str) -> io::Result<()> {
+        let status = Exec::cmd("ssh")
+            .args(&self.worker.ssh_args())
+            .arg(&self.worker.host)
+            .arg(script)
+            .join()
+            .map_err(|why| io::Error::new(io::ErrorKind::Other, format!("failed to run ssh: {:?}", why)))?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("`{}` failed on {} with {:?}", script, self.worker.name, status)
+            ))
+        }
+    }
+
+    fn transfer(&self, source: &str, destination: &str) -> io::Result<()> {
+        let shell = ["ssh ", &self.worker.ssh_args().iter().map(|arg| quote(arg)).collect::<Vec<String>>().join(" ")].concat();
+        let args = ["-az", "-e", shell.trim_right()];
+        transfer(OsStr::new(source), OsStr::new(destination), &args, self.policy)
+    }
+}
+
+/// The files that a `.dsc` lists, which are beside it.
+fn dsc_files(dsc: &Path) -> io::Result<Vec<String>> {
+    let control = parse_control(&misc::read_to_string(dsc)?);
+    Ok(control.get("Files")
+        .into_iter()
+        .flat_map(|files| files.lines())
+        .filter_map(|line| line.split_whitespace().nth(2))
+        .map(String::from)
+        .collect())
+}
+
+/// Verifies the files of a build which was pulled into `staged` against each `.changes` of the
+/// source, and then moves them into the build directory. Nothing is moved unless every file
+/// matches, and files which no `.changes` lists, such as the logs of the backend, are left out.
+fn accept(staged: &Path, source: &str, build_dir: &Path) -> io::Result<()> {
+    let prefix = [source, "_"].concat();
+    let mut changes = Vec::new();
+    for entry in staged.read_dir()? {
+        let path = entry?.path();
+        let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        if name.starts_with(&prefix) && name.ends_with(".changes") {
+            let parsed = misc::read_to_string(&path).and_then(|data| Changes::parse(&data));
+            changes.push((name.clone(), parsed.map_err(|why| io::Error::new(why.kind(), format!("{}: {}", name, why)))?));
+        }
+    }
+
+    if changes.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("the worker's build of {} left no .changes", source)));
+    }
+
+    let mut files = BTreeSet::new();
+    for &(ref name, ref changes) in &changes {
+        changes.verify(staged).map_err(|why| io::Error::new(why.kind(), format!("{}: {}", name, why)))?;
+        files.insert(name.clone());
+        files.extend(changes.files.iter().map(|file| file.name.clone()));
+    }
+
+    for file in files {
+        workspace::persist(&staged.join(&file), &build_dir.join(&file))?;
+    }
+
+    Ok(())
+}
+
+/// Quotes an argument for the shell of the worker, unless it is made only of characters which
+/// the shell does not interpret.
+fn quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_owned()
+    } else {
+        ["'", &arg.replace('\'', "'\\''"), "'"].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const MD5: &str = "f1d3ff8443297732862df21dc4e57262";
+    const SHA256: &str = "df3f619804a92fdb4057192dc43dd748ea778adc52bc498ce80524c014b81119";
+
+    #[test]
+    fn accepted_builds() {
+        let dir = tempdir().unwrap();
+        let (staged, build_dir) = (dir.path().join("staged"), dir.path().join("build"));
+        fs::create_dir(&staged).unwrap();
+        fs::create_dir(&build_dir).unwrap();
+
+        let changes = format!(
+            "Source: foo\nVersion: 1.0\nMaintainer: Foo <foo@example.com>\n\
+             Checksums-Sha256:\n {} 4 foo_1.0_arm64.deb\n\
+             Files:\n {} 4 utils optional foo_1.0_arm64.deb\n",
+            SHA256, MD5
+        );
+
+        misc::write(staged.join("foo_1.0_arm64.changes"), changes.as_bytes()).unwrap();
+        misc::write(staged.join("foo_1.0_arm64.deb"), b"\0\0\0\x01").unwrap();
+        misc::write(staged.join("foo_1.0_arm64.build"), b"log").unwrap();
+
+        // A file which does not match its checksums keeps the whole build out.
+        assert_eq!(accept(&staged, "foo", &build_dir).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(build_dir.read_dir().unwrap().count(), 0);
+
+        misc::write(staged.join("foo_1.0_arm64.deb"), b"\0\0\0\0").unwrap();
+        accept(&staged, "foo", &build_dir).unwrap();
+        assert!(build_dir.join("foo_1.0_arm64.deb").exists() && build_dir.join("foo_1.0_arm64.changes").exists());
+        assert!(!build_dir.join("foo_1.0_arm64.build").exists());
+        assert!(accept(&staged, "bar", &build_dir).is_err());
+
+        assert_eq!(quote("--arch=arm64"), "--arch=arm64");
+        assert_eq!(quote("echo 'hi' > x"), "'echo '\\''hi'\\'' > x'");
+        assert_eq!(quote(""), "''");
+    }
+}
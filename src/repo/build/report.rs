@@ -0,0 +1,257 @@
+use action;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use super::lintian::LintianReport;
+use super::summary::{Outcome, RunSummary};
+
+/// The directory of the repo which the reports of each suite are written to.
+const REPORTS: &str = "reports";
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+tr.failed { background: #fdd; }
+tr.cancelled { background: #eee; }
+ul { margin: 0; padding-left: 1.2em; }
+";
+
+/// Writes a static HTML report of the run to `reports/<suite>/index.html` within the repo, which
+/// lists the version, outcome, duration, and lintian tags of each source, and links to the logs
+/// which its builds wrote during the run. The logs are copied beside the report, as the logs of
+/// the project are not published, and `reports/index.html` is rewritten to list every suite.
+pub fn write_report(
+    repo: &Path,
+    logs: &Path,
+    summary: &RunSummary,
+    versions: &BTreeMap<String, String>,
+    lintian: &[LintianReport],
+    since: SystemTime,
+) -> io::Result<PathBuf> {
+    let reports = repo.join(REPORTS);
+    let directory = reports.join(&summary.suite);
+    let log_copies = directory.join("logs");
+    if log_copies.exists() {
+        action::remove_dir_all(&log_copies)?;
+    }
+
+    let mut linked = BTreeMap::new();
+    for source in &summary.sources {
+        let written = run_logs(&logs.join(&source.source), since)?;
+        if written.is_empty() {
+            continue
+        }
+
+        let destination = log_copies.join(&source.source);
+        action::create_dir_all(&destination)?;
+        let mut names = Vec::new();
+        for log in written {
+            let name = log.file_name().unwrap().to_string_lossy().into_owned();
+            action::copy(&log, destination.join(&name))?;
+            names.push(["logs/", &source.source, "/", &name].concat());
+        }
+
+        linked.insert(source.source.clone(), names);
+    }
+
+    let generated = Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
+    let report = directory.join("index.html");
+    action::create_dir_all(&directory)?;
+    action::write(&report, render(summary, versions, lintian, &linked, &generated))?;
+
+    let mut suites = reports.read_dir()
+        .map(|entries| {
+            entries.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("index.html").exists())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    if !suites.contains(&summary.suite) {
+        suites.push(summary.suite.clone());
+    }
+
+    suites.sort();
+    action::write(reports.join("index.html"), render_index(&suites))?;
+    Ok(report)
+}
+
+/// The logs of a source which were written since the run started, sorted by their name.
+fn run_logs(directory: &Path, since: SystemTime) -> io::Result<Vec<PathBuf>> {
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut logs = Vec::new();
+    for entry in directory.read_dir()? {
+        let entry = entry?;
+        if entry.metadata()?.modified()? >= since {
+            logs.push(entry.path());
+        }
+    }
+
+    logs.sort();
+    Ok(logs)
+}
+
+/// The report of a run, which links to the logs of each source by their path from the report.
+fn render(
+    summary: &RunSummary,
+    versions: &BTreeMap<String, String>,
+    lintian: &[LintianReport],
+    logs: &BTreeMap<String, Vec<String>>,
+    generated: &str,
+) -> String {
+    let mut html = String::new();
+    let suite = escape(&summary.suite);
+    let cache = summary.cache.as_ref()
+        .map_or_else(String::new, |cache| format!("<p>{}.</p>\n", escape(&cache.to_string())));
+
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{} build report</title>\n\
+         <style>\n{}</style>\n</head>\n<body>\n<h1>{}</h1>\n\
+         <p>{} built, {} unchanged, {} failed, {} cancelled in {}s; the pool changed by {} bytes.</p>\n\
+         {}<p>Generated {}.</p>\n<table>\n\
+         <tr><th>Source</th><th>Version</th><th>Outcome</th><th>Duration</th><th>Artifacts</th>\
+         <th>Lintian</th><th>Logs</th></tr>\n",
+        suite,
+        STYLE,
+        suite,
+        summary.count(Outcome::Built),
+        summary.count(Outcome::Unchanged),
+        summary.count(Outcome::Failed),
+        summary.count(Outcome::Cancelled),
+        summary.duration.as_secs(),
+        summary.pool_delta(),
+        cache,
+        escape(generated)
+    );
+
+    for source in &summary.sources {
+        let tags = lintian.iter()
+            .find(|report| report.source == source.source)
+            .map_or_else(String::new, |report| {
+                let tags = report.tags.iter()
+                    .map(|tag| ["<li>", &escape(&tag.to_string()), "</li>"].concat())
+                    .collect::<String>();
+
+                match (report.failed, tags.is_empty()) {
+                    (true, _) => ["failed<ul>", &tags, "</ul>"].concat(),
+                    (false, true) => "passed".to_owned(),
+                    (false, false) => ["passed<ul>", &tags, "</ul>"].concat(),
+                }
+            });
+
+        let links = logs.get(&source.source).map_or_else(String::new, |logs| {
+            logs.iter()
+                .map(|log| {
+                    let name = log.rsplit('/').next().unwrap_or(log);
+                    format!("<li><a href=\"{}\">{}</a></li>", escape(log), escape(name))
+                })
+                .collect::<String>()
+        });
+
+        let _ = write!(
+            html,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}s</td><td>{} bytes</td><td>{}</td><td>{}</td></tr>\n",
+            source.outcome,
+            escape(&source.source),
+            versions.get(&source.source).map_or_else(String::new, |version| escape(version)),
+            source.outcome,
+            source.duration.as_secs(),
+            source.artifact_bytes,
+            tags,
+            if links.is_empty() { links } else { ["<ul>", &links, "</ul>"].concat() }
+        );
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// The index of the reports, which links to the report of each suite.
+fn render_index(suites: &[String]) -> String {
+    let links = suites.iter()
+        .map(|suite| format!("<li><a href=\"{0}/index.html\">{0}</a></li>\n", escape(suite)))
+        .collect::<String>();
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Build reports</title>\n\
+         <style>\n{}</style>\n</head>\n<body>\n<h1>Build reports</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+        STYLE,
+        links
+    )
+}
+
+/// Escapes text for use within HTML elements and attribute values.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::LintianSeverity;
+    use std::time::Duration;
+    use super::super::lintian::LintianTag;
+    use super::super::summary::SourceSummary;
+
+    #[test]
+    fn build_report() {
+        let source = |name: &str, outcome, seconds, artifact_bytes| SourceSummary {
+            source: name.into(),
+            outcome,
+            duration: Duration::from_secs(seconds),
+            artifact_bytes,
+        };
+
+        let summary = RunSummary {
+            suite: "bionic".into(),
+            sources: vec![source("foo", Outcome::Built, 90, 2048), source("bar", Outcome::Failed, 12, 0)],
+            duration: Duration::from_secs(102),
+            pool_bytes_before: 4096,
+            pool_bytes_after: 6144,
+            cache: None,
+        };
+
+        let mut versions = BTreeMap::new();
+        versions.insert("foo".to_owned(), "1.0-1".to_owned());
+
+        let lintian = vec![LintianReport {
+            source: "foo".into(),
+            tags: vec![LintianTag {
+                severity: LintianSeverity::Warning,
+                package: "foo".into(),
+                tag: "binary-without-manpage".into(),
+                info: "usr/bin/<foo>".into(),
+            }],
+            failed: false,
+        }];
+
+        let mut logs = BTreeMap::new();
+        logs.insert("bar".to_owned(), vec!["logs/bar/1.0-amd64-20181001T120000.log".to_owned()]);
+
+        let html = render(&summary, &versions, &lintian, &logs, "2018-10-01 12:00:00 UTC");
+        assert!(html.contains("<p>1 built, 0 unchanged, 1 failed, 0 cancelled in 102s; the pool changed by 2048 bytes.</p>"));
+        assert!(html.contains(
+            "<tr class=\"built\"><td>foo</td><td>1.0-1</td><td>built</td><td>90s</td><td>2048 bytes</td>\
+             <td>passed<ul><li>Warning: foo: binary-without-manpage usr/bin/&lt;foo&gt;</li></ul></td><td></td></tr>"
+        ));
+        assert!(html.contains(
+            "<tr class=\"failed\"><td>bar</td><td></td><td>failed</td><td>12s</td><td>0 bytes</td><td></td>\
+             <td><ul><li><a href=\"logs/bar/1.0-amd64-20181001T120000.log\">1.0-amd64-20181001T120000.log</a></li></ul></td></tr>"
+        ));
+
+        assert!(render_index(&["bionic".into(), "cosmic".into()])
+            .contains("<li><a href=\"bionic/index.html\">bionic</a></li>\n<li><a href=\"cosmic/index.html\">cosmic</a></li>\n"));
+    }
+}
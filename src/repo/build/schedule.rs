@@ -0,0 +1,202 @@
+use config::Source;
+use interrupt;
+use progress::{Progress, Unit};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use super::BuildError;
+use super::graph::cycles;
+
+/// A build which failed, and the source package that it failed for.
+#[derive(Debug)]
+pub struct BuildFailure {
+    pub package: String,
+    pub why: BuildError,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Pending,
+    Running,
+    Built,
+}
+
+/// Runs the build of each source, with up to `jobs` builds running concurrently.
+///
+/// A source is only started once every other source that it depends on, as given by the
+/// dependency graph, has been built. Each build writes its output to its own logs, and is
+/// reported as it starts and finishes, along with the number of builds finished so far. After a
+/// build fails, or the run is interrupted, no further builds are started, and the first failure
+/// is returned once the running builds have finished.
+pub(crate) fn schedule<F>(
+    sources: Vec<Source>,
+    depends: Vec<Vec<usize>>,
+    jobs: usize,
+    build: F,
+) -> Result<(), BuildFailure>
+    where F: Fn(&Source) -> Result<(), BuildError> + Send + Sync + 'static
+{
+    let cyclic = cycles(&depends);
+    if let Some(&first) = cyclic.first() {
+        return Err(BuildFailure {
+            package: sources[first].name.clone(),
+            why: BuildError::DependencyCycle {
+                packages: cyclic.iter().map(|&id| sources[id].name.clone()).collect()
+            }
+        });
+    }
+
+    let jobs = jobs.max(1);
+    let sources = Arc::new(sources);
+    let build = Arc::new(build);
+    let (tx, rx) = mpsc::channel();
+
+    let mut state = vec![State::Pending; sources.len()];
+    let mut running = 0;
+    let mut failure: Option<BuildFailure> = None;
+    let mut finished = 0;
+    let progress = Progress::new("building", Some(sources.len() as u64), Unit::Items);
+
+    loop {
+        if failure.is_none() && !interrupt::is_interrupted() {
+            for id in 0..sources.len() {
+                if running == jobs {
+                    break
+                }
+
+                if state[id] != State::Pending || depends[id].iter().any(|&dep| state[dep] != State::Built) {
+                    continue
+                }
+
+                info!("{}: build started", sources[id].name);
+                state[id] = State::Running;
+                running += 1;
+
+                let (sources, build, tx) = (sources.clone(), build.clone(), tx.clone());
+                thread::spawn(move || {
+                    let result = build(&sources[id]);
+                    let _ = tx.send((id, result));
+                });
+            }
+        }
+
+        if running == 0 {
+            break
+        }
+
+        let (id, result) = rx.recv().expect("build thread exited without reporting its result");
+        running -= 1;
+        finished += 1;
+
+        let name = &sources[id].name;
+        match result {
+            Ok(()) => {
+                info!("{}: build finished ({}/{})", name, finished, sources.len());
+                state[id] = State::Built;
+            }
+            Err(why) => {
+                error!("{}: build failed ({}/{}): {}", name, finished, sources.len(), why);
+                if failure.is_none() {
+                    failure = Some(BuildFailure { package: name.clone(), why });
+                }
+            }
+        }
+
+        progress.inc(1);
+    }
+
+    progress.finish();
+    failure.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use super::super::graph::dependencies;
+    use toml;
+
+    fn sources(toml: &str) -> Vec<Source> {
+        #[derive(Deserialize)]
+        struct Sources { source: Vec<Source> }
+        toml::from_str::<Sources>(toml).unwrap().source
+    }
+
+    #[test]
+    fn parallel_build_schedule() {
+        let sources = sources(r#"
+            [[source]]
+            name = "app"
+            depends = ["libfoo", "libbar"]
+
+            [[source]]
+            name = "libfoo"
+
+            [[source]]
+            name = "libbar"
+
+            [[source]]
+            name = "other"
+        "#);
+
+        // Records when each build starts and finishes, and the peak number of concurrent builds.
+        let events = Arc::new(Mutex::new((Vec::new(), 0, 0)));
+        let recorder = events.clone();
+        let depends = dependencies(&sources, &[]);
+        schedule(sources, depends, 2, move |source| {
+            {
+                let mut events = recorder.lock().unwrap();
+                events.0.push(format!("start {}", source.name));
+                events.1 += 1;
+                events.2 = events.2.max(events.1);
+            }
+
+            thread::sleep(Duration::from_millis(20));
+            let mut events = recorder.lock().unwrap();
+            events.0.push(format!("finish {}", source.name));
+            events.1 -= 1;
+            Ok(())
+        }).unwrap();
+
+        let events = events.lock().unwrap();
+        let position = |event: &str| events.0.iter().position(|x| x == event).unwrap();
+        assert_eq!(events.2, 2);
+        assert!(position("start app") > position("finish libfoo"));
+        assert!(position("start app") > position("finish libbar"));
+
+        let cycle = sources_cycle();
+        let depends = dependencies(&cycle, &[]);
+        let failure = schedule(cycle, depends, 4, |_| Ok(())).unwrap_err();
+        match failure.why {
+            BuildError::DependencyCycle { ref packages } => assert_eq!(packages.len(), 2),
+            ref why => panic!("unexpected failure: {}", why)
+        }
+
+        let broken = sources_cycle().into_iter().skip(2).collect::<Vec<Source>>();
+        let depends = dependencies(&broken, &[]);
+        let failure = schedule(broken, depends, 4, |source| {
+            if source.name == "broken" { Err(BuildError::MissingDependencies) } else { Ok(()) }
+        }).unwrap_err();
+        assert_eq!(failure.package, "broken");
+    }
+
+    fn sources_cycle() -> Vec<Source> {
+        sources(r#"
+            [[source]]
+            name = "a"
+            depends = ["b"]
+
+            [[source]]
+            name = "b"
+            depends = ["a"]
+
+            [[source]]
+            name = "broken"
+
+            [[source]]
+            name = "dependent"
+            depends = ["broken"]
+        "#)
+    }
+}
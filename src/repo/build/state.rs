@@ -0,0 +1,178 @@
+use command::Command;
+use config::{Config, Source, SourceLocation};
+use digest::Digest;
+use hex_view::HexView;
+use misc;
+use sha2::Sha256;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use toml;
+use walkdir::WalkDir;
+
+/// The inputs of a source's last successful build.
+///
+/// When none of these have changed, the source does not need to be built again.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub(crate) struct BuildState {
+    /// The checksum of the source tarball, the commit of a git source, or the URL of a dsc.
+    pub source: String,
+    /// The digest of the debian directory that is kept in the repo for this source.
+    pub debian: Option<String>,
+    /// The digest of the assets that are kept in the repo for this source.
+    pub assets: Option<String>,
+    /// The digest of the patches that are kept in the repo for this source.
+    pub patches: Option<String>,
+    /// The digest of the source's configuration, and of the suite's build configuration.
+    pub config: String,
+}
+
+impl BuildState {
+    /// Determines the state of a source, if its location has something to compare against.
+    pub fn new(config: &Config, source: &Source, pwd: &Path) -> io::Result<Option<BuildState>> {
+        let suite = &config.archive;
+        let digest = match source.location {
+            Some(SourceLocation::URL { ref sha256, .. }) => sha256.clone(),
+            Some(SourceLocation::Dsc { ref dsc }) => dsc.clone(),
+            Some(SourceLocation::Git { .. }) => {
+                Command::new("git")
+                    .arg("-C")
+                    .arg(pwd.join(["build/", suite, "/", &source.name].concat()))
+                    .args(&["rev-parse", "HEAD"])
+                    .run_with_stdout()?
+                    .trim()
+                    .to_owned()
+            }
+            None => return Ok(None)
+        };
+
+        let debian = pwd.join(["debian/", suite, "/", &source.name].concat());
+        let assets = pwd.join(["assets/packages/", &source.name].concat());
+        let patches = pwd.join(["patches/", suite, "/", &source.name].concat());
+
+        // The source's entry, and every setting of the suite which is given to the builder.
+        let build_config = format!(
+            "{:?}\n{:?}\n{:?}\n{:?}\n{}\n{}",
+            source,
+            config.builder,
+            config.architectures,
+            config.extra_repos,
+            config.version,
+            config.default_component
+        );
+
+        Ok(Some(BuildState {
+            source: digest,
+            debian: if debian.exists() { Some(hash_directory(&debian)?) } else { None },
+            assets: if assets.exists() { Some(hash_directory(&assets)?) } else { None },
+            patches: if patches.exists() { Some(hash_directory(&patches)?) } else { None },
+            config: {
+                let mut hasher = Sha256::new();
+                hasher.input(build_config.as_bytes());
+                format!("{:x}", HexView::from(hasher.result().as_slice()))
+            },
+        }))
+    }
+
+    /// Reads the state of the last successful build, if there was one.
+    pub fn load(path: &Path) -> Option<BuildState> {
+        if !path.exists() {
+            return None;
+        }
+
+        misc::read(path).ok().and_then(|data| toml::from_slice(&data).ok())
+    }
+
+    /// A digest of every input, which identifies what a build was built from.
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input(format!("{:?}", self).as_bytes());
+        format!("{:x}", HexView::from(hasher.result().as_slice()))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let data = toml::to_vec(self).map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to serialize build state: {}", why)
+        ))?;
+
+        misc::write(path, &data)
+    }
+}
+
+/// Computes a digest of the relative paths, link targets, and file contents within a directory.
+fn hash_directory(dir: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+
+    let entries = WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()));
+
+    for entry in entries {
+        let entry = entry.map_err(|why| io::Error::new(io::ErrorKind::Other, format!("{}", why)))?;
+        let relative = entry.path().strip_prefix(dir).unwrap();
+        hasher.input(relative.to_string_lossy().as_bytes());
+        hasher.input(b"\0");
+
+        if entry.path_is_symlink() {
+            hasher.input(entry.path().read_link()?.to_string_lossy().as_bytes());
+        } else if entry.file_type().is_file() {
+            let mut file = File::open(entry.path())?;
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 { break }
+                hasher.input(&buffer[..read]);
+            }
+        }
+
+        hasher.input(b"\n");
+    }
+
+    Ok(format!("{:x}", HexView::from(hasher.result().as_slice())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn incremental_build_state() {
+        let dir = tempdir().unwrap();
+        let debian = dir.path().join("debian/bionic/foo");
+        fs::create_dir_all(&debian).unwrap();
+        misc::write(debian.join("rules"), "#!/usr/bin/make -f\n").unwrap();
+
+        let mut config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "test"
+            label = "test"
+            email = "test@example.com"
+
+            [[source]]
+            name = "foo"
+            location = { url = "https://example.com/foo.tar.gz", sha256 = "abc123" }
+        "#).unwrap();
+
+        let source = config.source.as_ref().unwrap()[0].clone();
+        let state = BuildState::new(&config, &source, dir.path()).unwrap().unwrap();
+        assert_eq!(state.source, "abc123");
+        assert!(state.assets.is_none());
+
+        let path = dir.path().join("foo.toml");
+        assert_eq!(BuildState::load(&path), None);
+        state.save(&path).unwrap();
+        assert_eq!(BuildState::load(&path).as_ref(), Some(&state));
+
+        misc::write(debian.join("rules"), "#!/usr/bin/make -f\n%:\n\tdh $@\n").unwrap();
+        let changed = BuildState::new(&config, &source, dir.path()).unwrap().unwrap();
+        assert!(changed.debian != state.debian && changed.config == state.config);
+
+        config.architectures.push("arm64".into());
+        let reconfigured = BuildState::new(&config, &source, dir.path()).unwrap().unwrap();
+        assert!(reconfigured.config != changed.config && reconfigured.debian == changed.debian);
+    }
+}
@@ -0,0 +1,245 @@
+use logging::json_escape;
+use notify::Notification;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use super::super::table;
+use walkdir::WalkDir;
+
+/// What became of a source in this run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Built,
+    /// The source was unchanged since its last build, and so it was not built again.
+    Unchanged,
+    Failed,
+    /// The source was not started, as another build failed before it could be.
+    Cancelled,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Outcome::Built => "built",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Failed => "failed",
+            Outcome::Cancelled => "cancelled",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SourceSummary {
+    pub source: String,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    /// The combined size of the files that the build produced.
+    pub artifact_bytes: u64,
+}
+
+/// How the compiler cache of the suite was used by a run.
+#[derive(Debug, PartialEq)]
+pub struct CacheSummary {
+    pub tool: &'static str,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// The hits and misses of the run, where the tool counts them.
+    pub counters: Option<(u64, u64)>,
+}
+
+impl fmt::Display for CacheSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.tool)?;
+        if let Some((hits, misses)) = self.counters {
+            write!(f, "{} hits, {} misses; ", hits, misses)?;
+        }
+
+        write!(f, "the cache changed by {} bytes", self.bytes_after as i64 - self.bytes_before as i64)
+    }
+}
+
+/// The outcome of every source which was to be built in a run, and how the pool grew.
+#[derive(Debug, PartialEq)]
+pub struct RunSummary {
+    pub suite: String,
+    pub sources: Vec<SourceSummary>,
+    pub duration: Duration,
+    pub pool_bytes_before: u64,
+    pub pool_bytes_after: u64,
+    pub cache: Option<CacheSummary>,
+}
+
+impl RunSummary {
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.sources.iter().filter(|source| source.outcome == outcome).count()
+    }
+
+    pub fn pool_delta(&self) -> i64 {
+        self.pool_bytes_after as i64 - self.pool_bytes_before as i64
+    }
+
+    /// A table of each source, followed by the totals of the run.
+    pub fn table(&self) -> String {
+        let mut rows = vec![["SOURCE", "OUTCOME", "DURATION", "ARTIFACTS"].iter()
+            .map(|&x| x.to_owned())
+            .collect::<Vec<String>>()];
+
+        for source in &self.sources {
+            rows.push(vec![
+                source.source.clone(),
+                source.outcome.to_string(),
+                format!("{}s", source.duration.as_secs()),
+                format!("{} bytes", source.artifact_bytes),
+            ]);
+        }
+
+        let mut table = format!(
+            "{}{}: {} built, {} unchanged, {} failed, {} cancelled in {}s; the pool changed by {} bytes\n",
+            table(&rows),
+            self.suite,
+            self.count(Outcome::Built),
+            self.count(Outcome::Unchanged),
+            self.count(Outcome::Failed),
+            self.count(Outcome::Cancelled),
+            self.duration.as_secs(),
+            self.pool_delta()
+        );
+
+        if let Some(ref cache) = self.cache {
+            table.push_str(&format!("{}\n", cache));
+        }
+
+        table
+    }
+
+    /// The summary as a JSON object, on a single line.
+    pub fn json(&self) -> String {
+        let sources = self.sources.iter()
+            .map(|source| format!(
+                "{{\"source\":\"{}\",\"outcome\":\"{}\",\"seconds\":{},\"artifact_bytes\":{}}}",
+                json_escape(&source.source),
+                source.outcome,
+                source.duration.as_secs(),
+                source.artifact_bytes
+            ))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let cache = self.cache.as_ref().map_or_else(String::new, |cache| {
+            let counters = cache.counters.map_or_else(String::new, |(hits, misses)| {
+                format!(",\"hits\":{},\"misses\":{}", hits, misses)
+            });
+
+            format!(
+                "\"cache\":{{\"tool\":\"{}\",\"bytes_before\":{},\"bytes_after\":{}{}}},",
+                cache.tool,
+                cache.bytes_before,
+                cache.bytes_after,
+                counters
+            )
+        });
+
+        format!(
+            "{{\"suite\":\"{}\",\"built\":{},\"unchanged\":{},\"failed\":{},\"cancelled\":{},\
+             \"seconds\":{},\"pool_bytes_before\":{},\"pool_bytes_after\":{},{}\"sources\":[{}]}}",
+            json_escape(&self.suite),
+            self.count(Outcome::Built),
+            self.count(Outcome::Unchanged),
+            self.count(Outcome::Failed),
+            self.count(Outcome::Cancelled),
+            self.duration.as_secs(),
+            self.pool_bytes_before,
+            self.pool_bytes_after,
+            cache,
+            sources
+        )
+    }
+
+    /// A notification of the outcome of the run, with the table as its body.
+    pub fn notification(&self) -> Notification {
+        let failed = self.count(Outcome::Failed);
+        let subject = if failed == 0 {
+            format!("{}: {} sources were built", self.suite, self.count(Outcome::Built))
+        } else {
+            format!("{}: {} of {} sources failed to build", self.suite, failed, self.sources.len())
+        };
+
+        Notification { subject, body: self.table(), summary: Some(self.json()) }
+    }
+
+    /// Appends the summary to the file as a line of JSON, so that each suite of a run may be
+    /// written to the same file.
+    pub fn append_json(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", self.json())
+    }
+}
+
+/// The combined size of every file beneath the path, which is zero if it does not exist.
+pub fn directory_bytes(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_summary() {
+        let source = |name: &str, outcome, seconds, artifact_bytes| SourceSummary {
+            source: name.into(),
+            outcome,
+            duration: Duration::from_secs(seconds),
+            artifact_bytes,
+        };
+
+        let summary = RunSummary {
+            suite: "bionic".into(),
+            sources: vec![
+                source("foo", Outcome::Built, 90, 2048),
+                source("bar", Outcome::Unchanged, 0, 0),
+                source("baz", Outcome::Failed, 12, 0),
+                source("qux", Outcome::Cancelled, 0, 0),
+            ],
+            duration: Duration::from_secs(102),
+            pool_bytes_before: 4096,
+            pool_bytes_after: 6144,
+            cache: None,
+        };
+
+        assert_eq!(summary.table(), [
+            "SOURCE OUTCOME   DURATION ARTIFACTS\n",
+            "foo    built     90s      2048 bytes\n",
+            "bar    unchanged 0s       0 bytes\n",
+            "baz    failed    12s      0 bytes\n",
+            "qux    cancelled 0s       0 bytes\n",
+            "bionic: 1 built, 1 unchanged, 1 failed, 1 cancelled in 102s; the pool changed by 2048 bytes\n",
+        ].concat());
+
+        assert!(summary.json().starts_with(
+            "{\"suite\":\"bionic\",\"built\":1,\"unchanged\":1,\"failed\":1,\"cancelled\":1,\"seconds\":102,"
+        ));
+        assert!(summary.json().ends_with(
+            "{\"source\":\"qux\",\"outcome\":\"cancelled\",\"seconds\":0,\"artifact_bytes\":0}]}"
+        ));
+
+        let cached = RunSummary {
+            cache: Some(CacheSummary { tool: "ccache", bytes_before: 1024, bytes_after: 3072, counters: Some((150, 50)) }),
+            .. summary
+        };
+
+        assert!(cached.table().ends_with("ccache: 150 hits, 50 misses; the cache changed by 2048 bytes\n"));
+        assert!(cached.json().contains(
+            "\"pool_bytes_after\":6144,\"cache\":{\"tool\":\"ccache\",\"bytes_before\":1024,\"bytes_after\":3072,\
+             \"hits\":150,\"misses\":50},\"sources\":["
+        ));
+    }
+}
@@ -0,0 +1,383 @@
+use checksum::hasher;
+use config::{Config, Transform};
+use debian::{append_tar_entry, write_deb, DebFile};
+use md5::Md5;
+use misc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive as TarArchive, Builder as TarBuilder, EntryType};
+use tempfile::tempdir;
+use super::super::pool::{cp_to_pool, ARCHIVES_ONLY, KEEP_PUBLISHED};
+
+/// The fields which list the packages that a package relates to.
+const RELATIONS: &[&str] = &[
+    "Pre-Depends", "Depends", "Recommends", "Suggests", "Enhances", "Breaks", "Conflicts",
+    "Replaces", "Provides",
+];
+
+/// A file which is injected into the data member of a package, by its path relative to the root.
+struct Injected {
+    path: PathBuf,
+    data: Vec<u8>,
+    mode: u32,
+}
+
+/// Emits the transformed copy of each package that the config declares, and places them into
+/// the pool of each of their components.
+pub fn transform_packages(config: &Config) -> io::Result<()> {
+    let transforms = match config.transform {
+        Some(ref transforms) if !transforms.is_empty() => transforms,
+        _ => return Ok(())
+    };
+
+    info!("transforming packages");
+    let mut components = BTreeMap::new();
+    for transform in transforms {
+        let component = config.component(&transform.component);
+        components.entry(component).or_insert_with(Vec::new).push(transform);
+    }
+
+    for (component, transforms) in components {
+        let dir = tempdir()?;
+        for transform in transforms {
+            transform_package(transform, dir.path()).map_err(|why| io::Error::new(
+                why.kind(),
+                format!("failed to transform {}: {}", transform.input.display(), why)
+            ))?;
+        }
+
+        // The same input is always transformed the same way, so a package whose version is
+        // unchanged is not published again.
+        let flags = ARCHIVES_ONLY | KEEP_PUBLISHED;
+        let policy = config.pool_policy();
+        let pool = config.pool_root();
+        cp_to_pool(dir.path(), &pool, &config.archive, component, flags, None, &policy, &config.pooled)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the transformed copy of the package into the directory, returning its path.
+fn transform_package(transform: &Transform, output: &Path) -> io::Result<PathBuf> {
+    let deb = DebFile::open(&transform.input)?;
+
+    let mut files = Vec::new();
+    for file in &transform.files {
+        let mode = fs::metadata(&file.src)?.permissions().mode() & 0o777;
+        files.push(Injected {
+            path: destination(&file.dst)?,
+            data: misc::read(&file.src)?,
+            mode,
+        });
+    }
+
+    let data = transform_data(&deb.member_tarball("data.tar")?, &files)?;
+    let control = deb.member_tarball("control.tar")?;
+    let (control, fields) = transform_control(&control, transform, &files)?;
+
+    let field = |key: &str| fields.iter()
+        .find(|&&(ref field, _)| field == key)
+        .map_or("", |&(_, ref value)| value.as_str());
+
+    // The epoch of a version is not a part of the filename.
+    let version = field("Version").splitn(2, ':').last().unwrap_or("");
+    let filename = [field("Package"), "_", version, "_", field("Architecture"), ".deb"].concat();
+    info!("transformed {} into {}", transform.input.display(), filename);
+
+    let path = output.join(filename);
+    write_deb(&path, &control, &data)?;
+    Ok(path)
+}
+
+/// The path of an injected file relative to the root of the package, which must name a file
+/// without any `..` components, so that it can't be written outside of the root when the
+/// package is installed.
+fn destination(dst: &Path) -> io::Result<PathBuf> {
+    let relative = dst.strip_prefix("/").unwrap_or(dst);
+    let safe = relative.components().next().is_some() && relative.components().all(|component| {
+        match component {
+            Component::Normal(_) => true,
+            _ => false
+        }
+    });
+
+    if safe {
+        Ok(relative.to_path_buf())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a path within the package", dst.display())
+        ))
+    }
+}
+
+/// Copies each entry of the data tarball, except those which are replaced, and appends the
+/// injected files along with any of their parent directories which it lacks.
+fn transform_data(tarball: &[u8], files: &[Injected]) -> io::Result<Vec<u8>> {
+    let mut builder = TarBuilder::new(Vec::new());
+    let mut directories = BTreeSet::new();
+
+    let mut archive = TarArchive::new(tarball);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let relative = path.strip_prefix(".").unwrap_or(&path).to_path_buf();
+        if files.iter().any(|file| file.path == relative) {
+            continue
+        }
+
+        if entry.header().entry_type().is_dir() {
+            directories.insert(relative);
+        }
+
+        let mut header = entry.header().clone();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        builder.append_data(&mut header, &path, data.as_slice())?;
+    }
+
+    for file in files {
+        let mut parents = file.path.ancestors()
+            .skip(1)
+            .filter(|parent| parent != &Path::new(""))
+            .collect::<Vec<&Path>>();
+        parents.reverse();
+
+        for parent in parents {
+            if directories.insert(parent.to_path_buf()) {
+                let path = format!("./{}/", parent.display());
+                append_tar_entry(&mut builder, &path, EntryType::Directory, 0o755, b"")?;
+            }
+        }
+
+        let path = format!("./{}", file.path.display());
+        append_tar_entry(&mut builder, &path, EntryType::Regular, file.mode, &file.data)?;
+    }
+
+    builder.into_inner()
+}
+
+/// Copies each entry of the control tarball, with the fields of the control file transformed,
+/// and the digests of the injected files recorded by the `md5sums` file. The transformed fields
+/// are returned with the tarball.
+fn transform_control(
+    tarball: &[u8],
+    transform: &Transform,
+    files: &[Injected]
+) -> io::Result<(Vec<u8>, Vec<(String, String)>)> {
+    let mut builder = TarBuilder::new(Vec::new());
+    let mut fields = None;
+
+    let mut archive = TarArchive::new(tarball);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        match path.strip_prefix(".").unwrap_or(&path).to_str() {
+            Some("control") => {
+                let injected = files.iter().map(|file| file.data.len() as u64).sum::<u64>();
+                let transformed = transform_fields(
+                    &String::from_utf8_lossy(&data),
+                    transform,
+                    (injected + 1023) / 1024
+                );
+
+                data = format_fields(&transformed).into_bytes();
+                fields = Some(transformed);
+            }
+            Some("md5sums") => {
+                let mut md5sums = String::from_utf8_lossy(&data).lines()
+                    .filter(|line| {
+                        let path = line.splitn(2, ' ').nth(1).map_or("", |path| path.trim());
+                        !files.iter().any(|file| file.path == Path::new(path))
+                    })
+                    .map(|line| [line, "\n"].concat())
+                    .collect::<String>();
+
+                for file in files {
+                    let digest = hasher::<Md5, _>(file.data.as_slice())?;
+                    md5sums.push_str(&format!("{}  {}\n", digest, file.path.display()));
+                }
+
+                data = md5sums.into_bytes();
+            }
+            _ => ()
+        }
+
+        let mut header = entry.header().clone();
+        header.set_size(data.len() as u64);
+        builder.append_data(&mut header, &path, data.as_slice())?;
+    }
+
+    let fields = fields.ok_or_else(|| io::Error::new(
+        io::ErrorKind::NotFound,
+        "the package does not have a control file"
+    ))?;
+
+    Ok((builder.into_inner()?, fields))
+}
+
+/// The fields of the control file, in their order, after the package is renamed, its version
+/// suffixed, its relations renamed, and the fields of the transform are replaced or added.
+///
+/// The installed size is increased by the kilobytes of the files which are injected.
+fn transform_fields(control: &str, transform: &Transform, injected: u64) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in control.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(&mut (_, ref mut value)) = fields.last_mut() {
+                value.push('\n');
+                value.push_str(line);
+            }
+        } else if let Some(pos) = line.find(':') {
+            fields.push((line[..pos].trim().to_owned(), line[pos + 1..].trim().to_owned()));
+        }
+    }
+
+    for &mut (ref key, ref mut value) in &mut fields {
+        match key.as_str() {
+            "Package" => if let Some(ref name) = transform.name {
+                *value = name.clone();
+            },
+            "Version" => if let Some(ref suffix) = transform.version_suffix {
+                value.push_str(suffix);
+            },
+            "Installed-Size" => if let Ok(size) = value.parse::<u64>() {
+                *value = (size + injected).to_string();
+            },
+            key if RELATIONS.contains(&key) && !transform.relations.is_empty() => {
+                *value = rename_relations(value, &transform.relations);
+            }
+            _ => ()
+        }
+    }
+
+    for (key, value) in &transform.fields {
+        // The lines of a value after the first are indented, as continuation lines are.
+        let value = value.trim().lines()
+            .map(|line| if line.trim().is_empty() { "." } else { line })
+            .collect::<Vec<&str>>()
+            .join("\n ");
+
+        match fields.iter().position(|&(ref field, _)| field == key) {
+            Some(pos) if value.is_empty() => { fields.remove(pos); }
+            Some(pos) => fields[pos].1 = value,
+            None if value.is_empty() => (),
+            None => fields.push((key.clone(), value))
+        }
+    }
+
+    fields
+}
+
+fn format_fields(fields: &[(String, String)]) -> String {
+    fields.iter().map(|&(ref key, ref value)| format!("{}: {}\n", key, value)).collect()
+}
+
+/// Renames each package of a relationship field which the map names, keeping its version
+/// constraints and architecture qualifiers.
+fn rename_relations(value: &str, renames: &BTreeMap<String, String>) -> String {
+    value.split(',')
+        .map(|relation| {
+            relation.split('|')
+                .map(|alternative| {
+                    let alternative = alternative.trim();
+                    let end = alternative.find(|c: char| {
+                        c.is_whitespace() || c == '(' || c == '[' || c == '<' || c == ':'
+                    }).unwrap_or_else(|| alternative.len());
+
+                    match renames.get(&alternative[..end]) {
+                        Some(name) => [name, &alternative[end..]].concat(),
+                        None => alternative.to_owned()
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" | ")
+        })
+        .filter(|relation| !relation.is_empty())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::TransformFile;
+    use debian::tarball;
+
+    #[test]
+    fn transform_vendor_package() {
+        let dir = tempdir().unwrap();
+        let control = "Package: vendor-tool\nVersion: 1:2.1\nArchitecture: amd64\n\
+            Maintainer: Vendor <vendor@example.com>\nInstalled-Size: 10\n\
+            Depends: vendor-lib (>= 2.0), libc6 | vendor-libc:any\n\
+            Description: vendor tool\n a tool\n";
+        let md5sums = "d41d8cd98f00b204e9800998ecf8427e  usr/bin/vendor-tool\n";
+
+        let input = dir.path().join("vendor-tool_2.1_amd64.deb");
+        write_deb(
+            &input,
+            &tarball(&[("control", control.as_bytes()), ("md5sums", md5sums.as_bytes())]).unwrap(),
+            &tarball(&[("usr/bin/vendor-tool", b"")]).unwrap()
+        ).unwrap();
+
+        let src = dir.path().join("vendor.conf");
+        misc::write(&src, b"branded=true\n").unwrap();
+
+        let mut transform = Transform {
+            input,
+            name: Some("pop-tool".into()),
+            version_suffix: Some("~pop1".into()),
+            relations: BTreeMap::new(),
+            fields: BTreeMap::new(),
+            files: vec![TransformFile { src, dst: PathBuf::from("/etc/vendor/vendor.conf") }],
+            component: None,
+        };
+
+        transform.relations.insert("vendor-lib".into(), "pop-lib".into());
+        transform.relations.insert("vendor-libc".into(), "pop-libc".into());
+        transform.fields.insert("Maintainer".into(), "Pop <info@example.com>".into());
+        transform.fields.insert("Conflicts".into(), "vendor-tool".into());
+
+        let output = dir.path().join("output");
+        fs::create_dir(&output).unwrap();
+        let path = transform_package(&transform, &output).unwrap();
+        assert_eq!(path, output.join("pop-tool_2.1~pop1_amd64.deb"));
+
+        let deb = DebFile::open(&path).unwrap();
+        let fields = deb.control().unwrap().fields;
+        assert_eq!(fields["Package"], "pop-tool");
+        assert_eq!(fields["Version"], "1:2.1~pop1");
+        assert_eq!(fields["Installed-Size"], "11");
+        assert_eq!(fields["Depends"], "pop-lib (>= 2.0), libc6 | pop-libc:any");
+        assert_eq!(fields["Maintainer"], "Pop <info@example.com>");
+        assert_eq!(fields["Conflicts"], "vendor-tool");
+        assert_eq!(fields["Description"], "vendor tool\n a tool");
+
+        let paths = deb.data().unwrap().into_iter()
+            .map(|entry| entry.path)
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(paths, vec![
+            PathBuf::from("./"),
+            PathBuf::from("./usr/"),
+            PathBuf::from("./usr/bin/"),
+            PathBuf::from("./usr/bin/vendor-tool"),
+            PathBuf::from("./etc/"),
+            PathBuf::from("./etc/vendor/"),
+            PathBuf::from("./etc/vendor/vendor.conf"),
+        ]);
+    }
+
+    #[test]
+    fn unsafe_destinations() {
+        assert_eq!(destination(Path::new("/etc/vendor.conf")).unwrap(), PathBuf::from("etc/vendor.conf"));
+        for dst in &["/etc/../../root/.ssh/authorized_keys", "../etc/vendor.conf", "/"] {
+            assert_eq!(destination(Path::new(dst)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use action;
+use command::Command;
+use config::{Config, Source, SourceLocation};
+use digest::Digest;
+use hex_view::HexView;
+use sha2::Sha256;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::state::BuildState;
+use super::super::prepare::SOURCES_TREES;
+
+/// Whether the prepared tree of a source may be cached. Shared assets and debian branches are
+/// fetched from outside of the inputs of the state, so the sources which use them are prepared
+/// on every run.
+pub fn cacheable(source: &Source) -> bool {
+    let tarball = match source.location {
+        Some(SourceLocation::URL { .. }) => true,
+        _ => false
+    };
+
+    tarball && source.extract && source.assets.is_none() && source.debian.is_none()
+}
+
+/// Where the prepared tree of a source is cached within the trees of its suite, which is keyed
+/// by a digest of its upstream tarball, debian overlay, package assets, and patches, so that a
+/// change to any of them prepares the source again.
+pub fn tree_path(pwd: &Path, suite: &str, state: &BuildState, source: &Source) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.input(format!(
+        "{}\n{:?}\n{:?}\n{:?}\n{:?}",
+        state.source,
+        state.debian,
+        state.assets,
+        state.patches,
+        source.patches
+    ).as_bytes());
+
+    pwd.join(SOURCES_TREES)
+        .join(suite)
+        .join(format!("{:x}", HexView::from(hasher.result().as_slice())))
+}
+
+/// Removes the prepared trees of the suite which no source of the config would reuse, such as
+/// those of versions that are no longer configured, returning the trees which were removed.
+pub fn prune_trees(config: &Config, pwd: &Path) -> io::Result<Vec<PathBuf>> {
+    let directory = pwd.join(SOURCES_TREES).join(&config.archive);
+    if !directory.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut current = Vec::new();
+    for source in config.source.iter().flat_map(|sources| sources.iter()).filter(|s| cacheable(s)) {
+        if let Some(state) = BuildState::new(config, source, pwd)? {
+            current.push(tree_path(pwd, &config.archive, &state, source));
+        }
+    }
+
+    let mut removed = Vec::new();
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+        if !current.contains(&path) {
+            action::remove_dir_all(&path)?;
+            removed.push(path);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Replaces the project with a copy of the prepared tree, so that the cached tree is never
+/// modified by the build.
+pub fn restore_tree(tree: &Path, project: &Path) -> io::Result<()> {
+    if project.exists() {
+        fs::remove_dir_all(project)?;
+    }
+
+    info!("reusing the prepared source at {}", tree.display());
+    copy_tree(tree, project)
+}
+
+/// Caches a copy of the prepared project, which is copied beside the tree and renamed into
+/// place, so that an interrupted copy is never reused.
+pub fn store_tree(project: &Path, tree: &Path) -> io::Result<()> {
+    let partial = tree.with_extension("partial");
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+
+    if let Some(parent) = tree.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    copy_tree(project, &partial)?;
+    fs::rename(&partial, tree)
+}
+
+/// Copies a tree with its permissions and links, by reflinks where the filesystem supports them.
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    Command::new("cp")
+        .args(&["-a", "--reflink=auto"])
+        .arg(src)
+        .arg(dst)
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+    use tempfile::tempdir;
+
+    #[test]
+    fn stale_trees_pruned() {
+        let dir = tempdir().unwrap();
+        let config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "test"
+            label = "test"
+            email = "test@example.com"
+
+            [[source]]
+            name = "foo"
+            location = { url = "https://example.com/foo-1.1.tar.gz", sha256 = "def456" }
+        "#).unwrap();
+
+        let source = &config.source.as_ref().unwrap()[0];
+        let state = BuildState::new(&config, source, dir.path()).unwrap().unwrap();
+        let current = tree_path(dir.path(), "bionic", &state, source);
+        let stale = dir.path().join(SOURCES_TREES).join("bionic/abc123");
+        let other = dir.path().join(SOURCES_TREES).join("cosmic/abc123");
+        for tree in &[&current, &stale, &other] {
+            fs::create_dir_all(tree.join("debian")).unwrap();
+        }
+
+        assert_eq!(prune_trees(&config, dir.path()).unwrap(), vec![stale.clone()]);
+        assert!(current.exists() && !stale.exists() && other.exists());
+    }
+}
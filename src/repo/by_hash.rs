@@ -0,0 +1,220 @@
+use checksum::{selected_hasher, Digests, MD5, SHA1, SHA256, SHA512};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Whether the file is an index which APT may fetch by its hash.
+pub fn is_index(filename: &str) -> bool {
+    ["Packages", "Sources", "Contents-", "Translation-", "Components-"]
+        .iter()
+        .any(|prefix| filename.starts_with(prefix))
+}
+
+/// The directories of the `by-hash` entries of each digest, as apt names them, by the flag that
+/// selects the digest.
+const BY_HASH: &[(u8, &str)] = &[(MD5, "MD5Sum"), (SHA1, "SHA1"), (SHA256, "SHA256"), (SHA512, "SHA512")];
+
+/// Ensures that each index within `dist_dir` has a `by-hash/<digest>/<hash>` entry for each of the
+/// selected digests which the `Release` lists, as apt fetches indices by the strongest of them,
+/// and prunes entries belonging to superseded indices, returning the paths that were removed.
+///
+/// Entries for up to `keep_history` previous generations of each directory's indices are retained,
+/// so that clients which fetched the `Release` file moments before an update can still resolve the
+/// indices that it references. Generations are ordered by the modification time of each entry.
+pub fn refresh_by_hash(dist_dir: &Path, keep_history: usize, digests: u8) -> io::Result<Vec<PathBuf>> {
+    let mut pruned = Vec::new();
+
+    let directories = WalkDir::new(dist_dir)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != "by-hash")
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    for directory in directories {
+        let mut indices = Vec::new();
+        for entry in directory.read_dir()? {
+            let entry = entry?;
+            let indexed = entry.file_name().to_str().map_or(false, is_index);
+            if indexed && entry.file_type()?.is_file() {
+                indices.push(entry.path());
+            }
+        }
+
+        if indices.is_empty() {
+            continue
+        }
+
+        let hashes = indices.iter()
+            .map(|index| File::open(index).and_then(|file| selected_hasher(file, digests)))
+            .collect::<io::Result<Vec<Digests>>>()?;
+
+        for &(flag, name) in BY_HASH.iter().filter(|&&(flag, _)| digests & flag != 0) {
+            let by_hash = directory.join("by-hash").join(name);
+            fs::create_dir_all(&by_hash)?;
+
+            let mut current = HashSet::new();
+            for (index, hashes) in indices.iter().zip(&hashes) {
+                let digest = match flag {
+                    MD5 => hashes.md5.as_str(),
+                    SHA1 => hashes.sha1.as_str(),
+                    SHA256 => hashes.sha256.as_str(),
+                    _ => hashes.sha512.as_str(),
+                };
+
+                let entry = by_hash.join(digest);
+                if !entry.exists() {
+                    // Copied rather than linked, as indices may be rewritten in place.
+                    fs::copy(index, &entry)?;
+                }
+
+                current.insert(digest);
+            }
+
+            let mut stale = Vec::new();
+            for entry in by_hash.read_dir()? {
+                let entry = entry?;
+                let is_current = entry.file_name().to_str().map_or(false, |x| current.contains(x));
+                if !is_current {
+                    stale.push((entry.metadata()?.modified()?, entry.path()));
+                }
+            }
+
+            // Newest entries first, so that the most recent generations are retained.
+            stale.sort_by(|a, b| b.cmp(a));
+            for (_, path) in stale.into_iter().skip(keep_history * indices.len()) {
+                info!("pruning stale by-hash entry at {}", path.display());
+                fs::remove_file(&path)?;
+                pruned.push(path);
+            }
+        }
+    }
+
+    pruned.sort();
+    Ok(pruned)
+}
+
+/// Carries the `by-hash` entries of the published dist directory over to a newly-generated one,
+/// so that their history is retained across generations.
+///
+/// Entries are hard-linked, which preserves the modification times used to order generations.
+pub fn carry_by_hash(current: &Path, new: &Path) -> io::Result<()> {
+    if !current.exists() {
+        return Ok(());
+    }
+
+    for entry in WalkDir::new(current).into_iter().flat_map(|e| e.ok()) {
+        let relative = entry.path().strip_prefix(current).unwrap();
+        let in_by_hash = relative.components().any(|c| c.as_os_str() == "by-hash");
+        if !in_by_hash || !entry.file_type().is_file() {
+            continue
+        }
+
+        let target = new.join(relative);
+        if target.exists() {
+            continue
+        }
+
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::hard_link(entry.path(), &target)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::{hasher, multi_hasher};
+    use debian::parse_control;
+    use misc;
+    use sha2::Sha256;
+    use tempfile::tempdir;
+
+    #[test]
+    fn by_hash_retention() {
+        let dir = tempdir().unwrap();
+        let binary = dir.path().join("main/binary-amd64");
+        fs::create_dir_all(&binary).unwrap();
+
+        let digest = |data: &[u8]| hasher::<Sha256, &[u8]>(data).unwrap();
+        let by_hash = binary.join("by-hash/SHA256");
+
+        misc::write(binary.join("Packages"), b"generation 1").unwrap();
+        assert!(refresh_by_hash(dir.path(), 1, SHA256).unwrap().is_empty());
+
+        misc::write(binary.join("Packages"), b"generation 2").unwrap();
+        assert!(refresh_by_hash(dir.path(), 1, SHA256).unwrap().is_empty());
+
+        misc::write(binary.join("Packages"), b"generation 3").unwrap();
+        assert_eq!(
+            refresh_by_hash(dir.path(), 1, SHA256).unwrap(),
+            vec![by_hash.join(digest(b"generation 1"))]
+        );
+
+        assert!(by_hash.join(digest(b"generation 2")).exists());
+        assert!(by_hash.join(digest(b"generation 3")).exists());
+    }
+
+    #[test]
+    fn by_hash_carried_across_generations() {
+        let dir = tempdir().unwrap();
+        let current = dir.path().join("bionic");
+        let new = dir.path().join("bionic.new");
+        for dist in &[&current, &new] {
+            fs::create_dir_all(dist.join("main/source")).unwrap();
+        }
+
+        misc::write(current.join("main/source/Sources"), b"generation 1").unwrap();
+        refresh_by_hash(&current, 1, SHA256).unwrap();
+
+        misc::write(new.join("main/source/Sources"), b"generation 2").unwrap();
+        carry_by_hash(&current, &new).unwrap();
+        refresh_by_hash(&new, 1, SHA256).unwrap();
+
+        let by_hash = new.join("main/source/by-hash/SHA256");
+        let digest = |data: &[u8]| hasher::<Sha256, &[u8]>(data).unwrap();
+        assert!(by_hash.join(digest(b"generation 1")).exists());
+        assert!(by_hash.join(digest(b"generation 2")).exists());
+    }
+
+    #[test]
+    fn by_hash_of_each_listed_digest() {
+        let dir = tempdir().unwrap();
+        let indices = ["main/binary-amd64/Packages", "main/source/Sources"];
+        for (id, index) in indices.iter().enumerate() {
+            fs::create_dir_all(dir.path().join(index).parent().unwrap()).unwrap();
+            misc::write(dir.path().join(index), format!("index {}", id)).unwrap();
+        }
+
+        refresh_by_hash(dir.path(), 1, SHA256 | SHA512).unwrap();
+
+        // The sections of the `Release` which apt-ftparchive lists for the selected digests.
+        let mut release = String::new();
+        for &(section, digest) in &[("SHA256", SHA256), ("SHA512", SHA512)] {
+            release.push_str(section);
+            release.push_str(":\n");
+            for index in &indices {
+                let path = dir.path().join(index);
+                let digests = File::open(&path).and_then(multi_hasher).unwrap();
+                let digest = if digest == SHA256 { digests.sha256 } else { digests.sha512 };
+                release.push_str(&format!(" {} {} {}\n", digest, path.metadata().unwrap().len(), index));
+            }
+        }
+
+        let release = parse_control(&release);
+        for &section in &["SHA256", "SHA512"] {
+            for line in release[section].lines().filter(|line| !line.trim().is_empty()) {
+                let fields = line.split_whitespace().collect::<Vec<&str>>();
+                let index = Path::new(fields[2]);
+                let entry = dir.path().join(index.parent().unwrap()).join("by-hash").join(section).join(fields[0]);
+                assert_eq!(misc::read(&entry).unwrap(), misc::read(dir.path().join(index)).unwrap(), "{}", line);
+            }
+        }
+
+        assert!(!dir.path().join("main/source/by-hash/MD5Sum").exists());
+    }
+}
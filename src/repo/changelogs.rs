@@ -0,0 +1,193 @@
+use debian::DebFile;
+use libflate::gzip::Decoder as GzDecoder;
+use misc;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use super::query::Published;
+use walkdir::WalkDir;
+
+/// The directory of the repo which the changelogs of each suite are published within.
+pub const CHANGELOGS: &str = "changelogs";
+
+/// Publishes the changelog of each source that the binary packages of the suite were built from,
+/// at `changelogs/<suite>/<component>/<bucket>/<source>/<source>_<version>/changelog`, where
+/// the version is given without its epoch, as apt expands `@CHANGEPATH@`.
+///
+/// The changelog is extracted from the `changelog.Debian.gz` of the first binary of the source
+/// which ships one, or else its `changelog.gz`, as a native package does. Changelogs of a version
+/// which is no longer published are removed. A package which can not be read is only warned
+/// about, as it is still published.
+pub fn refresh_changelogs(repo: &Path, suite: &str, published: &[Published]) -> io::Result<()> {
+    let root = repo.join(CHANGELOGS).join(suite);
+    let mut expected = BTreeSet::new();
+
+    for package in published.iter().filter(|package| package.architecture != "source") {
+        let deb = match package.files.first() {
+            Some(file) => repo.join(&file.0),
+            None => continue
+        };
+
+        let archive = match DebFile::open(&deb) {
+            Ok(archive) => archive,
+            Err(why) => {
+                warn!("failed to read the changelog of {}: {}", deb.display(), why);
+                continue
+            }
+        };
+
+        let destination = match archive.control() {
+            Ok(control) => {
+                let (source, version) = source_of(
+                    control.fields.get("Source").map(|x| x.as_str()),
+                    &package.package,
+                    &package.version
+                );
+
+                root.join(&package.component)
+                    .join(misc::pool_bucket(&source)?)
+                    .join(&source)
+                    .join([&source, "_", without_epoch(&version)].concat())
+                    .join("changelog")
+            }
+            Err(why) => {
+                warn!("failed to read the changelog of {}: {}", deb.display(), why);
+                continue
+            }
+        };
+
+        // The other binaries of a source are only read until one of them ships its changelog.
+        expected.insert(destination.clone());
+        if destination.exists() {
+            continue
+        }
+
+        match extract_changelog(&archive, &package.package) {
+            Ok(Some(changelog)) => {
+                fs::create_dir_all(destination.parent().unwrap())?;
+                misc::write(&destination, &changelog)?;
+            }
+            Ok(None) => debug!("{} does not ship a changelog", deb.display()),
+            Err(why) => warn!("failed to read the changelog of {}: {}", deb.display(), why)
+        }
+    }
+
+    if !root.exists() {
+        return Ok(());
+    }
+
+    let outdated = WalkDir::new(&root).into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && !expected.contains(entry.path()))
+        .map(|entry| entry.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    for path in outdated {
+        info!("removing {}, as its version is no longer published", path.display());
+        fs::remove_file(&path)?;
+    }
+
+    misc::remove_empty_directories_from(&root).map(|_| ())
+}
+
+/// The source of a binary package, and the version of the source, from the `Source` field of
+/// its control, which also names the version of the source if it differs from the binary's.
+fn source_of(field: Option<&str>, package: &str, version: &str) -> (String, String) {
+    match field.map(str::trim).filter(|field| !field.is_empty()) {
+        Some(field) => match field.find('(') {
+            Some(pos) => (
+                field[..pos].trim().to_owned(),
+                field[pos + 1..].trim_right_matches(')').trim().to_owned()
+            ),
+            None => (field.to_owned(), version.to_owned())
+        },
+        None => (package.to_owned(), version.to_owned())
+    }
+}
+
+fn without_epoch(version: &str) -> &str {
+    version.find(':').map_or(version, |pos| &version[pos + 1..])
+}
+
+/// The decompressed changelog that a package ships in its documentation, if it ships one, rather
+/// than a link to the documentation of another package.
+fn extract_changelog(archive: &DebFile, package: &str) -> io::Result<Option<Vec<u8>>> {
+    let names = [
+        ["usr/share/doc/", package, "/changelog.Debian.gz"].concat(),
+        ["usr/share/doc/", package, "/changelog.gz"].concat(),
+    ];
+
+    let mut found: Option<(usize, Vec<u8>)> = None;
+    archive.each_data(|entry| {
+        if !entry.header().entry_type().is_file() {
+            return Ok(());
+        }
+
+        let path = entry.path()?.to_string_lossy().trim_left_matches("./").to_owned();
+        if let Some(rank) = names.iter().position(|name| *name == path) {
+            if found.as_ref().map_or(true, |&(found, _)| rank < found) {
+                let mut changelog = Vec::new();
+                GzDecoder::new(entry)?.read_to_end(&mut changelog)?;
+                found = Some((rank, changelog));
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(found.map(|(_, changelog)| changelog))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use debian::{tarball, write_deb};
+    use deflate::Compression;
+    use deflate::write::GzEncoder;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn published_changelogs() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        fs::create_dir_all(repo.join("pool/bionic/main/f/foo")).unwrap();
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(b"foo (1:1.0-1) bionic; urgency=medium\n").unwrap();
+        let changelog = encoder.finish().unwrap();
+
+        let deb = |name: &str, source: &str, files: &[(&str, &[u8])]| {
+            let control = format!("Package: {}\nVersion: 1:1.0-1\nSource: {}\n", name, source);
+            let path = ["pool/bionic/main/f/foo/", name, "_1.0-1_amd64.deb"].concat();
+            write_deb(repo.join(&path), &tarball(&[("control", control.as_bytes())]).unwrap(), &tarball(files).unwrap()).unwrap();
+            Published {
+                suite: "bionic".into(),
+                component: "main".into(),
+                package: name.into(),
+                version: "1:1.0-1".into(),
+                architecture: "amd64".into(),
+                description: String::new(),
+                files: vec![(path, String::new(), String::new())],
+            }
+        };
+
+        let published = vec![
+            deb("foo-data", "foo", &[]),
+            deb("libfoo1", "foo (1:1.0-1)", &[("usr/share/doc/libfoo1/changelog.Debian.gz", &changelog[..])]),
+        ];
+
+        let stale = repo.join("changelogs/bionic/main/f/foo/foo_0.9-1/changelog");
+        fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        misc::write(&stale, b"old").unwrap();
+
+        refresh_changelogs(repo, "bionic", &published).unwrap();
+        let path = repo.join("changelogs/bionic/main/f/foo/foo_1.0-1/changelog");
+        assert_eq!(misc::read_to_string(&path).unwrap(), "foo (1:1.0-1) bionic; urgency=medium\n");
+        assert!(!stale.parent().unwrap().exists());
+
+        assert_eq!(source_of(None, "foo", "1.0"), ("foo".into(), "1.0".into()));
+        assert_eq!(source_of(Some("bar (2.0-1)"), "libbar", "2.0-1+b1"), ("bar".into(), "2.0-1".into()));
+    }
+}
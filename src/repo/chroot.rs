@@ -0,0 +1,155 @@
+use command::Command;
+use config::{Builder, BuilderBackend, Config};
+use libc;
+use std::io;
+
+/// A command which creates or refreshes a build environment.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ChrootCommand {
+    pub program: &'static str,
+    pub args: Vec<String>,
+}
+
+/// The name of the sbuild schroot, or path of the pbuilder base tarball, of a suite's architecture.
+pub(crate) fn chroot_name(builder: &Builder, backend: BuilderBackend, suite: &str, arch: &str) -> String {
+    if let Some(ref chroot) = builder.chroot {
+        return chroot.clone();
+    }
+
+    match backend {
+        BuilderBackend::Pbuilder => ["/var/cache/pbuilder/", suite, "-", arch, "-base.tgz"].concat(),
+        _ => [suite, "-", arch, "-sbuild"].concat(),
+    }
+}
+
+fn no_chroot() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "the dpkg builder builds on the host, and has no chroot")
+}
+
+pub(crate) fn create_command(builder: &Builder, suite: &str, arch: &str) -> io::Result<ChrootCommand> {
+    let backend = builder.backend.unwrap_or_default();
+    let chroot = chroot_name(builder, backend, suite, arch);
+
+    match backend {
+        BuilderBackend::Sbuild => {
+            let mut args = vec![format!("--arch={}", arch)];
+            // A configured schroot name is added as an alias, so that sbuild may find it.
+            if builder.chroot.is_some() {
+                args.push(format!("--alias={}", chroot));
+            }
+
+            args.push(suite.to_owned());
+            args.push(["/srv/chroot/", suite, "-", arch, "-sbuild"].concat());
+            if let Some(ref mirror) = builder.mirror {
+                args.push(mirror.clone());
+            }
+
+            Ok(ChrootCommand { program: "sbuild-createchroot", args })
+        }
+        BuilderBackend::Pbuilder => {
+            let mut args = vec![
+                "create".to_owned(),
+                "--distribution".into(),
+                suite.into(),
+                "--architecture".into(),
+                arch.into(),
+                "--basetgz".into(),
+                chroot,
+            ];
+
+            if let Some(ref mirror) = builder.mirror {
+                args.push("--mirror".into());
+                args.push(mirror.clone());
+            }
+
+            Ok(ChrootCommand { program: "pbuilder", args })
+        }
+        BuilderBackend::Dpkg => Err(no_chroot())
+    }
+}
+
+pub(crate) fn update_command(builder: &Builder, suite: &str, arch: &str) -> io::Result<ChrootCommand> {
+    let backend = builder.backend.unwrap_or_default();
+    let chroot = chroot_name(builder, backend, suite, arch);
+
+    match backend {
+        BuilderBackend::Sbuild => Ok(ChrootCommand { program: "sbuild-update", args: vec!["-udcar".into(), chroot] }),
+        BuilderBackend::Pbuilder => Ok(ChrootCommand {
+            program: "pbuilder",
+            args: vec!["update".into(), "--basetgz".into(), chroot]
+        }),
+        BuilderBackend::Dpkg => Err(no_chroot())
+    }
+}
+
+/// Runs the command, through `sudo` if we are not already root.
+fn run(command: &ChrootCommand) -> io::Result<()> {
+    info!("running {} {}", command.program, command.args.join(" "));
+    if unsafe { libc::geteuid() } == 0 {
+        Command::new(command.program).args(&command.args).run()
+    } else {
+        Command::new("sudo").arg(command.program).args(&command.args).run()
+    }
+}
+
+/// Creates the build environment of a suite's architecture for the given builder.
+pub fn create_chroot(builder: &Builder, suite: &str, arch: &str) -> io::Result<()> {
+    run(&create_command(builder, suite, arch)?)
+}
+
+/// Refreshes every build environment that the suite's sources are built within.
+pub fn update_chroots(config: &Config) -> io::Result<()> {
+    let mut builders = vec![config.builder.clone().unwrap_or_default()];
+    for source in config.source.iter().flat_map(|sources| sources.iter()) {
+        let builder = config.builder_for(source);
+        if !builders.contains(&builder) {
+            builders.push(builder);
+        }
+    }
+
+    let mut updated = Vec::new();
+    for builder in builders {
+        if builder.backend == Some(BuilderBackend::Dpkg) {
+            continue
+        }
+
+        let suite = builder.distribution.clone().unwrap_or_else(|| config.archive.clone());
+        for arch in builder.architectures.as_ref().unwrap_or(&config.architectures) {
+            let command = update_command(&builder, &suite, arch)?;
+            if !updated.contains(&command) {
+                run(&command)?;
+                updated.push(command);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chroot_commands() {
+        let sbuild = Builder { mirror: Some("http://archive.ubuntu.com/ubuntu".into()), .. Builder::default() };
+        assert_eq!(create_command(&sbuild, "bionic", "amd64").unwrap(), ChrootCommand {
+            program: "sbuild-createchroot",
+            args: vec![
+                "--arch=amd64".into(),
+                "bionic".into(),
+                "/srv/chroot/bionic-amd64-sbuild".into(),
+                "http://archive.ubuntu.com/ubuntu".into(),
+            ]
+        });
+        assert_eq!(update_command(&sbuild, "bionic", "amd64").unwrap().args, vec!["-udcar", "bionic-amd64-sbuild"]);
+
+        let pbuilder = Builder { backend: Some(BuilderBackend::Pbuilder), .. Builder::default() };
+        assert_eq!(update_command(&pbuilder, "bionic", "i386").unwrap().args, vec![
+            "update", "--basetgz", "/var/cache/pbuilder/bionic-i386-base.tgz"
+        ]);
+
+        let dpkg = Builder { backend: Some(BuilderBackend::Dpkg), .. Builder::default() };
+        assert!(create_command(&dpkg, "bionic", "amd64").is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use action;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::build::directory_bytes;
+use super::download::GIT_CACHE;
+use super::prepare::{CACHED_ASSETS, SOURCES_CACHE, SOURCES_KEYS, SOURCES_TREES};
+use workspace;
+
+/// The intermediate artifacts of the project which may be cleaned, and their directories. The
+/// published repo is never among them, as it may have been generated elsewhere with `repo_path`.
+const CLEAN_TARGETS: &[(&str, &[&str])] = &[
+    ("sources", &[SOURCES_TREES]),
+    ("builds", &["build/"]),
+    ("cache", &[SOURCES_CACHE, SOURCES_KEYS, GIT_CACHE, CACHED_ASSETS]),
+    ("logs", &["logs/"]),
+];
+
+/// Removes the directories of each target from the project, returning the bytes reclaimed by
+/// each directory which was removed. The `all` target cleans every target.
+pub fn clean(project: &Path, targets: &[&str]) -> io::Result<Vec<(&'static str, u64)>> {
+    let all = targets.contains(&"all");
+    let mut reclaimed = Vec::new();
+    for &(target, directories) in CLEAN_TARGETS {
+        if !all && !targets.contains(&target) {
+            continue
+        }
+
+        for &directory in directories {
+            let path = project.join(directory);
+            if path.exists() {
+                let bytes = directory_bytes(&path);
+                action::remove_dir_all(&path)?;
+                reclaimed.push((directory, bytes));
+            }
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+/// Removes the workspaces within `tmp` of runs which crashed or were killed, along with the files
+/// which they left staged in the project or repo, returning the bytes reclaimed by each. The
+/// workspaces of runs which are still running are kept.
+pub fn clean_stale(tmp: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+    let mut reclaimed = Vec::new();
+    for directory in workspace::stale(tmp)? {
+        let bytes = directory_bytes(&directory) + workspace::leftovers(&directory).iter()
+            .map(|path| directory_bytes(path))
+            .sum::<u64>();
+
+        action::perform(format_args!("remove {}", directory.display()), || workspace::remove(&directory))?;
+        reclaimed.push((directory, bytes));
+    }
+
+    Ok(reclaimed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn clean_targets() {
+        let dir = tempdir().unwrap();
+        let project = dir.path();
+        for &(file, contents) in &[
+            ("sources/trees/abc123/debian/control", "Source: foo\n"),
+            ("sources/cache/def456/foo-1.0.tar.gz", "tarball"),
+            ("logs/bionic/foo/1.0-amd64-20181001T120000.log", "log"),
+            ("repo/pool/bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb", "deb"),
+        ] {
+            let path = project.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            misc::write(path, contents).unwrap();
+        }
+
+        assert_eq!(clean(project, &["sources", "logs"]).unwrap(), vec![("sources/trees/", 12), ("logs/", 3)]);
+        assert!(!project.join("sources/trees").exists() && !project.join("logs").exists());
+        assert!(project.join("sources/cache/def456/foo-1.0.tar.gz").exists());
+
+        assert_eq!(clean(project, &["all"]).unwrap(), vec![("sources/cache/", 7)]);
+        assert!(project.join("repo/pool/bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb").exists());
+    }
+}
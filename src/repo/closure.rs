@@ -0,0 +1,238 @@
+use config::{Closure, Config};
+use debian::{compare_versions, parse_control};
+use http;
+use retry::retry;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use super::download::{DownloadError, UpstreamRelease};
+use super::verify::read_index;
+
+/// The fields of a package whose dependencies must be satisfied for it to be installed.
+const FIELDS: &[&str] = &["Pre-Depends", "Depends"];
+
+/// A dependency of a package which no package of the suite, nor of its upstream repositories,
+/// satisfies on that architecture.
+#[derive(Debug, PartialEq)]
+pub struct Unsatisfied {
+    pub architecture: String,
+    pub package: String,
+    pub version: String,
+    /// The field of the package which the dependency is from.
+    pub field: &'static str,
+    /// The dependency, with each of its alternatives.
+    pub dependency: String,
+}
+
+impl fmt::Display for Unsatisfied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}) cannot satisfy its {} on {}",
+            self.package,
+            self.version,
+            self.architecture,
+            self.field,
+            self.dependency
+        )
+    }
+}
+
+/// The versions of each package which may satisfy a dependency, by the names that they are
+/// installed as, or provided as. Packages which are provided without a version only satisfy
+/// dependencies without a version.
+#[derive(Default)]
+struct Available {
+    packages: HashMap<String, Vec<Option<String>>>,
+}
+
+impl Available {
+    fn add(&mut self, index: &str) {
+        let stanzas = index.split("\n\n").map(|x| x.trim()).filter(|x| !x.is_empty());
+        for control in stanzas.map(parse_control) {
+            let (name, version) = match (control.get("Package"), control.get("Version")) {
+                (Some(name), Some(version)) => (name, version),
+                _ => continue
+            };
+
+            self.insert(name, Some(version.clone()));
+            for provided in control.get("Provides").iter().flat_map(|x| x.split(',')) {
+                if let Some((name, constraint)) = parse_relation(provided) {
+                    let version = constraint.and_then(|(operator, version)| {
+                        if operator == "=" { Some(version.to_owned()) } else { None }
+                    });
+
+                    self.insert(name, version);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, name: &str, version: Option<String>) {
+        self.packages.entry(name.to_owned()).or_insert_with(Vec::new).push(version);
+    }
+
+    /// Whether any alternative of the dependency is satisfied.
+    fn satisfies(&self, dependency: &str) -> bool {
+        dependency.split('|').filter_map(parse_relation).any(|(name, constraint)| {
+            self.packages.get(name).map_or(false, |versions| {
+                versions.iter().any(|version| match (version, constraint) {
+                    (_, None) => true,
+                    (&Some(ref version), Some((operator, required))) => {
+                        satisfies_version(version, operator, required)
+                    }
+                    (&None, Some(_)) => false
+                })
+            })
+        })
+    }
+}
+
+/// Resolves every `Depends` and `Pre-Depends` of the `Packages` indices of the components of the
+/// generated suite at `dist`, for each architecture of the suite, against the packages of the
+/// suite and of its upstream repositories.
+pub fn check_closure(
+    config: &Config,
+    closure: &Closure,
+    dist: &Path,
+    components: &[String]
+) -> io::Result<Vec<Unsatisfied>> {
+    let client = http::client(&config.http_settings())?;
+    let policy = config.retry_policy();
+    let mut unsatisfied = Vec::new();
+
+    let as_io = |why: DownloadError| io::Error::new(io::ErrorKind::Other, format!("{}", why));
+    let mut releases = Vec::new();
+    for upstream in &closure.upstream {
+        let root = upstream.url.trim_right_matches('/');
+        let release = retry(&policy, &upstream.url, || UpstreamRelease::fetch(&client, root, &upstream.suite, &upstream.keyring))
+            .map_err(&as_io)?;
+        releases.push((upstream, release));
+    }
+
+    for arch in config.release_architectures() {
+        let mut available = Available::default();
+        let mut indices = Vec::new();
+        for component in components {
+            let directory = dist.join(component).join(["binary-", arch].concat());
+            if let Some((_, index)) = read_index(&directory, "Packages")? {
+                available.add(&index);
+                indices.push(index);
+            }
+        }
+
+        for &(upstream, ref release) in &releases {
+            for component in &upstream.components {
+                let directory = format!("{}/binary-{}", component, arch);
+                info!("fetching the packages of {} {}/{}", upstream.url, upstream.suite, directory);
+                let data = retry(&policy, &directory, || release.packages(&client, &directory))
+                    .map_err(&as_io)?;
+                available.add(&data);
+            }
+        }
+
+        for index in &indices {
+            unsatisfied.extend(unsatisfied_dependencies(arch, index, &available));
+        }
+    }
+
+    Ok(unsatisfied)
+}
+
+/// The dependencies of each package of the index which are not available.
+fn unsatisfied_dependencies(arch: &str, index: &str, available: &Available) -> Vec<Unsatisfied> {
+    let mut unsatisfied = Vec::new();
+    let stanzas = index.split("\n\n").map(|x| x.trim()).filter(|x| !x.is_empty());
+    for control in stanzas.map(parse_control) {
+        for &field in FIELDS {
+            let dependencies = control.get(field).iter()
+                .flat_map(|x| x.split(','))
+                .map(|x| x.split_whitespace().collect::<Vec<&str>>().join(" "))
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<String>>();
+
+            for dependency in dependencies {
+                if !available.satisfies(&dependency) {
+                    unsatisfied.push(Unsatisfied {
+                        architecture: arch.to_owned(),
+                        package: control.get("Package").cloned().unwrap_or_default(),
+                        version: control.get("Version").cloned().unwrap_or_default(),
+                        field,
+                        dependency,
+                    });
+                }
+            }
+        }
+    }
+
+    unsatisfied
+}
+
+/// Parses a relation, such as `libfoo1:any (>= 1.0)`, into the name of its package, and its
+/// version constraint if it has one.
+fn parse_relation(relation: &str) -> Option<(&str, Option<(&str, &str)>)> {
+    let relation = relation.trim();
+    let end = relation.find(|c: char| c.is_whitespace() || c == '(').unwrap_or(relation.len());
+    let name = relation[..end].split(':').next().unwrap_or("");
+    if name.is_empty() {
+        return None;
+    }
+
+    let constraint = relation[end..].find('(').and_then(|start| {
+        let constraint = &relation[end + start + 1..];
+        let constraint = constraint[..constraint.find(')')?].trim();
+        let split = constraint.find(|c: char| !"<>=".contains(c))?;
+        Some((constraint[..split].trim(), constraint[split..].trim()))
+    });
+
+    Some((name, constraint))
+}
+
+fn satisfies_version(version: &str, operator: &str, required: &str) -> bool {
+    let ordering = compare_versions(version, required);
+    match operator {
+        "<<" => ordering == Ordering::Less,
+        "<=" | "<" => ordering != Ordering::Greater,
+        "=" => ordering == Ordering::Equal,
+        ">=" | ">" => ordering != Ordering::Less,
+        ">>" => ordering == Ordering::Greater,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependency_closure() {
+        let suite = "Package: foo\nVersion: 1.0\nArchitecture: amd64\n\
+            Depends: libfoo1 (>= 1.2), libc6:any (>= 2.27), default-mta | mail-transport-agent,\n \
+            missing\nPre-Depends: dpkg (>> 1.19)\n\n\
+            Package: libfoo1\nVersion: 1.1\nArchitecture: amd64\nDepends: libc6 (<< 2.28)\n\n\
+            Package: bar\nVersion: 1.0\nArchitecture: all\nDepends: virtual-foo (= 1.0), libbar\n";
+
+        let upstream = "Package: libc6\nVersion: 2.27-3ubuntu1\nArchitecture: amd64\n\n\
+            Package: dpkg\nVersion: 1.19.0.5ubuntu2\nArchitecture: amd64\n\n\
+            Package: postfix\nVersion: 3.3.0-1\nArchitecture: amd64\n\
+            Provides: mail-transport-agent, virtual-foo (= 1.0), libbar\n";
+
+        let mut available = Available::default();
+        available.add(suite);
+        available.add(upstream);
+
+        let unsatisfied = unsatisfied_dependencies("amd64", suite, &available).into_iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+
+        assert_eq!(unsatisfied, vec![
+            "foo 1.0 (amd64) cannot satisfy its Depends on libfoo1 (>= 1.2)",
+            "foo 1.0 (amd64) cannot satisfy its Depends on missing",
+        ]);
+
+        assert_eq!(parse_relation("libc6:any (>=2.27)"), Some(("libc6", Some((">=", "2.27")))));
+        assert_eq!(parse_relation("libfoo1"), Some(("libfoo1", None)));
+    }
+}
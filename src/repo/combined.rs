@@ -0,0 +1,86 @@
+use debian::parse_control;
+use misc;
+use std::io::{self, Write};
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// Writes every `Packages` stanza in the repo into a single index, for dashboards and search
+/// tooling which want one view of the repo. This is not an index that apt can consume.
+///
+/// Each stanza is given `Suite` and `Component` pseudo-fields so that its origin can be
+/// recovered, and stanzas are sorted by package, suite, component, architecture, and version.
+pub fn generate_combined_index<W: Write>(repo: &Path, out: &mut W) -> io::Result<()> {
+    let dists = repo.join("dists");
+    let mut stanzas = Vec::new();
+
+    let indices = WalkDir::new(&dists)
+        .min_depth(4)
+        .max_depth(4)
+        .into_iter()
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_name() == "Packages");
+
+    for index in indices {
+        let (suite, component) = {
+            let mut components = index.path().strip_prefix(&dists).unwrap()
+                .iter()
+                .filter_map(|x| x.to_str());
+            match (components.next(), components.next()) {
+                (Some(suite), Some(component)) => (suite.to_owned(), component.to_owned()),
+                _ => continue
+            }
+        };
+
+        // Skips suites which are being staged for, or retired from, publishing.
+        if suite.ends_with(".new") || suite.ends_with(".old") {
+            continue
+        }
+
+        let data = misc::read_to_string(index.path())?;
+        for stanza in data.split("\n\n").map(|x| x.trim_right()).filter(|x| !x.is_empty()) {
+            let control = parse_control(stanza);
+            let field = |key: &str| control.get(key).cloned().unwrap_or_default();
+            let key = (field("Package"), suite.clone(), component.clone(), field("Architecture"), field("Version"));
+            stanzas.push((key, format!("{}\nSuite: {}\nComponent: {}\n", stanza, suite, component)));
+        }
+    }
+
+    stanzas.sort();
+    for (id, &(_, ref stanza)) in stanzas.iter().enumerate() {
+        if id != 0 {
+            out.write_all(b"\n")?;
+        }
+
+        out.write_all(stanza.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn combined_index() {
+        let dir = tempdir().unwrap();
+        for &(suite, contents) in &[
+            ("cosmic", "Package: foo\nVersion: 2.0\nArchitecture: amd64\n"),
+            ("bionic", "Package: foo\nVersion: 1.0\nArchitecture: amd64\n\nPackage: bar\nVersion: 1.0\nArchitecture: amd64\n"),
+            ("bionic.new", "Package: foo\nVersion: 1.1\nArchitecture: amd64\n"),
+        ] {
+            let binary = dir.path().join("dists").join(suite).join("main/binary-amd64");
+            fs::create_dir_all(&binary).unwrap();
+            misc::write(binary.join("Packages"), contents).unwrap();
+        }
+
+        let mut out = Vec::new();
+        generate_combined_index(dir.path(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+Package: bar\nVersion: 1.0\nArchitecture: amd64\nSuite: bionic\nComponent: main\n
+Package: foo\nVersion: 1.0\nArchitecture: amd64\nSuite: bionic\nComponent: main\n
+Package: foo\nVersion: 2.0\nArchitecture: amd64\nSuite: cosmic\nComponent: main\n");
+    }
+}
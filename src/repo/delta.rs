@@ -0,0 +1,199 @@
+use checksum::hasher;
+use command::Command;
+use debian::compare_versions;
+use misc;
+use sha2::Sha256;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use super::query::Published;
+use walkdir::WalkDir;
+
+/// The directory of the repo which the deltas of each suite are published within.
+pub const DELTAS: &str = "deltas";
+
+/// A delta from an older version of a package to its published version.
+#[derive(Debug, PartialEq)]
+struct Delta {
+    package: String,
+    architecture: String,
+    old_version: String,
+    new_version: String,
+}
+
+impl Delta {
+    /// The name of the delta, in the format of debdelta, where the epoch's `:` is escaped.
+    fn filename(&self) -> String {
+        let escape = |version: &str| version.replace(':', "%3a");
+        format!(
+            "{}_{}_{}_{}.debdelta",
+            self.package,
+            escape(&self.old_version),
+            escape(&self.new_version),
+            self.architecture
+        )
+    }
+
+    fn parse(filename: &str) -> Option<Delta> {
+        if !filename.ends_with(".debdelta") {
+            return None;
+        }
+
+        let fields = filename[..filename.len() - 9].split('_').collect::<Vec<&str>>();
+        if fields.len() != 4 {
+            return None;
+        }
+
+        let unescape = |version: &str| version.replace("%3a", ":");
+        Some(Delta {
+            package: fields[0].to_owned(),
+            old_version: unescape(fields[1]),
+            new_version: unescape(fields[2]),
+            architecture: fields[3].to_owned(),
+        })
+    }
+}
+
+/// Generates a delta with debdelta from the previously published version of each binary package
+/// of the suite to its newly published version, while the previous version is still in the pool,
+/// and writes the `Index` of the suite's deltas to `deltas/<suite>/Index`.
+///
+/// Deltas to a version which is no longer published are removed, and of the deltas to each
+/// published version, only the `keep` deltas from the newest versions are kept. A package which
+/// debdelta fails to generate a delta for is only warned about, as its `.deb` is still published.
+pub fn refresh_deltas(
+    repo: &Path,
+    suite: &str,
+    previous: &[Published],
+    current: &[Published],
+    keep: usize
+) -> io::Result<()> {
+    let root = repo.join(DELTAS).join(suite);
+    let published = current.iter()
+        .filter(|package| package.architecture != "source")
+        .map(|package| ((package.package.as_str(), package.architecture.as_str()), package))
+        .collect::<BTreeMap<(&str, &str), &Published>>();
+
+    for package in published.values() {
+        let older = previous.iter()
+            .filter(|old| {
+                old.package == package.package
+                    && old.architecture == package.architecture
+                    && compare_versions(&old.version, &package.version) == Ordering::Less
+            })
+            .max_by(|a, b| compare_versions(&a.version, &b.version));
+
+        let (older, old, new) = match (older, package.files.first()) {
+            (Some(older), Some(new)) => match older.files.first() {
+                Some(old) => (older, repo.join(&old.0), repo.join(&new.0)),
+                None => continue
+            },
+            _ => continue
+        };
+
+        if !old.is_file() || !new.is_file() {
+            continue
+        }
+
+        let delta = Delta {
+            package: package.package.clone(),
+            architecture: package.architecture.clone(),
+            old_version: older.version.clone(),
+            new_version: package.version.clone(),
+        };
+
+        let destination = root.join(&package.component)
+            .join(misc::pool_bucket(&package.package)?)
+            .join(&package.package)
+            .join(delta.filename());
+
+        if destination.exists() {
+            continue
+        }
+
+        fs::create_dir_all(destination.parent().unwrap())?;
+        info!("generating a delta from {} to {}", old.display(), new.display());
+        let generated = Command::new("debdelta").arg("-q").arg(&old).arg(&new).arg(&destination).run();
+        if let Err(why) = generated {
+            warn!("failed to generate a delta from {} to {}: {}", old.display(), new.display(), why);
+            let _ = fs::remove_file(&destination);
+        }
+    }
+
+    if !root.exists() {
+        return Ok(());
+    }
+
+    // The deltas to each published version, from the newest version to the oldest.
+    let mut deltas: BTreeMap<(String, String), Vec<(Delta, PathBuf)>> = BTreeMap::new();
+    for entry in WalkDir::new(&root).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+        let delta = match entry.file_name().to_str().and_then(Delta::parse) {
+            Some(delta) => delta,
+            None => continue
+        };
+
+        let target = published.get(&(delta.package.as_str(), delta.architecture.as_str()));
+        if target.map_or(true, |package| package.version != delta.new_version) {
+            info!("removing {}, as its version is no longer published", entry.path().display());
+            fs::remove_file(entry.path())?;
+            continue
+        }
+
+        deltas.entry((delta.package.clone(), delta.architecture.clone()))
+            .or_insert_with(Vec::new)
+            .push((delta, entry.path().to_path_buf()));
+    }
+
+    let mut index = Vec::new();
+    for (_, mut deltas) in deltas {
+        deltas.sort_by(|a, b| compare_versions(&b.0.old_version, &a.0.old_version));
+        for (position, (delta, path)) in deltas.into_iter().enumerate() {
+            if position >= keep {
+                info!("removing {}, as {} newer deltas are kept", path.display(), keep);
+                fs::remove_file(&path)?;
+                continue
+            }
+
+            let size = fs::metadata(&path)?.len();
+            let sha256 = hasher::<Sha256, File>(File::open(&path)?)?;
+            let filename = path.strip_prefix(repo).unwrap_or(&path).display().to_string();
+            index.push(format!(
+                "Package: {}\nArchitecture: {}\nOld-Version: {}\nNew-Version: {}\n\
+                 Filename: {}\nSize: {}\nSHA256: {}\n",
+                delta.package,
+                delta.architecture,
+                delta.old_version,
+                delta.new_version,
+                filename,
+                size,
+                sha256
+            ));
+        }
+    }
+
+    misc::remove_empty_directories_from(&root)?;
+    fs::create_dir_all(&root)?;
+    misc::write(root.join("Index"), index.join("\n").as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_filenames() {
+        let delta = Delta {
+            package: "libfoo1".into(),
+            architecture: "amd64".into(),
+            old_version: "1:1.0-1".into(),
+            new_version: "1:1.1-1".into(),
+        };
+
+        assert_eq!(delta.filename(), "libfoo1_1%3a1.0-1_1%3a1.1-1_amd64.debdelta");
+        assert_eq!(Delta::parse(&delta.filename()), Some(delta));
+        assert_eq!(Delta::parse("libfoo1_1.0-1_amd64.deb"), None);
+        assert_eq!(Delta::parse("Index"), None);
+    }
+}
@@ -0,0 +1,189 @@
+use debian::compare_versions;
+use logging::json_string;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use super::query::{table, Published};
+
+/// How a package differs between two states of a suite.
+#[derive(Debug, PartialEq)]
+pub struct PackageDiff {
+    pub package: String,
+    pub architecture: String,
+    /// The version of the package before, if it was published.
+    pub from: Option<String>,
+    /// The version of the package after, if it is still published.
+    pub to: Option<String>,
+}
+
+impl PackageDiff {
+    pub fn change(&self) -> &'static str {
+        match (self.from.as_ref(), self.to.as_ref()) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            (Some(from), Some(to)) => match compare_versions(from, to) {
+                Ordering::Greater => "downgraded",
+                _ => "upgraded"
+            }
+        }
+    }
+}
+
+/// Compares the newest version of each package of each architecture that two states of a suite
+/// publish, returning each package which was added, removed, or given another version, by name
+/// and then by architecture.
+pub fn diff(from: &[Published], to: &[Published]) -> Vec<PackageDiff> {
+    fn newest(packages: &[Published]) -> BTreeMap<(&str, &str), &str> {
+        let mut newest = BTreeMap::new();
+        for package in packages {
+            let version = newest.entry((package.package.as_str(), package.architecture.as_str()))
+                .or_insert_with(|| package.version.as_str());
+            if compare_versions(version, &package.version) == Ordering::Less {
+                *version = package.version.as_str();
+            }
+        }
+
+        newest
+    }
+
+    let from = newest(from);
+    let to = newest(to);
+
+    let mut keys = from.keys().chain(to.keys()).cloned().collect::<Vec<(&str, &str)>>();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter(|key| from.get(key) != to.get(key))
+        .map(|key| PackageDiff {
+            package: key.0.to_owned(),
+            architecture: key.1.to_owned(),
+            from: from.get(&key).map(|&version| version.to_owned()),
+            to: to.get(&key).map(|&version| version.to_owned()),
+        })
+        .collect()
+}
+
+/// A table of each change, followed by the number of each kind of change.
+pub fn diff_table(diffs: &[PackageDiff]) -> String {
+    let mut rows = vec![["CHANGE", "PACKAGE", "ARCH", "FROM", "TO"].iter()
+        .map(|&x| x.to_owned())
+        .collect::<Vec<String>>()];
+
+    let mut counts = BTreeMap::new();
+    for diff in diffs {
+        *counts.entry(diff.change()).or_insert(0) += 1;
+        rows.push(vec![
+            diff.change().to_owned(),
+            diff.package.clone(),
+            diff.architecture.clone(),
+            diff.from.clone().unwrap_or_else(|| "-".into()),
+            diff.to.clone().unwrap_or_else(|| "-".into()),
+        ]);
+    }
+
+    let count = |change| counts.get(change).cloned().unwrap_or(0);
+    format!(
+        "{}{} added, {} removed, {} upgraded, {} downgraded\n",
+        table(&rows),
+        count("added"),
+        count("removed"),
+        count("upgraded"),
+        count("downgraded")
+    )
+}
+
+/// The changes as a JSON object, which has the `from` and `to` states that were compared, and a
+/// `changes` array. Each change has its `package`, `architecture`, `change`, and the `from` and
+/// `to` versions, which are `null` where the package was not published.
+pub fn diff_json(from: &str, to: &str, diffs: &[PackageDiff]) -> String {
+    let version = |version: &Option<String>| version.as_ref().map_or("null".into(), |x| json_string(x));
+
+    let mut output = format!(
+        "{{\n  \"from\": {},\n  \"to\": {},\n  \"changes\": [",
+        json_string(from),
+        json_string(to)
+    );
+
+    for (id, diff) in diffs.iter().enumerate() {
+        output.push_str(if id == 0 { "\n" } else { ",\n" });
+        output.push_str(&format!(
+            "    {{ \"package\": {}, \"architecture\": {}, \"change\": {}, \
+             \"from\": {}, \"to\": {} }}",
+            json_string(&diff.package),
+            json_string(&diff.architecture),
+            json_string(diff.change()),
+            version(&diff.from),
+            version(&diff.to)
+        ));
+    }
+
+    output.push_str(if diffs.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suite_diff() {
+        let package = |name: &str, version: &str, arch: &str| Published {
+            suite: String::new(),
+            component: "main".into(),
+            package: name.into(),
+            version: version.into(),
+            architecture: arch.into(),
+            description: String::new(),
+            files: Vec::new(),
+        };
+
+        let staging = vec![
+            package("bar", "2.0", "amd64"),
+            package("baz", "1.0", "all"),
+            package("foo", "1.1", "amd64"),
+            package("foo", "1.1", "source"),
+            package("qux", "1.0", "amd64"),
+        ];
+
+        let release = vec![
+            package("baz", "1.0", "all"),
+            package("foo", "1.0", "amd64"),
+            package("foo", "1.0", "source"),
+            package("old", "0.1", "amd64"),
+            package("qux", "1.0", "amd64"),
+            package("qux", "1.1", "amd64"),
+        ];
+
+        let diffs = diff(&release, &staging);
+        let changes = diffs.iter()
+            .map(|diff| [diff.change(), &diff.package, &diff.architecture].join(" "))
+            .collect::<Vec<String>>();
+
+        assert_eq!(changes, vec![
+            "added bar amd64",
+            "upgraded foo amd64",
+            "upgraded foo source",
+            "removed old amd64",
+            "downgraded qux amd64",
+        ]);
+
+        assert_eq!(diff_table(&diffs[3..]), [
+            "CHANGE     PACKAGE ARCH  FROM TO\n",
+            "removed    old     amd64 0.1  -\n",
+            "downgraded qux     amd64 1.1  1.0\n",
+            "0 added, 1 removed, 0 upgraded, 1 downgraded\n",
+        ].concat());
+
+        assert_eq!(diff_json("release", "staging", &diffs[..1]), [
+            "{\n  \"from\": \"release\",\n  \"to\": \"staging\",\n  \"changes\": [\n",
+            "    { \"package\": \"bar\", \"architecture\": \"amd64\", \"change\": \"added\", ",
+            "\"from\": null, \"to\": \"2.0\" }\n",
+            "  ]\n}\n",
+        ].concat());
+
+        assert_eq!(
+            diff_json("a", "b", &[]),
+            "{\n  \"from\": \"a\",\n  \"to\": \"b\",\n  \"changes\": []\n}\n"
+        );
+    }
+}
@@ -2,8 +2,14 @@ use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use reqwest::Client;
 use std::io;
+use std::path::Path;
 use std::sync::Arc;
-use config::Direct;
+use checksum::PooledDigests;
+use config::{Direct, PoolPolicy, RetryPolicy};
+use error::Error;
+use retry::retry;
+use throttle::Bandwidth;
+use super::super::pool::{cp_to_pool, link_to_pool, ARCHIVES_ONLY};
 use super::request::{self, RequestCompare};
 
 /// Possible messages that may be returned when a download has succeeded.
@@ -12,16 +18,43 @@ pub enum DownloadResult {
 }
 
 /// Given an item with a URL, download the item if the item does not already exist.
-pub fn download(client: Arc<Client>, item: &Direct, suite: &str, component: &str) -> io::Result<DownloadResult> {
+///
+/// The packages in the local directory of the item, if it has one, are copied into the pool.
+pub fn download(
+    client: Arc<Client>,
+    item: &Direct,
+    pool: &Path,
+    suite: &str,
+    component: &str,
+    policy: &RetryPolicy,
+    bandwidth: &Bandwidth,
+    pool_policy: &PoolPolicy,
+    pooled: &PooledDigests,
+) -> io::Result<DownloadResult> {
     info!("checking if {} needs to be downloaded", item.name);
 
     let mut downloaded = 0;
 
-    for (destination, path) in item.get_destinations(suite, component)?.into_iter().zip(item.urls.iter()) {
-        let checksum = path.checksum.as_ref().map(|x| x.as_str());
+    for destination in item.get_destinations(pool, suite, component)? {
+        let checksum = destination.sha256.as_ref().map(|x| x.as_str());
         // If the file is to be repackaged, store it in the assets directory, else the pool.
         let target = destination.assets.as_ref().map_or(&destination.pool, |x| &x.1);
-        downloaded += request::file(client.clone(), item.name.clone(), &destination.url, RequestCompare::Checksum(checksum), target)?;
+        downloaded += retry(policy, &item.name, || {
+            request::file(client.clone(), item.name.clone(), &destination.url, RequestCompare::Checksum(checksum), target, bandwidth)
+        }).map_err(|why| Error::Download {
+            url: destination.url.clone(),
+            file: target.clone(),
+            why
+        })?;
+    }
+
+    if let Some(ref path) = item.path {
+        let component = item.component.as_ref().map_or(component, |x| x.as_str());
+        if item.link {
+            link_to_pool(path, pool, suite, component, ARCHIVES_ONLY, None, pool_policy)?;
+        } else {
+            cp_to_pool(path, pool, suite, component, ARCHIVES_ONLY, None, pool_policy, pooled)?;
+        }
     }
 
     info!("finished downloading {}", &item.name);
@@ -29,18 +62,26 @@ pub fn download(client: Arc<Client>, item: &Direct, suite: &str, component: &str
 }
 
 /// Downloads pre-built Debian packages in parallel
-pub fn parallel(items: &[Direct], suite: &str, component: &str) -> Vec<io::Result<DownloadResult>> {
-    let client = Arc::new(Client::new());
-
-    // Only up to 8 downloads at a time.
+pub fn parallel(
+    client: Arc<Client>,
+    items: &[Direct],
+    pool: &Path,
+    suite: &str,
+    component: &str,
+    policy: &RetryPolicy,
+    bandwidth: &Bandwidth,
+    pool_policy: &PoolPolicy,
+    pooled: &PooledDigests,
+) -> Vec<io::Result<DownloadResult>> {
+    // Only up to `max_transfers` at a time, which defaults to 8.
     let thread_pool = ThreadPoolBuilder::new()
-        .num_threads(8)
+        .num_threads(bandwidth.max_transfers)
         .build()
         .expect("failed to build thread pool");
 
     thread_pool.install(|| {
         items.par_iter()
-            .map(|item| download(client.clone(), item, suite, component))
+            .map(|item| download(client.clone(), item, pool, suite, component, policy, bandwidth, pool_policy, pooled))
             .collect()
     })
 }
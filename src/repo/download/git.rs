@@ -0,0 +1,87 @@
+use command::Command;
+use std::{env, fs, io};
+use std::path::Path;
+
+/// The directory where the repositories of git sources are mirrored between runs.
+pub const GIT_CACHE: &str = "sources/git/";
+
+/// The revision of a git source that is built. A commit takes precedence over a tag, and a tag
+/// over a branch. If none are given, the default branch of the repository is built.
+pub fn revision(branch: &Option<String>, tag: &Option<String>, commit: &Option<String>) -> String {
+    match (commit, tag, branch) {
+        (Some(commit), _, _) => commit.trim().to_owned(),
+        (None, Some(tag), _) => ["refs/tags/", tag].concat(),
+        (None, None, Some(branch)) => ["refs/heads/", branch].concat(),
+        (None, None, None) => "HEAD".to_owned(),
+    }
+}
+
+/// Mirrors the repository of a git source into the cache, and checks out its revision into the
+/// project directory, returning the commit that was checked out.
+///
+/// - If the repository has not been mirrored, it will be cloned; otherwise it will be fetched.
+/// - The revision is checked out on a local branch, named after the configured branch, so that
+///   the commit can be recorded by the build. Local changes to the project are discarded.
+pub fn fetch(
+    name: &str,
+    url: &str,
+    branch: &Option<String>,
+    tag: &Option<String>,
+    commit: &Option<String>,
+    project: &Path,
+) -> io::Result<String> {
+    let cache = env::current_dir()?.join([GIT_CACHE, name, ".git"].concat());
+
+    if cache.exists() {
+        git(&cache).args(&["remote", "set-url", "origin", url]).run()?;
+        git(&cache).args(&["fetch", "--prune", "origin"]).run()?;
+    } else {
+        fs::create_dir_all(GIT_CACHE)?;
+        Command::new("git").args(&["clone", "--mirror", url]).arg(&cache).run()?;
+    }
+
+    let revision = revision(branch, tag, commit);
+    let commit = git(&cache)
+        .args(&["rev-parse", "--verify", &[revision.as_str(), "^{commit}"].concat()])
+        .run_with_stdout()?
+        .trim()
+        .to_owned();
+
+    if project.join(".git").exists() {
+        git(project).args(&["remote", "set-url", "origin"]).arg(&cache).run()?;
+        git(project).args(&["fetch", "--tags", "origin"]).run()?;
+    } else {
+        if project.exists() {
+            fs::remove_dir_all(project)?;
+        }
+
+        Command::new("git").args(&["clone", "--no-checkout"]).arg(&cache).arg(project).run()?;
+    }
+
+    info!("checking out {} of {} at {}", revision, name, commit);
+    let local = branch.as_ref().map_or("master", |branch| branch.as_str());
+    git(project).args(&["checkout", "--force", "-B", local, &commit]).run()?;
+    git(project).args(&["clean", "-fdx"]).run()?;
+
+    Ok(commit)
+}
+
+fn git(directory: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(directory);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_revision_precedence() {
+        let some = |value: &str| Some(value.to_owned());
+        assert_eq!(revision(&some("stable"), &some("1.0"), &some("abc123 ")), "abc123");
+        assert_eq!(revision(&some("stable"), &some("1.0"), &None), "refs/tags/1.0");
+        assert_eq!(revision(&some("stable"), &None, &None), "refs/heads/stable");
+        assert_eq!(revision(&None, &None, &None), "HEAD");
+    }
+}
@@ -0,0 +1,308 @@
+use checksum::hasher;
+use config::{Mirror, RetryPolicy};
+use debian::{compare_versions, parse_control};
+use gpg;
+use libflate::gzip::Decoder as GzDecoder;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use reqwest::{self, Client, StatusCode};
+use retry::retry;
+use sha2::Sha256;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::NamedTempFile;
+use throttle::Bandwidth;
+use super::super::pool::pool_destination;
+use super::request::{self, RequestCompare};
+use super::DownloadError;
+
+/// A package of a `Packages` index which was selected to be mirrored.
+#[derive(Debug, PartialEq)]
+struct MirroredPackage {
+    name: String,
+    version: String,
+    filename: String,
+    sha256: String,
+}
+
+/// Downloads the packages which the mirror selects from the `Packages` indices of its suite, and
+/// stores them in the pool, returning the number of bytes that were downloaded.
+///
+/// The indices are verified against the digests of the suite's `Release`, whose signature is
+/// verified by the mirror's keyring. Each package is verified against the SHA256 digest of its
+/// index, and packages which are already in the pool with the same digest are not downloaded
+/// again.
+pub fn download(
+    client: Arc<Client>,
+    mirror: &Mirror,
+    architectures: &[String],
+    pool: &Path,
+    suite: &str,
+    component: &str,
+    policy: &RetryPolicy,
+    bandwidth: &Bandwidth,
+) -> io::Result<u64> {
+    let component = mirror.component.as_ref().map_or(component, |x| x.as_str());
+    let architectures = mirror.architectures.as_ref().map_or(architectures, |x| x.as_slice());
+    let root = mirror.url.trim_right_matches('/');
+
+    let as_io = |why: DownloadError| io::Error::new(io::ErrorKind::Other, format!("{}", why));
+    let release = retry(policy, &mirror.url, || UpstreamRelease::fetch(&client, root, &mirror.suite, &mirror.keyring))
+        .map_err(&as_io)?;
+
+    let mut selected: HashMap<(String, String), MirroredPackage> = HashMap::new();
+    for upstream in &mirror.components {
+        for arch in architectures {
+            let directory = format!("{}/binary-{}", upstream, arch);
+            info!("fetching the packages of {}/dists/{}/{}", root, mirror.suite, directory);
+            let data = retry(policy, &directory, || release.packages(&client, &directory))
+                .map_err(&as_io)?;
+
+            for (arch, package) in selected_packages(mirror, &data) {
+                let key = (package.name.clone(), arch);
+                let newer = selected.get(&key).map_or(true, |current| {
+                    compare_versions(&current.version, &package.version) == Ordering::Less
+                });
+
+                if newer {
+                    selected.insert(key, package);
+                }
+            }
+        }
+    }
+
+    if selected.is_empty() {
+        warn!("no packages from {} were selected by the mirror", mirror.url);
+        return Ok(0);
+    }
+
+    // Only up to `max_transfers` at a time, which defaults to 8.
+    let thread_pool = ThreadPoolBuilder::new()
+        .num_threads(bandwidth.max_transfers)
+        .build()
+        .expect("failed to build thread pool");
+
+    thread_pool.install(|| {
+        selected.par_iter()
+            .map(|(_, package)| {
+                let filename = &package.filename[package.filename.rfind('/').map_or(0, |pos| pos + 1)..];
+                let target = pool_destination(pool, suite, component, filename)?.join(filename);
+                let url = [root, "/", &package.filename].concat();
+                retry(policy, &package.name, || request::file(
+                    client.clone(),
+                    package.name.clone(),
+                    &url,
+                    RequestCompare::Checksum(Some(&package.sha256)),
+                    &target,
+                    bandwidth
+                ))
+            })
+            .collect::<io::Result<Vec<u64>>>()
+            .map(|downloaded| downloaded.into_iter().sum())
+    })
+}
+
+/// The `Release` of an upstream suite, whose signature was verified, and which lists the SHA256
+/// digests of the suite's indices.
+pub struct UpstreamRelease {
+    url: String,
+    sha256: HashMap<String, String>,
+}
+
+impl UpstreamRelease {
+    /// Fetches the `InRelease` of the suite, or its `Release` and `Release.gpg` where it has no
+    /// `InRelease`, and verifies its signature by the keyring.
+    pub fn fetch(client: &Client, root: &str, suite: &str, keyring: &Path) -> Result<Self, DownloadError> {
+        let url = format!("{}/dists/{}", root, suite);
+        let untrusted = |why: &str| DownloadError::Release { url: url.clone(), why: why.to_owned() };
+        let signature = |why: io::Error| DownloadError::Signature { name: url.clone(), why };
+
+        let contents = match get(client, &[&url, "/InRelease"].concat())? {
+            Some(signed) => {
+                let signed = temporary(&signed).map_err(&signature)?;
+                match gpg::verify_signed(signed.path(), keyring).map_err(&signature)? {
+                    Some((_, contents)) => contents,
+                    None => return Err(untrusted("the InRelease is not signed by a key of the keyring"))
+                }
+            }
+            None => {
+                let release = get(client, &[&url, "/Release"].concat())?
+                    .ok_or_else(|| untrusted("there is neither an InRelease nor a Release"))?;
+                let detached = get(client, &[&url, "/Release.gpg"].concat())?
+                    .ok_or_else(|| untrusted("the Release has no Release.gpg"))?;
+
+                let file = temporary(&release).map_err(&signature)?;
+                let detached = temporary(&detached).map_err(&signature)?;
+                if gpg::verify_detached_by(detached.path(), file.path(), keyring).map_err(&signature)?.is_none() {
+                    return Err(untrusted("the Release.gpg is not signed by a key of the keyring"));
+                }
+
+                String::from_utf8(release).map_err(|_| untrusted("the Release is not UTF-8"))?
+            }
+        };
+
+        Ok(UpstreamRelease { url: url.clone(), sha256: release_digests(&contents) })
+    }
+
+    /// Fetches the `Packages` index of a directory of the suite, such as `main/binary-amd64`,
+    /// preferring its gzip-compressed variant, and verifies it against the digest of the
+    /// `Release`.
+    pub fn packages(&self, client: &Client, directory: &str) -> Result<String, DownloadError> {
+        for name in &["Packages.gz", "Packages"] {
+            let path = [directory, "/", *name].concat();
+            let expected = match self.sha256.get(&path) {
+                Some(expected) => expected,
+                None => continue
+            };
+
+            let url = [&self.url, "/", &path].concat();
+            let data = match get(client, &url)? {
+                Some(data) => data,
+                None => continue
+            };
+
+            let received = hasher::<Sha256, &[u8]>(&data)
+                .map_err(|why| DownloadError::Index { url: url.clone(), why })?;
+            if &received != expected {
+                return Err(DownloadError::ChecksumInvalid { name: url, expected: expected.clone(), received });
+            }
+
+            let mut index = String::new();
+            let result = if name.ends_with(".gz") {
+                GzDecoder::new(&data[..]).and_then(|mut decoder| decoder.read_to_string(&mut index))
+            } else {
+                (&data[..]).read_to_string(&mut index)
+            };
+
+            return result.map(|_| index).map_err(|why| DownloadError::Index { url, why });
+        }
+
+        Err(DownloadError::Release {
+            url: self.url.clone(),
+            why: format!("it does not list a Packages index of {}", directory)
+        })
+    }
+}
+
+/// Requests a file, which is `None` where the server does not have it.
+fn get(client: &Client, url: &str) -> Result<Option<Vec<u8>>, DownloadError> {
+    let request = |why: reqwest::Error| DownloadError::Request { name: url.to_owned(), why };
+    let response = client.get(url).send().map_err(&request)?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let mut response = response.error_for_status().map_err(&request)?;
+    let mut data = Vec::new();
+    response.read_to_end(&mut data)
+        .map_err(|why| DownloadError::Index { url: url.to_owned(), why })?;
+    Ok(Some(data))
+}
+
+/// Stores the data in a temporary file, for `gpgv` to read.
+fn temporary(data: &[u8]) -> io::Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(data)?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// The SHA256 digests of the `Release`, by the path of each index within the suite.
+fn release_digests(release: &str) -> HashMap<String, String> {
+    release.lines()
+        .skip_while(|line| line.trim_right() != "SHA256:")
+        .skip(1)
+        .take_while(|line| line.starts_with(' '))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(digest), Some(_size), Some(path)) => Some((path.to_owned(), digest.to_owned())),
+                _ => None
+            }
+        })
+        .collect()
+}
+
+/// The packages of an index which the mirror selects, with their architectures.
+///
+/// Packages without a SHA256 digest cannot be verified, and are skipped.
+fn selected_packages(mirror: &Mirror, index: &str) -> Vec<(String, MirroredPackage)> {
+    index.split("\n\n")
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .filter_map(|stanza| {
+            let control = parse_control(stanza);
+            let name = control.get("Package")?;
+            let version = control.get("Version")?;
+            if !mirror.selects(name, version) {
+                return None;
+            }
+
+            let (filename, sha256) = match (control.get("Filename"), control.get("SHA256")) {
+                (Some(filename), Some(sha256)) => (filename, sha256),
+                _ => {
+                    warn!("skipping {} {}, which does not have a filename and SHA256 digest", name, version);
+                    return None;
+                }
+            };
+
+            let arch = control.get("Architecture").cloned().unwrap_or_default();
+            Some((arch, MirroredPackage {
+                name: name.clone(),
+                version: version.clone(),
+                filename: filename.clone(),
+                sha256: sha256.clone(),
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::MirrorPackage;
+
+    #[test]
+    fn mirror_index_selection() {
+        let mirror = Mirror {
+            url: "http://archive.ubuntu.com/ubuntu".into(),
+            suite: "bionic".into(),
+            keyring: "ubuntu-archive-keyring.gpg".into(),
+            components: vec!["main".into()],
+            architectures: None,
+            component: None,
+            packages: vec![MirrorPackage { name: "foo".into(), version: None }],
+        };
+
+        let index = "Package: foo\nVersion: 1.0\nArchitecture: amd64\n\
+            Filename: pool/main/f/foo/foo_1.0_amd64.deb\nSHA256: abc\n\n\
+            Package: foo\nVersion: 0.9\nArchitecture: amd64\nFilename: pool/main/f/foo/foo_0.9_amd64.deb\n\n\
+            Package: bar\nVersion: 1.0\nArchitecture: all\n\
+            Filename: pool/main/b/bar/bar_1.0_all.deb\nSHA256: def\n";
+
+        assert_eq!(selected_packages(&mirror, index), vec![(
+            "amd64".to_owned(),
+            MirroredPackage {
+                name: "foo".into(),
+                version: "1.0".into(),
+                filename: "pool/main/f/foo/foo_1.0_amd64.deb".into(),
+                sha256: "abc".into(),
+            }
+        )]);
+    }
+
+    #[test]
+    fn release_digest_listing() {
+        let release = "Origin: Ubuntu\nSuite: bionic\nMD5Sum:\n 1a 10 main/binary-amd64/Packages\n\
+            SHA256:\n 2b 10 main/binary-amd64/Packages\n 3c 5 main/binary-amd64/Packages.gz\n\
+            Acquire-By-Hash: yes\n";
+
+        let digests = release_digests(release);
+        assert_eq!(digests.len(), 2);
+        assert_eq!(digests["main/binary-amd64/Packages"], "2b");
+        assert_eq!(digests["main/binary-amd64/Packages.gz"], "3c");
+    }
+}
@@ -1,24 +1,56 @@
 mod direct;
+mod git;
+mod mirror;
 mod repos;
 mod request;
 mod sources;
+mod stream;
 
+pub(super) use self::git::GIT_CACHE;
+pub(super) use self::mirror::UpstreamRelease;
+
+use action;
 use config::Config;
+use http;
 use self::direct::DownloadResult;
+use super::RepoError;
 use std::io;
 use std::path::PathBuf;
-use std::process::exit;
 use std::sync::Arc;
-use reqwest::{self, Client};
+use std::sync::atomic::AtomicBool;
+use reqwest::{self, StatusCode};
+use retry::Retryable;
+use throttle::Bandwidth;
+
+pub fn all(config: &Config) -> Result<(), RepoError> {
+    if action::is_dry_run() {
+        dry_run(config, None);
+        return Ok(());
+    }
 
-pub fn all(config: &Config) {
     let mut errors = Vec::new();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let policy = config.retry_policy();
+    let bandwidth = Bandwidth::new(&config.network_limits());
+    let client = match http::client(&config.http_settings()) {
+        Ok(client) => Arc::new(client),
+        Err(why) => return Err(RepoError::failed(format!("failed to configure the HTTP client: {}", why)))
+    };
 
     if let Some(ref ddl_sources) = config.direct {
-        for (id, result) in direct::parallel(ddl_sources, &config.archive, &config.default_component)
-            .into_iter()
-            .enumerate()
-        {
+        let results = direct::parallel(
+            client.clone(),
+            ddl_sources,
+            &config.pool_root(),
+            &config.archive,
+            &config.default_component,
+            &policy,
+            &bandwidth,
+            &config.pool_policy(),
+            &config.pooled,
+        );
+
+        for (id, result) in results.into_iter().enumerate() {
             let name = &ddl_sources[id].name;
             match result {
                 Ok(DownloadResult::Downloaded(bytes)) => {
@@ -34,7 +66,7 @@ pub fn all(config: &Config) {
     }
 
     if let Some(ref sources) = config.source {
-        for (id, result) in sources::parallel(sources, &config.archive)
+        for (id, result) in sources::parallel(&client, sources, &config.archive, &policy, &bandwidth, &cancel)
             .into_iter()
             .enumerate()
         {
@@ -53,7 +85,17 @@ pub fn all(config: &Config) {
     }
 
     if let Some(ref repos) = config.repos {
-        match repos::download(repos, &config.archive, &config.default_component) {
+        let result = repos::download(
+            client.clone(),
+            repos,
+            &config.pool_root(),
+            &config.archive,
+            &config.default_component,
+            &policy,
+            &bandwidth,
+        );
+
+        match result {
             Ok(()) => {
                 info!("all repos fetched successfully");
             }
@@ -64,47 +106,119 @@ pub fn all(config: &Config) {
             }
         }
 
-        eprintln!("repos downloaded");
+        info!("repos downloaded");
+    }
+
+    if let Some(ref mirror) = config.mirror {
+        let result = mirror::download(
+            client.clone(),
+            mirror,
+            &config.architectures,
+            &config.pool_root(),
+            &config.archive,
+            &config.default_component,
+            &policy,
+            &bandwidth,
+        );
+
+        match result {
+            Ok(bytes) => {
+                info!("packages mirrored from {} successfully, downloading {} bytes", mirror.url, bytes);
+            }
+            Err(why) => {
+                let msg = format!("failed to mirror packages from {}: {}", mirror.url, why);
+                error!("{}", msg);
+                errors.push(msg);
+            }
+        }
     }
 
     if ! errors.is_empty() {
-        error!("exiting due to error(s): {:#?}", errors);
-        exit(1);
+        return Err(RepoError::failed(format!("{} download(s) failed: {:#?}", errors.len(), errors)));
     }
+
+    Ok(())
 }
 
 // TODO: Optimize with a shrinking queue.
-pub fn packages(sources: &Config, packages: &[&str]) {
+pub fn packages(sources: &Config, packages: &[&str]) -> Result<(), RepoError> {
+    if action::is_dry_run() {
+        dry_run(sources, Some(packages));
+        return Ok(());
+    }
+
     let mut downloaded = 0;
-    let client = Arc::new(Client::new());
+    let client = match http::client(&sources.http_settings()) {
+        Ok(client) => Arc::new(client),
+        Err(why) => return Err(RepoError::failed(format!("failed to configure the HTTP client: {}", why)))
+    };
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let policy = sources.retry_policy();
+    let bandwidth = Bandwidth::new(&sources.network_limits());
 
     if let Some(ref source) = sources.direct.as_ref() {
         for source in source.iter().filter(|s| packages.contains(&s.name.as_str())) {
-            if let Err(why) = direct::download(client.clone(), source, &sources.archive, &sources.default_component) {
-                error!("failed to download {}: {}", &source.name, why);
-                exit(1);
+            let result = direct::download(
+                client.clone(),
+                source,
+                &sources.pool_root(),
+                &sources.archive,
+                &sources.default_component,
+                &policy,
+                &bandwidth,
+                &sources.pool_policy(),
+                &sources.pooled,
+            );
+
+            if let Err(why) = result {
+                return Err(RepoError::failed(format!("failed to download {}: {}", &source.name, why)));
             }
 
             downloaded += 1;
             if downloaded == packages.len() {
-                return;
+                return Ok(());
             }
         }
     }
 
     if let Some(ref source) = sources.source.as_ref() {
         for source in source.iter().filter(|s| packages.contains(&s.name.as_str())) {
-            if let Err(why) = sources::download(source, &sources.archive) {
-                error!("failed to download source {}: {}", &source.name, why);
-                exit(1);
+            if let Err(why) = sources::download(&client, source, &sources.archive, &policy, &bandwidth, &cancel) {
+                return Err(RepoError::failed(format!("failed to download source {}: {}", &source.name, why)));
             }
 
             downloaded += 1;
             if downloaded == packages.len() {
-                return;
+                return Ok(());
             }
         }
     }
+
+    Ok(())
+}
+
+/// Reports the downloads which would be made for the given packages, or for every package.
+fn dry_run(config: &Config, packages: Option<&[&str]>) {
+    let selected = |name: &str| packages.map_or(true, |packages| packages.contains(&name));
+
+    for source in config.direct.iter().flat_map(|x| x.iter()).filter(|x| selected(&x.name)) {
+        action::report(format_args!("download {} {} to the pool", source.name, source.version));
+    }
+
+    for source in config.source.iter().flat_map(|x| x.iter()).filter(|x| selected(&x.name)) {
+        action::report(format_args!("fetch the source of {}", source.name));
+    }
+
+    if packages.is_none() {
+        for repo in config.repos.iter().flat_map(|x| x.iter()) {
+            action::report(format_args!("download the packages of {} to the pool", repo.repo));
+        }
+
+        if let Some(ref mirror) = config.mirror {
+            action::report(format_args!("mirror packages from {} {}", mirror.url, mirror.suite));
+        }
+    }
 }
 
 #[derive(Debug, Fail)]
@@ -117,6 +231,58 @@ pub enum DownloadError {
     DGet { url: String, why: io::Error },
     #[fail(display = "git exited with an error: {}", why)]
     GitFailed { why: io::Error },
+    #[fail(display = "failed to read the index at {}: {}", url, why)]
+    Index { url: String, why: io::Error },
+    #[fail(display = "the release of {} can't be trusted: {}", url, why)]
+    Release { url: String, why: String },
     #[fail(display = "failed to request data for {}: {}", name, why)]
-    Request { name: String, why: reqwest::Error }
+    Request { name: String, why: reqwest::Error },
+    #[fail(display = "failed to verify the upstream signature of {}: {}", name, why)]
+    Signature { name: String, why: io::Error },
+    #[fail(display = "failed to download {} to {:?}: {}", url, file, why)]
+    Stream { url: String, file: PathBuf, why: io::Error },
+}
+
+impl Retryable for DownloadError {
+    fn is_retryable(&self) -> bool {
+        match *self {
+            // The external tools report network failures only by their exit status, which is an
+            // `Other` error, whereas failing to spawn them, or to write their files, is permanent.
+            DownloadError::DGet { ref why, .. } | DownloadError::GitFailed { ref why } => {
+                why.kind() == io::ErrorKind::Other || why.is_retryable()
+            }
+            DownloadError::Request { ref why, .. } => request_is_retryable(why),
+            DownloadError::Index { ref why, .. } | DownloadError::Stream { ref why, .. } => why.is_retryable(),
+            DownloadError::Open { .. }
+                | DownloadError::ChecksumInvalid { .. }
+                | DownloadError::Release { .. }
+                | DownloadError::Signature { .. } => false,
+        }
+    }
+}
+
+/// Server errors, timeouts, and rate limits are transient. Errors without a status are failures
+/// to connect, or to receive the response.
+fn request_is_retryable(why: &reqwest::Error) -> bool {
+    match why.status() {
+        Some(status) => status.is_server_error()
+            || status == StatusCode::TOO_MANY_REQUESTS
+            || status == StatusCode::REQUEST_TIMEOUT,
+        None => !why.is_redirect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_failures_by_cause() {
+        let exited = io::Error::new(io::ErrorKind::Other, "git fetch failed with exit status: 128");
+        assert!(DownloadError::GitFailed { why: exited }.is_retryable());
+
+        let missing = io::Error::new(io::ErrorKind::NotFound, "failed to spawn dget: not found");
+        let url = "https://example.com/foo_1.0.dsc".to_owned();
+        assert!(!DownloadError::DGet { url, why: missing }.is_retryable());
+    }
 }
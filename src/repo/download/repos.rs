@@ -1,20 +1,30 @@
 use apt_repo_crawler::{filename_from_url, AptCrawler, AptEntry, AptPackage};
-use config::Repo;
+use config::{Repo, RetryPolicy};
 use crossbeam_channel::bounded;
-use deb_version;
-use debian::gen_filename;
+use debian::{compare_versions, gen_filename};
+use misc;
 use rayon::{scope, ThreadPoolBuilder};
 use rayon::prelude::*;
 use reqwest::Client;
+use retry::retry;
 use std::cmp::Ordering;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use throttle::Bandwidth;
 use super::request::{self, RequestCompare};
 
-pub fn download(repos: &[Repo], suite: &str, component: &str) -> io::Result<()> {
+pub fn download(
+    client: Arc<Client>,
+    repos: &[Repo],
+    pool: &Path,
+    suite: &str,
+    component: &str,
+    policy: &RetryPolicy,
+    bandwidth: &Bandwidth,
+) -> io::Result<()> {
     let mut result = Ok(());
-    let (in_tx, in_rx) = bounded::<AptEntry>(64);
+    let (in_tx, in_rx) = bounded::<(AptEntry, &str)>(64);
     let (out_tx, out_rx) = bounded::<(String, String, RequestCompare, PathBuf)>(64);
 
     {
@@ -24,12 +34,13 @@ pub fn download(repos: &[Repo], suite: &str, component: &str) -> io::Result<()>
             s.spawn(move |_| {
                 for repo in repos {
                     info!("fetching packages from {}", repo.repo);
+                    let component = repo.component.as_ref().map_or(component, |x| x.as_str());
                     let crawler = AptCrawler::new(repo.repo.clone())
                         .filter(Arc::new(repo.clone()))
                         .crawl();
 
                     for package in crawler {
-                        in_tx.send(package);
+                        in_tx.send((package, component));
                     }
                 }
             });
@@ -37,23 +48,27 @@ pub fn download(repos: &[Repo], suite: &str, component: &str) -> io::Result<()>
             // Thread for filtering packages to download, based on filter patterns and old-ness.
             s.spawn(move |_| {
                 // Sends data required by the file requester to the output channel.
-                let send_func = |file: AptEntry| -> bool {
+                let send_func = |file: AptEntry, component: &str| -> bool {
                     if let Ok(desc) = AptPackage::from_str(filename_from_url(file.url.as_str())) {
-                        out_tx.send((
-                            desc.name.to_owned(),
-                            file.url.as_str().to_owned(),
-                            RequestCompare::SizeAndModification(
-                                file.length,
-                                file.modified.map(|m| m.timestamp())
-                            ),
-                            get_destination(desc, suite, component)
-                        ));
+                        let name = desc.name.to_owned();
+                        match get_destination(desc, pool, suite, component) {
+                            Ok(destination) => out_tx.send((
+                                name,
+                                file.url.as_str().to_owned(),
+                                RequestCompare::SizeAndModification(
+                                    file.length,
+                                    file.modified.map(|m| m.timestamp())
+                                ),
+                                destination
+                            )),
+                            Err(why) => warn!("skipping {}: {}", file.url, why)
+                        }
                     }
 
                     true
                 };
 
-                let mut files: Vec<AptEntry> = Vec::new();
+                let mut files: Vec<(AptEntry, &str)> = Vec::new();
                 let mut names: Vec<String> = Vec::new();
                 let mut versions: Vec<String> = Vec::new();
 
@@ -62,11 +77,11 @@ pub fn download(repos: &[Repo], suite: &str, component: &str) -> io::Result<()>
                     Update(usize, String)
                 }
 
-                for file in in_rx {
+                for (file, component) in in_rx {
                     let mut update = None;
                     if let Ok(desc) = AptPackage::from_str(filename_from_url(file.url.as_str())) {
                         if let Some(position) = names.iter().position(|name| name == desc.name) {
-                            if deb_version::compare_versions(&versions[position], desc.version) == Ordering::Less {
+                            if compare_versions(&versions[position], desc.version) == Ordering::Less {
                                 update = Some(Insert::Update(position, desc.version.to_owned()));
                             }
                         } else {
@@ -76,38 +91,39 @@ pub fn download(repos: &[Repo], suite: &str, component: &str) -> io::Result<()>
 
                     match update {
                         Some(Insert::Append(name, version)) => {
-                            files.push(file);
+                            files.push((file, component));
                             names.push(name);
                             versions.push(version);
                         },
                         Some(Insert::Update(pos, version)) => {
-                            files[pos] = file;
+                            files[pos] = (file, component);
                             versions[pos] = version;
                         },
                         None => (),
                     }
                 }
 
-                for entry in files {
-                    send_func(entry);
+                for (entry, component) in files {
+                    send_func(entry, component);
                 }
             });
 
-            // Use a thread pool to ensure only up to 8 files are downloaded at the same time.
+            // Use a thread pool to ensure only up to `max_transfers` files, which defaults to 8,
+            // are downloaded at the same time.
             let thread_pool = ThreadPoolBuilder::new()
-                .num_threads(8)
+                .num_threads(bandwidth.max_transfers)
                 .build()
                 .expect("failed to build thread pool");
 
             thread_pool.install(move || {
                 // Main thread fetches packages in parallel
-                let client = Arc::new(Client::new());
                 *result = out_rx
                     .into_iter()
                     .par_bridge()
                     .map(|(name, url, compare, dest)| {
-                        let client = client.clone();
-                        request::file(client, name, &url, compare, &dest)?;
+                        retry(policy, &name, || {
+                            request::file(client.clone(), name.clone(), &url, compare, &dest, bandwidth)
+                        })?;
                         Ok(())
                     })
                     .collect::<io::Result<()>>();
@@ -118,7 +134,7 @@ pub fn download(repos: &[Repo], suite: &str, component: &str) -> io::Result<()>
     result
 }
 
-fn get_destination(desc: AptPackage, suite: &str, component: &str) -> PathBuf {
+fn get_destination(desc: AptPackage, pool: &Path, suite: &str, component: &str) -> io::Result<PathBuf> {
     let dst = match desc.extension {
         "tar.gz" | "tar.xz" | "dsc" => ["/", component, "/source/"].concat(),
         _ => ["/", component, "/binary-", desc.arch, "/"].concat()
@@ -133,5 +149,5 @@ fn get_destination(desc: AptPackage, suite: &str, component: &str) -> PathBuf {
         &desc.name
     };
 
-    PathBuf::from(["repo/pool/", suite, &dst, &name[0..1], "/", &name, "/", &filename].concat())
+    Ok(pool.join([suite, &dst, misc::pool_bucket(name)?, "/", &name, "/", &filename].concat()))
 }
@@ -1,102 +1,142 @@
 use checksum::hasher;
 use parallel_getter::ParallelGetter;
+use progress::{Progress, Unit};
 use reqwest::Client;
 use sha2::Sha256;
 use std::{fs::{self, File}, io};
 use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use std::sync::Arc;
+use super::request_is_retryable;
+use throttle::Bandwidth;
 use utime;
+use workspace;
 
-const ATTEMPTS: u8 = 3;
-
+#[derive(Clone, Copy)]
 pub enum RequestCompare<'a> {
     Checksum(Option<&'a str>),
     SizeAndModification(u64, Option<i64>)
 }
 
-pub fn file(client: Arc<Client>, name: String, url: &str, compare: RequestCompare, path: &Path) -> io::Result<u64> {
-    let mut tries = 0;
-
+/// Downloads the file to the path, unless it is already there.
+///
+/// The file is downloaded over several connections at a time, unless the bandwidth is limited,
+/// in which case it is downloaded over a single connection which is throttled to the limits.
+/// It is downloaded within the workspace of the run, and only moved to the path once it is
+/// complete, so that a download which fails never leaves a partial file in the pool.
+pub fn file(
+    client: Arc<Client>,
+    name: String,
+    url: &str,
+    compare: RequestCompare,
+    path: &Path,
+    bandwidth: &Bandwidth,
+) -> io::Result<u64> {
     let name = Arc::new(name);
-    loop {
-        let mut file = if path.exists() {
-            let mut requires_download = true;
+    let staged = workspace::stage(path);
+    if path.exists() {
+        let mut requires_download = true;
 
-            match compare {
-                RequestCompare::Checksum(Some(checksum)) => {
-                    let digest = hasher::<Sha256, File>(File::open(path)?)?;
-                    requires_download = digest != checksum;
-                }
-                RequestCompare::SizeAndModification(length, mtime) => {
-                    let file = File::open(path)?;
-                    let metadata = file.metadata()?;
-                    if metadata.len() == length {
-                        if let Some(modified) = mtime {
-                            if modified == metadata.mtime() {
-                                requires_download = false;
-                            }
-                        } else {
+        match compare {
+            RequestCompare::Checksum(Some(checksum)) => {
+                let digest = hasher::<Sha256, File>(File::open(path)?)?;
+                requires_download = digest != checksum;
+            }
+            RequestCompare::SizeAndModification(length, mtime) => {
+                let file = File::open(path)?;
+                let metadata = file.metadata()?;
+                if metadata.len() == length {
+                    if let Some(modified) = mtime {
+                        if modified == metadata.mtime() {
                             requires_download = false;
                         }
+                    } else {
+                        requires_download = false;
                     }
                 }
-                _ => ()
             }
+            _ => ()
+        }
 
-            if ! requires_download {
-                return Ok(0);
-            }
+        if ! requires_download {
+            return Ok(0);
+        }
+    } else if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            fs::create_dir_all(parent)?;
+        }
+    }
 
-            fs::OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .open(path)?
-        } else {
-            if let Some(parent) = path.parent() {
-                if !parent.exists() {
-                    fs::create_dir_all(parent)?;
-                }
-            }
-            File::create(path)?
-        };
+    let mut file = File::create(&staged)?;
 
-        info!("downloading package to {}", path.display());
+    info!("downloading package to {}", path.display());
+    let progress = Arc::new(Progress::new(name.as_str(), None, Unit::Bytes));
+    let bar = progress.clone();
+    let downloaded = if bandwidth.is_limited() {
+        throttled(&client, url, &mut file, bandwidth)?
+    } else {
         let name = name.clone();
-        let downloaded = ParallelGetter::new(url, &mut file)
+        ParallelGetter::new(url, &mut file)
             .client(client.clone())
             .threads(4)
             .threshold_memory(10 * 1024 * 1024)
             .threshold_parallel(1024 * 1024)
             .callback(1000, Box::new(move |p, t| {
-                info!("{}: downloaded {} out of {} MiB", name, p / 1024 / 1024, t / 1024 / 1024)
+                if bar.is_enabled() {
+                    bar.set_total(t);
+                    bar.set(p);
+                } else {
+                    info!("{}: downloaded {} out of {} MiB", name, p / 1024 / 1024, t / 1024 / 1024)
+                }
             }))
-            .get()? as u64;
+            .get()? as u64
+    };
 
-        info!("finished downloading {}", path.display());
-        if let RequestCompare::Checksum(Some(checksum)) = compare {
-            let digest = hasher::<Sha256, File>(File::open(path)?)?;
-            if digest == checksum {
-                return Ok(downloaded);
-            } else {
-                error!("checksum does not much for {}, removing.", path.display());
-                fs::remove_file(&path)?;
+    progress.finish();
 
-                if tries == ATTEMPTS {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("checksum does not match for {}", path.display())
-                    ));
+    info!("finished downloading {}", path.display());
+    match compare {
+        // A download which does not match is removed. This is not a transient failure, as the
+        // upstream served other contents than the checksum expects, so it is not retried.
+        RequestCompare::Checksum(Some(checksum)) => {
+            let digest = hasher::<Sha256, File>(File::open(&staged)?)?;
+            if digest != checksum {
+                error!("checksum does not much for {}, removing.", path.display());
+                fs::remove_file(&staged)?;
+                if path.exists() {
+                    fs::remove_file(&path)?;
                 }
 
-                tries += 1;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("checksum does not match for {}", path.display())
+                ));
             }
-        } else if let RequestCompare::SizeAndModification(_length, Some(mtime)) = compare {
-            let (atime, _) = utime::get_file_times(path)?;
-            utime::set_file_times(path, atime, mtime as u64)?;
-            return Ok(downloaded);
-        } else {
-            return Ok(downloaded);
         }
+        RequestCompare::SizeAndModification(_length, Some(mtime)) => {
+            let (atime, _) = utime::get_file_times(&staged)?;
+            utime::set_file_times(&staged, atime, mtime as u64)?;
+        }
+        _ => ()
     }
+
+    workspace::persist(&staged, path)?;
+    Ok(downloaded)
+}
+
+/// Downloads the file over a single connection, whose reads are throttled to the bandwidth.
+fn throttled(client: &Client, url: &str, file: &mut File, bandwidth: &Bandwidth) -> io::Result<u64> {
+    let response = client.get(url).send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|why| {
+            let kind = if request_is_retryable(&why) {
+                io::ErrorKind::ConnectionAborted
+            } else {
+                io::ErrorKind::Other
+            };
+
+            io::Error::new(kind, format!("failed to request {}: {}", url, why))
+        })?;
+
+    io::copy(&mut bandwidth.reader(response), file)
 }
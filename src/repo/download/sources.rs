@@ -1,33 +1,64 @@
 use command::Command;
-use config::{Source, SourceLocation};
+use config::{RetryPolicy, Source, SourceLocation, SourceSignature};
 use checksum::hasher;
+use gpg;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
-use reqwest;
+use misc;
+use reqwest::{Client, StatusCode};
+use reqwest::header::{ACCEPT_RANGES, RANGE};
+use retry::retry;
 use sha2::Sha256;
 use std::fs::{self, File};
 use std::{env, io};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use throttle::Bandwidth;
 use super::DownloadError;
+use super::git;
+use super::stream::{resume_to_file, stream_to_file};
+use super::super::prepare::{cached_source, SOURCES_KEYS};
+use tempfile::NamedTempFile;
+
+/// The keyserver that upstream keys are fetched from, unless a source names its own.
+const DEFAULT_KEYSERVER: &str = "hkps://keys.openpgp.org";
 
 /// Downloads source code repositories in parallel.
-pub fn parallel(items: &[Source], suite: &str) -> Vec<Result<(), DownloadError>> {
-    // Only up to 8 source clones at a time.
+pub fn parallel(
+    client: &Client,
+    items: &[Source],
+    suite: &str,
+    policy: &RetryPolicy,
+    bandwidth: &Bandwidth,
+    cancel: &Arc<AtomicBool>
+) -> Vec<Result<(), DownloadError>> {
+    // Only up to `max_transfers` source clones at a time, which defaults to 8.
     let thread_pool = ThreadPoolBuilder::new()
-        .num_threads(8)
+        .num_threads(bandwidth.max_transfers)
         .build()
         .expect("failed to build thread pool");
 
-    thread_pool.install(move || items.par_iter().map(|i| download(i, suite)).collect())
+    thread_pool.install(move || {
+        items.par_iter().map(|i| download(client, i, suite, policy, bandwidth, cancel)).collect()
+    })
 }
 
-pub fn download(item: &Source, suite: &str) -> Result<(), DownloadError> {
-    match item.location {
-        Some(SourceLocation::Git { ref git, ref branch, ref commit }) => {
-            download_git(&item.name, git, suite, branch, commit).map_err(|why| DownloadError::GitFailed { why })
+/// Fetches a source, retrying transient failures according to the policy.
+pub fn download(
+    client: &Client,
+    item: &Source,
+    suite: &str,
+    policy: &RetryPolicy,
+    bandwidth: &Bandwidth,
+    cancel: &Arc<AtomicBool>
+) -> Result<(), DownloadError> {
+    retry(policy, &item.name, || match item.location {
+        Some(SourceLocation::Git { ref git, ref branch, ref tag, ref commit }) => {
+            download_git(&item.name, git, suite, branch, tag, commit).map_err(|why| DownloadError::GitFailed { why })
         },
-        Some(SourceLocation::URL { ref url, ref checksum }) => {
-            download_(item, url, checksum)
+        Some(SourceLocation::URL { ref url, ref sha256 }) => {
+            download_(client, item, url, sha256, bandwidth, cancel)
         },
         Some(SourceLocation::Dsc { ref dsc }) => {
             download_dsc(item, dsc, suite).map_err(|why| {
@@ -35,139 +66,180 @@ pub fn download(item: &Source, suite: &str) -> Result<(), DownloadError> {
             })
         }
         None => Ok(())
-    }
+    })
 }
 
-fn download_(item: &Source, url: &str, checksum: &str) -> Result<(), DownloadError> {
-    let filename = &url[url.rfind('/').map_or(0, |x| x + 1)..];
-    let destination = PathBuf::from(["assets/cache/", &item.name, "_", &filename].concat());
-
-    let requires_download = if destination.is_file() {
-        let digest = File::open(&destination)
-            .and_then(hasher::<Sha256, File>)
-            .map_err(|why| DownloadError::Open {
-                file: destination.clone(),
-                why
-            })?;
-
-        digest != checksum
-    } else {
-        true
+/// Fetches an upstream tarball into the sources cache, unless it has already been cached.
+///
+/// The tarball is downloaded to a `.partial` file beside its destination, which is resumed by a
+/// later run if the download is interrupted and the server supports ranges. It is only moved into
+/// the cache once its SHA256 digest matches the configured digest, and once it is verified by its
+/// upstream signature, if the source has one, which a cached tarball is also verified by.
+fn download_(
+    client: &Client,
+    item: &Source,
+    url: &str,
+    sha256: &str,
+    bandwidth: &Bandwidth,
+    cancel: &Arc<AtomicBool>
+) -> Result<(), DownloadError> {
+    let destination = cached_source(url, sha256);
+    let filename = misc::filename_from_url(url);
+    let cached_signature = {
+        let mut cached = destination.as_os_str().to_owned();
+        cached.push(".sig");
+        PathBuf::from(cached)
     };
 
-    if requires_download {
-        warn!("checksum did not match for {}. downloading from {}", &item.name, url);
-        let mut file = File::create(&destination).map_err(|why| DownloadError::Open {
-            file: destination.clone(),
-            why
-        })?;
+    let verify = |tarball: &Path| {
+        item.signature.as_ref().map_or(Ok(()), |signature| {
+            verify_signature(client, item, signature, tarball, &cached_signature)
+        })
+    };
 
-        reqwest::get(url)
-            .and_then(|mut request| request.copy_to(&mut file))
-            .map_err(|why| DownloadError::Request { name: filename.to_owned(), why })?;
+    if destination.is_file() {
+        let digest = sha256_of(&destination)?;
+        if digest == sha256 {
+            return verify(&destination);
+        }
+
+        warn!("cached source for {} is corrupted; downloading it again", item.name);
+        let _ = fs::remove_file(&destination);
     }
 
-    let digest = File::open(&destination)
-        .and_then(hasher::<Sha256, File>)
-        .map_err(|why| DownloadError::Open {
-            file: destination.clone(),
+    let partial = {
+        let mut partial = destination.as_os_str().to_owned();
+        partial.push(".partial");
+        PathBuf::from(partial)
+    };
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(|why| DownloadError::Open { file: parent.to_path_buf(), why })?;
+    }
+
+    let offset = partial.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    let mut request = client.get(url);
+    if offset != 0 {
+        info!("resuming download of {} from {} bytes", filename, offset);
+        request = request.header(RANGE, format!("bytes={}-", offset).as_str());
+    }
+
+    let response = request.send()
+        .map_err(|why| DownloadError::Request { name: filename.to_owned(), why })?;
+
+    // A range which can't be satisfied is requested when the partial file is already complete.
+    if response.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+        let response = response.error_for_status()
+            .map_err(|why| DownloadError::Request { name: filename.to_owned(), why })?;
+
+        // Servers which don't support ranges will send the entire file instead, and a partial
+        // file is only worth keeping if the server will accept a range for it later.
+        let append = response.status() == StatusCode::PARTIAL_CONTENT;
+        let resumable = append || response.headers().get(ACCEPT_RANGES)
+            .map_or(false, |value| value.as_bytes() == b"bytes");
+
+        let name = &item.name;
+        let progress = |written: u64, rate: u64| {
+            info!("{}: downloaded {} MiB at {} KiB/s", name, written / 1024 / 1024, rate / 1024)
+        };
+
+        let response = bandwidth.reader(response);
+        let result = if resumable {
+            resume_to_file(response, &partial, append, cancel, progress)
+        } else {
+            stream_to_file(response, &partial, cancel, progress)
+        };
+
+        result.map_err(|why| DownloadError::Stream {
+            url: url.to_owned(),
+            file: partial.clone(),
             why
         })?;
+    }
 
-    if digest == checksum {
-        Ok(())
-    } else {
-        let _ = fs::remove_file(&destination);
-        Err(DownloadError::ChecksumInvalid {
+    let digest = sha256_of(&partial)?;
+    if digest != sha256 {
+        let _ = fs::remove_file(&partial);
+        return Err(DownloadError::ChecksumInvalid {
             name: item.name.clone(),
-            expected: checksum.to_owned(),
+            expected: sha256.to_owned(),
             received: digest
-        })
+        });
     }
+
+    verify(&partial)?;
+    fs::rename(&partial, &destination).map_err(|why| DownloadError::Open { file: destination.clone(), why })
 }
 
-/// Downloads the source repository via git, then attempts to build it.
+/// Verifies a tarball by its detached upstream signature, against the keyring of the signature,
+/// or else the upstream key, which is fetched from the keyserver once and kept with the sources.
 ///
-/// - If the build directory does not exist, it will be cloned.
-/// - Otherwise, the sources will be pulled from the build directory.
-fn download_git(name: &str, url: &str, suite: &str, branch: &Option<String>, commit: &Option<String>) -> io::Result<()> {
-    let path = env::current_dir()
-        .expect("failed to get current directory")
-        .join(["build/", suite].concat());
-
-    let path_with_name = path.join(name);
+/// A signature which verified the tarball is kept at `cached`, beside the tarball, so that it is
+/// only downloaded again if the cached signature no longer verifies it.
+fn verify_signature(
+    client: &Client,
+    item: &Source,
+    signature: &SourceSignature,
+    tarball: &Path,
+    cached: &Path
+) -> Result<(), DownloadError> {
+    let invalid = |why: io::Error| DownloadError::Signature { name: item.name.clone(), why };
 
-    let clone = || -> io::Result<()> {
-        Command::new("git").arg("-C").arg(&path).args(&["clone", &url, name]).run()
-    };
-
-    let pull = |branch: &str| -> io::Result<()> {
-        Command::new("git")
-            .arg("-C")
-            .arg(&path_with_name)
-            .args(&["pull", "origin", branch])
-            .run()
-    };
-
-    let reset = || -> io::Result<()> {
-        Command::new("git")
-            .arg("-C")
-            .arg(&path_with_name)
-            .args(&["reset", "--hard"])
-            .run()
-    };
+    let keyring = match signature.keyring {
+        Some(ref keyring) => keyring.clone(),
+        None => {
+            let fingerprint = signature.fingerprint.split_whitespace().collect::<String>();
+            let keyring = PathBuf::from([SOURCES_KEYS, &fingerprint, ".gpg"].concat());
+            if !keyring.exists() {
+                let keyserver = signature.keyserver.as_ref()
+                    .map_or(DEFAULT_KEYSERVER, |keyserver| keyserver.as_str());
+                fs::create_dir_all(SOURCES_KEYS)
+                    .and_then(|_| gpg::fetch_key(&signature.fingerprint, keyserver, &keyring))
+                    .map_err(invalid)?;
+            }
 
-    let reset_commit = || -> io::Result<()> {
-        if let Some(commit) = commit {
-            Command::new("git")
-                .arg("-C")
-                .arg(&path_with_name)
-                .args(&["reset", "--hard", &commit])
-                .run()?;
+            keyring
         }
-
-        Ok(())
     };
 
-    let checkout = || -> io::Result<&str> {
-        match branch {
-            Some(branch) => {
-                Command::new("git")
-                    .arg("-C")
-                    .arg(&path_with_name)
-                    .args(&["checkout", &branch])
-                    .run()?;
-                Ok(branch.as_str())
-            }
-            None => Ok("master")
+    if cached.is_file() {
+        match gpg::verify_detached(cached, tarball, &keyring, &signature.fingerprint) {
+            Ok(()) => return Ok(()),
+            Err(why) => warn!("cached signature of {} is invalid; downloading it again: {}", item.name, why)
         }
-    };
-
-    if path_with_name.exists() {
-        reset()?;
-        let branch = checkout()?;
+    }
 
-        if let Some(commit) = commit {
-            let current_revision = Command::new("git")
-                .arg("-C")
-                .arg(&path_with_name)
-                .args(&["rev-parse", branch])
-                .run_with_stdout()?;
+    let directory = cached.parent().unwrap_or_else(|| Path::new("."));
+    let mut detached = NamedTempFile::new_in(directory).map_err(invalid)?;
+    client.get(signature.url.as_str()).send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|mut response| response.copy_to(&mut detached))
+        .map_err(|why| DownloadError::Request {
+            name: misc::filename_from_url(&signature.url).to_owned(),
+            why
+        })?;
 
-            if current_revision.starts_with(commit.as_str()) {
-                return Ok(());
-            }
-        }
+    gpg::verify_detached(detached.path(), tarball, &keyring, &signature.fingerprint).map_err(invalid)?;
+    detached.persist(cached).map(|_| ()).map_err(|why| invalid(why.error))
+}
 
-        pull(branch)?;
-        reset_commit()?;
-    } else {
-        clone()?;
-        checkout()?;
-        reset_commit()?;
-    }
+fn sha256_of(path: &Path) -> Result<String, DownloadError> {
+    File::open(path)
+        .and_then(hasher::<Sha256, File>)
+        .map_err(|why| DownloadError::Open { file: path.to_path_buf(), why })
+}
 
-    Ok(())
+/// Fetches the source repository via git, and checks out its revision within the build directory.
+fn download_git(
+    name: &str,
+    url: &str,
+    suite: &str,
+    branch: &Option<String>,
+    tag: &Option<String>,
+    commit: &Option<String>,
+) -> io::Result<()> {
+    let project = env::current_dir()?.join(["build/", suite, "/", name].concat());
+    git::fetch(name, url, branch, tag, commit, &project).map(|_| ())
 }
 
 /// Downloads a debian package's sources from the given remote `dsc` URL.
@@ -177,15 +249,9 @@ fn download_git(name: &str, url: &str, suite: &str, branch: &Option<String>, com
 fn download_dsc(item: &Source, dsc: &str, suite: &str) -> io::Result<()> {
     let path = PathBuf::from(["build/", suite, "/", &item.name].concat());
     let mut result = Ok(());
-    if ! path.join(::misc::filename_from_url(dsc)).exists() {
+    if ! path.join(misc::filename_from_url(dsc)).exists() {
         fs::create_dir_all(&path)?;
-        let cwd = env::current_dir()?;
-        env::set_current_dir(&path)?;
-
-        result = Command::new("dget").args(&["-uxqd", dsc]).run();
-        if let Err(why) = env::set_current_dir(cwd) {
-            panic!("failed to set directory to original location: {}", why);
-        }
+        result = Command::new("dget").args(&["-uxqd", dsc]).current_dir(&path).run();
     }
 
     result
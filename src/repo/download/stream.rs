@@ -0,0 +1,178 @@
+use interrupt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+const FLUSH_INTERVAL: u64 = 16 * 1024 * 1024;
+const PROGRESS_INTERVAL: u64 = 1;
+
+/// Streams the contents of a reader to a file at `dst` using a bounded buffer.
+///
+/// The cancellation token, and whether the run was interrupted, are checked between each chunk,
+/// and the file is flushed periodically so that memory usage remains constant regardless of the
+/// size of the download. If the download is cancelled or fails, the partial file will be removed.
+/// Progress is reported via the callback as the number of bytes written, and the throughput in
+/// bytes per second.
+pub fn stream_to_file<R, F>(
+    reader: R,
+    dst: &Path,
+    cancel: &Arc<AtomicBool>,
+    progress: F,
+) -> io::Result<u64>
+    where R: Read,
+          F: FnMut(u64, u64),
+{
+    let result = File::create(dst).and_then(|file| stream(reader, file, cancel, progress));
+    if result.is_err() && dst.exists() {
+        let _ = fs::remove_file(dst);
+    }
+
+    result
+}
+
+/// Streams the contents of a reader to a partial download at `dst`, appending to the data that
+/// was already downloaded if `append` is set.
+///
+/// Unlike `stream_to_file`, the partial file is kept if the download fails or is cancelled, so
+/// that the download may be resumed from where it stopped.
+pub fn resume_to_file<R, F>(
+    reader: R,
+    dst: &Path,
+    append: bool,
+    cancel: &Arc<AtomicBool>,
+    progress: F,
+) -> io::Result<u64>
+    where R: Read,
+          F: FnMut(u64, u64),
+{
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(dst)
+        .and_then(|file| stream(reader, file, cancel, progress))
+}
+
+fn stream<R, F>(mut reader: R, mut file: File, cancel: &Arc<AtomicBool>, mut progress: F) -> io::Result<u64>
+    where R: Read,
+          F: FnMut(u64, u64),
+{
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut written = 0u64;
+    let mut unflushed = 0u64;
+    let start = Instant::now();
+    let mut last_report = start;
+
+    loop {
+        if cancel.load(Ordering::SeqCst) || interrupt::is_interrupted() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "download was cancelled"));
+        }
+
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => return Err(why),
+        };
+
+        file.write_all(&buffer[..read])?;
+        written += read as u64;
+        unflushed += read as u64;
+
+        if unflushed >= FLUSH_INTERVAL {
+            file.flush()?;
+            unflushed = 0;
+        }
+
+        if last_report.elapsed() >= Duration::from_secs(PROGRESS_INTERVAL) {
+            last_report = Instant::now();
+            progress(written, throughput(written, start.elapsed()));
+        }
+    }
+
+    file.flush()?;
+    file.sync_all()?;
+    progress(written, throughput(written, start.elapsed()));
+    Ok(written)
+}
+
+fn throughput(bytes: u64, elapsed: Duration) -> u64 {
+    let millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+    if millis == 0 { bytes } else { bytes * 1000 / millis }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use tempfile::tempdir;
+
+    /// A reader which requests cancellation after the first chunk has been read.
+    struct CancellingReader {
+        cancel: Arc<AtomicBool>,
+        chunks: usize,
+    }
+
+    impl Read for CancellingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.chunks += 1;
+            if self.chunks > 1 {
+                self.cancel.store(true, Ordering::SeqCst);
+            }
+
+            for byte in buf.iter_mut() {
+                *byte = 0;
+            }
+
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn cancelled_download_removes_partial_file() {
+        let dir = tempdir().unwrap();
+        let dst = dir.path().join("package.orig.tar.xz");
+        let cancel = Arc::new(AtomicBool::new(false));
+        let reader = CancellingReader { cancel: cancel.clone(), chunks: 0 };
+
+        let result = stream_to_file(reader, &dst, &cancel, |_, _| ());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn resumed_download_keeps_partial_file() {
+        let dir = tempdir().unwrap();
+        let dst = dir.path().join("package.orig.tar.xz.partial");
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let reader = CancellingReader { cancel: cancel.clone(), chunks: 0 };
+        let result = resume_to_file(reader, &dst, false, &cancel, |_, _| ());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        let partial = fs::metadata(&dst).unwrap().len();
+        assert!(partial > 0);
+
+        cancel.store(false, Ordering::SeqCst);
+        assert_eq!(resume_to_file(&b"rest"[..], &dst, true, &cancel, |_, _| ()).unwrap(), 4);
+        assert_eq!(fs::metadata(&dst).unwrap().len(), partial + 4);
+
+        assert_eq!(resume_to_file(&b"all"[..], &dst, false, &cancel, |_, _| ()).unwrap(), 3);
+        assert_eq!(misc::read(&dst).unwrap(), b"all");
+    }
+
+    #[test]
+    fn completed_download() {
+        let dir = tempdir().unwrap();
+        let dst = dir.path().join("package.orig.tar.xz");
+        let cancel = Arc::new(AtomicBool::new(false));
+        let data = vec![1u8; BUFFER_SIZE * 3 + 7];
+
+        assert_eq!(stream_to_file(data.as_slice(), &dst, &cancel, |_, _| ()).unwrap(), data.len() as u64);
+        assert_eq!(fs::metadata(&dst).unwrap().len(), data.len() as u64);
+    }
+}
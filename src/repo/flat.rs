@@ -0,0 +1,191 @@
+use action;
+use checksum::DigestCache;
+use compress::*;
+use config::Config;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use super::verify::read_index;
+use super::{collect_components, generate, integrity, sign};
+use workspace;
+
+/// Where the indices of a flat repository are generated, before they are moved to the root.
+const FLAT_BASE: &str = "flat.new";
+
+/// Generates a flat repository at the root of the repo, from within the repo directory.
+///
+/// The indices of each component and architecture are generated as they are for a suite, and
+/// are then merged into a single `Packages` and `Sources` index at the root, beside a `Release`
+/// which is signed unless the repository is `unsigned`. Packages remain in the pool, which the
+/// indices refer to by their path from the root, for sources such as `deb http://host/ ./`.
+pub(super) fn generate_flat(config: &Config, cache: &DigestCache) -> io::Result<()> {
+    let pool = ["pool/", &config.archive, "/"].concat();
+    let pool_path = Path::new(&pool);
+    let base = Path::new(FLAT_BASE);
+
+    if base.exists() {
+        action::remove_dir_all(base)?;
+    }
+
+    workspace::register(base)?;
+    for component in config.components() {
+        action::create_dir_all(pool_path.join(component))?;
+    }
+
+    if action::is_dry_run() {
+        action::report(format_args!("regenerate, and publish the flat indices of {}", pool));
+        return Ok(());
+    }
+
+    let components = collect_components(pool_path, FLAT_BASE, &[])?;
+    if !config.source_only {
+        generate::dists(config, FLAT_BASE, pool_path, &components, cache)?;
+    }
+
+    for component in &components {
+        let pool = [&pool, component.as_str()].concat();
+        generate::sources_index(config, component, FLAT_BASE, &pool, cache)?;
+    }
+
+    let mut packages = Vec::new();
+    let mut sources = Vec::new();
+    for component in &components {
+        let component = base.join(component);
+        for arch in config.release_architectures() {
+            let binary = component.join(["binary-", arch].concat());
+            if let Some((_, index)) = read_index(&binary, "Packages")? {
+                packages.push(index);
+            }
+        }
+
+        if let Some((_, index)) = read_index(&component.join("source"), "Sources")? {
+            sources.push(index);
+        }
+    }
+
+    let root = [FLAT_BASE, "/root"].concat();
+    let root_path = Path::new(&root);
+    fs::create_dir_all(root_path)?;
+
+    let (compression, level) = (config.compression(), config.compression_level);
+    compress_level("Packages", root_path, merge_indices(&packages).as_bytes(), compression, level)?;
+    compress_level("Sources", root_path, merge_indices(&sources).as_bytes(), compression, level)?;
+    generate::dists_release(config, &root, &[])?;
+    publish_flat(config, root_path)
+}
+
+/// Regenerates the `Release` of a published flat repository from its indices, and publishes it
+/// again, signed unless the repository is `unsigned`, from within the repo directory.
+pub(super) fn resign_flat(config: &Config) -> io::Result<()> {
+    let base = Path::new(FLAT_BASE);
+    if base.exists() {
+        action::remove_dir_all(base)?;
+    }
+
+    workspace::register(base)?;
+    let root = [FLAT_BASE, "/root"].concat();
+    let root_path = Path::new(&root);
+    fs::create_dir_all(root_path)?;
+
+    for entry in fs::read_dir(".")? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let index = name.to_str()
+            .map_or(false, |name| name.starts_with("Packages") || name.starts_with("Sources"));
+
+        if index && entry.file_type()?.is_file() {
+            fs::hard_link(entry.path(), root_path.join(&name))?;
+        }
+    }
+
+    generate::dists_release(config, &root, &[])?;
+    publish_flat(config, root_path)
+}
+
+/// Signs and audits the `Release` beside the flat indices, and moves them into the repo root.
+fn publish_flat(config: &Config, root_path: &Path) -> io::Result<()> {
+    if !config.unsigned {
+        let release = root_path.join("Release");
+        let signer = sign::Signer::new(config)?;
+        signer.in_release(&release, &root_path.join("InRelease"))?;
+        signer.detached(&release, &root_path.join("Release.gpg"))?;
+    }
+
+    let mismatched = integrity::audit_release(root_path)?;
+    if !mismatched.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("indices do not match the Release file: {:?}", mismatched)
+        ));
+    }
+
+    // The indices are published before the Release files which list them.
+    let mut files = fs::read_dir(root_path)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<io::Result<Vec<String>>>()?;
+    files.sort_by_key(|name| (name.contains("Release"), name.clone()));
+
+    for name in &files {
+        action::rename(root_path.join(name), name)?;
+    }
+
+    // The signatures of a repository which was signed before are stale once it is unsigned.
+    if config.unsigned {
+        for name in &["InRelease", "Release.gpg"] {
+            if Path::new(name).exists() {
+                action::remove_file(name)?;
+            }
+        }
+    }
+
+    action::remove_dir_all(FLAT_BASE)
+}
+
+/// Merges indices into one, which lists each stanza once, as the packages of the `all`
+/// architecture are listed by the index of every architecture.
+fn merge_indices(indices: &[String]) -> String {
+    let stanzas = indices.iter()
+        .flat_map(|index| index.split("\n\n"))
+        .map(|stanza| stanza.trim())
+        .filter(|stanza| !stanza.is_empty());
+
+    let mut listed = HashSet::new();
+    let mut merged = String::new();
+    for stanza in stanzas {
+        if listed.insert(stanza) {
+            if !merged.is_empty() {
+                merged.push('\n');
+            }
+
+            merged.push_str(stanza);
+            merged.push('\n');
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_indices() {
+        let amd64 = "Package: foo\nArchitecture: amd64\n\nPackage: foo-data\nArchitecture: all\n";
+        let i386 = "Package: foo\nArchitecture: i386\n\nPackage: foo-data\nArchitecture: all\n";
+
+        assert_eq!(merge_indices(&[amd64.into(), i386.into()]), "\
+Package: foo
+Architecture: amd64
+
+Package: foo-data
+Architecture: all
+
+Package: foo
+Architecture: i386
+");
+
+        assert_eq!(merge_indices(&[]), "");
+    }
+}
@@ -1,20 +1,31 @@
-use checksum::hasher;
-use config::Config;
+use action;
+use checksum::{DigestCache, MD5, SHA1, SHA256, SHA512};
+use chrono::{TimeZone, Utc};
+use config::{Config, Override};
 use debian::{self, *};
-use debarchive::Archive as DebArchive;
-use md5::Md5;
+use error::Error;
+use iter_reader::IteratorReader;
+use itertools::Itertools;
 use misc;
+use progress::{Progress, Unit};
 use rayon::{self, prelude::*};
-use sha1::Sha1;
-use sha2::{Sha256, Sha512};
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::collections::hash_map::{HashMap, Entry};
-use std::{env, fs::{self, File}, io::{self, Write}, path::{Path, PathBuf}, process::{Command, Stdio}};
-use deb_version::compare_versions;
+use std::{env, fs::{self, File}, io::{self, Read, Write}, path::{Path, PathBuf}, process::Command};
+use super::layout::is_pool_link;
+use walkdir::WalkDir;
 
 use compress::*;
 
-pub(crate) fn sources_index(component: &str, dist_base: &str, pool_base: &str) -> io::Result<()> {
+/// Generates the `Sources` index of a component from the `.dsc` files within its pool.
+pub(crate) fn sources_index(
+    config: &Config,
+    component: &str,
+    dist_base: &str,
+    pool_base: &str,
+    cache: &DigestCache,
+) -> io::Result<()> {
     let pool_path = PathBuf::from(pool_base).join("source");
     if ! pool_path.exists() {
         return Ok(());
@@ -24,26 +35,65 @@ pub(crate) fn sources_index(component: &str, dist_base: &str, pool_base: &str) -
     let path = PathBuf::from([dist_base, "/", component, "/source/"].concat());
     fs::create_dir_all(&path)?;
 
-    Command::new("apt-ftparchive")
-        .arg("sources")
-        .arg(pool_path)
-        .stderr(Stdio::inherit())
-        .stdout(Stdio::piped())
-        .spawn()
-        .and_then(|mut child| {
-            {
-                let stdout = child.stdout.as_mut().unwrap();
-                compress("Sources", &path, stdout, UNCOMPRESSED | GZ_COMPRESS | XZ_COMPRESS)?;
+    let mut dscs = WalkDir::new(&pool_path)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && entry.file_name().to_string_lossy().ends_with(".dsc"))
+        .filter(|entry| !is_pool_link(entry.path(), Path::new("pool")))
+        .map(|entry| entry.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    // Entries are sorted by their location in the pool, so the index is reproducible.
+    dscs.sort();
+
+    // A source whose files are missing or corrupted is left out, rather than failing the suite.
+    let generated = dscs.par_iter()
+        .filter_map(|dsc| match SourceEntry::from_dsc(dsc, cache) {
+            Ok(ref entry) if excludes_source(config, entry) => {
+                info!("excluding {} from the sources index", dsc.display());
+                None
+            }
+            Ok(entry) => Some(entry.generate_entry()),
+            Err(why) => {
+                warn!("skipping {} in the sources index: {}", dsc.display(), why);
+                None
             }
-
-            child.wait().and_then(|stat| {
-                if stat.success() {
-                    Ok(())
-                } else {
-                    Err(io::Error::new(io::ErrorKind::Other, "apt-ftparchive failed"))
-                }
-            })
         })
+        .collect::<Vec<Vec<u8>>>();
+
+    let sources_reader = IteratorReader::new(
+        generated.into_iter().intersperse(vec![b'\n']),
+        Vec::with_capacity(64 * 1024)
+    );
+
+    compress_level("Sources", &path, sources_reader, config.compression(), config.compression_level)
+}
+
+/// Whether a source package is kept out of the `Sources` index by an exclusion.
+fn excludes_source(config: &Config, entry: &SourceEntry) -> bool {
+    let field = |key: &str| entry.control.get(key).map_or("", |value| value.as_str());
+    config.excludes(field("Source"), field("Version"), "source", Some(field("Section")))
+}
+
+/// Determines the `Date` of the `Release` file for reproducible builds.
+///
+/// An explicit epoch takes precedence over the value of the `SOURCE_DATE_EPOCH` environment
+/// variable, which callers read with `env::var("SOURCE_DATE_EPOCH").ok()`. If neither is set,
+/// `None` is returned, and the current time will be used.
+pub(crate) fn release_date(epoch: Option<i64>, variable: Option<String>) -> io::Result<Option<String>> {
+    let epoch = match (epoch, variable) {
+        (Some(epoch), _) => epoch,
+        (None, Some(ref value)) if !value.is_empty() => value.trim().parse::<i64>().map_err(|why| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("SOURCE_DATE_EPOCH is not a valid timestamp: {}: {}", value, why)
+            )
+        })?,
+        _ => return Ok(None)
+    };
+
+    Ok(Some(Utc.timestamp(epoch, 0).format("%a, %d %b %Y %H:%M:%S UTC").to_string()))
 }
 
 // TODO: this can easily be replaced with Rust.
@@ -51,8 +101,46 @@ pub(crate) fn sources_index(component: &str, dist_base: &str, pool_base: &str) -
 pub(crate) fn dists_release(config: &Config, base: &str, components: &[String]) -> io::Result<()> {
     info!("generating dists release files");
 
-    let cwd = env::current_dir()?;
-    env::set_current_dir(base)?;
+    let variable = env::var("SOURCE_DATE_EPOCH").ok();
+    let mut options = release_date(config.source_date_epoch, variable)?.map_or_else(Vec::new, |date| {
+        vec!["-o".to_owned(), format!("APT::FTPArchive::Release::Date={}", date)]
+    });
+
+    if config.by_hash.is_some() {
+        options.push("-o".to_owned());
+        options.push("APT::FTPArchive::Release::Acquire-By-Hash=yes".to_owned());
+    }
+
+    // Digests which were not selected are neither computed nor listed by the `Release` file.
+    let digests = config.digests();
+    for &(flag, name) in &[(MD5, "MD5"), (SHA1, "SHA1"), (SHA256, "SHA256"), (SHA512, "SHA512")] {
+        if digests & flag == 0 {
+            options.push("-o".to_owned());
+            options.push(format!("APT::FTPArchive::{}=false", name));
+        }
+    }
+
+    if let Some(seconds) = config.valid_until {
+        options.push("-o".to_owned());
+        options.push(format!("APT::FTPArchive::Release::ValidTime={}", seconds));
+    }
+
+    if config.not_automatic {
+        options.push("-o".to_owned());
+        options.push("APT::FTPArchive::Release::NotAutomatic=yes".to_owned());
+    }
+
+    if config.but_automatic_upgrades {
+        options.push("-o".to_owned());
+        options.push("APT::FTPArchive::Release::ButAutomaticUpgrades=yes".to_owned());
+    }
+
+    // A suite which only publishes sources has no binary architectures.
+    let architectures = if config.source_only {
+        "source".to_owned()
+    } else {
+        config.release_architectures().join(" ")
+    };
 
     let components = components.iter()
         .fold(String::new(), |mut acc, x| {
@@ -72,7 +160,7 @@ pub(crate) fn dists_release(config: &Config, base: &str, components: &[String])
         .arg("-o")
         .arg(format!(
             "APT::FTPArchive::Release::Suite={}",
-            config.archive
+            config.release_suite()
         ))
         .arg("-o")
         .arg(format!(
@@ -82,120 +170,246 @@ pub(crate) fn dists_release(config: &Config, base: &str, components: &[String])
         .arg("-o")
         .arg(format!(
             "APT::FTPArchive::Release::Codename={}",
-            config.archive
+            config.release_codename()
         ))
         .arg("-o")
-        .arg("APT::FTPArchive::Release::Architectures=i386 amd64 all")
+        .arg(["APT::FTPArchive::Release::Architectures=", &architectures].concat())
         .arg("-o")
         .arg(["APT::FTPArchive::Release::Components=", components.trim_right()].concat())
         .arg("-o")
-        .arg(format!(
-            "APT::FTPArchive::Release::Description={} ({} {})",
-            config.label, config.archive, config.version
-        ))
+        .arg(["APT::FTPArchive::Release::Description=", &config.release_description()].concat())
+        .args(&options)
         .arg("release")
         .arg(".")
+        .current_dir(base)
         .output()
         .map(|data| data.stdout)?;
 
-    let mut release_file = File::create("Release")?;
-    release_file.write_all(&release)?;
-    env::set_current_dir(cwd)
+    let mut release = sort_release_files(&release);
+    if let Some(ref changelogs) = config.changelogs {
+        release = insert_field(&release, "Changelogs", &changelogs.template(&config.archive));
+    }
+
+    let mut release_file = File::create(Path::new(base).join("Release"))?;
+    release_file.write_all(&release)
 }
 
-/// Generates the `InRelease` file from the `Release` file via `gpg --clearsign`.
-pub(crate) fn gpg_in_release(email: &str, release_path: &Path, out_path: &Path) -> io::Result<()> {
-    info!("generating InRelease file");
-    let exit_status = Command::new("gpg")
-        .args(&[
-            "--clearsign",
-            "--local-user",
-            email,
-            "--batch",
-            "--yes",
-            "--digest-algo",
-            "sha512",
-            "-o",
-        ])
-        .arg(out_path)
-        .arg(release_path)
-        .status()?;
-
-    if exit_status.success() {
-        Ok(())
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "gpg_in_release failed",
-        ))
+/// Sorts the files which are listed by each digest of a `Release` file by their path, as
+/// `apt-ftparchive` lists them in the order that it finds them, so that the same dists are
+/// released the same way on every run.
+fn sort_release_files(release: &[u8]) -> Vec<u8> {
+    fn push_sorted(output: &mut String, files: &mut Vec<&str>) {
+        let path = |line: &str| line.split_whitespace().last().unwrap_or("").to_owned();
+        files.sort_by(|a, b| path(a).cmp(&path(b)));
+        for file in files.drain(..) {
+            output.push_str(file);
+            output.push('\n');
+        }
     }
+
+    let release = String::from_utf8_lossy(release);
+    let mut output = String::with_capacity(release.len());
+    let mut files = Vec::new();
+    for line in release.lines() {
+        if line.starts_with(' ') {
+            files.push(line);
+            continue
+        }
+
+        push_sorted(&mut output, &mut files);
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    push_sorted(&mut output, &mut files);
+    output.into_bytes()
 }
 
-/// Generates the `Release.gpg` file from the `Release` file via `gpg -abs`
-pub(crate) fn gpg_release(email: &str, release_path: &Path, out_path: &Path) -> io::Result<()> {
-    info!("generating Release.gpg file");
-    let exit_status = Command::new("gpg")
-        .args(&[
-            "-abs",
-            "--local-user",
-            email,
-            "--batch",
-            "--yes",
-            "--digest-algo",
-            "sha512",
-            "-o",
-        ])
-        .arg(out_path)
-        .arg(release_path)
-        .status()?;
-
-    if exit_status.success() {
-        Ok(())
-    } else {
-        Err(io::Error::new(io::ErrorKind::Other, "gpg_release failed"))
+/// Inserts a field into a `Release` file before its lists of files, for the fields which
+/// `apt-ftparchive` is unable to write.
+fn insert_field(release: &[u8], field: &str, value: &str) -> Vec<u8> {
+    let release = String::from_utf8_lossy(release);
+    let mut output = String::with_capacity(release.len() + field.len() + value.len() + 3);
+    let mut inserted = false;
+    for line in release.lines() {
+        if !inserted && line.ends_with(':') {
+            output.push_str(&[field, ": ", value, "\n"].concat());
+            inserted = true;
+        }
+
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    if !inserted {
+        output.push_str(&[field, ": ", value, "\n"].concat());
+    }
+
+    output.into_bytes()
+}
+
+/// Replaces the dist directory of a suite with a newly-generated one.
+///
+/// The new directory is atomically exchanged with the current directory, so clients will see
+/// either the old tree or the new tree in full, but never a mix of the two, nor neither. The old
+/// tree is kept at `<suite>.old` until the suite is next replaced, so that a client which resolved
+/// the suite before the exchange may still fetch the indices that it found there.
+///
+/// Where the filesystem cannot exchange them, the current directory is moved aside to
+/// `<suite>.old` before the new directory is renamed into its place, and the suite is missing for
+/// the moment between the two renames.
+pub(crate) fn swap_dists(current: &Path, new: &Path) -> io::Result<()> {
+    let old = {
+        let mut old = current.as_os_str().to_owned();
+        old.push(".old");
+        PathBuf::from(old)
+    };
+
+    if old.exists() {
+        action::remove_dir_all(&old)?;
+    }
+
+    info!("publishing {} as {}", new.display(), current.display());
+    if current.exists() {
+        // Exchanging the directories publishes the new suite in a single step.
+        match action::exchange(new, current) {
+            Ok(()) => return action::rename(new, &old),
+            Err(why) => {
+                warn!(
+                    "unable to exchange {} with {}, so it will be missing until it is replaced: {}",
+                    new.display(),
+                    current.display(),
+                    why
+                );
+                action::rename(current, &old)?;
+            }
+        }
     }
+
+    action::rename(new, current)
 }
 
 fn binary_suites(pool_base: &Path) -> io::Result<Vec<(String, PathBuf)>> {
     Ok(fs::read_dir(pool_base)?
         .filter_map(|entry| {
             let entry = entry.ok()?;
-            let arch = entry.file_name();
-            if &arch == "source" {
-                None
+            let name = entry.file_name();
+            let name = name.to_str()?;
+            if name.starts_with("binary-") {
+                Some((name["binary-".len()..].to_owned(), entry.path()))
             } else {
-                let path = pool_base.join(&arch);
-                let arch = match arch.to_str().unwrap() {
-                    "binary-amd64" => "amd64",
-                    "binary-arm64" => "arm64",
-                    "binary-armel" => "armel",
-                    "binary-armhf" => "armhf",
-                    "binary-i386" => "i386",
-                    "binary-mips" => "mips",
-                    "binary-mipsel" => "mipsel",
-                    "binary-mips64el" => "mips64el",
-                    "binary-ppc64el" => "ppc64el",
-                    "binary-s390x" => "s390x",
-                    "binary-all" => "all",
-                    arch => panic!("unsupported architecture: {}", arch),
-                };
-
-                Some((arch.to_owned(), path))
+                None
             }
         }).collect())
 }
 
-type ProcessedResults = Vec<io::Result<(PackageEntry, ContentsEntry, debian::Arch, debian::Component)>>;
+/// Lists the architecture independent packages of the pool in the index of every architecture,
+/// rather than in a `binary-all` index, which apt does not fetch.
+fn merge_arch_all(entries: &mut debian::Entries, architectures: &[String]) {
+    let all = match entries.remove("all") {
+        Some(all) => all,
+        None => return
+    };
+
+    for arch in architectures.iter().filter(|arch| arch.as_str() != "all") {
+        let components = entries.entry(arch.clone()).or_insert_with(HashMap::new);
+        for (component, &(ref packages, ref contents)) in &all {
+            let entry = components.entry(component.clone()).or_insert_with(|| (Vec::new(), Vec::new()));
+            entry.0.extend(packages.iter().cloned());
+            entry.1.extend(contents.iter().cloned());
+        }
+    }
+}
+
+/// Selects the newest version of each package of an architecture's archives, returning their
+/// paths in order.
+///
+/// Excluded versions are skipped, so that an earlier version may be published. Where an
+/// exclusion matches sections, the section of each archive is read, once any override has
+/// replaced it, so that a package is not left out of the indices by its excluded newest version.
+fn newest_archives(
+    config: &Config,
+    overrides: &BTreeMap<String, Override>,
+    arch: &str,
+    debs: Vec<PathBuf>
+) -> Vec<PathBuf> {
+    let sections = config.exclude.iter()
+        .flat_map(|exclusions| exclusions.iter())
+        .any(|exclusion| exclusion.section.is_some());
+
+    // An archive whose control cannot be read is left to fail as it is indexed.
+    let section = |package: &Path| -> Option<String> {
+        let mut control = DebFile::open(package).and_then(|archive| archive.control()).ok()?.fields;
+        if let Some(replaced) = control.get("Package").and_then(|name| overrides.get(name)) {
+            replaced.apply(&mut control);
+        }
+
+        control.get("Section").cloned()
+    };
+
+    // The control of each archive is read in parallel, and then compared in order.
+    let infos = debs.into_par_iter()
+        .filter_map(|package| get_debian_package_info(&package).map(|info| (info, package)))
+        .map(|(info, package)| {
+            let section = if sections { section(&package) } else { None };
+            (info, section, package)
+        })
+        .collect::<Vec<_>>();
+
+    let mut archives: HashMap<String, (String, PathBuf)> = HashMap::new();
+    for ((name, version), section, package) in infos {
+        if config.excludes(&name, &version, arch, section.as_ref().map(|x| x.as_str())) {
+            info!("excluding {} from the indices", package.display());
+            continue
+        }
+
+        match archives.entry(name) {
+            Entry::Occupied(mut entry) => {
+                if compare_versions(&entry.get().0, &version) == Ordering::Less {
+                    debug!("replacing {} with {}", entry.get().0, &version);
+                    entry.insert((version, package));
+                }
+            }
+            Entry::Vacant(mut entry) => {
+                entry.insert((version, package));
+            }
+        }
+    }
+
+    // The entries are processed in parallel, but collected in the order of their paths, so that
+    // the indices, and any error, are the same on each run.
+    let mut archives = archives.into_iter()
+        .map(|(_, (_, package))| package)
+        .collect::<Vec<PathBuf>>();
+
+    archives.sort();
+    archives
+}
+
+type ProcessedResults = Vec<io::Result<(
+    PackageEntry,
+    ContentsEntry,
+    Option<AppStreamFiles>,
+    debian::Arch,
+    debian::Component
+)>>;
 
 pub(crate) fn dists(
     config: &Config,
     dist_base: &str,
     pool_base: &Path,
     components: &[String],
+    cache: &DigestCache,
 ) -> io::Result<()> {
     info!("generating dist archives");
 
     let origin = &config.origin;
+    let overrides = match config.overrides {
+        Some(ref overrides) => overrides.load()?,
+        None => BTreeMap::new()
+    };
+
+    let progress = Progress::new("hashing packages", None, Unit::Items);
 
     // Collect the entries for each architecture of each component.
     let entries = components.par_iter().map(|component| {
@@ -203,80 +417,76 @@ pub(crate) fn dists(
         binary_suites(&pool_base.join(&component)).unwrap()
             .into_par_iter()
             .map(|(arch, path)| {
-                // Collect a list of packages to process for this architecture.
-                // This list will have older entries filtered.
-                let mut archives: HashMap<String, (String, PathBuf)> = HashMap::new();
-
-                // An iterator that returns debian archives found in the path.
-                let deb_iter = misc::walk_debs(&path, true)
-                    .filter(|e| !e.file_type().is_dir())
-                    .map(|e| e.path().to_path_buf());
-
-                for package in deb_iter {
-                    if let Some((name, version)) = get_debian_package_info(&package) {
-                        match archives.entry(name) {
-                            Entry::Occupied(mut entry) => {
-                                if compare_versions(&entry.get().0, &version) == Ordering::Less {
-                                    eprintln!("replacing {} with {}", entry.get().0, &version);
-                                    entry.insert((version, package));
-                                }
-                            }
-                            Entry::Vacant(mut entry) => {
-                                entry.insert((version, package));
-                            }
-                        }
-                    }
-                }
+                // The debian archives found in the path, which are sorted so that the same
+                // archive is chosen on each run when several have the same version.
+                let mut debs = misc::walk_debs(&path, true)
+                    .filter(|e| !e.file_type().is_dir() && !is_pool_link(e.path(), Path::new("pool")))
+                    .map(|e| e.path().to_path_buf())
+                    .collect::<Vec<PathBuf>>();
+
+                debs.sort();
+                let archives = newest_archives(config, &overrides, &arch, debs);
 
                 // Collect the entries for this architecture of this component
+                progress.add_total(archives.len() as u64);
                 archives.into_par_iter()
-                    .map(|(_, (_, debian_entry))| {
-                        info!("processing contents of {:?}", debian_entry);
+                    .map(|debian_entry| {
+                        debug!("processing contents of {:?}", debian_entry);
 
                         let arch: &str = &arch;
                         let component: &str = &component;
 
                         // Open the Debian archive, and get the IDs & required codecs for the inner control and data archives.
-                        let archive = DebArchive::new(&debian_entry)?;
+                        let index_error = |why| Error::Index { file: debian_entry.clone(), why };
+                        let archive = DebFile::open(&debian_entry).map_err(index_error)?;
                         // Open the control file within the control archive and read each key / value pair into a map.
-                        let control = archive.control_map()?;
+                        let mut control = archive.control().map(|control| control.fields).map_err(index_error)?;
+                        // Overrides are applied before the section is read, so that the Contents
+                        // archive lists the package by its new section too.
+                        let replaced = control.get("Package").and_then(|name| overrides.get(name));
+                        if let Some(replaced) = replaced {
+                            replaced.apply(&mut control);
+                        }
 
                         // The Contents archive requires that we know the package and section keys for each Debian package beforehand.
                         let package_name = match (control.get("Package"), control.get("Section")) {
-                            (Some(ref package), Some(ref section)) if component == "main" => [section, "/", package].concat(),
-                            (Some(ref package), Some(ref section)) => [component, "/", section, "/", package].concat(),
+                            (Some(ref package), Some(ref section)) => [section, "/", package].concat(),
                             _ => {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    "did not find package + section from control archive"
-                                ));
+                                return Err(index_error(io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    "the control file does not declare its Package and Section"
+                                )).into());
                             }
                         };
 
                         // Now get a listing of all the files for the Contents archive.
                         let mut files: Vec<PathBuf> = Vec::new();
+                        // And the files which describe its applications, for the AppStream metadata.
+                        let mut appstream_files = Vec::new();
 
                         // Runs each scope in parallel to generate the contents and checksums.
-                        let (content_res, ((sha1_res, sha256_res), (sha512_res, md5_res))) = {
+                        let (content_res, digests) = {
                             let path = &debian_entry;
-                            // TODO: use bus_writer instead of reading the same file in each thread.
-                            let generate_hashes = || {
-                                rayon::join(
-                                    || rayon::join(
-                                        || File::open(path).and_then(hasher::<Sha1, File>),
-                                        || File::open(path).and_then(hasher::<Sha256, File>),
-                                    ),
-                                    || rayon::join(
-                                        || File::open(path).and_then(hasher::<Sha512, File>),
-                                        || File::open(path).and_then(hasher::<Md5, File>),
-                                    )
-                                )
-                            };
+                            // Every digest is computed in a single pass over the archive, unless
+                            // the archive is unchanged since its digests were cached.
+                            let generate_hashes = || cache.digests(path);
 
                             rayon::join(
-                                || archive.data(|entry| {
-                                    let path = entry.path()?;
-                                    files.push(path.to_path_buf());
+                                || archive.each_data(|entry| {
+                                    // Directories are not listed by the Contents archive.
+                                    if !entry.header().entry_type().is_dir() {
+                                        let path = entry.path()?.to_path_buf();
+                                        let name = path.to_string_lossy().into_owned();
+                                        if let Some(ref settings) = config.appstream {
+                                            if is_appstream_file(&name, settings) {
+                                                let mut data = Vec::new();
+                                                entry.read_to_end(&mut data)?;
+                                                appstream_files.push((name, data));
+                                            }
+                                        }
+
+                                        files.push(path);
+                                    }
                                     Ok(())
                                 }),
                                 generate_hashes
@@ -284,26 +494,41 @@ pub(crate) fn dists(
                         };
 
                         drop(archive);
-                        content_res?;
+                        content_res.map_err(index_error)?;
+                        for lint in lint_multi_arch(&control, &files) {
+                            warn!("{}: {}", debian_entry.display(), lint);
+                        }
+
+                        let digests = digests.map_err(index_error)?;
                         let package_entry = PackageEntry {
                             control,
                             filename: debian_entry.clone(),
                             size: File::open(&debian_entry).and_then(|file| file.metadata().map(|m| m.len()))?,
-                            md5sum: md5_res?,
-                            sha1: sha1_res?,
-                            sha256: sha256_res?,
-                            sha512: sha512_res?,
+                            md5sum: digests.md5,
+                            sha1: digests.sha1,
+                            sha256: digests.sha256,
+                            sha512: digests.sha512,
+                        };
+
+                        let appstream = if appstream_files.is_empty() {
+                            None
+                        } else {
+                            let package = package_entry.control.get("Package").cloned().unwrap_or_default();
+                            Some(AppStreamFiles { package, files: appstream_files })
                         };
 
                         let contents_entry = ContentsEntry { package: package_name, files };
                         let arch: String = arch.to_owned();
                         let component: String = component.to_owned();
+                        progress.inc(1);
 
-                        Ok((package_entry, contents_entry, arch, component))
+                        Ok((package_entry, contents_entry, appstream, arch, component))
                     }).collect::<ProcessedResults>()
         }).collect::<Vec<ProcessedResults>>()
     }).collect::<Vec<Vec<ProcessedResults>>>();
 
+    progress.finish();
+
     // Flatten the results for each architecture of each component into a single iterator.
     let entries = entries.into_iter()
         .flat_map(|entries| entries.into_iter().flat_map(|x| x.into_iter()));
@@ -311,34 +536,249 @@ pub(crate) fn dists(
     // Validate the results of each parallel process, and collect them in a manner so that they
     // may be used for further parallel processing and compression.
     let mut entries_map: debian::Entries = HashMap::new();
+    let mut appstream: BTreeMap<String, BTreeMap<String, Vec<AppStreamFiles>>> = BTreeMap::new();
     for result in entries {
-        let (package, contents, arch, component) = result?;
+        let (package, contents, files, arch, component) = result?;
+        let excluded = {
+            let field = |key: &str| package.control.get(key).map_or("", |value| value.as_str());
+            config.excludes(field("Package"), field("Version"), &arch, Some(field("Section")))
+        };
+
+        if excluded {
+            info!("excluding {} from the indices", package.filename.display());
+            continue
+        }
 
-        match entries_map.entry(arch) {
-            Entry::Occupied(mut entry) => {
-                let entry = entry.get_mut();
-                match (*entry).0.entry(component) {
-                    Entry::Occupied(mut entry) => {
-                        (*entry.get_mut()).push(package);
-                    },
-                    Entry::Vacant(mut entry) => {
-                        entry.insert(vec![package]);
-                    }
-                }
-                (*entry).1.push(contents);
-            }
-            Entry::Vacant(mut entry) => {
-                entry.insert({
-                    let mut component_map = HashMap::new();
-                    component_map.insert(component, vec![package]);
-                    (component_map, vec![contents])
-                });
-            }
+        if let Some(files) = files {
+            appstream.entry(component.clone())
+                .or_insert_with(BTreeMap::new)
+                .entry(arch.clone())
+                .or_insert_with(Vec::new)
+                .push(files);
+        }
+
+        let entry = entries_map.entry(arch)
+            .or_insert_with(HashMap::new)
+            .entry(component)
+            .or_insert_with(|| (Vec::new(), Vec::new()));
+
+        entry.0.push(package);
+        entry.1.push(contents);
+    }
+
+    merge_arch_all(&mut entries_map, &config.architectures);
+
+    // Every configured architecture has an index, even if it does not have any packages yet.
+    for arch in config.architectures.iter().filter(|arch| arch.as_str() != "all") {
+        let components_map = entries_map.entry(arch.clone()).or_insert_with(HashMap::new);
+        for component in components {
+            components_map.entry(component.clone()).or_insert_with(|| (Vec::new(), Vec::new()));
         }
     }
 
     let destination = &Path::new(dist_base);
     let dist_files = DistFiles::new(destination, entries_map);
     // Re-enable duplicates checking.
-    dist_files.compress_and_release(config, origin, None)
+    dist_files.compress_and_release(config, origin, None)?;
+
+    // The AppStream metadata of each component is listed by architecture, like its packages.
+    if let Some(ref settings) = config.appstream {
+        for component in components.iter().filter(|x| !is_installer_component(x)) {
+            let mut architectures = appstream.remove(component).unwrap_or_default();
+            let all = architectures.remove("all").unwrap_or_default();
+            for arch in config.release_architectures() {
+                architectures.entry(arch.to_owned())
+                    .or_insert_with(Vec::new)
+                    .extend(all.iter().cloned());
+            }
+
+            info!("generating the AppStream metadata of {}", component);
+            let origin = [&config.archive, "-", component].concat();
+            write_dep11(
+                &destination.join(component),
+                &origin,
+                settings,
+                &architectures,
+                config.compression_level
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use tempfile::tempdir;
+    use toml;
+
+    fn write_suite(dist: &Path, generation: &str) {
+        fs::create_dir_all(dist.join("main/binary-amd64")).unwrap();
+        misc::write(dist.join("Release"), generation).unwrap();
+        misc::write(dist.join("main/binary-amd64/Packages"), generation).unwrap();
+    }
+
+    #[test]
+    fn arch_all_in_every_index() {
+        let package = |name: &str| (
+            PackageEntry {
+                control: vec![("Package".to_owned(), name.to_owned())].into_iter().collect(),
+                filename: PathBuf::from([name, "_1.0_all.deb"].concat()),
+                size: 0,
+                md5sum: String::new(),
+                sha1: String::new(),
+                sha256: String::new(),
+                sha512: String::new(),
+            },
+            ContentsEntry { package: ["misc/", name].concat(), files: Vec::new() }
+        );
+
+        let mut entries: debian::Entries = HashMap::new();
+        for &(arch, name) in &[("all", "foo-data"), ("amd64", "foo")] {
+            let (package, contents) = package(name);
+            entries.entry(arch.to_owned())
+                .or_insert_with(HashMap::new)
+                .insert("main".to_owned(), (vec![package], vec![contents]));
+        }
+
+        merge_arch_all(&mut entries, &["amd64".to_owned(), "i386".to_owned(), "all".to_owned()]);
+        assert!(!entries.contains_key("all"));
+        let names = |arch: &str| entries[arch]["main"].0.iter()
+            .map(|package| package.control["Package"].clone())
+            .collect::<Vec<String>>();
+        assert_eq!(names("amd64"), vec!["foo", "foo-data"]);
+        assert_eq!(names("i386"), vec!["foo-data"]);
+    }
+
+    #[test]
+    fn section_exclusions_before_newest() {
+        let dir = tempdir().unwrap();
+        let config: Config = toml::from_str(r#"
+archive = "bionic"
+version = "18.04"
+origin = "origin"
+label = "label"
+email = "info@example.com"
+
+[[exclude]]
+section = "games"
+"#).unwrap();
+
+        let mut debs = Vec::new();
+        for &(version, section) in &[("1.0", "utils"), ("1.1", "games")] {
+            let fields = format!("Package: foo\nVersion: {}\nArchitecture: amd64\nSection: {}\n", version, section);
+            let control = tarball(&[("control", fields.as_bytes())]).unwrap();
+            let deb = dir.path().join(format!("foo_{}_amd64.deb", version));
+            write_deb(&deb, &control, &tarball(&[]).unwrap()).unwrap();
+            debs.push(deb);
+        }
+
+        // The excluded newest version gives way to the version before it.
+        let archives = newest_archives(&config, &BTreeMap::new(), "amd64", debs.clone());
+        assert_eq!(archives, vec![dir.path().join("foo_1.0_amd64.deb")]);
+
+        let unfiltered = Config { exclude: None, .. config };
+        assert_eq!(newest_archives(&unfiltered, &BTreeMap::new(), "amd64", debs), vec![dir.path().join("foo_1.1_amd64.deb")]);
+    }
+
+    #[test]
+    fn reproducible_release_date() {
+        let variable = |value: &str| Some(value.to_owned());
+        assert_eq!(release_date(Some(0), None).unwrap().unwrap(), "Thu, 01 Jan 1970 00:00:00 UTC");
+        assert_eq!(release_date(None, variable("1540000000")).unwrap().unwrap(), "Sat, 20 Oct 2018 01:46:40 UTC");
+        assert_eq!(release_date(Some(0), variable("1540000000")).unwrap().unwrap(), "Thu, 01 Jan 1970 00:00:00 UTC");
+        assert!(release_date(None, variable("yesterday")).is_err());
+        assert_eq!(release_date(Some(0), variable("yesterday")).unwrap().unwrap(), "Thu, 01 Jan 1970 00:00:00 UTC");
+        assert_eq!(release_date(None, variable("")).unwrap(), None);
+        assert_eq!(release_date(None, None).unwrap(), None);
+
+        let release = b"Origin: Pop\nMD5Sum:\n 2 20 main/binary-i386/Packages\n 1 10 main/binary-amd64/Packages\n\
+            SHA256:\n 4 20 main/binary-i386/Packages\n 3 10 main/binary-amd64/Packages\n";
+        assert_eq!(String::from_utf8(sort_release_files(release)).unwrap(), "Origin: Pop\nMD5Sum:\n \
+            1 10 main/binary-amd64/Packages\n 2 20 main/binary-i386/Packages\nSHA256:\n \
+            3 10 main/binary-amd64/Packages\n 4 20 main/binary-i386/Packages\n");
+
+        let template = "https://apt.example.com/changelogs/bionic/@CHANGEPATH@/changelog";
+        assert_eq!(
+            String::from_utf8(insert_field(b"Origin: Pop\nMD5Sum:\n 1 10 main/binary-amd64/Packages\n", "Changelogs", template)).unwrap(),
+            ["Origin: Pop\nChangelogs: ", template, "\nMD5Sum:\n 1 10 main/binary-amd64/Packages\n"].concat()
+        );
+    }
+
+    #[test]
+    fn dists_double_buffering() {
+        let dir = tempdir().unwrap();
+        let current = dir.path().join("dists/bionic");
+        let new = dir.path().join("dists/bionic.new");
+        write_suite(&current, "0");
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let reader = {
+            let (current, stop, reads) = (current.clone(), stop.clone(), reads.clone());
+            thread::spawn(move || {
+                let (mut consistent, mut inconsistent, mut missing) = (0, 0, 0);
+                loop {
+                    let stopping = stop.load(Ordering::SeqCst);
+
+                    // Like a client that resolves the suite once, and then fetches its indices.
+                    // A suite which could not be opened, or whose indices could not be read
+                    // through it, was missing.
+                    let fetched = File::open(&current).and_then(|suite| {
+                        let base = PathBuf::from(format!("/proc/self/fd/{}", suite.as_raw_fd()));
+                        let release = misc::read_to_string(base.join("Release"))?;
+                        let packages = misc::read_to_string(base.join("main/binary-amd64/Packages"))?;
+                        Ok((release, packages))
+                    });
+
+                    match fetched {
+                        Ok((ref release, ref packages)) if release == packages => consistent += 1,
+                        Ok(_) => inconsistent += 1,
+                        Err(_) => missing += 1
+                    }
+
+                    reads.fetch_add(1, Ordering::SeqCst);
+                    if stopping {
+                        break
+                    }
+                }
+
+                (consistent, inconsistent, missing)
+            })
+        };
+
+        // The suite is only missing between swaps where the filesystem cannot exchange them.
+        let probe = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir(&probe.0).and_then(|_| fs::create_dir(&probe.1)).unwrap();
+        let exchanges = misc::exchange(&probe.0, &probe.1).is_ok();
+
+        for generation in 1..200 {
+            write_suite(&new, &generation.to_string());
+            swap_dists(&current, &new).unwrap();
+
+            // The tree replaced by this swap is only removed by the next, so every read which
+            // begins after this swap must finish before the next one.
+            let before = reads.load(Ordering::SeqCst);
+            while reads.load(Ordering::SeqCst) < before + 2 {
+                thread::yield_now();
+            }
+        }
+
+        stop.store(true, Ordering::SeqCst);
+        let (consistent, inconsistent, missing) = reader.join().unwrap();
+        assert!(consistent > 0);
+        assert_eq!(inconsistent, 0);
+        if exchanges {
+            assert_eq!(missing, 0);
+        }
+
+        assert_eq!(misc::read_to_string(current.join("Release")).unwrap(), "199");
+        assert_eq!(misc::read_to_string(dir.path().join("dists/bionic.old/Release")).unwrap(), "198");
+        assert!(!new.exists());
+    }
 }
@@ -0,0 +1,46 @@
+use action;
+use command::Command;
+use std::io;
+use std::path::Path;
+
+/// Runs the script of a hook with `sh -c` from the directory, with the given variables in its
+/// environment, which fails if the script exits with a failure.
+pub(crate) fn run_hook(
+    name: &str,
+    script: &str,
+    cwd: &Path,
+    env: &[(&str, &str)]
+) -> io::Result<()> {
+    action::perform(format_args!("run the {} hook: {}", name, script), || {
+        info!("running the {} hook: {}", name, script);
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script).current_dir(cwd);
+        for &(key, value) in env {
+            command.env(key, value);
+        }
+
+        command.run().map_err(|why| io::Error::new(
+            io::ErrorKind::Other,
+            format!("the {} hook failed: {}", name, why)
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn hook_environment() {
+        let dir = tempdir().unwrap();
+        let env = [("DEBREP_SUITE", "bionic"), ("DEBREP_SOURCE", "foo")];
+        let script = "echo \"$DEBREP_SOURCE $DEBREP_SUITE\" > hooked";
+        run_hook("prebuild", script, dir.path(), &env).unwrap();
+        assert_eq!(misc::read_to_string(dir.path().join("hooked")).unwrap(), "foo bionic\n");
+
+        let why = run_hook("postbuild", "exit 3", dir.path(), &env).unwrap_err();
+        assert!(why.to_string().starts_with("the postbuild hook failed"));
+    }
+}
@@ -0,0 +1,381 @@
+use checksum::hasher;
+use config::Config;
+use debian::parse_control;
+use misc::{self, is_deb, INCLUDE_DDEB, INCLUDE_SRCS};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use super::pool::{pool_component, pool_destination};
+use super::verify::{indices, listed_files};
+use walkdir::WalkDir;
+
+/// Describes how the directories of a foreign pool map to components of a suite in this repo.
+#[derive(Debug, Default)]
+pub struct ComponentMapping {
+    /// The suite that files will be imported into.
+    pub suite: String,
+    /// Maps a directory name within the foreign pool (such as `main`) to a component.
+    pub components: HashMap<String, String>,
+    /// The component to use for files which match no mapping. Unmapped files are otherwise skipped.
+    pub default: Option<String>,
+}
+
+impl ComponentMapping {
+    /// Infers the component of a file from the first directory of its path that has a mapping.
+    fn component_of(&self, relative: &Path) -> Option<&str> {
+        relative.parent()
+            .into_iter()
+            .flat_map(|parent| parent.components())
+            .filter_map(|component| component.as_os_str().to_str())
+            .filter_map(|name| self.components.get(name))
+            .map(|x| x.as_str())
+            .next()
+            .or_else(|| self.default.as_ref().map(|x| x.as_str()))
+    }
+}
+
+/// The files which were placed into the pool by an import, and those which were skipped.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<PathBuf>,
+    pub existing: Vec<PathBuf>,
+    pub skipped:  Vec<PathBuf>,
+    /// The version of each source package which an imported `Sources` index lists, by its name.
+    pub sources:  BTreeMap<String, String>,
+    /// The suites whose pools the files were placed in, which must be reindexed to publish them.
+    pub suites:   BTreeSet<String>,
+}
+
+/// Imports the binary and source files of a foreign pool tree, such as one managed by
+/// `reprepro` or `dak`, into the pool of this repository.
+///
+/// Files are hard-linked into place when possible, and are copied otherwise. The suites that the
+/// files were placed in are reported, whose dist files are then regenerated to index them.
+pub fn import_tree(src_pool: &Path, repo: &Path, mapping: ComponentMapping) -> io::Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let pool = repo.join("pool");
+
+    let files = WalkDir::new(src_pool)
+        .into_iter()
+        .filter_entry(|e| e.file_type().is_dir() || is_deb(e, INCLUDE_DDEB | INCLUDE_SRCS))
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file());
+
+    for file in files {
+        let src = file.path();
+        let relative = src.strip_prefix(src_pool).unwrap();
+        let filename = match file.file_name().to_str() {
+            Some(filename) if filename.contains('_') => filename,
+            _ => {
+                warn!("skipping {}: not a Debian package filename", src.display());
+                report.skipped.push(src.to_path_buf());
+                continue
+            }
+        };
+
+        let component = match mapping.component_of(relative) {
+            Some(component) => component,
+            None => {
+                warn!("skipping {}: no component mapping", src.display());
+                report.skipped.push(src.to_path_buf());
+                continue
+            }
+        };
+
+        let destination = match pool_destination(&pool, &mapping.suite, component, filename) {
+            Ok(destination) => destination,
+            Err(why) => {
+                warn!("skipping {}: {}", src.display(), why);
+                report.skipped.push(src.to_path_buf());
+                continue
+            }
+        };
+
+        place(src, &destination.join(filename), &mut report)?;
+    }
+
+    report.finish(&mapping.suite);
+    Ok(report)
+}
+
+/// Imports a suite of an existing repository, such as one published by `reprepro`, `aptly`, or
+/// `apt-ftparchive`, into the suite of this repo, reading the `Packages` and `Sources` indices of
+/// `src/dists/<from>` to place each file that they list in the component that lists it.
+///
+/// The foreign suite defaults to the suite of the same name, or else to the only suite of the
+/// repository. Each file is verified against the size and SHA256 digest of its entry, and files
+/// which are missing or differ are skipped. A repository without indices is imported by the
+/// directories of its pool instead, each of which is taken to be a component.
+pub fn import_repo(src: &Path, from: Option<&str>, repo: &Path, suite: &str) -> io::Result<ImportReport> {
+    let dists = src.join("dists");
+    if !dists.is_dir() {
+        let src_pool = src.join("pool");
+        let mut mapping = ComponentMapping { suite: suite.to_owned(), .. ComponentMapping::default() };
+        for entry in src_pool.read_dir()? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            mapping.components.insert(name.clone(), name);
+        }
+
+        return import_tree(&src_pool, repo, mapping);
+    }
+
+    let dist = dists.join(foreign_suite(&dists, from, suite)?);
+    let pool = repo.join("pool");
+    let mut report = ImportReport::default();
+    let mut seen = HashSet::new();
+
+    for (index, data) in indices(&dist)? {
+        // The dists are laid out by component as the pool is, such as `main/debug/binary-amd64`.
+        let component = match pool_component(index.strip_prefix(&dist).unwrap()) {
+            Some(component) => component,
+            None => continue
+        };
+
+        let stanzas = data.split("\n\n").map(|x| x.trim()).filter(|x| !x.is_empty());
+        for control in stanzas.map(parse_control) {
+            if let (Some(package), Some(version), Some(_)) =
+                (control.get("Package"), control.get("Version"), control.get("Directory"))
+            {
+                report.sources.insert(package.clone(), version.clone());
+            }
+
+            for (path, size, sha256) in listed_files(&control) {
+                let file = src.join(&path);
+                if !seen.insert(file.clone()) {
+                    continue
+                }
+
+                if !file.is_file() {
+                    warn!("skipping {}: it is listed by {}, but is missing", file.display(), index.display());
+                    report.skipped.push(file);
+                    continue
+                }
+
+                let actual = File::open(&file).and_then(hasher::<Sha256, File>)?;
+                if file.metadata()?.len().to_string() != size || actual != sha256 {
+                    warn!("skipping {}: it does not match its entry in {}", file.display(), index.display());
+                    report.skipped.push(file);
+                    continue
+                }
+
+                let filename = path.rsplit('/').next().unwrap_or(&path);
+                match pool_destination(&pool, suite, &component, filename) {
+                    Ok(destination) => place(&file, &destination.join(filename), &mut report)?,
+                    Err(why) => {
+                        warn!("skipping {}: {}", file.display(), why);
+                        report.skipped.push(file);
+                    }
+                }
+            }
+        }
+    }
+
+    report.finish(suite);
+    Ok(report)
+}
+
+/// The suite of the foreign dists to import, which are either named, of the same name as the
+/// suite they are imported into, or the only suite there is. Suites which are symlinks, as
+/// reprepro creates for the `Suite` of each codename, are not counted.
+fn foreign_suite(dists: &Path, from: Option<&str>, suite: &str) -> io::Result<String> {
+    if let Some(from) = from {
+        if !dists.join(from).is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no {} suite", dists.display(), from)
+            ));
+        }
+
+        return Ok(from.to_owned());
+    }
+
+    if dists.join(suite).is_dir() {
+        return Ok(suite.to_owned());
+    }
+
+    let mut suites = Vec::new();
+    for entry in dists.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            suites.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    suites.sort();
+    match suites.len() {
+        1 => Ok(suites.remove(0)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has the suites {:?}; select one with --suite", dists.display(), suites)
+        ))
+    }
+}
+
+impl ImportReport {
+    /// Sorts the imported files, and reports the suite as reached if any file is in its pool.
+    fn finish(&mut self, suite: &str) {
+        self.imported.sort();
+        if !self.imported.is_empty() || !self.existing.is_empty() {
+            self.suites.insert(suite.to_owned());
+        }
+    }
+}
+
+/// Hard-links the file into the pool when possible, and copies it otherwise.
+fn place(src: &Path, dst: &Path, report: &mut ImportReport) -> io::Result<()> {
+    if dst.exists() {
+        report.existing.push(dst.to_path_buf());
+        return Ok(());
+    }
+
+    info!("importing {} to {}", src.display(), dst.display());
+    fs::create_dir_all(dst.parent().unwrap())?;
+    if fs::hard_link(src, dst).is_err() {
+        fs::copy(src, dst)?;
+    }
+
+    report.imported.push(dst.to_path_buf());
+    Ok(())
+}
+
+/// Records each source of the config which is built on changelog changes as built at the version
+/// that was imported, so that it is not rebuilt until its changelog moves past that version.
+/// Sources which already have a record are left as they are.
+pub fn record_imported(config: &Config, pwd: &Path, report: &ImportReport) -> io::Result<Vec<String>> {
+    let mut recorded = Vec::new();
+    for source in config.source.iter().flat_map(|sources| sources.iter()) {
+        if source.build_on.as_ref().map_or(true, |rule| rule != "changelog") {
+            continue
+        }
+
+        let version = match report.sources.get(&source.name) {
+            Some(version) => version,
+            None => continue
+        };
+
+        let record = pwd.join(["record/", &config.archive, "/", &source.name].concat());
+        if record.exists() {
+            continue
+        }
+
+        fs::create_dir_all(record.parent().unwrap())?;
+        misc::write(&record, ["changelog\n", version.as_str()].concat())?;
+        recorded.push(source.name.clone());
+    }
+
+    Ok(recorded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::{DigestCache, ALL_DIGESTS};
+    use debian::{tarball, write_deb};
+    use misc;
+    use super::super::generate::dists;
+    use tempfile::tempdir;
+    use toml;
+
+    #[test]
+    fn foreign_tree_import() {
+        let dir = tempdir().unwrap();
+        let foreign = dir.path().join("foreign/pool");
+        let repo = dir.path().join("repo");
+
+        for file in &[
+            "main/f/foo/foo_1.0_amd64.deb",
+            "main/f/foo/foo_1.0.dsc",
+            "contrib/b/bar/bar_2.0_all.deb",
+            "non-free/n/nvidia/nvidia_390_amd64.deb",
+        ] {
+            let path = foreign.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            if file.ends_with(".deb") {
+                let name = file.rsplit('/').next().unwrap().split('_').next().unwrap();
+                let control = format!("Package: {0}\nVersion: 1.0\nSection: utils\nDescription: {0}\n", name);
+                let control = tarball(&[("control", control.as_bytes())]).unwrap();
+                write_deb(path, &control, &tarball(&[("./usr/bin/foo", &b"foo"[..])]).unwrap()).unwrap();
+            } else {
+                misc::write(path, b"").unwrap();
+            }
+        }
+
+        let mut mapping = ComponentMapping { suite: "bionic".into(), .. ComponentMapping::default() };
+        mapping.components.insert("main".into(), "main".into());
+        mapping.components.insert("contrib".into(), "proposed".into());
+
+        let report = import_tree(&foreign, &repo, mapping).unwrap();
+        let pool = repo.join("pool/bionic");
+        assert_eq!(report.imported, vec![
+            pool.join("main/binary-amd64/f/foo/foo_1.0_amd64.deb"),
+            pool.join("main/source/f/foo/foo_1.0.dsc"),
+            pool.join("proposed/binary-all/b/bar/bar_2.0_all.deb"),
+        ]);
+        assert_eq!(report.skipped, vec![foreign.join("non-free/n/nvidia/nvidia_390_amd64.deb")]);
+        assert_eq!(report.suites.iter().collect::<Vec<&String>>(), vec!["bionic"]);
+
+        // Once the suite is reindexed, its imported packages are published.
+        let config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "test"
+            label = "test"
+            email = "test@example.com"
+        "#).unwrap();
+
+        let dist = dir.path().join("dists/bionic");
+        let cache = DigestCache::new(BTreeMap::new(), ALL_DIGESTS);
+        dists(&config, dist.to_str().unwrap(), &pool, &["main".to_owned()], &cache).unwrap();
+        let packages = misc::read_to_string(dist.join("main/binary-amd64/Packages")).unwrap();
+        assert!(packages.contains("Package: foo\n"));
+        assert!(packages.contains("pool/bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb\n"));
+    }
+
+    #[test]
+    fn repo_import() {
+        let dir = tempdir().unwrap();
+        let foreign = dir.path().join("foreign");
+        let repo = dir.path().join("repo");
+        let package = foreign.join("pool/main/f/foo");
+        fs::create_dir_all(&package).unwrap();
+        fs::create_dir_all(foreign.join("dists/xenial")).unwrap();
+        for file in &["foo_1.0_amd64.deb", "foo_1.0.dsc", "bar_1.0_amd64.deb"] {
+            misc::write(package.join(file), file.as_bytes()).unwrap();
+        }
+
+        let sha256 = |file: &str| File::open(package.join(file)).and_then(hasher::<Sha256, File>).unwrap();
+        let binary = foreign.join("dists/bionic/main/binary-amd64");
+        let source = foreign.join("dists/bionic/main/source");
+        fs::create_dir_all(&binary).unwrap();
+        fs::create_dir_all(&source).unwrap();
+
+        misc::write(binary.join("Packages"), format!(
+            "Package: foo\nFilename: pool/main/f/foo/foo_1.0_amd64.deb\nSize: 17\nSHA256: {}\n\n\
+             Package: bar\nFilename: pool/main/f/foo/bar_1.0_amd64.deb\nSize: 17\nSHA256: {}\n",
+            sha256("foo_1.0_amd64.deb"),
+            sha256("foo_1.0_amd64.deb")
+        )).unwrap();
+
+        misc::write(source.join("Sources"), format!(
+            "Package: foo\nVersion: 1.0\nDirectory: pool/main/f/foo\nChecksums-Sha256:\n {} 11 foo_1.0.dsc\n",
+            sha256("foo_1.0.dsc")
+        )).unwrap();
+
+        // The suite must be selected when the dists hold several.
+        assert!(import_repo(&foreign, None, &repo, "cosmic").is_err());
+
+        let report = import_repo(&foreign, Some("bionic"), &repo, "cosmic").unwrap();
+        let pool = repo.join("pool/cosmic/main");
+        assert_eq!(report.imported, vec![
+            pool.join("binary-amd64/f/foo/foo_1.0_amd64.deb"),
+            pool.join("source/f/foo/foo_1.0.dsc"),
+        ]);
+        assert_eq!(report.skipped, vec![package.join("bar_1.0_amd64.deb")]);
+        assert_eq!(report.sources.get("foo").map(|x| x.as_str()), Some("1.0"));
+
+        let report = import_repo(&foreign, Some("bionic"), &repo, "cosmic").unwrap();
+        assert!(report.imported.is_empty() && report.existing.len() == 2);
+    }
+}
@@ -0,0 +1,412 @@
+use action;
+use command::Command;
+use config::Config;
+use cwd;
+use debian::{changes_checksums, is_upload_filename, parse_control, verify_upload_streaming, AcceptResult};
+use debian::{DEB_DEBUG_EXTENSION, DEB_EXTENSION, DEB_INSTALLER_EXTENSION, DEB_SOURCE_EXTENSIONS};
+use gpg;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::generate_release_files;
+use super::pool::{check_archive, mv_to_pool, KEEP_SOURCE};
+use super::queue::hold;
+use super::watch::{Inotify, SETTLE};
+
+/// Uploads which fail verification are moved here, within the incoming directory, along with a
+/// `.reason` file which describes why.
+const REJECTED: &str = "rejected";
+
+/// Accepted uploads are gathered here, within the incoming directory, before they are pooled.
+const STAGING: &str = ".staging";
+
+/// An upload whose signature and files have been verified.
+struct Upload {
+    /// The index of the config of the suite that the upload is for.
+    config: usize,
+    signer: String,
+    /// The name of the source, which a held upload is queued by.
+    source: String,
+    /// The contents of the `.changes` which were signed.
+    signed: String,
+    files: Vec<String>,
+}
+
+/// The reason that an upload was rejected, and the contents of its `.changes` which were
+/// signed, if its signature was valid.
+struct Rejection {
+    reason: String,
+    signed: Option<String>,
+}
+
+/// Processes the uploads of the incoming directory, as dput places them, and regenerates the
+/// dist files of each suite which accepted any of them.
+///
+/// The `.changes` of an upload must be signed by a key of the incoming keyring of the suite of
+/// its `Distribution`, and each file that it lists must match its checksum. The packages of an
+/// accepted upload are moved into the pool, unless the queue of the suite holds them, and a
+/// rejected upload is moved aside.
+pub fn process_incoming(configs: &[Config], directory: &Path) -> io::Result<()> {
+    let _cwd = cwd::lock();
+    let base = env::current_dir()?;
+    let directory = directory.canonicalize()?;
+
+    let mut changes = directory.read_dir()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().map_or(false, |ext| ext == "changes"))
+        .collect::<Vec<PathBuf>>();
+    changes.sort();
+
+    let mut accepted = BTreeSet::new();
+    for changes in changes {
+        let upload = match verify(configs, &base, &directory, &changes)? {
+            Ok(upload) => upload,
+            Err(rejection) => {
+                reject(&directory, &changes, rejection.signed.as_ref().map(|x| x.as_str()), &rejection.reason)?;
+                continue
+            }
+        };
+
+        let config = &configs[upload.config];
+        let signer = &upload.signer;
+        info!("accepting {} into {}, as signed by {}", changes.display(), config.archive, signer);
+        if let Err(why) = pool_upload(config, &directory, &changes, &upload.source, &upload.files) {
+            reject(&directory, &changes, Some(&upload.signed), &why.to_string())?;
+            continue
+        }
+
+        accepted.insert(upload.config);
+    }
+
+    for id in accepted {
+        env::set_current_dir(&base)?;
+        generate_release_files(&configs[id]).map_err(|why| io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to generate the dist files of {}: {}", configs[id].archive, why)
+        ))?;
+    }
+
+    env::set_current_dir(&base)
+}
+
+/// Watches the incoming directory, and processes it with `debrep incoming process` as each
+/// `.changes` arrives, until interrupted. As dput uploads the `.changes` last, an upload is only
+/// processed once each of its files has arrived.
+///
+/// Each run is given the arguments, which should include `--wait`, so that the project is only
+/// locked while uploads are being processed, and a run waits for any build to finish.
+pub fn watch_incoming(directory: &Path, args: &[String]) -> io::Result<()> {
+    let directory = &directory.canonicalize()?;
+    let mut inotify = Inotify::new()?;
+    inotify.watch_tree(directory)?;
+
+    let process = || -> io::Result<()> {
+        let mut command = Command::new(env::current_exe()?);
+        command.args(args).args(&["incoming", "process"]).arg(directory);
+        if let Err(why) = command.run() {
+            error!("failed to process the uploads of {}: {}", directory.display(), why);
+        }
+
+        Ok(())
+    };
+
+    info!("watching {} for uploads", directory.display());
+    process()?;
+    loop {
+        let mut changes = inotify.read(None)?;
+        loop {
+            let more = inotify.read(Some(SETTLE))?;
+            if more.is_empty() {
+                break
+            }
+
+            changes.extend(more);
+        }
+
+        // Rejected uploads are moved into a directory of their own, which is not processed.
+        let uploaded = changes.iter().any(|path| {
+            path.parent() == Some(directory) && path.extension().map_or(false, |ext| ext == "changes")
+        });
+
+        if uploaded {
+            process()?;
+        }
+    }
+}
+
+/// Verifies the signature of the `.changes`, and the files that it lists, returning the reason
+/// that the upload is rejected if either fails.
+///
+/// The upload must be signed by a key of the keyring of the suite that it is for. A key may be in
+/// the keyrings of several suites, so each keyring is tried until one is the suite's.
+fn verify(
+    configs: &[Config],
+    base: &Path,
+    directory: &Path,
+    changes: &Path
+) -> io::Result<Result<Upload, Rejection>> {
+    let mut keyrings = configs.iter()
+        .filter_map(|config| config.incoming.as_ref().map(|incoming| &incoming.keyring))
+        .collect::<Vec<&PathBuf>>();
+    keyrings.sort();
+    keyrings.dedup();
+
+    // The suite is chosen by the contents which were signed, rather than by the file as a whole.
+    // Keyrings are given to gpgv by their full path, as it would otherwise look in its home.
+    let mut rejection = None;
+    for keyring in keyrings {
+        let (signer, signed) = match gpg::verify_signed(changes, &base.join(keyring))? {
+            Some(verified) => verified,
+            None => continue
+        };
+
+        let control = parse_control(&signed);
+        let distribution = control.get("Distribution")
+            .and_then(|distribution| distribution.split_whitespace().next())
+            .unwrap_or("");
+
+        let config = configs.iter().position(|config| {
+            config.incoming.as_ref().map_or(false, |incoming| &incoming.keyring == keyring)
+                && accepts(config, distribution)
+        });
+
+        let config = match config {
+            Some(config) => config,
+            None => {
+                let reason = format!("{} is not a suite which accepts uploads signed by {}", distribution, signer);
+                rejection = Some(Rejection { reason, signed: Some(signed) });
+                continue
+            }
+        };
+
+        // The source may be followed by its version, where the binaries were versioned apart.
+        let source = control.get("Source")
+            .and_then(|source| source.split_whitespace().next())
+            .map(String::from);
+
+        let source = match source {
+            Some(source) => source,
+            None => {
+                let reason = "the .changes does not name its Source".into();
+                return Ok(Err(Rejection { reason, signed: Some(signed) }));
+            }
+        };
+
+        let files = upload_files(&signed, directory);
+        return Ok(match files {
+            Ok(files) => Ok(Upload { config, signer, source, signed, files }),
+            Err(reason) => Err(Rejection { reason, signed: Some(signed) })
+        });
+    }
+
+    Ok(Err(rejection.unwrap_or_else(|| Rejection {
+        reason: "the .changes is not signed by a key of any incoming keyring".into(),
+        signed: None
+    })))
+}
+
+/// Whether the suite of the config is the distribution of an upload.
+fn accepts(config: &Config, distribution: &str) -> bool {
+    config.archive == distribution
+        || config.suite.as_ref().map_or(false, |suite| suite == distribution)
+        || config.codename.as_ref().map_or(false, |codename| codename == distribution)
+}
+
+/// Verifies each file that the signed contents of the `.changes` list, within the directory,
+/// returning their names, or the reason that they were rejected.
+fn upload_files(signed: &str, directory: &Path) -> Result<Vec<String>, String> {
+    let mut names = changes_checksums(signed)
+        .map_err(|why| why.to_string())?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<String>>();
+    names.sort();
+
+    // Files are named by the uploader, so they must not lead out of the incoming directory.
+    let mut files = Vec::new();
+    for name in &names {
+        if !is_upload_filename(&name) {
+            return Err(format!("{} is not a valid filename", name));
+        }
+
+        let file = File::open(directory.join(name))
+            .map_err(|why| format!("{} was not uploaded: {}", name, why))?;
+        files.push((name.clone(), file));
+    }
+
+    match verify_upload_streaming(signed, files).map_err(|why| why.to_string())? {
+        AcceptResult::Accepted(files) => Ok(files),
+        AcceptResult::Rejected { file, reason } => Err(format!("{}: {}", file, reason)),
+    }
+}
+
+/// Moves the packages of an accepted upload into the pool, and removes the rest of the upload.
+fn pool_upload(
+    config: &Config,
+    directory: &Path,
+    changes: &Path,
+    source: &str,
+    files: &[String]
+) -> io::Result<()> {
+    if action::is_dry_run() {
+        action::report(format_args!("move the packages of {} into the pool", changes.display()));
+        return Ok(());
+    }
+
+    // Packages are checked as the pool would check them before any file of the upload is moved,
+    // so that a package which the pool refuses leaves the upload where it was uploaded.
+    let policy = config.pool_policy();
+    for name in files {
+        check_archive(&directory.join(name), &policy)?;
+    }
+
+    let stem = changes.file_stem().map_or_else(Default::default, |stem| stem.to_os_string());
+    let staging = directory.join(STAGING).join(stem);
+    action::create_dir_all(&staging)?;
+
+    for name in files {
+        let packaged = [DEB_EXTENSION, DEB_DEBUG_EXTENSION, DEB_INSTALLER_EXTENSION].iter()
+            .chain(DEB_SOURCE_EXTENSIONS)
+            .any(|ext| name.ends_with(ext));
+
+        if packaged {
+            action::rename(directory.join(name), staging.join(name))?;
+        } else {
+            action::remove_file(directory.join(name))?;
+        }
+    }
+
+    let component = config.incoming.as_ref().and_then(|x| x.component.clone());
+    let component = config.component(&component);
+    let pool = config.pool_root();
+    if !hold(config, &staging, component, KEEP_SOURCE, None, source)? {
+        mv_to_pool(&staging, &pool, &config.archive, component, KEEP_SOURCE, None, &policy)?;
+    }
+
+    action::remove_dir(&staging)?;
+    action::remove_file(changes)
+}
+
+/// Moves the `.changes` of the upload aside, with every file that its signed contents list which
+/// was uploaded, and records the reason that it was rejected beside it.
+///
+/// The files of an upload whose signature is not valid are left where they are, as anyone could
+/// have written its file list, such as to name the files of another upload.
+fn reject(directory: &Path, changes: &Path, signed: Option<&str>, reason: &str) -> io::Result<()> {
+    warn!("rejecting {}: {}", changes.display(), reason);
+    let rejected = directory.join(REJECTED);
+    action::create_dir_all(&rejected)?;
+
+    let filename = changes.file_name().map_or_else(Default::default, |name| name.to_os_string());
+    let stem = changes.file_stem().map_or_else(Default::default, |stem| stem.to_os_string());
+    let staging = directory.join(STAGING).join(&stem);
+
+    // Files which were listed are moved aside too, whether or not they were staged.
+    let listed = signed.map_or_else(Vec::new, |signed| changes_checksums(signed)
+        .map(|files| files.into_iter().map(|(name, _)| name).collect())
+        .unwrap_or_else(|_| Vec::new()));
+
+    for name in listed {
+        if !is_upload_filename(&name) {
+            continue
+        }
+
+        for path in &[directory.join(&name), staging.join(&name)] {
+            if path.is_file() {
+                action::rename(path, rejected.join(&name))?;
+            }
+        }
+    }
+
+    if staging.is_dir() {
+        action::remove_dir(&staging)?;
+    }
+
+    let mut reason_file = rejected.join(&stem);
+    reason_file.set_extension("reason");
+    action::rename(changes, rejected.join(filename))?;
+    action::write(reason_file, [reason, "\n"].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use debian::{tarball, write_deb};
+    use misc;
+    use tempfile::tempdir;
+    use toml;
+
+    #[test]
+    fn incoming_upload_files() {
+        let dir = tempdir().unwrap();
+        misc::write(dir.path().join("foo_1.0_amd64.deb"), &[0u8; 4]).unwrap();
+
+        // SHA256 of four zero bytes.
+        let zeroes = "df3f619804a92fdb4057192dc43dd748ea778adc52bc498ce80524c014b81119";
+        let changes = |files: &[&str]| files.iter().fold(
+            "Distribution: bionic\nChecksums-Sha256:\n".to_owned(),
+            |changes, file| changes + &format!(" {} 4 {}\n", zeroes, file)
+        );
+
+        let upload = |files: &[&str]| upload_files(&changes(files), dir.path());
+
+        assert_eq!(upload(&["foo_1.0_amd64.deb"]), Ok(vec!["foo_1.0_amd64.deb".into()]));
+        assert!(upload(&["foo_1.0.dsc"]).unwrap_err().starts_with("foo_1.0.dsc was not uploaded"));
+        assert_eq!(
+            upload(&["../foo_1.0_amd64.deb"]),
+            Err("../foo_1.0_amd64.deb is not a valid filename".into())
+        );
+
+        misc::write(dir.path().join("foo_1.0_amd64.deb"), &[1u8; 4]).unwrap();
+        let why = upload(&["foo_1.0_amd64.deb"]).unwrap_err();
+        assert!(why.starts_with("foo_1.0_amd64.deb: expected"));
+    }
+
+    #[test]
+    fn rejection_trusts_only_signed_lists() {
+        let dir = tempdir().unwrap();
+        let listing = "Checksums-Sha256:\n 00 4 foo_1.0_amd64.deb\n";
+        let (changes, deb) = (dir.path().join("foo_1.0_amd64.changes"), dir.path().join("foo_1.0_amd64.deb"));
+        let rejected = dir.path().join(REJECTED);
+
+        // Without a valid signature, the listing could name the files of another upload.
+        misc::write(&changes, listing).unwrap();
+        misc::write(&deb, b"").unwrap();
+        reject(dir.path(), &changes, None, "unsigned").unwrap();
+        assert!(deb.exists());
+        assert!(rejected.join("foo_1.0_amd64.changes").exists());
+        assert_eq!(misc::read_to_string(rejected.join("foo_1.0_amd64.reason")).unwrap(), "unsigned\n");
+
+        misc::write(&changes, listing).unwrap();
+        reject(dir.path(), &changes, Some(listing), "mismatched").unwrap();
+        assert!(!deb.exists());
+        assert!(rejected.join("foo_1.0_amd64.deb").exists());
+    }
+
+    #[test]
+    fn oversized_upload_stays_incoming() {
+        let dir = tempdir().unwrap();
+        let config: Config = toml::from_str(r#"
+archive = "bionic"
+version = "18.04"
+origin = "origin"
+label = "label"
+email = "info@example.com"
+max_package_bytes = 64
+"#).unwrap();
+
+        let deb = dir.path().join("foo_1.0_amd64.deb");
+        let changes = dir.path().join("foo_1.0_amd64.changes");
+        let control = tarball(&[("control", &b"Package: foo\nVersion: 1.0\nArchitecture: amd64\n"[..])]).unwrap();
+        write_deb(&deb, &control, &tarball(&[]).unwrap()).unwrap();
+        misc::write(&changes, b"").unwrap();
+
+        let why = pool_upload(&config, dir.path(), &changes, "foo", &["foo_1.0_amd64.deb".into()]).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+        assert!(deb.exists());
+        assert!(changes.exists());
+        assert!(!dir.path().join(STAGING).exists());
+    }
+}
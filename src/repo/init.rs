@@ -0,0 +1,114 @@
+use action;
+use std::io;
+use std::path::Path;
+
+const GITIGNORE: &str = "# Generated by debrep, and recreated or refetched as needed.
+/.debrep.lock
+/assets/cache/
+/build/
+/logs/
+/record/
+/repo/
+/snapshots/
+/sources/
+/tmp/
+";
+
+/// Creates a new debrep project at `root`, with a starter config for `suite`, the directories
+/// that the config's packages are given their files from, and a `.gitignore` for the
+/// directories which debrep generates.
+///
+/// An existing config for the suite is never overwritten, but a project may be given another
+/// suite by running this again.
+pub fn init(root: &Path, name: &str, suite: &str) -> io::Result<()> {
+    let config = root.join(["suites/", suite, ".toml"].concat());
+    if config.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("a config already exists at {}", config.display())
+        ));
+    }
+
+    let directories = [
+        "suites".to_owned(),
+        "keys".to_owned(),
+        "assets/share".to_owned(),
+        "assets/packages".to_owned(),
+        ["assets/replace/", suite].concat(),
+        ["debian/", suite].concat(),
+        ["patches/", suite].concat(),
+        ["metapackages/", suite].concat(),
+    ];
+
+    for directory in &directories {
+        action::create_dir_all(root.join(directory))?;
+    }
+
+    action::write(&config, starter_config(name, suite))?;
+
+    let gitignore = root.join(".gitignore");
+    if gitignore.exists() {
+        info!("keeping the existing {}", gitignore.display());
+    } else {
+        action::write(&gitignore, GITIGNORE)?;
+    }
+
+    info!("created the {} suite of {} at {}", suite, name, root.display());
+    Ok(())
+}
+
+fn starter_config(name: &str, suite: &str) -> String {
+    format!(r#"archive = "{suite}"
+version = "1.0"
+origin = "{name}"
+label = "{name}"
+email = "maintainer@example.com"
+architectures = ["amd64", "i386"]
+
+# Packages which are already in the deb format are downloaded into the pool.
+#
+# [[direct]]
+# name = "foo"
+# version = "1.0"
+#
+#     [[direct.urls]]
+#     url = "https://example.com/${{name}}_${{version}}_amd64.deb"
+#     sha256 = "<digest of the file>"
+
+# Sources are built from their upstream tarball, git repository, or dsc, with the packaging
+# within debian/{suite}/<name>/ overlaid onto them.
+#
+# [[source]]
+# name = "bar"
+# version = "1.0"
+# location = {{ git = "https://example.com/bar.git", tag = "1.0" }}
+"#, name = name, suite = suite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config;
+    use misc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn project_scaffolding() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("foo");
+        init(&root, "foo", "bionic").unwrap();
+
+        let config = root.join("suites/bionic.toml");
+        assert_eq!(config::check(&config, false).unwrap(), Vec::new());
+        assert_eq!(config::parse(config.clone()).unwrap().archive, "bionic");
+        assert!(root.join("debian/bionic").is_dir());
+        assert!(misc::read_to_string(root.join(".gitignore")).unwrap().contains("/build/"));
+
+        // A config is not overwritten, but another suite may be added to the project.
+        misc::write(&config, "archive = \"edited\"").unwrap();
+        assert!(init(&root, "foo", "bionic").is_err());
+        assert_eq!(misc::read_to_string(&config).unwrap(), "archive = \"edited\"");
+        init(&root, "foo", "cosmic").unwrap();
+        assert!(root.join("suites/cosmic.toml").exists());
+    }
+}
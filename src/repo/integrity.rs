@@ -0,0 +1,176 @@
+use checksum::hasher;
+use gpg;
+use misc;
+use sha2::Sha256;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use super::sign::Signer;
+use walkdir::WalkDir;
+
+pub const REPO_STATE: &str = ".repo-state";
+
+/// Records the SHA256 digest of every file within the suite's dist directory at publish time.
+///
+/// If a signer is given, the marker will be clearsigned by the signing backend of the suite, so
+/// that the marker itself can't be regenerated by someone that has edited the indices out-of-band.
+pub(crate) fn write_repo_state(repo: &Path, suite: &str, signer: Option<&Signer>) -> io::Result<()> {
+    let base = repo.join("dists").join(suite);
+    let marker = base.join(REPO_STATE);
+    info!("recording repository state at {}", marker.display());
+
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(&base).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+        let entry = entry.map_err(|why| io::Error::new(io::ErrorKind::Other, why))?;
+        if !entry.file_type().is_file() || entry.file_name() == REPO_STATE {
+            continue
+        }
+
+        let path = entry.path();
+        let digest = File::open(path).and_then(hasher::<Sha256, File>)?;
+        let relative = path.strip_prefix(&base).unwrap();
+        entries.push(format!("{} {}", digest, relative.display()));
+    }
+
+    {
+        let mut file = File::create(&marker)?;
+        for entry in entries {
+            writeln!(file, "{}", entry)?;
+        }
+    }
+
+    if let Some(signer) = signer {
+        let unsigned = base.join([REPO_STATE, ".unsigned"].concat());
+        fs::rename(&marker, &unsigned)?;
+        signer.in_release(&unsigned, &marker)?;
+        fs::remove_file(&unsigned)?;
+    }
+
+    Ok(())
+}
+
+/// Re-hashes each file recorded by the suite's `.repo-state` marker, and returns the files which
+/// have been modified or removed since the suite was last published.
+///
+/// Anyone who can edit the indices could also rewrite an unsigned marker, so the marker must be
+/// signed by a key of the given keyring, and only the contents which that signature covers are
+/// compared.
+pub fn detect_tampering(repo: &Path, suite: &str, keyring: &Path) -> io::Result<Vec<PathBuf>> {
+    let base = repo.join("dists").join(suite);
+    let marker = base.join(REPO_STATE);
+    let (signer, state) = gpg::verify_signed(&marker, keyring)?.ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is not signed by a key of {}", marker.display(), keyring.display())
+    ))?;
+
+    info!("{} was signed by {}", marker.display(), signer);
+
+    let mut tampered = Vec::new();
+    for record in state.lines() {
+        let mut fields = record.splitn(2, ' ');
+        let (digest, path) = match (fields.next(), fields.next()) {
+            (Some(digest), Some(path)) => (digest, base.join(path)),
+            _ => continue
+        };
+
+        let matches = path.is_file()
+            && File::open(&path).and_then(hasher::<Sha256, File>)? == digest;
+
+        if !matches {
+            warn!("{} has changed since it was last published", path.display());
+            tampered.push(path);
+        }
+    }
+
+    Ok(tampered)
+}
+
+/// Checks that every index listed in the SHA256 section of a dist `Release` file exists, and
+/// matches its recorded size and digest. The files which do not match are returned.
+pub fn audit_release(dist: &Path) -> io::Result<Vec<PathBuf>> {
+    let release = misc::read_to_string(dist.join("Release"))?;
+
+    let mut mismatched = Vec::new();
+    let records = release.lines()
+        .skip_while(|&line| line != "SHA256:")
+        .skip(1)
+        .take_while(|line| line.starts_with(' '));
+
+    for record in records {
+        let mut fields = record.split_whitespace();
+        let (digest, size, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(digest), Some(size), Some(path)) => (digest, size, dist.join(path)),
+            _ => continue
+        };
+
+        let matches = path.is_file()
+            && path.metadata()?.len().to_string() == size
+            && File::open(&path).and_then(hasher::<Sha256, File>)? == digest;
+
+        if !matches {
+            warn!("{} does not match its entry in the Release file", path.display());
+            mismatched.push(path);
+        }
+    }
+
+    Ok(mismatched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use command::Command;
+    use tempfile::tempdir;
+
+    #[test]
+    fn tampering_detection() {
+        let dir = tempdir().unwrap();
+        let binary = dir.path().join("dists/bionic/main/binary-amd64");
+        fs::create_dir_all(&binary).unwrap();
+        misc::write(binary.join("Packages"), b"Package: foo\n").unwrap();
+        misc::write(dir.path().join("dists/bionic/Release"), b"Suite: bionic\n").unwrap();
+
+        // An unsigned marker could have been rewritten along with the indices.
+        let keyring = dir.path().join("keyring.gpg");
+        write_repo_state(dir.path(), "bionic", None).unwrap();
+        misc::write(&keyring, b"").unwrap();
+        assert!(detect_tampering(dir.path(), "bionic", &keyring).is_err());
+
+        // The marker is signed by a throwaway key, where gpg is able to generate one.
+        let home = tempdir().unwrap();
+        let gpg = |args: &[&str]| Command::new("gpg").arg("--homedir").arg(home.path()).arg("--batch").args(args).run();
+        let marker = dir.path().join("dists/bionic").join(REPO_STATE);
+        let unsigned = dir.path().join("unsigned");
+        fs::rename(&marker, &unsigned).unwrap();
+        let generated = gpg(&["--passphrase", "", "--quick-gen-key", "test@example.com", "future-default", "default", "never"]);
+        if generated.is_err() {
+            return;
+        }
+
+        gpg(&["--yes", "--clearsign", "-o", marker.to_str().unwrap(), unsigned.to_str().unwrap()]).unwrap();
+        gpg(&["--yes", "--export", "-o", keyring.to_str().unwrap()]).unwrap();
+        assert!(detect_tampering(dir.path(), "bionic", &keyring).unwrap().is_empty());
+
+        misc::write(binary.join("Packages"), b"Package: evil\n").unwrap();
+        assert_eq!(
+            detect_tampering(dir.path(), "bionic", &keyring).unwrap(),
+            vec![binary.join("Packages")]
+        );
+    }
+
+    #[test]
+    fn release_audit() {
+        let dir = tempdir().unwrap();
+        let binary = dir.path().join("main/binary-amd64");
+        fs::create_dir_all(&binary).unwrap();
+        misc::write(binary.join("Packages"), b"Package: foo\n").unwrap();
+        let digest = File::open(binary.join("Packages")).and_then(hasher::<Sha256, File>).unwrap();
+        misc::write(
+            dir.path().join("Release"),
+            format!("Suite: bionic\nSHA256:\n {} 13 main/binary-amd64/Packages\n {} 0 main/source/Sources\n", digest, digest)
+        ).unwrap();
+
+        assert_eq!(audit_release(dir.path()).unwrap(), vec![dir.path().join("main/source/Sources")]);
+    }
+
+}
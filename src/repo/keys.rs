@@ -0,0 +1,90 @@
+use action;
+use config::Config;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::sign::{self, Signer};
+
+/// The keys which sign the suites of a repo, which are each listed once.
+#[derive(Debug, Default, PartialEq)]
+struct RepoKeys<'a> {
+    gpg: Vec<&'a str>,
+    native: Vec<&'a Path>,
+}
+
+/// Exports the public keys which sign the suites of each repo into its root, as a binary
+/// `<name>.gpg` keyring and an armored `<name>.asc` keyring, returning the keyrings written.
+pub fn export_keys(configs: &[Config], name: &str) -> io::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (repo, keys) in repo_keys(configs)? {
+        action::create_dir_all(repo)?;
+        for &(extension, armored) in &[("gpg", false), ("asc", true)] {
+            let keyring = repo.join([name, ".", extension].concat());
+            action::write(&keyring, sign::export_keys(&keys.gpg, &keys.native, armored)?)?;
+            written.push(keyring);
+        }
+    }
+
+    Ok(written)
+}
+
+/// Collects the keys which sign the suites of each repo, skipping the suites that are unsigned.
+fn repo_keys(configs: &[Config]) -> io::Result<BTreeMap<&Path, RepoKeys>> {
+    let mut repos = BTreeMap::new();
+    for config in configs.iter().filter(|config| !config.unsigned) {
+        let keys = repos.entry(config.repo_path()).or_insert_with(RepoKeys::default);
+        match Signer::new(config)? {
+            Signer::Gpg(key) => if !keys.gpg.contains(&key) {
+                keys.gpg.push(key);
+            },
+            Signer::Native(key) => if !keys.native.contains(&key) {
+                keys.native.push(key);
+            }
+        }
+    }
+
+    Ok(repos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    #[test]
+    fn keys_of_suites() {
+        let suite = |extra: &str| -> Config {
+            toml::from_str(&format!(r#"
+                archive = "bionic"
+                version = "18.04"
+                origin = "test"
+                label = "test"
+                email = "test@example.com"
+                {}
+
+                [signing.keys]
+                staging = "STAGINGKEY"
+                production = "PRODUCTIONKEY"
+            "#, extra)).unwrap()
+        };
+
+        let configs = vec![
+            suite("pocket = \"staging\""),
+            suite("signing_key = \"production\""),
+            suite("pocket = \"staging\"\nrepo_path = \"other\""),
+            suite("signing_key = \"0xFEEDBEEF\"\npocket = \"staging\""),
+            suite("pocket = \"production\"\nunsigned = true"),
+            suite(""),
+        ];
+
+        assert_eq!(configs[5].signing_key(), "test@example.com");
+
+        let repos = repo_keys(&configs).unwrap();
+        assert_eq!(repos[Path::new("repo")], RepoKeys {
+            gpg: vec!["STAGINGKEY", "PRODUCTIONKEY", "0xFEEDBEEF", "test@example.com"],
+            native: Vec::new(),
+        });
+
+        assert_eq!(repos[Path::new("other")].gpg, vec!["STAGINGKEY"]);
+    }
+}
@@ -0,0 +1,382 @@
+use action;
+use config::Config;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::pool::{pool_destination, same_contents};
+use super::store::Store;
+use walkdir::WalkDir;
+
+/// The number of days that the links to the previous layout of a suite are kept for, unless
+/// `layout_grace_days` is configured.
+pub const DEFAULT_GRACE_DAYS: u64 = 30;
+
+/// The archive and components that the pool of a suite was last generated with.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+pub struct PoolLayout {
+    pub archive: String,
+    pub components: Vec<String>,
+    /// The component of each source, by its name.
+    #[serde(default)]
+    pub sources: BTreeMap<String, String>,
+}
+
+impl PoolLayout {
+    pub fn of(config: &Config) -> PoolLayout {
+        PoolLayout {
+            archive: config.archive.clone(),
+            components: config.components().into_iter().map(String::from).collect(),
+            sources: config.source.iter()
+                .flat_map(|sources| sources.iter())
+                .map(|source| {
+                    let component = source.component.as_ref().unwrap_or(&config.default_component);
+                    (source.name.clone(), component.clone())
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The layouts are keyed by the path of their config, so that a renamed archive is still
+/// recognized. The suites of a config which defines several are keyed by their archive instead.
+fn layout_key(config: &Config) -> String {
+    if config.shared {
+        ["suite:", &config.archive].concat()
+    } else {
+        config.path.display().to_string()
+    }
+}
+
+/// Migrates the pool and dists of the suite within the repo to its configured layout, if its
+/// archive or components have changed since the store last recorded them.
+///
+/// The pool and dists of a renamed archive are moved to the new archive, or merged into it where
+/// this run has already pooled files under the new archive, and the files of a component which
+/// is no longer configured, or of a source whose component has changed, are moved into the
+/// component which now holds them. A link is left at each path which was moved, so that the URLs
+/// of the previous indices still resolve, until the grace period ends.
+///
+/// The layout is only recorded once every file was migrated, so that a file which was left in
+/// place, as its destination already exists, is migrated again by the next run.
+pub fn migrate_layout(repo: &Path, config: &Config, store: &mut Store, now: u64) -> io::Result<()> {
+    expire_links(repo, store, now)?;
+
+    let key = layout_key(config);
+    let current = PoolLayout::of(config);
+    let previous = match store.layouts.get(&key).cloned() {
+        Some(ref previous) if *previous == current => return Ok(()),
+        Some(previous) => previous,
+        None => {
+            store.layouts.insert(key, current);
+            return Ok(());
+        }
+    };
+
+    let mut links = Vec::new();
+    let mut complete = true;
+    if previous.archive != current.archive {
+        for directory in &["pool", "dists"] {
+            let old = repo.join(directory).join(&previous.archive);
+            let new = repo.join(directory).join(&current.archive);
+            if !old.symlink_metadata().map(|meta| meta.is_dir()).unwrap_or(false) {
+                continue
+            }
+
+            if new.exists() {
+                info!("merging {} into {}", old.display(), new.display());
+                if !merge_tree(&old, &new)? {
+                    complete = false;
+                    continue
+                }
+            } else {
+                info!("migrating {} to {}", old.display(), new.display());
+                action::rename(&old, &new)?;
+            }
+
+            action::symlink(&current.archive, &old)?;
+            links.push(old);
+        }
+    }
+
+    let (moved, migrated) = migrate_components(repo, config, &previous.archive, &current, store)?;
+    links.extend(moved);
+    complete = complete && migrated;
+
+    let expires = now + config.layout_grace_days.unwrap_or(DEFAULT_GRACE_DAYS) * 86_400;
+    for link in links {
+        let link = link.strip_prefix(repo).unwrap_or(&link).display().to_string();
+        store.transitional.insert(link, expires);
+    }
+
+    if complete {
+        store.layouts.insert(key, current);
+    } else {
+        warn!("the layout of {} was only partly migrated, and is migrated again by the next run", config.archive);
+    }
+
+    Ok(())
+}
+
+/// Moves each file of `old` into the same path within `new`, returning whether every file was
+/// moved, in which case `old` is removed. A file which is already in `new` with the same contents
+/// was migrated before, and is only removed from `old`.
+fn merge_tree(old: &Path, new: &Path) -> io::Result<bool> {
+    let files = WalkDir::new(old)
+        .min_depth(1)
+        .into_iter()
+        .flat_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    let mut merged = true;
+    for path in files {
+        let destination = new.join(path.strip_prefix(old).unwrap());
+        if destination.symlink_metadata().is_ok() {
+            if path.is_file() && destination.is_file() && same_contents(&path, &destination)? {
+                action::remove_file(&path)?;
+            } else {
+                warn!("{} was not migrated, as {} already exists", path.display(), destination.display());
+                merged = false;
+            }
+            continue
+        }
+
+        action::create_dir_all(destination.parent().unwrap())?;
+        action::rename(&path, &destination)?;
+    }
+
+    if merged {
+        action::remove_dir_all(old)?;
+    }
+
+    Ok(merged)
+}
+
+/// Moves each file of the suite's pool whose component differs from the one that its source is
+/// now configured in, or whose component is no longer configured, returning the links which are
+/// left in their place, and whether every such file was moved. Files of unknown sources within a
+/// removed component are moved to the default component.
+///
+/// The source of a file that was built before the archive was renamed is recorded under the
+/// `previous` archive.
+///
+/// Only the `source`, `binary-*`, and `debian-installer` directories of each component are
+/// searched, as debug symbols are routed into their subcomponents afterwards.
+fn migrate_components(
+    repo: &Path,
+    config: &Config,
+    previous: &str,
+    layout: &PoolLayout,
+    store: &Store
+) -> io::Result<(Vec<PathBuf>, bool)> {
+    let pool_root = repo.join("pool");
+    let pool = pool_root.join(&config.archive);
+    if !pool.is_dir() {
+        return Ok((Vec::new(), true));
+    }
+
+    let files = WalkDir::new(&pool)
+        .min_depth(3)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    let mut links = Vec::new();
+    let mut migrated = true;
+    for path in files {
+        let (component, directory) = {
+            let mut directories = path.strip_prefix(&pool).unwrap().iter().filter_map(|x| x.to_str());
+            match (directories.next(), directories.next()) {
+                (Some(component), Some(directory)) => (component.to_owned(), directory.to_owned()),
+                _ => continue
+            }
+        };
+
+        if directory != "source" && directory != "debian-installer" && !directory.starts_with("binary-") {
+            continue
+        }
+
+        let filename = path.file_name().and_then(|x| x.to_str()).unwrap().to_owned();
+        let source = store.provenance(&config.archive, &filename)
+            .or_else(|| store.provenance(previous, &filename))
+            .and_then(|(source, _)| layout.sources.get(source));
+
+        let wanted = match source {
+            Some(wanted) => wanted.as_str(),
+            None if layout.components.contains(&component) => continue,
+            None => config.default_component.as_str()
+        };
+
+        if wanted == component {
+            continue
+        }
+
+        let destination = pool_destination(&pool_root, &config.archive, wanted, &filename)?.join(&filename);
+        if destination.exists() {
+            warn!("{} was not migrated, as {} already exists", path.display(), destination.display());
+            migrated = false;
+            continue
+        }
+
+        info!("migrating {} to {}", path.display(), destination.display());
+        action::create_dir_all(destination.parent().unwrap())?;
+        action::rename(&path, &destination)?;
+        action::symlink(relative_target(&path, &destination), &path)?;
+        links.push(path);
+    }
+
+    Ok((links, migrated))
+}
+
+/// Removes each link of a previous layout whose grace period has ended.
+fn expire_links(repo: &Path, store: &mut Store, now: u64) -> io::Result<()> {
+    let expired = store.transitional.iter()
+        .filter(|&(_, &expires)| expires <= now)
+        .map(|(link, _)| link.clone())
+        .collect::<Vec<String>>();
+
+    for link in expired {
+        let path = repo.join(&link);
+        if path.symlink_metadata().map(|meta| meta.file_type().is_symlink()).unwrap_or(false) {
+            info!("removing {}, as its grace period has ended", path.display());
+            action::remove_file(&path)?;
+        }
+
+        store.transitional.remove(&link);
+    }
+
+    Ok(())
+}
+
+/// Whether an entry of the pool is a link to another file of the pool, such as those which are
+/// left behind by a migration, which is not indexed a second time.
+pub fn is_pool_link(path: &Path, pool_root: &Path) -> bool {
+    let is_link = path.symlink_metadata().map(|meta| meta.file_type().is_symlink()).unwrap_or(false);
+    is_link && match (fs::canonicalize(path), fs::canonicalize(pool_root)) {
+        (Ok(target), Ok(pool_root)) => target.starts_with(pool_root),
+        _ => false
+    }
+}
+
+/// The target of a link at `link` to `target`, relative to the directory of the link, so that
+/// the link resolves wherever the repo is published.
+fn relative_target(link: &Path, target: &Path) -> PathBuf {
+    let parent = link.parent().unwrap_or(link);
+    let common = parent.components().zip(target.components()).take_while(|&(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..parent.components().count() {
+        relative.push("..");
+    }
+
+    relative.extend(target.components().skip(common));
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use tempfile::tempdir;
+    use toml;
+
+    #[test]
+    fn layout_migration() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        let mut config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "debrep"
+            label = "debrep"
+            email = "debrep@example.com"
+            architectures = ["amd64"]
+        "#).unwrap();
+
+        let mut store = Store::default();
+        migrate_layout(repo, &config, &mut store, 0).unwrap();
+        assert_eq!(store.layouts[""], PoolLayout::of(&config));
+
+        let old = repo.join("pool/bionic/extra/binary-amd64/f/foo/foo_1.0_amd64.deb");
+        let dsc = repo.join("pool/bionic/extra/source/b/bar/bar_1.0.dsc");
+        for file in &[&old, &dsc] {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            misc::write(file, b"").unwrap();
+        }
+
+        fs::create_dir_all(repo.join("dists/bionic")).unwrap();
+
+        // The archive is renamed, and the `extra` component is no longer configured.
+        config.archive = "cosmic".into();
+        config.default_component = "universe".into();
+        migrate_layout(repo, &config, &mut store, 100).unwrap();
+
+        let moved = repo.join("pool/cosmic/universe/binary-amd64/f/foo/foo_1.0_amd64.deb");
+        assert!(moved.symlink_metadata().unwrap().is_file());
+        assert!(repo.join("pool/cosmic/universe/source/b/bar/bar_1.0.dsc").exists());
+        assert!(repo.join("pool/bionic").symlink_metadata().unwrap().file_type().is_symlink());
+        assert!(repo.join("dists/bionic").symlink_metadata().unwrap().file_type().is_symlink());
+
+        let link = repo.join("pool/cosmic/extra/binary-amd64/f/foo/foo_1.0_amd64.deb");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("../../../../universe/binary-amd64/f/foo/foo_1.0_amd64.deb"));
+        assert!(fs::metadata(repo.join("pool/bionic/extra/binary-amd64/f/foo/foo_1.0_amd64.deb")).is_ok());
+        assert!(is_pool_link(&link, &repo.join("pool")) && !is_pool_link(&moved, &repo.join("pool")));
+
+        let expires = 100 + DEFAULT_GRACE_DAYS * 86_400;
+        assert_eq!(store.transitional.len(), 4);
+        assert_eq!(store.transitional["pool/bionic"], expires);
+
+        migrate_layout(repo, &config, &mut store, expires).unwrap();
+        assert!(store.transitional.is_empty());
+        assert!(repo.join("pool/bionic").symlink_metadata().is_err());
+        assert!(link.symlink_metadata().is_err() && moved.exists());
+    }
+
+    #[test]
+    fn layout_migration_into_pooled_archive() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        let mut config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "debrep"
+            label = "debrep"
+            email = "debrep@example.com"
+            architectures = ["amd64"]
+        "#).unwrap();
+
+        let mut store = Store::default();
+        migrate_layout(repo, &config, &mut store, 0).unwrap();
+
+        let bar = "main/binary-amd64/b/bar/bar_1.0_amd64.deb";
+        let foo = "main/binary-amd64/f/foo/foo_1.0_amd64.deb";
+        for &(ref file, contents) in &[(["pool/bionic/", bar].concat(), "bar"), (["pool/bionic/", foo].concat(), "old")] {
+            let file = repo.join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            misc::write(file, contents).unwrap();
+        }
+
+        // The run has already pooled a rebuilt foo under the renamed archive.
+        let pooled = repo.join("pool/cosmic").join(foo);
+        fs::create_dir_all(pooled.parent().unwrap()).unwrap();
+        misc::write(&pooled, "new").unwrap();
+
+        config.archive = "cosmic".into();
+        migrate_layout(repo, &config, &mut store, 100).unwrap();
+        assert!(repo.join("pool/cosmic").join(bar).exists());
+        assert_eq!(misc::read(&pooled).unwrap(), b"new");
+        assert!(repo.join("pool/bionic").join(foo).exists());
+        assert_eq!(store.layouts[""].archive, "bionic");
+
+        // Once the conflicting file is resolved, the next run completes the migration.
+        fs::remove_file(repo.join("pool/bionic").join(foo)).unwrap();
+        migrate_layout(repo, &config, &mut store, 200).unwrap();
+        assert!(repo.join("pool/bionic").symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(store.layouts[""], PoolLayout::of(&config));
+    }
+}
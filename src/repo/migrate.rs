@@ -1,10 +1,14 @@
+use action;
 use config::Config;
+use cwd;
 use misc::{is_deb, INCLUDE_DDEB, INCLUDE_SRCS};
+use std::env;
 use std::io::{self, Error};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::os::unix::fs::MetadataExt;
 use super::{generate_release_files, ReleaseError};
+use super::pool::same_contents;
 use walkdir::WalkDir;
 
 #[derive(Debug, Fail)]
@@ -12,7 +16,13 @@ pub enum MigrationError {
     #[fail(display = "failed to migrate {:?} to {:?}: {}", src, dst, why)]
     Move { src: PathBuf, dst: PathBuf, why: Error },
     #[fail(display = "dist release file generation failed: {}", why)]
-    DistRelease { why: ReleaseError }
+    DistRelease { why: ReleaseError },
+    #[fail(display = "failed to read the components of {:?}: {}", pool, why)]
+    Components { pool: PathBuf, why: Error },
+    #[fail(display = "failed to return to the working directory: {}", why)]
+    WorkingDirectory { why: Error },
+    #[fail(display = "{} is not in the pool of {}", package, suite)]
+    NotPooled { package: String, suite: String },
 }
 
 impl From<ReleaseError> for MigrationError {
@@ -21,61 +31,201 @@ impl From<ReleaseError> for MigrationError {
     }
 }
 
-pub fn migrate(config: &Config, packages: &[&str], from_component: &str, to_component: &str) -> Result<(), MigrationError> {
+/// Moves packages from one component of a suite to another. If no packages are given, every
+/// package is moved.
+pub fn migrate(config: &Config, packages: Option<&[&str]>, from_component: &str, to_component: &str) -> Result<(), MigrationError> {
+    let _cwd = cwd::lock();
     info!("migrating {:?} from {} to {}", packages, from_component, to_component);
-    inner_migrate(config, packages, from_component, to_component)?;
+    let pool = config.pool_path();
+    let src_pool = pool.join(from_component);
+    let dst_pool = pool.join(to_component);
+    inner_migrate(&src_pool, &dst_pool, packages, false)?;
     generate_release_files(&config)?;
     Ok(())
 }
 
-fn inner_migrate(config: &Config, packages: &[&str], from_component: &str, to_component: &str) -> Result<(), MigrationError> {
-    let pool = ["repo/pool/", &config.archive, "/"].concat();
-    let src_pool = PathBuf::from([&pool, from_component, "/"].concat());
-    let dst_pool = PathBuf::from([&pool, to_component, "/"].concat());
+/// Promotes packages from the pool of one suite into the pool of another, such as from a staging
+/// pocket to its release pocket, keeping each package within the same component. The packages
+/// are copied, rather than moved, if `copy` is set. The dist files of both suites are then
+/// regenerated and signed.
+pub fn migrate_suite(from: &Config, to: &Config, packages: Option<&[&str]>, copy: bool) -> Result<(), MigrationError> {
+    let _cwd = cwd::lock();
+    info!("migrating {:?} from {} to {}", packages, from.archive, to.archive);
+    let src = from.pool_path();
+    let dst = to.pool_path();
 
-    packages.into_iter().map(|package| {
-        let files = WalkDir::new(&src_pool)
-            .min_depth(1)
+    let components = fs::read_dir(&src)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<Vec<_>>>())
+        .map_err(|why| MigrationError::Components { pool: src.clone(), why })?;
+
+    for component in components {
+        inner_migrate(&src.join(&component), &dst.join(&component), packages, copy)?;
+    }
+
+    // The generation of release files changes into the repo directory.
+    let pwd = env::current_dir().map_err(|why| MigrationError::WorkingDirectory { why })?;
+    for config in &[to, from] {
+        generate_release_files(config)?;
+        env::set_current_dir(&pwd).map_err(|why| MigrationError::WorkingDirectory { why })?;
+    }
+
+    Ok(())
+}
+
+/// Copies packages from the pool of one suite into the pool of another, without rebuilding them,
+/// and regenerates and signs the dist files of the suite that they are copied to. The packages
+/// are hard-linked where both pools are on the same filesystem.
+pub fn copy_packages(from: &Config, to: &Config, packages: &[&str]) -> Result<(), MigrationError> {
+    let _cwd = cwd::lock();
+    info!("copying {:?} from {} to {}", packages, from.archive, to.archive);
+    let src = from.pool_path();
+    let dst = to.pool_path();
+
+    // Each package is found at `<component>/binary-<arch>/<bucket>/<package>`.
+    for &package in packages {
+        let pooled = WalkDir::new(&src)
+            .min_depth(4)
             .max_depth(4)
             .into_iter()
-            .filter_entry(|e| match e.depth() {
-                1 | 2 => true,
-                3 => &e.file_name() == package,
-                4 => is_deb(e, INCLUDE_DDEB | INCLUDE_SRCS),
-                _ => false
-            })
-            .flat_map(|e| e.ok())
-            .filter(|e| e.depth() == 4);
-
-        files.into_iter().map(|file| {
-            let src = file.path();
-            let dst = dst_pool.join(file.path().strip_prefix(&src_pool).unwrap());
-
-            migrate_file(&src, &dst).map_err(|why| MigrationError::Move {
-                src: src.to_path_buf(),
-                dst,
-                why
-            })
-        }).collect::<Result<(), MigrationError>>()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_type().is_dir() && entry.file_name() == package);
+
+        if !pooled {
+            return Err(MigrationError::NotPooled {
+                package: package.to_owned(),
+                suite: from.archive.clone()
+            });
+        }
+    }
+
+    let components = fs::read_dir(&src)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<Vec<_>>>())
+        .map_err(|why| MigrationError::Components { pool: src.clone(), why })?;
+
+    for component in components {
+        inner_migrate(&src.join(&component), &dst.join(&component), Some(packages), true)?;
+    }
+
+    // The generation of release files changes into the repo directory.
+    let pwd = env::current_dir().map_err(|why| MigrationError::WorkingDirectory { why })?;
+    generate_release_files(to)?;
+    env::set_current_dir(&pwd).map_err(|why| MigrationError::WorkingDirectory { why })
+}
+
+fn inner_migrate(src_pool: &Path, dst_pool: &Path, packages: Option<&[&str]>, copy: bool) -> Result<(), MigrationError> {
+    let files = WalkDir::new(src_pool)
+        .min_depth(1)
+        .max_depth(4)
+        .into_iter()
+        .filter_entry(|e| match e.depth() {
+            1 | 2 => true,
+            3 => packages.map_or(true, |packages| packages.iter().any(|package| &e.file_name() == package)),
+            4 => is_deb(e, INCLUDE_DDEB | INCLUDE_SRCS),
+            _ => false
+        })
+        .flat_map(|e| e.ok())
+        .filter(|e| e.depth() == 4);
+
+    files.into_iter().map(|file| {
+        let src = file.path();
+        let dst = dst_pool.join(file.path().strip_prefix(src_pool).unwrap());
+
+        migrate_file(&src, &dst, copy).map_err(|why| MigrationError::Move {
+            src: src.to_path_buf(),
+            dst,
+            why
+        })
     }).collect()
 }
 
-fn migrate_file(src_path: &Path, dst_path: &Path) -> io::Result<()> {
+fn migrate_file(src_path: &Path, dst_path: &Path, copy: bool) -> io::Result<()> {
     if let Some(dst_parent) = dst_path.parent() {
         info!("migrating {} to {}", src_path.display(), dst_path.display());
         if !dst_parent.exists() {
-            fs::create_dir_all(&dst_parent)?;
+            action::create_dir_all(&dst_parent)?;
+        }
+
+        // A published file must not be replaced by different contents, as clients which have
+        // downloaded it would fail to verify the new one, just as when it is pooled.
+        if dst_path.exists() {
+            if !same_contents(src_path, dst_path)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} is already published, with different contents than {}",
+                        dst_path.display(),
+                        src_path.display()
+                    )
+                ));
+            }
+
+            info!("{} is already in the destination", dst_path.display());
+            if !copy {
+                action::remove_file(src_path)?;
+            }
+            return Ok(());
         }
 
         let src_metadata = fs::metadata(src_path)?;
         let dst_metadata = fs::metadata(&dst_parent)?;
         if src_metadata.dev() == dst_metadata.dev() {
-            fs::rename(src_path, dst_path)?;
+            if copy {
+                action::hard_link(src_path, dst_path)?;
+            } else {
+                action::rename(src_path, dst_path)?;
+            }
         } else {
-            fs::copy(src_path, dst_path)?;
-            fs::remove_file(src_path)?;
+            action::copy(src_path, dst_path)?;
+            if !copy {
+                action::remove_file(src_path)?;
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn migrate_between_pools() {
+        let dir = tempdir().unwrap();
+        let staging = dir.path().join("bionic-staging/main");
+        let release = dir.path().join("bionic/main");
+        for package in &["binary-amd64/f/foo", "binary-amd64/b/bar"] {
+            let path = staging.join(package);
+            fs::create_dir_all(&path).unwrap();
+            misc::write(path.join([&package[15..], "_1.0_amd64.deb"].concat()), "").unwrap();
+        }
+
+        inner_migrate(&staging, &release, Some(&["foo"]), true).unwrap();
+        assert!(staging.join("binary-amd64/f/foo/foo_1.0_amd64.deb").exists());
+        assert!(release.join("binary-amd64/f/foo/foo_1.0_amd64.deb").exists());
+        assert!(!release.join("binary-amd64/b/bar").exists());
+
+        inner_migrate(&staging, &release, None, false).unwrap();
+        assert!(!staging.join("binary-amd64/f/foo/foo_1.0_amd64.deb").exists());
+        assert!(!staging.join("binary-amd64/b/bar/bar_1.0_amd64.deb").exists());
+        assert!(release.join("binary-amd64/b/bar/bar_1.0_amd64.deb").exists());
+    }
+
+    #[test]
+    fn migrate_refuses_replacing_published() {
+        let dir = tempdir().unwrap();
+        let staging = dir.path().join("bionic-staging/main");
+        let release = dir.path().join("bionic/main");
+        for &(pool, contents) in &[(&staging, "rebuilt"), (&release, "published")] {
+            let path = pool.join("binary-amd64/f/foo");
+            fs::create_dir_all(&path).unwrap();
+            misc::write(path.join("foo_1.0_amd64.deb"), contents).unwrap();
+        }
+
+        assert!(inner_migrate(&staging, &release, Some(&["foo"]), true).is_err());
+        let published = release.join("binary-amd64/f/foo/foo_1.0_amd64.deb");
+        assert_eq!(misc::read(&published).unwrap(), b"published");
+    }
+}
@@ -1,207 +1,668 @@
+mod archive;
 mod build;
+mod by_hash;
+mod changelogs;
+mod chroot;
+mod clean;
+mod closure;
+mod combined;
+mod delta;
+mod diff;
 mod download;
+mod flat;
 mod generate;
+mod hook;
+mod import;
+mod incoming;
+mod init;
+mod integrity;
+mod keys;
+mod layout;
 mod migrate;
+mod pdiff;
+mod plan;
 mod pool;
 mod prepare;
+mod prune;
+mod publish;
+mod query;
+mod queue;
+mod resign;
+mod rsync;
+mod sign;
+mod snapshot;
+mod stats;
+mod store;
+mod test_apt;
+mod verify;
 mod version;
-
-pub use self::migrate::migrate;
-
-use config::Config;
-use misc::remove_empty_directories_from;
+mod watch;
+
+pub use self::archive::archive_suite;
+pub use self::chroot::{create_chroot, update_chroots};
+pub use self::clean::{clean, clean_stale};
+pub use self::combined::generate_combined_index;
+pub use self::diff::{diff, diff_json, diff_table};
+pub use self::import::{import_repo, record_imported};
+pub use self::incoming::{process_incoming, watch_incoming};
+pub use self::init::init;
+pub use self::integrity::detect_tampering;
+pub use self::keys::export_keys;
+pub use self::migrate::{copy_packages, migrate, migrate_suite};
+pub use self::plan::{apply_plan, plan_publish, PublishPlan};
+pub use self::pool::verify_dedup_integrity;
+pub use self::publish::publish;
+pub use self::query::{manifest, published, search, table, Published};
+pub use self::queue::{approve, queued, reject, Queued, QUEUE};
+pub use self::resign::resign;
+pub use self::snapshot::{create_snapshot, list_snapshots, publish_snapshot, snapshot_path};
+pub use self::stats::project_metrics;
+pub use self::store::{Store, STORE};
+pub use self::test_apt::test_apt;
+pub use self::verify::verify;
+pub use self::watch::watch;
+
+use action;
+use checksum::DigestCache;
+use compress::*;
+use config::{Config, DebugSymbols};
+use cwd;
+use debian::{is_installer_component, DEBIAN_INSTALLER};
+use interrupt;
+use misc::{self, remove_empty_directories_from};
 use rayon;
 use rayon::prelude::*;
-use std::{env, fs, io};
+use std::{env, fs, io, mem};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+use workspace;
 
+/// The packages of the config which a run operates on.
 pub enum Packages<'a> {
     All,
+    /// The named packages, which are rebuilt even if they are unchanged when `true` is given.
     Select(&'a [&'a str], bool)
 }
 
+/// The failure of a step of a run, which is returned to the caller rather than exiting, so that
+/// a service may run the steps of many repos within one process.
+#[derive(Debug, Fail)]
+pub enum RepoError {
+    #[fail(display = "{}", why)]
+    Failed { why: String },
+    /// The run was interrupted by a signal, after which its remaining steps should not be run.
+    #[fail(display = "{}", why)]
+    Interrupted { why: String },
+}
+
+impl RepoError {
+    pub(crate) fn failed(why: String) -> RepoError {
+        RepoError::Failed { why }
+    }
+
+    /// The exit status of a process whose run failed with this error.
+    pub fn exit_status(&self) -> i32 {
+        match *self {
+            RepoError::Failed { .. } => 1,
+            RepoError::Interrupted { .. } => interrupt::exit_status(),
+        }
+    }
+}
+
+/// A run of the steps which update the repo of a suite, from fetching its packages to
+/// generating its dist files, where each step is chained onto the last:
+///
+/// ```ignore
+/// Repo::prepare(config, Packages::All)?.download()?.build()?.generate()?;
+/// ```
+///
+/// The current directory is held by the run until it is dropped, so that runs on other threads
+/// wait for it to finish.
 pub struct Repo<'a> {
     config: Config,
-    packages: Packages<'a>
+    packages: Packages<'a>,
+    _cwd: cwd::Guard
 }
 
 impl<'a> Repo<'a> {
-    pub fn prepare(config: Config, packages: Packages<'a>) -> Repo<'a> {
+    pub fn prepare(config: Config, packages: Packages<'a>) -> Result<Repo<'a>, RepoError> {
+        let _cwd = cwd::lock();
         if let Err(why) = prepare::build_directories(&config.archive) {
-            error!("failed to clean build directories: {}", why);
-            exit(1);
+            return Err(RepoError::failed(format!("failed to clean build directories: {}", why)));
         }
 
         if let Err(why) = prepare::create_missing_directories(&config.archive) {
-            error!("unable to create directories in current directory: {}", why);
-            exit(1);
+            return Err(RepoError::failed(format!("unable to create directories in current directory: {}", why)));
         }
 
-        Repo { config, packages }
+        Ok(Repo { config, packages, _cwd })
     }
 
-    pub fn clean(self) -> Self {
+    pub fn clean(self) -> Result<Self, RepoError> {
         if let Err(why) = prepare::package_cleanup(&self.config) {
-            error!("failed to clean up file: {}", why);
-            exit(1);
+            return Err(RepoError::failed(format!("failed to clean up file: {}", why)));
         }
 
-        self
+        Ok(self)
     }
 
-    pub fn download(self) -> Self {
+    pub fn download(self) -> Result<Self, RepoError> {
         match self.packages {
-            Packages::All => download::all(&self.config),
+            Packages::All => download::all(&self.config)?,
             Packages::Select(ref packages, _) => {
-                download::packages(&self.config, packages)
+                download::packages(&self.config, packages)?
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn prune(self, keep: usize) -> Result<Self, RepoError> {
+        let pool = self.config.pool_path();
+        match prune::prune(&pool, keep) {
+            Ok(removed) => info!("pruned {} superseded files from {}", removed.len(), pool.display()),
+            Err(why) => return Err(RepoError::failed(format!("failed to prune pool: {}", why)))
+        }
+
+        Ok(self)
+    }
+
+    /// Moves the misplaced files of the pool into place, or only reports them unless `fix` is
+    /// set, failing if any are misplaced.
+    pub fn repair(self, fix: bool) -> Result<Self, RepoError> {
+        let suite = self.config.archive.clone();
+        match pool::check_pool_placement(&self.config.pool_root(), &suite, fix) {
+            Ok(ref repaired) if fix => info!("moved {} misplaced files within the pool of {}", repaired.len(), suite),
+            Ok(ref misplaced) if !misplaced.is_empty() => {
+                return Err(RepoError::failed(format!("{} files are misplaced within the pool of {}", misplaced.len(), suite)))
             }
+            Ok(_) => info!("every file of the pool of {} is in place", suite),
+            Err(why) => return Err(RepoError::failed(format!("failed to repair pool: {}", why)))
         }
 
-        self
+        Ok(self)
     }
 
-    pub fn build(self) -> Self {
+    pub fn build(self) -> Result<Self, RepoError> {
+        resign::warn_expiry(&self.config);
         match self.packages {
-            Packages::All => build::all(&self.config),
+            Packages::All => build::all(&self.config)?,
             Packages::Select(ref packages, force) => {
-                build::packages(&self.config, packages, force)
+                build::packages(&self.config, packages, force)?
             }
         }
 
-        self
+        Ok(self)
     }
 
-    pub fn generate(self) {
-        if let Err(why) = generate_release_files(&self.config) {
-            error!("failed to generate dist files: {}", why);
-            exit(1);
-        }
+    pub fn generate(self) -> Result<(), RepoError> {
+        generate_release_files(&self.config)
+            .map_err(|why| RepoError::failed(format!("failed to generate dist files: {}", why)))
     }
 
-    pub fn remove(self) -> Self {
+    pub fn remove(self, version: Option<&str>) -> Result<Self, RepoError> {
         if let Packages::Select(ref packages, _) = self.packages {
-            if let Err(why) = prepare::remove(packages, &self.config.archive, &self.config.default_component) {
-                error!("failed to remove file: {}", why);
-                exit(1);
+            let pool = self.config.pool_path();
+            if let Err(why) = prepare::remove(&pool, packages, version) {
+                return Err(RepoError::failed(format!("failed to remove file: {}", why)));
+            }
+
+            // Links to the removed files, and the directories which held them, are swept away.
+            let dists = self.config.repo_path().join("dists");
+            for directory in &[self.config.pool_root(), dists] {
+                if let Err(why) = misc::sweep(directory) {
+                    return Err(RepoError::failed(format!("failed to sweep {}: {}", directory.display(), why)));
+                }
             }
         }
 
-        self
+        Ok(self)
     }
 }
 
 #[derive(Debug, Fail)]
 pub enum ReleaseError {
+    #[fail(display = "generated dist files failed their audit: {}", why)]
+    Audit { why: io::Error },
+    #[fail(display = "failed to publish indices by hash: {}", why)]
+    ByHash { why: io::Error },
+    #[fail(display = "failed to publish the changelogs of {}: {}", suite, why)]
+    Changelogs { suite: String, why: io::Error },
+    #[fail(display = "failed to resolve the dependencies of {}: {}", suite, why)]
+    Closure { suite: String, why: io::Error },
     #[fail(display = "failed to collect component names from {:?}", pool)]
     Components { pool: PathBuf, why: io::Error },
+    #[fail(display = "failed to generate the deltas of {}: {}", suite, why)]
+    Delta { suite: String, why: io::Error },
+    #[fail(display = "failed to route debug symbols: {}", why)]
+    DebugSymbols { why: io::Error },
     #[fail(display = "failed to generate distribution files for {}: {}", suite, why)]
     DistGeneration { suite: String, why: io::Error },
     #[fail(display = "failed to generate dist release files for {}: {}", archive, why)]
     DistRelease { archive: String, why: io::Error },
     #[fail(display = "failed to remove dists directory at {:?}: {}", path, why)]
     DistRemoval { path: PathBuf, why: io::Error },
+    #[fail(display = "failed to publish the generated dist files of {}: {}", suite, why)]
+    DistSwap { suite: String, why: io::Error },
+    #[fail(display = "failed to generate the flat repository of {}: {}", suite, why)]
+    Flat { suite: String, why: io::Error },
+    #[fail(display = "failed to run the hooks of {}: {}", suite, why)]
+    Hook { suite: String, why: io::Error },
     #[fail(display = "failed to generate InRelease file: {}", why)]
     InRelease { why: io::Error },
+    #[fail(display = "failed to migrate the pool layout of {}: {}", suite, why)]
+    Layout { suite: String, why: io::Error },
+    #[fail(display = "failed to set the ownership of the repo: {}", why)]
+    Ownership { why: io::Error },
+    #[fail(display = "failed to generate pdiffs: {}", why)]
+    Pdiff { why: io::Error },
     #[fail(display = "pool cleanup failure at {:?}: {}", path, why)]
     PoolCleanup { path: PathBuf, why: io::Error },
     #[fail(display = "failed to generate Release.gpg file: {}", why)]
     ReleaseGPG { why: io::Error },
+    #[fail(display = "unable to switch dir to the repo at {:?}: {}", path, why)]
+    RepoDirectory { path: PathBuf, why: io::Error },
+    #[fail(display = "failed to record repository state: {}", why)]
+    RepoState { why: io::Error },
     #[fail(display = "failed to generate source index: {}", why)]
     Source { why: io::Error },
 }
 
 /// Generate the dist release files from the existing binary and source files.
+///
+/// If debug symbols are routed to a suite of their own, that suite is generated afterwards. A
+/// flat repository is generated at the root of the repo instead.
 pub fn generate_release_files(sources: &Config) -> Result<(), ReleaseError> {
-    env::set_current_dir("repo").expect("unable to switch dir to repo");
+    let _cwd = cwd::lock();
+    let repo = sources.repo_path();
 
-    let base = ["dists/", &sources.archive].concat();
+    // A dry run has no pool to generate from if the repo has yet to be created.
+    if action::is_dry_run() && !repo.exists() {
+        action::report(format_args!("create {}, and generate its dist files", repo.display()));
+        return Ok(());
+    }
+
+    // The store is kept in the project, rather than in the repo that is generated within.
+    let project = env::current_dir()
+        .map_err(|why| ReleaseError::RepoDirectory { path: repo.to_path_buf(), why })?;
+    let store_path = project.join(STORE);
+
+    action::create_dir_all(repo)
+        .and_then(|_| env::set_current_dir(repo))
+        .map_err(|why| ReleaseError::RepoDirectory { path: repo.to_path_buf(), why })?;
+
+    // Pool files which are unchanged since the last generation are not hashed again.
+    let mut store = Store::open(&store_path).unwrap_or_else(|why| {
+        warn!("unable to read the cached digests of the pool: {}", why);
+        Store::default()
+    });
+
+    if !sources.flat {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+        layout::migrate_layout(Path::new("."), sources, &mut store, now)
+            .map_err(|why| ReleaseError::Layout { suite: sources.archive.clone(), why })?;
+    }
+
+    // The files which were copied into the pool by this run were hashed as they were copied.
+    let mut digests = mem::replace(&mut store.digests, BTreeMap::new());
+    digests.extend(sources.pooled.take());
+    let cache = DigestCache::new(digests, sources.digests())
+        .mmap_threshold(sources.hashing().mmap_threshold());
+    let result = if sources.flat {
+        flat::generate_flat(sources, &cache)
+            .map_err(|why| ReleaseError::Flat { suite: sources.archive.clone(), why })
+    } else {
+        generate_suite(sources, &cache).and_then(|_| match sources.debug_suite() {
+            Some(debug) => generate_suite(&debug, &cache),
+            None => Ok(())
+        })
+    };
+
+    if !action::is_dry_run() {
+        store.digests = cache.into_entries();
+        if let Err(why) = store.save(&store_path) {
+            warn!("unable to cache the digests of the pool: {}", why);
+        }
+    }
+
+    result?;
+    if let Some(ref ownership) = sources.ownership {
+        action::perform(format_args!("give the repo to {}:{}", ownership.uid, ownership.gid), || {
+            pool::set_pool_ownership(Path::new("."), ownership.uid, ownership.gid)
+        }).map_err(|why| ReleaseError::Ownership { why })?;
+    }
+
+    match sources.hooks.as_ref().and_then(|hooks| hooks.post_generate.as_ref()) {
+        Some(script) => {
+            let repo = project.join(repo);
+            let repo = repo.to_string_lossy();
+            let env = [("DEBREP_SUITE", sources.archive.as_str()), ("DEBREP_REPO", &*repo)];
+            hook::run_hook("post-generate", script, &project, &env)
+                .map_err(|why| ReleaseError::Hook { suite: sources.archive.clone(), why })
+        }
+        None => Ok(())
+    }
+}
+
+/// Generates the dist files of a suite, from within the repo directory.
+fn generate_suite(sources: &Config, cache: &DigestCache) -> Result<(), ReleaseError> {
+    // The suite is generated beside the published suite, and only swapped in when complete.
+    let suite = ["dists/", &sources.archive].concat();
+    let base = [&suite, ".new"].concat();
     let pool = ["pool/", &sources.archive, "/"].concat();
     let pool_path = &Path::new(&pool);
 
     {
         let base = &Path::new(&base);
         if base.exists() {
-            fs::remove_dir_all(&base)
+            action::remove_dir_all(&base)
                 .map_err(|why| ReleaseError::DistRemoval { path: base.to_path_buf(), why })?;
         }
+
+        // A suite which was left half-generated by a failed run is removed with its workspace.
+        workspace::register(base)
+            .map_err(|why| ReleaseError::DistRemoval { path: base.to_path_buf(), why })?;
+    }
+
+    if let Some(ref routing) = sources.debug_symbols {
+        pool::route_debug_symbols(Path::new("pool"), &sources.archive, routing)
+            .map_err(|why| ReleaseError::DebugSymbols { why })?;
     }
 
     remove_empty_directories_from(pool_path)
         .map_err(|why| ReleaseError::PoolCleanup { path: pool_path.to_path_buf(), why})?;
 
+    // Configured components are listed in the release, even before they have packages.
+    for component in sources.components() {
+        let path = pool_path.join(component);
+        action::create_dir_all(&path).map_err(|why| ReleaseError::PoolCleanup { path, why })?;
+    }
+
+    if action::is_dry_run() {
+        action::report(format_args!("regenerate, sign, and publish the indices of {}", suite));
+        return Ok(());
+    }
+
     let release = PathBuf::from([&base, "/Release"].concat());
     let in_release = PathBuf::from([&base, "/InRelease"].concat());
     let release_gpg = PathBuf::from([&base, "/Release.gpg"].concat());
 
-    let components = collect_components(pool_path, &base).map_err(|why| {
+    let mut subcomponents = vec![DEBIAN_INSTALLER];
+    if let Some(DebugSymbols::Component { ref component }) = sources.debug_symbols {
+        subcomponents.push(component.as_str());
+    }
+
+    let components = collect_components(pool_path, &base, &subcomponents).map_err(|why| {
         ReleaseError::Components { pool: pool_path.to_path_buf(), why }
     })?;
 
-    // Generates the dist directory's archives in parallel.
-    generate::dists(sources, &base, pool_path, &components)
-        .map_err(|why| ReleaseError::DistGeneration {
-            suite: sources.archive.clone(),
-            why
-        })?;
+    // Generates the dist directory's archives in parallel, unless the suite only has sources.
+    if !sources.source_only {
+        generate::dists(sources, &base, pool_path, &components, cache)
+            .map_err(|why| ReleaseError::DistGeneration {
+                suite: sources.archive.clone(),
+                why
+            })?;
+    }
 
     // TODO: Merge this functionality with generate::dists
     // Then write the source archives in the dist directory
     components.par_iter().map(|component| {
         let pool = [&pool, component.as_str()].concat();
-        generate::sources_index(&component, &base, &pool)
+        generate::sources_index(sources, &component, &base, &pool, cache)
             .map_err(|why| ReleaseError::Source { why })
     }).collect::<Result<(), ReleaseError>>()?;
 
-    generate::dists_release(sources, &base, &components)
+    // Dependencies which cannot be satisfied are found before the suite is published.
+    if let Some(ref closure) = sources.closure {
+        let suite = sources.archive.clone();
+        let unsatisfied = closure::check_closure(sources, closure, Path::new(&base), &components)
+            .map_err(|why| ReleaseError::Closure { suite: suite.clone(), why })?;
+
+        for dependency in &unsatisfied {
+            warn!("{}: {}", suite, dependency);
+        }
+
+        if closure.fail && !unsatisfied.is_empty() {
+            return Err(ReleaseError::Closure {
+                suite,
+                why: io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} dependencies cannot be satisfied", unsatisfied.len())
+                )
+            });
+        }
+    }
+
+    if let Some(keep) = sources.pdiffs {
+        pdiff::refresh_pdiffs(Path::new(&suite), Path::new(&base), keep)
+            .map_err(|why| ReleaseError::Pdiff { why })?;
+    }
+
+    if let Some(keep_history) = sources.by_hash {
+        by_hash::carry_by_hash(Path::new(&suite), Path::new(&base))
+            .and_then(|_| by_hash::refresh_by_hash(Path::new(&base), keep_history, sources.digests()))
+            .map_err(|why| ReleaseError::ByHash { why })?;
+    }
+
+    // The installer's indices are published within their component, rather than as a component.
+    let release_components = components.iter()
+        .filter(|component| !is_installer_component(component))
+        .cloned()
+        .collect::<Vec<String>>();
+
+    generate::dists_release(sources, &base, &release_components)
         .map_err(|why| ReleaseError::DistRelease {
             archive: sources.archive.clone(),
             why,
         })?;
 
+    let signer = sign::Signer::new(sources).map_err(|why| ReleaseError::InRelease { why })?;
     let (inrelease, release) = rayon::join(
         || {
-            generate::gpg_in_release(&sources.email, &release, &in_release)
+            signer.in_release(&release, &in_release)
                 .map_err(|why| ReleaseError::InRelease { why })
         },
         || {
-            generate::gpg_release(&sources.email, &release, &release_gpg)
+            signer.detached(&release, &release_gpg)
                 .map_err(|why| ReleaseError::ReleaseGPG { why })
         }
     );
 
-    inrelease.and(release)
+    inrelease.and(release)?;
+
+    let mismatched = integrity::audit_release(Path::new(&base))
+        .map_err(|why| ReleaseError::Audit { why })?;
+    if !mismatched.is_empty() {
+        return Err(ReleaseError::Audit {
+            why: io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("indices do not match the Release file: {:?}", mismatched)
+            )
+        });
+    }
+
+    // The indices which are about to be replaced give the previous version of each package.
+    let previous = match sources.deltas {
+        Some(_) => query::published(Path::new("."), &sources.archive)
+            .map_err(|why| ReleaseError::Delta { suite: sources.archive.clone(), why })?,
+        None => Vec::new()
+    };
+
+    // An interrupted run leaves the published suite as it was.
+    interrupt::check().map_err(|why| ReleaseError::DistSwap { suite: sources.archive.clone(), why })?;
+    generate::swap_dists(Path::new(&suite), Path::new(&base))
+        .map_err(|why| ReleaseError::DistSwap { suite: sources.archive.clone(), why })?;
+
+    if let Some(keep) = sources.deltas {
+        query::published(Path::new("."), &sources.archive)
+            .and_then(|current| delta::refresh_deltas(Path::new("."), &sources.archive, &previous, &current, keep))
+            .map_err(|why| ReleaseError::Delta { suite: sources.archive.clone(), why })?;
+    }
+
+    if sources.changelogs.is_some() {
+        query::published(Path::new("."), &sources.archive)
+            .and_then(|current| changelogs::refresh_changelogs(Path::new("."), &sources.archive, &current))
+            .map_err(|why| ReleaseError::Changelogs { suite: sources.archive.clone(), why })?;
+    }
+
+    // Empty indices are only reported, as they may be intentional.
+    if let Err(why) = stats::empty_indices(Path::new(&suite)) {
+        warn!("failed to check for empty indices in {}: {}", suite, why);
+    }
+
+    if sources.repo_state {
+        integrity::write_repo_state(Path::new("."), &sources.archive, Some(&signer))
+            .map_err(|why| ReleaseError::RepoState { why })?;
+    }
+
+    Ok(())
+}
+
+/// Publishes an empty, but valid, suite skeleton for a repository which has no packages yet.
+///
+/// This creates empty `Packages` and `Sources` indices for each configured architecture of the
+/// default component, along with the dist `Release` file, so that `apt update` succeeds before the
+/// first package has been published. If a signing key is given, the `Release` will be signed.
+pub fn init_suite(repo: &Path, config: &Config, signing_key: Option<&str>) -> io::Result<()> {
+    let _cwd = cwd::lock();
+    let base = repo.join("dists").join(&config.archive);
+    let component = base.join(&config.default_component);
+    info!("initializing empty suite at {}", base.display());
+
+    fs::create_dir_all(repo.join("pool").join(&config.archive))?;
+
+    for arch in &config.architectures {
+        let binary = component.join(["binary-", arch].concat());
+        fs::create_dir_all(&binary)?;
+        compress_level("Packages", &binary, io::empty(), config.compression(), config.compression_level)?;
+    }
+
+    let source = component.join("source");
+    fs::create_dir_all(&source)?;
+    compress_level("Sources", &source, io::empty(), config.compression(), config.compression_level)?;
+
+    let base_str = base.to_str().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} is not a UTF-8 path", base.display())
+    ))?;
+
+    generate::dists_release(config, base_str, &[config.default_component.clone()])?;
+
+    if let Some(key) = signing_key {
+        sign::sign_release(key, &base)?;
+    }
+
+    Ok(())
 }
 
-fn collect_components(pool: &Path, base: &str) -> io::Result<Vec<String>> {
+/// The components of the suite's pool, along with any of the given subcomponents that they have,
+/// such as `main/debian-installer` or `main/debug`.
+fn collect_components(
+    pool: &Path,
+    base: &str,
+    subcomponents: &[&str]
+) -> io::Result<Vec<String>> {
     let mut components = Vec::new();
 
     for component in pool.read_dir()? {
         if let Ok(component) = component {
             if component.path().is_dir() {
-                let component = component.file_name();
-                let component = component.to_str().unwrap();
-                for arch in &[
-                    "binary-amd64",
-                    "binary-arm64",
-                    "binary-armel",
-                    "binary-armhf",
-                    "binary-i386",
-                    "binary-mips",
-                    "binary-mipsel",
-                    "binary-mips64el",
-                    "binary-ppc64el",
-                    "binary-s390x",
-                    "binary-all",
-                    "source",
-                ] {
-                    let _ = fs::create_dir_all([&base, "/", component, "/", arch].concat());
+                let name = component.file_name();
+                let name = name.to_str().unwrap();
+                components.push(name.to_owned());
+
+                for subcomponent in subcomponents {
+                    if component.path().join(subcomponent).is_dir() {
+                        components.push([name, "/", subcomponent].concat());
+                    }
                 }
+            }
+        }
+    }
 
-                components.push(component.to_owned());
+    // Architecture independent packages are listed in the index of every architecture, so there
+    // is no `binary-all` index to create, whether or not the pool has such packages.
+    for component in &components {
+        for arch in &[
+            "binary-amd64",
+            "binary-arm64",
+            "binary-armel",
+            "binary-armhf",
+            "binary-i386",
+            "binary-mips",
+            "binary-mipsel",
+            "binary-mips64el",
+            "binary-ppc64el",
+            "binary-s390x",
+            "source",
+        ] {
+            // Subcomponents only index binary packages.
+            if *arch == "source" && component.contains('/') {
+                continue
             }
+
+            let _ = fs::create_dir_all([base, "/", component, "/", arch].concat());
         }
     }
 
+    components.sort();
     Ok(components)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use super::integrity::audit_release;
+    use super::store::Store;
+    use super::verify::verify;
+    use tempfile::tempdir;
+    use toml;
+
+    #[test]
+    fn empty_suite_skeleton() {
+        // The `Release` file is generated by apt-ftparchive, which may not be installed.
+        if Command::new("apt-ftparchive").arg("--version").output().is_err() {
+            return;
+        }
+
+        let config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "test"
+            label = "test"
+            email = "test@example.com"
+            architectures = ["amd64"]
+        "#).unwrap();
+
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        init_suite(repo, &config, None).unwrap();
+
+        let base = repo.join("dists/bionic");
+        for index in &["main/binary-amd64/Packages", "main/binary-amd64/Packages.gz", "main/source/Sources"] {
+            assert!(base.join(index).is_file(), "{} was not created", index);
+        }
+
+        assert_eq!(fs::read(base.join("main/binary-amd64/Packages")).unwrap(), b"");
+        assert_eq!(fs::read(base.join("main/source/Sources")).unwrap(), b"");
+        assert!(!base.join("main/binary-all").exists());
+
+        // Each index must be listed by the `Release`, with its size and digests.
+        let release = fs::read_to_string(base.join("Release")).unwrap();
+        let sha256 = release.split("\nSHA256:\n").nth(1).expect("Release lists no SHA256 digests");
+        let listed = sha256.lines()
+            .take_while(|line| line.starts_with(' '))
+            .filter_map(|line| line.split_whitespace().nth(2))
+            .collect::<Vec<&str>>();
+
+        for index in &["main/binary-amd64/Packages", "main/binary-amd64/Packages.gz", "main/source/Sources"] {
+            assert!(listed.contains(index), "Release does not list {}", index);
+        }
+
+        assert_eq!(audit_release(&base).unwrap(), Vec::<PathBuf>::new());
+        assert_eq!(verify(repo, "bionic", &Store::default()).unwrap(), vec![]);
+    }
+}
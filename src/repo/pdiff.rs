@@ -0,0 +1,404 @@
+use checksum::hasher;
+use chrono::Utc;
+use compress::{compress, GZ_COMPRESS};
+use misc;
+use sha2::Sha256;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{fmt, fs};
+use walkdir::WalkDir;
+
+/// A file recorded within a pdiff `Index`, identified by its SHA256 digest and size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PdiffEntry {
+    pub sha256: String,
+    pub size:   u64,
+    pub name:   String,
+}
+
+impl fmt::Display for PdiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, " {} {:>7} {}", self.sha256, self.size, self.name)
+    }
+}
+
+/// An in-memory representation of a `Packages.diff/Index` file.
+#[derive(Debug, Default)]
+pub struct PdiffIndex {
+    pub current:  Option<(String, u64)>,
+    pub history:  Vec<PdiffEntry>,
+    pub patches:  Vec<PdiffEntry>,
+    pub download: Vec<PdiffEntry>,
+}
+
+impl PdiffIndex {
+    /// Reads an existing index, or returns an empty index if the file does not exist.
+    pub fn read(path: &Path) -> io::Result<PdiffIndex> {
+        if !path.exists() {
+            return Ok(PdiffIndex::default());
+        }
+
+        let data = misc::read_to_string(path)?;
+        let mut index = PdiffIndex::default();
+        let mut section = "";
+
+        for line in data.lines() {
+            if line.starts_with(' ') {
+                let mut fields = line.split_whitespace();
+                let entry = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(sha256), Some(size), Some(name)) => PdiffEntry {
+                        sha256: sha256.to_owned(),
+                        size: size.parse::<u64>().map_err(|why| invalid_index(path, why))?,
+                        name: name.to_owned(),
+                    },
+                    _ => return Err(invalid_index(path, line)),
+                };
+
+                match section {
+                    "SHA256-History" => index.history.push(entry),
+                    "SHA256-Patches" => index.patches.push(entry),
+                    "SHA256-Download" => index.download.push(entry),
+                    _ => ()
+                }
+            } else if let Some(pos) = line.find(':') {
+                section = &line[..pos];
+                if section == "SHA256-Current" {
+                    let mut fields = line[pos + 1..].split_whitespace();
+                    if let (Some(sha256), Some(size)) = (fields.next(), fields.next()) {
+                        let size = size.parse::<u64>().map_err(|why| invalid_index(path, why))?;
+                        index.current = Some((sha256.to_owned(), size));
+                    }
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        if let Some((ref sha256, size)) = self.current {
+            writeln!(file, "SHA256-Current: {} {}", sha256, size)?;
+        }
+
+        for &(key, entries) in &[
+            ("SHA256-History", &self.history),
+            ("SHA256-Patches", &self.patches),
+            ("SHA256-Download", &self.download),
+        ] {
+            writeln!(file, "{}:", key)?;
+            for entry in entries.iter() {
+                writeln!(file, "{}", entry)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid_index<E: fmt::Display>(path: &Path, why: E) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid pdiff index at {}: {}", path.display(), why)
+    )
+}
+
+fn digest(path: &Path) -> io::Result<(String, u64)> {
+    let size = fs::metadata(path)?.len();
+    let sha256 = File::open(path).and_then(hasher::<Sha256, File>)?;
+    Ok((sha256, size))
+}
+
+/// Generates an ed-style diff from the previous index to the current index, stores it within
+/// `diff_dir`, and records it in the `Index` file there.
+///
+/// The diff directory should be generated before the dist `Release` file, as `apt-ftparchive`
+/// includes any `Index` files that it finds in the dists directory.
+pub fn generate_pdiff(old_index: &Path, new_index: &Path, diff_dir: &Path) -> io::Result<()> {
+    let patch = ed_diff(old_index, new_index)?;
+    if patch.is_empty() {
+        info!("{} has not changed -- skipping pdiff generation", new_index.display());
+        return Ok(());
+    }
+
+    fs::create_dir_all(diff_dir)?;
+    // Patches are named by their time, and suffixed if a suite is regenerated in the same second.
+    let stamp = Utc::now().format("%Y-%m-%d-%H%M.%S").to_string();
+    let mut name = stamp.clone();
+    let mut suffix = 1;
+    while diff_dir.join([&name, ".gz"].concat()).exists() {
+        name = format!("{}-{}", stamp, suffix);
+        suffix += 1;
+    }
+
+    info!("generating pdiff {} in {}", name, diff_dir.display());
+
+    compress(&name, diff_dir, patch.as_slice(), GZ_COMPRESS)?;
+
+    let index_path = diff_dir.join("Index");
+    let mut index = PdiffIndex::read(&index_path)?;
+    let (old_sha256, old_size) = digest(old_index)?;
+    let (gz_sha256, gz_size) = digest(&diff_dir.join([&name, ".gz"].concat()))?;
+
+    index.current = Some(digest(new_index)?);
+    index.history.push(PdiffEntry { sha256: old_sha256, size: old_size, name: name.clone() });
+    index.patches.push(PdiffEntry {
+        sha256: hasher::<Sha256, &[u8]>(patch.as_slice())?,
+        size: patch.len() as u64,
+        name: name.clone(),
+    });
+    index.download.push(PdiffEntry {
+        sha256: gz_sha256,
+        size: gz_size,
+        name: [&name, ".gz"].concat(),
+    });
+
+    index.write(&index_path)
+}
+
+/// Retains only the most recent `keep` patches within the `Index`, removing older patch files.
+///
+/// Clients whose index is older than the remaining history will fall back to fetching the full
+/// index, which APT handles on its own.
+pub fn prune_pdiffs(diff_dir: &Path, keep: usize) -> io::Result<()> {
+    let index_path = diff_dir.join("Index");
+    let mut index = PdiffIndex::read(&index_path)?;
+
+    fn prune(entries: &mut Vec<PdiffEntry>, keep: usize) -> Vec<PdiffEntry> {
+        let excess = entries.len().saturating_sub(keep);
+        entries.drain(..excess).collect()
+    }
+
+    prune(&mut index.history, keep);
+    prune(&mut index.patches, keep);
+    for entry in prune(&mut index.download, keep) {
+        let path = diff_dir.join(&entry.name);
+        if path.exists() {
+            info!("removing expired pdiff at {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+
+    index.write(&index_path)
+}
+
+/// Carries the `Packages.diff` directory of each published `Packages` index over to the newly
+/// generated dist directory, and records a diff from the published index to its replacement,
+/// keeping at most `keep` diffs of each index.
+///
+/// Only uncompressed indices are diffed, so suites which do not publish them have no pdiffs.
+pub fn refresh_pdiffs(current: &Path, new: &Path, keep: usize) -> io::Result<()> {
+    let indices = WalkDir::new(new)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != "by-hash")
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.file_name() == "Packages")
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    for new_index in indices {
+        let relative = new_index.strip_prefix(new).unwrap();
+        let old_index = current.join(relative);
+        let diff_dir = new_index.with_file_name("Packages.diff");
+        let old_diff_dir = old_index.with_file_name("Packages.diff");
+
+        // Copied rather than linked, as the `Index` is rewritten in place.
+        if old_diff_dir.is_dir() {
+            fs::create_dir_all(&diff_dir)?;
+            for entry in old_diff_dir.read_dir()? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    fs::copy(entry.path(), diff_dir.join(entry.file_name()))?;
+                }
+            }
+        }
+
+        if old_index.is_file() {
+            generate_pdiff(&old_index, &new_index, &diff_dir)?;
+        }
+
+        if diff_dir.is_dir() {
+            prune_pdiffs(&diff_dir, keep)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates an ed script which transforms `old` into `new`, via `diff --ed`.
+fn ed_diff(old: &Path, new: &Path) -> io::Result<Vec<u8>> {
+    let output = Command::new("diff").arg("--ed").arg(old).arg(new).output()?;
+
+    // An exit status of 1 signifies that the files differ.
+    match output.status.code() {
+        Some(0) | Some(1) => Ok(output.stdout),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "diff failed for {} and {}: {}",
+                old.display(),
+                new.display(),
+                String::from_utf8_lossy(&output.stderr)
+            )
+        ))
+    }
+}
+
+/// Applies an ed script, as generated by `diff --ed`, to the original text.
+pub fn apply_ed(original: &str, script: &str) -> io::Result<String> {
+    let invalid = |command: &str| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unsupported ed command: {}", command)
+    );
+
+    let mut lines: Vec<&str> = original.lines().collect();
+    let mut script = script.lines();
+
+    while let Some(command) = script.next() {
+        if command.is_empty() {
+            return Err(invalid(command));
+        }
+
+        let (range, action) = command.split_at(command.len() - 1);
+        let mut bounds = range.splitn(2, ',').map(|x| x.parse::<usize>());
+        let start = match bounds.next() {
+            Some(Ok(start)) => start,
+            _ => return Err(invalid(command)),
+        };
+
+        let end = match bounds.next() {
+            Some(Ok(end)) => end,
+            Some(Err(_)) => return Err(invalid(command)),
+            None => start,
+        };
+
+        if end < start || end > lines.len() || (action != "a" && start == 0) {
+            return Err(invalid(command));
+        }
+
+        let text = if action == "a" || action == "c" {
+            script.by_ref().take_while(|&line| line != ".").collect::<Vec<&str>>()
+        } else {
+            Vec::new()
+        };
+
+        match action {
+            "a" => {
+                let tail = lines.split_off(start);
+                lines.extend(text);
+                lines.extend(tail);
+            }
+            "c" | "d" => {
+                let tail = lines.split_off(end);
+                lines.truncate(start - 1);
+                lines.extend(text);
+                lines.extend(tail);
+            }
+            _ => return Err(invalid(command)),
+        }
+    }
+
+    let mut output = lines.join("\n");
+    if !output.is_empty() {
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libflate::gzip::Decoder;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    const OLD: &str = "Package: bar\nVersion: 1.0\n\nPackage: foo\nVersion: 1.0\n\nPackage: qux\nVersion: 2.0\n";
+    const NEW: &str = "Package: bar\nVersion: 1.1\n\nPackage: foo\nVersion: 1.0\n\nPackage: zap\nVersion: 0.1\nDepends: foo\n";
+
+    #[test]
+    fn pdiff_roundtrip() {
+        let dir = tempdir().unwrap();
+        let old = dir.path().join("Packages-old");
+        let new = dir.path().join("Packages");
+        let diff_dir = dir.path().join("Packages.diff");
+        misc::write(&old, OLD).unwrap();
+        misc::write(&new, NEW).unwrap();
+
+        generate_pdiff(&old, &new, &diff_dir).unwrap();
+
+        let index = PdiffIndex::read(&diff_dir.join("Index")).unwrap();
+        assert_eq!(index.current, Some(digest(&new).unwrap()));
+        assert_eq!(index.history.len(), 1);
+        assert_eq!(index.patches.len(), 1);
+
+        let mut script = String::new();
+        Decoder::new(File::open(diff_dir.join(&index.download[0].name)).unwrap())
+            .unwrap()
+            .read_to_string(&mut script)
+            .unwrap();
+
+        assert_eq!(apply_ed(OLD, &script).unwrap(), NEW);
+    }
+
+    #[test]
+    fn pdiff_pruning() {
+        let dir = tempdir().unwrap();
+        let diff_dir = dir.path();
+        let mut index = PdiffIndex::default();
+        index.current = Some(("0".repeat(64), 100));
+
+        let names = ["2018-10-01-0000.00", "2018-10-02-0000.00", "2018-10-03-0000.00"];
+        for name in &names {
+            let entry = PdiffEntry { sha256: "0".repeat(64), size: 10, name: name.to_string() };
+            let download = PdiffEntry { name: [*name, ".gz"].concat(), .. entry.clone() };
+            misc::write(diff_dir.join(&download.name), b"").unwrap();
+            index.history.push(entry.clone());
+            index.patches.push(entry);
+            index.download.push(download);
+        }
+
+        index.write(&diff_dir.join("Index")).unwrap();
+        prune_pdiffs(diff_dir, 2).unwrap();
+
+        let index = PdiffIndex::read(&diff_dir.join("Index")).unwrap();
+        let patches = index.patches.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(patches, &names[1..]);
+        assert_eq!(index.history.len(), 2);
+        assert_eq!(index.download.len(), 2);
+        assert!(!diff_dir.join("2018-10-01-0000.00.gz").exists());
+        assert!(diff_dir.join("2018-10-03-0000.00.gz").exists());
+    }
+
+    #[test]
+    fn pdiff_generations() {
+        let dir = tempdir().unwrap();
+        let current = dir.path().join("dists/bionic");
+        let new = dir.path().join("dists/bionic.new");
+        fs::create_dir_all(current.join("main/binary-amd64")).unwrap();
+        fs::create_dir_all(new.join("main/binary-amd64")).unwrap();
+        misc::write(current.join("main/binary-amd64/Packages"), OLD).unwrap();
+        misc::write(new.join("main/binary-amd64/Packages"), NEW).unwrap();
+
+        refresh_pdiffs(&current, &new, 1).unwrap();
+        let index = PdiffIndex::read(&new.join("main/binary-amd64/Packages.diff/Index")).unwrap();
+        assert_eq!(index.current, Some(digest(&new.join("main/binary-amd64/Packages")).unwrap()));
+        assert_eq!(index.patches.len(), 1);
+
+        // The history of the published suite is carried over, and pruned to the window.
+        fs::remove_dir_all(&current).unwrap();
+        fs::rename(&new, &current).unwrap();
+        fs::create_dir_all(new.join("main/binary-amd64")).unwrap();
+        misc::write(new.join("main/binary-amd64/Packages"), OLD).unwrap();
+
+        refresh_pdiffs(&current, &new, 1).unwrap();
+        let diff_dir = new.join("main/binary-amd64/Packages.diff");
+        let carried = PdiffIndex::read(&diff_dir.join("Index")).unwrap();
+        assert_eq!(carried.history.len(), 1);
+        let (published, _) = digest(&current.join("main/binary-amd64/Packages")).unwrap();
+        assert_eq!(carried.history[0].sha256, published);
+        assert!(diff_dir.join(&carried.download[0].name).exists());
+    }
+}
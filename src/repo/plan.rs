@@ -0,0 +1,230 @@
+use action;
+use config::Config;
+use debian::{compare_versions, get_debian_package_info};
+use misc;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::pool::pool_destination;
+use toml;
+
+/// A file which will be moved from the incoming directory into the pool.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PoolMove {
+    pub source:      PathBuf,
+    pub destination: PathBuf,
+}
+
+/// A binary package entry which will be added to, or removed from, an index.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndexChange {
+    pub package:      String,
+    pub version:      String,
+    pub architecture: String,
+    pub component:    String,
+}
+
+/// The disk operations and index changes that publishing an incoming directory will perform.
+///
+/// A plan may be serialized to TOML, so that it can be reviewed and signed off before it is
+/// applied with `apply_plan`.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PublishPlan {
+    pub suite: String,
+    /// Pool files which will no longer be referenced by an index, as they are superseded.
+    pub unreferenced: Vec<PathBuf>,
+    pub pool: Vec<PoolMove>,
+    pub added: Vec<IndexChange>,
+    pub removed: Vec<IndexChange>,
+}
+
+impl PublishPlan {
+    /// Reads a plan which was written by `to_toml`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        toml::from_slice(&misc::read(path)?).map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse the plan at {}: {}", path.display(), why)
+        ))
+    }
+
+    pub fn to_toml(&self) -> io::Result<String> {
+        toml::to_string(self).map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to serialize the plan: {}", why)
+        ))
+    }
+}
+
+/// Computes the plan for publishing the contents of `incoming` into the repo, without writing
+/// anything to disk. Files whose package name cannot be derived from their filename are skipped.
+pub fn plan_publish(repo: &Path, incoming: &Path, suite: &Config) -> io::Result<PublishPlan> {
+    let pool = repo.join("pool");
+    let component = &suite.default_component;
+    let mut plan = PublishPlan { suite: suite.archive.clone(), .. PublishPlan::default() };
+
+    // The newest version of each package which will be indexed, and if it is an incoming file.
+    let mut indexed: HashMap<(PathBuf, String), (String, PathBuf, bool)> = HashMap::new();
+
+    let mut entries = incoming.read_dir()?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            continue
+        }
+
+        let filename = match path.file_name().and_then(|x| x.to_str()) {
+            Some(filename) => filename.to_owned(),
+            None => continue
+        };
+
+        let destination = match pool_destination(&pool, &suite.archive, component, &filename) {
+            Ok(destination) => destination,
+            Err(why) => {
+                warn!("skipping {}, as it cannot be pooled: {}", path.display(), why);
+                continue
+            }
+        };
+        let target = destination.join(&filename);
+
+        if let Some((name, version)) = get_debian_package_info(&path) {
+            let filestem = &filename[..filename.rfind('.').unwrap_or_else(|| filename.len())];
+            let change = |version: &str| IndexChange {
+                package: filename[..filename.find('_').unwrap()].to_owned(),
+                version: version.to_owned(),
+                architecture: misc::get_arch_from_stem(filestem).to_owned(),
+                component: component.clone(),
+            };
+
+            let key = (destination.clone(), name);
+            if !indexed.contains_key(&key) {
+                if let Some((current, path)) = newest_in(&destination, &key.1)? {
+                    indexed.insert(key.clone(), (current, path, false));
+                }
+            }
+
+            let supersedes = indexed.get(&key)
+                .map_or(true, |&(ref current, _, _)| compare_versions(current, &version) == Ordering::Less);
+
+            if supersedes {
+                match indexed.remove(&key) {
+                    Some((old, _, true)) => plan.added.retain(|entry| *entry != change(&old)),
+                    Some((old, old_path, false)) => {
+                        plan.removed.push(change(&old));
+                        plan.unreferenced.push(old_path);
+                    }
+                    None => ()
+                }
+
+                plan.added.push(change(&version));
+                indexed.insert(key, (version, target.clone(), true));
+            }
+        }
+
+        plan.pool.push(PoolMove { source: path, destination: target });
+    }
+
+    Ok(plan)
+}
+
+/// Finds the newest version of a package that is already stored within a pool directory.
+fn newest_in(directory: &Path, name: &str) -> io::Result<Option<(String, PathBuf)>> {
+    let mut newest: Option<(String, PathBuf)> = None;
+    if !directory.exists() {
+        return Ok(None);
+    }
+
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+        if let Some((package, version)) = get_debian_package_info(&path) {
+            if package != name {
+                continue
+            }
+
+            let replace = newest.as_ref()
+                .map_or(true, |&(ref current, _)| compare_versions(current, &version) == Ordering::Less);
+
+            if replace {
+                newest = Some((version, path));
+            }
+        }
+    }
+
+    Ok(newest)
+}
+
+/// Executes the pool operations of a plan.
+///
+/// The dist files of the suite must be regenerated afterwards to apply the index changes.
+pub fn apply_plan(plan: &PublishPlan) -> io::Result<()> {
+    for operation in &plan.pool {
+        info!("moving {} to {}", operation.source.display(), operation.destination.display());
+        if let Some(parent) = operation.destination.parent() {
+            action::create_dir_all(parent)?;
+        }
+
+        action::rename(&operation.source, &operation.destination)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const CONFIG: &str = r#"
+archive = "bionic"
+version = "18.04"
+origin = "origin"
+label = "label"
+email = "info@example.com"
+"#;
+
+    #[test]
+    fn publish_plan_for_one_package() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        let incoming = dir.path().join("incoming");
+        let pooled = repo.join("pool/bionic/main/binary-amd64/f/foo");
+        fs::create_dir_all(&pooled).unwrap();
+        fs::create_dir_all(&incoming).unwrap();
+        misc::write(pooled.join("foo_1.0_amd64.deb"), b"").unwrap();
+        misc::write(incoming.join("foo_1.1_amd64.deb"), b"").unwrap();
+        misc::write(incoming.join("notes.txt"), b"").unwrap();
+
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let plan = plan_publish(&repo, &incoming, &config).unwrap();
+
+        let entry = |version: &str| IndexChange {
+            package: "foo".into(),
+            version: version.into(),
+            architecture: "amd64".into(),
+            component: "main".into(),
+        };
+
+        assert_eq!(plan, PublishPlan {
+            suite: "bionic".into(),
+            unreferenced: vec![pooled.join("foo_1.0_amd64.deb")],
+            pool: vec![PoolMove {
+                source: incoming.join("foo_1.1_amd64.deb"),
+                destination: pooled.join("foo_1.1_amd64.deb"),
+            }],
+            added: vec![entry("1.1")],
+            removed: vec![entry("1.0")],
+        });
+
+        let path = dir.path().join("plan.toml");
+        misc::write(&path, plan.to_toml().unwrap()).unwrap();
+        assert_eq!(PublishPlan::open(&path).unwrap(), plan);
+        assert!(!pooled.join("foo_1.1_amd64.deb").exists());
+
+        apply_plan(&plan).unwrap();
+        assert!(pooled.join("foo_1.1_amd64.deb").exists());
+    }
+}
@@ -1,88 +1,1136 @@
 use std::{io, fs};
+use std::fs::File;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use debian::DEB_SOURCE_EXTENSIONS;
+use action;
+use checksum::{hasher, PooledDigests};
+use config::{DebugSymbols, PolicyCheck, PoolPolicy};
+use debian::{compare_versions, lint_package, DebFile, DEBIAN_INSTALLER, DEB_INSTALLER_EXTENSION, DEB_SOURCE_EXTENSIONS};
+use error::Error;
+use interrupt;
+use libc;
 use misc;
+use sha2::Sha256;
+use std::cmp::Ordering;
+use walkdir::WalkDir;
 
 pub const KEEP_SOURCE: u8 = 1;
 pub const ARCHIVES_ONLY: u8 = 2;
+/// Replaces files in the pool whose contents differ, rather than refusing to pool them.
+pub const REPLACE: u8 = 8;
+/// Keeps files in the pool whose contents differ, and discards the file which would replace them,
+/// for files which are regenerated on each run, but are not reproducible.
+pub const KEEP_PUBLISHED: u8 = 16;
+/// Copies files into the pool, rather than moving them, so that the source is left untouched.
+const COPY: u8 = 4;
+/// Pools archives whose version is lower than that of an archive already published for the same
+/// package and architecture, rather than refusing them.
+pub const ALLOW_DOWNGRADE: u8 = 32;
 
-pub fn mv_to_pool<P: AsRef<Path>>(path: P, suite: &str, component: &str, flags: u8, filter: Option<&str>) -> io::Result<()> {
+pub fn mv_to_pool<P: AsRef<Path>>(
+    path: P,
+    pool_root: &Path,
+    suite: &str,
+    component: &str,
+    flags: u8,
+    filter: Option<&str>,
+    policy: &PoolPolicy,
+) -> io::Result<()> {
     info!("moving items in {} to pool at {}/{}", path.as_ref().display(), suite, component);
     pool(
         path.as_ref(),
+        pool_root,
         suite,
         component,
         flags,
+        policy,
         |src, dst| if flags & KEEP_SOURCE != 0 || !is_source(src) {
-            fs::rename(src, dst)
+            action::rename(src, dst)
         } else {
-            fs::remove_file(src)
+            action::remove_file(src)
         },
         filter
     )
 }
 
+/// Copies the files in `path` to the pool, such as prebuilt packages from a local directory.
+///
+/// Archives are checked just as they would be by `mv_to_pool`, but the source is left intact.
+/// Each file is hashed as it is copied, and its digests are recorded in `pooled`, so that it is
+/// only read once.
+pub fn cp_to_pool<P: AsRef<Path>>(
+    path: P,
+    pool_root: &Path,
+    suite: &str,
+    component: &str,
+    flags: u8,
+    filter: Option<&str>,
+    policy: &PoolPolicy,
+    pooled: &PooledDigests,
+) -> io::Result<()> {
+    info!("copying items in {} to pool at {}/{}", path.as_ref().display(), suite, component);
+    pool(
+        path.as_ref(),
+        pool_root,
+        suite,
+        component,
+        flags | COPY,
+        policy,
+        |src, dst| action::perform(format_args!("copy {} to {}", src.display(), dst.display()), || {
+            pooled.copy(src, dst)
+        }),
+        filter
+    )
+}
+
+/// Hard-links the files in `path` into the pool, so that a package which is published in several
+/// suites is only stored once. Files are copied instead where the pool is on another filesystem.
+pub fn link_to_pool<P: AsRef<Path>>(
+    path: P,
+    pool_root: &Path,
+    suite: &str,
+    component: &str,
+    flags: u8,
+    filter: Option<&str>,
+    policy: &PoolPolicy,
+) -> io::Result<()> {
+    info!("linking items in {} to pool at {}/{}", path.as_ref().display(), suite, component);
+    pool(
+        path.as_ref(),
+        pool_root,
+        suite,
+        component,
+        flags | COPY,
+        policy,
+        link_or_copy,
+        filter
+    )
+}
+
+fn link_or_copy(src: &Path, dst: &Path) -> io::Result<()> {
+    action::perform(format_args!("link {} to {}", src.display(), dst.display()), || {
+        // A file which differs from the source is replaced, as it would be by a copy.
+        if dst.exists() {
+            fs::remove_file(dst)?;
+        }
+
+        match fs::hard_link(src, dst) {
+            Err(ref why) if why.raw_os_error() == Some(libc::EXDEV) => {
+                debug!("{} is on another filesystem, and will be copied", src.display());
+                fs::copy(src, dst).map(|_| ())
+            }
+            result => result
+        }
+    })
+}
+
 fn is_source(src: &Path) -> bool {
     let path = src.to_str().unwrap();
-    path.ends_with(".dsc") || path.ends_with(".tar.gz") || path.ends_with(".tar.xz")
+    DEB_SOURCE_EXTENSIONS.into_iter().any(|ext| path.ends_with(ext))
 }
 
 fn is_archive(src: &Path) -> bool {
     let path = src.to_str().unwrap();
-    path.ends_with(".deb") || path.ends_with(".ddeb")
+    path.ends_with(".deb") || path.ends_with(".ddeb") || path.ends_with(DEB_INSTALLER_EXTENSION)
+}
+
+/// Computes the directory within the pool where the given file should be stored.
+///
+/// Installer packages, `.udeb`s, are stored in the `debian-installer` subcomponent of the
+/// component. Fails if a package name cannot be derived from the filename.
+pub fn pool_destination(pool: &Path, suite: &str, component: &str, filename: &str) -> io::Result<PathBuf> {
+    let mut package = &filename[..filename.find('_').unwrap_or(0)];
+    let is_source = DEB_SOURCE_EXTENSIONS.into_iter().any(|ext| filename.ends_with(&ext[1..]));
+    let mut pool = pool.join(suite).join(component);
+    if filename.ends_with(DEB_INSTALLER_EXTENSION) {
+        pool = pool.join(DEBIAN_INSTALLER);
+    }
+
+    if is_source {
+        Ok(pool.join("source").join(misc::pool_bucket(package)?).join(package))
+    } else {
+        if package.ends_with("-dbgsym") {
+            package = &package[..package.len() - 7];
+        }
+
+        let filestem = &filename[..filename.rfind('.').unwrap_or_else(|| filename.len())];
+        let arch = misc::get_arch_from_stem(filestem);
+        Ok(pool.join(["binary-", arch].concat()).join(misc::pool_bucket(package)?).join(package))
+    }
+}
+
+/// Whether the archive holds debug symbols, rather than a package of its own.
+fn is_debug_symbols(filename: &str) -> bool {
+    let package = &filename[..filename.find('_').unwrap_or(0)];
+    filename.ends_with(".ddeb") || (filename.ends_with(".deb") && package.ends_with("-dbgsym"))
+}
+
+/// Moves the debug symbol packages of every component of the suite's pool to the suite and
+/// component which they are routed to, returning their new paths.
+///
+/// Only the `binary-*` directories of each component are searched, so debug symbols which have
+/// already been routed to a subcomponent are left where they are.
+pub fn route_debug_symbols(
+    pool_root: &Path,
+    suite: &str,
+    routing: &DebugSymbols
+) -> io::Result<Vec<PathBuf>> {
+    let mut routed = Vec::new();
+    let pool = pool_root.join(suite);
+    if !pool.exists() {
+        return Ok(routed);
+    }
+
+    for component in pool.read_dir()? {
+        let component = component?;
+        if !component.path().is_dir() {
+            continue
+        }
+
+        let component_name = component.file_name();
+        let component_name = component_name.to_str().unwrap();
+        let (dst_suite, dst_component) = routing.destination(suite, component_name);
+
+        for arch in component.path().read_dir()? {
+            let arch = arch?;
+            if !arch.file_name().to_str().map_or(false, |x| x.starts_with("binary-")) {
+                continue
+            }
+
+            // Archives are stored at `binary-<arch>/<bucket>/<package>/<file>`.
+            let archives = WalkDir::new(arch.path())
+                .min_depth(3)
+                .max_depth(3)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.path().to_path_buf())
+                .collect::<Vec<PathBuf>>();
+
+            for path in archives {
+                let filename = path.file_name().and_then(|x| x.to_str()).unwrap();
+                if !is_debug_symbols(filename) {
+                    continue
+                }
+
+                let dst = pool_destination(pool_root, dst_suite, &dst_component, filename)?;
+                action::create_dir_all(&dst)?;
+                let dst = dst.join(filename);
+                info!("routing {} to {}", path.display(), dst.display());
+                action::rename(&path, &dst)?;
+                routed.push(dst);
+            }
+        }
+    }
+
+    Ok(routed)
+}
+
+/// Refuses an archive which breaks the policy of the pool, or whose filename names another
+/// architecture than its control file, as it would be refused when it is pooled. Files which are
+/// not archives are not checked.
+pub fn check_archive(path: &Path, policy: &PoolPolicy) -> io::Result<()> {
+    if !is_archive(path) {
+        return Ok(());
+    }
+
+    let stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+    check_policy(path, policy)?;
+    check_architecture(path, misc::get_arch_from_stem(stem))
+}
+
+/// Rejects archives which exceed the configured maximum package size, if a limit is set.
+fn check_package_size(path: &Path, limit: Option<u64>) -> io::Result<()> {
+    if let Some(limit) = limit {
+        let size = fs::metadata(path)?.len();
+        if size > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is {} bytes, which exceeds the maximum package size of {} bytes",
+                    path.display(),
+                    size,
+                    limit
+                )
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuses archives which break the policy of the pool, and warns of those which only break the
+/// checks that the policy warns of. Every violation of the archive is reported at once.
+fn check_policy(path: &Path, policy: &PoolPolicy) -> io::Result<()> {
+    let mut violations = Vec::new();
+    match check_package_size(path, policy.max_bytes) {
+        Err(ref why) if why.kind() == io::ErrorKind::InvalidData => {
+            violations.push((PolicyCheck::MaxBytes, why.to_string()));
+        }
+        result => result?
+    }
+
+    if policy.inspects() {
+        let archive = DebFile::open(path)?;
+        let control = archive.control()?.fields;
+        for field in policy.required_fields.iter().flat_map(|fields| fields.iter()) {
+            if control.get(field.as_str()).map_or(true, |value| value.trim().is_empty()) {
+                let why = format!("{} does not declare the {} field, which the policy requires", path.display(), field);
+                violations.push((PolicyCheck::RequiredFields, why));
+            }
+        }
+
+        let mut forbidden = Vec::new();
+        if policy.forbidden_paths.is_some() {
+            archive.each_data(|entry| {
+                if entry.header().entry_type().is_dir() {
+                    return Ok(());
+                }
+
+                let file = entry.path()?.to_string_lossy().trim_left_matches("./").trim_left_matches('/').to_owned();
+                let file = ["/", &file].concat();
+                if let Some(glob) = policy.forbids(&file) {
+                    forbidden.push((file, glob.to_owned()));
+                }
+
+                Ok(())
+            })?;
+        }
+
+        if let Some(&(ref file, ref glob)) = forbidden.first() {
+            let others = match forbidden.len() - 1 {
+                0 => String::new(),
+                others => format!(", along with {} other forbidden files", others)
+            };
+
+            let why = format!("{} installs {}, which the policy forbids by {}{}", path.display(), file, glob, others);
+            violations.push((PolicyCheck::ForbiddenPaths, why));
+        }
+    }
+
+    let mut refused = Vec::new();
+    for (check, why) in violations {
+        if policy.warns(check) {
+            warn!("{}", why);
+        } else {
+            refused.push(why);
+        }
+    }
+
+    if refused.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, refused.join("; ")))
+    }
 }
 
+/// Ensures that the architecture in the archive's filename agrees with its control file.
+///
+/// A mislabeled archive would otherwise be placed into the wrong pool directory, and indexed
+/// under an architecture that it cannot be installed on. Any other issues with the control file
+/// are reported as warnings before the archive is pooled.
+fn check_architecture(path: &Path, filename_arch: &str) -> io::Result<()> {
+    let control = DebFile::open(path)?.control()?.fields;
+    for lint in lint_package(&control) {
+        warn!("{}: {}", path.display(), lint);
+    }
+
+    match control.get("Architecture") {
+        Some(arch) if arch.as_str() == filename_arch => Ok(()),
+        Some(arch) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is named for the {} architecture, but its control file declares {}",
+                path.display(),
+                filename_arch,
+                arch
+            )
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not declare an architecture in its control file", path.display())
+        ))
+    }
+}
+
+/// Refuses an archive whose version is lower than the highest version which is already in the
+/// pool for its package and architecture, as apt would never upgrade to it.
+fn check_version_regression(path: &Path, destination: &Path, filename: &str) -> io::Result<()> {
+    if !destination.exists() {
+        return Ok(());
+    }
+
+    let (package, arch) = match (filename.find('_'), filename.rfind('.')) {
+        (Some(pos), Some(ext)) => (&filename[..pos + 1], misc::get_arch_from_stem(&filename[..ext])),
+        _ => return Ok(())
+    };
+
+    let mut published = Vec::new();
+    for entry in destination.read_dir()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue
+        };
+
+        let same_package = name != filename
+            && name.starts_with(package)
+            && name.rfind('.').map_or(false, |ext| misc::get_arch_from_stem(&name[..ext]) == arch)
+            && is_archive(&entry.path());
+
+        if same_package {
+            published.push(archive_version(&entry.path())?);
+        }
+    }
+
+    let version = archive_version(path)?;
+    match newer_version(&version, &published) {
+        Some(newer) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has version {}, which is lower than the published version {}; give the \
+                 package a higher version, or give --allow-downgrade to pool it",
+                path.display(),
+                version,
+                newer
+            )
+        )),
+        None => Ok(())
+    }
+}
+
+/// The `Version` in the control file of an archive.
+pub(super) fn archive_version(path: &Path) -> io::Result<String> {
+    DebFile::open(path)?.control()?.fields.get("Version").cloned().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} does not declare a version in its control file", path.display())
+    ))
+}
+
+/// The highest of the published versions, if it is higher than `version`.
+fn newer_version<'a>(version: &str, published: &'a [String]) -> Option<&'a str> {
+    published.iter()
+        .filter(|published| compare_versions(published, version) == Ordering::Greater)
+        .max_by(|a, b| compare_versions(a, b))
+        .map(|published| published.as_str())
+}
+
+/// Checks if a file already in the pool is identical to the file that would replace it.
+pub(super) fn same_contents(src: &Path, dst: &Path) -> io::Result<bool> {
+    if fs::metadata(src)?.len() != fs::metadata(dst)?.len() {
+        return Ok(false);
+    }
+
+    let src = File::open(src).and_then(hasher::<Sha256, File>)?;
+    let dst = File::open(dst).and_then(hasher::<Sha256, File>)?;
+    Ok(src == dst)
+}
+
+/// Applies the action to each file of `path`, and its destination within the pool.
+///
+/// This may be safely re-run after a partial failure. A file that has already been pooled with
+/// identical contents is removed from the source rather than pooled again, and any file that has
+/// since disappeared from the source is skipped. When a file can't be pooled, or the run is
+/// interrupted, the files which were newly placed in the pool are taken out again.
+///
+/// A file that is already in the pool with different contents is refused, unless `REPLACE` or
+/// `KEEP_PUBLISHED` is given, as clients which have downloaded the published file would fail to
+/// verify the new one. So is an archive with a lower version than one that is already published
+/// for its package and architecture, unless `ALLOW_DOWNGRADE` is given.
 fn pool<F: Fn(&Path, &Path) -> io::Result<()>>(
     path: &Path,
+    pool_root: &Path,
     suite: &str,
     component: &str,
     flags: u8,
+    policy: &PoolPolicy,
     action: F,
     filter: Option<&str>,
 ) -> io::Result<()> {
-    for entry in path.read_dir()? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() || (flags & ARCHIVES_ONLY != 0 && !is_archive(&path)) {
-            continue;
-        }
-        let filename = path.file_name().and_then(|x| x.to_str());
-        let filestem = path.file_stem().and_then(|x| x.to_str());
-
-        if let (Some(filename), Some(filestem)) = (filename, filestem) {
-            if let Some(name) = filter {
-                if !(filename.starts_with(&[name, "_"].concat())
-                    || filename.starts_with(&[name, "-dbgsym_"].concat()))
-                {
+    // The files which were placed in the pool, which are taken out again if another fails, so
+    // that the files of a source are either all pooled, or are all left where they were.
+    let mut placed: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let result = (|| -> io::Result<()> {
+        // Files are pooled in the order of their names, so that it is the same on every run.
+        let mut paths = path.read_dir()?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        paths.sort();
+
+        for path in paths {
+            interrupt::check()?;
+            if path.is_dir() || (flags & ARCHIVES_ONLY != 0 && !is_archive(&path)) {
+                continue;
+            }
+            let filename = path.file_name().and_then(|x| x.to_str());
+            let filestem = path.file_stem().and_then(|x| x.to_str());
+
+            if let (Some(filename), Some(filestem)) = (filename, filestem) {
+                if let Some(name) = filter {
+                    if !(filename.starts_with(&[name, "_"].concat())
+                        || filename.starts_with(&[name, "-dbgsym_"].concat()))
+                    {
+                        continue
+                    }
+                }
+
+                if !path.exists() {
+                    info!("{} was already moved out of the source", path.display());
                     continue
                 }
-            }
 
-            info!("migrating {} to pool", path.display());
-            let mut package = &filename[..filename.find('_').unwrap_or(0)];
+                check_archive(&path, policy)?;
 
-            let is_source = DEB_SOURCE_EXTENSIONS.into_iter().any(|ext| filename.ends_with(&ext[1..]));
-            let destination = if is_source {
-                PathBuf::from(
-                    ["repo/pool/", suite, "/", component, "/source/", &package[0..1], "/", package].concat()
-                )
-            } else {
-                if package.ends_with("-dbgsym") {
-                    package = &package[..package.len() - 7];
+                info!("migrating {} to pool", path.display());
+                let destination = pool_destination(pool_root, suite, component, filename)
+                    .map_err(|why| io::Error::new(why.kind(), format!("{}: {}", path.display(), why)))?;
+                let target = destination.join(filename);
+                let pool_error = |why| Error::Pool {
+                    file: path.clone(),
+                    destination: target.clone(),
+                    why
+                };
+
+                if target.exists() {
+                    if same_contents(&path, &target).map_err(pool_error)? {
+                        info!("{} is already in the pool", target.display());
+                        if flags & COPY == 0 {
+                            action::remove_file(&path)?;
+                        }
+                        continue
+                    }
+
+                    if flags & KEEP_PUBLISHED != 0 {
+                        warn!(
+                            "keeping the published {}, which differs from {} at the same version",
+                            target.display(),
+                            path.display()
+                        );
+
+                        if flags & COPY == 0 {
+                            action::remove_file(&path)?;
+                        }
+                        continue
+                    }
+
+                    if flags & REPLACE == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!(
+                                "{} is already in the pool, with different contents than {}; \
+                                 published files must not be replaced, so give the package a new \
+                                 version, or give --force to replace it",
+                                target.display(),
+                                path.display()
+                            )
+                        ));
+                    }
+
+                    warn!("replacing {}, which differs from {}", target.display(), path.display());
+                }
+
+                if is_archive(&path) && flags & ALLOW_DOWNGRADE == 0 {
+                    check_version_regression(&path, &destination, filename)?;
                 }
 
-                let arch = misc::get_arch_from_stem(filestem);
+                info!("creating in pool: {:?}", destination);
+                let existed = target.exists();
+                action::create_dir_all(&destination)
+                    .and_then(|_| action(&path, &target))
+                    .map_err(pool_error)?;
 
-                PathBuf::from(
-                    ["repo/pool/", suite, "/", component, "/binary-", arch, "/", &package[0..1], "/", package].concat(),
-                )
-            };
+                if !existed {
+                    placed.push((path.clone(), target.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    })();
+
+    if result.is_err() {
+        for &(ref src, ref dst) in placed.iter().rev() {
+            if let Err(why) = take_out(src, dst, flags) {
+                error!("failed to take {} out of the pool: {}", dst.display(), why);
+            }
+        }
+    }
+
+    result
+}
+
+/// Undoes the pooling of a file which was not in the pool before, where a copy is removed, and a
+/// moved file is moved back.
+fn take_out(src: &Path, dst: &Path, flags: u8) -> io::Result<()> {
+    if !dst.exists() {
+        return Ok(());
+    }
+
+    info!("taking {} out of the pool", dst.display());
+    if flags & COPY != 0 {
+        fs::remove_file(dst)
+    } else {
+        fs::rename(dst, src)
+    }
+}
+
+/// Locates hard-linked files within the pool whose link groups are no longer consistent.
+///
+/// Deduplicated artifacts are linked across suites under the same filename, so each member of a
+/// link group must share that filename, and every link to the inode must reside within the pool.
+/// A link that lives elsewhere may be overwritten in place, silently altering the pooled file.
+pub fn verify_dedup_integrity(pool: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut groups: HashMap<(u64, u64), (u64, Vec<PathBuf>)> = HashMap::new();
+
+    for entry in WalkDir::new(pool).into_iter().flat_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue
+        }
+
+        let metadata = entry.metadata().map_err(|why| Error::Walk {
+            directory: pool.to_path_buf(),
+            why: why.into()
+        })?;
+
+        if metadata.nlink() < 2 {
+            continue
+        }
+
+        groups.entry((metadata.dev(), metadata.ino()))
+            .or_insert_with(|| (metadata.nlink(), Vec::new()))
+            .1
+            .push(entry.path().to_path_buf());
+    }
+
+    let mut flagged = Vec::new();
+    for (_, (links, members)) in groups {
+        let consistent = members.len() as u64 == links
+            && members.windows(2).all(|pair| pair[0].file_name() == pair[1].file_name());
+
+        if !consistent {
+            warn!("hard link group is inconsistent: {:?}", members);
+            flagged.extend(members);
+        }
+    }
+
+    flagged.sort();
+    Ok(flagged)
+}
+
+/// A pool file which is not stored where `pool_destination` would place it.
+#[derive(Debug, PartialEq)]
+pub struct Misplacement {
+    pub suite: String,
+    pub path: PathBuf,
+    pub expected: PathBuf,
+}
+
+/// Verifies that each file of the suite's pool is stored where `pool_destination` places it,
+/// deriving the package and architecture of each archive from its control file rather than its
+/// path, and returning the files which are misplaced. This finds files of pools which were laid
+/// out by older versions, such as before libraries were bucketed by their `lib` prefix, or which
+/// were edited by hand, at any depth beneath each component.
+///
+/// If `fix` is set, the misplaced files are moved into place, and an archive whose filename
+/// disagrees with its control file is renamed as it would be named by `dpkg-name`. A misplaced
+/// file whose destination already holds the same contents is removed, but one which differs from
+/// its destination is left in place, as either may be published. The dist files of the suite
+/// must then be regenerated, so that each `Filename` points to the new location.
+pub fn check_pool_placement(pool_root: &Path, suite: &str, fix: bool) -> io::Result<Vec<Misplacement>> {
+    let pool = pool_root.join(suite);
+    if !pool.exists() {
+        return Ok(Vec::new());
+    }
+
+    let files = WalkDir::new(&pool)
+        .min_depth(2)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    let mut repaired = Vec::new();
+    for path in files {
+        let component = match path.strip_prefix(&pool).ok().and_then(pool_component) {
+            Some(component) => component,
+            None => continue
+        };
+
+        let filename = match canonical_filename(&path) {
+            Ok(filename) => filename,
+            Err(why) => {
+                warn!("unable to repair the placement of {}: {}", path.display(), why);
+                continue
+            }
+        };
+
+        let expected = match pool_destination(pool_root, suite, &component, &filename) {
+            Ok(destination) => destination.join(&filename),
+            Err(why) => {
+                warn!("unable to repair the placement of {}: {}", path.display(), why);
+                continue
+            }
+        };
+
+        if path == expected {
+            continue
+        }
+
+        if !fix {
+            warn!("{} is misplaced, and should be at {}", path.display(), expected.display());
+            repaired.push(Misplacement { suite: suite.to_owned(), path, expected });
+            continue
+        }
+
+        if expected.exists() {
+            if !same_contents(&path, &expected)? {
+                warn!("{} is misplaced, but {} differs from it, so it is left in place", path.display(), expected.display());
+                continue
+            }
+
+            info!("removing {}, which is already at {}", path.display(), expected.display());
+            action::remove_file(&path)?;
+        } else {
+            info!("moving {} to {}", path.display(), expected.display());
+            action::create_dir_all(expected.parent().unwrap())?;
+            action::rename(&path, &expected)?;
+        }
+
+        // Removes the directories that were left behind, up to the component, if now empty.
+        let component_dir = pool.join(&component);
+        for directory in path.ancestors().skip(1).take_while(|&dir| dir != component_dir.as_path()) {
+            if action::remove_dir(directory).is_err() {
+                break
+            }
+        }
+
+        repaired.push(Misplacement { suite: suite.to_owned(), path, expected });
+    }
+
+    Ok(repaired)
+}
+
+/// The component of a file from its path within the suite's pool, which is every directory before
+/// its `source`, `binary-*`, or `debian-installer` directory, so that subcomponents such as those
+/// which debug symbols are routed to are kept. If there is no such directory, the file's
+/// component is the first directory of its path.
+pub(super) fn pool_component(relative: &Path) -> Option<String> {
+    let directories = relative.parent()?.iter().map(|x| x.to_str()).collect::<Option<Vec<&str>>>()?;
+    let end = directories.iter()
+        .position(|&x| x == "source" || x == DEBIAN_INSTALLER || x.starts_with("binary-"))
+        .unwrap_or(1);
+
+    if end == 0 || directories.is_empty() {
+        None
+    } else {
+        Some(directories[..end].join("/"))
+    }
+}
+
+/// The filename that a pool file should have, which for an archive is derived from the package,
+/// version, and architecture of its control file. The epoch of the version is not included.
+fn canonical_filename(path: &Path) -> io::Result<String> {
+    let filename = path.file_name().and_then(|x| x.to_str()).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the filename is not valid UTF-8"
+    ))?;
+
+    if !is_archive(path) {
+        return Ok(filename.to_owned());
+    }
+
+    let control = DebFile::open(path)?.control()?.fields;
+    let field = |key: &str| control.get(key).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("its control file does not declare a {}", key)
+    ));
+
+    let (package, version, arch) = (field("Package")?, field("Version")?, field("Architecture")?);
+    let version = version.find(':').map_or(version.as_str(), |pos| &version[pos + 1..]);
+    let extension = &filename[filename.rfind('.').unwrap()..];
+    Ok([package.as_str(), "_", version, "_", arch.as_str(), extension].concat())
+}
+
+/// Sets the owner and group of every file and directory within the pool and dists of a repo.
+///
+/// Deployments which serve the repo directly, as `www-data` for example, may require this.
+/// Symlinks are not followed. Changing ownership requires root, so this fails early otherwise.
+pub fn set_pool_ownership(repo: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("setting ownership of {} to {}:{} requires root", repo.display(), uid, gid)
+        ));
+    }
+
+    for directory in &["pool", "dists"] {
+        let directory = repo.join(directory);
+        if !directory.exists() {
+            continue
+        }
+
+        for entry in WalkDir::new(&directory) {
+            let entry = entry.map_err(|why| Error::Walk {
+                directory: directory.clone(),
+                why: why.into()
+            })?;
+
+            if entry.file_type().is_symlink() {
+                continue
+            }
 
-            info!("creating in pool: {:?}", destination);
-            fs::create_dir_all(&destination)?;
-            action(&path, &destination.join(filename))?;
+            misc::chown(entry.path(), uid, gid).map_err(|why| io::Error::new(
+                why.kind(),
+                format!("failed to set ownership of {}: {}", entry.path().display(), why)
+            ))?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::ALL_DIGESTS;
+    use debian::{tarball, write_deb};
+    use tempfile::tempdir;
+
+    #[test]
+    fn dedup_integrity() {
+        let dir = tempdir().unwrap();
+        let pool = dir.path();
+        let bionic = pool.join("bionic/main/binary-amd64/f/foo");
+        let cosmic = pool.join("cosmic/main/binary-amd64/f/foo");
+        fs::create_dir_all(&bionic).unwrap();
+        fs::create_dir_all(&cosmic).unwrap();
+
+        let original = bionic.join("foo_1.0_amd64.deb");
+        misc::write(&original, b"foo").unwrap();
+        fs::hard_link(&original, cosmic.join("foo_1.0_amd64.deb")).unwrap();
+        assert!(verify_dedup_integrity(pool).unwrap().is_empty());
+
+        let corrupted = bionic.join("bar_1.0_amd64.deb");
+        misc::write(&corrupted, b"bar").unwrap();
+        fs::hard_link(&corrupted, cosmic.join("foo_2.0_amd64.deb")).unwrap();
+        assert_eq!(
+            verify_dedup_integrity(pool).unwrap(),
+            vec![corrupted, cosmic.join("foo_2.0_amd64.deb")]
+        );
+    }
+
+    #[test]
+    fn package_size_limit() {
+        let dir = tempdir().unwrap();
+        let package = dir.path().join("foo_1.0_amd64.deb");
+        misc::write(&package, &[0u8; 1024][..]).unwrap();
+
+        assert!(check_package_size(&package, None).is_ok());
+        assert!(check_package_size(&package, Some(1024)).is_ok());
+        assert_eq!(
+            check_package_size(&package, Some(1023)).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn pool_policy() {
+        let dir = tempdir().unwrap();
+        let package = dir.path().join("foo_1.0_amd64.deb");
+        let control = tarball(&[("control", &b"Package: foo\nVersion: 1.0\nMaintainer: Foo <foo@example.com>\n"[..])]).unwrap();
+        let data = tarball(&[("./usr/bin/foo", &b"foo"[..]), ("./usr/local/bin/foo", &b"foo"[..])]).unwrap();
+        write_deb(&package, &control, &data).unwrap();
+
+        let mut policy = PoolPolicy {
+            forbidden_paths: Some(vec!["/usr/local/*".into()]),
+            required_fields: Some(vec!["Maintainer".into(), "Homepage".into()]),
+            ..PoolPolicy::default()
+        };
+
+        let why = check_policy(&package, &policy).unwrap_err().to_string();
+        assert!(why.contains("does not declare the Homepage field") && !why.contains("Maintainer field"));
+        assert!(why.contains("installs /usr/local/bin/foo, which the policy forbids by /usr/local/*"));
+
+        policy.warn = Some(vec![PolicyCheck::RequiredFields]);
+        let why = check_policy(&package, &policy).unwrap_err().to_string();
+        assert!(!why.contains("Homepage"));
+
+        policy.warn = Some(vec![PolicyCheck::RequiredFields, PolicyCheck::ForbiddenPaths]);
+        policy.max_bytes = Some(1);
+        assert!(check_policy(&package, &policy).unwrap_err().to_string().contains("exceeds the maximum package size"));
+
+        policy.warn.as_mut().unwrap().push(PolicyCheck::MaxBytes);
+        assert!(check_policy(&package, &policy).is_ok());
+    }
+
+    #[test]
+    fn mislabeled_architecture() {
+        let dir = tempdir().unwrap();
+        let build = dir.path().join("build");
+        let pool = dir.path().join("pool");
+        fs::create_dir_all(&build).unwrap();
+
+        let package = build.join("foo_1.0_amd64.deb");
+        let control = tarball(&[("control", &b"Package: foo\nVersion: 1.0\nArchitecture: arm64\n"[..])]).unwrap();
+        write_deb(&package, &control, &tarball(&[]).unwrap()).unwrap();
+
+        let why = mv_to_pool(&build, &pool, "bionic", "main", 0, None, &PoolPolicy::default()).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+        assert!(why.to_string().contains("named for the amd64 architecture, but its control file declares arm64"));
+        assert!(package.exists());
+        assert!(!pool.join("bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb").exists());
+        assert!(!pool.join("bionic/main/binary-arm64").exists());
+    }
+
+    #[test]
+    fn debug_symbol_routing() {
+        let dir = tempdir().unwrap();
+        let pool = dir.path();
+        let foo = pool.join("bionic/main/binary-amd64/f/foo");
+        fs::create_dir_all(&foo).unwrap();
+        misc::write(foo.join("foo_1.0_amd64.deb"), b"foo").unwrap();
+        misc::write(foo.join("foo-dbgsym_1.0_amd64.deb"), b"foo").unwrap();
+        misc::write(foo.join("foo-dbgsym_1.0_amd64.ddeb"), b"foo").unwrap();
+
+        let routing = DebugSymbols::Component { component: "debug".into() };
+        let debug = pool.join("bionic/main/debug/binary-amd64/f/foo");
+        let mut routed = route_debug_symbols(pool, "bionic", &routing).unwrap();
+        routed.sort();
+        assert_eq!(routed, vec![
+            debug.join("foo-dbgsym_1.0_amd64.ddeb"),
+            debug.join("foo-dbgsym_1.0_amd64.deb"),
+        ]);
+        assert!(foo.join("foo_1.0_amd64.deb").exists());
+        assert!(route_debug_symbols(pool, "bionic", &routing).unwrap().is_empty());
+
+        let routing = DebugSymbols::Suite { suite: "bionic-debug".into() };
+        routed = route_debug_symbols(pool, "bionic", &routing).unwrap();
+        assert!(routed.is_empty());
+        let dbgsym = "foo-dbgsym_1.0_amd64.deb";
+        fs::rename(debug.join(dbgsym), foo.join(dbgsym)).unwrap();
+        assert_eq!(
+            route_debug_symbols(pool, "bionic", &routing).unwrap(),
+            vec![pool.join("bionic-debug/main/binary-amd64/f/foo/foo-dbgsym_1.0_amd64.deb")]
+        );
+    }
+
+    #[test]
+    fn copy_to_pool() {
+        let dir = tempdir().unwrap();
+        let vendor = dir.path().join("vendor");
+        let pool = dir.path().join("pool");
+        fs::create_dir_all(&vendor).unwrap();
+        misc::write(vendor.join("foo_1.0.dsc"), b"foo").unwrap();
+
+        let pooled = PooledDigests::new(ALL_DIGESTS);
+        for _ in 0..2 {
+            cp_to_pool(&vendor, &pool, "bionic", "main", 0, None, &PoolPolicy::default(), &pooled).unwrap();
+            assert!(vendor.join("foo_1.0.dsc").exists());
+            assert!(pool.join("bionic/main/source/f/foo/foo_1.0.dsc").exists());
+        }
+
+        // The file was only copied once, as it was already in the pool the second time.
+        let digests = pooled.take();
+        assert_eq!(digests.len(), 1);
+        assert_eq!(digests.values().next().unwrap().sha256, hasher::<Sha256, &[u8]>(b"foo").unwrap());
+    }
+
+    #[test]
+    fn pool_rollback() {
+        let dir = tempdir().unwrap();
+        let build = dir.path().join("build");
+        let pool = dir.path().join("pool");
+        fs::create_dir_all(&build).unwrap();
+        for file in &["foo_1.0.dsc", "foo_1.0.tar.xz", "foo_1.0_amd64.deb"] {
+            misc::write(build.join(file), b"foo").unwrap();
+        }
+
+        // The corrupt package is pooled after the source, and as it can't be pooled, the source
+        // which was already placed is taken out again.
+        assert!(mv_to_pool(&build, &pool, "bionic", "main", KEEP_SOURCE, None, &PoolPolicy::default()).is_err());
+        assert!(build.join("foo_1.0.dsc").exists() && build.join("foo_1.0.tar.xz").exists());
+        assert!(!pool.join("bionic/main/source/f/foo/foo_1.0.dsc").exists());
+
+        fs::remove_file(build.join("foo_1.0_amd64.deb")).unwrap();
+        mv_to_pool(&build, &pool, "bionic", "main", KEEP_SOURCE, None, &PoolPolicy::default()).unwrap();
+        assert!(pool.join("bionic/main/source/f/foo/foo_1.0.dsc").exists());
+    }
+
+    #[test]
+    fn link_into_pool() {
+        let dir = tempdir().unwrap();
+        let vendor = dir.path().join("vendor");
+        let pool = dir.path().join("pool");
+        fs::create_dir_all(&vendor).unwrap();
+        misc::write(vendor.join("foo_1.0.dsc"), b"foo").unwrap();
+
+        for suite in &["bionic", "cosmic"] {
+            link_to_pool(&vendor, &pool, suite, "main", 0, None, &PoolPolicy::default()).unwrap();
+        }
+
+        let linked = pool.join("cosmic/main/source/f/foo/foo_1.0.dsc");
+        assert_eq!(fs::metadata(&linked).unwrap().nlink(), 3);
+        assert_eq!(
+            fs::metadata(&linked).unwrap().ino(),
+            fs::metadata(vendor.join("foo_1.0.dsc")).unwrap().ino()
+        );
+    }
+
+    #[test]
+    fn degenerate_filenames() {
+        let pool = Path::new("repo/pool");
+        assert_eq!(
+            pool_destination(pool, "bionic", "main", "foo_1.0_amd64.deb").unwrap(),
+            pool.join("bionic/main/binary-amd64/f/foo")
+        );
+        assert!(pool_destination(pool, "bionic", "main", "_1.0_amd64.deb").is_err());
+        assert!(pool_destination(pool, "bionic", "main", "foo.deb").is_err());
+        assert_eq!(
+            pool_destination(pool, "bionic", "main", "foo-udeb_1.0_amd64.udeb").unwrap(),
+            pool.join("bionic/main/debian-installer/binary-amd64/f/foo-udeb")
+        );
+    }
+
+    #[test]
+    fn pool_ownership() {
+        let dir = tempdir().unwrap();
+        let package = dir.path().join("pool/bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb");
+        fs::create_dir_all(package.parent().unwrap()).unwrap();
+        misc::write(&package, b"foo").unwrap();
+
+        let result = set_pool_ownership(dir.path(), 65534, 65534);
+        if unsafe { libc::geteuid() } == 0 {
+            result.unwrap();
+            let metadata = fs::metadata(&package).unwrap();
+            assert_eq!((metadata.uid(), metadata.gid()), (65534, 65534));
+        } else {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        }
+    }
+
+    #[test]
+    fn pool_placement() {
+        let dir = tempdir().unwrap();
+        let pool_root = dir.path().join("pool");
+        let pool = pool_root.join("bionic/main/binary-amd64");
+        let misplaced = pool.join("l/libssl1.1/libssl1.1_1.1.0g-2ubuntu4_amd64.deb");
+        let placed = pool.join("f/foo/foo_1.0_amd64.deb");
+        for &(file, package, version) in &[(&misplaced, "libssl1.1", "1.1.0g-2ubuntu4"), (&placed, "foo", "1.0")] {
+            let fields = format!("Package: {}\nVersion: {}\nArchitecture: amd64\n", package, version);
+            let control = tarball(&[("control", fields.as_bytes())]).unwrap();
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            write_deb(file, &control, &tarball(&[]).unwrap()).unwrap();
+        }
+
+        let expected = pool.join("libs/libssl1.1/libssl1.1_1.1.0g-2ubuntu4_amd64.deb");
+        let report = vec![Misplacement {
+            suite: "bionic".into(),
+            path: misplaced.clone(),
+            expected: expected.clone(),
+        }];
+
+        assert_eq!(check_pool_placement(&pool_root, "bionic", false).unwrap(), report);
+        assert!(misplaced.exists());
+
+        assert_eq!(check_pool_placement(&pool_root, "bionic", true).unwrap(), report);
+        assert!(expected.exists() && !pool.join("l").exists() && placed.exists());
+        assert!(check_pool_placement(&pool_root, "bionic", false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pool_repair() {
+        let dir = tempdir().unwrap();
+        let pool_root = dir.path().join("pool");
+        let main = pool_root.join("bionic/main");
+        let placed = main.join("source/f/foo/foo_1.0.dsc");
+        let misplaced = main.join("f/foo/foo_1.0.tar.xz");
+        let duplicate = main.join("foo_1.0.dsc");
+        let conflict = main.join("source/b/bar_1.0.dsc");
+        let published = main.join("source/b/bar/bar_1.0.dsc");
+        let subcomponent = main.join("debug/source/f/foo/foo_1.0.dsc");
+        for (file, contents) in &[
+            (&placed, "foo"),
+            (&misplaced, "foo"),
+            (&duplicate, "foo"),
+            (&conflict, "bar"),
+            (&published, "baz"),
+            (&subcomponent, "foo"),
+        ] {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            misc::write(file, contents.as_bytes()).unwrap();
+        }
+
+        let repaired = check_pool_placement(&pool_root, "bionic", true).unwrap();
+        let expected = main.join("source/f/foo/foo_1.0.tar.xz");
+        assert_eq!(repaired, vec![
+            Misplacement { suite: "bionic".into(), path: misplaced.clone(), expected: expected.clone() },
+            Misplacement { suite: "bionic".into(), path: duplicate.clone(), expected: placed.clone() },
+        ]);
+
+        assert!(expected.exists() && placed.exists() && !duplicate.exists());
+        assert!(!main.join("f").exists() && conflict.exists() && published.exists() && subcomponent.exists());
+        assert!(check_pool_placement(&pool_root, "bionic", true).unwrap().is_empty());
+        assert!(check_pool_placement(&pool_root, "cosmic", true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resumable_pooling() {
+        let dir = tempdir().unwrap();
+        let build = dir.path().join("build");
+        let pool_root = dir.path().join("pool");
+        let source = pool_root.join("bionic/main/source");
+        fs::create_dir_all(&build).unwrap();
+
+        for file in &["bar_1.0.dsc", "baz_1.0.dsc", "foo_1.0.dsc"] {
+            misc::write(build.join(file), file.as_bytes()).unwrap();
+        }
+
+        // Simulates a failure after `bar` was copied into the pool, but before its source was removed.
+        fs::create_dir_all(source.join("b/bar")).unwrap();
+        misc::write(source.join("b/bar/bar_1.0.dsc"), b"bar_1.0.dsc").unwrap();
+
+        let run = || pool(&build, &pool_root, "bionic", "main", 0, None, |src, dst| fs::rename(src, dst), None);
+        run().unwrap();
+        run().unwrap();
+
+        assert_eq!(build.read_dir().unwrap().count(), 0);
+        for file in &["b/bar/bar_1.0.dsc", "b/baz/baz_1.0.dsc", "f/foo/foo_1.0.dsc"] {
+            let name = Path::new(file).file_name().unwrap().to_str().unwrap();
+            assert_eq!(misc::read_to_string(source.join(file)).unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn published_files_are_not_replaced() {
+        let dir = tempdir().unwrap();
+        let build = dir.path().join("build");
+        let pool_root = dir.path().join("pool");
+        let published = pool_root.join("bionic/main/source/f/foo/foo_1.0.dsc");
+        fs::create_dir_all(&build).unwrap();
+        fs::create_dir_all(published.parent().unwrap()).unwrap();
+        misc::write(&published, b"published").unwrap();
+        misc::write(build.join("foo_1.0.dsc"), b"rebuilt").unwrap();
+
+        let why = mv_to_pool(&build, &pool_root, "bionic", "main", KEEP_SOURCE, None, &PoolPolicy::default()).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(misc::read_to_string(&published).unwrap(), "published");
+        assert!(build.join("foo_1.0.dsc").exists());
+
+        mv_to_pool(&build, &pool_root, "bionic", "main", KEEP_SOURCE | REPLACE, None, &PoolPolicy::default()).unwrap();
+        assert_eq!(misc::read_to_string(&published).unwrap(), "rebuilt");
+    }
+
+    #[test]
+    fn version_regressions() {
+        let published = vec!["1.0-1".to_owned(), "1:0.9-1".to_owned(), "1.2-1".to_owned()];
+        assert_eq!(newer_version("1.5-1", &published), Some("1:0.9-1"));
+        assert_eq!(newer_version("1:1.0-1", &published), None);
+        assert_eq!(newer_version("1:0.9-1", &published), None);
+        assert_eq!(newer_version("1.1", &["1.0-1".to_owned()]), None);
+        assert_eq!(newer_version("1.0", &[]), None);
+    }
+}
@@ -1,31 +1,42 @@
-use std::{fs, io};
+use action;
+use std::io;
 use std::path::{Path, PathBuf};
 use config::Config;
 use debian::DEB_SOURCE_EXTENSIONS;
+use misc;
 use super::version::changelog;
 use walkdir::{DirEntry, WalkDir};
 
 pub const CACHED_ASSETS: &str = "assets/cache/";
 pub const SHARED_ASSETS: &str = "assets/share/";
 pub const PACKAGE_ASSETS: &str = "assets/packages/";
+pub const SOURCES_CACHE: &str = "sources/cache/";
+pub const SOURCES_KEYS: &str = "sources/keys/";
+/// Where the prepared trees of sources are cached, by the digest of their inputs.
+pub const SOURCES_TREES: &str = "sources/trees/";
+
+/// The location of an upstream tarball within the sources cache, which is keyed by its SHA256.
+pub fn cached_source(url: &str, sha256: &str) -> PathBuf {
+    PathBuf::from([SOURCES_CACHE, sha256, "/", misc::filename_from_url(url)].concat())
+}
 
 pub fn create_missing_directories(suite: &str) -> io::Result<()> {
     let record = ["record/", suite].concat();
     let logs = ["logs/", suite].concat();
     let build = ["build/", suite].concat();
-    [CACHED_ASSETS, SHARED_ASSETS, PACKAGE_ASSETS, &build, &record, &logs].iter()
-        .map(|dir| if Path::new(dir).exists() { Ok(()) } else { fs::create_dir_all(dir) })
+    [CACHED_ASSETS, SHARED_ASSETS, PACKAGE_ASSETS, SOURCES_CACHE, &build, &record, &logs].iter()
+        .map(|dir| if Path::new(dir).exists() { Ok(()) } else { action::create_dir_all(dir) })
         .collect::<io::Result<()>>()
 }
 
 pub fn package_cleanup(config: &Config) -> io::Result<()> {
-    let path = PathBuf::from(["repo/pool/", &config.archive, "/", &config.default_component].concat());
-    for entry in WalkDir::new(path).min_depth(3).max_depth(3).into_iter().filter_map(|x| x.ok()) {
+    let pool = config.pool_path();
+    for entry in WalkDir::new(&pool).min_depth(4).max_depth(4).into_iter().filter_map(|x| x.ok()) {
         let path = entry.path();
         if let Some(filename) = path.file_name().and_then(|x| x.to_str()) {
             if !config.package_exists(filename) {
                 info!("removing files at {:?}", path);
-                fs::remove_dir_all(path)?;
+                action::remove_dir_all(path)?;
             }
         }
     }
@@ -37,11 +48,11 @@ pub fn package_cleanup(config: &Config) -> io::Result<()> {
                     let cpath = PathBuf::from(["debian/", &config.archive, "/", &source.name, "/changelog"].concat());
                     if cpath.exists() {
                         let keep = changelog(&cpath, source.retain)?;
-                        for (file, version) in locate_files(&source.name, &config.archive) {
+                        for (file, version) in locate_files(&source.name, &pool) {
                             if !keep.iter().any(|x| version.as_str() == x.as_str()) {
                                 let path = file.path();
                                 info!("removing file at {:?}", path);
-                                fs::remove_file(path)?;
+                                action::remove_file(path)?;
                             }
                         }
                     }
@@ -57,30 +68,105 @@ pub fn build_directories(suite: &str) -> io::Result<()> {
     let path = PathBuf::from(["build/", suite].concat());
     if path.exists() {
         debug!("removing {}", path.display());
-        fs::remove_dir_all(&path)?;
+        action::remove_dir_all(&path)?;
     }
 
-    fs::create_dir_all(&path)
+    action::create_dir_all(&path)
 }
 
-pub fn remove(packages: &[&str], suite: &str, component: &str) -> io::Result<()> {
-    let path = PathBuf::from(["repo/pool/", suite, "/", component].concat());
-    for entry in WalkDir::new(path).min_depth(3).max_depth(3).into_iter().filter_map(|x| x.ok()) {
-        let path = entry.path();
-        if let Some(filename) = path.file_name().and_then(|x| x.to_str()) {
-            if packages.contains(&filename) {
-                info!("removing files at {:?}", path);
-                fs::remove_dir_all(path)?;
+/// Removes the files of each package from every component of the suite's pool, returning the
+/// files that were removed.
+///
+/// A package may name either a source, which removes every file kept for the source, or a
+/// binary. If a version is given, only the files of that version are removed. An orig tarball
+/// is only removed with the last revision in the pool which uses it.
+pub fn remove(pool: &Path, packages: &[&str], version: Option<&str>) -> io::Result<Vec<PathBuf>> {
+    // File names never contain the epoch of a version.
+    let version = version.map(|v| v.find(':').map_or(v, |pos| &v[pos + 1..]));
+
+    let mut removed = Vec::new();
+    for &package in packages {
+        let files = WalkDir::new(pool)
+            .into_iter()
+            .filter_map(|x| x.ok())
+            .filter(|x| x.file_type().is_file())
+            .filter(|entry| {
+                let source = entry.path().parent()
+                    .and_then(|x| x.file_name())
+                    .map_or(false, |x| x == package);
+
+                source || entry.file_name().to_str()
+                    .and_then(|x| x.split('_').next())
+                    .map_or(false, |name| {
+                        name == package || (name.ends_with("-dbgsym") && &name[..name.len() - 7] == package)
+                    })
+            })
+            .map(|entry| entry.path().to_path_buf())
+            .collect::<Vec<PathBuf>>();
+
+        let mut matched = Vec::new();
+        for file in &files {
+            let (file_version, orig) = match file_version(file) {
+                Some(found) => found,
+                None => continue
+            };
+
+            let version = match version {
+                Some(version) => version,
+                None => {
+                    matched.push(file.clone());
+                    continue
+                }
+            };
+
+            if file_version == version {
+                matched.push(file.clone());
+            } else if orig && upstream(version) == file_version {
+                // Other revisions which share the orig tarball keep it in the pool.
+                let shared = files.iter()
+                    .filter(|other| other.parent() == file.parent())
+                    .filter_map(|other| file_version(other))
+                    .any(|(other, orig)| !orig && other != version && upstream(&other) == file_version);
+
+                if !shared {
+                    matched.push(file.clone());
+                }
             }
         }
+
+        if matched.is_empty() {
+            warn!("{}: no files to remove from {}", package, pool.display());
+        }
+
+        for file in matched {
+            info!("removing file at {}", file.display());
+            action::remove_file(&file)?;
+            removed.push(file);
+        }
     }
 
-    Ok(())
+    Ok(removed)
+}
+
+/// The version within a pool file's name, and whether the file is an orig tarball, whose
+/// version is the upstream version.
+pub fn file_version(path: &Path) -> Option<(String, bool)> {
+    let version = get_version(path.file_name()?.to_str()?.split('_').nth(1)?);
+    Some(if version.ends_with(".orig") {
+        (version[..version.len() - 5].to_owned(), true)
+    } else if version.ends_with(".debian") {
+        (version[..version.len() - 7].to_owned(), false)
+    } else {
+        (version.to_owned(), false)
+    })
 }
 
-fn locate_files(name: &str, archive: &str) -> Vec<(DirEntry, String)> {
-    let path = PathBuf::from(["repo/pool/", archive, "/"].concat());
+/// The upstream version of a version, which excludes the debian revision.
+pub fn upstream(version: &str) -> &str {
+    version.rfind('-').map_or(version, |pos| &version[..pos])
+}
 
+fn locate_files(name: &str, pool: &Path) -> Vec<(DirEntry, String)> {
     fn matches(entry: &DirEntry, name: &str) -> bool {
         if entry.path().is_dir() {
             true
@@ -93,7 +179,7 @@ fn locate_files(name: &str, archive: &str) -> Vec<(DirEntry, String)> {
         }
     }
 
-    WalkDir::new(path)
+    WalkDir::new(pool)
         .into_iter()
         .filter_entry(|e| matches(e, name))
         .flat_map(|e| e.ok().and_then(|e| if e.path().is_file() { Some(e) } else { None }))
@@ -113,3 +199,51 @@ fn get_version(e: &str) -> &str {
 
     e
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn remove_package_versions() {
+        let dir = tempdir().unwrap();
+        let pool = dir.path();
+        let main = pool.join("main/f/foo");
+        let universe = pool.join("universe/b/bar");
+        fs::create_dir_all(&main).unwrap();
+        fs::create_dir_all(&universe).unwrap();
+
+        let files = [
+            "foo_1.0.orig.tar.gz",
+            "foo_1.0-1.debian.tar.xz",
+            "foo_1.0-1.dsc",
+            "foo_1.0-2.debian.tar.xz",
+            "foo_1.0-2.dsc",
+            "libfoo1_1.0-1_amd64.deb",
+            "libfoo1_1.0-2_amd64.deb",
+        ];
+
+        for file in &files {
+            misc::write(main.join(file), "").unwrap();
+        }
+
+        misc::write(universe.join("bar_2.0_all.deb"), "").unwrap();
+
+        // The orig tarball remains for the revision which still uses it.
+        let removed = remove(pool, &["foo"], Some("1.0-1")).unwrap();
+        assert_eq!(removed.len(), 3);
+        assert!(main.join("foo_1.0.orig.tar.gz").exists());
+        assert!(main.join("foo_1.0-2.dsc").exists());
+        assert!(!main.join("libfoo1_1.0-1_amd64.deb").exists());
+
+        let removed = remove(pool, &["libfoo1"], Some("1:1.0-2")).unwrap();
+        assert_eq!(removed, vec![main.join("libfoo1_1.0-2_amd64.deb")]);
+
+        remove(pool, &["foo", "bar"], None).unwrap();
+        assert_eq!(fs::read_dir(&main).unwrap().count(), 0);
+        assert!(!universe.join("bar_2.0_all.deb").exists());
+    }
+}
@@ -0,0 +1,241 @@
+use action;
+use debian::{compare_versions, parse_control};
+use misc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use super::pool::archive_version;
+use super::prepare::{file_version, upstream};
+use walkdir::WalkDir;
+
+/// What a file within the pool is an artifact of.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum Artifact {
+    /// The binary package of an architecture.
+    Binary(String),
+    /// The `.dsc`, debian tarball, or native tarball of a source.
+    Source,
+    /// The orig tarball of a source, which may be shared between revisions.
+    Orig,
+}
+
+/// Removes every version of each package in the pool which is older than the `keep` newest.
+///
+/// The versions of each binary package are compared for each architecture, and the versions of
+/// each source package are compared by their `.dsc` files. As the names of pooled files omit the
+/// epochs of their versions, versions are compared as their control files and `.dsc` declare
+/// them. An orig tarball is removed once no remaining revision of its source uses it. Returns the
+/// files that were removed.
+pub fn prune(pool: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let keep = keep.max(1);
+
+    // Files are grouped by their directory, package name, and artifact, and then by version.
+    let mut groups: BTreeMap<(PathBuf, String, Artifact), BTreeMap<String, Vec<PathBuf>>> = BTreeMap::new();
+    for entry in WalkDir::new(pool).into_iter().filter_map(|x| x.ok()) {
+        if !entry.file_type().is_file() {
+            continue
+        }
+
+        let path = entry.path();
+        let (name, artifact) = match artifact(path) {
+            Some(artifact) => artifact,
+            None => continue
+        };
+
+        let (version, _) = match file_version(path) {
+            Some(version) => version,
+            None => continue
+        };
+
+        let parent = path.parent().unwrap_or(pool).to_path_buf();
+        groups.entry((parent, name, artifact))
+            .or_insert_with(BTreeMap::new)
+            .entry(version)
+            .or_insert_with(Vec::new)
+            .push(path.to_path_buf());
+    }
+
+    let mut removed = Vec::new();
+    let mut kept_upstreams = HashSet::new();
+    let mut pruned_upstreams = HashSet::new();
+
+    for (&(ref parent, ref name, ref artifact), versions) in &groups {
+        if *artifact == Artifact::Orig {
+            continue
+        }
+
+        let declared = versions.iter()
+            .map(|(version, files)| {
+                declared_version(artifact, files).map(|declared| (version, declared.unwrap_or_else(|| version.clone())))
+            })
+            .collect::<io::Result<HashMap<&String, String>>>()?;
+
+        let mut ordered: Vec<&String> = versions.keys().collect();
+        ordered.sort_by(|a, b| compare_versions(&declared[b], &declared[a]));
+
+        for (position, version) in ordered.into_iter().enumerate() {
+            let key = (parent.clone(), name.clone(), upstream(version).to_owned());
+            if position < keep {
+                if *artifact == Artifact::Source {
+                    kept_upstreams.insert(key);
+                }
+
+                continue
+            }
+
+            if *artifact == Artifact::Source {
+                pruned_upstreams.insert(key);
+            }
+
+            for file in &versions[version] {
+                info!("pruning superseded file at {}", file.display());
+                action::remove_file(file)?;
+                removed.push(file.clone());
+            }
+        }
+    }
+
+    for (&(ref parent, ref name, ref artifact), versions) in &groups {
+        if *artifact != Artifact::Orig {
+            continue
+        }
+
+        for (version, files) in versions {
+            let key = (parent.clone(), name.clone(), version.clone());
+            if pruned_upstreams.contains(&key) && !kept_upstreams.contains(&key) {
+                for file in files {
+                    info!("pruning unused orig tarball at {}", file.display());
+                    action::remove_file(file)?;
+                    removed.push(file.clone());
+                }
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+/// The version that the files of a version of an artifact declare, which is read from the control
+/// file of a binary, or from the `.dsc` of a source. A source without its `.dsc` declares none.
+fn declared_version(artifact: &Artifact, files: &[PathBuf]) -> io::Result<Option<String>> {
+    match *artifact {
+        Artifact::Binary(_) => files.first().map_or(Ok(None), |file| archive_version(file).map(Some)),
+        Artifact::Source => {
+            let dsc = match files.iter().find(|file| file.extension().map_or(false, |ext| ext == "dsc")) {
+                Some(dsc) => dsc,
+                None => return Ok(None)
+            };
+
+            parse_control(&misc::read_to_string(dsc)?).get("Version").cloned().map(Some).ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not declare a version", dsc.display())
+            ))
+        }
+        Artifact::Orig => Ok(None),
+    }
+}
+
+/// Determines the package name and artifact of a file in the pool, by its file name.
+fn artifact(path: &Path) -> Option<(String, Artifact)> {
+    let filename = path.file_name()?.to_str()?;
+    let mut fields = filename.split('_');
+    let name = fields.next()?.to_owned();
+    fields.next()?;
+
+    let artifact = match fields.next() {
+        Some(arch) if arch.ends_with(".deb") || arch.ends_with(".ddeb") => {
+            Artifact::Binary(arch[..arch.rfind('.')?].to_owned())
+        }
+        Some(_) => return None,
+        None if file_version(path)?.1 => Artifact::Orig,
+        None => Artifact::Source,
+    };
+
+    Some((name, artifact))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use debian::{tarball, write_deb};
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Writes a file of the pool, as a binary package or a `.dsc` which declares the version.
+    fn write_pooled(pool: &Path, file: &str, version: &str) {
+        if file.ends_with(".deb") {
+            let control = format!("Package: {}\nVersion: {}\n", &file[..file.find('_').unwrap()], version);
+            let control = tarball(&[("control", control.as_bytes())]).unwrap();
+            write_deb(pool.join(file), &control, &tarball(&[]).unwrap()).unwrap();
+        } else {
+            misc::write(pool.join(file), format!("Version: {}\n", version)).unwrap();
+        }
+    }
+
+    #[test]
+    fn prune_superseded_versions() {
+        let dir = tempdir().unwrap();
+        let pool = dir.path().join("main/f/foo");
+        fs::create_dir_all(&pool).unwrap();
+
+        let files = [
+            "foo_1.9.orig.tar.gz",
+            "foo_1.9-1.debian.tar.xz",
+            "foo_1.9-1.dsc",
+            "foo_1.10.orig.tar.gz",
+            "foo_1.10-1.debian.tar.xz",
+            "foo_1.10-1.dsc",
+            "foo_1.10-2.debian.tar.xz",
+            "foo_1.10-2.dsc",
+            "foo_1.9-1_amd64.deb",
+            "foo_1.10-1_amd64.deb",
+            "foo_1.10-2_amd64.deb",
+            "foo_1.10-1_i386.deb",
+        ];
+
+        for file in &files {
+            write_pooled(&pool, file, &file_version(&pool.join(file)).unwrap().0);
+        }
+
+        let removed = prune(dir.path(), 2).unwrap();
+        let mut removed = removed.iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<&str>>();
+        removed.sort();
+
+        // 1.10 is newer than 1.9, and its orig tarball is still used by the remaining revisions.
+        assert_eq!(removed, vec![
+            "foo_1.9-1.debian.tar.xz",
+            "foo_1.9-1.dsc",
+            "foo_1.9-1_amd64.deb",
+            "foo_1.9.orig.tar.gz",
+        ]);
+
+        assert!(prune(dir.path(), 1).unwrap().iter().all(|path| {
+            path.file_name().unwrap().to_str().unwrap().contains("1.10-1")
+        }));
+        assert!(pool.join("foo_1.10.orig.tar.gz").exists());
+        assert!(pool.join("foo_1.10-1_i386.deb").exists());
+
+        // An epoch is not in the names of the files, so 1:1.0 is kept over 2.0.
+        let dir = tempdir().unwrap();
+        let pool = dir.path().join("main/b/bar");
+        fs::create_dir_all(&pool).unwrap();
+        for &(file, version) in &[
+            ("bar_2.0.dsc", "2.0"),
+            ("bar_2.0.tar.xz", "2.0"),
+            ("bar_2.0_amd64.deb", "2.0"),
+            ("bar_1.0.dsc", "1:1.0"),
+            ("bar_1.0.tar.xz", "1:1.0"),
+            ("bar_1.0_amd64.deb", "1:1.0"),
+        ] {
+            write_pooled(&pool, file, version);
+        }
+
+        let mut removed = prune(dir.path(), 1).unwrap().iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_owned())
+            .collect::<Vec<String>>();
+        removed.sort();
+        assert_eq!(removed, vec!["bar_2.0.dsc", "bar_2.0.tar.xz", "bar_2.0_amd64.deb"]);
+    }
+}
@@ -0,0 +1,56 @@
+pub(super) mod rsync;
+mod s3;
+
+use action;
+use config::Config;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::store::{Store, STORE};
+use throttle::Bandwidth;
+
+/// The files of a dist which list every other index, and must therefore be published last.
+const RELEASE_FILES: &[&str] = &["Release", "Release.gpg", "InRelease"];
+
+/// Pushes the dists and pool of the suite, along with those of its debug symbols suite, to each
+/// publish target of its config. The time of the publish is recorded in the store, for its metrics.
+pub fn publish(config: &Config) -> io::Result<()> {
+    let suites = Some(config.archive.clone()).into_iter()
+        .chain(config.debug_suite().map(|debug| debug.archive))
+        .collect::<Vec<String>>();
+
+    let targets = config.rsync.iter().flat_map(|x| x.iter()).collect::<Vec<_>>();
+    if config.s3.is_none() && targets.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no publish targets are configured for {}", config.archive)
+        ));
+    }
+
+    let policy = config.retry_policy();
+    let bandwidth = Bandwidth::new(&config.network_limits());
+    if let Some(ref bucket) = config.s3 {
+        s3::sync(config.repo_path(), &suites, bucket, &policy, &bandwidth)?;
+    }
+
+    for target in targets {
+        rsync::push(config.repo_path(), &suites, target, &policy, &bandwidth)?;
+    }
+
+    if !action::is_dry_run() {
+        let published = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs());
+        let recorded = Store::open(Path::new(STORE)).and_then(|mut store| {
+            for suite in &suites {
+                store.record_publish(suite, published);
+            }
+
+            store.save(Path::new(STORE))
+        });
+
+        if let Err(why) = recorded {
+            warn!("failed to record the publish of {}: {}", config.archive, why);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use action;
+use config::{RetryPolicy, RsyncTarget};
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use super::super::rsync::transfer;
+use throttle::Bandwidth;
+use super::RELEASE_FILES;
+
+/// Pushes the dists and pool of each suite to the target, along with its deltas and changelogs, if it has
+/// them.
+///
+/// As with a bucket, the pool is pushed first, then the deltas and indices, and then the `Release` files of
+/// each dist. The arguments of the target are given to each transfer, so `--delete` removes the
+/// files of each suite which are no longer in the repo. The bandwidth limit is given as a
+/// `--bwlimit`, unless the target sets its own.
+pub fn push(
+    repo: &Path,
+    suites: &[String],
+    target: &RsyncTarget,
+    policy: &RetryPolicy,
+    bandwidth: &Bandwidth
+) -> io::Result<()> {
+    let destination = target.destination();
+    info!("publishing {} to {}", suites.join(", "), destination);
+
+    for suite in suites {
+        let releases = RELEASE_FILES.iter()
+            .map(|&file| ["--exclude=/dists/", suite.as_str(), "/", file].concat())
+            .collect::<Vec<String>>();
+
+        let passes = [
+            ("pool", &[][..]),
+            ("deltas", &[][..]),
+            ("changelogs", &[][..]),
+            ("dists", &releases[..]),
+            ("dists", &[][..]),
+        ];
+        for &(directory, excludes) in &passes {
+            // The source is relative to the repo, so that its path is recreated at the target.
+            let source = repo.join(".").join(directory).join(suite);
+            if !source.exists() {
+                continue
+            }
+
+            let args = arguments(target, excludes, bandwidth.rsync_limit());
+            action::perform(format_args!("rsync {} to {}", source.display(), destination), || {
+                transfer(source.as_os_str(), OsStr::new(&destination), &args, policy)
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+fn arguments(target: &RsyncTarget, excludes: &[String], bwlimit: Option<u64>) -> Vec<String> {
+    let mut args = vec!["-az".to_owned(), "--relative".to_owned()];
+    if let Some(ref identity) = target.identity {
+        args.push("-e".to_owned());
+        args.push(format!("ssh -i '{}'", identity.display()));
+    }
+
+    args.extend_from_slice(excludes);
+    if let Some(bwlimit) = bwlimit {
+        if !target.args.iter().any(|arg| arg.starts_with("--bwlimit")) {
+            args.push(format!("--bwlimit={}", bwlimit));
+        }
+    }
+
+    args.extend_from_slice(&target.args);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn rsync_arguments() {
+        let target = RsyncTarget {
+            host: Some("deploy@apt.example.com".into()),
+            path: "/srv/apt/".into(),
+            identity: Some(PathBuf::from("keys/deploy")),
+            args: vec!["--delete".into(), "--bwlimit=1000".into()],
+        };
+
+        assert_eq!(target.destination(), "deploy@apt.example.com:/srv/apt/");
+        assert_eq!(arguments(&target, &["--exclude=/dists/bionic/InRelease".into()], Some(512)), vec![
+            "-az",
+            "--relative",
+            "-e",
+            "ssh -i 'keys/deploy'",
+            "--exclude=/dists/bionic/InRelease",
+            "--delete",
+            "--bwlimit=1000",
+        ]);
+
+        let local = RsyncTarget { host: None, path: "mnt".into(), identity: None, args: vec![] };
+        assert_eq!(local.destination(), "mnt/");
+        assert_eq!(arguments(&local, &[], None), vec!["-az", "--relative"]);
+        assert_eq!(arguments(&local, &[], Some(512)), vec!["-az", "--relative", "--bwlimit=512"]);
+    }
+}
@@ -0,0 +1,277 @@
+use debian::{compare_versions, parse_control};
+use logging::json_string;
+use regex::RegexBuilder;
+use std::io;
+use std::path::{Component, Path};
+use super::verify::{indices, listed_files};
+
+/// A package which is published in a suite, as its indices list it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Published {
+    pub suite: String,
+    pub component: String,
+    pub package: String,
+    pub version: String,
+    /// The architecture of the package, or `source` for a source package.
+    pub architecture: String,
+    /// The description of a binary package, which source packages do not have.
+    pub description: String,
+    /// The path of each file of the package within the repo, with its size and SHA256 digest.
+    pub files: Vec<(String, String, String)>,
+}
+
+/// Reads every package which the `Packages` and `Sources` indices of a suite list.
+///
+/// Packages of `Architecture: all` are listed by the index of every architecture, but are only
+/// returned once. Packages are sorted by name, then by architecture, and then by version.
+pub fn published(repo: &Path, suite: &str) -> io::Result<Vec<Published>> {
+    let dist = repo.join("dists").join(suite);
+    let mut packages = Vec::new();
+
+    for (index, data) in indices(&dist)? {
+        let component = match index.strip_prefix(&dist).ok().and_then(|x| x.components().next()) {
+            Some(Component::Normal(component)) => component.to_string_lossy().into_owned(),
+            _ => continue
+        };
+
+        let stanzas = data.split("\n\n").map(|x| x.trim()).filter(|x| !x.is_empty());
+        for control in stanzas.map(parse_control) {
+            let is_source = control.contains_key("Directory");
+            let (package, version) = match (control.get("Package"), control.get("Version")) {
+                (Some(package), Some(version)) => (package.clone(), version.clone()),
+                _ => continue
+            };
+
+            let architecture = match control.get("Architecture") {
+                Some(_) if is_source => "source".to_owned(),
+                Some(architecture) => architecture.clone(),
+                None => continue
+            };
+
+            packages.push(Published {
+                suite: suite.to_owned(),
+                component: component.clone(),
+                package,
+                version,
+                architecture,
+                description: control.get("Description").cloned().unwrap_or_default(),
+                files: listed_files(&control),
+            });
+        }
+    }
+
+    packages.sort_by(|a, b| {
+        a.package.cmp(&b.package)
+            .then_with(|| a.architecture.cmp(&b.architecture))
+            .then_with(|| compare_versions(&a.version, &b.version))
+            .then_with(|| a.component.cmp(&b.component))
+    });
+
+    packages.dedup();
+    Ok(packages)
+}
+
+/// The packages whose name or description matches the pattern, which is a regular expression
+/// that ignores case.
+pub fn search<'a>(packages: &'a [Published], pattern: &str) -> io::Result<Vec<&'a Published>> {
+    let pattern = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid search pattern {}: {}", pattern, why)
+        ))?;
+
+    Ok(packages.iter()
+        .filter(|package| pattern.is_match(&package.package) || pattern.is_match(&package.description))
+        .collect())
+}
+
+/// Writes a JSON manifest of the packages that each suite publishes, for tools which would
+/// otherwise need to parse the indices themselves.
+///
+/// The manifest is an object with a `suites` array, in which each suite has its `suite` name and
+/// a `packages` array. Each package has its `package`, `version`, `architecture`, `component`,
+/// and `files`, which each have the `path` of the file within the repo, its `size`, and `sha256`.
+pub fn manifest(suites: &[(String, Vec<Published>)]) -> String {
+
+    let mut output = String::from("{\n  \"suites\": [");
+    for (id, &(ref suite, ref packages)) in suites.iter().enumerate() {
+        output.push_str(if id == 0 { "\n" } else { ",\n" });
+        output.push_str(&format!("    {{\n      \"suite\": {},\n      \"packages\": [", json_string(suite)));
+
+        for (id, package) in packages.iter().enumerate() {
+            let files = package.files.iter()
+                .map(|&(ref path, ref size, ref sha256)| format!(
+                    "{{ \"path\": {}, \"size\": {}, \"sha256\": {} }}",
+                    json_string(path),
+                    size.parse::<u64>().unwrap_or(0),
+                    json_string(sha256)
+                ))
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            output.push_str(if id == 0 { "\n" } else { ",\n" });
+            output.push_str(&format!(
+                "        {{ \"package\": {}, \"version\": {}, \"architecture\": {}, \
+                 \"component\": {}, \"files\": [{}] }}",
+                json_string(&package.package),
+                json_string(&package.version),
+                json_string(&package.architecture),
+                json_string(&package.component),
+                files
+            ));
+        }
+
+        output.push_str(if packages.is_empty() { "]\n    }" } else { "\n      ]\n    }" });
+    }
+
+    output.push_str(if suites.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+    output
+}
+
+/// Aligns the columns of each row, with a space between each, and writes a line for each row.
+pub fn table(rows: &[Vec<String>]) -> String {
+    let mut widths = Vec::new();
+    for row in rows {
+        for (id, column) in row.iter().enumerate() {
+            if widths.len() <= id {
+                widths.push(0);
+            }
+
+            widths[id] = widths[id].max(column.chars().count());
+        }
+    }
+
+    let mut output = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for (id, column) in row.iter().enumerate() {
+            if id != 0 {
+                line.push(' ');
+            }
+
+            line.push_str(column);
+            for _ in column.chars().count()..widths[id] {
+                line.push(' ');
+            }
+        }
+
+        output.push_str(line.trim_right());
+        output.push('\n');
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use misc;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn published_packages() {
+        let dir = tempdir().unwrap();
+        let dist = dir.path().join("dists/bionic/main");
+        for directory in &["binary-amd64", "binary-i386", "source"] {
+            fs::create_dir_all(dist.join(directory)).unwrap();
+        }
+
+        let entry = |name: &str, version: &str, arch: &str| format!(
+            "Package: {}\nVersion: {}\nArchitecture: {}\n\
+             Filename: pool/bionic/main/binary-{}/f/{}/{}_{}_{}.deb\nSize: 3\nSHA256: abc\n",
+            name, version, arch, arch, name, name, version, arch
+        );
+
+        let data = |arch: &str| [
+            entry("foo", "1.0", arch),
+            entry("foo", "0.9", arch),
+            entry("foo-data", "1.0", "all"),
+        ].join("\n");
+
+        misc::write(dist.join("binary-amd64/Packages"), data("amd64")).unwrap();
+        misc::write(dist.join("binary-i386/Packages"), data("i386")).unwrap();
+        misc::write(dist.join("source/Sources"), [
+            "Package: foo\nVersion: 1.0\nArchitecture: any\nDirectory: pool/bionic/main/source/f/foo\n",
+            "Files:\n 900150983cd24fb0d6963f7d28e17f72 3 foo_1.0.dsc\n",
+            "Checksums-Sha256:\n def 3 foo_1.0.dsc\n",
+        ].concat()).unwrap();
+
+        let packages = published(dir.path(), "bionic").unwrap();
+        let listed = packages.iter()
+            .map(|x| [x.package.as_str(), &x.version, &x.architecture, &x.component].join(" "))
+            .collect::<Vec<String>>();
+
+        assert_eq!(listed, vec![
+            "foo 0.9 amd64 main",
+            "foo 1.0 amd64 main",
+            "foo 0.9 i386 main",
+            "foo 1.0 i386 main",
+            "foo 1.0 source main",
+            "foo-data 1.0 all main",
+        ]);
+
+        assert_eq!(packages[4].files, vec![(
+            "pool/bionic/main/source/f/foo/foo_1.0.dsc".to_owned(),
+            "3".to_owned(),
+            "def".to_owned()
+        )]);
+    }
+
+    #[test]
+    fn json_manifest() {
+        let package = Published {
+            suite: "bionic".into(),
+            component: "main".into(),
+            package: "foo".into(),
+            version: "1.0".into(),
+            architecture: "amd64".into(),
+            description: "frobnicates bars\n the long description".into(),
+            files: vec![("pool/bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb".into(), "3".into(), "abc".into())],
+        };
+
+        let suites = vec![("bionic".to_owned(), vec![package]), ("cosmic".to_owned(), Vec::new())];
+        assert_eq!(manifest(&suites), [
+            "{\n  \"suites\": [\n",
+            "    {\n      \"suite\": \"bionic\",\n      \"packages\": [\n",
+            "        { \"package\": \"foo\", \"version\": \"1.0\", \"architecture\": \"amd64\", ",
+            "\"component\": \"main\", \"files\": [{ \"path\": ",
+            "\"pool/bionic/main/binary-amd64/f/foo/foo_1.0_amd64.deb\", \"size\": 3, \"sha256\": \"abc\" }] }\n",
+            "      ]\n    },\n",
+            "    {\n      \"suite\": \"cosmic\",\n      \"packages\": []\n    }\n",
+            "  ]\n}\n",
+        ].concat());
+
+        assert_eq!(manifest(&[]), "{\n  \"suites\": []\n}\n");
+    }
+
+    #[test]
+    fn package_search() {
+        let packages = vec![Published {
+            suite: "bionic".into(),
+            component: "main".into(),
+            package: "foo".into(),
+            version: "1.0".into(),
+            architecture: "amd64".into(),
+            description: "frobnicates bars\n the long description".into(),
+            files: Vec::new(),
+        }];
+
+        // Packages are matched by their name or description, ignoring case.
+        let found = |pattern| search(&packages, pattern).unwrap().len();
+        assert_eq!((found("^foo$"), found("FROBNICATES"), found("long desc"), found("^bar")), (1, 1, 1, 0));
+        assert!(search(&packages, "(").is_err());
+    }
+
+    #[test]
+    fn aligned_table() {
+        let rows = vec![
+            vec!["PACKAGE".to_owned(), "VERSION".to_owned(), "ARCH".to_owned()],
+            vec!["linux-firmware".to_owned(), "1.0".to_owned(), "all".to_owned()],
+        ];
+
+        assert_eq!(table(&rows), "PACKAGE        VERSION ARCH\nlinux-firmware 1.0     all\n");
+    }
+}
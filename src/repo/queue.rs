@@ -0,0 +1,352 @@
+use action;
+use config::Config;
+use cwd;
+use debian::{parse_control, DEB_EXTENSION, DEB_INSTALLER_EXTENSION};
+use misc;
+use std::collections::BTreeSet;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use super::generate_release_files;
+use super::pool::{check_archive, mv_to_pool, pool_destination};
+
+/// The directory of the project which the held packages of each suite are kept within, until
+/// they are approved or rejected. It is not within the repo, so held packages are never served.
+pub const QUEUE: &str = "queue";
+
+/// A source, or an upload, which is held in the queue of a suite.
+#[derive(Debug, PartialEq)]
+pub struct Queued {
+    pub suite: String,
+    pub name: String,
+    pub component: String,
+    pub reason: String,
+    pub files: Vec<String>,
+    /// The flags that the files are pooled with once they are approved.
+    flags: u8,
+}
+
+/// Moves the files of a source into the queue of the suite, rather than into the pool, if the
+/// queue holds them, returning whether they were held. The files are those that `mv_to_pool`
+/// would select by the filter. A source which was already held is replaced, as by a newer build.
+///
+/// Archives are checked as the pool would check them before they are held, so that a package
+/// which the pool refuses fails now, rather than once it is approved.
+pub fn hold(
+    config: &Config,
+    path: &Path,
+    component: &str,
+    flags: u8,
+    filter: Option<&str>,
+    name: &str
+) -> io::Result<bool> {
+    let queue = match config.queue {
+        Some(ref queue) => queue,
+        None => return Ok(false)
+    };
+
+    let files = files_of(path, filter)?;
+    if files.is_empty() {
+        return Ok(false);
+    }
+
+    let reason = if queue.holds(name) {
+        format!("{} is held by the queue policy of {}", name, config.archive)
+    } else if queue.holds_new() {
+        match new_package(&config.pool_root(), &config.archive, component, &files)? {
+            Some(package) => format!("{} is new to {}/{}", package, config.archive, component),
+            None => return Ok(false)
+        }
+    } else {
+        return Ok(false);
+    };
+
+    let policy = config.pool_policy();
+    for file in &files {
+        check_archive(&path.join(file), &policy)?;
+    }
+
+    info!("holding {} in the queue of {}, as {}", name, config.archive, reason);
+    let directory = entry_path(&config.archive, name);
+    if directory.exists() {
+        action::remove_dir_all(&directory)?;
+    }
+
+    action::create_dir_all(&directory)?;
+    for file in &files {
+        action::rename(path.join(file), directory.join(file))?;
+    }
+
+    let record = format!("Source: {}\nComponent: {}\nFlags: {}\nReason: {}\n", name, component, flags, reason);
+    action::write(record_path(&config.archive, name), record)?;
+    Ok(true)
+}
+
+/// The sources which are held in the queue of the suite.
+pub fn queued(config: &Config) -> io::Result<Vec<Queued>> {
+    let queue = Path::new(QUEUE).join(&config.archive);
+    if !queue.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut held = Vec::new();
+    for entry in queue.read_dir()? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().map_or(true, |ext| ext != "queued") {
+            continue
+        }
+
+        let record = parse_control(&misc::read_to_string(&path)?);
+        let field = |name: &str| record.get(name).cloned().unwrap_or_default();
+        let name = field("Source");
+        let directory = entry_path(&config.archive, &name);
+
+        let mut files = Vec::new();
+        if directory.is_dir() {
+            for file in directory.read_dir()? {
+                files.push(file?.file_name().to_string_lossy().into_owned());
+            }
+        }
+
+        files.sort();
+        held.push(Queued {
+            suite: config.archive.clone(),
+            component: field("Component"),
+            reason: field("Reason"),
+            flags: field("Flags").parse::<u8>().unwrap_or(0),
+            files,
+            name,
+        });
+    }
+
+    held.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(held)
+}
+
+/// Moves the held files of each named source into the pool, and regenerates the dist files of
+/// each suite which they were held in. Fails before anything is pooled if a name is not held.
+pub fn approve(configs: &[Config], names: &[&str]) -> io::Result<()> {
+    let _cwd = cwd::lock();
+    let base = env::current_dir()?;
+    let held = select(configs, names)?;
+
+    let mut approved = BTreeSet::new();
+    for (id, entry) in held {
+        pool_entry(&configs[id], &entry)?;
+        approved.insert(id);
+    }
+
+    for id in approved {
+        env::set_current_dir(&base)?;
+        generate_release_files(&configs[id]).map_err(|why| io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to generate the dist files of {}: {}", configs[id].archive, why)
+        ))?;
+    }
+
+    env::set_current_dir(&base)
+}
+
+/// Moves the held files of the source into the pool of its suite, and forgets it.
+fn pool_entry(config: &Config, entry: &Queued) -> io::Result<()> {
+    info!("approving {} into {}", entry.name, config.archive);
+    let directory = entry_path(&config.archive, &entry.name);
+    let pool = config.pool_root();
+    mv_to_pool(&directory, &pool, &config.archive, &entry.component, entry.flags, None, &config.pool_policy())
+        .map_err(|why| io::Error::new(why.kind(), format!("{} failed to migrate to pool: {}", entry.name, why)))?;
+
+    remove(&config.archive, &entry.name)
+}
+
+/// Removes the held files of each named source, which are not published. As the build of a
+/// source is still recorded, it is not built again until its version changes.
+pub fn reject(configs: &[Config], names: &[&str]) -> io::Result<()> {
+    for (id, entry) in select(configs, names)? {
+        info!("rejecting {} from {}", entry.name, configs[id].archive);
+        remove(&configs[id].archive, &entry.name)?;
+    }
+
+    Ok(())
+}
+
+/// The held sources of the names, with the index of the config of their suite.
+fn select(configs: &[Config], names: &[&str]) -> io::Result<Vec<(usize, Queued)>> {
+    let mut held = Vec::new();
+    for (id, config) in configs.iter().enumerate() {
+        for entry in queued(config)? {
+            if names.contains(&entry.name.as_str()) {
+                held.push((id, entry));
+            }
+        }
+    }
+
+    for name in names {
+        if !held.iter().any(|&(_, ref entry)| entry.name == *name) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not held in the queue of any suite", name)
+            ));
+        }
+    }
+
+    Ok(held)
+}
+
+fn remove(suite: &str, name: &str) -> io::Result<()> {
+    let directory = entry_path(suite, name);
+    if directory.exists() {
+        action::remove_dir_all(&directory)?;
+    }
+
+    action::remove_file(record_path(suite, name))
+}
+
+fn entry_path(suite: &str, name: &str) -> PathBuf {
+    Path::new(QUEUE).join(suite).join(name)
+}
+
+fn record_path(suite: &str, name: &str) -> PathBuf {
+    Path::new(QUEUE).join(suite).join([name, ".queued"].concat())
+}
+
+/// The files of the directory which the filter selects, as `mv_to_pool` selects them.
+fn files_of(path: &Path, filter: Option<&str>) -> io::Result<Vec<String>> {
+    if !path.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in path.read_dir()? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue
+        }
+
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let selected = filter.map_or(true, |filter| {
+            name.starts_with(&[filter, "_"].concat()) || name.starts_with(&[filter, "-dbgsym_"].concat())
+        });
+
+        if selected {
+            files.push(name);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// The first package of the files which has never been pooled in the component of the suite,
+/// whether as a source, or as a binary of any architecture.
+fn new_package(pool_root: &Path, suite: &str, component: &str, files: &[String]) -> io::Result<Option<String>> {
+    for file in files {
+        let packaged = [DEB_EXTENSION, DEB_INSTALLER_EXTENSION, ".dsc"].iter().any(|ext| file.ends_with(ext));
+        if !packaged || file.contains("-dbgsym_") {
+            continue
+        }
+
+        // The destination is `<component>/<binary-arch or source>/<bucket>/<package>`.
+        let destination = pool_destination(pool_root, suite, component, file)?;
+        let package = destination.file_name().map(|x| x.to_os_string()).unwrap_or_default();
+        let bucket = destination.parent().and_then(|x| x.file_name()).map(|x| x.to_os_string()).unwrap_or_default();
+        let component = destination.parent().and_then(|x| x.parent()).and_then(|x| x.parent());
+
+        let pooled = match component {
+            Some(component) if component.is_dir() => component.read_dir()?
+                .filter_map(|entry| entry.ok())
+                .any(|entry| entry.path().join(&bucket).join(&package).is_dir()),
+            _ => false
+        };
+
+        if !pooled {
+            return Ok(Some(package.to_string_lossy().into_owned()));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use debian::{tarball, write_deb};
+    use std::fs;
+    use super::super::pool::KEEP_SOURCE;
+    use tempfile::tempdir;
+    use toml;
+
+    #[test]
+    fn new_packages() {
+        let dir = tempdir().unwrap();
+        let pool = dir.path().join("pool");
+        fs::create_dir_all(pool.join("bionic/main/binary-amd64/f/foo")).unwrap();
+        fs::create_dir_all(pool.join("bionic/main/source/b/bar")).unwrap();
+
+        let new = |files: &[&str]| {
+            let files = files.iter().map(|&x| x.to_owned()).collect::<Vec<String>>();
+            new_package(&pool, "bionic", "main", &files).unwrap()
+        };
+
+        // A package which is published for another architecture, or as a source, is not new.
+        assert_eq!(new(&["foo_1.1_arm64.deb", "foo_1.1_arm64.changes"]), None);
+        assert_eq!(new(&["bar_2.0.dsc", "bar_2.0_amd64.deb"]), None);
+        assert_eq!(new(&["foo-dbgsym_1.1_amd64.ddeb", "libfoo1_1.1_amd64.deb"]), Some("libfoo1".into()));
+        assert_eq!(new_package(&pool, "bionic", "contrib", &["foo_1.1_amd64.deb".into()]).unwrap(), Some("foo".into()));
+
+        misc::write(dir.path().join("foo_1.1_amd64.deb"), b"").unwrap();
+        misc::write(dir.path().join("foobar_1.0_amd64.deb"), b"").unwrap();
+        assert_eq!(files_of(dir.path(), Some("foo")).unwrap(), vec!["foo_1.1_amd64.deb".to_owned()]);
+    }
+
+    #[test]
+    fn held_sources() {
+        // The queue is kept within the project, which is the working directory.
+        let _cwd = cwd::lock();
+        let base = env::current_dir().unwrap();
+        let dir = tempdir().unwrap();
+        env::set_current_dir(dir.path()).unwrap();
+
+        let config: Config = toml::from_str(r#"
+            archive = "bionic"
+            version = "18.04"
+            origin = "debrep"
+            label = "debrep"
+            email = "debrep@example.com"
+            architectures = ["amd64"]
+
+            [queue]
+            packages = ["foo", "bar"]
+        "#).unwrap();
+
+        let build = Path::new("build");
+        fs::create_dir_all(build).unwrap();
+        for file in &["foo_1.0.dsc", "foo_1.0.tar.xz", "bar_1.0.dsc"] {
+            misc::write(build.join(file), b"").unwrap();
+        }
+
+        // An archive which the pool would refuse is not held.
+        let mislabeled = build.join("foo_1.0_amd64.deb");
+        let control = tarball(&[("control", &b"Package: foo\nVersion: 1.0\nArchitecture: arm64\n"[..])]).unwrap();
+        write_deb(&mislabeled, &control, &tarball(&[]).unwrap()).unwrap();
+        assert!(hold(&config, build, "main", KEEP_SOURCE, Some("foo"), "foo").is_err());
+        assert!(mislabeled.exists() && build.join("foo_1.0.dsc").exists());
+        fs::remove_file(&mislabeled).unwrap();
+
+        for &name in &["foo", "bar"] {
+            assert!(hold(&config, build, "main", KEEP_SOURCE, Some(name), name).unwrap());
+        }
+
+        let held = queued(&config).unwrap();
+        assert_eq!(held.iter().map(|x| x.name.as_str()).collect::<Vec<&str>>(), vec!["bar", "foo"]);
+        assert_eq!(held[1].files, vec!["foo_1.0.dsc".to_owned(), "foo_1.0.tar.xz".to_owned()]);
+
+        pool_entry(&config, &held[1]).unwrap();
+        assert!(Path::new("repo/pool/bionic/main/source/f/foo/foo_1.0.dsc").exists());
+        reject(&[config.clone()], &["bar"]).unwrap();
+        assert!(queued(&config).unwrap().is_empty());
+        assert!(!Path::new("repo/pool/bionic/main/source/b/bar").exists());
+        assert!(reject(&[config.clone()], &["bar"]).is_err());
+
+        env::set_current_dir(base).unwrap();
+    }
+}
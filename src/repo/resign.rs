@@ -0,0 +1,145 @@
+use action;
+use chrono::{DateTime, Utc};
+use config::Config;
+use cwd;
+use debian::parse_control;
+use misc;
+use std::{env, fs, io};
+use std::path::Path;
+use super::flat::resign_flat;
+use super::snapshot::link_tree;
+use super::{generate, integrity, sign};
+use workspace;
+
+/// Regenerates the `Release` files of a suite from the indices which are already published, and
+/// signs them again, so that their `Date` and `Valid-Until` are renewed without building or
+/// indexing anything. The debug suite of the suite is re-signed along with it.
+pub fn resign(config: &Config) -> io::Result<()> {
+    let _cwd = cwd::lock();
+    if action::is_dry_run() {
+        action::report(format_args!("regenerate and sign the Release files of {}", config.archive));
+        return Ok(());
+    }
+
+    if generate::release_date(config.source_date_epoch, env::var("SOURCE_DATE_EPOCH").ok())?.is_some() {
+        warn!("the Date of {} is pinned, so its Valid-Until will not be renewed", config.archive);
+    }
+
+    env::set_current_dir(config.repo_path())?;
+    if config.flat {
+        return resign_flat(config);
+    }
+
+    resign_suite(config)?;
+    match config.debug_suite() {
+        Some(ref debug) if Path::new("dists").join(&debug.archive).exists() => resign_suite(debug),
+        _ => Ok(())
+    }
+}
+
+/// Regenerates the `Release` of a published suite beside it, from hard links of its indices, and
+/// swaps it in once it has been signed and audited, from within the repo directory.
+fn resign_suite(config: &Config) -> io::Result<()> {
+    let suite = ["dists/", &config.archive].concat();
+    let base = [&suite, ".new"].concat();
+    let base_path = Path::new(&base);
+
+    let release = misc::read_to_string(Path::new(&suite).join("Release"))?;
+    let components = parse_control(&release).get("Components")
+        .map_or_else(Vec::new, |components| {
+            components.split_whitespace().map(String::from).collect::<Vec<String>>()
+        });
+
+    if base_path.exists() {
+        action::remove_dir_all(base_path)?;
+    }
+
+    workspace::register(base_path)?;
+
+    link_tree(Path::new(&suite), base_path)?;
+    for name in &["Release", "InRelease", "Release.gpg"] {
+        let path = base_path.join(name);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    info!("re-signing {}", suite);
+    generate::dists_release(config, &base, &components)?;
+    let signer = sign::Signer::new(config)?;
+    signer.in_release(&base_path.join("Release"), &base_path.join("InRelease"))?;
+    signer.detached(&base_path.join("Release"), &base_path.join("Release.gpg"))?;
+
+    let mismatched = integrity::audit_release(base_path)?;
+    if !mismatched.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("indices do not match the Release file: {:?}", mismatched)
+        ));
+    }
+
+    generate::swap_dists(Path::new(&suite), base_path)
+}
+
+/// Warns when the published `Release` of the suite has less than a quarter of its validity left,
+/// as apt refuses a suite once it has expired, unless it is rebuilt or re-signed before then.
+pub(super) fn warn_expiry(config: &Config) {
+    let validity = match config.valid_until {
+        Some(validity) => validity,
+        None => return
+    };
+
+    let release = if config.flat {
+        config.repo_path().join("Release")
+    } else {
+        config.repo_path().join("dists").join(&config.archive).join("Release")
+    };
+
+    let release = match misc::read_to_string(&release) {
+        Ok(release) => release,
+        Err(_) => return
+    };
+
+    match expires_within(&release, validity, Utc::now().timestamp()) {
+        Some(remaining) if remaining <= 0 => {
+            warn!("the Release of {} has expired; run `debrep resign` to renew it", config.archive)
+        }
+        Some(remaining) => warn!(
+            "the Release of {} expires in {} hours; run `debrep resign` to renew it",
+            config.archive,
+            remaining / 3600
+        ),
+        None => ()
+    }
+}
+
+/// The seconds until the `Valid-Until` of a `Release`, if less than a quarter of its validity
+/// remains at `now`.
+fn expires_within(release: &str, validity: u64, now: i64) -> Option<i64> {
+    let valid_until = parse_control(release).get("Valid-Until")
+        .and_then(|date| DateTime::parse_from_rfc2822(&date.replace("UTC", "+0000")).ok())?;
+
+    let remaining = valid_until.timestamp() - now;
+    if remaining < validity as i64 / 4 {
+        Some(remaining)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_expiry() {
+        let release = "Suite: bionic\nValid-Until: Thu, 01 Jan 2037 00:00:00 UTC\n";
+        let valid_until = 2114380800;
+        let week = 604800;
+
+        assert_eq!(expires_within(release, week, valid_until - week as i64), None);
+        assert_eq!(expires_within(release, week, valid_until - 3600), Some(3600));
+        assert_eq!(expires_within(release, week, valid_until + 60), Some(-60));
+        assert_eq!(expires_within("Suite: bionic\n", week, valid_until), None);
+    }
+}
@@ -0,0 +1,59 @@
+use action;
+use config::RetryPolicy;
+use retry::retry;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+use std::{io, fs};
+
+/// Exit codes of rsync which are caused by the network, rather than by the transfer itself.
+///
+/// - 5: failed to start the client-server protocol
+/// - 10: socket I/O error
+/// - 12: error in the protocol data stream
+/// - 30: timed out while sending or receiving data
+/// - 35: timed out while waiting for a daemon connection
+const TRANSIENT_EXIT_CODES: &[i32] = &[5, 10, 12, 30, 35];
+
+pub fn rsync(src: &Path, dst: &Path, policy: &RetryPolicy) -> io::Result<()> {
+    action::perform(format_args!("rsync {} to {}", src.display(), dst.display()), || {
+        rsync_(src, dst, policy)
+    })
+}
+
+fn rsync_(src: &Path, dst: &Path, policy: &RetryPolicy) -> io::Result<()> {
+    info!("rsyncing {} to {}", src.display(), dst.display());
+
+    if src.is_dir() && ! dst.exists() {
+        fs::create_dir_all(dst).map_err(|why| io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to create destination directory at {:?} for rsync: {}", dst, why)
+        ))?;
+    }
+
+    transfer(src.as_os_str(), dst.as_os_str(), &["--ignore-existing", "-avz"], policy)
+}
+
+/// Runs rsync with the given arguments, retrying it after network errors. The destination may
+/// be on another host, as in `host:path`.
+pub fn transfer<A: AsRef<OsStr>>(
+    src: &OsStr,
+    dst: &OsStr,
+    args: &[A],
+    policy: &RetryPolicy
+) -> io::Result<()> {
+    retry(policy, "rsync", || {
+        let status = Command::new("rsync").args(args).arg(src).arg(dst).status()?;
+        match status.code() {
+            Some(0) => Ok(()),
+            Some(code) if TRANSIENT_EXIT_CODES.contains(&code) => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                format!("rsync failed with a network error: {}", status)
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("rsync failed with exit status: {}", status)
+            ))
+        }
+    })
+}
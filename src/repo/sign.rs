@@ -0,0 +1,327 @@
+use config::{Config, SigningBackend};
+use error::Error;
+use rayon;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+/// Signs the `Release` file with the backend selected by the config.
+pub(crate) enum Signer<'a> {
+    Gpg(&'a str),
+    Native(&'a Path),
+}
+
+impl<'a> Signer<'a> {
+    pub fn new(config: &'a Config) -> io::Result<Self> {
+        match config.signing {
+            Some(ref signing) if signing.backend == SigningBackend::Native => {
+                config.named_key()
+                    .map(|_| Path::new(config.signing_key()))
+                    .or_else(|| signing.key.as_ref().map(|key| key.as_path()))
+                    .map(Signer::Native)
+                    .ok_or_else(|| io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "the native signing backend requires a signing.key, or one of the \
+                         signing.keys, to be set"
+                    ))
+            }
+            _ => Ok(Signer::Gpg(config.signing_key()))
+        }
+    }
+
+    /// Generates the clearsigned `InRelease` file from the `Release` file.
+    pub fn in_release(&self, release_path: &Path, out_path: &Path) -> io::Result<()> {
+        match *self {
+            Signer::Gpg(key) => gpg_in_release(key, release_path, out_path),
+            Signer::Native(key) => native::clearsign(key, release_path, out_path),
+        }
+    }
+
+    /// Generates the detached `Release.gpg` signature of the `Release` file.
+    pub fn detached(&self, release_path: &Path, out_path: &Path) -> io::Result<()> {
+        match *self {
+            Signer::Gpg(key) => gpg_release(key, release_path, out_path),
+            Signer::Native(key) => native::detached(key, release_path, out_path),
+        }
+    }
+}
+
+/// Generates the `InRelease` file from the `Release` file via `gpg --clearsign`.
+pub(crate) fn gpg_in_release(email: &str, release_path: &Path, out_path: &Path) -> io::Result<()> {
+    info!("generating InRelease file");
+    let exit_status = Command::new("gpg")
+        .args(&[
+            "--clearsign",
+            "--local-user",
+            email,
+            "--batch",
+            "--yes",
+            "--digest-algo",
+            "sha512",
+            "-o",
+        ])
+        .arg(out_path)
+        .arg(release_path)
+        .status();
+
+    signed("gpg --clearsign", email, release_path, exit_status)
+}
+
+/// Generates the `Release.gpg` file from the `Release` file via `gpg -abs`
+pub(crate) fn gpg_release(email: &str, release_path: &Path, out_path: &Path) -> io::Result<()> {
+    info!("generating Release.gpg file");
+    let exit_status = Command::new("gpg")
+        .args(&[
+            "-abs",
+            "--local-user",
+            email,
+            "--batch",
+            "--yes",
+            "--digest-algo",
+            "sha512",
+            "-o",
+        ])
+        .arg(out_path)
+        .arg(release_path)
+        .status();
+
+    signed("gpg -abs", email, release_path, exit_status)
+}
+
+/// Checks that gpg signed the `Release` file, naming the key that it failed to sign with.
+fn signed(
+    command: &'static str,
+    key: &str,
+    release_path: &Path,
+    status: io::Result<ExitStatus>
+) -> io::Result<()> {
+    let why = match status {
+        Ok(ref status) if status.success() => return Ok(()),
+        Ok(status) => io::Error::new(
+            io::ErrorKind::Other,
+            format!("gpg exited with {} while signing with {}", status, key)
+        ),
+        Err(why) => why,
+    };
+
+    Err(Error::Sign { command, file: release_path.to_path_buf(), why }.into())
+}
+
+/// Exports the public keys of gpg key IDs and native key files, as a binary or an armored keyring.
+pub(crate) fn export_keys(gpg: &[&str], native: &[&Path], armored: bool) -> io::Result<Vec<u8>> {
+    let mut keyring = Vec::new();
+    if !gpg.is_empty() {
+        let mut command = Command::new("gpg");
+        command.arg("--batch");
+        if armored {
+            command.arg("--armor");
+        }
+
+        let output = command.arg("--export").args(gpg).output()?;
+        // gpg succeeds without exporting anything when none of the keys are in the keyring.
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("gpg failed to export the public keys of {}", gpg.join(", "))
+            ));
+        }
+
+        keyring.extend_from_slice(&output.stdout);
+    }
+
+    if !native.is_empty() {
+        keyring.extend_from_slice(&native::export(native, armored)?);
+    }
+
+    Ok(keyring)
+}
+
+/// Signs the `Release` file of a dist directory, producing both its `InRelease` and `Release.gpg`.
+pub(crate) fn sign_release(key: &str, dist: &Path) -> io::Result<()> {
+    let release = dist.join("Release");
+    let (inrelease, detached) = rayon::join(
+        || gpg_in_release(key, &release, &dist.join("InRelease")),
+        || gpg_release(key, &release, &dist.join("Release.gpg"))
+    );
+
+    inrelease.and(detached)
+}
+
+#[cfg(feature = "native-signing")]
+mod native {
+    use misc;
+    use sequoia_openpgp::armor;
+    use sequoia_openpgp::cert::Cert;
+    use sequoia_openpgp::crypto::KeyPair;
+    use sequoia_openpgp::parse::Parse;
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::serialize::Marshal;
+    use sequoia_openpgp::serialize::stream::{Armorer, Message, Signer};
+    use sequoia_openpgp::types::HashAlgorithm;
+    use std::fmt::Display;
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::path::Path;
+
+    fn other<E: Display>(why: E) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, format!("{}", why))
+    }
+
+    /// Obtains the first valid, unencrypted signing key from a key file.
+    fn keypair(key: &Path) -> io::Result<KeyPair> {
+        let cert = Cert::from_file(key).map_err(other)?;
+        let policy = StandardPolicy::new();
+        let key = cert.keys()
+            .unencrypted_secret()
+            .with_policy(&policy, None)
+            .supported()
+            .alive()
+            .revoked(false)
+            .for_signing()
+            .next()
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not contain an unencrypted signing key", key.display())
+            ))?;
+
+        key.key().clone().into_keypair().map_err(other)
+    }
+
+    pub fn clearsign(key: &Path, release_path: &Path, out_path: &Path) -> io::Result<()> {
+        info!("generating InRelease file");
+        let release = misc::read(release_path)?;
+        let mut sink = File::create(out_path)?;
+        let mut signer = Signer::new(Message::new(&mut sink), keypair(key)?)
+            .hash_algo(HashAlgorithm::SHA512).map_err(other)?
+            .cleartext()
+            .build().map_err(other)?;
+
+        signer.write_all(&release)?;
+        signer.finalize().map_err(other)
+    }
+
+    pub fn detached(key: &Path, release_path: &Path, out_path: &Path) -> io::Result<()> {
+        info!("generating Release.gpg file");
+        let release = misc::read(release_path)?;
+        let mut sink = File::create(out_path)?;
+        let message = Armorer::new(Message::new(&mut sink))
+            .kind(armor::Kind::Signature)
+            .build().map_err(other)?;
+        let mut signer = Signer::new(message, keypair(key)?)
+            .hash_algo(HashAlgorithm::SHA512).map_err(other)?
+            .detached()
+            .build().map_err(other)?;
+
+        signer.write_all(&release)?;
+        signer.finalize().map_err(other)
+    }
+
+    /// Exports the certificate of each key file, without its secret key material.
+    pub fn export(keys: &[&Path], armored: bool) -> io::Result<Vec<u8>> {
+        let mut keyring = Vec::new();
+        for key in keys {
+            Cert::from_file(key).map_err(other)?.serialize(&mut keyring).map_err(other)?;
+        }
+
+        if !armored {
+            return Ok(keyring);
+        }
+
+        let mut writer = armor::Writer::new(Vec::new(), armor::Kind::PublicKey)?;
+        writer.write_all(&keyring)?;
+        writer.finalize()
+    }
+}
+
+#[cfg(not(feature = "native-signing"))]
+mod native {
+    use std::io;
+    use std::path::Path;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Other,
+            "debrep was built without the native-signing feature, so the gpg backend must be used"
+        )
+    }
+
+    pub fn clearsign(_key: &Path, _release: &Path, _out: &Path) -> io::Result<()> { Err(unsupported()) }
+
+    pub fn detached(_key: &Path, _release: &Path, _out: &Path) -> io::Result<()> { Err(unsupported()) }
+
+    pub fn export(_keys: &[&Path], _armored: bool) -> io::Result<Vec<u8>> { Err(unsupported()) }
+}
+
+#[cfg(all(test, feature = "native-signing"))]
+mod tests {
+    use super::*;
+    use misc;
+    use sequoia_openpgp::{self as openpgp, Cert, KeyHandle};
+    use sequoia_openpgp::cert::CertBuilder;
+    use sequoia_openpgp::parse::Parse;
+    use sequoia_openpgp::parse::stream::{DetachedVerifierBuilder, MessageLayer, MessageStructure};
+    use sequoia_openpgp::parse::stream::{VerificationHelper, VerifierBuilder};
+    use sequoia_openpgp::policy::StandardPolicy;
+    use sequoia_openpgp::serialize::Marshal;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    /// Accepts a message which is signed by the key of the certificate.
+    struct SignedBy(Cert);
+
+    impl VerificationHelper for SignedBy {
+        fn get_certs(&mut self, _ids: &[KeyHandle]) -> openpgp::Result<Vec<Cert>> {
+            Ok(vec![self.0.clone()])
+        }
+
+        fn check(&mut self, structure: MessageStructure) -> openpgp::Result<()> {
+            for layer in structure.into_iter() {
+                if let MessageLayer::SignatureGroup { ref results } = layer {
+                    if results.iter().any(|result| result.is_ok()) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            Err(openpgp::Error::InvalidOperation("not signed by the key".into()).into())
+        }
+    }
+
+    #[test]
+    fn native_signatures_verify() {
+        let dir = tempdir().unwrap();
+        let (cert, _) = CertBuilder::new()
+            .add_userid("test@example.com")
+            .add_signing_subkey()
+            .generate()
+            .unwrap();
+
+        let key = dir.path().join("signing.pgp");
+        let mut secret = Vec::new();
+        cert.as_tsk().serialize(&mut secret).unwrap();
+        misc::write(&key, &secret).unwrap();
+
+        let release = dir.path().join("Release");
+        misc::write(&release, b"Suite: bionic\nCodename: bionic\n").unwrap();
+        let signer = Signer::Native(&key);
+        signer.in_release(&release, &dir.path().join("InRelease")).unwrap();
+        signer.detached(&release, &dir.path().join("Release.gpg")).unwrap();
+
+        let policy = StandardPolicy::new();
+        let in_release = misc::read(dir.path().join("InRelease")).unwrap();
+        let mut verifier = VerifierBuilder::from_bytes(&in_release).unwrap()
+            .with_policy(&policy, None, SignedBy(cert.clone()))
+            .unwrap();
+        let mut signed = String::new();
+        verifier.read_to_string(&mut signed).unwrap();
+        assert_eq!(signed.trim_right(), "Suite: bionic\nCodename: bionic");
+
+        let signature = misc::read(dir.path().join("Release.gpg")).unwrap();
+        let verify = |data: &[u8]| DetachedVerifierBuilder::from_bytes(&signature)
+            .and_then(|builder| builder.with_policy(&policy, None, SignedBy(cert.clone())))
+            .and_then(|mut verifier| verifier.verify_bytes(data));
+
+        assert!(verify(&misc::read(&release).unwrap()).is_ok());
+        assert!(verify(b"Suite: evil\n").is_err());
+    }
+}
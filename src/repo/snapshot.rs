@@ -0,0 +1,178 @@
+use misc;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use super::generate::swap_dists;
+use walkdir::WalkDir;
+use workspace;
+
+/// The directories of the repo which are captured by a snapshot.
+const CAPTURED: &[&str] = &["dists", "pool"];
+
+/// A snapshot, and the suites that were captured within it.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    pub suites: Vec<String>,
+}
+
+/// The directory of a snapshot, after validating that the name is a single path component.
+pub fn snapshot_path(root: &Path, name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid snapshot name: {:?}", name)
+        ));
+    }
+
+    Ok(root.join("snapshots").join(name))
+}
+
+/// Captures the dists and pool of a suite in the repo into `snapshots/<name>`, using hard links.
+pub fn create_snapshot(root: &Path, repo: &Path, suite: &str, name: &str) -> io::Result<()> {
+    let snapshot = snapshot_path(root, name)?;
+    for dir in CAPTURED {
+        let source = root.join(repo).join(dir).join(suite);
+        let destination = snapshot.join(dir).join(suite);
+        if destination.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} was already captured in snapshot {}", suite, name)
+            ));
+        }
+
+        if source.exists() {
+            link_tree(&source, &destination)?;
+        }
+    }
+
+    info!("captured {} in snapshot {}", suite, name);
+    Ok(())
+}
+
+/// Lists the snapshots, and the suites within them, by name.
+pub fn list_snapshots(root: &Path) -> io::Result<Vec<Snapshot>> {
+    let directory = root.join("snapshots");
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(&directory)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue
+        }
+
+        let dists = entry.path().join("dists");
+        let mut suites = Vec::new();
+        if dists.exists() {
+            for suite in fs::read_dir(&dists)? {
+                suites.push(suite?.file_name().to_string_lossy().into_owned());
+            }
+        }
+
+        suites.sort();
+        snapshots.push(Snapshot { name: entry.file_name().to_string_lossy().into_owned(), suites });
+    }
+
+    snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(snapshots)
+}
+
+/// Restores the dists and pool of a suite from a snapshot, replacing those of the repo.
+pub fn publish_snapshot(root: &Path, repo: &Path, suite: &str, name: &str) -> io::Result<()> {
+    let snapshot = snapshot_path(root, name)?;
+    if !snapshot.join("dists").join(suite).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} was not captured in snapshot {}", suite, name)
+        ));
+    }
+
+    for dir in CAPTURED {
+        let source = snapshot.join(dir).join(suite);
+        let current = root.join(repo).join(dir).join(suite);
+        let new = root.join(repo).join(dir).join([suite, ".new"].concat());
+
+        if new.exists() {
+            fs::remove_dir_all(&new)?;
+        }
+
+        workspace::register(&new)?;
+        if source.exists() {
+            link_tree(&source, &new)?;
+            swap_dists(&current, &new)?;
+        } else if current.exists() {
+            fs::remove_dir_all(&current)?;
+        }
+    }
+
+    info!("published {} from snapshot {}", suite, name);
+    Ok(())
+}
+
+/// Recreates a directory tree at the destination, hard linking each of its files.
+///
+/// Files are copied instead when they may not be linked, such as across file systems.
+pub(super) fn link_tree(source: &Path, destination: &Path) -> io::Result<()> {
+    for entry in WalkDir::new(source) {
+        let entry = entry.map_err(|why| io::Error::new(io::ErrorKind::Other, format!("{}", why)))?;
+        let target = destination.join(entry.path().strip_prefix(source).unwrap());
+
+        if entry.path_is_symlink() {
+            symlink(entry.path().read_link()?, &target)?;
+        } else if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            misc::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn snapshot_lifecycle() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let repo = Path::new("repo");
+        let dists = root.join("repo/dists/bionic");
+        let pool = root.join("repo/pool/bionic/main/f/foo");
+        fs::create_dir_all(&dists).unwrap();
+        fs::create_dir_all(&pool).unwrap();
+        misc::write(dists.join("Release"), "Suite: bionic\n").unwrap();
+        misc::write(pool.join("foo_1.0_amd64.deb"), "1.0").unwrap();
+
+        create_snapshot(root, repo, "bionic", "qa").unwrap();
+        assert!(create_snapshot(root, repo, "bionic", "qa").is_err());
+        assert!(create_snapshot(root, repo, "bionic", "../qa").is_err());
+
+        let captured = root.join("snapshots/qa/pool/bionic/main/f/foo/foo_1.0_amd64.deb");
+        let ino = |path: &Path| fs::metadata(path).unwrap().ino();
+        assert_eq!(ino(&captured), ino(&pool.join("foo_1.0_amd64.deb")));
+
+        assert_eq!(list_snapshots(root).unwrap(), vec![
+            Snapshot { name: "qa".into(), suites: vec!["bionic".into()] }
+        ]);
+
+        // The dists are regenerated into new files, and superseded packages are removed.
+        fs::remove_file(dists.join("Release")).unwrap();
+        misc::write(dists.join("Release"), "Suite: bionic\nVersion: 2\n").unwrap();
+        fs::remove_file(pool.join("foo_1.0_amd64.deb")).unwrap();
+        misc::write(pool.join("foo_2.0_amd64.deb"), "2.0").unwrap();
+
+        publish_snapshot(root, repo, "bionic", "qa").unwrap();
+        assert_eq!(misc::read_to_string(dists.join("Release")).unwrap(), "Suite: bionic\n");
+        assert!(pool.join("foo_1.0_amd64.deb").exists());
+        assert!(!pool.join("foo_2.0_amd64.deb").exists());
+        assert!(publish_snapshot(root, repo, "cosmic", "qa").is_err());
+    }
+}
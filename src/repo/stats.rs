@@ -0,0 +1,308 @@
+use chrono::DateTime;
+use debian::parse_control;
+use misc;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use super::queue::QUEUE;
+use super::store::{Store, SuiteCounters, STORE};
+use walkdir::WalkDir;
+
+/// The number of packages indexed for an architecture of a suite's component.
+#[derive(Debug, PartialEq)]
+pub struct PackageCount {
+    pub suite:     String,
+    pub component: String,
+    pub arch:      String,
+    pub count:     u64,
+}
+
+/// Statistics describing the current state of a repo.
+#[derive(Debug, Default, PartialEq)]
+pub struct RepoStats {
+    /// The combined size of every file in the pool.
+    pub pool_bytes: u64,
+    pub packages: Vec<PackageCount>,
+    /// The `Valid-Until` time of each suite's `Release`, as a UNIX timestamp.
+    pub valid_until: Vec<(String, i64)>,
+}
+
+/// Collects statistics from the pool and dist files of a repo.
+pub fn repo_stats(repo: &Path) -> io::Result<RepoStats> {
+    let mut stats = RepoStats::default();
+
+    let pool = repo.join("pool");
+    if pool.exists() {
+        for entry in WalkDir::new(&pool).into_iter().flat_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                stats.pool_bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+        }
+    }
+
+    let dists = repo.join("dists");
+    if !dists.exists() {
+        return Ok(stats);
+    }
+
+    let mut suites = dists.read_dir()?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    suites.sort();
+
+    for suite_path in suites.into_iter().filter(|path| path.is_dir()) {
+        let suite = match suite_path.file_name().and_then(|x| x.to_str()) {
+            Some(suite) => suite.to_owned(),
+            None => continue
+        };
+
+        // Skips suites which are being staged for, or retired from, publishing.
+        if suite.ends_with(".new") || suite.ends_with(".old") {
+            continue
+        }
+
+        let release = suite_path.join("Release");
+        if release.exists() {
+            let control = parse_control(&misc::read_to_string(&release)?);
+            let valid_until = control.get("Valid-Until")
+                .and_then(|date| DateTime::parse_from_rfc2822(&date.replace("UTC", "+0000")).ok());
+            if let Some(date) = valid_until {
+                stats.valid_until.push((suite.clone(), date.timestamp()));
+            }
+        }
+
+        let indices = WalkDir::new(&suite_path)
+            .min_depth(3)
+            .max_depth(3)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .flat_map(|e| e.ok())
+            .filter(|e| e.file_name() == "Packages");
+
+        for index in indices {
+            let relative = index.path().strip_prefix(&suite_path).unwrap();
+            let mut components = relative.iter().filter_map(|x| x.to_str());
+            let (component, arch) = match (components.next(), components.next()) {
+                (Some(component), Some(arch)) if arch.starts_with("binary-") => (component, &arch[7..]),
+                _ => continue
+            };
+
+            let count = count_entries(index.path())?;
+
+            stats.packages.push(PackageCount {
+                suite: suite.clone(),
+                component: component.to_owned(),
+                arch: arch.to_owned(),
+                count,
+            });
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Counts the package entries within an uncompressed `Packages` or `Sources` index.
+fn count_entries(index: &Path) -> io::Result<u64> {
+    Ok(misc::read_to_string(index)?
+        .lines()
+        .filter(|line| line.starts_with("Package:"))
+        .count() as u64)
+}
+
+/// Finds the `Packages` and `Sources` indices of a suite's dist directory which have no entries.
+///
+/// An empty index is valid, but often means that an architecture was configured and never built.
+pub fn empty_indices(dist: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut empty = Vec::new();
+
+    let indices = WalkDir::new(dist)
+        .min_depth(3)
+        .max_depth(3)
+        .sort_by(|a, b| a.path().cmp(b.path()))
+        .into_iter()
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_name() == "Packages" || e.file_name() == "Sources");
+
+    for index in indices {
+        if count_entries(index.path())? == 0 {
+            let relative = index.path().strip_prefix(dist).unwrap();
+            warn!("{} has no packages: was this intended?", relative.display());
+            empty.push(index.path().to_path_buf());
+        }
+    }
+
+    Ok(empty)
+}
+
+/// Writes the statistics of a repo as gauges in the Prometheus text exposition format.
+pub fn metrics_prometheus<W: Write>(repo: &Path, out: &mut W) -> io::Result<()> {
+    let stats = repo_stats(repo)?;
+
+    writeln!(out, "# HELP debrep_pool_bytes Combined size of the files in the pool.")?;
+    writeln!(out, "# TYPE debrep_pool_bytes gauge")?;
+    writeln!(out, "debrep_pool_bytes {}", stats.pool_bytes)?;
+
+    writeln!(out, "# HELP debrep_package_count Number of packages in each binary index.")?;
+    writeln!(out, "# TYPE debrep_package_count gauge")?;
+    for package in &stats.packages {
+        writeln!(
+            out,
+            "debrep_package_count{{suite=\"{}\",component=\"{}\",arch=\"{}\"}} {}",
+            escape(&package.suite),
+            escape(&package.component),
+            escape(&package.arch),
+            package.count
+        )?;
+    }
+
+    writeln!(out, "# HELP debrep_release_valid_until_seconds Time when the suite's Release expires.")?;
+    writeln!(out, "# TYPE debrep_release_valid_until_seconds gauge")?;
+    for &(ref suite, valid_until) in &stats.valid_until {
+        writeln!(out, "debrep_release_valid_until_seconds{{suite=\"{}\"}} {}", escape(suite), valid_until)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the metrics of the suites of a project, followed by the statistics of its repo: the
+/// builds and publishes of each suite, as recorded by the store of the project, and the number
+/// of sources held in the queue of each suite.
+pub fn project_metrics<W: Write>(root: &Path, repo: &Path, suites: &[String], out: &mut W) -> io::Result<()> {
+    let store = Store::open(&root.join(STORE))?;
+    let counters = |suite: &str| store.counters.get(suite).cloned().unwrap_or_else(SuiteCounters::default);
+
+    writeln!(out, "# HELP debrep_builds_succeeded_total Builds of each suite which succeeded.")?;
+    writeln!(out, "# TYPE debrep_builds_succeeded_total counter")?;
+    for suite in suites {
+        writeln!(out, "debrep_builds_succeeded_total{{suite=\"{}\"}} {}", escape(suite), counters(suite).builds_succeeded)?;
+    }
+
+    writeln!(out, "# HELP debrep_builds_failed_total Builds of each suite which failed.")?;
+    writeln!(out, "# TYPE debrep_builds_failed_total counter")?;
+    for suite in suites {
+        writeln!(out, "debrep_builds_failed_total{{suite=\"{}\"}} {}", escape(suite), counters(suite).builds_failed)?;
+    }
+
+    writeln!(out, "# HELP debrep_last_publish_timestamp_seconds Time when the suite was last published.")?;
+    writeln!(out, "# TYPE debrep_last_publish_timestamp_seconds gauge")?;
+    for suite in suites {
+        if let Some(published) = counters(suite).last_published {
+            writeln!(out, "debrep_last_publish_timestamp_seconds{{suite=\"{}\"}} {}", escape(suite), published)?;
+        }
+    }
+
+    writeln!(out, "# HELP debrep_queue_depth Number of sources held in the queue of each suite.")?;
+    writeln!(out, "# TYPE debrep_queue_depth gauge")?;
+    for suite in suites {
+        writeln!(out, "debrep_queue_depth{{suite=\"{}\"}} {}", escape(suite), queue_depth(&root.join(QUEUE).join(suite))?)?;
+    }
+
+    metrics_prometheus(repo, out)
+}
+
+/// Counts the records of the sources which are held in the queue of a suite.
+fn queue_depth(queue: &Path) -> io::Result<u64> {
+    if !queue.is_dir() {
+        return Ok(0);
+    }
+
+    let mut depth = 0;
+    for entry in queue.read_dir()? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().map_or(false, |ext| ext == "queued") {
+            depth += 1;
+        }
+    }
+
+    Ok(depth)
+}
+
+/// Escapes a label value for the exposition format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use tempfile::tempdir;
+
+    #[test]
+    fn empty_index_detection() {
+        let dir = tempdir().unwrap();
+        for &(index, contents) in &[
+            ("main/binary-amd64/Packages", "Package: foo\nVersion: 1.0\n"),
+            ("main/binary-arm64/Packages", ""),
+            ("main/source/Sources", "Package: foo\nBinary: foo\n"),
+        ] {
+            let path = dir.path().join(index);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            misc::write(path, contents).unwrap();
+        }
+
+        assert_eq!(empty_indices(dir.path()).unwrap(), vec![dir.path().join("main/binary-arm64/Packages")]);
+    }
+
+    #[test]
+    fn prometheus_exposition() {
+        let dir = tempdir().unwrap();
+        let binary = dir.path().join("dists/bionic/main/binary-amd64");
+        let pool = dir.path().join("pool/bionic/main/binary-amd64/f/foo");
+        fs::create_dir_all(&binary).unwrap();
+        fs::create_dir_all(&pool).unwrap();
+        misc::write(pool.join("foo_1.0_amd64.deb"), &[0u8; 100][..]).unwrap();
+        misc::write(binary.join("Packages"), "Package: foo\nVersion: 1.0\n\nPackage: bar\nVersion: 1.0\n").unwrap();
+        misc::write(
+            dir.path().join("dists/bionic/Release"),
+            "Suite: bionic\nValid-Until: Thu, 01 Jan 2037 00:00:00 UTC\n"
+        ).unwrap();
+
+        // The previous dists of the suite are not a suite of their own.
+        let previous = dir.path().join("dists/bionic.old/main/binary-amd64");
+        fs::create_dir_all(&previous).unwrap();
+        misc::write(previous.join("Packages"), "Package: foo\nVersion: 0.9\n").unwrap();
+
+        let mut out = Vec::new();
+        metrics_prometheus(dir.path(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let sample = Regex::new(r#"^[a-zA-Z_:][a-zA-Z0-9_:]*(\{([a-zA-Z_][a-zA-Z0-9_]*="[^"]*",?)*\})? -?[0-9]+$"#).unwrap();
+        for line in out.lines().filter(|line| !line.starts_with('#')) {
+            assert!(sample.is_match(line), "invalid sample: {}", line);
+        }
+
+        assert!(out.contains("debrep_pool_bytes 100\n"));
+        assert!(out.contains("debrep_package_count{suite=\"bionic\",component=\"main\",arch=\"amd64\"} 2\n"));
+        assert!(out.contains("debrep_release_valid_until_seconds{suite=\"bionic\"} 2114380800\n"));
+        assert!(!out.contains("bionic.old"));
+    }
+
+    #[test]
+    fn project_exposition() {
+        let dir = tempdir().unwrap();
+        let mut store = Store::default();
+        store.record_outcomes("bionic", 4, 1);
+        store.record_publish("bionic", 1_540_000_000);
+        fs::create_dir_all(dir.path().join("record")).unwrap();
+        store.save(&dir.path().join(STORE)).unwrap();
+
+        let queue = dir.path().join(QUEUE).join("bionic");
+        fs::create_dir_all(queue.join("foo")).unwrap();
+        misc::write(queue.join("foo.queued"), "Source: foo\n").unwrap();
+
+        let suites = vec!["bionic".to_owned(), "cosmic".to_owned()];
+        let mut out = Vec::new();
+        project_metrics(dir.path(), &dir.path().join("repo"), &suites, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("debrep_builds_succeeded_total{suite=\"bionic\"} 4\n"));
+        assert!(out.contains("debrep_builds_failed_total{suite=\"bionic\"} 1\n"));
+        assert!(out.contains("debrep_builds_failed_total{suite=\"cosmic\"} 0\n"));
+        assert!(out.contains("debrep_last_publish_timestamp_seconds{suite=\"bionic\"} 1540000000\n"));
+        assert!(!out.contains("debrep_last_publish_timestamp_seconds{suite=\"cosmic\"}"));
+        assert!(out.contains("debrep_queue_depth{suite=\"bionic\"} 1\n"));
+        assert!(out.contains("debrep_queue_depth{suite=\"cosmic\"} 0\n"));
+        assert!(out.contains("debrep_pool_bytes 0\n"));
+    }
+}
@@ -0,0 +1,181 @@
+use checksum::CachedDigests;
+use config::BuilderBackend;
+use misc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use super::layout::PoolLayout;
+use toml;
+
+/// The state of every build of the project, which is kept beside the records of each suite.
+pub const STORE: &str = "record/state.toml";
+
+/// What was known of a source once it was last built, and moved into the pool.
+#[derive(Debug, Deserialize, Clone, Serialize, PartialEq)]
+pub struct BuildRecord {
+    /// The version of the source, according to its `.changes` files.
+    pub version: String,
+    /// When the build finished, in seconds since the Unix epoch.
+    pub built: u64,
+    /// The digest of the inputs that the source was built from, as compared by incremental builds.
+    pub inputs: Option<String>,
+    pub backend: BuilderBackend,
+    /// The chroot of each architecture that the source was built within, or `host` for dpkg.
+    pub chroots: Vec<String>,
+    /// The SHA256 digest of each file that the build produced, by its filename.
+    pub artifacts: BTreeMap<String, String>,
+}
+
+/// What is counted of the runs of a suite, which is exposed at `/metrics`.
+#[derive(Debug, Default, Deserialize, Clone, Serialize, PartialEq)]
+#[serde(default)]
+pub struct SuiteCounters {
+    pub builds_succeeded: u64,
+    pub builds_failed: u64,
+    /// When the suite was last published, in seconds since the Unix epoch.
+    pub last_published: Option<u64>,
+}
+
+/// A small store of the builds of each suite, by suite and then by source, and of the digests of
+/// the files of the pool.
+///
+/// The store is a TOML file, as the records of each suite are, which is rewritten as a whole
+/// whenever a build is recorded, or the dist files are generated.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Store {
+    #[serde(default)]
+    pub builds: BTreeMap<String, BTreeMap<String, BuildRecord>>,
+    /// The digests of the files of the pool, by their canonical path, which are reused until the
+    /// file changes.
+    #[serde(default)]
+    pub digests: BTreeMap<String, CachedDigests>,
+    /// The layout that the pool of each suite was last generated with, by its config.
+    #[serde(default)]
+    pub layouts: BTreeMap<String, PoolLayout>,
+    /// The links which were left at the previous paths of migrated files, within the repo, and
+    /// when they expire, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub transitional: BTreeMap<String, u64>,
+    /// The sources of each suite whose builds were interrupted, which are built again by the next
+    /// run, rather than having the files that they left behind pooled.
+    #[serde(default)]
+    pub interrupted: BTreeMap<String, BTreeSet<String>>,
+    /// The counts of the builds of each suite, and when it was last published.
+    #[serde(default)]
+    pub counters: BTreeMap<String, SuiteCounters>,
+}
+
+impl Store {
+    /// Opens the store, which is empty if it does not exist yet.
+    pub fn open(path: &Path) -> io::Result<Store> {
+        if !path.exists() {
+            return Ok(Store::default());
+        }
+
+        toml::from_slice(&misc::read(path)?).map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse the build state at {}: {}", path.display(), why)
+        ))
+    }
+
+    /// Writes the store beside its path, and renames it into place, so that an interrupted write
+    /// never leaves a partial store behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let data = toml::to_vec(self).map_err(|why| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to serialize the build state: {}", why)
+        ))?;
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".partial");
+        misc::write(&temporary, &data)?;
+        fs::rename(&temporary, path)
+    }
+
+    pub fn record_build(&mut self, suite: &str, source: &str, record: BuildRecord) {
+        self.builds.entry(suite.to_owned()).or_insert_with(BTreeMap::new)
+            .insert(source.to_owned(), record);
+    }
+
+    pub fn record_interruption(&mut self, suite: &str, source: &str) {
+        self.interrupted.entry(suite.to_owned()).or_insert_with(BTreeSet::new)
+            .insert(source.to_owned());
+    }
+
+    /// Counts the builds of a run of the suite which succeeded, and those which failed.
+    pub fn record_outcomes(&mut self, suite: &str, succeeded: u64, failed: u64) {
+        let counters = self.counters.entry(suite.to_owned()).or_insert_with(SuiteCounters::default);
+        counters.builds_succeeded += succeeded;
+        counters.builds_failed += failed;
+    }
+
+    pub fn record_publish(&mut self, suite: &str, published: u64) {
+        self.counters.entry(suite.to_owned()).or_insert_with(SuiteCounters::default)
+            .last_published = Some(published);
+    }
+
+    /// Whether the last build of the source was interrupted, which is forgotten once it is resumed.
+    pub fn resume(&mut self, suite: &str, source: &str) -> bool {
+        let resumed = self.interrupted.get_mut(suite).map_or(false, |sources| sources.remove(source));
+        if self.interrupted.get(suite).map_or(false, |sources| sources.is_empty()) {
+            self.interrupted.remove(suite);
+        }
+
+        resumed
+    }
+
+    /// The source which produced a file of the suite, and the digest that it was built with.
+    pub fn provenance(&self, suite: &str, filename: &str) -> Option<(&str, &str)> {
+        self.builds.get(suite)?.iter().find_map(|(source, record)| {
+            record.artifacts.get(filename).map(|sha256| (source.as_str(), sha256.as_str()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn build_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.toml");
+        let mut store = Store::open(&path).unwrap();
+        assert_eq!(store, Store::default());
+
+        let mut artifacts = BTreeMap::new();
+        artifacts.insert("foo_1.0_amd64.deb".to_owned(), "abc123".to_owned());
+        store.record_build("bionic", "foo", BuildRecord {
+            version: "1.0".into(),
+            built: 1_540_000_000,
+            inputs: Some("def456".into()),
+            backend: BuilderBackend::Sbuild,
+            chroots: vec!["bionic-amd64-sbuild".into()],
+            artifacts,
+        });
+
+        store.record_interruption("bionic", "bar");
+        store.record_outcomes("bionic", 2, 1);
+        store.record_outcomes("bionic", 1, 0);
+        store.record_publish("bionic", 1_540_000_100);
+        store.save(&path).unwrap();
+        let mut store = Store::open(&path).unwrap();
+        assert_eq!(store.builds["bionic"]["foo"].version, "1.0");
+        assert!(store.builds.get("cosmic").is_none());
+        assert_eq!(store.counters["bionic"], SuiteCounters {
+            builds_succeeded: 3,
+            builds_failed: 1,
+            last_published: Some(1_540_000_100),
+        });
+        assert_eq!(store.provenance("bionic", "foo_1.0_amd64.deb"), Some(("foo", "abc123")));
+        assert_eq!(store.provenance("bionic", "bar_1.0_amd64.deb"), None);
+
+        // An interrupted build is only resumed once.
+        assert!(!store.resume("bionic", "foo"));
+        assert!(store.resume("bionic", "bar"));
+        assert!(!store.resume("bionic", "bar"));
+        assert!(store.interrupted.is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+use config::{Config, SigningBackend, TestApt, Upstream};
+use misc;
+use serve::serve;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile;
+
+/// The outcome of a step of the smoke test of a suite.
+#[derive(Debug, PartialEq)]
+pub struct AptStep {
+    pub architecture: String,
+    /// The step, which is either `update`, or the installation of a package.
+    pub step: String,
+    /// Why apt failed the step, if it did.
+    pub error: Option<String>,
+}
+
+impl fmt::Display for AptStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error {
+            Some(ref why) => write!(f, "{} {} failed: {}", self.architecture, self.step, why),
+            None => write!(f, "{} {} passed", self.architecture, self.step),
+        }
+    }
+}
+
+/// Smoke tests the published suite end to end, by serving its repo, and running `apt-get update`
+/// and `apt-get install --download-only` for each of the packages with each architecture.
+///
+/// Apt is given a root of its own, with an empty dpkg status, so that the signature of the suite
+/// is verified, and the packages are resolved, as they would be on a fresh install. The packages
+/// of the config are tested unless packages are given.
+pub fn test_apt(config: &Config, packages: &[&str]) -> io::Result<Vec<AptStep>> {
+    let default = TestApt::default();
+    let test = config.test_apt.as_ref().unwrap_or(&default);
+    let packages = if packages.is_empty() {
+        test.packages.iter().map(|x| x.as_str()).collect::<Vec<&str>>()
+    } else {
+        packages.to_vec()
+    };
+
+    if packages.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no packages were given to install from {}", config.archive)
+        ));
+    }
+
+    let root = tempfile::tempdir()?;
+    let keyring = match test.keyring {
+        Some(ref keyring) => keyring.canonicalize()?,
+        None => export_key(config, root.path())?,
+    };
+
+    let server = serve(config.repo_path(), "127.0.0.1:0".parse().unwrap(), None, None)?;
+    let upstream = test.upstream.as_ref()
+        .or_else(|| config.closure.as_ref().map(|closure| &closure.upstream));
+
+    let sources = sources_list(
+        &format!("http://{}/", server.addr()),
+        &config.archive,
+        &config.components(),
+        &keyring,
+        upstream.map_or(&[][..], |x| x.as_slice())
+    );
+
+    let architectures = test.architectures.as_ref()
+        .map(|x| x.iter().map(|x| x.as_str()).collect())
+        .unwrap_or_else(|| config.release_architectures());
+
+    let mut steps = Vec::new();
+    for arch in architectures {
+        let apt = AptRoot::new(&root.path().join(arch), &sources, arch)?;
+        let step = |step: String, error| AptStep { architecture: arch.to_owned(), step, error };
+
+        let updated = apt.run(&["update"]);
+        let failed = updated.is_err();
+        steps.push(step("update".into(), updated.err()));
+        if failed {
+            continue
+        }
+
+        for &package in &packages {
+            let args = ["install", "--download-only", "--no-install-recommends", package];
+            let installed = apt.run(&args);
+            steps.push(step(["install ", package].concat(), installed.err()));
+        }
+    }
+
+    server.shutdown();
+    Ok(steps)
+}
+
+/// The `sources.list` of the suite, which is verified by its keyring, and of the upstream
+/// repositories, which are verified by the keys that the host trusts.
+fn sources_list(
+    url: &str,
+    suite: &str,
+    components: &[&str],
+    keyring: &Path,
+    upstream: &[Upstream]
+) -> String {
+    let mut sources = format!(
+        "deb [signed-by={}] {} {} {}\n",
+        keyring.display(),
+        url,
+        suite,
+        components.join(" ")
+    );
+
+    for upstream in upstream {
+        sources.push_str(&format!(
+            "deb {} {} {}\n",
+            upstream.url,
+            upstream.suite,
+            upstream.components.join(" ")
+        ));
+    }
+
+    sources
+}
+
+/// Exports the public key which signs the suite from gpg, for apt to verify the suite with.
+fn export_key(config: &Config, directory: &Path) -> io::Result<PathBuf> {
+    if config.signing.as_ref().map_or(false, |signing| signing.backend == SigningBackend::Native) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "test_apt.keyring must be given when the suite is signed by the native backend"
+        ));
+    }
+
+    let keyring = directory.join("repo.gpg");
+    let status = Command::new("gpg")
+        .args(&["--batch", "--yes", "--output"])
+        .arg(&keyring)
+        .args(&["--export", config.signing_key()])
+        .status()?;
+
+    if !status.success() || keyring.metadata().map_or(true, |metadata| metadata.len() == 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to export the public key of {} from gpg", config.signing_key())
+        ));
+    }
+
+    Ok(keyring)
+}
+
+/// The directories of an apt root for one architecture, which apt is pointed to by its options.
+struct AptRoot {
+    options: Vec<String>,
+}
+
+impl AptRoot {
+    fn new(root: &Path, sources: &str, arch: &str) -> io::Result<Self> {
+        for directory in &["sources.list.d", "preferences.d", "lists/partial", "archives/partial"] {
+            fs::create_dir_all(root.join(directory))?;
+        }
+
+        misc::write(root.join("sources.list"), sources.as_bytes())?;
+        misc::write(root.join("status"), b"")?;
+
+        let path = |name: &str| root.join(name).to_string_lossy().into_owned();
+        let options = vec![
+            ["Dir::Etc::SourceList=", &path("sources.list")].concat(),
+            ["Dir::Etc::SourceParts=", &path("sources.list.d")].concat(),
+            ["Dir::Etc::PreferencesParts=", &path("preferences.d")].concat(),
+            ["Dir::Etc::Trusted=", &path("trusted.gpg")].concat(),
+            ["Dir::State::Lists=", &path("lists")].concat(),
+            ["Dir::State::status=", &path("status")].concat(),
+            ["Dir::Cache=", &path("cache")].concat(),
+            ["Dir::Cache::Archives=", &path("archives")].concat(),
+            ["APT::Architecture=", arch].concat(),
+            ["APT::Architectures=", arch].concat(),
+            "APT::Update::Error-Mode=any".into(),
+            "Acquire::Languages=none".into(),
+            "Debug::NoLocking=true".into(),
+        ];
+
+        Ok(AptRoot { options })
+    }
+
+    /// Runs `apt-get` with the root, returning the errors that it reported if it fails.
+    fn run(&self, args: &[&str]) -> Result<(), String> {
+        let mut command = Command::new("apt-get");
+        command.args(&["--yes", "--quiet"]);
+        for option in &self.options {
+            command.arg("-o").arg(option);
+        }
+
+        let output = command.args(args).output()
+            .map_err(|why| format!("failed to run apt-get: {}", why))?;
+
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let errors = stderr.lines()
+            .filter(|line| line.starts_with("E:"))
+            .map(|line| line.trim_left_matches("E:").trim())
+            .collect::<Vec<&str>>();
+
+        Err(if errors.is_empty() { stderr.trim().to_owned() } else { errors.join("; ") })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apt_sources() {
+        let upstream = Upstream {
+            url: "http://archive.ubuntu.com/ubuntu".into(),
+            suite: "bionic".into(),
+            keyring: "/usr/share/keyrings/ubuntu-archive-keyring.gpg".into(),
+            components: vec!["main".into(), "universe".into()],
+        };
+
+        let sources = sources_list(
+            "http://127.0.0.1:8080/",
+            "bionic",
+            &["main", "proprietary"],
+            Path::new("/tmp/repo.gpg"),
+            &[upstream]
+        );
+
+        assert_eq!(sources, [
+            "deb [signed-by=/tmp/repo.gpg] http://127.0.0.1:8080/ bionic main proprietary\n",
+            "deb http://archive.ubuntu.com/ubuntu bionic main universe\n",
+        ].concat());
+    }
+}
@@ -0,0 +1,261 @@
+use checksum::hasher;
+use debian::{parse_control, Control, DEB_SOURCE_EXTENSIONS};
+use libflate::gzip::Decoder as GzDecoder;
+use misc;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use super::integrity::audit_release;
+use super::store::Store;
+use walkdir::WalkDir;
+use xz2::read::XzDecoder;
+
+/// A discrepancy between the pool of a suite and the indices which describe it.
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    /// An index which does not match its entry in the `Release` file.
+    Release(PathBuf),
+    /// A file which is listed by an index, but is not in the pool.
+    Missing { path: PathBuf, index: PathBuf },
+    /// A file whose size or digest differs from its entry in an index.
+    Mismatched { path: PathBuf, index: PathBuf },
+    /// A package in the pool which no index lists.
+    Orphaned(PathBuf),
+    /// A package whose digest differs from the one that its source was recorded to build.
+    Provenance { path: PathBuf, source: String },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Drift::Release(ref path) => {
+                write!(f, "{} does not match its entry in the Release file", path.display())
+            }
+            Drift::Missing { ref path, ref index } => {
+                write!(f, "{} is listed by {}, but is missing", path.display(), index.display())
+            }
+            Drift::Mismatched { ref path, ref index } => {
+                write!(f, "{} does not match its entry in {}", path.display(), index.display())
+            }
+            Drift::Orphaned(ref path) => write!(f, "{} is not listed by any index", path.display()),
+            Drift::Provenance { ref path, ref source } => {
+                write!(f, "{} is not the package that {} was built as", path.display(), source)
+            }
+        }
+    }
+}
+
+/// Re-walks the pool of a published suite, recomputing the digest of each file, and cross-checks
+/// every size and digest that the suite's `Packages`, `Sources`, and `Release` files record.
+///
+/// Files which are listed but missing, files which differ from their entries, and packages in the
+/// pool which no index lists are returned, along with packages which differ from the artifacts
+/// that the store recorded for their builds.
+pub fn verify(repo: &Path, suite: &str, store: &Store) -> io::Result<Vec<Drift>> {
+    let dist = repo.join("dists").join(suite);
+    let pool = repo.join("pool").join(suite);
+    info!("verifying {} against the indices of {}", pool.display(), dist.display());
+
+    let mut drift = audit_release(&dist)?.into_iter().map(Drift::Release).collect::<Vec<Drift>>();
+
+    // Files may be listed by several indices, such as those of `Architecture: all` packages, so
+    // the digest of each file is only computed once.
+    let mut digests: HashMap<PathBuf, Option<(u64, String)>> = HashMap::new();
+
+    for (index, data) in indices(&dist)? {
+        let stanzas = data.split("\n\n").map(|x| x.trim()).filter(|x| !x.is_empty());
+        for control in stanzas.map(parse_control) {
+            for (path, size, sha256) in listed_files(&control) {
+                let path = repo.join(path);
+                if !digests.contains_key(&path) {
+                    let actual = if path.is_file() { Some(digest(&path)?) } else { None };
+                    digests.insert(path.clone(), actual);
+                }
+
+                match digests[&path].clone() {
+                    None => drift.push(Drift::Missing { path, index: index.clone() }),
+                    Some((actual_size, actual)) => {
+                        if size != actual_size.to_string() || sha256 != actual {
+                            drift.push(Drift::Mismatched { path, index: index.clone() });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let packages = WalkDir::new(&pool)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.file_name().to_str().map_or(false, is_package));
+
+    for entry in packages {
+        if !digests.contains_key(entry.path()) {
+            drift.push(Drift::Orphaned(entry.path().to_path_buf()));
+        }
+
+        let filename = entry.file_name().to_str().unwrap_or("");
+        if let Some((source, recorded)) = store.provenance(suite, filename) {
+            let actual = match digests.get(entry.path()) {
+                Some(&Some((_, ref sha256))) => sha256.clone(),
+                _ => digest(entry.path())?.1,
+            };
+
+            if actual != recorded {
+                let path = entry.path().to_path_buf();
+                drift.push(Drift::Provenance { path, source: source.to_owned() });
+            }
+        }
+    }
+
+    Ok(drift)
+}
+
+/// The packages and source packages of the pool, which should each be listed by an index.
+fn is_package(filename: &str) -> bool {
+    [".deb", ".ddeb", ".udeb"].iter().any(|ext| filename.ends_with(ext))
+        || DEB_SOURCE_EXTENSIONS.iter().any(|ext| filename.ends_with(ext))
+}
+
+/// The path, size, and SHA256 digest of each file that a `Packages` or `Sources` entry lists.
+pub(super) fn listed_files(control: &Control) -> Vec<(String, String, String)> {
+    if let (Some(filename), Some(size), Some(sha256)) =
+        (control.get("Filename"), control.get("Size"), control.get("SHA256"))
+    {
+        return vec![(filename.clone(), size.clone(), sha256.clone())];
+    }
+
+    match (control.get("Directory"), control.get("Checksums-Sha256")) {
+        (Some(directory), Some(files)) => files.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(sha256), Some(size), Some(name)) => Some((
+                        [directory.as_str(), "/", name].concat(),
+                        size.to_owned(),
+                        sha256.to_owned()
+                    )),
+                    _ => None
+                }
+            })
+            .collect(),
+        _ => Vec::new()
+    }
+}
+
+/// Reads every `Packages` and `Sources` index of the dist directory, preferring the
+/// uncompressed variant of each.
+pub(super) fn indices(dist: &Path) -> io::Result<Vec<(PathBuf, String)>> {
+    let directories = WalkDir::new(dist)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|e| e.file_name() != "by-hash" && e.file_name() != "Packages.diff")
+        .flat_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
+        .map(|e| e.path().to_path_buf())
+        .collect::<Vec<PathBuf>>();
+
+    let mut indices = Vec::new();
+    for directory in directories {
+        for name in &["Packages", "Sources"] {
+            if let Some(index) = read_index(&directory, name)? {
+                indices.push(index);
+            }
+        }
+    }
+
+    Ok(indices)
+}
+
+pub(super) fn read_index(directory: &Path, name: &str) -> io::Result<Option<(PathBuf, String)>> {
+    for extension in &["", ".gz", ".xz"] {
+        let path = directory.join([name, extension].concat());
+        if !path.is_file() {
+            continue
+        }
+
+        let data = match *extension {
+            "" => misc::read_to_string(&path)?,
+            ".gz" => decode(GzDecoder::new(File::open(&path)?)?)?,
+            _ => decode(XzDecoder::new(File::open(&path)?))?,
+        };
+
+        return Ok(Some((path, data)));
+    }
+
+    Ok(None)
+}
+
+fn decode<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut data = String::new();
+    reader.read_to_string(&mut data)?;
+    Ok(data)
+}
+
+fn digest(path: &Path) -> io::Result<(u64, String)> {
+    let size = path.metadata()?.len();
+    let sha256 = File::open(path).and_then(hasher::<Sha256, File>)?;
+    Ok((size, sha256))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+    use toml;
+
+    #[test]
+    fn pool_drift() {
+        let dir = tempdir().unwrap();
+        let repo = dir.path();
+        let package = repo.join("pool/bionic/main/binary-amd64/f/foo");
+        let binary = repo.join("dists/bionic/main/binary-amd64");
+        fs::create_dir_all(&package).unwrap();
+        fs::create_dir_all(&binary).unwrap();
+
+        misc::write(package.join("foo_1.0_amd64.deb"), b"foo").unwrap();
+        misc::write(package.join("foo_0.9_amd64.deb"), b"old").unwrap();
+        misc::write(package.join("bar_1.0_amd64.deb"), b"changed").unwrap();
+        let (_, sha256) = digest(&package.join("foo_1.0_amd64.deb")).unwrap();
+
+        let entry = |name: &str| format!(
+            "Package: {}\nFilename: {}/{}_1.0_amd64.deb\nSize: 3\nSHA256: {}\n",
+            name,
+            "pool/bionic/main/binary-amd64/f/foo",
+            name,
+            sha256
+        );
+
+        let index = binary.join("Packages");
+        misc::write(&index, [entry("foo"), entry("bar"), entry("baz")].join("\n")).unwrap();
+        misc::write(repo.join("dists/bionic/Release"), b"Suite: bionic\n").unwrap();
+
+        assert_eq!(verify(repo, "bionic", &Store::default()).unwrap(), vec![
+            Drift::Mismatched { path: package.join("bar_1.0_amd64.deb"), index: index.clone() },
+            Drift::Missing { path: package.join("baz_1.0_amd64.deb"), index: index.clone() },
+            Drift::Orphaned(package.join("foo_0.9_amd64.deb")),
+        ]);
+
+        // A package which was replaced after it was built no longer matches its build.
+        let store: Store = toml::from_str(&format!(r#"
+            [builds.bionic.foo]
+            version = "1.0"
+            built = 1540000000
+            backend = "sbuild"
+            chroots = ["bionic-amd64-sbuild"]
+            artifacts = {{ "foo_1.0_amd64.deb" = "{}", "bar_1.0_amd64.deb" = "{}" }}
+        "#, sha256, sha256)).unwrap();
+
+        assert_eq!(verify(repo, "bionic", &store).unwrap(), vec![
+            Drift::Mismatched { path: package.join("bar_1.0_amd64.deb"), index: index.clone() },
+            Drift::Missing { path: package.join("baz_1.0_amd64.deb"), index: index.clone() },
+            Drift::Provenance { path: package.join("bar_1.0_amd64.deb"), source: "foo".into() },
+            Drift::Orphaned(package.join("foo_0.9_amd64.deb")),
+        ]);
+    }
+}
@@ -0,0 +1,308 @@
+use command::Command;
+use config::Config;
+use libc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::ptr;
+use std::time::Duration;
+use walkdir::WalkDir;
+
+/// How long the tree must be quiet after a change before rebuilding, so that a burst of changes,
+/// such as an editor's save or a checkout, is rebuilt once.
+pub(super) const SETTLE: Duration = Duration::from_millis(500);
+
+/// The directories which are watched, where they exist.
+const WATCHED: &[&str] = &[
+    "suites",
+    "debian",
+    "patches",
+    "metapackages",
+    "assets/packages",
+    "assets/share",
+];
+
+const MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+/// A rebuild of a suite, which is either of the given sources, or of the entire suite.
+#[derive(Debug, PartialEq)]
+struct Rebuild {
+    suite: String,
+    sources: Option<BTreeSet<String>>,
+}
+
+/// Watches the configs, and the debian overlays, patches, and assets of their sources, and
+/// rebuilds what they affect as they change, until interrupted.
+///
+/// Each rebuild runs as `debrep build` with the given arguments, so that a failed build is
+/// reported without ending the watch. As builds are incremental, only the sources whose files
+/// changed are rebuilt, and the dist files of the suite are regenerated afterwards.
+pub fn watch(configs: &[Config], args: &[String]) -> io::Result<()> {
+    let mut inotify = Inotify::new()?;
+    for directory in WATCHED.iter().map(Path::new).filter(|x| x.is_dir()) {
+        inotify.watch_tree(directory)?;
+    }
+
+    let suites = configs.iter()
+        .map(|config| {
+            let sources = config.source.iter()
+                .flat_map(|x| x.iter())
+                .map(|source| source.name.as_str())
+                .collect();
+
+            (config.archive.as_str(), sources)
+        })
+        .collect::<Vec<(&str, Vec<&str>)>>();
+
+    info!("watching for changes to {} suites", suites.len());
+    loop {
+        let mut changes = inotify.read(None)?;
+        loop {
+            let more = inotify.read(Some(SETTLE))?;
+            if more.is_empty() {
+                break
+            }
+
+            changes.extend(more);
+        }
+
+        for rebuild in plan(&changes, &suites) {
+            match rebuild.sources {
+                Some(ref sources) => {
+                    let sources = sources.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+                    info!("rebuilding {} in {}", sources.join(", "), rebuild.suite);
+                }
+                None => info!("rebuilding {}", rebuild.suite),
+            }
+
+            let mut command = Command::new(env::current_exe()?);
+            command.args(args).args(&["--suites", &rebuild.suite, "build"]);
+            if let Some(ref sources) = rebuild.sources {
+                command.arg("packages").args(sources);
+            }
+
+            if let Err(why) = command.run() {
+                error!("failed to rebuild {}: {}", rebuild.suite, why);
+            }
+        }
+    }
+}
+
+/// Determines what to rebuild in each suite after the given paths have changed.
+///
+/// Changes to the overlay, patches, or assets of a source rebuild the source, whereas changes
+/// to a config, to the metapackages of a suite, or to the shared assets rebuild the entire suite.
+fn plan(changes: &[PathBuf], suites: &[(&str, Vec<&str>)]) -> Vec<Rebuild> {
+    let mut rebuilds: BTreeMap<&str, Option<BTreeSet<String>>> = BTreeMap::new();
+    {
+        let mut rebuild = |suite: &str, source: Option<&str>| {
+            let suite = match suites.iter().find(|&&(name, _)| name == suite) {
+                Some(&(suite, ref sources)) => {
+                    if source.map_or(false, |source| !sources.contains(&source)) {
+                        return
+                    }
+
+                    suite
+                }
+                None => return
+            };
+
+            match (rebuilds.entry(suite).or_insert_with(|| Some(BTreeSet::new())), source) {
+                (&mut Some(ref mut sources), Some(source)) => {
+                    sources.insert(source.to_owned());
+                }
+                (entire, None) => *entire = None,
+                _ => ()
+            }
+        };
+
+        for path in changes {
+            let mut components = path.components().filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None
+            });
+
+            match (components.next(), components.next(), components.next()) {
+                (Some("suites"), None, _) | (Some("assets"), Some("share"), _) => {
+                    for &(suite, _) in suites {
+                        rebuild(suite, None);
+                    }
+                }
+                (Some("suites"), Some(config), None) => if config.ends_with(".toml") {
+                    rebuild(&config[..config.len() - 5], None);
+                }
+                (Some("metapackages"), Some(suite), _) => rebuild(suite, None),
+                (Some("debian"), Some(suite), Some(source))
+                    | (Some("patches"), Some(suite), Some(source)) => rebuild(suite, Some(source)),
+                (Some("assets"), Some("packages"), Some(source)) => for &(suite, _) in suites {
+                    rebuild(suite, Some(source));
+                }
+                _ => ()
+            }
+        }
+    }
+
+    rebuilds.into_iter()
+        .map(|(suite, sources)| Rebuild { suite: suite.to_owned(), sources })
+        .collect()
+}
+
+/// Watches directories for changes to the files within them, which are read as relative to the
+/// directories that were given.
+pub(super) struct Inotify {
+    fd: libc::c_int,
+    watches: HashMap<libc::c_int, PathBuf>,
+}
+
+impl Inotify {
+    pub(super) fn new() -> io::Result<Inotify> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Inotify { fd, watches: HashMap::new() })
+    }
+
+    /// Watches a directory, and every directory beneath it, as inotify does not watch recursively.
+    pub(super) fn watch_tree(&mut self, root: &Path) -> io::Result<()> {
+        let directories = WalkDir::new(root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_dir());
+
+        for entry in directories {
+            let path = CString::new(entry.path().as_os_str().as_bytes())
+                .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+
+            let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), MASK) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            self.watches.insert(wd, entry.path().to_path_buf());
+        }
+
+        Ok(())
+    }
+
+    /// Waits for changes, or until the timeout has passed, and returns the paths which changed.
+    ///
+    /// Directories which were created are watched in turn. If the kernel's queue of events
+    /// overflowed, the `suites` directory is returned, as anything may have changed.
+    pub(super) fn read(&mut self, timeout: Option<Duration>) -> io::Result<Vec<PathBuf>> {
+        let timeout = timeout.map_or(-1, |timeout| {
+            (timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis())) as libc::c_int
+        });
+
+        let mut pollfd = libc::pollfd { fd: self.fd, events: libc::POLLIN, revents: 0 };
+        match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+            0 => return Ok(Vec::new()),
+            ready if ready < 0 => {
+                let why = io::Error::last_os_error();
+                return if why.kind() == io::ErrorKind::Interrupted {
+                    Ok(Vec::new())
+                } else {
+                    Err(why)
+                };
+            }
+            _ => ()
+        }
+
+        let mut buffer = [0u8; 16 * 1024];
+        let read = unsafe {
+            libc::read(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+        };
+
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let header = mem::size_of::<libc::inotify_event>();
+        let (mut changes, mut created) = (Vec::new(), Vec::new());
+        let mut offset = 0;
+        while offset + header <= read as usize {
+            let event = unsafe {
+                ptr::read_unaligned(buffer.as_ptr().add(offset) as *const libc::inotify_event)
+            };
+
+            let name = &buffer[offset + header..offset + header + event.len as usize];
+            let name = &name[..name.iter().position(|&byte| byte == 0).unwrap_or(name.len())];
+            offset += header + event.len as usize;
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                warn!("changes were missed while watching, so every suite will be rebuilt");
+                changes.push(PathBuf::from("suites"));
+                continue
+            }
+
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&event.wd);
+                continue
+            }
+
+            let path = match self.watches.get(&event.wd) {
+                Some(directory) => directory.join(OsStr::from_bytes(name)),
+                None => continue
+            };
+
+            let added = event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0;
+            if added && event.mask & libc::IN_ISDIR != 0 {
+                created.push(path.clone());
+            }
+
+            changes.push(path);
+        }
+
+        for directory in created {
+            // The directory may have already been removed again.
+            if let Err(why) = self.watch_tree(&directory) {
+                debug!("unable to watch {}: {}", directory.display(), why);
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebuild_plan() {
+        let suites = vec![("bionic", vec!["foo", "bar"]), ("cosmic", vec!["foo"])];
+        let changes = |paths: &[&str]| {
+            plan(&paths.iter().map(PathBuf::from).collect::<Vec<PathBuf>>(), &suites)
+        };
+        let sources = |names: &[&str]| Some(names.iter().map(|&x| x.to_owned()).collect());
+
+        assert_eq!(changes(&["debian/bionic/foo/control", "patches/bionic/bar/01.patch"]), vec![
+            Rebuild { suite: "bionic".into(), sources: sources(&["bar", "foo"]) },
+        ]);
+
+        assert_eq!(changes(&["assets/packages/foo/logo.png", "suites/cosmic.toml"]), vec![
+            Rebuild { suite: "bionic".into(), sources: sources(&["foo"]) },
+            Rebuild { suite: "cosmic".into(), sources: None },
+        ]);
+
+        // Unknown sources and suites, and files which are not configs, are not rebuilt.
+        let ignored = changes(&["debian/bionic/baz/control", "debian/disco/foo/x", "suites/x.swp"]);
+        assert_eq!(ignored, vec![]);
+        assert_eq!(changes(&["assets/share/x"]).len(), 2);
+    }
+}
@@ -0,0 +1,97 @@
+use config::RetryPolicy;
+use std::fmt::Display;
+use std::io;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Distinguishes transient failures, which may succeed when retried, from permanent failures.
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for io::Error {
+    fn is_retryable(&self) -> bool {
+        match self.kind() {
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::WouldBlock => true,
+            _ => false
+        }
+    }
+}
+
+/// Calls the function until it succeeds, fails permanently, or has used each attempt of the
+/// policy, sleeping with an exponential backoff between each attempt.
+pub fn retry<T, E, F>(policy: &RetryPolicy, name: &str, mut func: F) -> Result<T, E>
+    where E: Retryable + Display,
+          F: FnMut() -> Result<T, E>,
+{
+    let mut attempt = 1;
+    loop {
+        match func() {
+            Err(ref why) if attempt < policy.attempts && why.is_retryable() => {
+                let delay = policy.delay(attempt, random());
+                warn!("{} failed on attempt {} of {}: {}; retrying in {:?}", name, attempt, policy.attempts, why, delay);
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result
+        }
+    }
+}
+
+/// A value from `0` to `1` for the jitter of a delay, which does not need to be unpredictable.
+fn random() -> f64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos());
+    f64::from(nanos) / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn retries_transient_failures() {
+        let policy = RetryPolicy { attempts: 3, backoff: 0, max_backoff: 0, jitter: false };
+        let transient = || io::Error::new(io::ErrorKind::TimedOut, "timed out");
+
+        let mut calls = 0;
+        let result = retry(&policy, "test", || {
+            calls += 1;
+            if calls < 3 { Err(transient()) } else { Ok(calls) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: io::Result<()> = retry(&policy, "test", || { calls += 1; Err(transient()) });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result: io::Result<()> = retry(&policy, "test", || {
+            calls += 1;
+            Err(io::Error::new(io::ErrorKind::NotFound, "not found"))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let policy = RetryPolicy { attempts: 5, backoff: 100, max_backoff: 350, jitter: false };
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(200));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_millis(350));
+        assert_eq!(policy.delay(40, 0.0), Duration::from_millis(350));
+
+        let policy = RetryPolicy { jitter: true, .. policy };
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(50));
+        assert_eq!(policy.delay(1, 1.0), Duration::from_millis(100));
+    }
+}
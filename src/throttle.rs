@@ -0,0 +1,128 @@
+use config::NetworkLimits;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits the rate of the transfers which are accounted to it, so that a limit may be shared by
+/// every connection of the run, or kept by one.
+pub struct Throttle {
+    bytes_per_second: u64,
+    /// When the bytes which have been accounted so far may have been transferred by.
+    next: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Throttle {
+        Throttle { bytes_per_second: bytes_per_second.max(1), next: Mutex::new(None) }
+    }
+
+    /// The delay until the bytes may be transferred, after those which were accounted before
+    /// them. Time which was left idle is not saved up for a later burst.
+    fn account(&self, bytes: u64, now: Instant) -> Duration {
+        let transfer = Duration::from_nanos(bytes.saturating_mul(1_000_000_000) / self.bytes_per_second);
+        let mut next = self.next.lock().unwrap();
+        let start = match *next {
+            Some(next) if next > now => next,
+            _ => now
+        };
+
+        *next = Some(start + transfer);
+        start + transfer - now
+    }
+
+    /// Sleeps until the bytes which were just transferred are within the rate.
+    pub fn consume(&self, bytes: u64) {
+        let delay = self.account(bytes, Instant::now());
+        if delay > Duration::from_millis(0) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// The concurrency and bandwidth that the downloads and publishes of a run are limited to.
+pub struct Bandwidth {
+    pub max_transfers: usize,
+    connection: Option<u64>,
+    total: Option<Arc<Throttle>>,
+}
+
+impl Bandwidth {
+    pub fn new(limits: &NetworkLimits) -> Bandwidth {
+        Bandwidth {
+            max_transfers: limits.max_transfers(),
+            connection: limits.connection_bytes_per_second,
+            total: limits.bytes_per_second.map(|rate| Arc::new(Throttle::new(rate))),
+        }
+    }
+
+    /// Whether the transfers are limited, and so must be made over a single connection.
+    pub fn is_limited(&self) -> bool {
+        self.connection.is_some() || self.total.is_some()
+    }
+
+    /// Limits a reader of one connection to the rate of a connection, and to the rate of the run.
+    pub fn reader<R: Read>(&self, inner: R) -> Throttled<R> {
+        let mut throttles = Vec::new();
+        throttles.extend(self.connection.map(|rate| Arc::new(Throttle::new(rate))));
+        throttles.extend(self.total.clone());
+        Throttled { inner, throttles }
+    }
+
+    /// The `--bwlimit` of rsync, in KiB per second, which is the lower of the two limits.
+    pub fn rsync_limit(&self) -> Option<u64> {
+        let total = self.total.as_ref().map(|throttle| throttle.bytes_per_second);
+        let rate = match (self.connection, total) {
+            (Some(connection), Some(total)) => Some(connection.min(total)),
+            (connection, total) => connection.or(total)
+        };
+
+        rate.map(|rate| (rate / 1024).max(1))
+    }
+}
+
+/// A reader whose reads are delayed to stay within the rate of each of its throttles.
+pub struct Throttled<R> {
+    inner: R,
+    throttles: Vec<Arc<Throttle>>,
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for throttle in &self.throttles {
+            throttle.consume(read as u64);
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttled_rates() {
+        let throttle = Throttle::new(1000);
+        let now = Instant::now();
+        assert_eq!(throttle.account(500, now), Duration::from_millis(500));
+        assert_eq!(throttle.account(500, now), Duration::from_millis(1000));
+        assert_eq!(throttle.account(250, now + Duration::from_millis(1500)), Duration::from_millis(250));
+
+        // Idle time is not saved up for a burst.
+        assert_eq!(throttle.account(100, now + Duration::from_secs(10)), Duration::from_millis(100));
+
+        let limits = |connection, total| NetworkLimits {
+            max_transfers: None,
+            connection_bytes_per_second: connection,
+            bytes_per_second: total,
+        };
+
+        assert!(!Bandwidth::new(&limits(None, None)).is_limited());
+        assert_eq!(Bandwidth::new(&limits(None, None)).max_transfers, 8);
+        assert_eq!(Bandwidth::new(&limits(Some(1_048_576), Some(524_288))).rsync_limit(), Some(512));
+        assert_eq!(Bandwidth::new(&limits(None, Some(100))).rsync_limit(), Some(1));
+        assert_eq!(Bandwidth::new(&limits(Some(2048), None)).reader(&b""[..]).throttles.len(), 1);
+    }
+}
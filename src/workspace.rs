@@ -0,0 +1,209 @@
+use libc;
+use misc;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use utime;
+
+/// The directory of the project that the workspace of each run is created within, unless the
+/// run was given `--tmp-dir`.
+pub const TMP_DIR: &str = "tmp/";
+
+/// The file of a workspace which holds the PID of the run that it belongs to.
+const OWNER: &str = "owner";
+
+/// The file of a workspace which lists the paths outside of it that its run staged files in.
+const STAGED: &str = "staged";
+
+static WORKSPACE: AtomicPtr<Workspace> = AtomicPtr::new(ptr::null_mut());
+static STAGED_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The directory that the intermediate files of a run are written to, before they are moved into
+/// the project or the repo, so that a run which fails midway does not leave partial files in
+/// their place.
+struct Workspace {
+    directory: PathBuf,
+    /// The paths outside of the workspace which are staged in, such as `dists/<suite>.new`, which
+    /// are removed if the run leaves them behind.
+    staged: Mutex<Vec<PathBuf>>,
+}
+
+/// Creates the workspace of this run within `root`, which is removed along with each path that
+/// was registered with it when the run exits, even if it exits with an error. The workspaces of
+/// runs which crashed, or were killed, are removed by `debrep clean --stale`.
+pub fn start(root: &Path) -> io::Result<()> {
+    if !WORKSPACE.load(Ordering::SeqCst).is_null() {
+        return Ok(());
+    }
+
+    let directory = root.join(process::id().to_string());
+
+    // A workspace with the PID of this run was left by a run which is no longer running.
+    if directory.exists() {
+        remove(&directory)?;
+    }
+
+    fs::create_dir_all(&directory)?;
+    misc::write(directory.join(OWNER), process::id().to_string())?;
+    misc::write(directory.join(STAGED), b"")?;
+
+    let workspace = Box::new(Workspace { directory, staged: Mutex::new(Vec::new()) });
+    WORKSPACE.store(Box::into_raw(workspace), Ordering::SeqCst);
+    unsafe { libc::atexit(cleanup); }
+    Ok(())
+}
+
+fn workspace() -> Option<&'static Workspace> {
+    unsafe { WORKSPACE.load(Ordering::SeqCst).as_ref() }
+}
+
+/// A path that a file which is then moved to `dst` with `persist` may be written to, which is
+/// within the workspace, or else beside `dst` when the run has none, as in a dry run.
+pub fn stage(dst: &Path) -> PathBuf {
+    match workspace() {
+        Some(workspace) => {
+            let id = STAGED_FILES.fetch_add(1, Ordering::SeqCst);
+            workspace.directory.join(format!("{}-{}", id, file_name(dst)))
+        }
+        None => beside(dst)
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| "file".into(), |name| name.to_string_lossy().into_owned())
+}
+
+/// The path beside `dst` that a file is staged at when it may not be staged in the workspace.
+fn beside(dst: &Path) -> PathBuf {
+    dst.with_file_name([&file_name(dst), ".staged"].concat())
+}
+
+/// Moves a staged file or directory into the place of `dst`. When the workspace is on another
+/// filesystem, a file is copied beside `dst` first, with its modification time, so that `dst` is
+/// still replaced whole.
+pub fn persist(staged: &Path, dst: &Path) -> io::Result<()> {
+    match fs::rename(staged, dst) {
+        Err(ref why) if why.raw_os_error() == Some(libc::EXDEV) && staged.is_file() => {
+            let beside = beside(dst);
+            register(&beside)?;
+            fs::copy(staged, &beside)?;
+            let (atime, mtime) = utime::get_file_times(staged)?;
+            utime::set_file_times(&beside, atime, mtime)?;
+            fs::rename(&beside, dst)?;
+            fs::remove_file(staged)
+        }
+        result => result
+    }
+}
+
+/// Registers a path outside of the workspace which files are staged in, to be removed if it
+/// still exists when the run exits. The path is recorded within the workspace, so that it is
+/// also removed with the workspace of a run which crashed.
+pub fn register(path: &Path) -> io::Result<()> {
+    let workspace = match workspace() {
+        Some(workspace) => workspace,
+        None => return Ok(())
+    };
+
+    let path = env::current_dir()?.join(path);
+    let mut staged = workspace.staged.lock().unwrap();
+    if !staged.contains(&path) {
+        let mut list = OpenOptions::new().append(true).open(workspace.directory.join(STAGED))?;
+        writeln!(list, "{}", path.display())?;
+        staged.push(path);
+    }
+
+    Ok(())
+}
+
+extern "C" fn cleanup() {
+    if let Some(workspace) = workspace() {
+        let _ = remove(&workspace.directory);
+    }
+}
+
+/// The workspaces within `root` whose runs are no longer running.
+pub fn stale(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut stale = Vec::new();
+    if !root.is_dir() {
+        return Ok(stale);
+    }
+
+    for entry in fs::read_dir(root)? {
+        let directory = entry?.path();
+        let owner = misc::read_to_string(directory.join(OWNER))
+            .ok()
+            .and_then(|pid| pid.trim().parse::<libc::pid_t>().ok());
+
+        // Directories without an owner are not workspaces, and are left alone.
+        if let Some(pid) = owner {
+            if !is_running(pid) {
+                stale.push(directory);
+            }
+        }
+    }
+
+    stale.sort();
+    Ok(stale)
+}
+
+fn is_running(pid: libc::pid_t) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 } || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The paths which the run of a workspace staged files in, that it left behind.
+pub fn leftovers(directory: &Path) -> Vec<PathBuf> {
+    misc::read_to_string(directory.join(STAGED))
+        .unwrap_or_default()
+        .lines()
+        .map(PathBuf::from)
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Removes a workspace, along with the paths which its run left behind.
+pub fn remove(directory: &Path) -> io::Result<()> {
+    for path in leftovers(directory) {
+        if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    fs::remove_dir_all(directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn stale_workspaces() {
+        let dir = tempdir().unwrap();
+        let (crashed, running) = (dir.path().join("tmp/crashed"), dir.path().join("tmp/running"));
+        let staged = dir.path().join("repo/dists/bionic.new");
+        for directory in &[&crashed, &running, &staged] {
+            fs::create_dir_all(directory).unwrap();
+        }
+
+        // PIDs are at most 2^22 on Linux, so this one is never running.
+        misc::write(crashed.join(OWNER), b"2147483647").unwrap();
+        misc::write(crashed.join(STAGED), format!("{}\n{}\n", staged.display(), dir.path().join("gone").display())).unwrap();
+        misc::write(running.join(OWNER), process::id().to_string()).unwrap();
+        fs::create_dir(dir.path().join("tmp/other")).unwrap();
+
+        assert_eq!(stale(&dir.path().join("tmp")).unwrap(), vec![crashed.clone()]);
+        assert_eq!(leftovers(&crashed), vec![staged.clone()]);
+        remove(&crashed).unwrap();
+        assert!(!crashed.exists() && !staged.exists());
+        assert!(running.exists() && dir.path().join("repo/dists").exists());
+        assert!(stale(&dir.path().join("missing")).unwrap().is_empty());
+    }
+}